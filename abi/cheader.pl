@@ -0,0 +1,95 @@
+#!/usr/bin/perl -w
+
+# Generate a C header from a module of the abi crate, e.g.
+# perl abi/cheader.pl abi/src/stat.rs > kernel/stat.h.
+#
+# Translates the module's constants to #defines, its #[repr(C)] structures to
+# C structures, and its size assertions to _Static_asserts, keeping their
+# one-line doc comments. SYS_FORK becomes SYS_fork, as user/usys.pl expects.
+# Other items, such as impl blocks, are left out.
+
+use strict;
+
+my %ctypes = (
+    "i8" => "char", "u8" => "uchar",
+    "i16" => "short", "u16" => "ushort",
+    "i32" => "int", "u32" => "uint",
+    "i64" => "long", "u64" => "uint64",
+    "usize" => "uint64",
+);
+
+my $path = shift or die "usage: cheader.pl <module.rs>\n";
+open(my $in, "<", $path) or die "cheader.pl: cannot open $path\n";
+
+print "// Generated from $path by abi/cheader.pl - do not edit.\n";
+
+my $doc = "";      # doc comment of the next item
+my $skip = 0;      # depth of braces in an item that is left out
+my $blank = 0;     # whether a blank line is pending
+
+sub out {
+    my $line = shift;
+    print "\n" if $blank;
+    $blank = 0;
+    print $line;
+}
+
+# The C name of a structure, e.g. sockaddr_vm for SockaddrVm.
+sub cname {
+    my $name = shift;
+    $name =~ s/(?<=[a-z0-9])([A-Z])/_$1/g;
+    return lc($name);
+}
+
+sub comment {
+    my $doc = shift;
+    return $doc eq "" ? "" : "  // $doc";
+}
+
+while (my $line = <$in>) {
+    chomp $line;
+    if ($skip > 0) {
+        $skip += () = $line =~ /\{/g;
+        $skip -= () = $line =~ /\}/g;
+        next;
+    }
+    if ($line =~ /^\/\/! ?(.*)$/) {
+        out("// $1\n");
+    } elsif ($line =~ /^\s*\/\/\/ ?(.*)$/) {
+        $doc = $1;
+    } elsif ($line =~ /^pub const (\w+): \w+ = (.+);$/) {
+        my ($name, $value) = ($1, $2);
+        $name =~ s/^SYS_(\w+)$/"SYS_" . lc($1)/e;
+        $value =~ s/_//g;
+        $value =~ s/^0o/0/;
+        out("#define $name $value" . comment($doc) . "\n");
+        $doc = "";
+    } elsif ($line =~ /^pub struct (\w+) \{$/) {
+        out("struct " . cname($1) . " {" . comment($doc) . "\n");
+        $doc = "";
+    } elsif ($line =~ /^\s+pub (?:r#)?(\w+): (\w+),$/) {
+        my $ctype = $ctypes{$2} or die "cheader.pl: $path: no C type for $2\n";
+        out("  $ctype $1;" . comment($doc) . "\n");
+        $doc = "";
+    } elsif ($line =~ /^\s+pub (\w+): \[(\w+); (\w+)\],$/) {
+        my $ctype = $2 eq "u8" ? "char" : $ctypes{$2};
+        defined $ctype or die "cheader.pl: $path: no C type for $2\n";
+        out("  $ctype $1\[$3\];" . comment($doc) . "\n");
+        $doc = "";
+    } elsif ($line =~ /^\}$/) {
+        out("};\n");
+    } elsif ($line =~ /^const_assert_eq!\(mem::size_of::<(\w+)>\(\), (\d+)\);$/) {
+        my $struct = cname($1);
+        out("_Static_assert(sizeof(struct $struct) == $2, \"struct $struct\");\n");
+    } elsif ($line =~ /^$/) {
+        $blank = 1;
+    } elsif ($line =~ /^(?:pub )?(?:impl|fn|const fn)\b.*\{$/) {
+        $skip = 1;
+        $doc = "";
+    } elsif ($line =~ /^(?:const|use|#)/) {
+        # Private constants, imports and attributes stay on the Rust side.
+        $doc = "";
+    } else {
+        die "cheader.pl: $path: cannot translate: $line\n";
+    }
+}
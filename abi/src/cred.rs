@@ -0,0 +1,34 @@
+//! Capabilities, each of which lets a process bypass a permission check or make a privileged
+//! system call. The bits are those of the same capabilities in Linux.
+//!
+//! A process whose effective user ID is the superuser's has the capabilities of its bounding
+//! set, and other processes have none. capdrop() removes capabilities from the bounding set for
+//! good, and fork() and exec() keep the set, so the superuser can run a program with fewer
+//! privileges, which no set-user-ID program gives back.
+
+/// Change the owner and group of files
+pub const CAP_CHOWN: u64 = 0x1;
+/// Bypass the permission checks of files
+pub const CAP_DAC_OVERRIDE: u64 = 0x2;
+/// Change the mode of files that others own
+pub const CAP_FOWNER: u64 = 0x8;
+/// Send signals to the processes of other users
+pub const CAP_KILL: u64 = 0x20;
+/// Set the group IDs to any group
+pub const CAP_SETGID: u64 = 0x40;
+/// Set the user IDs to any user
+pub const CAP_SETUID: u64 = 0x80;
+/// Bind vsock ports below 1024
+pub const CAP_NET_BIND: u64 = 0x400;
+/// Attach to processes with ptrace()
+pub const CAP_SYS_PTRACE: u64 = 0x8_0000;
+/// Administer the system: mknod(), chroot(), quotas of others, tracing and debugging
+pub const CAP_SYS_ADMIN: u64 = 0x20_0000;
+/// Power off and suspend the machine
+pub const CAP_SYS_BOOT: u64 = 0x40_0000;
+/// Exceed disk quotas
+pub const CAP_SYS_RESOURCE: u64 = 0x100_0000;
+/// Set the realtime clock
+pub const CAP_SYS_TIME: u64 = 0x200_0000;
+/// Read and clear the kernel log
+pub const CAP_SYSLOG: u64 = 0x4_0000_0000;
@@ -0,0 +1,20 @@
+//! Directory entries. A directory is a file containing a sequence of them.
+
+use core::mem;
+
+use static_assertions::const_assert_eq;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Maximum length of a file name
+pub const DIRSIZ: usize = 14;
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Dirent {
+    /// Inode number, or 0 if the entry is free
+    pub inum: u16,
+    /// File name, NUL-terminated if shorter than DIRSIZ
+    pub name: [u8; DIRSIZ],
+}
+
+const_assert_eq!(mem::size_of::<Dirent>(), 16);
@@ -0,0 +1,84 @@
+//! Error codes, which a failed system call returns negated, and user programs find in errno.
+
+/// Operation not permitted
+pub const EPERM: i32 = 1;
+/// No such file or directory
+pub const ENOENT: i32 = 2;
+/// No such process
+pub const ESRCH: i32 = 3;
+/// Interrupted system call
+pub const EINTR: i32 = 4;
+/// I/O error
+pub const EIO: i32 = 5;
+/// Argument list too long
+pub const E2BIG: i32 = 7;
+/// Exec format error
+pub const ENOEXEC: i32 = 8;
+/// Bad file descriptor
+pub const EBADF: i32 = 9;
+/// No child processes
+pub const ECHILD: i32 = 10;
+/// Resource temporarily unavailable
+pub const EAGAIN: i32 = 11;
+/// Out of memory
+pub const ENOMEM: i32 = 12;
+/// Permission denied
+pub const EACCES: i32 = 13;
+/// Bad address
+pub const EFAULT: i32 = 14;
+/// Device or resource busy
+pub const EBUSY: i32 = 16;
+/// File exists
+pub const EEXIST: i32 = 17;
+/// Cross-device link
+pub const EXDEV: i32 = 18;
+/// No such device
+pub const ENODEV: i32 = 19;
+/// Not a directory
+pub const ENOTDIR: i32 = 20;
+/// Is a directory
+pub const EISDIR: i32 = 21;
+/// Invalid argument
+pub const EINVAL: i32 = 22;
+/// Too many open files in system
+pub const ENFILE: i32 = 23;
+/// Too many open files
+pub const EMFILE: i32 = 24;
+/// Inappropriate ioctl for device
+pub const ENOTTY: i32 = 25;
+/// File too large
+pub const EFBIG: i32 = 27;
+/// No space left on device
+pub const ENOSPC: i32 = 28;
+/// Illegal seek
+pub const ESPIPE: i32 = 29;
+/// Broken pipe
+pub const EPIPE: i32 = 32;
+/// Result too large
+pub const ERANGE: i32 = 34;
+/// File name too long
+pub const ENAMETOOLONG: i32 = 36;
+/// Function not implemented
+pub const ENOSYS: i32 = 38;
+/// Directory not empty
+pub const ENOTEMPTY: i32 = 39;
+/// Identifier removed
+pub const EIDRM: i32 = 43;
+/// Socket operation on non-socket
+pub const ENOTSOCK: i32 = 88;
+/// Message too long
+pub const EMSGSIZE: i32 = 90;
+/// Address family not supported by protocol
+pub const EAFNOSUPPORT: i32 = 97;
+/// Address already in use
+pub const EADDRINUSE: i32 = 98;
+/// Connection reset by peer
+pub const ECONNRESET: i32 = 104;
+/// Transport endpoint is already connected
+pub const EISCONN: i32 = 106;
+/// Transport endpoint is not connected
+pub const ENOTCONN: i32 = 107;
+/// Connection refused
+pub const ECONNREFUSED: i32 = 111;
+/// Disk quota exceeded
+pub const EDQUOT: i32 = 122;
@@ -0,0 +1,85 @@
+//! The rings of ioring_setup() and ioring_enter(), through which a process queues reads,
+//! writes and fsyncs, and collects their results, without a system call for each.
+//!
+//! ioring_setup() maps a page at IORING_ADDR, holding an IoringHeader, IORING_SQ_ENTRIES
+//! submission entries from IORING_SQ_OFF, and IORING_CQ_ENTRIES completion entries from
+//! IORING_CQ_OFF. The process fills the submission entry at sq_tail and advances sq_tail, and
+//! ioring_enter() takes entries from sq_head, and adds a completion entry at cq_tail for each.
+//! The process reads the completion entry at cq_head and advances cq_head. The indices only
+//! grow, and index i is entry i % IORING_SQ_ENTRIES or i % IORING_CQ_ENTRIES.
+
+use core::mem;
+
+use static_assertions::{const_assert, const_assert_eq};
+use zerocopy::{AsBytes, FromBytes};
+
+/// User address of the ring page
+pub const IORING_ADDR: usize = 0x3f_ffff_d000;
+/// Number of submission entries
+pub const IORING_SQ_ENTRIES: u32 = 64;
+/// Number of completion entries
+pub const IORING_CQ_ENTRIES: u32 = 64;
+/// Offset of the submission entries in the ring page
+pub const IORING_SQ_OFF: usize = 64;
+/// Offset of the completion entries in the ring page
+pub const IORING_CQ_OFF: usize = 2112;
+
+/// Does nothing
+pub const IORING_OP_NOP: u32 = 0;
+/// Reads len bytes into addr, as read() does
+pub const IORING_OP_READ: u32 = 1;
+/// Writes len bytes from addr, as write() does
+pub const IORING_OP_WRITE: u32 = 2;
+/// Waits until the writes to the file are on the disk
+pub const IORING_OP_FSYNC: u32 = 3;
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct IoringHeader {
+    /// Next submission entry for the kernel to take
+    pub sq_head: u32,
+    /// Next submission entry for the process to fill
+    pub sq_tail: u32,
+    /// Next completion entry for the process to read
+    pub cq_head: u32,
+    /// Next completion entry for the kernel to fill
+    pub cq_tail: u32,
+}
+
+const_assert_eq!(mem::size_of::<IoringHeader>(), 16);
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct IoringSqe {
+    /// IORING_OP_*
+    pub opcode: u32,
+    /// File descriptor
+    pub fd: i32,
+    /// Buffer of a read or write
+    pub addr: u64,
+    /// Number of bytes to read or write
+    pub len: u32,
+    /// Zero
+    pub reserved: u32,
+    /// Copied to the completion entry
+    pub user_data: u64,
+}
+
+const_assert_eq!(mem::size_of::<IoringSqe>(), 32);
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct IoringCqe {
+    /// user_data of the submission entry
+    pub user_data: u64,
+    /// What the system call would return: a byte count, or a negated error number
+    pub res: i32,
+    /// Zero
+    pub flags: u32,
+}
+
+const_assert_eq!(mem::size_of::<IoringCqe>(), 16);
+
+const_assert!(IORING_SQ_OFF >= mem::size_of::<IoringHeader>());
+const_assert!(IORING_CQ_OFF >= IORING_SQ_OFF + IORING_SQ_ENTRIES as usize * 32);
+const_assert!(IORING_CQ_OFF + IORING_CQ_ENTRIES as usize * 16 <= 4096);
@@ -0,0 +1,17 @@
+//! Keys and flags of System V IPC objects: semaphore sets and message queues.
+//!
+//! A process finds an object by its key, which processes agree on beforehand, and gets the
+//! object's identifier, which the other calls take. The low nine bits of the flags are the
+//! permissions of a new object, as in a file's mode.
+
+/// The key that always makes a new object, which other processes reach only by its identifier
+pub const IPC_PRIVATE: i32 = 0;
+/// Make the object if no object has the key
+pub const IPC_CREAT: i32 = 0x200;
+/// With IPC_CREAT, fail if an object has the key already
+pub const IPC_EXCL: i32 = 0x400;
+/// Fail with EAGAIN instead of waiting
+pub const IPC_NOWAIT: i32 = 0x800;
+
+/// Command that removes the object, which only its owner may
+pub const IPC_RMID: i32 = 0;
@@ -0,0 +1,29 @@
+//! The system call ABI of rv6, shared by the kernel and user programs.
+//!
+//! The kernel uses this crate as is. The user programs, which are written in C, use the headers
+//! that abi/cheader.pl generates from its modules, such as kernel/stat.h from `stat`, and
+//! user/usys.pl generates the system call stubs from kernel/syscall.h. So a system call, error
+//! code, or structure is added here, and both sides follow. The size of each structure is
+//! asserted here, and again by the generated headers, so that the C compiler lays it out as Rust
+//! does.
+//!
+//! The modules are written so that abi/cheader.pl can translate them: constants and `#[repr(C)]`
+//! structures of integers and byte arrays, with one-line doc comments, and size assertions.
+//! Other items, such as `impl` blocks, stay on the Rust side.
+
+#![no_std]
+#![deny(warnings, unused_results)]
+
+pub mod cred;
+pub mod dirent;
+pub mod errno;
+pub mod ioring;
+pub mod ipc;
+pub mod mqueue;
+pub mod quota;
+pub mod sem;
+pub mod socket;
+pub mod stat;
+pub mod syscall;
+pub mod time;
+pub mod vdso;
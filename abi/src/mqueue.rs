@@ -0,0 +1,34 @@
+//! Message queues of mq_open(), mq_send() and mq_receive(). See `ipc` for keys.
+//!
+//! A queue holds up to mq_maxmsg messages of up to mq_msgsize bytes each, both fixed when the
+//! queue is made. mq_receive() takes the oldest of the messages of the highest priority. A
+//! descriptor of a queue works with poll(): it is readable while the queue holds a message, and
+//! writable while the queue has room for one. Unlinking a queue frees its key, but the queue
+//! lives until its last descriptor is closed.
+
+use core::mem;
+
+use static_assertions::const_assert_eq;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Maximum number of messages in a queue
+pub const MQ_MAXMSG: i32 = 10;
+/// Maximum size of a message
+pub const MQ_MSGSIZE: i32 = 128;
+/// Priorities are less than this
+pub const MQ_PRIO_MAX: u32 = 32;
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct MqAttr {
+    /// O_NONBLOCK if the descriptor is nonblocking; ignored by mq_open()
+    pub mq_flags: i32,
+    /// Maximum number of messages in the queue
+    pub mq_maxmsg: i32,
+    /// Maximum size of a message in the queue
+    pub mq_msgsize: i32,
+    /// Number of messages in the queue; ignored by mq_open()
+    pub mq_curmsgs: i32,
+}
+
+const_assert_eq!(mem::size_of::<MqAttr>(), 16);
@@ -0,0 +1,39 @@
+//! Disk quotas of quotactl(), which limit the blocks and inodes that the files of each user
+//! take.
+//!
+//! A hard limit is never exceeded. A soft limit may be exceeded for a grace period, after which
+//! it acts as a hard limit until usage drops below it. A limit of 0 is no limit. Blocks count
+//! the data blocks of files, i.e. their sizes rounded up to whole blocks.
+
+use core::mem;
+
+use static_assertions::const_assert_eq;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Reads the limits and usage of a user into a Dqblk
+pub const Q_GETQUOTA: i32 = 1;
+/// Sets the limits of a user from a Dqblk, ignoring its usage; superuser only
+pub const Q_SETQUOTA: i32 = 2;
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Dqblk {
+    /// Hard limit on blocks
+    pub bhardlimit: u32,
+    /// Soft limit on blocks
+    pub bsoftlimit: u32,
+    /// Blocks in use
+    pub curblocks: u32,
+    /// Hard limit on inodes
+    pub ihardlimit: u32,
+    /// Soft limit on inodes
+    pub isoftlimit: u32,
+    /// Inodes in use
+    pub curinodes: u32,
+    /// Ticks left before the soft limit on blocks acts as a hard limit, or 0 if not over it
+    pub btime: u32,
+    /// Ticks left before the soft limit on inodes acts as a hard limit, or 0 if not over it
+    pub itime: u32,
+}
+
+const_assert_eq!(mem::size_of::<Dqblk>(), 32);
@@ -0,0 +1,41 @@
+//! Semaphore sets of semget(), semop() and semctl(). See `ipc` for keys and flags.
+//!
+//! semop() applies an array of Sembufs to a set at once: either all of them, or, if one would
+//! make a value negative or wait for a value to be 0, none, and waits until it can apply all of
+//! them. An operation with SEM_UNDO is undone when the process exits, so that a process that
+//! dies holding a semaphore releases it.
+
+use core::mem;
+
+use static_assertions::const_assert_eq;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Maximum number of semaphores in a set
+pub const SEMMSL: i32 = 16;
+/// Maximum number of operations in a semop() call
+pub const SEMOPM: i32 = 8;
+/// Maximum value of a semaphore
+pub const SEMVMX: i32 = 32767;
+
+/// Undo the operation when the process exits
+pub const SEM_UNDO: i16 = 0x1000;
+
+/// semctl() command that returns the value of a semaphore
+pub const GETVAL: i32 = 12;
+/// semctl() command that returns the number of processes waiting for the value to grow
+pub const GETNCNT: i32 = 14;
+/// semctl() command that sets the value of a semaphore, dropping its undo adjustments
+pub const SETVAL: i32 = 16;
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Sembuf {
+    /// Index of the semaphore in the set
+    pub sem_num: u16,
+    /// Added to the value; 0 waits for the value to be 0
+    pub sem_op: i16,
+    /// IPC_NOWAIT and SEM_UNDO
+    pub sem_flg: i16,
+}
+
+const_assert_eq!(mem::size_of::<Sembuf>(), 6);
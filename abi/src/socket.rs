@@ -0,0 +1,39 @@
+//! Sockets of socket() and its relatives. The only family is AF_VSOCK, which connects the
+//! machine to the host it runs on.
+
+use core::mem;
+
+use static_assertions::const_assert_eq;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Address family of virtio sockets
+pub const AF_VSOCK: i32 = 40;
+
+/// Connection-based byte stream
+pub const SOCK_STREAM: i32 = 1;
+
+/// Binds to any CID of the machine
+pub const VMADDR_CID_ANY: u32 = 0xffff_ffff;
+/// CID of the machine itself, whose connections do not leave it
+pub const VMADDR_CID_LOCAL: u32 = 1;
+/// CID of the host
+pub const VMADDR_CID_HOST: u32 = 2;
+/// Binds to a free port
+pub const VMADDR_PORT_ANY: u32 = 0xffff_ffff;
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct SockaddrVm {
+    /// AF_VSOCK
+    pub svm_family: u16,
+    /// Zero
+    pub svm_reserved1: u16,
+    /// Port
+    pub svm_port: u32,
+    /// Context ID, which names a machine
+    pub svm_cid: u32,
+    /// Zero
+    pub svm_zero: [u8; 4],
+}
+
+const_assert_eq!(mem::size_of::<SockaddrVm>(), 16);
@@ -0,0 +1,41 @@
+//! File status, which fstat() returns.
+
+use core::mem;
+
+use static_assertions::const_assert_eq;
+use zerocopy::{AsBytes, FromBytes};
+
+/// Directory
+pub const T_DIR: i16 = 1;
+/// File
+pub const T_FILE: i16 = 2;
+/// Device
+pub const T_DEVICE: i16 = 3;
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Stat {
+    /// File system's disk device
+    pub dev: i32,
+    /// Inode number
+    pub ino: u32,
+    /// Type of file
+    pub r#type: i16,
+    /// Number of links to file
+    pub nlink: i16,
+    /// Permission bits, with I_SUID and I_SGID
+    pub mode: u32,
+    /// Size of file in bytes
+    pub size: u64,
+    /// Owner's user ID
+    pub uid: u32,
+    /// Group ID
+    pub gid: u32,
+}
+
+const_assert_eq!(mem::size_of::<Stat>(), 32);
+
+/// exec() sets the effective user ID to the owner
+pub const I_SUID: u32 = 0o4000;
+/// exec() sets the effective group ID to the group
+pub const I_SGID: u32 = 0o2000;
@@ -0,0 +1,87 @@
+//! System call numbers, which user programs pass in a7.
+
+pub const SYS_FORK: i32 = 1;
+pub const SYS_EXIT: i32 = 2;
+pub const SYS_WAIT: i32 = 3;
+pub const SYS_PIPE: i32 = 4;
+pub const SYS_READ: i32 = 5;
+pub const SYS_KILL: i32 = 6;
+pub const SYS_EXEC: i32 = 7;
+pub const SYS_FSTAT: i32 = 8;
+pub const SYS_CHDIR: i32 = 9;
+pub const SYS_DUP: i32 = 10;
+pub const SYS_GETPID: i32 = 11;
+pub const SYS_SBRK: i32 = 12;
+pub const SYS_SLEEP: i32 = 13;
+pub const SYS_UPTIME: i32 = 14;
+pub const SYS_OPEN: i32 = 15;
+pub const SYS_WRITE: i32 = 16;
+pub const SYS_MKNOD: i32 = 17;
+pub const SYS_UNLINK: i32 = 18;
+pub const SYS_LINK: i32 = 19;
+pub const SYS_MKDIR: i32 = 20;
+pub const SYS_CLOSE: i32 = 21;
+pub const SYS_POWEROFF: i32 = 22;
+pub const SYS_SYSINFO: i32 = 23;
+pub const SYS_FCNTL: i32 = 24;
+pub const SYS_IOCTL: i32 = 25;
+pub const SYS_POLL: i32 = 26;
+pub const SYS_PIPE2: i32 = 27;
+pub const SYS_EVENTFD: i32 = 28;
+pub const SYS_TIMERFD_CREATE: i32 = 29;
+pub const SYS_TIMERFD_SETTIME: i32 = 30;
+pub const SYS_TIMERFD_GETTIME: i32 = 31;
+pub const SYS_GETCWD: i32 = 32;
+pub const SYS_CHROOT: i32 = 33;
+pub const SYS_UMASK: i32 = 34;
+pub const SYS_CLOCK_GETTIME: i32 = 35;
+pub const SYS_GETRANDOM: i32 = 36;
+pub const SYS_TRACE: i32 = 37;
+pub const SYS_PTRACE: i32 = 38;
+pub const SYS_GETUID: i32 = 39;
+pub const SYS_GETEUID: i32 = 40;
+pub const SYS_GETGID: i32 = 41;
+pub const SYS_GETEGID: i32 = 42;
+pub const SYS_SETUID: i32 = 43;
+pub const SYS_SETGID: i32 = 44;
+pub const SYS_CHMOD: i32 = 45;
+pub const SYS_CHOWN: i32 = 46;
+pub const SYS_AUDIT: i32 = 47;
+pub const SYS_SIGSEND: i32 = 48;
+pub const SYS_SETPGID: i32 = 49;
+pub const SYS_GETPGID: i32 = 50;
+pub const SYS_CLOCK_SETTIME: i32 = 51;
+pub const SYS_ADJTIME: i32 = 52;
+pub const SYS_DMESG: i32 = 53;
+pub const SYS_LOGFILTER: i32 = 54;
+pub const SYS_TRACEON: i32 = 55;
+pub const SYS_TRACEREAD: i32 = 56;
+pub const SYS_LEAKCHECK: i32 = 57;
+pub const SYS_FAILINJECT: i32 = 58;
+pub const SYS_KTEST: i32 = 59;
+pub const SYS_FUZZINFO: i32 = 60;
+pub const SYS_PROFILE: i32 = 61;
+pub const SYS_SUSPEND: i32 = 62;
+pub const SYS_SOCKET: i32 = 63;
+pub const SYS_BIND: i32 = 64;
+pub const SYS_LISTEN: i32 = 65;
+pub const SYS_ACCEPT: i32 = 66;
+pub const SYS_CONNECT: i32 = 67;
+pub const SYS_FADVISE: i32 = 68;
+pub const SYS_SENDFILE: i32 = 69;
+pub const SYS_IORING_SETUP: i32 = 70;
+pub const SYS_IORING_ENTER: i32 = 71;
+pub const SYS_SPLICE: i32 = 72;
+pub const SYS_QUOTACTL: i32 = 73;
+pub const SYS_PIDFD_OPEN: i32 = 74;
+pub const SYS_SIGPENDING: i32 = 75;
+pub const SYS_CAPGET: i32 = 76;
+pub const SYS_CAPDROP: i32 = 77;
+pub const SYS_SEMGET: i32 = 78;
+pub const SYS_SEMOP: i32 = 79;
+pub const SYS_SEMCTL: i32 = 80;
+pub const SYS_MQ_OPEN: i32 = 81;
+pub const SYS_MQ_SEND: i32 = 82;
+pub const SYS_MQ_RECEIVE: i32 = 83;
+pub const SYS_MQ_UNLINK: i32 = 84;
+pub const SYS_MQ_GETATTR: i32 = 85;
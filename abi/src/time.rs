@@ -0,0 +1,72 @@
+//! Clocks and times of clock_gettime() and its relatives.
+
+use core::mem;
+
+use static_assertions::const_assert_eq;
+use zerocopy::{AsBytes, FromBytes};
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Wall-clock time since the Unix epoch
+pub const CLOCK_REALTIME: i32 = 0;
+/// Time since boot, which never jumps
+pub const CLOCK_MONOTONIC: i32 = 1;
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Timespec {
+    /// Seconds
+    pub tv_sec: u64,
+    /// Nanoseconds
+    pub tv_nsec: u64,
+}
+
+const_assert_eq!(mem::size_of::<Timespec>(), 16);
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Timeval {
+    /// Seconds
+    pub tv_sec: u64,
+    /// Microseconds
+    pub tv_usec: u64,
+}
+
+const_assert_eq!(mem::size_of::<Timeval>(), 16);
+
+impl Timespec {
+    pub const fn from_nanos(nanos: u64) -> Self {
+        Self {
+            tv_sec: nanos / NSEC_PER_SEC,
+            tv_nsec: nanos % NSEC_PER_SEC,
+        }
+    }
+
+    /// Returns the time in nanoseconds, or `None` if it is not a valid `timespec` or overflows.
+    pub fn to_nanos(self) -> Option<u64> {
+        if self.tv_nsec >= NSEC_PER_SEC {
+            return None;
+        }
+        self.tv_sec
+            .checked_mul(NSEC_PER_SEC)?
+            .checked_add(self.tv_nsec)
+    }
+
+    /// Like `from_nanos`, for a signed duration. A negative duration has a negative `tv_sec`.
+    pub fn from_signed_nanos(nanos: i64) -> Self {
+        Self {
+            tv_sec: nanos.div_euclid(NSEC_PER_SEC as i64) as u64,
+            tv_nsec: nanos.rem_euclid(NSEC_PER_SEC as i64) as u64,
+        }
+    }
+
+    /// Like `to_nanos`, for a signed duration.
+    pub fn to_signed_nanos(self) -> Option<i64> {
+        if self.tv_nsec >= NSEC_PER_SEC {
+            return None;
+        }
+        (self.tv_sec as i64)
+            .checked_mul(NSEC_PER_SEC as i64)?
+            .checked_add(self.tv_nsec as i64)
+    }
+}
@@ -0,0 +1,41 @@
+//! The page that the kernel shares with each process at VDSO_ADDR, from which the process reads
+//! the clocks and its CPU without a system call.
+//!
+//! The page holds a VdsoData, which the kernel fills each time it returns to the process, and
+//! which the process can read but not write. The process reads the time CSR itself, which counts
+//! at timebase_freq from boot, and converts it to nanoseconds to get the monotonic clock. The
+//! realtime clock is realtime_offset plus the monotonic time, plus the part of slew applied by
+//! then, which grows by 1ns every VDSO_SLEW_RATIO ns from slew_start, up to slew.
+
+use core::mem;
+
+use static_assertions::const_assert_eq;
+use zerocopy::{AsBytes, FromBytes};
+
+/// User address of the shared page
+pub const VDSO_ADDR: usize = 0x3f_ffff_c000;
+/// A slew changes the realtime clock by 1ns every this many ns
+pub const VDSO_SLEW_RATIO: u64 = 2000;
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct VdsoData {
+    /// Clock ticks since boot, as uptime() returns
+    pub ticks: u64,
+    /// Frequency of the time CSR, in Hz
+    pub timebase_freq: u64,
+    /// Wall-clock time in ns when the monotonic clock was zero, without slew
+    pub realtime_offset: u64,
+    /// Adjustment of realtime_offset being slewed, in ns
+    pub slew: i64,
+    /// Monotonic time when the slew started, in ns
+    pub slew_start: u64,
+    /// Changes each time the realtime clock is set or slewed
+    pub realtime_seq: u64,
+    /// CPU that the process runs on
+    pub cpu: u32,
+    /// Zero
+    pub reserved: u32,
+}
+
+const_assert_eq!(mem::size_of::<VdsoData>(), 56);
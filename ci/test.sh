@@ -1,6 +1,8 @@
 #!/usr/bin/env bash
 set -e
 
+cargo fmt --manifest-path=abi/Cargo.toml -- --check -l
 cargo fmt --manifest-path=kernel-rs/Cargo.toml -- --check -l
+cargo clippy --manifest-path=abi/Cargo.toml
 cargo clippy --manifest-path=kernel-rs/Cargo.toml
 make qemu USERTEST=yes RUST_MODE=release
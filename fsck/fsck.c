@@ -0,0 +1,378 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <stdarg.h>
+
+#define stat xv6_stat  // avoid clash with host struct stat
+#include "kernel/types.h"
+#include "kernel/param.h"
+#include "kernel/fs.h"
+#include "kernel/stat.h"
+
+// fsck fs.img
+// Checks the file system of the disk image without changing it, and prints
+// each inconsistency that it finds: checksums that do not match, inodes of
+// unknown types or sizes, blocks outside the data area or used twice, a
+// free bitmap that disagrees with the inodes, an inode bitmap that
+// disagrees with the types of the inodes, directories whose entries
+// refer to free inodes or lack "." and "..", and link counts that differ
+// from the number of entries referring to each inode. Exits with status 1
+// if it found any.
+
+FILE *img;
+struct superblock sb;
+uint datastart;  // First data block
+int errors;
+
+uchar *used;      // Whether each block is used by an inode
+short *nrefs;     // Number of entries referring to each inode, except "."
+ushort *parent;   // Directory whose entry (other than "..") refers to each directory
+struct dinode *inodes;
+
+void
+error(char *fmt, ...)
+{
+  va_list ap;
+
+  printf("fsck: ");
+  va_start(ap, fmt);
+  vprintf(fmt, ap);
+  va_end(ap);
+  printf("\n");
+  errors++;
+}
+
+void
+rblock(uint b, void *buf)
+{
+  if(fseek(img, (long)b * BSIZE, SEEK_SET) != 0 || fread(buf, BSIZE, 1, img) != 1){
+    fprintf(stderr, "fsck: cannot read block %u\n", b);
+    exit(1);
+  }
+}
+
+// Whether the checksum of an inode block or a directory block matches.
+int
+sealed(char *buf)
+{
+  uint checksum;
+
+  if(!(sb.flags & FS_CHECKSUMS))
+    return 1;
+  memmove(&checksum, buf + BSIZE - sizeof(checksum), sizeof(checksum));
+  return crc32c(buf, BSIZE - sizeof(checksum)) == checksum;
+}
+
+// Whether inode inum holds the checksum of its block instead of an inode.
+int
+checksum_inode(uint inum)
+{
+  return (sb.flags & FS_CHECKSUMS) && inum % IPB == IPB - 1;
+}
+
+// Marks block b as used by inode inum.
+void
+use(uint inum, uint b)
+{
+  if(b < datastart || b >= sb.size){
+    error("inode %u: block %u is outside the data blocks", inum, b);
+    return;
+  }
+  if(used[b])
+    error("inode %u: block %u is used twice", inum, b);
+  used[b] = 1;
+}
+
+// Returns extent i of inode inum, given FS_EXTENTS.
+struct extent
+extent(uint inum, uint i)
+{
+  struct dinode *dip = &inodes[inum];
+  struct extent buf[EXTENTS_PER_BLOCK], none = {0, 0};
+  uint b;
+
+  if(i < NEXTENT_INLINE)
+    return ((struct extent*)dip->addrs)[i];
+  i -= NEXTENT_INLINE;
+  b = dip->addrs[NEXTENT_INLINE * 2 + i / EXTENTS_PER_BLOCK];
+  if(b == 0 || b >= sb.size)
+    return none;
+  rblock(b, buf);
+  return buf[i % EXTENTS_PER_BLOCK];
+}
+
+// Returns the block holding block fbn of inode inum, or 0 if there is none.
+uint
+bmap(uint inum, uint fbn)
+{
+  struct dinode *dip = &inodes[inum];
+  uint indirect[NINDIRECT];
+  struct extent e;
+  uint i;
+
+  if(sb.flags & FS_EXTENTS){
+    for(i = 0; i < NEXTENT; i++){
+      e = extent(inum, i);
+      if(e.len == 0)
+        break;
+      if(fbn < e.len)
+        return e.start + fbn;
+      fbn -= e.len;
+    }
+    return 0;
+  }
+  if(fbn < NDIRECT)
+    return dip->addrs[fbn];
+  if(dip->addrs[NDIRECT] == 0 || dip->addrs[NDIRECT] >= sb.size)
+    return 0;
+  rblock(dip->addrs[NDIRECT], indirect);
+  return indirect[fbn - NDIRECT];
+}
+
+// Marks the blocks of inode inum and its extent blocks as used, given
+// FS_EXTENTS.
+void
+use_extents(uint inum)
+{
+  struct dinode *dip = &inodes[inum];
+  struct extent e;
+  uint i, b, n;
+
+  for(i = 0; i < NEXTENT_BLOCKS; i++)
+    if(dip->addrs[NEXTENT_INLINE * 2 + i] != 0)
+      use(inum, dip->addrs[NEXTENT_INLINE * 2 + i]);
+  n = 0;
+  for(i = 0; i < NEXTENT; i++){
+    e = extent(inum, i);
+    if(e.len == 0)
+      break;
+    if(e.start + e.len < e.start || e.start + e.len > sb.size){
+      error("inode %u: extent %u is outside the disk", inum, i);
+      continue;
+    }
+    for(b = e.start; b < e.start + e.len; b++)
+      use(inum, b);
+    n += e.len;
+  }
+  if(n > MAXFILE)
+    error("inode %u: extents map %u blocks", inum, n);
+}
+
+void
+check_inodes(void)
+{
+  char buf[BSIZE];
+  uint indirect[NINDIRECT];
+  struct dinode *dip;
+  uint inum, b, i;
+
+  for(inum = 0; inum < sb.ninodes; inum++){
+    if(inum % IPB == 0){
+      rblock(IBLOCK(inum, sb), buf);
+      if(!sealed(buf))
+        error("inode block %u: checksum mismatch", IBLOCK(inum, sb));
+    }
+    inodes[inum] = ((struct dinode*)buf)[inum % IPB];
+    dip = &inodes[inum];
+    if(inum == 0 || checksum_inode(inum) || dip->type == 0)
+      continue;
+    if(dip->type != T_DIR && dip->type != T_FILE && dip->type != T_DEVICE){
+      error("inode %u: unknown type %d", inum, dip->type);
+      dip->type = 0;
+      continue;
+    }
+    if(dip->size > MAXFILE * BSIZE)
+      error("inode %u: size %u is too large", inum, dip->size);
+    if(sb.flags & FS_EXTENTS){
+      use_extents(inum);
+      continue;
+    }
+    for(i = 0; i < NDIRECT; i++)
+      if(dip->addrs[i] != 0)
+        use(inum, dip->addrs[i]);
+    b = dip->addrs[NDIRECT];
+    if(b != 0){
+      use(inum, b);
+      if(b < sb.size){
+        rblock(b, indirect);
+        for(i = 0; i < NINDIRECT; i++)
+          if(indirect[i] != 0)
+            use(inum, indirect[i]);
+      }
+    }
+  }
+  if(inodes[ROOTINO].type != T_DIR)
+    error("root inode %u is not a directory", ROOTINO);
+}
+
+void
+check_bitmap(void)
+{
+  uchar buf[BSIZE];
+  uint b;
+  int marked;
+
+  for(b = 0; b < sb.size; b++){
+    if(b % BPB == 0)
+      rblock(BBLOCK(b, sb), buf);
+    marked = (buf[(b % BPB) / 8] >> (b % 8)) & 1;
+    if(b < datastart && !marked)
+      error("block %u: metadata block is marked free", b);
+    else if(b >= datastart && used[b] && !marked)
+      error("block %u: used, but marked free", b);
+    else if(b >= datastart && !used[b] && marked)
+      error("block %u: marked used, but no inode uses it", b);
+  }
+}
+
+// Checks that the inode bitmap marks exactly inode 0, the inodes that hold
+// checksums, and the inodes in use.
+void
+check_ibitmap(void)
+{
+  uchar buf[BSIZE];
+  uint inum;
+  int marked, inuse;
+
+  for(inum = 0; inum < sb.ninodes; inum++){
+    if(inum % BPB == 0)
+      rblock(IBBLOCK(inum, sb), buf);
+    marked = (buf[(inum % BPB) / 8] >> (inum % 8)) & 1;
+    inuse = inum == 0 || checksum_inode(inum) || inodes[inum].type != 0;
+    if(inuse && !marked)
+      error("inode %u: in use, but marked free", inum);
+    else if(!inuse && marked)
+      error("inode %u: marked used, but free", inum);
+  }
+}
+
+// Checks the entries of directory inum.
+void
+check_dir(uint inum)
+{
+  char buf[BSIZE];
+  struct dirent *de;
+  uint off, b, child;
+
+  for(off = 0; off < inodes[inum].size; off += sizeof(*de)){
+    if(off % BSIZE == 0){
+      b = bmap(inum, off / BSIZE);
+      if(b == 0 || b >= sb.size)
+        memset(buf, 0, BSIZE);
+      else {
+        rblock(b, buf);
+        if(!sealed(buf))
+          error("directory %u: checksum mismatch in block %u", inum, b);
+      }
+    }
+    de = (struct dirent*)(buf + off % BSIZE);
+    if((sb.flags & FS_CHECKSUMS) && off % BSIZE == BSIZE - sizeof(*de))
+      continue;
+    if(off == 0 && (de->inum != inum || strncmp(de->name, ".", DIRSIZ) != 0))
+      error("directory %u: first entry is not \".\"", inum);
+    if(off == sizeof(*de) && strncmp(de->name, "..", DIRSIZ) != 0)
+      error("directory %u: second entry is not \"..\"", inum);
+    if(de->inum == 0)
+      continue;
+    child = de->inum;
+    if(child >= sb.ninodes || checksum_inode(child) || inodes[child].type == 0){
+      error("directory %u: entry %.*s refers to free inode %u", inum, DIRSIZ, de->name, child);
+      continue;
+    }
+    if(strncmp(de->name, ".", DIRSIZ) == 0)
+      continue;
+    nrefs[child]++;
+    if(strncmp(de->name, "..", DIRSIZ) != 0 && inodes[child].type == T_DIR){
+      if(parent[child] != 0)
+        error("directory %u: is in directories %u and %u", child, parent[child], inum);
+      parent[child] = inum;
+    }
+  }
+}
+
+void
+check_dirs(void)
+{
+  char buf[BSIZE];
+  struct dirent de;
+  uint inum, b, dotdot;
+
+  for(inum = 1; inum < sb.ninodes; inum++)
+    if(inodes[inum].type == T_DIR)
+      check_dir(inum);
+
+  // ".." of each directory is the directory that it is in.
+  parent[ROOTINO] = ROOTINO;
+  for(inum = 1; inum < sb.ninodes; inum++){
+    if(inodes[inum].type != T_DIR || inodes[inum].size < 2 * sizeof(de))
+      continue;
+    b = bmap(inum, 0);
+    if(b == 0 || b >= sb.size)
+      continue;
+    rblock(b, buf);
+    memmove(&de, buf + sizeof(de), sizeof(de));
+    dotdot = de.inum;
+    if(parent[inum] == 0)
+      error("directory %u: is in no directory", inum);
+    else if(dotdot != parent[inum])
+      error("directory %u: \"..\" is %u, but it is in %u", inum, dotdot, parent[inum]);
+  }
+
+  for(inum = 1; inum < sb.ninodes; inum++)
+    if(inodes[inum].type != 0 && inodes[inum].nlink != nrefs[inum])
+      error("inode %u: nlink is %d, but %d entries refer to it",
+            inum, inodes[inum].nlink, nrefs[inum]);
+}
+
+int
+main(int argc, char *argv[])
+{
+  char buf[BSIZE];
+  uint checksum;
+
+  if(argc != 2){
+    fprintf(stderr, "Usage: fsck fs.img\n");
+    exit(1);
+  }
+  img = fopen(argv[1], "rb");
+  if(img == 0){
+    perror(argv[1]);
+    exit(1);
+  }
+
+  rblock(1, buf);
+  memmove(&sb, buf, sizeof(sb));
+  if(sb.magic != FSMAGIC){
+    fprintf(stderr, "fsck: %s has no file system\n", argv[1]);
+    exit(1);
+  }
+  if(sb.flags & FS_CHECKSUMS){
+    checksum = crc32c(&sb, sizeof(sb) - sizeof(sb.checksum));
+    if(checksum != sb.checksum)
+      error("superblock: checksum mismatch");
+  }
+  datastart = sb.bmapstart + sb.size / BPB + 1;
+  if(sb.inodestart + (sb.ninodes + IPB - 1) / IPB > sb.ibmapstart ||
+     sb.ibmapstart + (sb.ninodes + BPB - 1) / BPB > sb.bmapstart || datastart > sb.size){
+    fprintf(stderr, "fsck: %s has an invalid superblock\n", argv[1]);
+    exit(1);
+  }
+
+  used = calloc(sb.size, sizeof(*used));
+  nrefs = calloc(sb.ninodes, sizeof(*nrefs));
+  parent = calloc(sb.ninodes, sizeof(*parent));
+  inodes = calloc(sb.ninodes, sizeof(*inodes));
+  if(used == 0 || nrefs == 0 || parent == 0 || inodes == 0){
+    fprintf(stderr, "fsck: out of memory\n");
+    exit(1);
+  }
+
+  check_inodes();
+  check_bitmap();
+  check_ibitmap();
+  check_dirs();
+
+  printf("fsck: %s: %d errors%s\n", argv[1], errors,
+         (sb.flags & FS_CHECKSUMS) ? ", with checksums" : "");
+  exit(errors ? 1 : 0);
+}
@@ -0,0 +1,111 @@
+#include <stdio.h>
+#include <unistd.h>
+#include <stdlib.h>
+#include <string.h>
+#include <fcntl.h>
+
+#define stat xv6_stat  // avoid clash with host struct stat
+#include "kernel/types.h"
+#include "kernel/param.h"
+#include "kernel/fs.h"
+#include "kernel/kdump.h"
+
+// kdump fs.img [dir]
+// Prints the crash dump that the kernel wrote to the dump partition of the
+// disk image: the panic message, the registers of the CPU that panicked, and
+// the kernel log. Given a directory, also saves each memory region of the
+// dump there, as <address>.bin, e.g. for
+// gdb-multiarch -ex "restore dir/0x80023000.bin binary 0x80023000".
+
+int fd;
+long part;  // Offset of the dump partition in the image
+
+void
+rblock(uint64 block, void *buf, long n)
+{
+  if(pread(fd, buf, n, part + block * BSIZE) != n){
+    perror("read");
+    exit(1);
+  }
+}
+
+// Copies n bytes from the given block of the partition to the file out.
+void
+copy(uint64 block, uint64 n, FILE *out)
+{
+  char buf[BSIZE];
+  long len;
+
+  while(n > 0){
+    len = n < BSIZE ? n : BSIZE;
+    rblock(block++, buf, len);
+    fwrite(buf, 1, len, out);
+    n -= len;
+  }
+}
+
+int
+main(int argc, char *argv[])
+{
+  static char *regs[] = KDUMP_REG_NAMES;
+  char path[512];
+  struct kdumphdr h;
+  struct kdumpregion *r;
+  FILE *out;
+  long size;
+  int i;
+
+  if(argc < 2 || argc > 3){
+    fprintf(stderr, "Usage: kdump fs.img [dir]\n");
+    exit(1);
+  }
+  fd = open(argv[1], O_RDONLY);
+  if(fd < 0){
+    perror(argv[1]);
+    exit(1);
+  }
+  size = lseek(fd, 0, SEEK_END);
+  if(size < (long)DUMPSIZE * BSIZE){
+    fprintf(stderr, "kdump: %s has no dump partition\n", argv[1]);
+    exit(1);
+  }
+  part = size / BSIZE * BSIZE - (long)DUMPSIZE * BSIZE;
+
+  rblock(0, &h, sizeof(h));
+  if(h.magic == KDUMP_EMPTY){
+    printf("kdump: no crash dump\n");
+    exit(0);
+  }
+  if(h.magic != KDUMP_MAGIC || h.nregion > KDUMP_NREGION){
+    fprintf(stderr, "kdump: %s has no dump partition\n", argv[1]);
+    exit(1);
+  }
+
+  h.msg[KDUMP_MSGLEN - 1] = 0;
+  printf("panic on CPU %u at %lu.%09lu: %s\n", h.cpu, (unsigned long)(h.uptime / 1000000000),
+         (unsigned long)(h.uptime % 1000000000), h.msg);
+  for(i = 0; i < KDUMP_NREGS; i++)
+    printf("%-8s0x%016lx\n", regs[i], (unsigned long)h.regs[i]);
+  for(i = 0; i < h.nregion; i++){
+    r = &h.regions[i];
+    printf("region 0x%lx-0x%lx\n", (unsigned long)r->addr, (unsigned long)(r->addr + r->len));
+  }
+  printf("log:\n");
+  fflush(stdout);
+  copy(h.logblock, h.loglen, stdout);
+
+  if(argc == 3){
+    for(i = 0; i < h.nregion; i++){
+      r = &h.regions[i];
+      snprintf(path, sizeof(path), "%s/0x%lx.bin", argv[2], (unsigned long)r->addr);
+      out = fopen(path, "w");
+      if(out == 0){
+        perror(path);
+        exit(1);
+      }
+      copy(r->block, r->len, out);
+      fclose(out);
+    }
+  }
+  exit(0);
+}
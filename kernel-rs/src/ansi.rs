@@ -0,0 +1,350 @@
+//! ANSI escape sequences in console output.
+//!
+//! A serial console passes output through unchanged, since the terminal on the other end
+//! interprets escape sequences itself. A console that draws text by itself, such as a
+//! framebuffer console, instead feeds its output to a `TextScreen`, which interprets the basic
+//! CSI sequences that curses-like programs use:
+//! * `ESC [ n A`, `B`, `C`, `D` -- move the cursor up, down, forward, back
+//! * `ESC [ row ; col H` or `f` -- move the cursor to a position, counting from 1
+//! * `ESC [ col G` -- move the cursor to a column
+//! * `ESC [ n J` -- erase below (0), above (1), or the whole screen (2)
+//! * `ESC [ n K` -- erase to the end (0), to the start (1), or the whole line (2)
+//! * `ESC [ ... m` -- set graphic rendition: reset, bold, reverse, and the 16 colors
+//!
+//! Other sequences are consumed and ignored.
+
+use core::{cmp, mem};
+
+/// ASCII escape.
+const ESC: u8 = 0x1b;
+
+/// Maximum number of parameters of a CSI sequence. Later parameters are ignored.
+const MAX_PARAMS: usize = 8;
+
+/// What the parser is in the middle of.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    /// Ordinary text.
+    Ground,
+    /// After ESC.
+    Escape,
+    /// After ESC [, reading parameters.
+    Csi,
+    /// Inside a CSI sequence that will be ignored, e.g. one with a private marker.
+    CsiIgnore,
+}
+
+/// An action that console output asks for.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// Print a character.
+    Print(u8),
+    /// Execute a control character, such as newline or backspace.
+    Control(u8),
+    /// Execute the CSI sequence ending with this byte, with the parameters that
+    /// `Parser::params` returns.
+    Csi(u8),
+}
+
+/// Splits console output into characters, control characters, and CSI sequences.
+pub struct Parser {
+    state: State,
+    params: [u16; MAX_PARAMS],
+    len: usize,
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            len: 0,
+        }
+    }
+
+    /// Returns the parameters of the last CSI sequence. A parameter that was omitted is zero.
+    pub fn params(&self) -> &[u16] {
+        &self.params[..cmp::min(self.len, MAX_PARAMS)]
+    }
+
+    /// Feeds a byte of output. Returns the action it completes, if any.
+    pub fn feed(&mut self, c: u8) -> Option<Action> {
+        match self.state {
+            State::Ground => {
+                match c {
+                    ESC => {
+                        self.state = State::Escape;
+                        None
+                    }
+                    0..=0x1f | 0x7f => Some(Action::Control(c)),
+                    _ => Some(Action::Print(c)),
+                }
+            }
+            State::Escape => {
+                self.state = if c == b'[' {
+                    self.params = [0; MAX_PARAMS];
+                    self.len = 0;
+                    State::Csi
+                } else {
+                    // Other escape sequences are two bytes long, and not supported.
+                    State::Ground
+                };
+                None
+            }
+            State::Csi | State::CsiIgnore => {
+                match c {
+                    b'0'..=b'9' => {
+                        if self.len == 0 {
+                            self.len = 1;
+                        }
+                        if let Some(param) = self.params.get_mut(self.len - 1) {
+                            *param = param.saturating_mul(10).saturating_add((c - b'0') as u16);
+                        }
+                        None
+                    }
+                    b';' => {
+                        // The first parameter was empty.
+                        self.len = cmp::max(self.len, 1) + 1;
+                        None
+                    }
+                    // Private markers and intermediate bytes.
+                    b'<'..=b'?' | b' '..=b'/' => {
+                        self.state = State::CsiIgnore;
+                        None
+                    }
+                    0x40..=0x7e => {
+                        let ignore = self.state == State::CsiIgnore;
+                        self.state = State::Ground;
+                        if ignore {
+                            None
+                        } else {
+                            Some(Action::Csi(c))
+                        }
+                    }
+                    // Control characters are executed even in the middle of a sequence.
+                    0..=0x1f if c != ESC => Some(Action::Control(c)),
+                    ESC => {
+                        self.state = State::Escape;
+                        None
+                    }
+                    _ => {
+                        self.state = State::Ground;
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// One of the 16 ANSI colors: black, red, green, yellow, blue, magenta, cyan, white, and their
+/// bright versions.
+pub type Color = u8;
+
+/// The color of text that has not set its colors.
+pub const DEFAULT_FG: Color = 7;
+pub const DEFAULT_BG: Color = 0;
+
+/// A character on a text screen and its colors.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Cell {
+    pub c: u8,
+    pub fg: Color,
+    pub bg: Color,
+}
+
+impl Cell {
+    /// A blank cell with the given background.
+    const fn blank(bg: Color) -> Self {
+        Self {
+            c: b' ',
+            fg: DEFAULT_FG,
+            bg,
+        }
+    }
+}
+
+/// Something that shows a grid of `Cell`s, such as a framebuffer with a font.
+pub trait TextDisplay {
+    /// Returns the number of rows and columns.
+    fn size(&self) -> (usize, usize);
+
+    /// Draws `cell` at a position.
+    fn draw(&mut self, row: usize, col: usize, cell: Cell);
+
+    /// Moves every row up by one, and fills the last row with `blank`.
+    fn scroll_up(&mut self, blank: Cell);
+}
+
+/// The cursor and the rendition of console output on a `TextDisplay`.
+pub struct TextScreen {
+    parser: Parser,
+    row: usize,
+    col: usize,
+    fg: Color,
+    bg: Color,
+    bold: bool,
+    reverse: bool,
+}
+
+impl TextScreen {
+    pub const fn new() -> Self {
+        Self {
+            parser: Parser::new(),
+            row: 0,
+            col: 0,
+            fg: DEFAULT_FG,
+            bg: DEFAULT_BG,
+            bold: false,
+            reverse: false,
+        }
+    }
+
+    /// Interprets a byte of console output, drawing on `display`.
+    pub fn write<D: TextDisplay>(&mut self, c: u8, display: &mut D) {
+        match self.parser.feed(c) {
+            Some(Action::Print(c)) => self.print(c, display),
+            Some(Action::Control(c)) => self.control(c, display),
+            Some(Action::Csi(final_byte)) => {
+                let mut params = [0; MAX_PARAMS];
+                let len = self.parser.params().len();
+                params[..len].copy_from_slice(self.parser.params());
+                self.csi(final_byte, &params[..len], display)
+            }
+            None => {}
+        }
+    }
+
+    fn print<D: TextDisplay>(&mut self, c: u8, display: &mut D) {
+        let (_, cols) = display.size();
+        if self.col >= cols {
+            // Wrap only when the next character comes, so that writing the last column does
+            // not scroll.
+            self.col = 0;
+            self.newline(display);
+        }
+        let (mut fg, mut bg) = (self.fg, self.bg);
+        if self.bold && fg < 8 {
+            fg += 8;
+        }
+        if self.reverse {
+            mem::swap(&mut fg, &mut bg);
+        }
+        display.draw(self.row, self.col, Cell { c, fg, bg });
+        self.col += 1;
+    }
+
+    fn newline<D: TextDisplay>(&mut self, display: &mut D) {
+        let (rows, _) = display.size();
+        if self.row + 1 < rows {
+            self.row += 1;
+        } else {
+            display.scroll_up(Cell::blank(self.bg));
+        }
+    }
+
+    fn control<D: TextDisplay>(&mut self, c: u8, display: &mut D) {
+        let (_, cols) = display.size();
+        match c {
+            // The kernel and user programs end lines with a newline alone.
+            b'\n' => {
+                self.col = 0;
+                self.newline(display);
+            }
+            b'\r' => self.col = 0,
+            // Backspace
+            8 => self.col = self.col.saturating_sub(1),
+            b'\t' => self.col = cmp::min((self.col / 8 + 1) * 8, cols - 1),
+            _ => {}
+        }
+    }
+
+    /// Fills the cells from `(row, from)` to `(row, to)`, exclusive, with blanks.
+    fn erase<D: TextDisplay>(&self, row: usize, from: usize, to: usize, display: &mut D) {
+        for col in from..to {
+            display.draw(row, col, Cell::blank(self.bg));
+        }
+    }
+
+    fn csi<D: TextDisplay>(&mut self, final_byte: u8, params: &[u16], display: &mut D) {
+        let (rows, cols) = display.size();
+        let param = |i: usize| params.get(i).copied().unwrap_or(0) as usize;
+        // Movements treat an omitted or zero count as 1.
+        let count = cmp::max(param(0), 1);
+        match final_byte {
+            b'A' => self.row = self.row.saturating_sub(count),
+            b'B' => self.row = cmp::min(self.row + count, rows - 1),
+            b'C' => self.col = cmp::min(self.col + count, cols - 1),
+            b'D' => self.col = cmp::min(self.col, cols - 1).saturating_sub(count),
+            b'H' | b'f' => {
+                self.row = cmp::min(cmp::max(param(0), 1), rows) - 1;
+                self.col = cmp::min(cmp::max(param(1), 1), cols) - 1;
+            }
+            b'G' => self.col = cmp::min(count, cols) - 1,
+            b'J' => {
+                let col = cmp::min(self.col, cols);
+                match param(0) {
+                    0 => {
+                        self.erase(self.row, col, cols, display);
+                        for row in self.row + 1..rows {
+                            self.erase(row, 0, cols, display);
+                        }
+                    }
+                    1 => {
+                        for row in 0..self.row {
+                            self.erase(row, 0, cols, display);
+                        }
+                        self.erase(self.row, 0, cmp::min(col + 1, cols), display);
+                    }
+                    2 => {
+                        for row in 0..rows {
+                            self.erase(row, 0, cols, display);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            b'K' => {
+                let col = cmp::min(self.col, cols);
+                match param(0) {
+                    0 => self.erase(self.row, col, cols, display),
+                    1 => self.erase(self.row, 0, cmp::min(col + 1, cols), display),
+                    2 => self.erase(self.row, 0, cols, display),
+                    _ => {}
+                }
+            }
+            b'm' => {
+                if params.is_empty() {
+                    self.sgr(0);
+                }
+                for &p in params {
+                    self.sgr(p);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Applies a parameter of Select Graphic Rendition.
+    fn sgr(&mut self, p: u16) {
+        match p {
+            0 => {
+                self.fg = DEFAULT_FG;
+                self.bg = DEFAULT_BG;
+                self.bold = false;
+                self.reverse = false;
+            }
+            1 => self.bold = true,
+            7 => self.reverse = true,
+            22 => self.bold = false,
+            27 => self.reverse = false,
+            30..=37 => self.fg = (p - 30) as Color,
+            39 => self.fg = DEFAULT_FG,
+            40..=47 => self.bg = (p - 40) as Color,
+            49 => self.bg = DEFAULT_BG,
+            90..=97 => self.fg = (p - 90 + 8) as Color,
+            100..=107 => self.bg = (p - 100 + 8) as Color,
+            _ => {}
+        }
+    }
+}
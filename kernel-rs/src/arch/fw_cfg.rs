@@ -0,0 +1,145 @@
+//! QEMU's firmware configuration device, through which QEMU passes named files to the guest,
+//! and the guest configures some devices, such as ramfb.
+//!
+//! See docs/specs/fw_cfg.txt of QEMU. Every register and structure of the device is big-endian.
+
+use core::{
+    ptr,
+    sync::atomic::{fence, Ordering},
+};
+
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::arch::memlayout::FW_CFG;
+
+/// Data register. Each read returns the next byte of the selected item.
+const DATA: usize = 0x00;
+/// Selector register, which selects the item to access.
+const SELECTOR: usize = 0x08;
+/// DMA address register. Writing the address of a `DmaAccess` starts it.
+const DMA_ADDRESS: usize = 0x10;
+
+/// Item that holds "QEMU".
+const SIGNATURE: u16 = 0x00;
+/// Item that holds the feature bitmap.
+const ID: u16 = 0x01;
+/// Item that holds the directory of named files.
+const FILE_DIR: u16 = 0x19;
+
+/// Feature bit of `ID`: the DMA interface is available.
+const FEATURE_DMA: u32 = 1 << 1;
+
+/// Control bits of `DmaAccess`.
+const DMA_ERROR: u32 = 1 << 0;
+const DMA_SELECT: u32 = 1 << 3;
+const DMA_WRITE: u32 = 1 << 4;
+
+/// `struct FWCfgDmaAccess`.
+#[derive(AsBytes, FromBytes)]
+#[repr(C)]
+struct DmaAccess {
+    control: u32,
+    length: u32,
+    address: u64,
+}
+
+/// `struct FWCfgFile`, an entry of the file directory.
+#[derive(AsBytes, FromBytes)]
+#[repr(C)]
+struct FileEntry {
+    size: u32,
+    select: u16,
+    reserved: u16,
+    name: [u8; 56],
+}
+
+/// A named file of the device.
+pub struct FwCfgFile {
+    select: u16,
+    size: usize,
+}
+
+impl FwCfgFile {
+    /// Returns the file named `name`, or `None` if there is none.
+    pub fn find(name: &str) -> Option<Self> {
+        let mut signature = [0; 4];
+        read(SIGNATURE, &mut signature);
+        if &signature != b"QEMU" {
+            return None;
+        }
+        let mut count = 0u32;
+        read(FILE_DIR, count.as_bytes_mut());
+        for _ in 0..u32::from_be(count) {
+            let mut entry = FileEntry::new_zeroed();
+            read_next(entry.as_bytes_mut());
+            let len = entry.name.iter().position(|c| *c == 0).unwrap_or(56);
+            if &entry.name[..len] == name.as_bytes() {
+                return Some(Self {
+                    select: u16::from_be(entry.select),
+                    size: u32::from_be(entry.size) as usize,
+                });
+            }
+        }
+        None
+    }
+
+    /// Returns the size of the file, in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Reads the beginning of the file into `buf`.
+    pub fn read(&self, buf: &mut [u8]) {
+        read(self.select, buf);
+    }
+
+    /// Writes `data` at the beginning of the file, which only some files allow.
+    /// Returns Ok(()) on success, Err(()) if the device rejects it.
+    pub fn write(&self, data: &[u8]) -> Result<(), ()> {
+        let mut features = 0u32;
+        read(ID, features.as_bytes_mut());
+        if u32::from_be(features) & FEATURE_DMA == 0 {
+            return Err(());
+        }
+        let mut access = DmaAccess {
+            control: (((self.select as u32) << 16) | DMA_SELECT | DMA_WRITE).to_be(),
+            length: (data.len() as u32).to_be(),
+            address: (data.as_ptr() as u64).to_be(),
+        };
+        let access = &mut access as *mut DmaAccess;
+        fence(Ordering::SeqCst);
+        // SAFETY: FW_CFG is identically mapped from physical address, and the registers are for
+        // MMIO. `access` and `data` are identically mapped too, and live until the device
+        // clears `access.control`.
+        unsafe {
+            ptr::write_volatile((FW_CFG + DMA_ADDRESS) as *mut u64, (access as u64).to_be());
+            loop {
+                let control = u32::from_be(ptr::read_volatile(ptr::addr_of!((*access).control)));
+                if control & DMA_ERROR != 0 {
+                    return Err(());
+                }
+                if control == 0 {
+                    break;
+                }
+            }
+        }
+        fence(Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// Selects `item`, and reads its beginning into `buf`.
+fn read(item: u16, buf: &mut [u8]) {
+    // SAFETY: FW_CFG is identically mapped from physical address, and the registers are for MMIO.
+    unsafe { ptr::write_volatile((FW_CFG + SELECTOR) as *mut u16, item.to_be()) };
+    read_next(buf);
+}
+
+/// Reads the next bytes of the selected item into `buf`.
+fn read_next(buf: &mut [u8]) {
+    for b in buf {
+        // SAFETY: FW_CFG is identically mapped from physical address, and the registers are for
+        // MMIO.
+        *b = unsafe { ptr::read_volatile((FW_CFG + DATA) as *const u8) };
+    }
+}
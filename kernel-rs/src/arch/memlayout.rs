@@ -4,10 +4,16 @@
 //! based on qemu's hw/riscv/virt.c:
 //!
 //! 00001000 -- boot ROM, provided by qemu
+//! 00101000 -- goldfish RTC
+//! 03000000 -- PCIe I/O port window
 //! 02000000 -- CLINT
 //! 0C000000 -- PLIC
 //! 10000000 -- uart0
 //! 10001000 -- virtio disk
+//! 10002000 -- virtio entropy source
+//! 10003000 -- virtio socket device
+//! 10100000 -- firmware configuration device
+//! 30000000 -- PCIe configuration space
 //! 80000000 -- boot ROM jumps here in machine mode
 //!             -kernel loads the kernel here
 //! unused RAM after 80000000.
@@ -24,6 +30,9 @@ use crate::arch::addr::{MAXVA, PGSIZE};
 /// SiFive Test Finisher. (virt device only)
 pub const FINISHER: usize = 0x100000;
 
+/// Goldfish real-time clock.
+pub const RTC: usize = 0x101000;
+
 /// qemu puts UART registers here in physical memory.
 pub const UART0: usize = 0x10000000;
 pub const UART0_IRQ: usize = 10;
@@ -32,8 +41,34 @@ pub const UART0_IRQ: usize = 10;
 pub const VIRTIO0: usize = 0x10001000;
 pub const VIRTIO0_IRQ: usize = 1;
 
+/// virtio mmio interface of the entropy source, which is polled.
+pub const VIRTIO1: usize = 0x10002000;
+
+/// virtio mmio interface of the socket device.
+pub const VIRTIO2: usize = 0x10003000;
+pub const VIRTIO2_IRQ: usize = 3;
+
+/// PCIe I/O ports are mapped here.
+pub const PCIE_PIO: usize = 0x3000000;
+pub const PCIE_PIO_SIZE: usize = 0x10000;
+
+/// PCIe configuration space of the devices on bus 0.
+pub const PCIE_ECAM: usize = 0x30000000;
+
+/// The first of the 4 interrupts of PCIe devices.
+pub const PCIE_IRQ: usize = 32;
+
+/// QEMU's firmware configuration device.
+pub const FW_CFG: usize = 0x10100000;
+
 /// core local interruptor (CLINT), which contains the timer.
 pub const CLINT: usize = 0x2000000;
+
+/// Writing 1 raises a machine-mode software interrupt on the hart.
+pub const fn clint_msip(hartid: usize) -> usize {
+    CLINT.wrapping_add(hartid.wrapping_mul(4))
+}
+
 pub const fn clint_mtimecmp(hartid: usize) -> usize {
     CLINT
         .wrapping_add(0x4000)
@@ -84,6 +119,14 @@ pub const fn kstack(p: usize) -> usize {
 ///   fixed-size stack
 ///   expandable heap
 ///   ...
+///   VDSO (the page the kernel shares with the process)
+///   IORING (the rings of ioring_setup(), if any)
 ///   TRAPFRAME (p->trapframe, used by the trampoline)
 ///   TRAMPOLINE (the same page as in the kernel)
 pub const TRAPFRAME: usize = TRAMPOLINE.wrapping_sub(PGSIZE);
+
+/// map the rings of ioring_setup() beneath the trapframe.
+pub const IORING: usize = TRAPFRAME.wrapping_sub(PGSIZE);
+
+/// map the page that the kernel shares with the process beneath the rings.
+pub const VDSO: usize = IORING.wrapping_sub(PGSIZE);
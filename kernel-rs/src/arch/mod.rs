@@ -1,7 +1,10 @@
 //! Architecture-dependent code.
 
 pub mod addr;
+pub mod fw_cfg;
 pub mod memlayout;
+pub mod pci;
 pub mod plic;
 pub mod poweroff;
 pub mod riscv;
+pub mod rtc;
@@ -0,0 +1,102 @@
+//! The PCI Express host bridge of QEMU's virt machine, enough to find devices on bus 0 and set
+//! up their I/O port ranges.
+//!
+//! The bridge has no firmware that sets up devices, so the kernel assigns each device it uses an
+//! address in the I/O port window, which the bridge maps into memory at PCIE_PIO.
+
+use core::ptr;
+
+use crate::arch::memlayout::{PCIE_ECAM, PCIE_IRQ, PCIE_PIO};
+
+/// Offsets in the configuration space of a device.
+const VENDOR_ID: usize = 0x00;
+const DEVICE_ID: usize = 0x02;
+const COMMAND: usize = 0x04;
+const BAR0: usize = 0x10;
+const INTERRUPT_PIN: usize = 0x3d;
+
+/// Bit of `COMMAND`: the device responds to I/O port accesses.
+const COMMAND_IO: u16 = 1 << 0;
+
+/// Bit of a base address register: the range is in the I/O port space.
+const BAR_IO: u32 = 1 << 0;
+
+/// Number of devices on a bus.
+const DEVICES: usize = 32;
+
+/// Vendor ID read from a slot that has no device.
+const NO_VENDOR: u16 = 0xffff;
+
+/// A device on bus 0, function 0.
+pub struct PciDevice {
+    /// Address of the configuration space.
+    config: usize,
+    slot: usize,
+}
+
+impl PciDevice {
+    /// Returns the devices on the bus.
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..DEVICES)
+            .map(|slot| {
+                Self {
+                    config: PCIE_ECAM + (slot << 15),
+                    slot,
+                }
+            })
+            .filter(|dev| dev.vendor_id() != NO_VENDOR)
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read16(VENDOR_ID)
+    }
+
+    pub fn device_id(&self) -> u16 {
+        self.read16(DEVICE_ID)
+    }
+
+    /// Places the I/O port range of the first base address register at `port`, and enables I/O
+    /// port accesses to the device.
+    /// Returns the address at which the range is mapped, or `None` if the register is not for
+    /// I/O ports.
+    pub fn map_io(&self, port: u32) -> Option<usize> {
+        if self.read32(BAR0) & BAR_IO == 0 {
+            return None;
+        }
+        self.write32(BAR0, port);
+        self.write16(COMMAND, self.read16(COMMAND) | COMMAND_IO);
+        Some(PCIE_PIO + port as usize)
+    }
+
+    /// Returns the PLIC interrupt of the device, or `None` if it does not interrupt.
+    pub fn irq(&self) -> Option<usize> {
+        // SAFETY: see `PciDevice::read32`.
+        let pin = unsafe { ptr::read_volatile((self.config + INTERRUPT_PIN) as *const u8) };
+        if pin == 0 {
+            return None;
+        }
+        // The bridge rotates INTA-INTD of each slot onto its 4 interrupts.
+        Some(PCIE_IRQ + (pin as usize - 1 + self.slot) % 4)
+    }
+
+    fn read16(&self, off: usize) -> u16 {
+        // SAFETY: see `PciDevice::read32`.
+        unsafe { ptr::read_volatile((self.config + off) as *const u16) }
+    }
+
+    fn write16(&self, off: usize, value: u16) {
+        // SAFETY: see `PciDevice::read32`.
+        unsafe { ptr::write_volatile((self.config + off) as *mut u16, value) }
+    }
+
+    fn read32(&self, off: usize) -> u32 {
+        // SAFETY: the configuration space is for MMIO. It is accessed only before paging is
+        // turned on, since the kernel does not map it.
+        unsafe { ptr::read_volatile((self.config + off) as *const u32) }
+    }
+
+    fn write32(&self, off: usize, value: u32) {
+        // SAFETY: see `PciDevice::read32`.
+        unsafe { ptr::write_volatile((self.config + off) as *mut u32, value) }
+    }
+}
@@ -1,25 +1,41 @@
 //! the riscv Platform Level Interrupt Controller (PLIC).
 use crate::arch::{
-    memlayout::{plic_sclaim, plic_senable, plic_spriority, PLIC, UART0_IRQ, VIRTIO0_IRQ},
+    memlayout::{plic_sclaim, plic_senable, plic_spriority, PLIC},
     riscv::r_tp,
 };
 
-pub unsafe fn plicinit() {
+/// `irqs` are the interrupts that have handlers.
+pub unsafe fn plicinit(irqs: impl Iterator<Item = usize>) {
     // set desired IRQ priorities non-zero (otherwise disabled).
-    unsafe { *((PLIC.wrapping_add(UART0_IRQ.wrapping_mul(4))) as *mut u32) = 1 };
-    unsafe { *((PLIC + VIRTIO0_IRQ * 4) as *mut u32) = 1 };
+    for irq in irqs {
+        unsafe { *((PLIC + irq * 4) as *mut u32) = 1 };
+    }
 }
 
-pub unsafe fn plicinithart() {
+/// `irqs` are the interrupts that this hart takes.
+pub unsafe fn plicinithart(irqs: impl Iterator<Item = usize>) {
     let hart: usize = r_tp();
 
-    // set uart's enable bit for this hart's S-mode.
-    unsafe { *(plic_senable(hart) as *mut u32) = (1 << UART0_IRQ | 1 << VIRTIO0_IRQ) as u32 };
+    // set their enable bits for this hart's S-mode.
+    // Each enable register holds the bits of 32 interrupts.
+    for irq in irqs {
+        unsafe { plic_set_enabled(hart, irq, true) };
+    }
 
     // set this hart's S-mode priority threshold to 0.
     unsafe { *(plic_spriority(hart) as *mut u32) = 0 };
 }
 
+/// enables or disables `irq` for the S-mode of `hart`.
+pub unsafe fn plic_set_enabled(hart: usize, irq: usize, enabled: bool) {
+    let senable = (plic_senable(hart) + irq / 32 * 4) as *mut u32;
+    if enabled {
+        unsafe { *senable |= 1 << (irq % 32) };
+    } else {
+        unsafe { *senable &= !(1 << (irq % 32)) };
+    }
+}
+
 /// ask the PLIC what interrupt we should serve.
 pub unsafe fn plic_claim() -> u32 {
     let hart: usize = r_tp();
@@ -317,6 +317,14 @@ pub fn r_stval() -> usize {
     x
 }
 
+/// Machine-mode Counter-Enable: supervisor mode may read the cycle CSR.
+pub const MCOUNTEREN_CY: u64 = 1 << 0;
+/// Machine-mode Counter-Enable: supervisor mode may read the time CSR.
+pub const MCOUNTEREN_TM: u64 = 1 << 1;
+
+/// Supervisor-mode Counter-Enable: user mode may read the time CSR.
+pub const SCOUNTEREN_TM: u64 = 1 << 1;
+
 /// Machine-mode Counter-Enable.
 #[inline]
 pub unsafe fn w_mcounteren(x: u64) {
@@ -334,6 +342,14 @@ pub fn r_mcounteren() -> u64 {
     x
 }
 
+/// Supervisor-mode Counter-Enable.
+#[inline]
+pub unsafe fn w_scounteren(x: u64) {
+    unsafe {
+        asm!("csrw scounteren, {}", in(reg) x);
+    }
+}
+
 /// Machine-mode cycle counter.
 #[inline]
 pub fn r_time() -> u64 {
@@ -344,6 +360,16 @@ pub fn r_time() -> u64 {
     x
 }
 
+/// Number of cycles the CPU has run, which varies more between interrupts than the time does.
+#[inline]
+pub fn r_cycle() -> u64 {
+    let mut x;
+    unsafe {
+        asm!("csrr {}, cycle", out(reg) x);
+    }
+    x
+}
+
 /// Enable device interrupts.
 #[inline]
 pub unsafe fn intr_on() {
@@ -361,6 +387,15 @@ pub fn intr_off() {
     unsafe { x.write() };
 }
 
+/// Wait for an interrupt. Returns at once if one is pending, even if device interrupts are
+/// disabled.
+#[inline]
+pub fn wfi() {
+    unsafe {
+        asm!("wfi");
+    }
+}
+
 /// Are device interrupts enabled?
 #[inline]
 pub fn intr_get() -> bool {
@@ -387,6 +422,16 @@ pub fn r_sp() -> usize {
     x
 }
 
+/// Reads the frame pointer.
+#[inline]
+pub fn r_fp() -> usize {
+    let mut x;
+    unsafe {
+        asm!("mv {}, s0", out(reg) x);
+    }
+    x
+}
+
 #[inline]
 pub unsafe fn w_tp(x: usize) {
     unsafe {
@@ -403,6 +448,22 @@ pub fn r_ra() -> usize {
     x
 }
 
+/// Synchronize the instruction fetches of this hart with the memory writes before.
+#[inline]
+pub unsafe fn fence_i() {
+    unsafe {
+        asm!("fence.i");
+    }
+}
+
+/// Raise a breakpoint exception.
+#[inline]
+pub fn ebreak() {
+    unsafe {
+        asm!("ebreak");
+    }
+}
+
 /// Flush the TLB.
 #[inline]
 pub unsafe fn sfence_vma() {
@@ -411,3 +472,11 @@ pub unsafe fn sfence_vma() {
         asm!("sfence.vma zero, zero");
     }
 }
+
+/// Flush the TLB entries of the page at `va`.
+#[inline]
+pub unsafe fn sfence_vma_addr(va: usize) {
+    unsafe {
+        asm!("sfence.vma {}, zero", in(reg) va);
+    }
+}
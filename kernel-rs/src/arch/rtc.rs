@@ -0,0 +1,31 @@
+//! Goldfish real-time clock of QEMU's virt machine.
+
+use core::ptr;
+
+use crate::arch::memlayout::RTC;
+
+/// Low 32 bits of the time. Reading it latches the high 32 bits.
+const TIME_LOW: usize = 0x00;
+/// High 32 bits of the time.
+const TIME_HIGH: usize = 0x04;
+
+/// Returns the wall-clock time, in nanoseconds since the Unix epoch.
+pub fn rtc_read() -> u64 {
+    // SAFETY: RTC is identically mapped from physical address, and the registers are for MMIO.
+    // TIME_LOW must be read first.
+    unsafe {
+        let low = ptr::read_volatile((RTC + TIME_LOW) as *const u32);
+        let high = ptr::read_volatile((RTC + TIME_HIGH) as *const u32);
+        ((high as u64) << 32) | low as u64
+    }
+}
+
+/// Sets the wall-clock time to `nanos` since the Unix epoch.
+pub fn rtc_write(nanos: u64) {
+    // SAFETY: see `rtc_read`. Each write replaces its half of the time, so TIME_HIGH is written
+    // first, and the low half is right at the moment the write completes.
+    unsafe {
+        ptr::write_volatile((RTC + TIME_HIGH) as *mut u32, (nanos >> 32) as u32);
+        ptr::write_volatile((RTC + TIME_LOW) as *mut u32, nanos as u32);
+    }
+}
@@ -1,15 +1,20 @@
 //! Array based arena.
 
-use core::{marker::PhantomPinned, ptr::NonNull};
+use core::hash::Hash;
+use core::marker::PhantomPinned;
 
 use array_macro::array;
 use pin_project::pin_project;
 
-use super::{Arena, ArenaObject, ArenaRc, ArenaRef, Handle};
+use super::{
+    hash_index::HashIndex, Arena, ArenaObject, ArenaRc, ArenaRef, ArenaStats, Handle, WeakArena,
+};
 use crate::{
     lock::{SpinLock, SpinLockGuard},
+    some_or,
     util::{
-        static_arc::StaticArc,
+        hash::hash,
+        static_arc::{Ref, StaticArc, Weak},
         strong_pin::{StrongPin, StrongPinMut},
     },
 };
@@ -19,6 +24,9 @@ use crate::{
 pub struct ArrayArena<T, const CAPACITY: usize> {
     #[pin]
     entries: [StaticArc<T>; CAPACITY],
+    /// Index of the entries allocated by `find_or_alloc_keyed`.
+    index: HashIndex<CAPACITY>,
+    stats: ArenaStats,
     #[pin]
     _marker: PhantomPinned,
 }
@@ -26,18 +34,21 @@ pub struct ArrayArena<T, const CAPACITY: usize> {
 impl<T, const CAPACITY: usize> ArrayArena<T, CAPACITY> {
     /// Returns an `ArrayArena` of size `CAPACITY` that is filled with `D`'s const default value.
     /// Note that `D` must `impl const Default`.
+    /// `name` is used to identify the arena in its usage statistics.
     ///
     /// # Examples
     ///
     /// ```rust,no_run
-    /// let arr_arena = ArrayArena::<D, 100>::new();
+    /// let arr_arena = ArrayArena::<D, 100>::new("arena");
     /// ```
     // Note: We cannot use the generic `T` in the following function, since we need to only allow
     // types that `impl const Default`, not just `impl Default`.
     #[allow(clippy::new_ret_no_self)]
-    pub const fn new<D: Default>() -> ArrayArena<D, CAPACITY> {
+    pub const fn new<D: Default>(name: &'static str) -> ArrayArena<D, CAPACITY> {
         ArrayArena {
             entries: array![_ => StaticArc::new(Default::default()); CAPACITY],
+            index: HashIndex::new(),
+            stats: ArenaStats::new(name, CAPACITY),
             _marker: PhantomPinned,
         }
     }
@@ -47,6 +58,32 @@ impl<T, const CAPACITY: usize> ArrayArena<T, CAPACITY> {
         // SAFETY: the pointer is valid, and it creates a unique `StrongPinMut`.
         unsafe { StrongPinMut::new_unchecked(&raw mut (*self.ptr().as_ptr()).entries) }
     }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn entry<'s>(self: StrongPinMut<'s, Self>, index: usize) -> StrongPinMut<'s, StaticArc<T>> {
+        // SAFETY: the pointer is valid, and it creates a unique `StrongPinMut`.
+        unsafe { StrongPinMut::new_unchecked(&raw mut (*self.ptr().as_ptr()).entries[index]) }
+    }
+
+    /// Returns the index of the first empty entry.
+    fn find_empty(mut self: StrongPinMut<'_, Self>) -> Option<usize> {
+        self.as_mut()
+            .entries()
+            .iter_mut()
+            .position(|entry| entry.is_empty())
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn index_mut<'s>(self: StrongPinMut<'s, Self>) -> &'s mut HashIndex<CAPACITY> {
+        // SAFETY: `index` is not structurally pinned, and `self` is a unique `StrongPinMut`.
+        unsafe { &mut (*self.ptr().as_ptr()).index }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn stats_mut<'s>(self: StrongPinMut<'s, Self>) -> &'s mut ArenaStats {
+        // SAFETY: `stats` is not structurally pinned, and `self` is a unique `StrongPinMut`.
+        unsafe { &mut (*self.ptr().as_ptr()).stats }
+    }
 }
 
 impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
@@ -64,51 +101,135 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
             self,
             |arena: ArenaRef<'_, '_, SpinLock<ArrayArena<T, CAPACITY>>>| {
                 let mut guard = arena.strong_pinned_lock();
-                let this = guard.get_strong_pinned_mut();
+                let mut this = guard.get_strong_pinned_mut();
 
-                let mut empty: Option<NonNull<StaticArc<T>>> = None;
-                for mut entry in this.entries().iter_mut() {
-                    if !entry.as_mut().is_borrowed() {
-                        let _ = empty.get_or_insert(entry.ptr());
+                let mut empty = None;
+                for (i, mut entry) in this.as_mut().entries().iter_mut().enumerate() {
+                    if entry.as_mut().is_empty() {
+                        let _ = empty.get_or_insert(i);
                         // Note: Do not use `break` here.
                         // We must first search through all entries, and then alloc at empty
                         // only if the entry we're finding for doesn't exist.
-                    } else if let Some(entry) = entry.as_mut().try_borrow() {
-                        // The entry is not under finalization. Check its data.
-                        if c(&entry) {
-                            let handle = Handle(arena.0.brand(entry));
-                            return Some(ArenaRc::new(arena, handle));
+                    } else if entry.as_mut().is_borrowed() {
+                        if let Some(entry) = entry.as_mut().try_borrow() {
+                            // The entry is not under finalization. Check its data.
+                            if c(&entry) {
+                                let handle = Handle(arena.0.brand(entry));
+                                return Some(ArenaRc::new(arena, handle));
+                            }
                         }
                     }
                 }
 
-                empty.map(|ptr| {
-                    // SAFETY: `ptr` is valid, and there's no `StrongPinMut`.
-                    let mut entry = unsafe { StrongPinMut::new_unchecked(ptr.as_ptr()) };
-                    n(entry.as_mut().get_mut().unwrap());
-                    let handle = Handle(arena.0.brand(entry.borrow()));
-                    ArenaRc::new(arena, handle)
-                })
+                let i = some_or!(empty, {
+                    this.stats_mut().record_failure();
+                    return None;
+                });
+                this.as_mut().index_mut().remove(i);
+                let mut entry = this.as_mut().entry(i);
+                n(entry.as_mut().get_mut().unwrap());
+                let handle = Handle(arena.0.brand(entry.borrow()));
+                this.stats_mut().record_alloc();
+                Some(ArenaRc::new(arena, handle))
             },
         )
     }
 
-    fn alloc<F: FnOnce() -> Self::Data>(self: StrongPin<'_, Self>, f: F) -> Option<ArenaRc<Self>> {
+    fn find_or_alloc_keyed<
+        K: Hash + ?Sized,
+        C: Fn(&Self::Data) -> bool,
+        N: FnOnce(&mut Self::Data),
+    >(
+        self: StrongPin<'_, Self>,
+        key: &K,
+        c: C,
+        n: N,
+    ) -> Option<ArenaRc<Self>> {
+        let hash = hash(key);
         ArenaRef::new(
             self,
             |arena: ArenaRef<'_, '_, SpinLock<ArrayArena<T, CAPACITY>>>| {
                 let mut guard = arena.strong_pinned_lock();
-                let this = guard.get_strong_pinned_mut();
+                let mut this = guard.get_strong_pinned_mut();
 
-                for mut entry in this.entries().iter_mut() {
-                    if let Some(data) = entry.as_mut().get_mut() {
-                        *data = f();
-                        let handle = Handle(arena.0.brand(entry.borrow()));
-                        return Some(ArenaRc::new(arena, handle));
+                let mut cursor = this.index.first(hash);
+                while let Some(i) = cursor {
+                    cursor = this.index.next(i, hash);
+                    let mut entry = this.as_mut().entry(i);
+                    // The index may contain finalized entries, which we must skip.
+                    if entry.as_mut().is_borrowed() {
+                        if let Some(entry) = entry.as_mut().try_borrow() {
+                            // The entry is not under finalization. Check its data.
+                            if c(&entry) {
+                                let handle = Handle(arena.0.brand(entry));
+                                return Some(ArenaRc::new(arena, handle));
+                            }
+                        }
                     }
                 }
-                None
+
+                let i = some_or!(this.as_mut().find_empty(), {
+                    this.stats_mut().record_failure();
+                    return None;
+                });
+                this.as_mut().index_mut().insert(i, hash);
+                let mut entry = this.as_mut().entry(i);
+                n(entry.as_mut().get_mut().unwrap());
+                let handle = Handle(arena.0.brand(entry.borrow()));
+                this.stats_mut().record_alloc();
+                Some(ArenaRc::new(arena, handle))
             },
         )
     }
+
+    fn alloc<F: FnOnce() -> Self::Data>(self: StrongPin<'_, Self>, f: F) -> Option<ArenaRc<Self>> {
+        ArenaRef::new(
+            self,
+            |arena: ArenaRef<'_, '_, SpinLock<ArrayArena<T, CAPACITY>>>| {
+                let mut guard = arena.strong_pinned_lock();
+                let mut this = guard.get_strong_pinned_mut();
+
+                let i = some_or!(this.as_mut().find_empty(), {
+                    this.stats_mut().record_failure();
+                    return None;
+                });
+                this.as_mut().index_mut().remove(i);
+                let mut entry = this.as_mut().entry(i);
+                *entry.as_mut().get_mut().unwrap() = f();
+                let handle = Handle(arena.0.brand(entry.borrow()));
+                this.stats_mut().record_alloc();
+                Some(ArenaRc::new(arena, handle))
+            },
+        )
+    }
+
+    fn dealloc<'id, 'a, 'b>(
+        self: ArenaRef<'id, '_, Self>,
+        handle: Handle<'id, Self::Data>,
+        ctx: <Self::Data as ArenaObject>::Ctx<'a, 'b>,
+    ) {
+        if let Ok(mut rm) = handle.0.into_inner().into_mut() {
+            rm.finalize::<Self>(ctx);
+
+            // Update the statistics before the entry becomes empty,
+            // so that `in_use` never exceeds the number of non-empty entries.
+            let mut guard = self.strong_pinned_lock();
+            guard.get_strong_pinned_mut().stats_mut().record_dealloc();
+            drop(rm);
+        }
+    }
+
+    fn stats(self: StrongPin<'_, Self>) -> ArenaStats {
+        self.strong_pinned_lock().get_strong_pinned_mut().stats
+    }
+}
+
+// SAFETY: an entry is reused only if it `is_empty`, i.e., no `Weak` refers to it.
+unsafe impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> WeakArena
+    for SpinLock<ArrayArena<T, CAPACITY>>
+{
+    fn upgrade(self: StrongPin<'_, Self>, weak: &Weak<Self::Data>) -> Option<Ref<Self::Data>> {
+        let _guard = self.strong_pinned_lock();
+        weak.upgrade()
+    }
 }
@@ -0,0 +1,425 @@
+//! Array based arena that grows by allocating pages from the page allocator.
+
+use core::{
+    marker::PhantomData,
+    mem,
+    pin::Pin,
+    ptr::{self, NonNull},
+};
+
+use array_macro::array;
+use pin_project::pin_project;
+
+use super::{Arena, ArenaObject, ArenaRc, ArenaRef, ArenaStats, Handle};
+use crate::{
+    arch::addr::{pgrounddown, PGSIZE},
+    hal::hal,
+    kasan::{REDZONE, REDZONE_BYTE},
+    ktest, ktest_assert,
+    lock::{SpinLock, SpinLockGuard},
+    page::Page,
+    some_or,
+    util::{
+        intrusive_list::{List, ListEntry, ListNode},
+        static_arc::StaticArc,
+        strong_pin::{StrongPin, StrongPinMut},
+    },
+};
+
+/// A page allocated from `Kmem` that holds additional entries of a `ChunkedArena`.
+///
+/// # Safety
+///
+/// * A `Chunk` is always located at the beginning of a page.
+/// * The header is followed by `Chunk::<T>::LEN` initialized entries, `Chunk::<T>::STRIDE`
+///   bytes apart, starting at `Chunk::<T>::ENTRIES_OFFSET` bytes from the beginning of the page.
+/// * `in_use` equals the number of non-empty entries in this chunk.
+#[pin_project]
+#[repr(C)]
+struct Chunk<T> {
+    #[pin]
+    list_entry: ListEntry,
+    in_use: usize,
+    _marker: PhantomData<StaticArc<T>>,
+}
+
+/// A homogeneous memory allocator equipped with reference counts.
+/// Starts with `CAPACITY` statically allocated entries, and allocates a page of entries from
+/// `Kmem` whenever every entry is in use. The page is returned to `Kmem` when all entries in it
+/// become empty again.
+#[pin_project]
+pub struct ChunkedArena<T, const CAPACITY: usize> {
+    #[pin]
+    entries: [StaticArc<T>; CAPACITY],
+    #[pin]
+    chunks: List<Chunk<T>>,
+    stats: ArenaStats,
+}
+
+// SAFETY: `ChunkedArena` never exposes its internal lists and entries.
+unsafe impl<T: Send, const CAPACITY: usize> Send for ChunkedArena<T, CAPACITY> {}
+
+impl<T> Chunk<T> {
+    /// The offset of the first entry from the beginning of the page.
+    const ENTRIES_OFFSET: usize = (mem::size_of::<Self>() + mem::align_of::<StaticArc<T>>() - 1)
+        & !(mem::align_of::<StaticArc<T>>() - 1);
+    /// The number of entries in a chunk.
+    const LEN: usize = (PGSIZE - Self::ENTRIES_OFFSET) / Self::STRIDE;
+    // The list entry must be located at the beginning of the page.
+    const LIST_ENTRY_OFFSET: usize = 0;
+    /// The distance between entries, which leaves a redzone of at least `REDZONE` bytes after
+    /// each entry.
+    const STRIDE: usize =
+        (mem::size_of::<StaticArc<T>>() + REDZONE + mem::align_of::<StaticArc<T>>() - 1)
+            & !(mem::align_of::<StaticArc<T>>() - 1);
+
+    /// Returns a pointer to the first entry of the chunk.
+    fn entries_ptr(this: NonNull<Self>) -> *mut StaticArc<T> {
+        (this.as_ptr() as usize + Self::ENTRIES_OFFSET) as _
+    }
+
+    /// Returns a pointer to the `i`th entry of the chunk.
+    fn entry_ptr(this: NonNull<Self>, i: usize) -> *mut StaticArc<T> {
+        (Self::entries_ptr(this) as usize + i * Self::STRIDE) as _
+    }
+
+    /// Returns the redzone after the `i`th entry of the chunk.
+    fn redzone(this: NonNull<Self>, i: usize) -> *mut [u8] {
+        let size = mem::size_of::<StaticArc<T>>();
+        let ptr = (Self::entry_ptr(this, i) as usize + size) as *mut u8;
+        ptr::slice_from_raw_parts_mut(ptr, Self::STRIDE - size)
+    }
+
+    /// Panics if the redzone after the `i`th entry of the chunk, or before it, is overwritten.
+    /// `name` is the name of the arena.
+    fn check_redzones(this: NonNull<Self>, i: usize, name: &str) {
+        for j in i.saturating_sub(1)..=i {
+            // SAFETY: the redzone is in the chunk, and no entry covers it.
+            let redzone = unsafe { &*Self::redzone(this, j) };
+            if redzone.iter().any(|b| *b != REDZONE_BYTE) {
+                panic!(
+                    "kasan: {}: redzone after entry {:p} overwritten",
+                    name,
+                    Self::entry_ptr(this, j)
+                );
+            }
+        }
+    }
+
+    /// Returns the index of the entry at `entry` in the chunk.
+    fn index_of(this: NonNull<Self>, entry: NonNull<StaticArc<T>>) -> usize {
+        (entry.as_ptr() as usize - Self::entries_ptr(this) as usize) / Self::STRIDE
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn entries<'s>(
+        self: StrongPinMut<'s, Self>,
+    ) -> impl Iterator<Item = StrongPinMut<'s, StaticArc<T>>> + 's {
+        let this = self.ptr();
+        // SAFETY: the invariant of `Chunk`, and `self` is a unique `StrongPinMut`.
+        (0..Self::LEN)
+            .map(move |i| unsafe { StrongPinMut::new_unchecked(Self::entry_ptr(this, i)) })
+    }
+}
+
+// SAFETY: `Chunk` owns a `ListEntry`.
+unsafe impl<T> ListNode for Chunk<T> {
+    fn get_list_entry(self: Pin<&Self>) -> Pin<&ListEntry> {
+        unsafe { Pin::new_unchecked(&self.get_ref().list_entry) }
+    }
+
+    fn from_list_entry(list_entry: *const ListEntry) -> *const Self {
+        (list_entry as usize - Self::LIST_ENTRY_OFFSET) as *const Self
+    }
+}
+
+impl<T, const CAPACITY: usize> ChunkedArena<T, CAPACITY> {
+    /// Returns a `ChunkedArena` that initially has `CAPACITY` entries filled with `D`'s const default value.
+    /// Note that `D` must `impl const Default`.
+    /// `name` is used to identify the arena in its usage statistics.
+    ///
+    /// # Examples
+    ///
+    /// ```rust,no_run
+    /// let chunked_arena = ChunkedArena::<D, 100>::new("arena");
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// Must be used only after initializing it with `ChunkedArena::init`.
+    // Note: We cannot use the generic `T` in the following function, since we need to only allow
+    // types that `impl const Default`, not just `impl Default`.
+    #[allow(clippy::new_ret_no_self)]
+    pub const unsafe fn new<D: Default>(name: &'static str) -> ChunkedArena<D, CAPACITY> {
+        ChunkedArena {
+            entries: array![_ => StaticArc::new(Default::default()); CAPACITY],
+            chunks: unsafe { List::new() },
+            stats: ArenaStats::new(name, CAPACITY),
+        }
+    }
+
+    pub fn init(self: Pin<&mut Self>) {
+        self.project().chunks.init();
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn stats_mut<'s>(self: StrongPinMut<'s, Self>) -> &'s mut ArenaStats {
+        // SAFETY: `stats` is not structurally pinned, and `self` is a unique `StrongPinMut`.
+        unsafe { &mut (*self.ptr().as_ptr()).stats }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn chunks<'s>(self: StrongPinMut<'s, Self>) -> StrongPinMut<'s, List<Chunk<T>>> {
+        // SAFETY: the pointer is valid, and it creates a unique `StrongPinMut`.
+        unsafe { StrongPinMut::new_unchecked(&raw mut (*self.ptr().as_ptr()).chunks) }
+    }
+
+    /// Returns an iterator over every entry, starting from the statically allocated ones.
+    #[allow(clippy::needless_lifetimes)]
+    fn iter_entries<'s>(
+        self: StrongPinMut<'s, Self>,
+    ) -> impl Iterator<Item = StrongPinMut<'s, StaticArc<T>>> + 's {
+        let ptr = self.ptr().as_ptr();
+        // SAFETY: the pointers are valid, and `entries` and `chunks` are disjoint fields.
+        let entries = unsafe { StrongPinMut::new_unchecked(&raw mut (*ptr).entries) };
+        let chunks = unsafe { StrongPinMut::new_unchecked(&raw mut (*ptr).chunks) };
+        entries
+            .iter_mut()
+            .chain(chunks.iter_shared_mut().flat_map(|chunk| chunk.entries()))
+    }
+
+    /// Returns the chunk that contains the given entry,
+    /// or `None` if it is one of the statically allocated entries.
+    fn chunk_of(&self, entry: NonNull<StaticArc<T>>) -> Option<NonNull<Chunk<T>>> {
+        let start = self.entries.as_ptr() as usize;
+        let end = start + mem::size_of::<[StaticArc<T>; CAPACITY]>();
+        let addr = entry.as_ptr() as usize;
+        if (start..end).contains(&addr) {
+            None
+        } else {
+            // SAFETY: the invariant of `Chunk`.
+            Some(unsafe { NonNull::new_unchecked(pgrounddown(addr) as _) })
+        }
+    }
+
+    /// Records that the given empty entry has been allocated.
+    fn record_alloc(mut self: StrongPinMut<'_, Self>, entry: NonNull<StaticArc<T>>) {
+        if let Some(mut chunk) = self.chunk_of(entry) {
+            Chunk::check_redzones(chunk, Chunk::index_of(chunk, entry), self.stats.name);
+            // SAFETY: we have the `StrongPinMut` of the arena that owns the chunk.
+            unsafe { chunk.as_mut().in_use += 1 };
+        }
+        self.as_mut().stats_mut().record_alloc();
+    }
+}
+
+impl<T: Default, const CAPACITY: usize> ChunkedArena<T, CAPACITY> {
+    /// Allocates a new chunk from `Kmem` and returns its first entry.
+    /// Returns `None` if `Kmem` is out of pages.
+    fn grow(mut self: StrongPinMut<'_, Self>) -> Option<NonNull<StaticArc<T>>> {
+        if Chunk::<T>::LEN == 0 {
+            return None;
+        }
+
+        let page = hal().kmem().alloc()?;
+        let ptr = page.into_usize() as *mut Chunk<T>;
+        // SAFETY: `ptr` is the beginning of a page that we own. Hence, it satisfies
+        // the invariant of `Chunk` after we initialize all of its entries.
+        let mut chunk = unsafe {
+            ptr::write(
+                ptr,
+                Chunk {
+                    list_entry: ListEntry::new(),
+                    in_use: 0,
+                    _marker: PhantomData,
+                },
+            );
+            let this = NonNull::new_unchecked(ptr);
+            for i in 0..Chunk::<T>::LEN {
+                ptr::write(Chunk::entry_ptr(this, i), StaticArc::new(T::default()));
+                (*Chunk::redzone(this, i)).fill(REDZONE_BYTE);
+            }
+            Pin::new_unchecked(&mut *ptr)
+        };
+        chunk.as_mut().project().list_entry.init();
+
+        // The chunk lives until `record_dealloc` removes it from the list.
+        self.as_mut()
+            .chunks()
+            .cursor_back_mut()
+            .insert_after(chunk.as_ref());
+        self.stats_mut().capacity += Chunk::<T>::LEN;
+
+        // SAFETY: `ptr` is a valid chunk.
+        Some(unsafe { NonNull::new_unchecked(Chunk::entries_ptr(NonNull::new_unchecked(ptr))) })
+    }
+
+    /// Records that the given entry is about to become empty,
+    /// and returns the page of its chunk if every entry in the chunk is empty.
+    /// The returned page must be freed after the entry becomes empty.
+    fn record_dealloc(
+        mut self: StrongPinMut<'_, Self>,
+        entry: NonNull<StaticArc<T>>,
+    ) -> Option<Page> {
+        self.as_mut().stats_mut().record_dealloc();
+        let mut this = self.chunk_of(entry)?;
+        Chunk::check_redzones(this, Chunk::index_of(this, entry), self.stats.name);
+        // SAFETY: we have the `StrongPinMut` of the arena that owns the chunk.
+        let chunk = unsafe { this.as_mut() };
+        chunk.in_use -= 1;
+        if chunk.in_use > 0 {
+            return None;
+        }
+        for i in 0..Chunk::<T>::LEN {
+            Chunk::check_redzones(this, i, self.stats.name);
+        }
+
+        // The chunk is now empty. Remove it from the arena.
+        // The entries are not dropped, since they do not own any resources after finalization.
+        unsafe { Pin::new_unchecked(&chunk.list_entry) }.remove();
+        self.stats_mut().capacity -= Chunk::<T>::LEN;
+        // SAFETY: the invariant of `Chunk`, and the chunk has been removed from the arena.
+        Some(unsafe { Page::from_usize(chunk as *mut _ as _) })
+    }
+}
+
+impl<T: 'static + ArenaObject + Unpin + Send + Default, const CAPACITY: usize> Arena
+    for SpinLock<ChunkedArena<T, CAPACITY>>
+{
+    type Data = T;
+    type Guard<'s> = SpinLockGuard<'s, ChunkedArena<T, CAPACITY>>;
+
+    fn find_or_alloc<C: Fn(&Self::Data) -> bool, N: FnOnce(&mut Self::Data)>(
+        self: StrongPin<'_, Self>,
+        c: C,
+        n: N,
+    ) -> Option<ArenaRc<Self>> {
+        ArenaRef::new(
+            self,
+            |arena: ArenaRef<'_, '_, SpinLock<ChunkedArena<T, CAPACITY>>>| {
+                let mut guard = arena.strong_pinned_lock();
+                let mut this = guard.get_strong_pinned_mut();
+
+                let mut empty: Option<NonNull<StaticArc<T>>> = None;
+                for mut entry in this.as_mut().iter_entries() {
+                    if !entry.as_mut().is_borrowed() {
+                        let _ = empty.get_or_insert(entry.ptr());
+                        // Note: Do not use `break` here.
+                        // We must first search through all entries, and then alloc at empty
+                        // only if the entry we're finding for doesn't exist.
+                    } else if let Some(entry) = entry.as_mut().try_borrow() {
+                        // The entry is not under finalization. Check its data.
+                        if c(&entry) {
+                            let handle = Handle(arena.0.brand(entry));
+                            return Some(ArenaRc::new(arena, handle));
+                        }
+                    }
+                }
+
+                let ptr = some_or!(empty.or_else(|| this.as_mut().grow()), {
+                    this.stats_mut().record_failure();
+                    return None;
+                });
+                // SAFETY: `ptr` is valid, and there's no `StrongPinMut`.
+                let mut entry = unsafe { StrongPinMut::new_unchecked(ptr.as_ptr()) };
+                n(entry.as_mut().get_mut().unwrap());
+                this.record_alloc(ptr);
+                let handle = Handle(arena.0.brand(entry.borrow()));
+                Some(ArenaRc::new(arena, handle))
+            },
+        )
+    }
+
+    fn alloc<F: FnOnce() -> Self::Data>(self: StrongPin<'_, Self>, f: F) -> Option<ArenaRc<Self>> {
+        ArenaRef::new(
+            self,
+            |arena: ArenaRef<'_, '_, SpinLock<ChunkedArena<T, CAPACITY>>>| {
+                let mut guard = arena.strong_pinned_lock();
+                let mut this = guard.get_strong_pinned_mut();
+
+                let mut empty: Option<NonNull<StaticArc<T>>> = None;
+                for mut entry in this.as_mut().iter_entries() {
+                    if !entry.as_mut().is_borrowed() {
+                        empty = Some(entry.ptr());
+                        break;
+                    }
+                }
+
+                let ptr = some_or!(empty.or_else(|| this.as_mut().grow()), {
+                    this.stats_mut().record_failure();
+                    return None;
+                });
+                // SAFETY: `ptr` is valid, and there's no `StrongPinMut`.
+                let mut entry = unsafe { StrongPinMut::new_unchecked(ptr.as_ptr()) };
+                *entry.as_mut().get_mut().unwrap() = f();
+                this.record_alloc(ptr);
+                let handle = Handle(arena.0.brand(entry.borrow()));
+                Some(ArenaRc::new(arena, handle))
+            },
+        )
+    }
+
+    fn dealloc<'id, 'a, 'b>(
+        self: ArenaRef<'id, '_, Self>,
+        handle: Handle<'id, Self::Data>,
+        ctx: <Self::Data as ArenaObject>::Ctx<'a, 'b>,
+    ) {
+        if let Ok(mut rm) = handle.0.into_inner().into_mut() {
+            rm.finalize::<Self>(ctx);
+
+            // Update the statistics before the entry becomes empty,
+            // so that `in_use` never exceeds the number of non-empty entries.
+            let mut guard = self.strong_pinned_lock();
+            // SAFETY: `rm` holds a valid pointer.
+            let ptr = unsafe { NonNull::new_unchecked(rm.cell()) };
+            let page = guard.get_strong_pinned_mut().record_dealloc(ptr);
+            drop(rm);
+            if let Some(page) = page {
+                hal().kmem().free(page);
+            }
+        }
+    }
+
+    fn stats(self: StrongPin<'_, Self>) -> ArenaStats {
+        self.strong_pinned_lock().get_strong_pinned_mut().stats
+    }
+}
+
+ktest! {
+    fn chunked_arena_grow_shrink(ctx) {
+        #[derive(Default)]
+        struct Entry(usize);
+
+        impl ArenaObject for Entry {
+            type Ctx<'a, 'id: 'a> = ();
+
+            #[allow(clippy::needless_lifetimes)]
+            fn finalize<'a, 'id: 'a, A: Arena>(&mut self, _: ()) {}
+        }
+
+        let mut arena = SpinLock::new("ktest", unsafe {
+            ChunkedArena::<Entry, 1>::new::<Entry>("ktest")
+        });
+        // SAFETY: `arena` is not moved until it is dropped.
+        let mut arena = unsafe { Pin::new_unchecked(&mut arena) };
+        arena.as_mut().get_pin_mut().init();
+        let arena = unsafe { StrongPin::new_unchecked(arena.as_ref().get_ref()) };
+
+        // The second entry takes a chunk, which is freed with it.
+        let first = arena.alloc(|| Entry(1)).expect("chunked_arena_grow_shrink");
+        let second = arena.alloc(|| Entry(2)).expect("chunked_arena_grow_shrink");
+        let grown = arena.stats();
+        let found = arena
+            .find_or_alloc(|entry| entry.0 == 2, |_| ())
+            .expect("chunked_arena_grow_shrink");
+        let same = ptr::eq(&*found, &*second);
+        found.free(());
+        second.free(());
+        let shrunk = arena.stats();
+        first.free(());
+        ktest_assert!(grown.capacity == 1 + Chunk::<Entry>::LEN && grown.in_use == 2);
+        ktest_assert!(same);
+        ktest_assert!(shrunk.capacity == 1 && shrunk.in_use == 1);
+        ktest_assert!(arena.stats().in_use == 0);
+    }
+}
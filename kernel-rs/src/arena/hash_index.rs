@@ -0,0 +1,83 @@
+//! Hash index of arena entries.
+
+const NIL: usize = usize::MAX;
+
+/// Maps the hash values of keys to the indices of the arena entries that hold them.
+///
+/// Entries whose hash values fall into the same bucket are chained through `next`.
+/// Since an arena has at most `CAPACITY` entries, we also use `CAPACITY` buckets.
+///
+/// The index does not know whether an entry is currently allocated.
+/// Hence, the arena must check each entry returned by `first` and `next`,
+/// and must `remove` an entry from the index before reusing it for a different key.
+pub struct HashIndex<const CAPACITY: usize> {
+    /// The first entry of each bucket.
+    buckets: [usize; CAPACITY],
+    /// The next entry in the same bucket.
+    next: [usize; CAPACITY],
+    /// The hash value of each entry.
+    hashes: [u64; CAPACITY],
+    /// Whether each entry is in the index.
+    indexed: [bool; CAPACITY],
+}
+
+impl<const CAPACITY: usize> HashIndex<CAPACITY> {
+    pub const fn new() -> Self {
+        Self {
+            buckets: [NIL; CAPACITY],
+            next: [NIL; CAPACITY],
+            hashes: [0; CAPACITY],
+            indexed: [false; CAPACITY],
+        }
+    }
+
+    fn bucket(hash: u64) -> usize {
+        (hash % CAPACITY as u64) as usize
+    }
+
+    /// Returns the first entry that was inserted with `hash`.
+    pub fn first(&self, hash: u64) -> Option<usize> {
+        self.find(self.buckets[Self::bucket(hash)], hash)
+    }
+
+    /// Returns the entry that was inserted with `hash`, next to `index`.
+    pub fn next(&self, index: usize, hash: u64) -> Option<usize> {
+        self.find(self.next[index], hash)
+    }
+
+    fn find(&self, mut index: usize, hash: u64) -> Option<usize> {
+        while index != NIL {
+            if self.hashes[index] == hash {
+                return Some(index);
+            }
+            index = self.next[index];
+        }
+        None
+    }
+
+    /// Inserts the entry at `index` with `hash`, removing its previous hash value if exists.
+    pub fn insert(&mut self, index: usize, hash: u64) {
+        self.remove(index);
+        let bucket = Self::bucket(hash);
+        self.next[index] = self.buckets[bucket];
+        self.buckets[bucket] = index;
+        self.hashes[index] = hash;
+        self.indexed[index] = true;
+    }
+
+    /// Removes the entry at `index` from the index, if it is in the index.
+    pub fn remove(&mut self, index: usize) {
+        if !self.indexed[index] {
+            return;
+        }
+        self.indexed[index] = false;
+
+        let next = self.next[index];
+        self.next[index] = NIL;
+        let mut link = &mut self.buckets[Self::bucket(self.hashes[index])];
+        while *link != index {
+            link = &mut self.next[*link];
+        }
+        *link = next;
+    }
+}
@@ -3,18 +3,28 @@
 //! Includes the `Arena` trait, which represents a type that can be used as an arena.
 //! For types that `impl Arena`, you can allocate a thread safe `Rc` (reference counted pointer) from it.
 //!
-//! This module also includes pre-built arenas, such as `ArrayArena`(array based arena) or `MruArena`(list based arena).
+//! This module also includes pre-built arenas, such as `ArrayArena`(array based arena), `MruArena`(list based arena),
+//! or `ChunkedArena`(array based arena that grows using the page allocator).
 
+use core::cmp;
+use core::fmt;
+use core::hash::Hash;
 use core::mem::ManuallyDrop;
 use core::ops::Deref;
 
 use crate::util::strong_pin::StrongPin;
-use crate::util::{branded::Branded, static_arc::Ref};
+use crate::util::{
+    branded::Branded,
+    static_arc::{Ref, Weak},
+};
 
 mod array_arena;
+mod chunked_arena;
+mod hash_index;
 mod mru_arena;
 
 pub use array_arena::ArrayArena;
+pub use chunked_arena::ChunkedArena;
 pub use mru_arena::MruArena;
 
 /// A homogeneous memory allocator. Provides `Rc<Arena>` to the outside.
@@ -35,6 +45,25 @@ pub trait Arena: Sized + Sync {
         n: N,
     ) -> Option<ArenaRc<Self>>;
 
+    /// Same as `find_or_alloc`, but looks for the data using `key` instead of checking every entry.
+    /// * `c` must return `true` only if the data's key equals `key`.
+    /// * `n` must initialize the data so that its key equals `key`.
+    ///
+    /// Arenas that maintain a hash index of keys find the data in O(1) time.
+    /// Otherwise, this is the same as `find_or_alloc`.
+    fn find_or_alloc_keyed<
+        K: Hash + ?Sized,
+        C: Fn(&Self::Data) -> bool,
+        N: FnOnce(&mut Self::Data),
+    >(
+        self: StrongPin<'_, Self>,
+        _key: &K,
+        c: C,
+        n: N,
+    ) -> Option<ArenaRc<Self>> {
+        self.find_or_alloc(c, n)
+    }
+
     /// Allocates an `Rc` using the first empty entry.
     /// * Uses `f` to initialze a new `Rc`.
     ///
@@ -57,6 +86,73 @@ pub trait Arena: Sized + Sync {
             rm.finalize::<Self>(ctx);
         }
     }
+
+    /// Returns a snapshot of the usage statistics of the arena.
+    fn stats(self: StrongPin<'_, Self>) -> ArenaStats;
+}
+
+/// An arena that supports `ArenaWeak`.
+///
+/// # Safety
+///
+/// The arena must not reuse an entry while a `Weak` refers to it.
+pub unsafe trait WeakArena: Arena {
+    /// Returns a new handle to the data that `weak` refers to, if the data has not been finalized.
+    /// The arena's lock is held while upgrading `weak`.
+    fn upgrade(self: StrongPin<'_, Self>, weak: &Weak<Self::Data>) -> Option<Ref<Self::Data>>;
+}
+
+/// Usage statistics of an arena.
+#[derive(Clone, Copy)]
+pub struct ArenaStats {
+    /// The name of the arena.
+    pub name: &'static str,
+    /// The number of entries of the arena.
+    pub capacity: usize,
+    /// The number of entries that are currently allocated.
+    pub in_use: usize,
+    /// The maximum value that `in_use` has ever reached.
+    pub high_water: usize,
+    /// The number of allocations that failed because the arena was full.
+    pub failed: usize,
+}
+
+impl ArenaStats {
+    pub const fn new(name: &'static str, capacity: usize) -> Self {
+        Self {
+            name,
+            capacity,
+            in_use: 0,
+            high_water: 0,
+            failed: 0,
+        }
+    }
+
+    /// Records that an empty entry has been allocated.
+    fn record_alloc(&mut self) {
+        self.in_use += 1;
+        self.high_water = cmp::max(self.high_water, self.in_use);
+    }
+
+    /// Records that an allocation has failed since no empty entry exists.
+    fn record_failure(&mut self) {
+        self.failed += 1;
+    }
+
+    /// Records that an allocated entry has become empty.
+    fn record_dealloc(&mut self) {
+        self.in_use -= 1;
+    }
+}
+
+impl fmt::Display for ArenaStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}/{} in use, high-water {}, {} failed allocations",
+            self.name, self.in_use, self.capacity, self.high_water, self.failed
+        )
+    }
 }
 
 pub trait ArenaObject {
@@ -174,8 +270,63 @@ impl<A: Arena> ArenaRc<A> {
     }
 }
 
+// Dead code is allowed since no cache uses `ArenaWeak` yet.
+#[allow(dead_code)]
+impl<A: WeakArena> ArenaRc<A> {
+    /// Returns an `ArenaWeak` that refers to the same data.
+    pub fn downgrade(&self) -> ArenaWeak<A> {
+        ArenaWeak {
+            arena: self.arena,
+            inner: self.inner.downgrade(),
+        }
+    }
+}
+
 impl<A: Arena> Drop for ArenaRc<A> {
     fn drop(&mut self) {
         panic!();
     }
 }
+
+/// A weak reference to data allocated from `A: WeakArena`.
+///
+/// Unlike `ArenaRc`, it does not keep the data alive: the data gets finalized when the last
+/// `ArenaRc` is freed, and `upgrade` fails after that. Still, the arena does not reuse the entry
+/// until every `ArenaWeak` referring to it drops. Hence, unlike `ArenaRc`, `ArenaWeak` can simply
+/// be dropped.
+///
+/// # Safety
+///
+/// * `arena` is pinned.
+/// * `inner` is allocated from `arena`.
+/// * We can safely dereference `arena` until `inner` gets dropped,
+///   because we panic if the arena drops earlier than `inner`.
+pub struct ArenaWeak<A: WeakArena> {
+    arena: *const A,
+    inner: Weak<A::Data>,
+}
+
+// `ArenaWeak` is `Send` for the same reason as `ArenaRc`.
+unsafe impl<T: Sync, A: WeakArena<Data = T>> Send for ArenaWeak<A> {}
+
+#[allow(dead_code)]
+impl<A: WeakArena> ArenaWeak<A> {
+    /// Returns a new `ArenaRc` if the data has not been finalized.
+    pub fn upgrade(&self) -> Option<ArenaRc<A>> {
+        // SAFETY: Safe because of `ArenaWeak`'s invariant.
+        let arena = unsafe { StrongPin::new_unchecked(&*self.arena) };
+        ArenaRef::new(arena, |arena| {
+            let handle = Handle(arena.0.brand(arena.upgrade(&self.inner)?));
+            Some(ArenaRc::new(arena, handle))
+        })
+    }
+}
+
+impl<A: WeakArena> Clone for ArenaWeak<A> {
+    fn clone(&self) -> Self {
+        Self {
+            arena: self.arena,
+            inner: self.inner.clone(),
+        }
+    }
+}
@@ -1,5 +1,6 @@
 //! List based arena.
 
+use core::hash::Hash;
 use core::mem;
 use core::pin::Pin;
 use core::ptr::NonNull;
@@ -7,13 +8,20 @@ use core::ptr::NonNull;
 use array_macro::array;
 use pin_project::pin_project;
 
-use super::{Arena, ArenaObject, ArenaRc, ArenaRef, Handle};
+use super::{
+    hash_index::HashIndex, Arena, ArenaObject, ArenaRc, ArenaRef, ArenaStats, Handle, WeakArena,
+};
 use crate::util::strong_pin::StrongPin;
 use crate::{
     lock::{SpinLock, SpinLockGuard},
+    some_or,
+    util::hash::hash,
     util::intrusive_list::{List, ListEntry, ListNode},
     util::pinned_array::IterPinMut,
-    util::{static_arc::StaticArc, strong_pin::StrongPinMut},
+    util::{
+        static_arc::{Ref, StaticArc, Weak},
+        strong_pin::StrongPinMut,
+    },
 };
 
 #[pin_project]
@@ -32,6 +40,9 @@ pub struct MruArena<T, const CAPACITY: usize> {
     entries: [MruEntry<T>; CAPACITY],
     #[pin]
     list: List<MruEntry<T>>,
+    /// Index of the entries allocated by `find_or_alloc_keyed`.
+    index: HashIndex<CAPACITY>,
+    stats: ArenaStats,
 }
 
 // SAFETY: `MruArena` never exposes its internal lists and entries.
@@ -76,11 +87,12 @@ unsafe impl<T> ListNode for MruEntry<T> {
 impl<T, const CAPACITY: usize> MruArena<T, CAPACITY> {
     /// Returns an `MruArena` of size `CAPACITY` that is filled with `D`'s const default value.
     /// Note that `D` must `impl const Default`.
+    /// `name` is used to identify the arena in its usage statistics.
     ///
     /// # Examples
     ///
     /// ```rust,no_run
-    /// let mru_arena = MruArena::<D, 100>::new();
+    /// let mru_arena = MruArena::<D, 100>::new("arena");
     /// ```
     ///
     /// # Safety
@@ -89,10 +101,12 @@ impl<T, const CAPACITY: usize> MruArena<T, CAPACITY> {
     // Note: We cannot use the generic `T` in the following function, since we need to only allow
     // types that `impl const Default`, not just `impl Default`.
     #[allow(clippy::new_ret_no_self)]
-    pub const unsafe fn new<D: Default>() -> MruArena<D, CAPACITY> {
+    pub const unsafe fn new<D: Default>(name: &'static str) -> MruArena<D, CAPACITY> {
         MruArena {
             entries: array![_ => MruEntry::new(Default::default()); CAPACITY],
             list: unsafe { List::new() },
+            index: HashIndex::new(),
+            stats: ArenaStats::new(name, CAPACITY),
         }
     }
 
@@ -110,6 +124,29 @@ impl<T, const CAPACITY: usize> MruArena<T, CAPACITY> {
         // SAFETY: the pointer is valid, and it creates a unique `StrongPinMut`.
         unsafe { StrongPinMut::new_unchecked(&raw mut (*self.ptr().as_ptr()).list) }
     }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn entry<'s>(self: StrongPinMut<'s, Self>, index: usize) -> StrongPinMut<'s, MruEntry<T>> {
+        // SAFETY: the pointer is valid, and it creates a unique `StrongPinMut`.
+        unsafe { StrongPinMut::new_unchecked(&raw mut (*self.ptr().as_ptr()).entries[index]) }
+    }
+
+    /// Returns the index of `entry` in `entries`.
+    fn index_of(&self, entry: NonNull<MruEntry<T>>) -> usize {
+        (entry.as_ptr() as usize - self.entries.as_ptr() as usize) / mem::size_of::<MruEntry<T>>()
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn index_mut<'s>(self: StrongPinMut<'s, Self>) -> &'s mut HashIndex<CAPACITY> {
+        // SAFETY: `index` is not structurally pinned, and `self` is a unique `StrongPinMut`.
+        unsafe { &mut (*self.ptr().as_ptr()).index }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn stats_mut<'s>(self: StrongPinMut<'s, Self>) -> &'s mut ArenaStats {
+        // SAFETY: `stats` is not structurally pinned, and `self` is a unique `StrongPinMut`.
+        unsafe { &mut (*self.ptr().as_ptr()).stats }
+    }
 }
 
 impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
@@ -127,32 +164,100 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
             self,
             |arena: ArenaRef<'_, '_, SpinLock<MruArena<T, CAPACITY>>>| {
                 let mut guard = arena.strong_pinned_lock();
-                let this = guard.get_strong_pinned_mut();
+                let mut this = guard.get_strong_pinned_mut();
 
-                let mut empty: Option<NonNull<StaticArc<T>>> = None;
-                for entry in this.list().iter_shared_mut() {
+                let mut empty: Option<NonNull<MruEntry<T>>> = None;
+                for entry in this.as_mut().list().iter_shared_mut() {
+                    let ptr = entry.ptr();
                     let mut entry = entry.data();
+                    let was_empty = !entry.as_mut().is_borrowed();
 
                     if let Some(entry) = entry.as_mut().try_borrow() {
                         // The entry is not under finalization. Check its data.
                         if c(&entry) {
+                            if was_empty {
+                                this.stats_mut().record_alloc();
+                            }
                             let handle = Handle(arena.0.brand(entry));
                             return Some(ArenaRc::new(arena, handle));
                         }
                     }
 
-                    if !entry.as_mut().is_borrowed() {
-                        let _ = empty.get_or_insert(entry.ptr());
+                    if entry.as_mut().is_empty() {
+                        let _ = empty.get_or_insert(ptr);
                     }
                 }
 
-                empty.map(|ptr| {
-                    // SAFETY: `ptr` is valid, and there's no `StrongPinMut`.
-                    let mut entry = unsafe { StrongPinMut::new_unchecked(ptr.as_ptr()) };
-                    n(entry.as_mut().get_mut().unwrap());
-                    let handle = Handle(arena.0.brand(entry.borrow()));
-                    ArenaRc::new(arena, handle)
-                })
+                let ptr = some_or!(empty, {
+                    this.stats_mut().record_failure();
+                    return None;
+                });
+                let i = this.index_of(ptr);
+                this.as_mut().index_mut().remove(i);
+                let mut entry = this.as_mut().entry(i).data();
+                n(entry.as_mut().get_mut().unwrap());
+                let handle = Handle(arena.0.brand(entry.borrow()));
+                this.stats_mut().record_alloc();
+                Some(ArenaRc::new(arena, handle))
+            },
+        )
+    }
+
+    fn find_or_alloc_keyed<
+        K: Hash + ?Sized,
+        C: Fn(&Self::Data) -> bool,
+        N: FnOnce(&mut Self::Data),
+    >(
+        self: StrongPin<'_, Self>,
+        key: &K,
+        c: C,
+        n: N,
+    ) -> Option<ArenaRc<Self>> {
+        let hash = hash(key);
+        ArenaRef::new(
+            self,
+            |arena: ArenaRef<'_, '_, SpinLock<MruArena<T, CAPACITY>>>| {
+                let mut guard = arena.strong_pinned_lock();
+                let mut this = guard.get_strong_pinned_mut();
+
+                let mut cursor = this.index.first(hash);
+                while let Some(i) = cursor {
+                    cursor = this.index.next(i, hash);
+                    let mut entry = this.as_mut().entry(i).data();
+                    let was_empty = !entry.as_mut().is_borrowed();
+
+                    if let Some(entry) = entry.as_mut().try_borrow() {
+                        // The entry is not under finalization. Check its data.
+                        if c(&entry) {
+                            if was_empty {
+                                this.stats_mut().record_alloc();
+                            }
+                            let handle = Handle(arena.0.brand(entry));
+                            return Some(ArenaRc::new(arena, handle));
+                        }
+                    }
+                }
+
+                let mut empty: Option<NonNull<MruEntry<T>>> = None;
+                for entry in this.as_mut().list().iter_shared_mut() {
+                    let ptr = entry.ptr();
+                    if entry.data().is_empty() {
+                        empty = Some(ptr);
+                        break;
+                    }
+                }
+
+                let ptr = some_or!(empty, {
+                    this.stats_mut().record_failure();
+                    return None;
+                });
+                let i = this.index_of(ptr);
+                this.as_mut().index_mut().insert(i, hash);
+                let mut entry = this.as_mut().entry(i).data();
+                n(entry.as_mut().get_mut().unwrap());
+                let handle = Handle(arena.0.brand(entry.borrow()));
+                this.stats_mut().record_alloc();
+                Some(ArenaRc::new(arena, handle))
             },
         )
     }
@@ -162,17 +267,28 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
             self,
             |arena: ArenaRef<'_, '_, SpinLock<MruArena<T, CAPACITY>>>| {
                 let mut guard = arena.strong_pinned_lock();
-                let this = guard.get_strong_pinned_mut();
+                let mut this = guard.get_strong_pinned_mut();
 
-                for entry in this.list().iter_shared_mut().rev() {
-                    let mut entry = entry.data();
-                    if let Some(data) = entry.as_mut().get_mut() {
-                        *data = f();
-                        let handle = Handle(arena.0.brand(entry.borrow()));
-                        return Some(ArenaRc::new(arena, handle));
+                let mut empty: Option<NonNull<MruEntry<T>>> = None;
+                for entry in this.as_mut().list().iter_shared_mut().rev() {
+                    let ptr = entry.ptr();
+                    if entry.data().is_empty() {
+                        empty = Some(ptr);
+                        break;
                     }
                 }
-                None
+
+                let ptr = some_or!(empty, {
+                    this.stats_mut().record_failure();
+                    return None;
+                });
+                let i = this.index_of(ptr);
+                this.as_mut().index_mut().remove(i);
+                let mut entry = this.as_mut().entry(i).data();
+                *entry.as_mut().get_mut().unwrap() = f();
+                let handle = Handle(arena.0.brand(entry.borrow()));
+                this.stats_mut().record_alloc();
+                Some(ArenaRc::new(arena, handle))
             },
         )
     }
@@ -194,9 +310,100 @@ impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> Arena
             // * The value of `DATA_OFFSET` is proper.
             let ptr = unsafe { Pin::new_unchecked(&*ptr) };
 
-            let mut this = self.strong_pinned_lock();
-            let this = this.get_strong_pinned_mut().as_ref().as_pin().get_ref();
-            unsafe { Pin::new_unchecked(&this.list) }.push_back(ptr);
+            let mut guard = self.strong_pinned_lock();
+            let mut this = guard.get_strong_pinned_mut();
+            this.as_mut().list().cursor_back_mut().insert_after(ptr);
+
+            // Update the statistics before the entry becomes empty,
+            // so that `in_use` never exceeds the number of non-empty entries.
+            this.stats_mut().record_dealloc();
+            drop(rm);
         }
     }
+
+    fn stats(self: StrongPin<'_, Self>) -> ArenaStats {
+        self.strong_pinned_lock().get_strong_pinned_mut().stats
+    }
+}
+
+impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize>
+    SpinLock<MruArena<T, CAPACITY>>
+{
+    /// Same as `find_or_alloc_keyed`, but never allocates.
+    pub fn find_keyed<K: Hash + ?Sized, C: Fn(&T) -> bool>(
+        self: StrongPin<'_, Self>,
+        key: &K,
+        c: C,
+    ) -> Option<ArenaRc<Self>> {
+        let hash = hash(key);
+        ArenaRef::new(
+            self,
+            |arena: ArenaRef<'_, '_, SpinLock<MruArena<T, CAPACITY>>>| {
+                let mut guard = arena.strong_pinned_lock();
+                let mut this = guard.get_strong_pinned_mut();
+
+                let mut cursor = this.index.first(hash);
+                while let Some(i) = cursor {
+                    cursor = this.index.next(i, hash);
+                    let mut entry = this.as_mut().entry(i).data();
+                    let was_empty = !entry.as_mut().is_borrowed();
+
+                    if let Some(entry) = entry.as_mut().try_borrow() {
+                        if c(&entry) {
+                            if was_empty {
+                                this.stats_mut().record_alloc();
+                            }
+                            let handle = Handle(arena.0.brand(entry));
+                            return Some(ArenaRc::new(arena, handle));
+                        }
+                    }
+                }
+                None
+            },
+        )
+    }
+
+    /// Same as `find_or_alloc_keyed`, but only finds data that nobody refers to, and never
+    /// allocates.
+    pub fn find_unused_keyed<K: Hash + ?Sized, C: Fn(&T) -> bool>(
+        self: StrongPin<'_, Self>,
+        key: &K,
+        c: C,
+    ) -> Option<ArenaRc<Self>> {
+        let hash = hash(key);
+        ArenaRef::new(
+            self,
+            |arena: ArenaRef<'_, '_, SpinLock<MruArena<T, CAPACITY>>>| {
+                let mut guard = arena.strong_pinned_lock();
+                let mut this = guard.get_strong_pinned_mut();
+
+                let mut cursor = this.index.first(hash);
+                while let Some(i) = cursor {
+                    cursor = this.index.next(i, hash);
+                    let mut entry = this.as_mut().entry(i).data();
+                    if entry.as_mut().is_borrowed() {
+                        continue;
+                    }
+                    if let Some(entry) = entry.as_mut().try_borrow() {
+                        if c(&entry) {
+                            this.stats_mut().record_alloc();
+                            let handle = Handle(arena.0.brand(entry));
+                            return Some(ArenaRc::new(arena, handle));
+                        }
+                    }
+                }
+                None
+            },
+        )
+    }
+}
+
+// SAFETY: an entry is reused only if it `is_empty`, i.e., no `Weak` refers to it.
+unsafe impl<T: 'static + ArenaObject + Unpin + Send, const CAPACITY: usize> WeakArena
+    for SpinLock<MruArena<T, CAPACITY>>
+{
+    fn upgrade(self: StrongPin<'_, Self>, weak: &Weak<Self::Data>) -> Option<Ref<Self::Data>> {
+        let _guard = self.strong_pinned_lock();
+        weak.upgrade()
+    }
 }
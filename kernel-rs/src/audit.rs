@@ -0,0 +1,181 @@
+//! The audit log, which records security-relevant events for the superuser to read.
+//!
+//! Records are kept in a ring buffer that overwrites the oldest record when it is full.
+//! Each record has a sequence number, so a reader asks for the records from the first one it
+//! has not read yet, and can tell from a gap in the numbers how many it missed.
+
+use core::{cmp, mem};
+
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::addr::UVAddr, cred::Caps, error::KernelError, lock::SpinLock, param::MAXPROCNAME,
+    proc::KernelCtx, time::ktime_now,
+};
+
+/// Number of records the log keeps.
+const AUDIT_LEN: usize = 64;
+
+/// Maximum number of records copied out while holding the lock.
+const CHUNK: usize = 8;
+
+/// What an audit record is about.
+#[derive(Copy, Clone)]
+#[repr(u32)]
+pub enum AuditEvent {
+    /// A program was executed. The argument is the inode number of the program.
+    Exec = 1,
+    /// setuid() was called. The argument is the requested user ID.
+    Setuid = 2,
+    /// setgid() was called. The argument is the requested group ID.
+    Setgid = 3,
+    /// An access to a file, or a change of its permissions, was denied. The argument is the
+    /// inode number.
+    Denied = 4,
+    /// A system call was denied because the process lacks a capability that it needs. The
+    /// argument is the system call number.
+    Privileged = 5,
+    /// kill() or sigsend() was called. The argument is the target process ID, or the negated
+    /// process group ID.
+    Kill = 6,
+    /// The realtime clock was set or slewed. The argument is the change of the clock in
+    /// nanoseconds, which may be negative.
+    SetTime = 7,
+    /// capdrop() was called. The argument is the capabilities to drop.
+    CapDrop = 8,
+}
+
+/// `struct auditrec` of user programs.
+#[derive(Copy, Clone, AsBytes)]
+#[repr(C)]
+pub struct AuditRecord {
+    /// Sequence number, counting from zero at boot.
+    seq: u64,
+    /// Time since boot, in nanoseconds.
+    time: u64,
+    /// An `AuditEvent`.
+    event: u32,
+    /// The process that caused the event, and its IDs at the time.
+    pid: i32,
+    uid: u32,
+    euid: u32,
+    egid: u32,
+    /// Zero if the operation succeeded, or the error number it failed with.
+    error: i32,
+    /// Depends on `event`.
+    arg: u64,
+    /// Name of the process.
+    name: [u8; MAXPROCNAME],
+}
+
+impl AuditRecord {
+    const fn zero() -> Self {
+        Self {
+            seq: 0,
+            time: 0,
+            event: 0,
+            pid: 0,
+            uid: 0,
+            euid: 0,
+            egid: 0,
+            error: 0,
+            arg: 0,
+            name: [0; MAXPROCNAME],
+        }
+    }
+}
+
+struct AuditLogInner {
+    records: [AuditRecord; AUDIT_LEN],
+
+    /// Sequence number of the next record.
+    next_seq: u64,
+}
+
+/// The kernel's audit log.
+pub struct AuditLog {
+    inner: SpinLock<AuditLogInner>,
+}
+
+impl AuditLog {
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinLock::new(
+                "audit",
+                AuditLogInner {
+                    records: [AuditRecord::zero(); AUDIT_LEN],
+                    next_seq: 0,
+                },
+            ),
+        }
+    }
+
+    /// Appends `record` with the next sequence number, overwriting the oldest record if the log
+    /// is full.
+    fn append(&self, mut record: AuditRecord) {
+        let mut inner = self.inner.lock();
+        record.seq = inner.next_seq;
+        inner.records[record.seq as usize % AUDIT_LEN] = record;
+        inner.next_seq += 1;
+    }
+
+    /// Copies the records from the sequence number `seq` on into `out`, oldest first.
+    /// Starts from the oldest record kept if `seq` was overwritten.
+    /// Returns the number of records copied.
+    fn read(&self, seq: u64, out: &mut [AuditRecord]) -> usize {
+        let inner = self.inner.lock();
+        let start = cmp::max(seq, inner.next_seq.saturating_sub(AUDIT_LEN as u64));
+        let n = cmp::min(inner.next_seq.saturating_sub(start) as usize, out.len());
+        for (i, record) in out[..n].iter_mut().enumerate() {
+            *record = inner.records[(start as usize + i) % AUDIT_LEN];
+        }
+        n
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Records `event` of the current process in the audit log. `error` is the error the
+    /// operation failed with, or `None` if it succeeded.
+    pub fn audit(&self, event: AuditEvent, arg: u64, error: Option<KernelError>) {
+        let data = self.proc().deref_data();
+        let mut record = AuditRecord {
+            time: ktime_now(),
+            event: event as u32,
+            pid: self.proc().pid(),
+            uid: data.cred.uid,
+            euid: data.cred.euid(),
+            egid: data.cred.egid,
+            error: error.map_or(0, |e| e.errno()),
+            arg,
+            ..AuditRecord::zero()
+        };
+        record.name.copy_from_slice(&data.name);
+        self.kernel().audit().append(record);
+    }
+
+    /// Copy up to `n` audit records from the sequence number `seq` on to the `struct auditrec`
+    /// array at `addr`. Needs `Caps::SYS_ADMIN`.
+    /// Returns Ok(number of records copied) on success, Err(KernelError) on error.
+    pub fn audit_read(&mut self, addr: UVAddr, n: usize, seq: u64) -> Result<usize, KernelError> {
+        self.require_cap(Caps::SYS_ADMIN)?;
+        let mut buf = [AuditRecord::zero(); CHUNK];
+        let mut copied = 0;
+        let mut seq = seq;
+        while copied < n {
+            let len = self
+                .kernel()
+                .audit()
+                .read(seq, &mut buf[..cmp::min(CHUNK, n - copied)]);
+            if len == 0 {
+                break;
+            }
+            let dst = addr + copied * mem::size_of::<AuditRecord>();
+            self.proc_mut()
+                .memory_mut()
+                .copy_out_bytes(dst, buf[..len].as_bytes())?;
+            copied += len;
+            seq = buf[len - 1].seq + 1;
+        }
+        Ok(copied)
+    }
+}
@@ -0,0 +1,95 @@
+//! Backtraces of the kernel stack, printed on panic, and for the watchdog.
+//!
+//! The kernel is built with frame pointers, so each frame saves the return address at fp - 8
+//! and the caller's fp at fp - 16. Return addresses are resolved against the symbol table in
+//! the .ksyms section, which the Makefile generates from a first link of the kernel with
+//! kernel/ksyms.pl, and links in again. Each entry of the table is the address of a function
+//! as a u64, the length of its name as a u16, and the name, padded to 8 bytes. Entries are
+//! sorted by address.
+
+use core::{convert::TryInto, pin::Pin, slice, str};
+
+use crate::{
+    arch::addr::{pgrounddown, PGSIZE},
+    arch::riscv::{r_fp, r_sp},
+    kernel::Kernel,
+};
+
+extern "C" {
+    // kernel.ld
+    static ksyms_start: [u8; 0];
+    static ksyms_end: [u8; 0];
+}
+
+/// Maximum number of frames printed.
+const MAX_DEPTH: usize = 32;
+
+/// Returns the symbol table.
+fn ksyms() -> &'static [u8] {
+    // SAFETY: kernel.ld places the table between `ksyms_start` and `ksyms_end`, and it is never
+    // written.
+    unsafe {
+        let start = ksyms_start.as_ptr();
+        slice::from_raw_parts(start, ksyms_end.as_ptr().offset_from(start) as usize)
+    }
+}
+
+/// Returns the name of the function that contains `addr`, and the offset of `addr` in it.
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    let mut syms = ksyms();
+    let mut found = None;
+    while syms.len() >= 10 {
+        let start = u64::from_le_bytes(syms[..8].try_into().unwrap()) as usize;
+        let len = u16::from_le_bytes(syms[8..10].try_into().unwrap()) as usize;
+        if start > addr {
+            break;
+        }
+        let name = syms.get(10..10 + len)?;
+        found = Some((str::from_utf8(name).unwrap_or("?"), addr - start));
+        syms = syms.get((10 + len + 7) & !7..).unwrap_or(&[]);
+    }
+    found
+}
+
+/// Prints the return addresses of the frames on the current stack, with their functions.
+pub fn print_backtrace(kernel: Pin<&Kernel>) {
+    print_frames(kernel, r_fp(), r_sp());
+}
+
+/// Prints the return addresses of the frames on the kernel stack of another CPU, given the frame
+/// pointer that it had. The CPU may still be running, so the frames are only a best effort.
+pub fn print_backtrace_at(kernel: Pin<&Kernel>, fp: usize) {
+    print_frames(kernel, fp, fp.wrapping_sub(16));
+}
+
+/// Prints the frames from the frame pointer `fp` up, on the stack page that contains `sp`.
+fn print_frames(kernel: Pin<&Kernel>, mut fp: usize, sp: usize) {
+    // Kernel stacks are a page each, and a frame is above the frames it calls.
+    let top = pgrounddown(sp).wrapping_add(PGSIZE);
+    kernel.write_str("backtrace:\n");
+    for depth in 0..MAX_DEPTH {
+        if fp <= sp || fp > top || fp % 8 != 0 {
+            break;
+        }
+        // SAFETY: fp - 16..fp is on the stack page, which is mapped.
+        let (ra, caller_fp) =
+            unsafe { (*((fp - 8) as *const usize), *((fp - 16) as *const usize)) };
+        // The return address may be past the end of a function that does not return.
+        match lookup(ra.wrapping_sub(1)) {
+            Some((name, off)) => {
+                kernel.write_fmt(format_args!(
+                    "  #{} {:#018x} {}+{:#x}\n",
+                    depth,
+                    ra,
+                    name,
+                    off + 1
+                ))
+            }
+            None => kernel.write_fmt(format_args!("  #{} {:#018x}\n", depth, ra)),
+        }
+        if caller_fp <= fp {
+            break;
+        }
+        fp = caller_fp;
+    }
+}
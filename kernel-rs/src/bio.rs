@@ -4,6 +4,14 @@
 //! contents.  Caching disk blocks in memory reduces the number of disk reads and also provides a
 //! synchronization point for disk blocks used by multiple processes.
 //!
+//! The cache is split into shards, each with its own lock and list, and NBUF buffers in all.
+//! The hash of (dev, blockno) decides which of the first NBUFSHARD shards is the home of a block,
+//! so harts using different blocks rarely wait for each other. A block whose home has no free
+//! buffer goes to one of the NSPILL spill shards instead. The blocks that the log pins may all
+//! fall into one home, and the home and the spill shards together hold as many of them as the
+//! log does. A gate lock of each home is held while a block of it is looked up, so that the
+//! block gets a buffer in one shard at most.
+//!
 //! Interface:
 //! * To get a buffer for a particular disk block, call read.
 //! * After changing buffer data, call bwrite to write it to disk.
@@ -11,21 +19,35 @@
 //! * Do not use the buffer after calling release.
 //! * Only one process at a time can use a buffer, so do not keep them longer than necessary.
 
+use core::iter;
 use core::mem::{self, ManuallyDrop};
 use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::ptr;
+
+use array_macro::array;
+use pin_project::pin_project;
+use static_assertions::const_assert;
 
 use crate::arena::ArenaRc;
-use crate::util::strong_pin::StrongPin;
+use crate::util::{
+    branded::{BlockNo, DevNo},
+    hash::hash,
+    pinned_array::IterPinMut,
+    strong_pin::StrongPin,
+};
 use crate::{
-    arena::{Arena, ArenaObject, MruArena},
+    arena::{Arena, ArenaObject, ArenaStats, MruArena},
+    hal::hal,
+    ktest, ktest_assert,
     lock::{SleepLock, SpinLock},
-    param::{BSIZE, NBUF},
+    param::{BSIZE, LOGSIZE, NBUF, NBUFSHARD, ROOTDEV},
     proc::{KernelCtx, WaitChannel},
 };
 
 pub struct BufEntry {
-    dev: u32,
-    pub blockno: u32,
+    dev: DevNo,
+    pub blockno: BlockNo,
 
     /// WaitChannel saying virtio_disk request is done.
     pub vdisk_request_waitchannel: WaitChannel,
@@ -36,8 +58,8 @@ pub struct BufEntry {
 impl BufEntry {
     pub const fn new() -> Self {
         Self {
-            dev: 0,
-            blockno: 0,
+            dev: DevNo::new(0),
+            blockno: BlockNo::new(0),
             vdisk_request_waitchannel: WaitChannel::new(),
             inner: SleepLock::new("buffer", BufInner::new()),
         }
@@ -100,10 +122,31 @@ impl BufInner {
     }
 }
 
-pub type Bcache = SpinLock<MruArena<BufEntry, NBUF>>;
+/// Number of shards that take the blocks of homes that are full.
+const NSPILL: usize = 2;
+
+/// Number of buffers in a shard.
+const SHARDSIZE: usize = NBUF / (NBUFSHARD + NSPILL);
+
+const_assert!(NBUF % (NBUFSHARD + NSPILL) == 0);
+// The blocks that the log pins fit even if they all fall into one home.
+const_assert!(SHARDSIZE * (1 + NSPILL) >= LOGSIZE);
+
+/// A shard of the buffer cache.
+pub type BcacheShard = SpinLock<MruArena<BufEntry, SHARDSIZE>>;
+
+#[pin_project]
+pub struct Bcache {
+    /// The homes, followed by the spill shards.
+    #[pin]
+    shards: [BcacheShard; NBUFSHARD + NSPILL],
+
+    /// Held while looking up a block of the home with the same index.
+    gates: [SpinLock<()>; NBUFSHARD],
+}
 
 /// A reference counted smart pointer to a `BufEntry`.
-pub struct BufUnlocked(ManuallyDrop<ArenaRc<Bcache>>);
+pub struct BufUnlocked(ManuallyDrop<ArenaRc<BcacheShard>>);
 
 /// A locked `BufEntry`.
 ///
@@ -184,23 +227,136 @@ impl Drop for Buf {
 impl Bcache {
     /// # Safety
     ///
-    /// Must be used only after initializing it with `MruArena::init`.
+    /// Must be used only after initializing it with `Bcache::init`.
     pub const unsafe fn new_bcache() -> Self {
-        SpinLock::new("BCACHE", unsafe { MruArena::<BufEntry, NBUF>::new() })
+        Self {
+            shards: array![_ => SpinLock::new("BCACHE", unsafe {
+                MruArena::<BufEntry, SHARDSIZE>::new("bcache")
+            }); NBUFSHARD + NSPILL],
+            gates: array![_ => SpinLock::new("BCACHE_GATE", ()); NBUFSHARD],
+        }
+    }
+
+    pub fn init(self: Pin<&mut Self>) {
+        for shard in IterPinMut::from(self.project().shards) {
+            shard.get_pin_mut().init();
+        }
+    }
+
+    /// Returns the index of the home of the indicated block.
+    fn home(dev: DevNo, blockno: BlockNo) -> usize {
+        // The shards use the low bits of the hash for their own indices.
+        (hash(&(dev, blockno)) >> 32) as usize % NBUFSHARD
+    }
+
+    /// Returns the shards where a block of home `home` may be: the home, and the spill shards.
+    #[allow(clippy::needless_lifetimes)]
+    fn shards<'s>(
+        self: StrongPin<'s, Self>,
+        home: usize,
+    ) -> impl Iterator<Item = StrongPin<'s, BcacheShard>> {
+        iter::once(home)
+            .chain(NBUFSHARD..NBUFSHARD + NSPILL)
+            // SAFETY: the shards are pinned, as self is.
+            .map(move |i| unsafe { StrongPin::new_unchecked(&self.ptr().shards[i]) })
     }
 
     /// Return a unlocked buf with the contents of the indicated block.
-    pub fn get_buf(self: StrongPin<'_, Self>, dev: u32, blockno: u32) -> BufUnlocked {
-        BufUnlocked(ManuallyDrop::new(
-            self.find_or_alloc(
-                |buf| buf.dev == dev && buf.blockno == blockno,
-                |buf| {
-                    buf.dev = dev;
-                    buf.blockno = blockno;
-                    buf.inner.get_mut().valid = false;
-                },
-            )
-            .expect("[BufGuard::new] no buffers"),
-        ))
+    pub fn get_buf(self: StrongPin<'_, Self>, dev: DevNo, blockno: BlockNo) -> BufUnlocked {
+        let home = Self::home(dev, blockno);
+        let key = (dev, blockno);
+        let c = |buf: &BufEntry| buf.dev == dev && buf.blockno == blockno;
+        let n = |buf: &mut BufEntry| {
+            buf.dev = dev;
+            buf.blockno = blockno;
+            buf.inner.get_mut().valid = false;
+        };
+        let gate = self.ptr().gates[home].lock();
+        // The block may be in a spill shard even if its home has room by now.
+        let buf = self
+            .shards(home)
+            .find_map(|shard| shard.find_keyed(&key, c))
+            .or_else(|| {
+                self.shards(home)
+                    .find_map(|shard| shard.find_or_alloc_keyed(&key, c, n))
+            });
+        drop(gate);
+        BufUnlocked(ManuallyDrop::new(buf.unwrap_or_else(|| {
+            panic!("[BufGuard::new] no buffers ({})", self.stats())
+        })))
+    }
+
+    /// Forget the contents of the indicated block, if it is cached and nobody uses it, so that
+    /// the next read reads it from the disk again.
+    pub fn evict(self: StrongPin<'_, Self>, dev: DevNo, blockno: BlockNo, ctx: &KernelCtx<'_, '_>) {
+        let home = Self::home(dev, blockno);
+        let gate = self.ptr().gates[home].lock();
+        let buf = self.shards(home).find_map(|shard| {
+            shard.find_unused_keyed(&(dev, blockno), |buf| {
+                buf.dev == dev && buf.blockno == blockno
+            })
+        });
+        drop(gate);
+        if let Some(buf) = buf {
+            let mut buf = BufUnlocked(ManuallyDrop::new(buf)).lock(ctx);
+            buf.deref_inner_mut().valid = false;
+            buf.free(ctx);
+        }
+    }
+
+    /// Returns the usage statistics of the shards added up. The high-water mark is the sum of
+    /// those of the shards, which reached them at different times.
+    pub fn stats(self: StrongPin<'_, Self>) -> ArenaStats {
+        let mut stats = ArenaStats::new("bcache", 0);
+        for shard in &self.ptr().shards {
+            // SAFETY: the shards are pinned, as self is.
+            let shard = unsafe { StrongPin::new_unchecked(shard) }.stats();
+            stats.capacity += shard.capacity;
+            stats.in_use += shard.in_use;
+            stats.high_water += shard.high_water;
+            stats.failed += shard.failed;
+        }
+        stats
+    }
+}
+
+ktest! {
+    fn buf_cached(ctx) {
+        let buf = hal().disk().read(ROOTDEV, BlockNo::new(1), ctx);
+        let data = buf.deref_inner().data.as_ptr();
+        let mut head = [0; 16];
+        head.copy_from_slice(&buf.deref_inner().data[..16]);
+        buf.free(ctx);
+
+        // The block is still cached, so it is not read again.
+        let buf = ctx.kernel().bcache().get_buf(ROOTDEV, BlockNo::new(1)).lock(ctx);
+        let valid = buf.deref_inner().valid;
+        let same = buf.deref_inner().data.as_ptr() == data && buf.deref_inner().data[..16] == head;
+        buf.free(ctx);
+        ktest_assert!(valid);
+        ktest_assert!(same);
+    }
+}
+
+ktest! {
+    fn buf_spilled(ctx) {
+        // More blocks of one home than it has buffers, which nobody reads.
+        let bcache = ctx.kernel().bcache();
+        let mut blocks = (1_000_000..)
+            .map(BlockNo::new)
+            .filter(|b| Bcache::home(ROOTDEV, *b) == 0);
+        let bufs = array![_ => bcache.get_buf(ROOTDEV, blocks.next().unwrap()); SHARDSIZE + 1];
+
+        // The spilled block keeps its buffer, even once its home has room.
+        let spilled = bufs[SHARDSIZE].blockno;
+        let again = bcache.get_buf(ROOTDEV, spilled);
+        let same = ptr::eq(&*again, &*bufs[SHARDSIZE]);
+        drop(bufs);
+        let after = bcache.get_buf(ROOTDEV, spilled);
+        let kept = ptr::eq(&*again, &*after);
+        drop(again);
+        drop(after);
+        ktest_assert!(same);
+        ktest_assert!(kept);
     }
 }
@@ -0,0 +1,98 @@
+//! Character devices, whose drivers claim their major device numbers with `register_chardev!`
+//! next to their code, instead of being added to the device table one by one at boot.
+//!
+//! `register_chardev!` places a `CharDev` in the .chardevs section, which kernel.ld gathers
+//! between `chardevs_start` and `chardevs_end`. At boot, `init_devsw` puts the `Devsw` of each in
+//! the device table at its major number, where the device files that mknod() makes with the
+//! number reach it. Majors that no driver claims have no functions, and two drivers that claim the
+//! same major stop the boot. The majors are also in kernel/file.h, for the programs that make the
+//! device files.
+
+use core::slice;
+
+use crate::{file::Devsw, param::NDEV};
+
+/// Registers `$devsw` as the functions of the character devices with major number `$major`,
+/// which `$name` names in messages.
+///
+/// e.g.
+/// ```rust,no_run
+/// register_chardev!(URANDOM_MAJOR, "urandom", Devsw {
+///     mode: 0o666,
+///     read: Some(urandom_read),
+///     write: Some(urandom_write),
+///     ..Devsw::NONE
+/// });
+/// ```
+#[macro_export]
+macro_rules! register_chardev {
+    ($major:expr, $name:literal, $devsw:expr) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".chardevs"]
+            static CHARDEV: $crate::chardev::CharDev = $crate::chardev::CharDev {
+                major: $major,
+                name: $name,
+                devsw: $devsw,
+            };
+        };
+    };
+}
+
+/// A character device registered with `register_chardev!`.
+pub struct CharDev {
+    /// The major device number that the driver claims.
+    pub major: u16,
+
+    /// The name of the device, for messages.
+    pub name: &'static str,
+
+    pub devsw: Devsw,
+}
+
+extern "C" {
+    // kernel.ld
+    static chardevs_start: [u8; 0];
+    static chardevs_end: [u8; 0];
+}
+
+/// Returns the registered character devices.
+fn chardevs() -> &'static [CharDev] {
+    // SAFETY: kernel.ld places the `CharDev`s between `chardevs_start` and `chardevs_end`.
+    unsafe {
+        let start = chardevs_start.as_ptr() as *const CharDev;
+        let end = chardevs_end.as_ptr() as *const CharDev;
+        slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Returns the name of the character device with major number `major`, if it is registered.
+pub fn chardev_name(major: usize) -> Option<&'static str> {
+    chardevs()
+        .iter()
+        .find(|dev| dev.major as usize == major)
+        .map(|dev| dev.name)
+}
+
+/// Fills the device table `devsw` with the registered character devices.
+/// Called once by `Kernel::init`.
+pub fn init_devsw(devsw: &mut [Devsw; NDEV]) {
+    let mut claimed: [Option<&str>; NDEV] = [None; NDEV];
+    for dev in chardevs() {
+        let major = dev.major as usize;
+        assert!(
+            major < NDEV,
+            "register_chardev: major {} of {} is past NDEV",
+            major,
+            dev.name
+        );
+        if let Some(name) = claimed[major] {
+            panic!(
+                "register_chardev: {} and {} both claim major {}",
+                name, dev.name, major
+            );
+        }
+        claimed[major] = Some(dev.name);
+        devsw[major] = dev.devsw;
+    }
+}
@@ -1,72 +1,195 @@
-//! Console input and output, to the uart. Reads are line at a time.
+//! Console input and output, to the uart and the framebuffer.
 //!
-//! Implements special input characters:
+//! Output goes to the uart unchanged, and the terminal on the other end interprets ANSI escape
+//! sequences. If QEMU has a ramfb display, output can also or instead be drawn on it, through
+//! `ansi::TextScreen`. QEMU chooses where output goes with
+//! `-fw_cfg name=opt/rv6/console,string=...`, where the string is "uart" (the default), "fb",
+//! or "both". Input always comes from the uart.
+//!
+//! A second uart, if the machine has one, is another terminal, /dev/ttyS1. It works the same as
+//! the console, except that the kernel does not print to it and it has no display.
+//!
+//! In canonical mode, reads are line at a time, and these special input characters are
+//! implemented:
 //! * newline -- end of line
 //! * control-h -- backspace
 //! * control-u -- kill line
 //! * control-d -- end of file
+//!
+//! With ISIG, these characters act on processes instead:
+//! * control-c -- send SIGINT to the foreground process group
+//! * control-z -- send SIGTSTP to the foreground process group
 //! * control-p -- print process list
+//!
+//! A shell sets the foreground process group with the TIOCSPGRP ioctl.
+//! Programs that want every keystroke as it is typed, such as editors, turn canonical mode off
+//! with the TCSETS ioctl. Then reads return as soon as any input is available.
+
+use core::{cmp, fmt, pin::Pin};
 
-use core::{fmt, pin::Pin};
+use bitflags::bitflags;
+use zerocopy::{AsBytes, FromBytes};
 
 use crate::{
-    arch::addr::UVAddr,
-    hal::hal,
+    ansi::{TextDisplay, TextScreen},
+    arch::{addr::UVAddr, fw_cfg::FwCfgFile},
+    driver::Device,
+    error::KernelError,
+    file::{Devsw, IoctlArg},
+    gdbstub::GdbStub,
+    hal::{hal, Hal},
+    irq::{IrqChip, IrqReturn},
     kernel::{Kernel, KernelRef},
-    lock::{SleepableLock, SleepableLockGuard, SpinLock, SpinLockGuard},
-    proc::KernelCtx,
+    lock::{SleepableLock, SleepableLockGuard, SpinLock},
+    poll::PollEvents,
+    proc::{KernelCtx, SIGINT, SIGTSTP},
+    ramfb::RamFb,
+    register_chardev, register_driver,
+    softirq::SoftIrq,
+    some_or,
     uart::Uart,
-    util::spin_loop,
+    util::{ring_buffer::RingBuffer, spin_loop},
 };
 
 /// Size of console input buffer.
 const INPUT_BUF: usize = 128;
 /// Size of console output buffer.
 const OUTPUT_BUF: usize = 32;
-
-struct OutputBuffer {
-    buf: [u8; OUTPUT_BUF],
-    /// Read index.
-    w: usize,
-    /// Write index.
-    r: usize,
+/// Size of the buffer of input that the uart interrupt took and its soft interrupt did not yet
+/// process.
+const RX_BUF: usize = 64;
+
+/// Major device number of the console, CONSOLE in kernel/file.h.
+const CONSOLE_MAJOR: u16 = 1;
+
+/// Major device number of the second uart, TTYS1 in kernel/file.h.
+const TTYS1_MAJOR: u16 = 3;
+
+/// Name of the firmware configuration file that chooses where console output goes.
+const CONSOLE_OPTION: &str = "opt/rv6/console";
+
+/// ioctl request to get the window size.
+pub const TIOCGWINSZ: u32 = 0x5413;
+/// ioctl request to set the window size.
+pub const TIOCSWINSZ: u32 = 0x5414;
+
+/// ioctl request to get the foreground process group.
+pub const TIOCGPGRP: u32 = 0x540f;
+/// ioctl request to set the foreground process group.
+pub const TIOCSPGRP: u32 = 0x5410;
+/// ioctl request to get the terminal attributes.
+pub const TCGETS: u32 = 0x5401;
+/// ioctl request to set the terminal attributes.
+pub const TCSETS: u32 = 0x5402;
+
+bitflags! {
+    /// Input flags of a terminal.
+    pub struct InputFlags: u32 {
+        /// Translate carriage return to newline on input.
+        const ICRNL = 0o400;
+    }
 }
 
-impl OutputBuffer {
-    pub const fn new() -> Self {
-        Self {
-            buf: [0; OUTPUT_BUF],
-            w: 0,
-            r: 0,
-        }
+bitflags! {
+    /// Local flags of a terminal.
+    pub struct LocalFlags: u32 {
+        /// Handle the special characters that act on processes instead of passing them to
+        /// readers.
+        const ISIG = 0o1;
+        /// Canonical mode: edit input, and make it available a line at a time.
+        const ICANON = 0o2;
+        /// Echo input characters.
+        const ECHO = 0o10;
     }
 }
 
+/// `struct termios` of user programs.
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct Termios {
+    /// `InputFlags`.
+    pub iflag: u32,
+    /// `LocalFlags`.
+    pub lflag: u32,
+}
+
+/// `struct winsize` of user programs.
+/// The console cannot detect the size of the terminal, so programs that know better set it.
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct WinSize {
+    pub row: u16,
+    pub col: u16,
+    pub xpixel: u16,
+    pub ypixel: u16,
+}
+
 struct InputBuffer {
-    buf: [u8; INPUT_BUF],
-    /// Read index.
-    r: usize,
-    /// Write index.
-    w: usize,
-    /// Edit index.
-    e: usize,
+    buf: RingBuffer<u8, INPUT_BUF>,
+    /// Number of bytes at the back of `buf` that are still being edited.
+    /// `read()` cannot consume them yet.
+    editing: usize,
+    /// Attributes of the terminal, which decide how input is processed.
+    /// A terminal starts line-editing and echoing.
+    iflag: InputFlags,
+    lflag: LocalFlags,
+    /// The process group that the interrupt and suspend characters signal, or 0 if none.
+    foreground: i32,
 }
 
 impl InputBuffer {
     pub const fn new() -> Self {
         Self {
-            buf: [0; INPUT_BUF],
-            w: 0,
-            r: 0,
-            e: 0,
+            buf: RingBuffer::new(0),
+            editing: 0,
+            iflag: InputFlags::ICRNL,
+            lflag: LocalFlags::from_bits_truncate(
+                LocalFlags::ISIG.bits() | LocalFlags::ICANON.bits() | LocalFlags::ECHO.bits(),
+            ),
+            foreground: 0,
+        }
+    }
+
+    fn canonical(&self) -> bool {
+        self.lflag.contains(LocalFlags::ICANON)
+    }
+
+    fn echo(&self) -> bool {
+        self.lflag.contains(LocalFlags::ECHO)
+    }
+
+    /// Returns `true` if there is no byte that `read()` can consume.
+    fn is_empty(&self) -> bool {
+        self.buf.len() == self.editing
+    }
+}
+
+/// Console output drawn on a display.
+struct Screen {
+    text: TextScreen,
+    /// `None` if output is not drawn.
+    display: Option<RamFb>,
+}
+
+impl Screen {
+    fn draw(&mut self, c: u8) {
+        if let Some(display) = &mut self.display {
+            self.text.write(c, display);
         }
     }
 }
 
 pub struct Console {
     uart: Uart,
+    /// Whether output goes to the uart.
+    uart_output: bool,
+    screen: SpinLock<Screen>,
     input_buffer: SleepableLock<InputBuffer>,
-    output_buffer: SleepableLock<OutputBuffer>,
+    /// Output waiting for the uart to send it.
+    output_buffer: SleepableLock<RingBuffer<u8, OUTPUT_BUF>>,
+    winsize: SpinLock<WinSize>,
+    /// Input that `intr` took from the uart, for `input`.
+    rx: RingBuffer<u8, RX_BUF>,
 }
 
 impl Console {
@@ -76,8 +199,26 @@ impl Console {
     pub const unsafe fn new(uart: usize) -> Self {
         Self {
             uart: unsafe { Uart::new(uart) },
+            uart_output: true,
+            screen: SpinLock::new(
+                "console_screen",
+                Screen {
+                    text: TextScreen::new(),
+                    display: None,
+                },
+            ),
             input_buffer: SleepableLock::new("console_input", InputBuffer::new()),
-            output_buffer: SleepableLock::new("console_output", OutputBuffer::new()),
+            output_buffer: SleepableLock::new("console_output", RingBuffer::new(0)),
+            winsize: SpinLock::new(
+                "console_winsize",
+                WinSize {
+                    row: 24,
+                    col: 80,
+                    xpixel: 0,
+                    ypixel: 0,
+                },
+            ),
+            rx: RingBuffer::new(0),
         }
     }
 
@@ -85,18 +226,74 @@ impl Console {
         self.uart.init();
     }
 
+    /// Chooses where output goes, as QEMU says.
+    ///
+    /// # Safety
+    ///
+    /// This method must be called only once, and only for the console.
+    pub unsafe fn init_output(&mut self) {
+        let mut option = [0; 8];
+        let len = FwCfgFile::find(CONSOLE_OPTION).map_or(0, |file| {
+            let len = cmp::min(file.size(), option.len());
+            file.read(&mut option[..len]);
+            len
+        });
+        let option = option[..len].split(|c| *c == 0 || *c == b'\n').next();
+        let (uart_output, fb_output) = match option {
+            Some(b"fb") => (false, true),
+            Some(b"both") => (true, true),
+            _ => (true, false),
+        };
+        if !fb_output {
+            return;
+        }
+        // SAFETY: this method is called only once.
+        if let Some(display) = unsafe { RamFb::new() } {
+            let (rows, cols) = display.size();
+            let (width, height) = display.resolution();
+            *self.winsize.get_mut() = WinSize {
+                row: rows as u16,
+                col: cols as u16,
+                xpixel: width as u16,
+                ypixel: height as u16,
+            };
+            self.screen.get_mut().display = Some(display);
+            self.uart_output = uart_output;
+        }
+    }
+
+    /// Draws `c` on the display, if there is one.
+    fn draw(&self, c: u8) {
+        self.screen.lock().draw(c);
+    }
+
+    /// Writes a character to the uart, spinning until it takes it, and draws it unless the screen
+    /// is locked. Takes no lock that this CPU may already hold, so that a panic from anywhere,
+    /// even from within the console or the kernel log, can print. The uart gets the character
+    /// even if console output goes only to the display, where the panic may not show.
+    pub fn putc_emergency(&self, c: u8) {
+        while self.uart.is_full() {}
+        self.uart.putc(c);
+        if let Some(mut screen) = self.screen.try_lock() {
+            screen.draw(c);
+        }
+    }
+
     /// Doesn't use interrupts, for use by kernel println() and to echo characters.
     /// It spins waiting for the uart's output register to be empty.
-    fn putc_spin(&self, c: u8, kernel: Pin<&Kernel>) {
+    pub fn putc_spin(&self, c: u8, kernel: Pin<&Kernel>) {
         let intr = hal().cpus().push_off();
-        if kernel.is_panicked() {
+        if kernel.is_panicked_elsewhere() {
             spin_loop();
         }
 
-        // Wait for Transmit Holding Empty to be set in LSR.
-        while self.uart.is_full() {}
+        if self.uart_output {
+            // Wait for Transmit Holding Empty to be set in LSR.
+            while self.uart.is_full() {}
 
-        self.uart.putc(c);
+            self.uart.putc(c);
+        }
+        self.draw(c);
 
         unsafe { hal().cpus().pop_off(intr) };
     }
@@ -108,7 +305,7 @@ impl Console {
         self.putc_spin(8, kernel);
     }
 
-    /// Add a character to the output buffer and tell the UART to start sending if it isn't
+    /// Draw a character, and add it to the output buffer and tell the UART to start sending if it isn't
     /// already. Blocks if the output buffer is full. Since it may block, it can't be called
     /// from interrupts; it's only suitable for use by write().
     fn putc_sleep(&self, c: u8, ctx: &KernelCtx<'_, '_>) {
@@ -116,17 +313,18 @@ impl Console {
             spin_loop();
         }
 
+        self.draw(c);
+        if !self.uart_output {
+            return;
+        }
+
         let mut guard = self.output_buffer.lock();
 
-        while guard.w == guard.r.wrapping_add(OUTPUT_BUF) {
+        while guard.push(c).is_err() {
             // Buffer is full.
             // Wait for flush_output_buffer() to open up space in the buffer.
             guard.sleep(ctx);
         }
-
-        let ind = guard.w % OUTPUT_BUF;
-        guard.buf[ind] = c;
-        guard.w += 1;
         self.flush_output_buffer(guard, ctx.kernel());
     }
 
@@ -134,11 +332,11 @@ impl Console {
     /// Called from both the top- and bottom-half.
     fn flush_output_buffer(
         &self,
-        mut guard: SleepableLockGuard<'_, OutputBuffer>,
+        mut guard: SleepableLockGuard<'_, RingBuffer<u8, OUTPUT_BUF>>,
         kernel: KernelRef<'_, '_>,
     ) {
         loop {
-            if guard.w == guard.r {
+            if guard.is_empty() {
                 // Transmit buffer is empty.
                 return;
             }
@@ -149,8 +347,7 @@ impl Console {
                 return;
             }
 
-            let c = guard.buf[guard.r % OUTPUT_BUF];
-            guard.r += 1;
+            let c = guard.pop().unwrap();
 
             // Maybe uart.putc() is waiting for space in the buffer.
             guard.wakeup(kernel);
@@ -159,7 +356,12 @@ impl Console {
         }
     }
 
-    fn write(&self, src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    fn write(
+        &self,
+        src: UVAddr,
+        n: i32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
         for i in 0..n {
             let mut c = [0u8];
             if ctx
@@ -168,37 +370,52 @@ impl Console {
                 .copy_in_bytes(&mut c, src + i as usize)
                 .is_err()
             {
-                return i;
+                return Ok(i as usize);
             }
             self.putc_sleep(c[0], ctx);
         }
-        n
+        Ok(n as usize)
     }
 
-    fn read(&self, mut dst: UVAddr, mut n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+    fn read(
+        &self,
+        mut dst: UVAddr,
+        mut n: i32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
         let mut guard = self.input_buffer.lock();
         let target = n;
         while n > 0 {
             // Wait until interrupt handler has put some
             // input into CONS.buffer.
-            while guard.r == guard.w {
+            while guard.is_empty() {
+                if !guard.canonical() && n < target {
+                    // In raw mode, return what has arrived so far.
+                    return Ok((target - n) as usize);
+                }
                 if ctx.proc().killed() {
-                    return -1;
+                    return Err(KernelError::Interrupted);
+                }
+                if n == target && ctx.stop_requested() {
+                    // Stop now rather than when input arrives.
+                    guard.reacquire_after(|| ctx.check_stop());
+                    continue;
                 }
                 guard.sleep(ctx);
             }
-            let cin = guard.buf[guard.r % INPUT_BUF] as i32;
-            guard.r = guard.r.wrapping_add(1);
+            let cin = guard.buf.front().unwrap() as i32;
 
             // end-of-file
-            if cin == ctrl('D') {
-                if n < target {
-                    // Save ^D for next time, to make sure
-                    // caller gets a 0-byte result.
-                    guard.r = guard.r.wrapping_sub(1)
+            if cin == ctrl('D') && guard.canonical() {
+                if n == target {
+                    let _ = guard.buf.pop();
                 }
+                // Otherwise, save ^D for next time, to make sure
+                // caller gets a 0-byte result.
                 break;
             } else {
+                let _ = guard.buf.pop();
+
                 // Copy the input byte to the user-space buffer.
                 let cbuf = [cin as u8];
                 if ctx
@@ -211,69 +428,174 @@ impl Console {
                 }
                 dst = dst + 1;
                 n -= 1;
-                if cin == '\n' as i32 {
+                if cin == '\n' as i32 && guard.canonical() {
                     // A whole line has arrived, return to
                     // the user-level read().
                     break;
                 }
             }
         }
-        target - n
+        Ok((target - n) as usize)
+    }
+
+    fn ioctl(
+        &self,
+        cmd: u32,
+        arg: IoctlArg,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        match cmd {
+            TIOCGWINSZ => {
+                let winsize = *self.winsize.lock();
+                arg.write(&winsize, ctx)?;
+            }
+            TIOCSWINSZ => {
+                let winsize = arg.read::<WinSize>(ctx)?;
+                *self.winsize.lock() = winsize;
+            }
+            TIOCGPGRP => {
+                let foreground = self.input_buffer.lock().foreground;
+                arg.write(&foreground, ctx)?;
+            }
+            TIOCSPGRP => {
+                let foreground = arg.read::<i32>(ctx)?;
+                if foreground < 0 {
+                    return Err(KernelError::InvalidArgument);
+                }
+                self.input_buffer.lock().foreground = foreground;
+            }
+            TCGETS => {
+                let guard = self.input_buffer.lock();
+                let termios = Termios {
+                    iflag: guard.iflag.bits(),
+                    lflag: guard.lflag.bits(),
+                };
+                drop(guard);
+                arg.write(&termios, ctx)?;
+            }
+            TCSETS => {
+                let termios = arg.read::<Termios>(ctx)?;
+                let mut guard = self.input_buffer.lock();
+                guard.iflag = InputFlags::from_bits_truncate(termios.iflag);
+                guard.lflag = LocalFlags::from_bits_truncate(termios.lflag);
+                if !guard.canonical() && guard.editing > 0 {
+                    // Leaving canonical mode hands the line being edited to read().
+                    guard.editing = 0;
+                    guard.wakeup(ctx.kernel());
+                    ctx.kernel().poll_queue().wakeup(ctx.kernel());
+                }
+            }
+            _ => return Err(KernelError::NotTty),
+        }
+        Ok(0)
+    }
+
+    fn poll(&self) -> PollEvents {
+        if self.input_buffer.lock().is_empty() {
+            PollEvents::POLLOUT
+        } else {
+            PollEvents::POLLIN | PollEvents::POLLOUT
+        }
     }
 
     /// Handle a uart interrupt, raised because input has arrived, or the uart is ready for more
-    /// output, or both. Called from trap.c. Do erase/kill processing, append to the input buffer,
-    /// and wake up read() if a whole line has arrived.
+    /// output, or both. Called from trap.rs. Takes the input from the uart, which stops the
+    /// interrupt, and raises the soft interrupt `irq` of the uart, which calls `input`. Input that
+    /// finds `rx` full is dropped, as if the uart's own buffer overran.
+    pub fn intr(&self, irq: SoftIrq) {
+        while let Ok(c) = self.uart.getc() {
+            // SAFETY: the PLIC lets one CPU at a time handle the interrupt of the uart.
+            let _ = unsafe { self.rx.push_shared(c as u8) };
+        }
+        self.uart.ack_intr();
+        hal().softirqs().raise(irq);
+    }
+
+    /// The soft interrupt of the uart. In canonical mode, do erase/kill processing, append to the
+    /// input buffer, and wake up read() if a whole line has arrived. In raw mode, append to the
+    /// input buffer and wake up read() at once. Then send out the buffered output.
     ///
     /// # Note
     ///
-    /// When `self.uart.getc()` is `Ok(ctrl('P'))`, this method is unsafe.
-    pub unsafe fn intr(&self, kernel: KernelRef<'_, '_>) {
-        // Read and process incoming characters.
-        while let Ok(c) = self.uart.getc() {
+    /// When the input has ctrl('P'), this method is unsafe.
+    pub unsafe fn input(&self, kernel: KernelRef<'_, '_>) {
+        // Process incoming characters.
+        // SAFETY: the soft interrupt of the uart runs on one CPU at a time.
+        while let Some(c) = unsafe { self.rx.pop_shared() } {
+            let c = c as i32;
             let mut guard = self.input_buffer.lock();
+            let c = if c == '\r' as i32 && guard.iflag.contains(InputFlags::ICRNL) {
+                '\n' as i32
+            } else {
+                c
+            };
+            let canonical = guard.canonical();
+            let echo = guard.echo();
+            let isig = guard.lflag.contains(LocalFlags::ISIG);
             match c {
                 // Print process list.
-                m if m == ctrl('P') => {
+                m if m == ctrl('P') && isig => {
                     unsafe { kernel.dump() };
                 }
 
+                // Interrupt or suspend the foreground process group.
+                m if (m == ctrl('C') || m == ctrl('Z')) && isig => {
+                    // Discard the line being edited.
+                    while guard.editing > 0 {
+                        let _ = guard.buf.pop_back();
+                        guard.editing -= 1;
+                    }
+                    if echo {
+                        self.putc_spin(b'^', kernel.as_ref());
+                        self.putc_spin((m + '@' as i32) as u8, kernel.as_ref());
+                        self.putc_spin(b'\n', kernel.as_ref());
+                    }
+                    let foreground = guard.foreground;
+                    drop(guard);
+                    if foreground > 0 {
+                        let sig = if m == ctrl('C') { SIGINT } else { SIGTSTP };
+                        let _ = kernel.procs().signal(-foreground, sig, None);
+                    }
+                }
+
                 // Kill line.
-                m if m == ctrl('U') => {
-                    while guard.e != guard.w
-                        && guard.buf[guard.e.wrapping_sub(1) % INPUT_BUF] != b'\n'
-                    {
-                        guard.e = guard.e.wrapping_sub(1);
-                        self.put_backspace_spin(kernel.as_ref());
+                m if m == ctrl('U') && canonical => {
+                    while guard.editing > 0 && guard.buf.back() != Some(b'\n') {
+                        let _ = guard.buf.pop_back();
+                        guard.editing -= 1;
+                        if echo {
+                            self.put_backspace_spin(kernel.as_ref());
+                        }
                     }
                 }
 
                 // Backspace
-                m if m == ctrl('H') | '\x7f' as i32 => {
-                    if guard.e != guard.w {
-                        guard.e = guard.e.wrapping_sub(1);
-                        self.put_backspace_spin(kernel.as_ref());
+                m if (m == ctrl('H') || m == '\x7f' as i32) && canonical => {
+                    if guard.editing > 0 {
+                        let _ = guard.buf.pop_back();
+                        guard.editing -= 1;
+                        if echo {
+                            self.put_backspace_spin(kernel.as_ref());
+                        }
                     }
                 }
 
                 _ => {
-                    if c != 0 && guard.e.wrapping_sub(guard.r) < INPUT_BUF {
-                        let c = if c == '\r' as i32 { '\n' as i32 } else { c };
-
+                    if (c != 0 || !canonical) && !guard.buf.is_full() {
                         // Echo back to the user.
-                        self.putc_spin(c as u8, kernel.as_ref());
+                        if echo {
+                            self.putc_spin(c as u8, kernel.as_ref());
+                        }
 
                         // Store for consumption by read().
-                        let ind = guard.e % INPUT_BUF;
-                        guard.buf[ind] = c as u8;
-                        guard.e = guard.e.wrapping_add(1);
-                        if c == '\n' as i32
-                            || c == ctrl('D')
-                            || guard.e == guard.r.wrapping_add(INPUT_BUF)
-                        {
-                            // Wake up read() if a whole line (or end-of-file) has arrived.
-                            guard.w = guard.e;
+                        let _ = guard.buf.push(c as u8);
+                        guard.editing += 1;
+                        if !canonical || c == '\n' as i32 || c == ctrl('D') || guard.buf.is_full() {
+                            // Wake up read() if a whole line (or end-of-file) has arrived,
+                            // or at once in raw mode.
+                            guard.editing = 0;
                             guard.wakeup(kernel);
+                            kernel.poll_queue().wakeup(kernel);
                         }
                     }
                 }
@@ -283,39 +605,34 @@ impl Console {
         // Write buffered characters.
         self.flush_output_buffer(self.output_buffer.lock(), kernel);
     }
-}
 
-pub struct Printer(SpinLock<()>);
-
-pub struct PrinterGuard<'a> {
-    kernel: Pin<&'a Kernel>,
-    _guard: Option<SpinLockGuard<'a, ()>>,
-}
-
-impl Printer {
-    pub const fn new() -> Self {
-        Self(SpinLock::new("Printer", ()))
-    }
-
-    pub fn lock<'a>(&'a self, kernel: Pin<&'a Kernel>) -> PrinterGuard<'a> {
-        PrinterGuard {
-            kernel,
-            _guard: Some(self.0.lock()),
+    /// Sends out the buffered characters, and leaves only the input interrupt on, so that a key
+    /// can wake up the suspended machine.
+    fn suspend(&self, kernel: KernelRef<'_, '_>) {
+        let mut guard = self.output_buffer.lock();
+        while let Some(c) = guard.pop() {
+            while self.uart.is_full() {}
+            self.uart.putc(c);
         }
+        guard.wakeup(kernel);
+        self.uart.disable_tx_intr();
     }
 
-    pub fn without_lock<'a>(&'a self, kernel: Pin<&'a Kernel>) -> PrinterGuard<'a> {
-        PrinterGuard {
-            kernel,
-            _guard: None,
-        }
+    /// Initializes the uart again, as it may have lost its state while the machine was suspended.
+    fn resume(&self, kernel: KernelRef<'_, '_>) {
+        self.uart.init();
+        self.flush_output_buffer(self.output_buffer.lock(), kernel);
     }
 }
 
-impl fmt::Write for PrinterGuard<'_> {
+/// Prints to the uart directly, through `Console::putc_emergency`, without going through the
+/// kernel log, which may be locked by this CPU or by one that stopped. Used after a panic.
+pub struct Printer;
+
+impl fmt::Write for Printer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         for c in s.bytes() {
-            hal().console().putc_spin(c, self.kernel);
+            hal().console().putc_emergency(c);
         }
         Ok(())
     }
@@ -326,14 +643,176 @@ const fn ctrl(x: char) -> i32 {
     x as i32 - '@' as i32
 }
 
+register_chardev!(
+    CONSOLE_MAJOR,
+    "console",
+    Devsw {
+        mode: 0o666,
+        read: Some(console_read),
+        write: Some(console_write),
+        ioctl: Some(console_ioctl),
+        poll: Some(console_poll),
+        suspend: Some(console_suspend),
+        resume: Some(console_resume),
+    }
+);
+
 /// User write()s to the console go here.
-pub fn console_write(src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+fn console_write(src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, KernelError> {
     hal().console().write(src, n, ctx)
 }
 
 /// User read()s from the console go here.
 /// Copy (up to) a whole input line to dst.
 /// User_dist indicates whether dst is a user or kernel address.
-pub fn console_read(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> i32 {
+fn console_read(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, KernelError> {
     hal().console().read(dst, n, ctx)
 }
+
+/// User ioctl()s on the console go here.
+fn console_ioctl(
+    cmd: u32,
+    arg: IoctlArg,
+    ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, KernelError> {
+    hal().console().ioctl(cmd, arg, ctx)
+}
+
+/// User poll()s on the console go here.
+fn console_poll(_ctx: &KernelCtx<'_, '_>) -> PollEvents {
+    hal().console().poll()
+}
+
+/// Suspends the console, before the machine is suspended.
+fn console_suspend(kernel: KernelRef<'_, '_>) -> Result<(), KernelError> {
+    hal().console().suspend(kernel);
+    Ok(())
+}
+
+/// Resumes the console, after the machine is resumed.
+fn console_resume(kernel: KernelRef<'_, '_>) -> Result<(), KernelError> {
+    hal().console().resume(kernel);
+    Ok(())
+}
+
+/// I/O port at which the second uart is placed.
+const SERIAL_PORT: u32 = 0x1000;
+
+/// Name of the QEMU file whose presence gives the second uart to the GDB stub.
+/// Pass it with `-fw_cfg name=opt/rv6/gdb,string=on`.
+const GDB_OPTION: &str = "opt/rv6/gdb";
+
+// QEMU's PCI serial port, a 16550a whose registers are in I/O ports.
+register_driver!("pci1b36,2", probe_serial);
+
+/// Sets up the second uart, as /dev/ttyS1 or for the GDB stub.
+fn probe_serial(mut hal: Pin<&mut Hal>, dev: &Device) {
+    let uart = match dev {
+        Device::Pci(pci) => pci.map_io(SERIAL_PORT).zip(pci.irq()),
+        Device::VirtioMmio { .. } => None,
+    };
+    let (uart, irq) = some_or!(uart, return);
+    if FwCfgFile::find(GDB_OPTION).is_some() {
+        // SAFETY: the PCI device owns uart..(uart + 8), which we mapped just now.
+        let gdb = unsafe { GdbStub::new(uart) };
+        gdb.init();
+        hal.as_mut().set_gdb(gdb);
+        hal.request_irq(IrqChip::Plic, irq, "gdb", gdb_intr);
+    } else {
+        // SAFETY: the PCI device owns uart..(uart + 8), which we mapped just now.
+        let serial = unsafe { Console::new(uart) };
+        serial.init();
+        hal.as_mut().set_serial(serial);
+        hal.request_irq(IrqChip::Plic, irq, "ttyS1", serial_intr);
+    }
+}
+
+/// Handles an interrupt of the console uart.
+pub fn console_intr(kernel: KernelRef<'_, '_>) -> IrqReturn {
+    hal().console().intr(SoftIrq::Console);
+    hal().suspend().wake(kernel);
+    IrqReturn::Handled
+}
+
+/// Handles an interrupt of the second uart, as /dev/ttyS1.
+fn serial_intr(kernel: KernelRef<'_, '_>) -> IrqReturn {
+    if let Some(serial) = hal().serial() {
+        serial.intr(SoftIrq::Serial);
+        hal().suspend().wake(kernel);
+    }
+    IrqReturn::Handled
+}
+
+/// Handles an interrupt of the second uart, for the GDB stub.
+fn gdb_intr(_kernel: KernelRef<'_, '_>) -> IrqReturn {
+    if let Some(gdb) = hal().gdb() {
+        gdb.intr();
+    }
+    IrqReturn::Handled
+}
+
+// Without a second uart, the device files of ttyS1 fail with ENODEV.
+register_chardev!(
+    TTYS1_MAJOR,
+    "ttyS1",
+    Devsw {
+        mode: 0o666,
+        read: Some(serial_read),
+        write: Some(serial_write),
+        ioctl: Some(serial_ioctl),
+        poll: Some(serial_poll),
+        suspend: Some(serial_suspend),
+        resume: Some(serial_resume),
+    }
+);
+
+/// User read()s from /dev/ttyS1 go here.
+fn serial_read(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, KernelError> {
+    hal()
+        .serial()
+        .ok_or(KernelError::NoDevice)?
+        .read(dst, n, ctx)
+}
+
+/// User write()s to /dev/ttyS1 go here.
+fn serial_write(src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, KernelError> {
+    hal()
+        .serial()
+        .ok_or(KernelError::NoDevice)?
+        .write(src, n, ctx)
+}
+
+/// User ioctl()s on /dev/ttyS1 go here.
+fn serial_ioctl(
+    cmd: u32,
+    arg: IoctlArg,
+    ctx: &mut KernelCtx<'_, '_>,
+) -> Result<usize, KernelError> {
+    hal()
+        .serial()
+        .ok_or(KernelError::NoDevice)?
+        .ioctl(cmd, arg, ctx)
+}
+
+/// User poll()s on /dev/ttyS1 go here.
+fn serial_poll(_ctx: &KernelCtx<'_, '_>) -> PollEvents {
+    hal()
+        .serial()
+        .map_or(PollEvents::empty(), |serial| serial.poll())
+}
+
+/// Suspends /dev/ttyS1, if there is one, before the machine is suspended.
+fn serial_suspend(kernel: KernelRef<'_, '_>) -> Result<(), KernelError> {
+    if let Some(serial) = hal().serial() {
+        serial.suspend(kernel);
+    }
+    Ok(())
+}
+
+/// Resumes /dev/ttyS1, if there is one, after the machine is resumed.
+fn serial_resume(kernel: KernelRef<'_, '_>) -> Result<(), KernelError> {
+    if let Some(serial) = hal().serial() {
+        serial.resume(kernel);
+    }
+    Ok(())
+}
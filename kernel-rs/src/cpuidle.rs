@@ -0,0 +1,158 @@
+//! The idle governor, which chooses how a CPU waits when it has no process to run.
+//!
+//! A CPU can wait in one of the `IDLE_STATES`: it can spin, which wakes up at once, or stop with
+//! `wfi` until an interrupt, which saves power but takes longer to wake up from. The machine has
+//! no firmware for deeper states, nor for scaling the frequency of the CPUs, so these are all.
+//! The governor predicts how long the CPU will be idle from the earliest deadline of its timers,
+//! and chooses the deepest state whose target residency, the shortest stay that pays for entering
+//! it, fits in the prediction. A CPU that makes a process runnable meanwhile kicks a CPU that
+//! waits in `wfi` with an IPI, so that the runnable processes spread over the idle CPUs.
+//!
+//! Each CPU counts how many times it entered each state and how long it stayed there, which
+//! /proc/idle reports.
+
+use core::{
+    hint,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use array_macro::array;
+
+use crate::{
+    arch::riscv::{intr_off, intr_on, wfi},
+    cpu::cpuid,
+    hal::hal,
+    ipi::send_ipi,
+    kernel::KernelRef,
+    param::NCPU,
+    time::ktime_now,
+};
+
+/// A way to wait for something to do.
+pub struct IdleState {
+    pub name: &'static str,
+
+    /// Time it takes to wake up from the state, in nanoseconds.
+    exit_latency_ns: u64,
+
+    /// Shortest stay in the state that is worth entering it for, in nanoseconds.
+    target_residency_ns: u64,
+}
+
+/// The idle states, from the shallowest.
+pub const IDLE_STATES: [IdleState; 2] = [
+    IdleState {
+        name: "spin",
+        exit_latency_ns: 0,
+        target_residency_ns: 0,
+    },
+    IdleState {
+        name: "wfi",
+        exit_latency_ns: 10_000,
+        target_residency_ns: 100_000,
+    },
+];
+
+const NIDLE: usize = IDLE_STATES.len();
+
+/// Indices of the idle states in `IDLE_STATES`.
+const SPIN: usize = 0;
+const WFI: usize = 1;
+
+/// Statistics of an idle state on a CPU.
+#[derive(Copy, Clone, Default)]
+pub struct IdleStats {
+    /// Number of times the CPU entered the state.
+    pub usage: u64,
+
+    /// Time the CPU stayed in the state, in nanoseconds.
+    pub time: u64,
+}
+
+pub struct CpuIdle {
+    /// Times each CPU entered each idle state.
+    usage: [[AtomicU64; NIDLE]; NCPU],
+
+    /// Time each CPU stayed in each idle state, in nanoseconds.
+    time: [[AtomicU64; NIDLE]; NCPU],
+
+    /// Whether each CPU waits in `wfi` until an interrupt.
+    waiting: [AtomicBool; NCPU],
+}
+
+impl CpuIdle {
+    pub const fn new() -> Self {
+        Self {
+            usage: array![_ => array![_ => AtomicU64::new(0); NIDLE]; NCPU],
+            time: array![_ => array![_ => AtomicU64::new(0); NIDLE]; NCPU],
+            waiting: array![_ => AtomicBool::new(false); NCPU],
+        }
+    }
+
+    /// Returns the deepest idle state worth entering for `predicted` nanoseconds of idleness.
+    fn select(predicted: u64) -> usize {
+        IDLE_STATES
+            .iter()
+            .rposition(|state| {
+                state.target_residency_ns <= predicted && state.exit_latency_ns <= predicted
+            })
+            .unwrap_or(0)
+    }
+
+    /// Waits in an idle state for a while, as this CPU has no process to run. Called by the
+    /// scheduler, with interrupts enabled.
+    pub fn idle(&self, kernel: KernelRef<'_, '_>) {
+        let id = cpuid();
+        let start = ktime_now();
+        let predicted = kernel
+            .timers()
+            .next_deadline()
+            .map_or(u64::MAX, |deadline| deadline.saturating_sub(start));
+        let state = Self::select(predicted);
+        match state {
+            SPIN => hint::spin_loop(),
+            WFI => {
+                // An interrupt that makes a process runnable after the check is not taken, but
+                // still ends `wfi`, as interrupts are disabled. A CPU that makes one runnable
+                // after the check sees `waiting` and kicks this one.
+                intr_off();
+                self.waiting[id].store(true, Ordering::SeqCst);
+                if kernel.procs().count_runnable() == 0 {
+                    wfi();
+                }
+                self.waiting[id].store(false, Ordering::SeqCst);
+                // SAFETY: the scheduler runs with interrupts enabled.
+                unsafe { intr_on() };
+            }
+            _ => unreachable!("CpuIdle::idle"),
+        }
+        let _ = self.usage[id][state].fetch_add(1, Ordering::Relaxed);
+        let _ = self.time[id][state].fetch_add(ktime_now() - start, Ordering::Relaxed);
+    }
+
+    /// Interrupts a CPU other than this one that waits in `wfi`, if there is one, so that it
+    /// runs a process that was just made runnable.
+    pub fn kick(&self) {
+        let intr = hal().cpus().push_off();
+        let me = cpuid();
+        let waiting = |id: &usize| {
+            *id != me
+                && self.waiting[*id]
+                    .compare_exchange(true, false, Ordering::SeqCst, Ordering::Relaxed)
+                    .is_ok()
+        };
+        if let Some(id) = (0..NCPU).find(waiting) {
+            send_ipi(id);
+        }
+        // SAFETY: interrupts were disabled by the `push_off` above.
+        unsafe { hal().cpus().pop_off(intr) };
+    }
+
+    /// Returns the statistics of the idle states of the CPU `id`, indexed as `IDLE_STATES`.
+    pub fn stats(&self, id: usize) -> [IdleStats; NIDLE] {
+        array![i => IdleStats {
+            usage: self.usage[id][i].load(Ordering::Relaxed),
+            time: self.time[id][i].load(Ordering::Relaxed),
+        }; NIDLE]
+    }
+}
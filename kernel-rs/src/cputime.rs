@@ -0,0 +1,102 @@
+//! CPU time accounting, which tells how each CPU and each process spent its time.
+//!
+//! Each CPU is in one of the states of `CpuState` at any time, and switches between them at the
+//! boundaries that the trap and scheduler code cross: entering and leaving user mode, switching
+//! to and from a process, and handling a device interrupt. At each switch, the time since the
+//! last switch goes to the state that the CPU leaves, and, if that was user mode or the kernel
+//! on behalf of a process, to the user or system time of the process running on the CPU.
+//! /proc/stat and /proc/<pid>/stat report the totals.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use array_macro::array;
+
+use crate::{cpu::cpuid, hal::hal, param::NCPU, time::ktime_now};
+
+/// What a CPU is doing.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CpuState {
+    /// Running a process in user mode.
+    User = 0,
+    /// Running the kernel on behalf of a process, or the scheduler.
+    System = 1,
+    /// Waiting for a process to become runnable.
+    Idle = 2,
+    /// Handling a device interrupt.
+    Irq = 3,
+}
+
+/// Number of `CpuState`s.
+pub const NSTATE: usize = 4;
+
+impl CpuState {
+    const fn from_usize(state: usize) -> Self {
+        match state {
+            0 => Self::User,
+            1 => Self::System,
+            2 => Self::Idle,
+            _ => Self::Irq,
+        }
+    }
+}
+
+pub struct CpuTimes {
+    /// The current state of each CPU.
+    states: [AtomicUsize; NCPU],
+
+    /// The monotonic time of the last switch of each CPU, or 0 if it has not switched yet.
+    since: [AtomicU64; NCPU],
+
+    /// The time each CPU spent in each state, in nanoseconds, indexed by `CpuState`.
+    times: [[AtomicU64; NSTATE]; NCPU],
+}
+
+impl CpuTimes {
+    pub const fn new() -> Self {
+        Self {
+            states: array![_ => AtomicUsize::new(CpuState::System as usize); NCPU],
+            since: array![_ => AtomicU64::new(0); NCPU],
+            times: array![_ => array![_ => AtomicU64::new(0); NSTATE]; NCPU],
+        }
+    }
+
+    /// Switches this CPU to `state`, accounting the time since the last switch to the state
+    /// it leaves, and to the process running on it, if any.
+    /// Returns the state it left.
+    ///
+    /// # Safety
+    ///
+    /// Interrupts must be disabled.
+    pub unsafe fn switch(&self, state: CpuState) -> CpuState {
+        let id = cpuid();
+        let now = ktime_now();
+        let prev = CpuState::from_usize(self.states[id].swap(state as usize, Ordering::Relaxed));
+        let since = self.since[id].swap(now, Ordering::Relaxed);
+        // The first switch of a CPU has nothing to account.
+        if since != 0 {
+            let elapsed = now.saturating_sub(since);
+            let _ = self.times[id][prev as usize].fetch_add(elapsed, Ordering::Relaxed);
+            if matches!(prev, CpuState::User | CpuState::System) {
+                // SAFETY: interrupts are disabled.
+                let proc = unsafe { hal().cpus().current_unchecked() }.get_proc();
+                // SAFETY: the process is running on this CPU, whose interrupts are disabled, so it
+                // stays valid until we return.
+                if let Some(proc) = unsafe { proc.as_ref() } {
+                    proc.add_cpu_time(prev == CpuState::User, elapsed);
+                }
+            }
+        }
+        prev
+    }
+
+    /// Returns whether the CPU `id` has started, i.e., switched at least once.
+    pub fn started(&self, id: usize) -> bool {
+        self.since[id].load(Ordering::Relaxed) != 0
+    }
+
+    /// Returns the time the CPU `id` spent in each state, in nanoseconds, indexed by `CpuState`.
+    /// The time since its last switch is not included.
+    pub fn times(&self, id: usize) -> [u64; NSTATE] {
+        array![i => self.times[id][i].load(Ordering::Relaxed); NSTATE]
+    }
+}
@@ -0,0 +1,186 @@
+//! User and group IDs and capabilities of processes.
+//!
+//! Each process has a real and an effective user ID, and a real and an effective group ID.
+//! Permission checks use the effective IDs. exec() of a file with the set-user-ID or set-group-ID
+//! bit sets the effective ID to the file's owner or group.
+//!
+//! Privileged operations check a capability each, instead of the user ID. A process whose
+//! effective user ID is `ROOT_UID` has the capabilities of its bounding set, and other processes
+//! have none. The bounding set starts with every capability and only shrinks, with capdrop(), so
+//! a process that dropped a capability never has it again, even through a set-user-ID program.
+//! See `rv6_abi::cred` for the capabilities.
+
+use bitflags::bitflags;
+use rv6_abi::cred::*;
+
+use crate::{audit::AuditEvent, error::KernelError, proc::KernelCtx};
+
+/// The user ID of the superuser.
+pub const ROOT_UID: u32 = 0;
+
+/// The largest user or group ID. Inodes and IPC objects keep their owners in 16 bits.
+pub const MAX_ID: u32 = u16::MAX as u32;
+
+bitflags! {
+    /// Capabilities of a process.
+    pub struct Caps: u64 {
+        const CHOWN = CAP_CHOWN;
+        const DAC_OVERRIDE = CAP_DAC_OVERRIDE;
+        const FOWNER = CAP_FOWNER;
+        const KILL = CAP_KILL;
+        const SETGID = CAP_SETGID;
+        const SETUID = CAP_SETUID;
+        const NET_BIND = CAP_NET_BIND;
+        const SYS_PTRACE = CAP_SYS_PTRACE;
+        const SYS_ADMIN = CAP_SYS_ADMIN;
+        const SYS_BOOT = CAP_SYS_BOOT;
+        const SYS_RESOURCE = CAP_SYS_RESOURCE;
+        const SYS_TIME = CAP_SYS_TIME;
+        const SYSLOG = CAP_SYSLOG;
+    }
+}
+
+/// The credentials of a process, which fork() copies.
+#[derive(Copy, Clone)]
+pub struct Cred {
+    /// Real and effective user IDs.
+    pub uid: u32,
+    euid: u32,
+
+    /// Real and effective group IDs.
+    pub gid: u32,
+    pub egid: u32,
+
+    /// The capabilities that the process has.
+    caps: Caps,
+
+    /// The capabilities that the process may have, which only shrinks.
+    bound: Caps,
+}
+
+impl Cred {
+    /// Returns the credentials of the initial process, the superuser with every capability.
+    pub const fn root() -> Self {
+        Self {
+            uid: ROOT_UID,
+            euid: ROOT_UID,
+            gid: 0,
+            egid: 0,
+            caps: Caps::all(),
+            bound: Caps::all(),
+        }
+    }
+
+    pub fn euid(&self) -> u32 {
+        self.euid
+    }
+
+    /// Sets the effective user ID to `euid`, which gives the capabilities of the bounding set
+    /// if it is the superuser's, and takes them all away otherwise.
+    pub fn set_euid(&mut self, euid: u32) {
+        self.euid = euid;
+        self.caps = if euid == ROOT_UID {
+            self.bound
+        } else {
+            Caps::empty()
+        };
+    }
+
+    pub fn caps(&self) -> Caps {
+        self.caps
+    }
+
+    /// Returns whether the process has the capability `cap`.
+    pub fn capable(&self, cap: Caps) -> bool {
+        self.caps.contains(cap)
+    }
+
+    /// Returns whether the process may send signals to a process with the credentials `target`:
+    /// if one of its user IDs is one of the target's, or it has `Caps::KILL`.
+    pub fn may_signal(&self, target: &Cred) -> bool {
+        self.capable(Caps::KILL)
+            || [self.uid, self.euid]
+                .iter()
+                .any(|id| *id == target.uid || *id == target.euid)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Returns whether the current process has the capability `cap`.
+    pub fn capable(&self, cap: Caps) -> bool {
+        self.proc().deref_data().cred.capable(cap)
+    }
+
+    /// Returns Ok(()) if the current process has the capability `cap`, or
+    /// Err(KernelError::NotPermitted) otherwise, which is audited.
+    pub fn require_cap(&self, cap: Caps) -> Result<(), KernelError> {
+        if self.capable(cap) {
+            Ok(())
+        } else {
+            let num = self.proc().trap_frame().a7 as u64;
+            self.audit(AuditEvent::Privileged, num, Some(KernelError::NotPermitted));
+            Err(KernelError::NotPermitted)
+        }
+    }
+
+    /// Set the user IDs to `uid`. A process with `Caps::SETUID` sets both the real and the
+    /// effective ID, and other processes may only set the effective ID back to the real one.
+    /// Audited.
+    /// Returns Ok(()) on success, Err(KernelError::InvalidArgument) if `uid` is above `MAX_ID`,
+    /// Err(KernelError::NotPermitted) if the process may not set it.
+    pub fn setuid(&mut self, uid: u32) -> Result<(), KernelError> {
+        let privileged = self.capable(Caps::SETUID);
+        let cred = &mut self.proc_mut().deref_mut_data().cred;
+        let res = if uid > MAX_ID {
+            Err(KernelError::InvalidArgument)
+        } else if privileged || uid == cred.uid {
+            if privileged {
+                cred.uid = uid;
+            }
+            cred.set_euid(uid);
+            Ok(())
+        } else {
+            Err(KernelError::NotPermitted)
+        };
+        self.audit(AuditEvent::Setuid, uid as u64, res.err());
+        res
+    }
+
+    /// Set the group IDs to `gid`, with the same rules as `setuid` but with `Caps::SETGID`.
+    /// Returns Ok(()) on success, Err(KernelError::InvalidArgument) if `gid` is above `MAX_ID`,
+    /// Err(KernelError::NotPermitted) if the process may not set it.
+    pub fn setgid(&mut self, gid: u32) -> Result<(), KernelError> {
+        let privileged = self.capable(Caps::SETGID);
+        let cred = &mut self.proc_mut().deref_mut_data().cred;
+        let res = if gid > MAX_ID {
+            Err(KernelError::InvalidArgument)
+        } else if privileged || gid == cred.gid {
+            if privileged {
+                cred.gid = gid;
+            }
+            cred.egid = gid;
+            Ok(())
+        } else {
+            Err(KernelError::NotPermitted)
+        };
+        self.audit(AuditEvent::Setgid, gid as u64, res.err());
+        res
+    }
+
+    /// Removes the capabilities `caps` from the bounding set of the current process, and from
+    /// its capabilities, for good. Audited.
+    /// Returns Ok(()) on success, Err(KernelError::InvalidArgument) if `caps` has unknown bits.
+    pub fn capdrop(&mut self, caps: u64) -> Result<(), KernelError> {
+        let res = match Caps::from_bits(caps) {
+            Some(caps) => {
+                let cred = &mut self.proc_mut().deref_mut_data().cred;
+                cred.bound.remove(caps);
+                cred.caps.remove(caps);
+                Ok(())
+            }
+            None => Err(KernelError::InvalidArgument),
+        };
+        self.audit(AuditEvent::CapDrop, caps, res.err());
+        res
+    }
+}
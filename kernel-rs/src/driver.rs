@@ -0,0 +1,139 @@
+//! Drivers, which declare the devices they handle with `register_driver!` next to their code,
+//! instead of being initialized one by one at boot.
+//!
+//! `register_driver!` places a `Driver` in the .drivers section, which kernel.ld gathers between
+//! `drivers_start` and `drivers_end`. At boot, `probe_devices` finds the devices of the machine on
+//! its buses, names each with a compatible string as a devicetree would, and calls the probe
+//! function of the first driver with the same compatible string:
+//!
+//! * "pciVVVV,DDDD" for the PCI device with vendor ID VVVV and device ID DDDD, on bus 0.
+//! * "virtio,deviceN" for the virtio MMIO device with device ID N.
+//!
+//! The IDs are in lowercase hexadecimal without leading zeros, such as "pci1b36,2". Devices that
+//! no driver handles are left alone. The machine has no devicetree of its own here, since the
+//! kernel does not take one from firmware, so the buses are those of QEMU's virt machine. The
+//! first uart, the console, is not probed, since the kernel prints to it before probing.
+
+use core::{fmt, pin::Pin, slice};
+
+use crate::{
+    arch::{
+        memlayout::{VIRTIO0, VIRTIO1, VIRTIO2},
+        pci::PciDevice,
+    },
+    hal::Hal,
+    virtio::virtio_device_id,
+};
+
+/// Registers a driver for the devices whose compatible string is `$compatible`, which
+/// `probe_devices` passes to `$probe`.
+///
+/// e.g.
+/// ```rust,no_run
+/// register_driver!("virtio,device4", probe_rng);
+/// ```
+#[macro_export]
+macro_rules! register_driver {
+    ($compatible:literal, $probe:path) => {
+        const _: () = {
+            #[used]
+            #[link_section = ".drivers"]
+            static DRIVER: $crate::driver::Driver = $crate::driver::Driver {
+                compatible: $compatible,
+                probe: $probe,
+            };
+        };
+    };
+}
+
+/// The virtio MMIO slots of the machine.
+const VIRTIO_SLOTS: [usize; 3] = [VIRTIO0, VIRTIO1, VIRTIO2];
+
+/// A driver registered with `register_driver!`.
+pub struct Driver {
+    /// The compatible string of the devices that the driver handles.
+    pub compatible: &'static str,
+
+    /// Sets up the device, and gives it to the `Hal`.
+    pub probe: fn(Pin<&mut Hal>, &Device),
+}
+
+/// A device found on a bus.
+pub enum Device {
+    /// A device on PCI bus 0.
+    Pci(PciDevice),
+
+    /// A virtio device whose MMIO registers start at `base`.
+    VirtioMmio { base: usize, device_id: u32 },
+}
+
+impl fmt::Display for Device {
+    /// Writes the compatible string of the device.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pci(dev) => write!(f, "pci{:x},{:x}", dev.vendor_id(), dev.device_id()),
+            Self::VirtioMmio { device_id, .. } => write!(f, "virtio,device{:x}", device_id),
+        }
+    }
+}
+
+/// Checks whether what is written to it is the string it was made with, without a buffer.
+struct Matcher<'a> {
+    rest: &'a [u8],
+    matches: bool,
+}
+
+impl fmt::Write for Matcher<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match self.rest.strip_prefix(s.as_bytes()) {
+            Some(rest) => self.rest = rest,
+            None => self.matches = false,
+        }
+        Ok(())
+    }
+}
+
+impl Device {
+    /// Returns whether `compatible` is the compatible string of the device.
+    fn is_compatible(&self, compatible: &str) -> bool {
+        let mut matcher = Matcher {
+            rest: compatible.as_bytes(),
+            matches: true,
+        };
+        let _ = fmt::write(&mut matcher, format_args!("{}", self));
+        matcher.matches && matcher.rest.is_empty()
+    }
+}
+
+extern "C" {
+    // kernel.ld
+    static drivers_start: [u8; 0];
+    static drivers_end: [u8; 0];
+}
+
+/// Returns the registered drivers.
+fn drivers() -> &'static [Driver] {
+    // SAFETY: kernel.ld places the `Driver`s between `drivers_start` and `drivers_end`.
+    unsafe {
+        let start = drivers_start.as_ptr() as *const Driver;
+        let end = drivers_end.as_ptr() as *const Driver;
+        slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+/// Finds the devices of the machine, and gives each to the driver registered for it, if any.
+/// Called once by `Hal::init`, before paging is turned on.
+pub fn probe_devices(mut hal: Pin<&mut Hal>) {
+    let pci = PciDevice::all().map(Device::Pci);
+    let virtio = VIRTIO_SLOTS.iter().filter_map(|&base| {
+        virtio_device_id(base).map(|device_id| Device::VirtioMmio { base, device_id })
+    });
+    for dev in pci.chain(virtio) {
+        if let Some(driver) = drivers()
+            .iter()
+            .find(|driver| dev.is_compatible(driver.compatible))
+        {
+            (driver.probe)(hal.as_mut(), &dev);
+        }
+    }
+}
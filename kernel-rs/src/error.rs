@@ -0,0 +1,107 @@
+//! Kernel error codes.
+
+use rv6_abi::errno::*;
+
+/// Reasons why a kernel operation failed.
+///
+/// Each variant corresponds to an errno-style code, defined in `rv6_abi::errno` for user programs.
+/// A failed system call returns the negation of the code to user space.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(i32)]
+pub enum KernelError {
+    /// Operation not permitted (EPERM).
+    NotPermitted = EPERM,
+    /// No such file or directory (ENOENT).
+    NoEntry = ENOENT,
+    /// No such process (ESRCH).
+    NoProcess = ESRCH,
+    /// Interrupted system call (EINTR).
+    Interrupted = EINTR,
+    /// I/O error (EIO).
+    Io = EIO,
+    /// Argument list too long (E2BIG).
+    ArgListTooLong = E2BIG,
+    /// Exec format error (ENOEXEC).
+    ExecFormat = ENOEXEC,
+    /// Bad file descriptor (EBADF).
+    BadFd = EBADF,
+    /// No child processes (ECHILD).
+    NoChild = ECHILD,
+    /// Resource temporarily unavailable (EAGAIN).
+    TryAgain = EAGAIN,
+    /// Out of memory (ENOMEM).
+    NoMemory = ENOMEM,
+    /// Permission denied (EACCES).
+    PermissionDenied = EACCES,
+    /// Bad address (EFAULT).
+    Fault = EFAULT,
+    /// Device or resource busy (EBUSY).
+    Busy = EBUSY,
+    /// File exists (EEXIST).
+    Exists = EEXIST,
+    /// Cross-device link (EXDEV).
+    CrossDevice = EXDEV,
+    /// No such device (ENODEV).
+    NoDevice = ENODEV,
+    /// Not a directory (ENOTDIR).
+    NotDir = ENOTDIR,
+    /// Is a directory (EISDIR).
+    IsDir = EISDIR,
+    /// Invalid argument (EINVAL).
+    InvalidArgument = EINVAL,
+    /// Too many open files in system (ENFILE).
+    FileTableFull = ENFILE,
+    /// Too many open files (EMFILE).
+    TooManyFiles = EMFILE,
+    /// Inappropriate ioctl for device (ENOTTY).
+    NotTty = ENOTTY,
+    /// File too large (EFBIG).
+    FileTooLarge = EFBIG,
+    /// No space left on device (ENOSPC).
+    NoSpace = ENOSPC,
+    /// Illegal seek (ESPIPE).
+    IllegalSeek = ESPIPE,
+    /// Broken pipe (EPIPE).
+    BrokenPipe = EPIPE,
+    /// Result too large (ERANGE).
+    Range = ERANGE,
+    /// File name too long (ENAMETOOLONG).
+    NameTooLong = ENAMETOOLONG,
+    /// Function not implemented (ENOSYS).
+    NoSys = ENOSYS,
+    /// Directory not empty (ENOTEMPTY).
+    NotEmpty = ENOTEMPTY,
+    /// Identifier removed (EIDRM).
+    IdentifierRemoved = EIDRM,
+    /// Socket operation on non-socket (ENOTSOCK).
+    NotSocket = ENOTSOCK,
+    /// Message too long (EMSGSIZE).
+    MessageTooLong = EMSGSIZE,
+    /// Address family not supported by protocol (EAFNOSUPPORT).
+    FamilyNotSupported = EAFNOSUPPORT,
+    /// Address already in use (EADDRINUSE).
+    AddrInUse = EADDRINUSE,
+    /// Connection reset by peer (ECONNRESET).
+    ConnReset = ECONNRESET,
+    /// Transport endpoint is already connected (EISCONN).
+    IsConnected = EISCONN,
+    /// Transport endpoint is not connected (ENOTCONN).
+    NotConnected = ENOTCONN,
+    /// Connection refused (ECONNREFUSED).
+    ConnRefused = ECONNREFUSED,
+    /// Disk quota exceeded (EDQUOT).
+    QuotaExceeded = EDQUOT,
+}
+
+impl KernelError {
+    /// Returns the errno-style code of this error.
+    pub const fn errno(self) -> i32 {
+        self as i32
+    }
+
+    /// Returns the value that a system call failed with this error passes to user space,
+    /// i.e. the negated code.
+    pub const fn to_syscall_ret(self) -> usize {
+        -(self.errno() as isize) as usize
+    }
+}
@@ -0,0 +1,172 @@
+//! Event counters, which processes and the kernel use to notify each other through file
+//! descriptors.
+//!
+//! Writing to an event counter adds a value to it, and reading from it returns the value and
+//! resets it to zero. A reader sleeps while the counter is zero, so the counter works as a
+//! wakeup channel that can also be waited for with poll().
+
+use core::mem;
+
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::addr::UVAddr,
+    error::KernelError,
+    file::FileType,
+    fs::FcntlFlags,
+    kernel::KernelRef,
+    lock::SpinLock,
+    poll::PollEvents,
+    proc::{KernelCtx, WaitChannel},
+};
+
+/// The maximum value of a counter.
+const MAX: u64 = u64::MAX - 1;
+
+pub struct EventFd {
+    /// The counter.
+    count: SpinLock<u64>,
+
+    /// In semaphore mode, a read decrements the counter by one and returns 1 instead.
+    semaphore: bool,
+
+    /// WaitChannel for saying the counter was changed.
+    waitchannel: WaitChannel,
+}
+
+impl EventFd {
+    pub const fn new(count: u64, semaphore: bool) -> Self {
+        Self {
+            count: SpinLock::new("eventfd", count),
+            semaphore,
+            waitchannel: WaitChannel::new(),
+        }
+    }
+
+    /// Reads the counter as an 8-byte integer into `addr`, and resets it.
+    /// If the counter is zero, sleeps until it becomes nonzero, or returns
+    /// `Err(KernelError::TryAgain)` if `nonblock` is set.
+    /// Returns Ok(8) on success, Err(KernelError) on error.
+    pub fn read(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        if n < mem::size_of::<u64>() {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut count = self.count.lock();
+        while *count == 0 {
+            if nonblock {
+                return Err(KernelError::TryAgain);
+            }
+            if ctx.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            self.waitchannel.sleep(&mut count, ctx);
+        }
+        let value = if self.semaphore { 1 } else { *count };
+        ctx.proc_mut().memory_mut().copy_out(addr, &value)?;
+        *count -= value;
+        drop(count);
+        self.wakeup(ctx.kernel());
+        Ok(mem::size_of::<u64>())
+    }
+
+    /// Adds the 8-byte integer at `addr` to the counter.
+    /// If the counter would exceed its maximum, sleeps until it is read, or returns
+    /// `Err(KernelError::TryAgain)` if `nonblock` is set.
+    /// Returns Ok(8) on success, Err(KernelError) on error.
+    pub fn write(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        if n < mem::size_of::<u64>() {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut value = 0u64;
+        ctx.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(value.as_bytes_mut(), addr)?;
+        if value > MAX {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut count = self.count.lock();
+        while MAX - *count < value {
+            if nonblock {
+                return Err(KernelError::TryAgain);
+            }
+            if ctx.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            self.waitchannel.sleep(&mut count, ctx);
+        }
+        *count += value;
+        drop(count);
+        self.wakeup(ctx.kernel());
+        Ok(mem::size_of::<u64>())
+    }
+
+    /// Adds `value` to the counter, saturating at its maximum.
+    /// Unlike `EventFd::write`, never sleeps, so kernel code can use this to notify processes.
+    // Dead code is allowed since no kernel subsystem signals an event counter yet.
+    #[allow(dead_code)]
+    pub fn signal(&self, value: u64, kernel: KernelRef<'_, '_>) {
+        let mut count = self.count.lock();
+        *count = count.saturating_add(value).min(MAX);
+        drop(count);
+        self.wakeup(kernel);
+    }
+
+    /// Returns the readiness of the counter.
+    pub fn poll(&self) -> PollEvents {
+        let count = *self.count.lock();
+        let mut events = PollEvents::empty();
+        if count > 0 {
+            events |= PollEvents::POLLIN;
+        }
+        if count < MAX {
+            events |= PollEvents::POLLOUT;
+        }
+        events
+    }
+
+    fn wakeup(&self, kernel: KernelRef<'_, '_>) {
+        self.waitchannel.wakeup(kernel);
+        kernel.poll_queue().wakeup(kernel);
+    }
+}
+
+/// Flag for eventfd(): reads decrement the counter by one.
+pub const EFD_SEMAPHORE: i32 = 1;
+
+impl KernelCtx<'_, '_> {
+    /// Create an event counter with the initial value `count`, and allocate a file descriptor
+    /// for it. `flags` may contain EFD_SEMAPHORE, O_NONBLOCK, and O_CLOEXEC.
+    /// Returns Ok(file descriptor) on success, Err(KernelError) on error.
+    pub fn eventfd(&mut self, count: u32, flags: i32) -> Result<usize, KernelError> {
+        let status = FcntlFlags::from_bits(flags & !EFD_SEMAPHORE)
+            .filter(|status| (FcntlFlags::O_NONBLOCK | FcntlFlags::O_CLOEXEC).contains(*status))
+            .ok_or(KernelError::InvalidArgument)?;
+        let event = EventFd::new(count as u64, flags & EFD_SEMAPHORE != 0);
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::EventFd { event }, true, true)
+            .map_err(|_| KernelError::FileTableFull)?;
+        f.set_status_flags(status);
+        let fd = f.fdalloc(self)?;
+        if status.contains(FcntlFlags::O_CLOEXEC) {
+            self.proc_mut()
+                .deref_mut_data()
+                .fds
+                .set_cloexec(fd as usize, true);
+        }
+        Ok(fd as usize)
+    }
+}
@@ -3,17 +3,20 @@
 use core::{cmp, mem};
 
 use bitflags::bitflags;
-use itertools::*;
 use zerocopy::{AsBytes, FromBytes};
 
 use crate::{
     arch::addr::{pgroundup, PAddr, PGSIZE},
-    fs::{FileSystem, Path},
+    audit::AuditEvent,
+    error::KernelError,
+    fs::{FileSystem, InodeType, Path, I_SGID, I_SUID, MAY_EXEC},
     hal::hal,
     page::Page,
     param::MAXARG,
     proc::KernelCtx,
+    util::static_vec::StaticVec,
     vm::UserMemory,
+    with_fs,
 };
 
 /// "\x7FELF" in little endian
@@ -96,48 +99,74 @@ impl ProgHdr {
 }
 
 impl KernelCtx<'_, '_> {
-    pub fn exec(&mut self, path: &Path, args: &[Page]) -> Result<usize, ()> {
+    pub fn exec(&mut self, path: &Path, args: &[Page]) -> Result<usize, KernelError> {
         if args.len() > MAXARG {
-            return Err(());
+            return Err(KernelError::ArgListTooLong);
         }
 
         let allocator = hal().kmem();
 
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let tx = scopeguard::guard(tx, |t| t.end(self));
-        let ptr = self.kernel().fs().namei(path, &tx, self)?;
-        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((&tx, self)));
-        let ip = ptr.lock(self);
-        let mut ip = scopeguard::guard(ip, |ip| ip.free(self));
-
-        // Check ELF header
-        let mut elf: ElfHdr = Default::default();
-        ip.read_kernel(&mut elf, 0, self)?;
-        if !elf.is_valid() {
-            return Err(());
-        }
+        let (elf, mem, mode, uid, gid, inum) = with_fs!(self.kernel().fs(), fs => {
+            let tx = fs.as_pin().get_ref().begin_tx(self);
+            let tx = scopeguard::guard(tx, |t| t.end(self));
+            let ptr = fs.namei(path, &tx, self)?;
+            let ptr = scopeguard::guard(ptr, |ptr| ptr.free((&tx, self)));
+            let ip = ptr.lock(self);
+            let mut ip = scopeguard::guard(ip, |ip| ip.free(self));
 
-        let trap_frame: PAddr = (self.proc().trap_frame() as *const _ as usize).into();
-        let mem = UserMemory::new(trap_frame, None, allocator).ok_or(())?;
-        let mut mem = scopeguard::guard(mem, |mem| mem.free(allocator));
+            // Only a regular file that someone may execute runs, even for the superuser.
+            let inner = ip.deref_inner();
+            if inner.typ != InodeType::File || inner.mode & 0o111 == 0 {
+                return Err(KernelError::PermissionDenied);
+            }
+            ip.check_access(MAY_EXEC, self)?;
+            let (mode, uid, gid) = (inner.mode, inner.uid, inner.gid);
+            let inum = ip.inum.into_u32() as u64;
+
+            // Check ELF header
+            let mut elf: ElfHdr = Default::default();
+            ip.read_kernel(&mut elf, 0, self)
+                .map_err(|_| KernelError::ExecFormat)?;
+            if !elf.is_valid() {
+                return Err(KernelError::ExecFormat);
+            }
+
+            let trap_frame: PAddr = (self.proc().trap_frame() as *const _ as usize).into();
+            let mem = UserMemory::new(trap_frame, None, allocator).ok_or(KernelError::NoMemory)?;
+            let mut mem = scopeguard::guard(mem, |mem| mem.free(allocator));
 
-        // Load program into memory.
-        for i in 0..elf.phnum as usize {
-            let off = elf.phoff + i * mem::size_of::<ProgHdr>();
+            // Load program into memory.
+            for i in 0..elf.phnum as usize {
+                let off = elf.phoff + i * mem::size_of::<ProgHdr>();
 
-            let mut ph: ProgHdr = Default::default();
-            ip.read_kernel(&mut ph, off as _, self)?;
-            if ph.is_prog_load() {
-                if ph.memsz < ph.filesz || ph.vaddr % PGSIZE != 0 {
-                    return Err(());
+                let mut ph: ProgHdr = Default::default();
+                ip.read_kernel(&mut ph, off as _, self)
+                    .map_err(|_| KernelError::ExecFormat)?;
+                if ph.is_prog_load() {
+                    if ph.memsz < ph.filesz || ph.vaddr % PGSIZE != 0 {
+                        return Err(KernelError::ExecFormat);
+                    }
+                    let end = ph
+                        .vaddr
+                        .checked_add(ph.memsz)
+                        .ok_or(KernelError::ExecFormat)?;
+                    let _ = mem.alloc(end, allocator)?;
+                    let file = (ip.dev, ip.inum);
+                    mem.load_file(
+                        ph.vaddr.into(),
+                        file,
+                        |dst, off| ip.read_bytes_kernel(dst, off, self),
+                        ph.off as _,
+                        ph.filesz as _,
+                    )?;
                 }
-                let _ = mem.alloc(ph.vaddr.checked_add(ph.memsz).ok_or(())?, allocator)?;
-                mem.load_file(ph.vaddr.into(), &mut ip, ph.off as _, ph.filesz as _, self)?;
             }
-        }
-        drop(ip);
-        drop(ptr);
-        drop(tx);
+            drop(ip);
+            drop(ptr);
+            drop(tx);
+            (elf, scopeguard::ScopeGuard::into_inner(mem), mode, uid, gid, inum)
+        });
+        let mut mem = scopeguard::guard(mem, |mem| mem.free(allocator));
 
         // Allocate two pages at the next page boundary.
         // Use the second as the user stack.
@@ -148,8 +177,8 @@ impl KernelCtx<'_, '_> {
         let stackbase: usize = sp - PGSIZE;
 
         // Push argument strings, prepare rest of stack in ustack.
-        let mut ustack = [0usize; MAXARG + 1];
-        for (arg, stack) in izip!(args, &mut ustack) {
+        let mut ustack = StaticVec::<usize, { MAXARG + 1 }>::new();
+        for arg in args {
             let null_idx = arg
                 .iter()
                 .position(|c| *c == 0)
@@ -160,25 +189,25 @@ impl KernelCtx<'_, '_> {
             // riscv sp must be 16-byte aligned
             sp &= !0xf;
             if sp < stackbase {
-                return Err(());
+                return Err(KernelError::ArgListTooLong);
             }
 
             mem.copy_out_bytes(sp.into(), bytes)?;
-            *stack = sp;
+            ustack.push(sp).map_err(|_| KernelError::ArgListTooLong)?;
         }
         let argc: usize = args.len();
-        ustack[argc] = 0;
+        ustack.push(0).map_err(|_| KernelError::ArgListTooLong)?;
 
         // push the array of argv[] pointers.
-        let argv_size = (argc + 1) * mem::size_of::<usize>();
+        let argv_size = ustack.len() * mem::size_of::<usize>();
         sp -= argv_size;
         sp &= !0xf;
         if sp < stackbase {
-            return Err(());
+            return Err(KernelError::ArgListTooLong);
         }
         // SAFETY: any byte can be considered as a valid u8.
         let (_, ustack, _) = unsafe { ustack.align_to::<u8>() };
-        mem.copy_out_bytes(sp.into(), &ustack[..argv_size])?;
+        mem.copy_out_bytes(sp.into(), ustack)?;
 
         // Save program name for debugging.
         let path_str = path.as_bytes();
@@ -200,6 +229,30 @@ impl KernelCtx<'_, '_> {
             scopeguard::ScopeGuard::into_inner(mem),
         )
         .free(allocator);
+        self.ptrace_exec();
+
+        // A set-user-ID or set-group-ID program runs with the IDs of its owner or group,
+        // unless it is traced.
+        if mode & (I_SUID | I_SGID) != 0 && !self.is_ptraced() {
+            let cred = &mut self.proc_mut().deref_mut_data().cred;
+            if mode & I_SUID != 0 {
+                cred.set_euid(uid as u32);
+            }
+            if mode & I_SGID != 0 {
+                cred.egid = gid as u32;
+            }
+        }
+        self.audit(AuditEvent::Exec, inum, None);
+
+        // Close the files marked close-on-exec.
+        for fd in 0..self.proc().deref_data().fds.len() {
+            let fds = &mut self.proc_mut().deref_mut_data().fds;
+            if fds.cloexec(fd) {
+                if let Some(f) = fds.take(fd) {
+                    f.free(self);
+                }
+            }
+        }
 
         // arguments to user main(argc, argv)
         // argc is returned via the system call return
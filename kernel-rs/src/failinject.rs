@@ -0,0 +1,69 @@
+//! Fault injection, which makes an operation of the kernel fail on purpose so that the paths that
+//! handle its failure can be tested.
+//!
+//! The superuser arms a site with failinject(), giving N, and the Nth operation at that site from
+//! then on fails, once. The count covers every process. The sites are:
+//!
+//! * `FaultSite::Kalloc`: page allocations, which fail as if `Kmem` were out of pages.
+//! * `FaultSite::Disk`: blocks of file contents read or written for a process, as by read() and
+//!   write(), which fail with `KernelError::Io` as if the disk failed the request, whether or not
+//!   the block is cached. Other disk requests, such as those of directories or of the log, have
+//!   no way to fail.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use array_macro::array;
+
+use crate::{cred::Caps, error::KernelError, hal::hal, proc::KernelCtx};
+
+/// Number of `FaultSite`s.
+const NSITES: usize = 2;
+
+/// Where faults are injected.
+#[derive(Copy, Clone)]
+pub enum FaultSite {
+    Kalloc = 0,
+    Disk = 1,
+}
+
+impl FaultSite {
+    fn from_usize(site: usize) -> Option<Self> {
+        match site {
+            0 => Some(Self::Kalloc),
+            1 => Some(Self::Disk),
+            _ => None,
+        }
+    }
+}
+
+pub struct FaultInjector {
+    /// For each site, the number of operations up to the one that fails, or 0 if none fails.
+    countdowns: [AtomicUsize; NSITES],
+}
+
+impl FaultInjector {
+    pub const fn new() -> Self {
+        Self {
+            countdowns: array![_ => AtomicUsize::new(0); NSITES],
+        }
+    }
+
+    /// Counts an operation at `site`, and returns `true` if it must fail.
+    pub fn should_fail(&self, site: FaultSite) -> bool {
+        self.countdowns[site as usize]
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            == Ok(1)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Make the `nth` operation at `site` from now on fail, or none if `nth` is 0. Injecting faults
+    /// needs `Caps::SYS_ADMIN`.
+    /// Returns Ok(number of operations that the site had left before failing, which is 0 if it
+    /// failed or was not armed) on success, Err(KernelError) on error.
+    pub fn fail_inject(&self, site: usize, nth: usize) -> Result<usize, KernelError> {
+        self.require_cap(Caps::SYS_ADMIN)?;
+        let site = FaultSite::from_usize(site).ok_or(KernelError::InvalidArgument)?;
+        Ok(hal().faults().countdowns[site as usize].swap(nth, Ordering::Relaxed))
+    }
+}
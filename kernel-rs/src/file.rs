@@ -6,32 +6,45 @@ use core::{
     mem::{self, ManuallyDrop},
     ops::Deref,
     ops::DerefMut,
+    ops::Range,
+    pin::Pin,
+    sync::atomic::{AtomicI32, Ordering},
 };
 
+use zerocopy::{AsBytes, FromBytes};
+
 use crate::{
     arch::addr::UVAddr,
-    arena::{Arena, ArenaObject, ArenaRc, ArrayArena},
-    fs::{FileSystem, InodeGuard, RcInode, Ufs},
-    hal::hal,
+    arena::{Arena, ArenaObject, ArenaRc, ChunkedArena},
+    error::KernelError,
+    eventfd::EventFd,
+    fs::{FcntlFlags, FileSystem, FsInode, InodeGuard, DEFAULT_DEVICE_MODE},
+    kernel::KernelRef,
     lock::SpinLock,
+    mqueue::RcMsgQueue,
     param::{BSIZE, MAXOPBLOCKS, NFILE},
+    pidfd::PidFd,
     pipe::AllocatedPipe,
+    poll::PollEvents,
     proc::KernelCtx,
+    procfs::ProcFile,
+    timerfd::TimerFd,
     util::strong_pin::StrongPin,
+    vsock::Socket,
+    with_inode,
 };
 
 pub enum FileType {
     None,
-    Pipe {
-        pipe: AllocatedPipe,
-    },
-    Inode {
-        inner: InodeFileType,
-    },
-    Device {
-        ip: RcInode<<Ufs as FileSystem>::InodeInner>,
-        major: u16,
-    },
+    Pipe { pipe: AllocatedPipe },
+    Inode { inner: InodeFileType },
+    Device { ip: FsInode, major: u16 },
+    EventFd { event: EventFd },
+    PidFd { pidfd: PidFd },
+    TimerFd { timer: TimerFd },
+    Proc { file: ProcFile },
+    Socket { socket: Socket },
+    MsgQueue { queue: RcMsgQueue },
 }
 
 /// It has an inode and an offset.
@@ -40,9 +53,23 @@ pub enum FileType {
 ///
 /// The offset should be accessed only when the inode is locked.
 pub struct InodeFileType {
-    pub ip: RcInode<<Ufs as FileSystem>::InodeInner>,
+    pub ip: FsInode,
     // It should be accessed only when `ip` is locked.
     pub off: UnsafeCell<u32>,
+    // It should be accessed only when `ip` is locked.
+    pub readahead: UnsafeCell<Readahead>,
+}
+
+/// How far an open inode file reads ahead of sequential reads, which `fadvise` tunes.
+pub struct Readahead {
+    /// Number of blocks to read ahead.
+    window: u32,
+
+    /// Offset at which the last read ended. A read from there is sequential.
+    next: u32,
+
+    /// Block up to which the blocks have been read ahead, exclusive.
+    until: u32,
 }
 
 /// It can be acquired when the inode of `InodeFileType` is locked. `ip` is the guard of the locked
@@ -51,23 +78,84 @@ pub struct InodeFileType {
 struct InodeFileTypeGuard<'a, I> {
     ip: ManuallyDrop<InodeGuard<'a, I>>,
     off: &'a mut u32,
+    readahead: &'a mut Readahead,
 }
 
 pub struct File {
     pub typ: FileType,
     readable: bool,
     writable: bool,
+    /// File status flags, i.e. the bits of `FcntlFlags` in `File::STATUS_FLAGS`.
+    status: AtomicI32,
 }
 
-pub type FileTable = SpinLock<ArrayArena<File, NFILE>>;
+pub type FileTable = SpinLock<ChunkedArena<File, NFILE>>;
+
+/// A device's read or write function.
+/// Returns Ok(number of bytes transferred) on success, Err(KernelError) on error.
+pub type DevswFn = fn(UVAddr, i32, &mut KernelCtx<'_, '_>) -> Result<usize, KernelError>;
+
+/// A device's ioctl function, which handles the request `cmd` with the argument `arg`.
+/// Returns Ok(request-specific value) on success, Err(KernelError) on error.
+pub type DevIoctlFn = fn(u32, IoctlArg, &mut KernelCtx<'_, '_>) -> Result<usize, KernelError>;
+
+/// A device's poll function, which returns the device's readiness.
+/// A device that changes its readiness must call `PollQueue::wakeup`.
+pub type DevPollFn = fn(&KernelCtx<'_, '_>) -> PollEvents;
 
-/// map major device number to device functions.
+/// A device's suspend or resume function, which quiesces the device before the machine is
+/// suspended, or brings it back after. A suspend function may refuse with an error.
+/// Returns Ok(()) on success, Err(KernelError) on error.
+pub type DevPmFn = fn(KernelRef<'_, '_>) -> Result<(), KernelError>;
+
+/// The functions of the devices with a major device number, registered with `register_chardev!`.
 #[derive(Copy, Clone)]
 pub struct Devsw {
-    pub read: Option<fn(UVAddr, i32, &mut KernelCtx<'_, '_>) -> i32>,
-    pub write: Option<fn(UVAddr, i32, &mut KernelCtx<'_, '_>) -> i32>,
+    /// The mode of new device files of this device.
+    pub mode: u16,
+    pub read: Option<DevswFn>,
+    pub write: Option<DevswFn>,
+    pub ioctl: Option<DevIoctlFn>,
+    pub poll: Option<DevPollFn>,
+    pub suspend: Option<DevPmFn>,
+    pub resume: Option<DevPmFn>,
+}
+
+impl Devsw {
+    /// The functions of a major that no driver claims, to be filled in with `..Devsw::NONE`.
+    pub const NONE: Self = Self {
+        mode: DEFAULT_DEVICE_MODE,
+        read: None,
+        write: None,
+        ioctl: None,
+        poll: None,
+        suspend: None,
+        resume: None,
+    };
 }
 
+/// The argument of an ioctl request, passed as is from user space.
+/// Depending on the request, it is either an integer or a pointer to user memory.
+#[derive(Copy, Clone, Debug)]
+pub struct IoctlArg(usize);
+
+/// fcntl commands.
+pub const F_GETFD: i32 = 1;
+pub const F_SETFD: i32 = 2;
+pub const F_GETFL: i32 = 3;
+pub const F_SETFL: i32 = 4;
+pub const F_SETPIPE_SZ: i32 = 1031;
+pub const F_GETPIPE_SZ: i32 = 1032;
+
+/// File descriptor flag for F_GETFD and F_SETFD: close the descriptor on exec.
+pub const FD_CLOEXEC: i32 = 1;
+
+/// fadvise advice.
+pub const FADV_NORMAL: i32 = 0;
+pub const FADV_RANDOM: i32 = 1;
+pub const FADV_SEQUENTIAL: i32 = 2;
+pub const FADV_DONTNEED: i32 = 4;
+
 /// A reference counted smart pointer to a `File`.
 pub type RcFile = ArenaRc<FileTable>;
 
@@ -78,17 +166,51 @@ impl Default for FileType {
 }
 
 impl InodeFileType {
-    fn lock(
-        &self,
-        ctx: &KernelCtx<'_, '_>,
-    ) -> InodeFileTypeGuard<'_, <Ufs as FileSystem>::InodeInner> {
-        let ip = self.ip.lock(ctx);
+    /// Returns the guard of this file, given the guard of its inode, which `with_inode!` gives
+    /// with the type of its file system.
+    ///
+    /// # Safety
+    ///
+    /// `ip` must be the locked inode of this file.
+    unsafe fn guard<'a, I>(&'a self, ip: InodeGuard<'a, I>) -> InodeFileTypeGuard<'a, I> {
         // SAFETY: `ip` is locked and `off` can be exclusively accessed.
         let off = unsafe { &mut *self.off.get() };
+        // SAFETY: `ip` is locked and `readahead` can be exclusively accessed.
+        let readahead = unsafe { &mut *self.readahead.get() };
         InodeFileTypeGuard {
             ip: ManuallyDrop::new(ip),
             off,
+            readahead,
+        }
+    }
+}
+
+impl Readahead {
+    /// Number of blocks to read ahead by default, or given FADV_NORMAL.
+    const DEFAULT_WINDOW: u32 = 4;
+    /// Number of blocks to read ahead given FADV_SEQUENTIAL.
+    const SEQUENTIAL_WINDOW: u32 = 16;
+
+    pub const fn new() -> Self {
+        Self {
+            window: Self::DEFAULT_WINDOW,
+            next: 0,
+            until: 0,
+        }
+    }
+
+    /// Returns the blocks to read ahead after a read of `off..end`, and records that they are.
+    fn after_read(&mut self, off: u32, end: u32) -> Range<u32> {
+        let sequential = off == self.next;
+        self.next = end;
+        if !sequential || self.window == 0 {
+            return 0..0;
         }
+        // The block of a read that ended in the middle of it is already cached.
+        let first = cmp::max((end as usize + BSIZE - 1) / BSIZE, self.until as usize) as u32;
+        let last = (end as usize / BSIZE) as u32 + self.window;
+        self.until = cmp::max(self.until, last);
+        first..last
     }
 }
 
@@ -122,18 +244,125 @@ impl<I> Drop for InodeFileTypeGuard<'_, I> {
     }
 }
 
+impl IoctlArg {
+    pub const fn new(arg: usize) -> Self {
+        Self(arg)
+    }
+
+    /// Copies a `T` from the user memory that the argument points to.
+    pub fn read<T: AsBytes + FromBytes>(
+        self,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<T, KernelError> {
+        let mut value = T::new_zeroed();
+        ctx.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(value.as_bytes_mut(), self.0.into())?;
+        Ok(value)
+    }
+
+    /// Copies `value` to the user memory that the argument points to.
+    pub fn write<T: AsBytes>(
+        self,
+        value: &T,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        ctx.proc_mut().memory_mut().copy_out(self.0.into(), value)
+    }
+}
+
 impl File {
+    /// Flags that can be changed by F_SETFL.
+    pub const STATUS_FLAGS: FcntlFlags =
+        FcntlFlags::from_bits_truncate(FcntlFlags::O_NONBLOCK.bits() | FcntlFlags::O_APPEND.bits());
+
     pub const fn new(typ: FileType, readable: bool, writable: bool) -> Self {
         Self {
             typ,
             readable,
             writable,
+            status: AtomicI32::new(0),
         }
     }
 
+    /// Returns the access mode and the status flags of this file, as reported by F_GETFL.
+    pub fn status_flags(&self) -> FcntlFlags {
+        let mode = match (self.readable, self.writable) {
+            (true, true) => FcntlFlags::O_RDWR,
+            (false, true) => FcntlFlags::O_WRONLY,
+            _ => FcntlFlags::O_RDONLY,
+        };
+        mode | FcntlFlags::from_bits_truncate(self.status.load(Ordering::Relaxed))
+    }
+
+    /// Sets the status flags of this file to those in `flags`.
+    /// The other bits of `flags` are ignored.
+    pub fn set_status_flags(&self, flags: FcntlFlags) {
+        self.status
+            .store((flags & Self::STATUS_FLAGS).bits(), Ordering::Relaxed);
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.status_flags().contains(FcntlFlags::O_NONBLOCK)
+    }
+
+    /// Tunes the readahead of this file with `advice`, one of the FADV_* values, for the `len`
+    /// bytes from `off`, or up to the end of the file if `len` is 0. FADV_DONTNEED drops those
+    /// bytes from the buffer cache.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn fadvise(
+        &self,
+        off: u32,
+        len: u32,
+        advice: i32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let inner = match &self.typ {
+            FileType::Inode { inner } => inner,
+            FileType::Pipe { .. } | FileType::Socket { .. } => {
+                return Err(KernelError::IllegalSeek)
+            }
+            _ => return Err(KernelError::InvalidArgument),
+        };
+        with_inode!(&inner.ip, ptr => {
+            // SAFETY: `ptr` is the inode of `inner`.
+            let mut ip = unsafe { inner.guard(ptr.lock(ctx)) };
+            let res = match advice {
+                FADV_NORMAL => {
+                    ip.readahead.window = Readahead::DEFAULT_WINDOW;
+                    Ok(0)
+                }
+                FADV_RANDOM => {
+                    ip.readahead.window = 0;
+                    Ok(0)
+                }
+                FADV_SEQUENTIAL => {
+                    ip.readahead.window = Readahead::SEQUENTIAL_WINDOW;
+                    Ok(0)
+                }
+                FADV_DONTNEED => {
+                    // Only the blocks that lie wholly in the range, or up to the end of the file.
+                    let first = (off as usize + BSIZE - 1) / BSIZE;
+                    let last = if len == 0 {
+                        (ip.deref_inner().size as usize + BSIZE - 1) / BSIZE
+                    } else {
+                        off.saturating_add(len) as usize / BSIZE
+                    };
+                    ip.evict_blocks(first as u32..last as u32, ctx);
+                    // Read the blocks again if they are read next.
+                    ip.readahead.until = cmp::min(ip.readahead.until, first as u32);
+                    Ok(0)
+                }
+                _ => Err(KernelError::InvalidArgument),
+            };
+            ip.free(ctx);
+            res
+        })
+    }
+
     /// Get metadata about file self.
     /// addr is a user virtual address, pointing to a struct stat.
-    pub fn stat(&self, addr: UVAddr, ctx: &mut KernelCtx<'_, '_>) -> Result<(), ()> {
+    pub fn stat(&self, addr: UVAddr, ctx: &mut KernelCtx<'_, '_>) -> Result<(), KernelError> {
         match &self.typ {
             FileType::Inode {
                 inner: InodeFileType { ip, .. },
@@ -142,47 +371,248 @@ impl File {
                 let st = ip.stat(ctx);
                 ctx.proc_mut().memory_mut().copy_out(addr, &st)
             }
-            _ => Err(()),
+            FileType::Proc { file } => ctx.proc_mut().memory_mut().copy_out(addr, &file.stat()),
+            _ => Err(KernelError::InvalidArgument),
         }
     }
 
     /// Read from file self.
     /// addr is a user virtual address.
-    pub fn read(&self, addr: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    pub fn read(
+        &self,
+        addr: UVAddr,
+        n: i32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
         if !self.readable {
-            return Err(());
+            return Err(KernelError::BadFd);
         }
 
         match &self.typ {
-            FileType::Pipe { pipe } => pipe.read(addr, n as usize, ctx),
+            FileType::Pipe { pipe } => pipe.read(addr, n as usize, self.nonblocking(), ctx),
+            FileType::EventFd { event } => event.read(addr, n as usize, self.nonblocking(), ctx),
+            FileType::TimerFd { timer } => timer.read(addr, n as usize, self.nonblocking(), ctx),
+            FileType::PidFd { pidfd } => pidfd.read(self.nonblocking(), ctx),
+            FileType::Proc { file } => file.read(addr, n as usize, ctx),
+            FileType::Socket { socket } => socket.read(addr, n as usize, self.nonblocking(), ctx),
+            // Messages are received with mq_receive().
+            FileType::MsgQueue { .. } => Err(KernelError::BadFd),
             FileType::Inode { inner } => {
-                let mut ip = inner.lock(ctx);
-                let curr_off = *ip.off;
-                let ret = ip.read_user(addr, curr_off, n as u32, ctx);
-                if let Ok(v) = ret {
-                    *ip.off += v as u32;
-                }
-                ip.free(ctx);
-                ret
+                with_inode!(&inner.ip, ptr => {
+                    // SAFETY: `ptr` is the inode of `inner`.
+                    let mut ip = unsafe { inner.guard(ptr.lock(ctx)) };
+                    let curr_off = *ip.off;
+                    let ret = ip.read_user(addr, curr_off, n as u32, ctx);
+                    if let Ok(v) = ret {
+                        *ip.off += v as u32;
+                        let blocks = ip.readahead.after_read(curr_off, *ip.off);
+                        ip.read_ahead(blocks, ctx);
+                    }
+                    ip.free(ctx);
+                    ret
+                })
             }
             FileType::Device { major, .. } => {
-                let major = ctx.kernel().devsw().get(*major as usize).ok_or(())?;
-                let read = major.read.ok_or(())?;
-                Ok(read(addr, n, ctx) as usize)
+                let major = ctx
+                    .kernel()
+                    .devsw()
+                    .get(*major as usize)
+                    .ok_or(KernelError::NoDevice)?;
+                let read = major.read.ok_or(KernelError::NoDevice)?;
+                read(addr, n, ctx)
             }
             FileType::None => panic!("File::read"),
         }
     }
 
+    /// Waits until the writes to this file are on the disk. A write reaches the disk when the log
+    /// commits, which it does once no system call is writing, so this commits the log unless
+    /// another system call is writing, and then the last of them does.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn fsync(&self, ctx: &KernelCtx<'_, '_>) -> Result<usize, KernelError> {
+        match &self.typ {
+            FileType::Inode { inner } => {
+                with_inode!(&inner.ip, ctx.kernel().vfs(), (_, fs) => {
+                    let tx = fs.as_pin().get_ref().begin_tx(ctx);
+                    tx.end(ctx);
+                });
+                Ok(0)
+            }
+            _ => Err(KernelError::InvalidArgument),
+        }
+    }
+
+    /// Sends up to `count` bytes of inode file `from` to pipe or socket self, straight from the
+    /// buffer cache instead of through user memory. Starts at `*off` and advances it if `off` is
+    /// given, or at the file offset of `from` otherwise. Sleeps while self has no room, unless it
+    /// is nonblocking.
+    /// Returns Ok(number of bytes sent) on success, Err(KernelError) on error.
+    pub fn sendfile(
+        &self,
+        from: &File,
+        mut off: Option<&mut u32>,
+        count: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        if !self.writable || !from.readable {
+            return Err(KernelError::BadFd);
+        }
+        let inner = match &from.typ {
+            FileType::Inode { inner } => inner,
+            _ => return Err(KernelError::InvalidArgument),
+        };
+        let send = |src: &[u8], ctx: &mut KernelCtx<'_, '_>| {
+            match &self.typ {
+                FileType::Pipe { pipe } => pipe.write_kernel(src, ctx),
+                FileType::Socket { socket } => socket.write_kernel(src, ctx),
+                _ => Err(KernelError::InvalidArgument),
+            }
+        };
+        if !matches!(self.typ, FileType::Pipe { .. } | FileType::Socket { .. }) {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let mut sent = 0;
+        while sent < count {
+            let generation = ctx.kernel().poll_queue().generation();
+            let (res, eof) = with_inode!(&inner.ip, ptr => {
+                // SAFETY: `ptr` is the inode of `inner`.
+                let mut ip = unsafe { inner.guard(ptr.lock(ctx)) };
+                let curr_off = off.as_deref().copied().unwrap_or(*ip.off);
+                let n = cmp::min(count - sent, u32::MAX as usize) as u32;
+                let res = ip.read_into(curr_off, n, send, ctx);
+                if let Ok(m) = res {
+                    match off.as_deref_mut() {
+                        Some(off) => *off += m as u32,
+                        None => *ip.off += m as u32,
+                    }
+                }
+                let eof = curr_off >= ip.deref_inner().size;
+                ip.free(ctx);
+                (res, eof)
+            });
+            let m = match res {
+                Ok(m) => m,
+                Err(_) if sent > 0 => break,
+                Err(e) => return Err(e),
+            };
+            sent += m;
+            if m == 0 {
+                if eof {
+                    break;
+                } else if self.nonblocking() {
+                    if sent == 0 {
+                        return Err(KernelError::TryAgain);
+                    }
+                    break;
+                } else if ctx.proc().killed() {
+                    return Err(KernelError::Interrupted);
+                }
+                // Wait for room.
+                ctx.kernel().poll_queue().wait(generation, ctx);
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Moves up to `len` bytes from self to `out`, one of which must be a pipe, without copying
+    /// them through user memory. Between two pipes, whole pages of data change hands instead of
+    /// being copied. The other file, if any, must be an inode file, and `off` is its offset to
+    /// use and advance instead of the file offset, if given. Sleeps while there is nothing to
+    /// move, unless a pipe is nonblocking.
+    /// Returns Ok(number of bytes moved) on success, Err(KernelError) on error.
+    pub fn splice(
+        &self,
+        out: &File,
+        off_in: Option<&mut u32>,
+        off_out: Option<&mut u32>,
+        len: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        if !self.readable || !out.writable {
+            return Err(KernelError::BadFd);
+        }
+        match (&self.typ, &out.typ) {
+            (FileType::Pipe { pipe }, FileType::Pipe { pipe: to }) => {
+                if off_in.is_some() || off_out.is_some() {
+                    return Err(KernelError::IllegalSeek);
+                }
+                if len == 0 {
+                    return Ok(0);
+                }
+                pipe.splice(to, len, self.nonblocking() || out.nonblocking(), ctx)
+            }
+            (FileType::Inode { .. }, FileType::Pipe { .. }) => {
+                if off_out.is_some() {
+                    return Err(KernelError::IllegalSeek);
+                }
+                out.sendfile(self, off_in, len, ctx)
+            }
+            (FileType::Pipe { pipe }, FileType::Inode { inner }) => {
+                if off_in.is_some() {
+                    return Err(KernelError::IllegalSeek);
+                }
+                if len == 0 {
+                    return Ok(0);
+                }
+                let unread = pipe.wait_unread(self.nonblocking(), ctx)?;
+                // As much as one transaction of write() holds.
+                let max = (MAXOPBLOCKS - 1 - 1 - 2) / 2 * BSIZE;
+                let n = cmp::min(cmp::min(len, unread), max);
+                if n == 0 {
+                    return Ok(0);
+                }
+                let append = out.status_flags().contains(FcntlFlags::O_APPEND);
+                with_inode!(&inner.ip, ctx.kernel().vfs(), (ptr, fs) => {
+                    let tx = fs.as_pin().get_ref().begin_tx(ctx);
+                    // SAFETY: `ptr` is the inode of `inner`.
+                    let mut ip = unsafe { inner.guard(ptr.lock(ctx)) };
+                    if append && off_out.is_none() {
+                        *ip.off = ip.deref_inner().size;
+                    }
+                    let curr_off = off_out.as_deref().copied().unwrap_or(*ip.off);
+                    let res = ip.write_from(
+                        curr_off,
+                        n as u32,
+                        |dst, ctx| pipe.read_exact_kernel(dst, ctx),
+                        &tx,
+                        ctx,
+                    );
+                    if let Ok(m) = res {
+                        match off_out {
+                            Some(off) => *off += m as u32,
+                            None => *ip.off += m as u32,
+                        }
+                    }
+                    tx.end(ctx);
+                    ip.free(ctx);
+                    res
+                })
+            }
+            _ => Err(KernelError::InvalidArgument),
+        }
+    }
+
     /// Write to file self.
     /// addr is a user virtual address.
-    pub fn write(&self, addr: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    pub fn write(
+        &self,
+        addr: UVAddr,
+        n: i32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
         if !self.writable {
-            return Err(());
+            return Err(KernelError::BadFd);
         }
 
         match &self.typ {
-            FileType::Pipe { pipe } => pipe.write(addr, n as usize, ctx),
+            FileType::Pipe { pipe } => pipe.write(addr, n as usize, self.nonblocking(), ctx),
+            FileType::EventFd { event } => event.write(addr, n as usize, self.nonblocking(), ctx),
+            FileType::TimerFd { .. } | FileType::PidFd { .. } | FileType::MsgQueue { .. } => {
+                Err(KernelError::BadFd)
+            }
+            FileType::Proc { file } => file.write(addr, n as usize, ctx),
+            FileType::Socket { socket } => socket.write(addr, n as usize, self.nonblocking(), ctx),
             FileType::Inode { inner } => {
                 let n = n as usize;
 
@@ -193,25 +623,33 @@ impl File {
                 // this really belongs lower down, since write()
                 // might be writing a device like the console.
                 let max = (MAXOPBLOCKS - 1 - 1 - 2) / 2 * BSIZE;
+                let append = self.status_flags().contains(FcntlFlags::O_APPEND);
 
                 let mut bytes_written: usize = 0;
                 while bytes_written < n {
                     let bytes_to_write = cmp::min(n - bytes_written, max);
-                    let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
-                    let mut ip = inner.lock(ctx);
-                    let curr_off = *ip.off;
-                    let r = ip.write_user(
-                        addr + bytes_written,
-                        curr_off,
-                        bytes_to_write as u32,
-                        ctx,
-                        &tx,
-                    );
-                    if let Ok(r) = r {
-                        *ip.off += r as u32;
-                    }
-                    tx.end(ctx);
-                    ip.free(ctx);
+                    let r = with_inode!(&inner.ip, ctx.kernel().vfs(), (ptr, fs) => {
+                        let tx = fs.as_pin().get_ref().begin_tx(ctx);
+                        // SAFETY: `ptr` is the inode of `inner`.
+                        let mut ip = unsafe { inner.guard(ptr.lock(ctx)) };
+                        if append {
+                            *ip.off = ip.deref_inner().size;
+                        }
+                        let curr_off = *ip.off;
+                        let r = ip.write_user(
+                            addr + bytes_written,
+                            curr_off,
+                            bytes_to_write as u32,
+                            ctx,
+                            &tx,
+                        );
+                        if let Ok(r) = r {
+                            *ip.off += r as u32;
+                        }
+                        tx.end(ctx);
+                        ip.free(ctx);
+                        r
+                    });
                     let r = r?;
                     if r != bytes_to_write {
                         // error from write_user
@@ -220,18 +658,78 @@ impl File {
                     bytes_written += r;
                 }
                 if bytes_written != n {
-                    return Err(());
+                    // write_user stops early only if it failed to copy from user memory, or if a
+                    // fault was injected after it wrote some bytes.
+                    return Err(KernelError::Fault);
                 }
                 Ok(n)
             }
             FileType::Device { major, .. } => {
-                let major = ctx.kernel().devsw().get(*major as usize).ok_or(())?;
-                let write = major.write.ok_or(())?;
-                Ok(write(addr, n, ctx) as usize)
+                let major = ctx
+                    .kernel()
+                    .devsw()
+                    .get(*major as usize)
+                    .ok_or(KernelError::NoDevice)?;
+                let write = major.write.ok_or(KernelError::NoDevice)?;
+                write(addr, n, ctx)
             }
             FileType::None => panic!("File::read"),
         }
     }
+
+    /// Returns the readiness of file self.
+    /// Only the events of the directions that self was opened for are reported.
+    pub fn poll(&self, ctx: &KernelCtx<'_, '_>) -> PollEvents {
+        let events = match &self.typ {
+            FileType::Pipe { pipe } => pipe.poll(),
+            FileType::EventFd { event } => event.poll(),
+            FileType::TimerFd { timer } => timer.poll(),
+            FileType::PidFd { pidfd } => pidfd.poll(ctx.kernel()),
+            FileType::Socket { socket } => socket.poll(),
+            FileType::MsgQueue { queue } => queue.data.poll(),
+            FileType::Inode { .. } | FileType::Proc { .. } => {
+                PollEvents::POLLIN | PollEvents::POLLOUT
+            }
+            FileType::Device { major, .. } => {
+                ctx.kernel()
+                    .devsw()
+                    .get(*major as usize)
+                    .and_then(|major| major.poll)
+                    .map_or(PollEvents::POLLIN | PollEvents::POLLOUT, |poll| poll(ctx))
+            }
+            FileType::None => panic!("File::poll"),
+        };
+        let mut mask = PollEvents::POLLERR | PollEvents::POLLHUP;
+        if self.readable {
+            mask |= PollEvents::POLLIN;
+        }
+        if self.writable {
+            mask |= PollEvents::POLLOUT;
+        }
+        events & mask
+    }
+
+    /// Send the control request `cmd` with the argument `arg` to the device of file self.
+    /// Returns Err(KernelError::NotTty) if self is not a device or its driver takes no requests.
+    pub fn ioctl(
+        &self,
+        cmd: u32,
+        arg: IoctlArg,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        match &self.typ {
+            FileType::Device { major, .. } => {
+                let major = ctx
+                    .kernel()
+                    .devsw()
+                    .get(*major as usize)
+                    .ok_or(KernelError::NoDevice)?;
+                let ioctl = major.ioctl.ok_or(KernelError::NotTty)?;
+                ioctl(cmd, arg, ctx)
+            }
+            _ => Err(KernelError::NotTty),
+        }
+    }
 }
 
 impl const Default for File {
@@ -244,54 +742,59 @@ impl ArenaObject for File {
     type Ctx<'a, 'id: 'a> = &'a KernelCtx<'id, 'a>;
 
     fn finalize<'a, 'id: 'a, A: Arena>(&mut self, ctx: Self::Ctx<'a, 'id>) {
+        if let FileType::TimerFd { timer } = &self.typ {
+            // SAFETY: `self` is in the file table and has not moved since the timer was armed.
+            unsafe { Pin::new_unchecked(timer) }.cancel(ctx.kernel());
+        }
         let typ = mem::replace(&mut self.typ, FileType::None);
         match typ {
-            FileType::Pipe { pipe } => {
-                if let Some(page) = pipe.close(self.writable, ctx) {
-                    hal().kmem().free(page);
-                }
-            }
+            FileType::Pipe { pipe } => pipe.close(self.writable, ctx),
+            FileType::Socket { socket } => socket.close(ctx.kernel()),
+            FileType::MsgQueue { queue } => queue.free(()),
             FileType::Inode {
                 inner: InodeFileType { ip, .. },
             }
-            | FileType::Device { ip, .. } => {
-                let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
-                ip.free((&tx, ctx));
-                tx.end(ctx);
-            }
+            | FileType::Device { ip, .. } => ip.free(ctx),
             _ => (),
         }
     }
 }
 
 impl FileTable {
-    pub const fn new_ftable() -> Self {
-        SpinLock::new("FTABLE", ArrayArena::<File, NFILE>::new())
+    /// # Safety
+    ///
+    /// Must be used only after initializing it with `ChunkedArena::init`.
+    pub const unsafe fn new_ftable() -> Self {
+        SpinLock::new("FTABLE", unsafe {
+            ChunkedArena::<File, NFILE>::new("ftable")
+        })
     }
 
     /// Allocate a file structure.
+    /// Returns `typ` back on failure, so that the caller can release it.
     pub fn alloc_file(
         self: StrongPin<'_, Self>,
         typ: FileType,
         readable: bool,
         writable: bool,
-    ) -> Result<RcFile, ()> {
-        self.alloc(|| File::new(typ, readable, writable)).ok_or(())
+    ) -> Result<RcFile, FileType> {
+        let mut typ = Some(typ);
+        self.alloc(|| File::new(typ.take().unwrap(), readable, writable))
+            .ok_or_else(|| typ.take().unwrap())
     }
 }
 
 impl RcFile {
     /// Allocate a file descriptor for the given file.
+    /// The descriptor's close-on-exec flag is cleared.
     /// Takes over file reference from caller on success.
-    pub fn fdalloc(self, ctx: &mut KernelCtx<'_, '_>) -> Result<i32, ()> {
-        let proc_data = ctx.proc_mut().deref_mut_data();
-        for (fd, f) in proc_data.open_files.iter_mut().enumerate() {
-            if f.is_none() {
-                *f = Some(self);
-                return Ok(fd as i32);
+    pub fn fdalloc(self, ctx: &mut KernelCtx<'_, '_>) -> Result<i32, KernelError> {
+        match ctx.proc_mut().deref_mut_data().fds.insert(self) {
+            Ok(fd) => Ok(fd as i32),
+            Err((f, e)) => {
+                f.free(ctx);
+                Err(e)
             }
         }
-        self.free(ctx);
-        Err(())
     }
 }
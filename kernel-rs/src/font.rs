@@ -0,0 +1,215 @@
+//! The bitmap font of the framebuffer console.
+//!
+//! Glyphs of the printable ASCII characters, rasterized from DejaVu Sans Mono at 14 pixels.
+//! Each glyph is `HEIGHT` rows of `WIDTH` pixels, and the most significant bit of a row is its
+//! leftmost pixel.
+
+/// Width of a glyph, in pixels.
+pub const WIDTH: usize = 8;
+
+/// Height of a glyph, in pixels.
+pub const HEIGHT: usize = 16;
+
+/// The first character that has a glyph.
+const FIRST: u8 = b' ';
+
+/// Returns the glyph of `c`. Characters without a glyph look like `?`.
+pub fn glyph(c: u8) -> &'static [u8; HEIGHT] {
+    GLYPHS
+        .get(c.wrapping_sub(FIRST) as usize)
+        .unwrap_or(&GLYPHS[(b'?' - FIRST) as usize])
+}
+
+#[rustfmt::skip]
+static GLYPHS: [[u8; HEIGHT]; 95] = [
+    // ' '
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // '!'
+    [0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // '"'
+    [0x00, 0x00, 0x00, 0x24, 0x24, 0x24, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // '#'
+    [0x00, 0x00, 0x00, 0x00, 0x12, 0x14, 0x7e, 0x24, 0x2c, 0x7e, 0x28, 0x48, 0x00, 0x00, 0x00, 0x00],
+    // '$'
+    [0x00, 0x00, 0x00, 0x00, 0x1c, 0x28, 0x60, 0x38, 0x1c, 0x02, 0x06, 0x3c, 0x00, 0x00, 0x00, 0x00],
+    // '%'
+    [0x00, 0x00, 0x00, 0x00, 0x70, 0x50, 0x72, 0x0c, 0x64, 0x0a, 0x0a, 0x0e, 0x00, 0x00, 0x00, 0x00],
+    // '&'
+    [0x00, 0x00, 0x00, 0x18, 0x20, 0x20, 0x30, 0x70, 0x4a, 0x4e, 0x46, 0x3e, 0x00, 0x00, 0x00, 0x00],
+    // '\''
+    [0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // '('
+    [0x00, 0x00, 0x00, 0x08, 0x08, 0x18, 0x10, 0x10, 0x10, 0x10, 0x10, 0x08, 0x08, 0x00, 0x00, 0x00],
+    // ')'
+    [0x00, 0x00, 0x00, 0x10, 0x10, 0x18, 0x08, 0x08, 0x08, 0x08, 0x08, 0x10, 0x10, 0x00, 0x00, 0x00],
+    // '*'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x3c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // '+'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x7e, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // ','
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x10, 0x10, 0x00, 0x00],
+    // '-'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // '.'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // '/'
+    [0x00, 0x00, 0x00, 0x00, 0x04, 0x0c, 0x08, 0x08, 0x10, 0x10, 0x20, 0x20, 0x40, 0x00, 0x00, 0x00],
+    // '0'
+    [0x00, 0x00, 0x00, 0x18, 0x24, 0x66, 0x42, 0x5a, 0x42, 0x66, 0x24, 0x3c, 0x00, 0x00, 0x00, 0x00],
+    // '1'
+    [0x00, 0x00, 0x00, 0x38, 0x38, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x3e, 0x00, 0x00, 0x00, 0x00],
+    // '2'
+    [0x00, 0x00, 0x00, 0x38, 0x44, 0x06, 0x04, 0x0c, 0x08, 0x10, 0x20, 0x7e, 0x00, 0x00, 0x00, 0x00],
+    // '3'
+    [0x00, 0x00, 0x00, 0x38, 0x04, 0x04, 0x04, 0x18, 0x04, 0x06, 0x06, 0x7c, 0x00, 0x00, 0x00, 0x00],
+    // '4'
+    [0x00, 0x00, 0x00, 0x0c, 0x0c, 0x14, 0x24, 0x24, 0x44, 0x7e, 0x04, 0x04, 0x00, 0x00, 0x00, 0x00],
+    // '5'
+    [0x00, 0x00, 0x00, 0x3c, 0x20, 0x20, 0x38, 0x0c, 0x06, 0x06, 0x04, 0x7c, 0x00, 0x00, 0x00, 0x00],
+    // '6'
+    [0x00, 0x00, 0x00, 0x1c, 0x20, 0x60, 0x58, 0x64, 0x62, 0x62, 0x26, 0x3c, 0x00, 0x00, 0x00, 0x00],
+    // '7'
+    [0x00, 0x00, 0x00, 0x7e, 0x04, 0x04, 0x0c, 0x08, 0x08, 0x18, 0x10, 0x30, 0x00, 0x00, 0x00, 0x00],
+    // '8'
+    [0x00, 0x00, 0x00, 0x3c, 0x24, 0x66, 0x24, 0x3c, 0x66, 0x42, 0x66, 0x3c, 0x00, 0x00, 0x00, 0x00],
+    // '9'
+    [0x00, 0x00, 0x00, 0x38, 0x64, 0x46, 0x46, 0x66, 0x3e, 0x06, 0x04, 0x38, 0x00, 0x00, 0x00, 0x00],
+    // ':'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // ';'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00, 0x18, 0x18, 0x10, 0x10, 0x00, 0x00],
+    // '<'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x0e, 0x70, 0x60, 0x1c, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // '='
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7e, 0x7e, 0x00, 0x7e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // '>'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x70, 0x0e, 0x06, 0x38, 0x60, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // '?'
+    [0x00, 0x00, 0x00, 0x3c, 0x24, 0x04, 0x0c, 0x08, 0x10, 0x10, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00],
+    // '@'
+    [0x00, 0x00, 0x00, 0x00, 0x3c, 0x62, 0x4e, 0x52, 0x52, 0x52, 0x5e, 0x40, 0x20, 0x1c, 0x00, 0x00],
+    // 'A'
+    [0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0x24, 0x24, 0x24, 0x7e, 0x42, 0x42, 0x00, 0x00, 0x00, 0x00],
+    // 'B'
+    [0x00, 0x00, 0x00, 0x78, 0x66, 0x66, 0x64, 0x7c, 0x66, 0x62, 0x66, 0x7c, 0x00, 0x00, 0x00, 0x00],
+    // 'C'
+    [0x00, 0x00, 0x00, 0x1c, 0x30, 0x60, 0x60, 0x60, 0x60, 0x60, 0x20, 0x1e, 0x00, 0x00, 0x00, 0x00],
+    // 'D'
+    [0x00, 0x00, 0x00, 0x78, 0x6c, 0x46, 0x46, 0x42, 0x46, 0x46, 0x44, 0x78, 0x00, 0x00, 0x00, 0x00],
+    // 'E'
+    [0x00, 0x00, 0x00, 0x3e, 0x60, 0x60, 0x60, 0x7e, 0x60, 0x60, 0x60, 0x7e, 0x00, 0x00, 0x00, 0x00],
+    // 'F'
+    [0x00, 0x00, 0x00, 0x3e, 0x20, 0x20, 0x20, 0x3c, 0x20, 0x20, 0x20, 0x20, 0x00, 0x00, 0x00, 0x00],
+    // 'G'
+    [0x00, 0x00, 0x00, 0x1c, 0x20, 0x60, 0x40, 0x44, 0x46, 0x62, 0x22, 0x3e, 0x00, 0x00, 0x00, 0x00],
+    // 'H'
+    [0x00, 0x00, 0x00, 0x42, 0x42, 0x42, 0x66, 0x7e, 0x42, 0x42, 0x42, 0x42, 0x00, 0x00, 0x00, 0x00],
+    // 'I'
+    [0x00, 0x00, 0x00, 0x3c, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3c, 0x00, 0x00, 0x00, 0x00],
+    // 'J'
+    [0x00, 0x00, 0x00, 0x1c, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04, 0x78, 0x00, 0x00, 0x00, 0x00],
+    // 'K'
+    [0x00, 0x00, 0x00, 0x42, 0x44, 0x48, 0x70, 0x78, 0x68, 0x4c, 0x46, 0x42, 0x00, 0x00, 0x00, 0x00],
+    // 'L'
+    [0x00, 0x00, 0x00, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x3e, 0x00, 0x00, 0x00, 0x00],
+    // 'M'
+    [0x00, 0x00, 0x00, 0x66, 0x66, 0x66, 0x5a, 0x5a, 0x5a, 0x42, 0x42, 0x42, 0x00, 0x00, 0x00, 0x00],
+    // 'N'
+    [0x00, 0x00, 0x00, 0x62, 0x62, 0x72, 0x52, 0x52, 0x4a, 0x4e, 0x46, 0x46, 0x00, 0x00, 0x00, 0x00],
+    // 'O'
+    [0x00, 0x00, 0x00, 0x18, 0x24, 0x66, 0x42, 0x42, 0x42, 0x66, 0x24, 0x3c, 0x00, 0x00, 0x00, 0x00],
+    // 'P'
+    [0x00, 0x00, 0x00, 0x3c, 0x66, 0x62, 0x62, 0x7e, 0x70, 0x60, 0x60, 0x60, 0x00, 0x00, 0x00, 0x00],
+    // 'Q'
+    [0x00, 0x00, 0x00, 0x18, 0x24, 0x66, 0x42, 0x42, 0x42, 0x66, 0x26, 0x3c, 0x0c, 0x00, 0x00, 0x00],
+    // 'R'
+    [0x00, 0x00, 0x00, 0x78, 0x6c, 0x46, 0x44, 0x7c, 0x6c, 0x44, 0x42, 0x42, 0x00, 0x00, 0x00, 0x00],
+    // 'S'
+    [0x00, 0x00, 0x00, 0x3c, 0x60, 0x40, 0x60, 0x3c, 0x06, 0x02, 0x06, 0x7c, 0x00, 0x00, 0x00, 0x00],
+    // 'T'
+    [0x00, 0x00, 0x00, 0x7e, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // 'U'
+    [0x00, 0x00, 0x00, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x66, 0x3c, 0x00, 0x00, 0x00, 0x00],
+    // 'V'
+    [0x00, 0x00, 0x00, 0x42, 0x42, 0x66, 0x24, 0x24, 0x24, 0x3c, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // 'W'
+    [0x00, 0x00, 0x00, 0x00, 0x42, 0x42, 0x5a, 0x5a, 0x5a, 0x66, 0x66, 0x24, 0x00, 0x00, 0x00, 0x00],
+    // 'X'
+    [0x00, 0x00, 0x00, 0x42, 0x26, 0x34, 0x18, 0x18, 0x18, 0x24, 0x66, 0x42, 0x00, 0x00, 0x00, 0x00],
+    // 'Y'
+    [0x00, 0x00, 0x00, 0x42, 0x66, 0x24, 0x3c, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // 'Z'
+    [0x00, 0x00, 0x00, 0x3e, 0x06, 0x04, 0x0c, 0x08, 0x10, 0x30, 0x20, 0x7e, 0x00, 0x00, 0x00, 0x00],
+    // '['
+    [0x00, 0x00, 0x00, 0x18, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x18, 0x00, 0x00],
+    // '\\'
+    [0x00, 0x00, 0x00, 0x40, 0x20, 0x20, 0x30, 0x10, 0x18, 0x08, 0x0c, 0x04, 0x04, 0x00, 0x00, 0x00],
+    // ']'
+    [0x00, 0x00, 0x00, 0x18, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x18, 0x00, 0x00],
+    // '^'
+    [0x00, 0x00, 0x00, 0x18, 0x3c, 0x24, 0x42, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // '_'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7e, 0x00],
+    // '`'
+    [0x00, 0x00, 0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    // 'a'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x38, 0x04, 0x06, 0x3e, 0x46, 0x46, 0x3e, 0x00, 0x00, 0x00, 0x00],
+    // 'b'
+    [0x00, 0x00, 0x00, 0x60, 0x60, 0x78, 0x64, 0x62, 0x62, 0x62, 0x66, 0x7c, 0x00, 0x00, 0x00, 0x00],
+    // 'c'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x1c, 0x30, 0x20, 0x20, 0x20, 0x20, 0x1e, 0x00, 0x00, 0x00, 0x00],
+    // 'd'
+    [0x00, 0x00, 0x00, 0x06, 0x06, 0x1e, 0x26, 0x46, 0x46, 0x46, 0x66, 0x3e, 0x00, 0x00, 0x00, 0x00],
+    // 'e'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x24, 0x62, 0x7e, 0x40, 0x60, 0x3e, 0x00, 0x00, 0x00, 0x00],
+    // 'f'
+    [0x00, 0x00, 0x00, 0x0e, 0x18, 0x3c, 0x18, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x00, 0x00, 0x00],
+    // 'g'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x26, 0x46, 0x46, 0x46, 0x66, 0x3e, 0x04, 0x2c, 0x18, 0x00],
+    // 'h'
+    [0x00, 0x00, 0x00, 0x60, 0x60, 0x6c, 0x64, 0x66, 0x66, 0x66, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00],
+    // 'i'
+    [0x00, 0x00, 0x00, 0x08, 0x00, 0x30, 0x18, 0x08, 0x08, 0x08, 0x08, 0x7e, 0x00, 0x00, 0x00, 0x00],
+    // 'j'
+    [0x00, 0x00, 0x00, 0x08, 0x00, 0x38, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x08, 0x18, 0x20, 0x00],
+    // 'k'
+    [0x00, 0x00, 0x00, 0x20, 0x20, 0x20, 0x24, 0x28, 0x38, 0x2c, 0x24, 0x22, 0x00, 0x00, 0x00, 0x00],
+    // 'l'
+    [0x00, 0x00, 0x00, 0x30, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x0c, 0x00, 0x00, 0x00, 0x00],
+    // 'm'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x74, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x5a, 0x00, 0x00, 0x00, 0x00],
+    // 'n'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x64, 0x66, 0x66, 0x66, 0x66, 0x66, 0x00, 0x00, 0x00, 0x00],
+    // 'o'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x24, 0x66, 0x42, 0x42, 0x66, 0x3c, 0x00, 0x00, 0x00, 0x00],
+    // 'p'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x64, 0x62, 0x62, 0x62, 0x66, 0x7c, 0x60, 0x60, 0x00, 0x00],
+    // 'q'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x18, 0x26, 0x66, 0x46, 0x46, 0x66, 0x3e, 0x06, 0x06, 0x00, 0x00],
+    // 'r'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x3a, 0x30, 0x30, 0x30, 0x30, 0x30, 0x00, 0x00, 0x00, 0x00],
+    // 's'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x1c, 0x24, 0x20, 0x38, 0x04, 0x04, 0x3c, 0x00, 0x00, 0x00, 0x00],
+    // 't'
+    [0x00, 0x00, 0x00, 0x00, 0x10, 0x7c, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1c, 0x00, 0x00, 0x00, 0x00],
+    // 'u'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x66, 0x66, 0x66, 0x66, 0x26, 0x3e, 0x00, 0x00, 0x00, 0x00],
+    // 'v'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x66, 0x24, 0x24, 0x3c, 0x18, 0x18, 0x00, 0x00, 0x00, 0x00],
+    // 'w'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x42, 0x5a, 0x5a, 0x24, 0x24, 0x00, 0x00, 0x00, 0x00],
+    // 'x'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x24, 0x18, 0x18, 0x18, 0x24, 0x42, 0x00, 0x00, 0x00, 0x00],
+    // 'y'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x42, 0x62, 0x24, 0x24, 0x1c, 0x18, 0x18, 0x10, 0x30, 0x00, 0x00],
+    // 'z'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x3c, 0x04, 0x0c, 0x08, 0x10, 0x20, 0x3c, 0x00, 0x00, 0x00, 0x00],
+    // '{'
+    [0x00, 0x00, 0x00, 0x0c, 0x08, 0x18, 0x18, 0x10, 0x30, 0x10, 0x18, 0x18, 0x08, 0x0c, 0x00, 0x00],
+    // '|'
+    [0x00, 0x00, 0x00, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x00, 0x00],
+    // '}'
+    [0x00, 0x00, 0x00, 0x30, 0x10, 0x18, 0x18, 0x08, 0x0c, 0x08, 0x18, 0x18, 0x10, 0x30, 0x00, 0x00],
+    // '~'
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x20, 0x5e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+];
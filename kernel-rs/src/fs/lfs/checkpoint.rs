@@ -0,0 +1,210 @@
+//! The checkpoint, which saves the segment usage table and the inode map at fixed addresses, so
+//! that mounting the file system does not need to read the whole log.
+//!
+//! Segment 0 holds two checkpoint regions after the boot block and the superblock, and the
+//! checkpoints alternate between them. A region is a header block followed by the blocks of the
+//! table and the map. The header is written last, and its checksum covers the whole region, so a
+//! checkpoint that a crash cut short leaves an invalid region, and mounting falls back to the
+//! other one, the previous checkpoint.
+//!
+//! The segment writer checkpoints right after it writes a partial segment, while no transaction
+//! is outstanding, so a checkpoint never records a block that is not on disk. It does so once
+//! the table has changed and SEGTABLE_INTERVAL seconds have passed since the last checkpoint, or
+//! once a segment became free. Mounting checkpoints too, after it rolls forward.
+
+use core::{mem, sync::atomic::Ordering};
+
+use static_assertions::const_assert;
+use zerocopy::{AsBytes, FromBytes, LayoutVerified};
+
+use super::{
+    imap::IMAP_BLOCKS,
+    now,
+    segment::SegManager,
+    segtable::{SEGSIZE, SEGTABLE_BLOCKS},
+    Lfs,
+};
+use crate::{
+    hal::hal,
+    param::BSIZE,
+    proc::KernelCtx,
+    util::{
+        branded::{BlockNo, DevNo},
+        hash::crc32c,
+    },
+};
+
+/// Magic number of a checkpoint header.
+const CHECKPOINT_MAGIC: u32 = 0x4c46_5343;
+
+/// The first block of checkpoint region 0, after the boot block and the superblock.
+const CHECKPOINT_START: usize = 2;
+
+/// Number of blocks of a checkpoint region.
+const CHECKPOINT_BLOCKS: usize = 1 + SEGTABLE_BLOCKS + IMAP_BLOCKS;
+
+const_assert!(CHECKPOINT_START + 2 * CHECKPOINT_BLOCKS <= SEGSIZE);
+
+/// The header block of a checkpoint region.
+#[repr(C)]
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+pub struct CheckpointHeader {
+    magic: u32,
+
+    /// Number of the checkpoint, which grows with every checkpoint. Checkpoint n is in region
+    /// n % 2.
+    pub serial: u32,
+
+    /// Serial number of the first partial segment written after the checkpoint.
+    pub next: u32,
+
+    /// The segment, and the offset in it, where that partial segment goes.
+    pub seg: u32,
+    pub start: u32,
+
+    /// When the checkpoint was written, in seconds since the Unix epoch.
+    pub time: u32,
+
+    /// CRC32C of the checksums of the blocks of the region, that of the header with this field
+    /// 0 first.
+    crc: u32,
+
+    /// Zero
+    reserved: u32,
+}
+
+const_assert!(mem::size_of::<CheckpointHeader>() <= BSIZE);
+
+/// Returns the address of block `i` of the region of checkpoint `serial`.
+fn region_block(serial: u32, i: usize) -> BlockNo {
+    let region = CHECKPOINT_START + (serial as usize % 2) * CHECKPOINT_BLOCKS;
+    BlockNo::new((region + i) as u32)
+}
+
+impl CheckpointHeader {
+    /// Returns the checksum of a region whose other blocks have the checksums `crcs[1..]`.
+    fn checksum(&self, crcs: &mut [u32; CHECKPOINT_BLOCKS]) -> u32 {
+        let header = Self { crc: 0, ..*self };
+        crcs[0] = crc32c(header.as_bytes());
+        crc32c(crcs.as_bytes())
+    }
+}
+
+impl Lfs {
+    /// Writes a checkpoint of the segment usage table and the inode map, if one is due. Called by
+    /// the segment writer after it wrote the partial segments of the transactions, while no
+    /// transaction is outstanding.
+    pub fn checkpoint(&self, segmanager: &SegManager, ctx: &KernelCtx<'_, '_>) {
+        if self.segtable.lock().checkpoint_due(now(ctx)) {
+            self.write_checkpoint(
+                segmanager.dev(),
+                segmanager.serial(),
+                segmanager.segment(),
+                segmanager.start(),
+                ctx,
+            );
+        }
+    }
+
+    /// Writes a checkpoint of `dev`, after which the segment writer writes partial segment
+    /// `next` at offset `start` of segment `seg`.
+    pub fn write_checkpoint(
+        &self,
+        dev: DevNo,
+        next: u32,
+        seg: usize,
+        start: usize,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        let now = now(ctx);
+        let mut header = CheckpointHeader {
+            magic: CHECKPOINT_MAGIC,
+            serial: self
+                .checkpoint_serial
+                .load(Ordering::Relaxed)
+                .wrapping_add(1),
+            next,
+            seg: seg as u32,
+            start: start as u32,
+            time: now,
+            crc: 0,
+            reserved: 0,
+        };
+
+        let mut crcs = [0; CHECKPOINT_BLOCKS];
+        for (i, crc) in crcs.iter_mut().enumerate().skip(1) {
+            let b = region_block(header.serial, i);
+            let mut buf = ctx.kernel().bcache().get_buf(dev, b).lock(ctx);
+            let data = &mut buf.deref_inner_mut().data;
+            if i - 1 < SEGTABLE_BLOCKS {
+                self.segtable.lock().save(i - 1, data, now);
+            } else {
+                self.imap.lock().save(i - 1 - SEGTABLE_BLOCKS, data);
+            }
+            *crc = crc32c(&data[..]);
+            buf.deref_inner_mut().valid = true;
+            hal().disk().write(&mut buf, ctx);
+            buf.free(ctx);
+        }
+
+        // Write the header last, which completes the checkpoint.
+        header.crc = header.checksum(&mut crcs);
+        let b = region_block(header.serial, 0);
+        let mut buf = ctx.kernel().bcache().get_buf(dev, b).lock(ctx);
+        let data = &mut buf.deref_inner_mut().data;
+        data[..mem::size_of::<CheckpointHeader>()].copy_from_slice(header.as_bytes());
+        data[mem::size_of::<CheckpointHeader>()..].fill(0);
+        buf.deref_inner_mut().valid = true;
+        hal().disk().write(&mut buf, ctx);
+        buf.free(ctx);
+        self.checkpoint_serial
+            .store(header.serial, Ordering::Relaxed);
+    }
+
+    /// Returns whether the device `dev` holds LFS, that is, whether either checkpoint region of
+    /// it is valid.
+    pub fn probe(dev: DevNo, ctx: &KernelCtx<'_, '_>) -> bool {
+        (0..2).any(|region| Self::read_region(dev, region, ctx).is_some())
+    }
+
+    /// Reads the header of checkpoint region `region` of `dev`, and returns it if the region is
+    /// valid.
+    fn read_region(dev: DevNo, region: u32, ctx: &KernelCtx<'_, '_>) -> Option<CheckpointHeader> {
+        let buf = hal().disk().read(dev, region_block(region, 0), ctx);
+        let header =
+            LayoutVerified::<_, CheckpointHeader>::new_from_prefix(&buf.deref_inner().data[..])
+                .map(|(header, _)| *header);
+        buf.free(ctx);
+        let header = header
+            .filter(|header| header.magic == CHECKPOINT_MAGIC && header.serial % 2 == region)?;
+
+        let mut crcs = [0; CHECKPOINT_BLOCKS];
+        for (i, crc) in crcs.iter_mut().enumerate().skip(1) {
+            let buf = hal().disk().read(dev, region_block(region, i), ctx);
+            *crc = crc32c(&buf.deref_inner().data[..]);
+            buf.free(ctx);
+        }
+        Some(header).filter(|header| header.checksum(&mut crcs) == header.crc)
+    }
+
+    /// Loads the segment usage table and the inode map from the newer valid checkpoint of
+    /// `dev`. Returns its header, or None if neither region is valid.
+    pub fn load_checkpoint(&self, dev: DevNo, ctx: &KernelCtx<'_, '_>) -> Option<CheckpointHeader> {
+        let header = (0..2)
+            .filter_map(|region| Self::read_region(dev, region, ctx))
+            .max_by_key(|header| header.serial)?;
+        for i in 1..CHECKPOINT_BLOCKS {
+            let buf = hal().disk().read(dev, region_block(header.serial, i), ctx);
+            let data = &buf.deref_inner().data;
+            if i - 1 < SEGTABLE_BLOCKS {
+                self.segtable.lock().load(i - 1, data);
+            } else {
+                self.imap.lock().load(i - 1 - SEGTABLE_BLOCKS, data);
+            }
+            buf.free(ctx);
+        }
+        self.checkpoint_serial
+            .store(header.serial, Ordering::Relaxed);
+        Some(header)
+    }
+}
@@ -0,0 +1,182 @@
+//! The cleaner, which makes free segments out of segments whose blocks are partly dead, by
+//! copying their live blocks into the current segment.
+//!
+//! The cleaner picks a victim from the segment usage table, and reads the summaries of the
+//! partial segments in it, which name the inode and the offset of each block. A block is live if
+//! its inode still maps the offset to the block's address, and the cleaner writes each live block
+//! again through a transaction, which moves it into the current segment and accounts its old
+//! address as dead. It repoints a data or indirect block in its inode, under the inode's lock,
+//! which writes the inode too. Once no block of the victim is live, the usage table counts it as
+//! free.
+//!
+//! A transaction cleans before it begins while fewer than `MIN_FREE` segments are free, so that
+//! the segment writer always finds a free segment, and the end of a transaction queues cleaning
+//! on the workqueue while fewer than `BG_FREE` are free, so that most cleaning happens in the
+//! background before anyone waits for it.
+
+use super::{
+    now,
+    segment::{SegSummary, SumEntry, SUMMARY_INODE},
+    segtable::SEGSIZE,
+    Lfs, LfsTx,
+};
+use crate::{
+    hal::hal,
+    kmsg::Level,
+    log,
+    param::MAXOPBLOCKS,
+    proc::KernelCtx,
+    util::branded::{BlockNo, DevNo, Inum},
+};
+
+/// Number of free segments below which a transaction cleans before it begins.
+const MIN_FREE: usize = 4;
+
+/// Number of free segments below which the end of a transaction queues cleaning.
+const BG_FREE: usize = 8;
+
+/// Number of blocks that the cleaner moves in a transaction. Moving a data block also writes the
+/// indirect block and the inode that point to it.
+const MOVES_PER_TX: usize = MAXOPBLOCKS / 3;
+
+impl Lfs {
+    /// Cleans segments if fewer than `MIN_FREE` are free. Called before each transaction.
+    pub fn clean_if_short(&self, ctx: &KernelCtx<'_, '_>) {
+        if self.segtable.lock().nfree() < MIN_FREE {
+            self.clean(MIN_FREE, ctx);
+        }
+    }
+
+    /// Queues cleaning on the workqueue if fewer than `BG_FREE` segments are free. Called after
+    /// each transaction. If the queue is full, a later transaction queues it again.
+    pub fn clean_in_background(&self, ctx: &KernelCtx<'_, '_>) {
+        fn clean_work(ctx: &KernelCtx<'_, '_>, arg: usize) {
+            // SAFETY: `arg` came from `clean_in_background`, and the file system lives as long as
+            // the kernel, which holds it.
+            let fs = unsafe { &*(arg as *const Lfs) };
+            fs.clean(BG_FREE, ctx);
+        }
+
+        if self.segtable.lock().nfree() < BG_FREE {
+            let arg = self as *const Self as usize;
+            let _ = ctx
+                .kernel()
+                .workqueue()
+                .queue(clean_work, arg, ctx.kernel());
+        }
+    }
+
+    /// Cleans segments until `target` segments are free, or no segment can be freed.
+    pub fn clean(&self, target: usize, ctx: &KernelCtx<'_, '_>) {
+        let guard = self.cleaner.lock(ctx);
+        while self.segtable.lock().nfree() < target {
+            let current = self.segmanager.lock().segment();
+            let victim = match self.segtable.lock().victim(now(ctx), current) {
+                Some(victim) => victim,
+                None => break,
+            };
+            self.clean_segment(victim, ctx);
+            let live = self.segtable.lock().live(victim);
+            if live > 0 {
+                // The summaries name fewer live blocks than the table counts, so cleaning the
+                // segment again would not free it either.
+                log!(
+                    Level::Warn,
+                    "fs::lfs",
+                    "segment {} still has {} live bytes after cleaning",
+                    victim,
+                    live
+                );
+                break;
+            }
+        }
+        guard.free(ctx);
+    }
+
+    /// Moves the live blocks of segment `seg` into the current segment.
+    fn clean_segment(&self, seg: usize, ctx: &KernelCtx<'_, '_>) {
+        let dev = self.segmanager.lock().dev();
+        let mut start = 0;
+        let mut tx = None;
+        let mut moves = 0;
+        while start < SEGSIZE {
+            let summary = BlockNo::new((seg * SEGSIZE + start) as u32);
+            let buf = hal().disk().read(dev, summary, ctx);
+            let sum = SegSummary::read(&buf.deref_inner().data[..]);
+            buf.free(ctx);
+            let sum = match sum {
+                Some(sum) => sum,
+                None => break,
+            };
+
+            for (i, entry) in sum.entries().iter().enumerate() {
+                let old = summary + (1 + i as u32);
+                if self.block_addr(dev, entry, ctx) != Some(old) {
+                    continue;
+                }
+                let t = tx.get_or_insert_with(|| {
+                    self.segmanager.begin_op(ctx);
+                    LfsTx { fs: self }
+                });
+                self.move_block(entry, old, t, ctx);
+                moves += 1;
+                if moves == MOVES_PER_TX {
+                    tx.take().expect("clean_segment").end(ctx);
+                    moves = 0;
+                }
+            }
+            start += 1 + sum.entries().len();
+        }
+        if let Some(tx) = tx {
+            tx.end(ctx);
+        }
+    }
+
+    /// Writes the block at `old`, which `entry` names, into the current segment.
+    fn move_block(&self, entry: &SumEntry, old: BlockNo, tx: &LfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        let dev = self.segmanager.lock().dev();
+        let from = hal().disk().read(dev, old, ctx);
+        let new = tx.alloc(entry.inum, entry.off, None, ctx);
+        let mut to = ctx.kernel().bcache().get_buf(dev, new).lock(ctx);
+        to.deref_inner_mut()
+            .data
+            .copy_from_slice(&from.deref_inner().data[..]);
+        to.deref_inner_mut().valid = true;
+        from.free(ctx);
+        tx.write(to, ctx);
+        if self.set_block_addr(dev, entry, old, new, tx, ctx) {
+            tx.kill(old);
+        } else {
+            // The block moved or died after `clean_segment` looked at it.
+            tx.kill(new);
+        }
+    }
+
+    /// Points the block that `entry` names from `old` to `new`, in the inode map if it is an inode
+    /// and in its inode under the inode's lock otherwise, if it is still at `old`. Returns
+    /// whether it was.
+    fn set_block_addr(
+        &self,
+        dev: DevNo,
+        entry: &SumEntry,
+        old: BlockNo,
+        new: BlockNo,
+        tx: &LfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> bool {
+        if entry.off == SUMMARY_INODE {
+            let mut imap = self.imap.lock();
+            if imap.get_inode(entry.inum) != Some(old) {
+                return false;
+            }
+            imap.set_inode(entry.inum, Some(new));
+            return true;
+        }
+        let ip = self.itable().get_inode(dev, Inum::new(entry.inum));
+        let mut guard = ip.lock(ctx);
+        let moved = guard.repoint(entry.off, old, new, tx, ctx);
+        guard.free(ctx);
+        ip.free((tx, ctx));
+        moved
+    }
+}
@@ -0,0 +1,287 @@
+//! Directories of LFS, and how paths resolve to inodes.
+//!
+//! A directory is a file of `Dirent`s, in the same format as in UFS, so that user programs read
+//! the directories of either alike. A directory entry holds the number of its inode, which the
+//! inode map finds wherever the inode has moved. An entry whose inode the map does not have is not
+//! found, as the inode is free.
+
+use core::mem;
+
+use rv6_abi::dirent::{Dirent, DIRSIZ};
+
+use super::{inode::ROOTINO, InodeInner, Lfs, LfsTx};
+use crate::{
+    error::KernelError,
+    fs::{FileName, FileSystem, InodeGuard, InodeType, Path, RcInode, MAY_EXEC},
+    proc::KernelCtx,
+    util::{branded::Inum, strong_pin::StrongPin},
+};
+
+const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
+
+/// Fill in the name of `de`. If name is shorter than DIRSIZ, NUL character is appended as
+/// terminator.
+fn set_name(de: &mut Dirent, name: &FileName<{ DIRSIZ }>) {
+    let name = name.as_bytes();
+    de.name = [0; DIRSIZ];
+    de.name[..name.len()].copy_from_slice(name);
+}
+
+/// Returns the name of `de`, which contains no NUL characters.
+fn get_name(de: &Dirent) -> &FileName<{ DIRSIZ }> {
+    let len = de.name.iter().position(|ch| *ch == 0).unwrap_or(DIRSIZ);
+    // SAFETY: de.name[..len] doesn't contain '\0', and len must be <= DIRSIZ.
+    unsafe { FileName::from_bytes(&de.name[..len]) }
+}
+
+// Directories
+impl InodeGuard<'_, InodeInner> {
+    /// Returns the first entry of the directory, and its byte offset, for which `f` returns true.
+    fn find_dirent<F: FnMut(&Dirent, u32) -> bool>(
+        &mut self,
+        mut f: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Option<(Dirent, u32)> {
+        for off in (0..self.deref_inner().size).step_by(DIRENT_SIZE) {
+            let mut de = Dirent::default();
+            self.read_kernel(&mut de, off, ctx).expect("find_dirent");
+            if f(&de, off) {
+                return Some((de, off));
+            }
+        }
+        None
+    }
+
+    /// Look for a directory entry in a directory.
+    /// If found, return the entry and byte offset of entry.
+    pub fn dirlookup(
+        &mut self,
+        name: &FileName<{ DIRSIZ }>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<InodeInner>, u32), KernelError> {
+        assert_eq!(self.deref_inner().typ, InodeType::Dir, "dirlookup not DIR");
+
+        let (de, off) = self
+            .find_dirent(|de, _| de.inum != 0 && get_name(de) == name, ctx)
+            .ok_or(KernelError::NoEntry)?;
+        let ptr = ctx
+            .kernel()
+            .vfs()
+            .lfs()
+            .get_inode(self.dev, Inum::new(de.inum as u32))?;
+        Ok((ptr, off))
+    }
+
+    /// Write a new directory entry (name, inum) into the directory dp.
+    pub fn dirlink(
+        &mut self,
+        name: &FileName<{ DIRSIZ }>,
+        inum: Inum,
+        tx: &LfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        // Check that name is not present.
+        if let Ok((ip, _)) = self.dirlookup(name, ctx) {
+            ip.free((tx, ctx));
+            return Err(KernelError::Exists);
+        };
+
+        // Look for an empty Dirent.
+        let (mut de, off) = self
+            .find_dirent(|de, _| de.inum == 0, ctx)
+            .unwrap_or((Default::default(), self.deref_inner().size));
+        de.inum = inum.into_u32() as _;
+        set_name(&mut de, name);
+        self.write_kernel(&de, off, tx, ctx).expect("dirlink");
+        Ok(())
+    }
+
+    /// Empty the directory entry at byte offset `off` of the directory. If no entry follows it,
+    /// also shrink the directory to end after the last entry in use.
+    pub fn dirunlink(&mut self, off: u32, tx: &LfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        self.write_kernel(&Dirent::default(), off, tx, ctx)
+            .expect("dirunlink");
+
+        let mut size = self.deref_inner().size;
+        // Keep "." and "..".
+        while size > 2 * DIRENT_SIZE as u32 {
+            let off = size - DIRENT_SIZE as u32;
+            let mut de = Dirent::default();
+            self.read_kernel(&mut de, off, ctx).expect("dirunlink");
+            if de.inum != 0 {
+                break;
+            }
+            size = off;
+        }
+        if size < self.deref_inner().size {
+            self.shrink(size, tx, ctx);
+        }
+    }
+
+    /// Is the directory dp empty except for "." and ".." ?
+    pub fn is_dir_empty(&mut self, ctx: &KernelCtx<'_, '_>) -> bool {
+        self.find_dirent(|de, off| off >= 2 * DIRENT_SIZE as u32 && de.inum != 0, ctx)
+            .is_none()
+    }
+}
+
+impl Lfs {
+    pub fn namei(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        tx: &LfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<InodeInner>, KernelError> {
+        Ok(self.namex(path, false, tx, ctx)?.0)
+    }
+
+    pub fn nameiparent<'s>(
+        self: StrongPin<'_, Self>,
+        path: &'s Path,
+        tx: &LfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<InodeInner>, &'s FileName<{ DIRSIZ }>), KernelError> {
+        let (ip, name_in_path) = self.namex(path, true, tx, ctx)?;
+        let name_in_path = name_in_path.ok_or(KernelError::NoEntry)?;
+        Ok((ip, name_in_path))
+    }
+
+    /// Writes the path of the directory `dir` from the root directory of the process into `buf`,
+    /// as getcwd() does, by following ".." up and finding each directory's name in its parent.
+    /// Returns the length of the path, which is not NUL-terminated, or Err(KernelError::Range) if
+    /// it does not fit.
+    pub fn path_of(
+        self: StrongPin<'_, Self>,
+        dir: &RcInode<InodeInner>,
+        buf: &mut [u8],
+        tx: &LfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        // The path is built backwards from the end of `buf`.
+        let mut start = buf.len();
+        let top = ctx
+            .proc()
+            .deref_data()
+            .root
+            .as_ref()
+            .map(|root| root.inum());
+        let mut ptr = dir.clone();
+        while ptr.inum != ROOTINO && Some(ptr.inum) != top {
+            let mut ip = ptr.lock(ctx);
+            // SAFETY: b".." does not contain any NUL characters.
+            let parent = ip.dirlookup(unsafe { FileName::from_bytes(b"..") }, ctx);
+            ip.free(ctx);
+            let inum = ptr.inum;
+            ptr.free((tx, ctx));
+            ptr = parent?.0;
+
+            let mut name = [0; DIRSIZ];
+            let mut dp = ptr.lock(ctx);
+            let len = dp
+                .find_dirent(
+                    |de, _| {
+                        de.inum as u32 == inum.into_u32()
+                            && !matches!(get_name(de).as_bytes(), b"." | b"..")
+                    },
+                    ctx,
+                )
+                .map(|(de, _)| {
+                    let de_name = get_name(&de).as_bytes();
+                    name[..de_name.len()].copy_from_slice(de_name);
+                    de_name.len()
+                });
+            dp.free(ctx);
+            let res = match len {
+                // The directory was removed if its parent has no entry for it.
+                None => Err(KernelError::NoEntry),
+                Some(len) if len >= start => Err(KernelError::Range),
+                Some(len) => Ok(len),
+            };
+            let len = match res {
+                Ok(len) => len,
+                Err(e) => {
+                    ptr.free((tx, ctx));
+                    return Err(e);
+                }
+            };
+            buf[start - len..start].copy_from_slice(&name[..len]);
+            start -= len + 1;
+            buf[start] = b'/';
+        }
+        ptr.free((tx, ctx));
+
+        if start == buf.len() {
+            // `dir` is the root.
+            if buf.is_empty() {
+                return Err(KernelError::Range);
+            }
+            start -= 1;
+            buf[start] = b'/';
+        }
+        let len = buf.len() - start;
+        buf.copy_within(start.., 0);
+        Ok(len)
+    }
+
+    /// Look up and return the inode for a path name, starting at the root directory of the
+    /// process for an absolute path and at its current directory otherwise. Each inode on the way
+    /// comes from the inode map by `dirlookup`.
+    /// If parent is true, return the inode for the parent and the final path element.
+    fn namex<'s>(
+        self: StrongPin<'_, Self>,
+        mut path: &'s Path,
+        parent: bool,
+        tx: &LfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(RcInode<InodeInner>, Option<&'s FileName<{ DIRSIZ }>>), KernelError> {
+        // The directories of the process are on this file system, as it is the only one mounted.
+        let root = ctx.proc().deref_data().root.as_ref();
+        let root = root
+            .map(|root| root.as_lfs().ok_or(KernelError::CrossDevice))
+            .transpose()?;
+        let mut ptr = if path.is_absolute() {
+            root.map_or_else(|| self.root(), |root| root.clone())
+        } else {
+            let cwd = ctx.proc().cwd().as_lfs().ok_or(KernelError::CrossDevice)?;
+            cwd.clone()
+        };
+
+        while let Some((new_path, name)) = path.skipelem() {
+            path = new_path;
+
+            // ".." of the process's root directory is itself, as for the file system's root.
+            if name.as_bytes() == b".."
+                && root.map_or(false, |root| root.inum == ptr.inum)
+                && !(parent && path.is_empty_string())
+            {
+                continue;
+            }
+
+            let mut ip = ptr.lock(ctx);
+            if ip.deref_inner().typ != InodeType::Dir {
+                ip.free(ctx);
+                ptr.free((tx, ctx));
+                return Err(KernelError::NotDir);
+            }
+            if parent && path.is_empty_string() {
+                // Stop one level early.
+                ip.free(ctx);
+                return Ok((ptr, Some(name)));
+            }
+            if let Err(e) = ip.check_access(MAY_EXEC, ctx) {
+                ip.free(ctx);
+                ptr.free((tx, ctx));
+                return Err(e);
+            }
+            let next = ip.dirlookup(name, ctx);
+            ip.free(ctx);
+            ptr.free((tx, ctx));
+            ptr = next?.0
+        }
+        if parent {
+            ptr.free((tx, ctx));
+            return Err(KernelError::NoEntry);
+        }
+        Ok((ptr, None))
+    }
+}
@@ -0,0 +1,90 @@
+//! The inode map, which maps each inode number to the address of the block that holds the inode.
+//!
+//! An inode moves to the current segment whenever it is written, like any other block, so the
+//! file system finds it through the map instead of at a fixed address. Each inode has a block of
+//! its own. The map lives in memory, and the checkpoint writes it out in IMAP_BLOCKS blocks.
+//! Inodes written since the last checkpoint are mapped again when they are rolled forward, as
+//! their segment summaries name them.
+
+use core::cmp;
+
+use zerocopy::AsBytes;
+
+use crate::{ktest, ktest_assert, param::BSIZE, util::branded::BlockNo};
+
+/// Number of blocks that the map takes in the checkpoint.
+pub const IMAP_BLOCKS: usize = 4;
+
+/// Number of inodes, counting from inode 0, which is never used.
+pub const NIMAP: usize = IMAP_BLOCKS * BSIZE / 4;
+
+pub struct Imap {
+    /// The address of each inode, or 0 if the inode is free.
+    addrs: [u32; NIMAP],
+}
+
+impl Imap {
+    pub const fn new() -> Self {
+        Self { addrs: [0; NIMAP] }
+    }
+
+    /// Returns the address of inode `inum`, or None if it is free.
+    pub fn get_inode(&self, inum: u32) -> Option<BlockNo> {
+        match self.addrs.get(inum as usize) {
+            Some(0) | None => None,
+            Some(addr) => Some(BlockNo::new(*addr)),
+        }
+    }
+
+    /// Maps inode `inum` to `addr`, or frees it if `addr` is None.
+    pub fn set_inode(&mut self, inum: u32, addr: Option<BlockNo>) {
+        assert!(inum > 0 && (inum as usize) < NIMAP, "Imap::set_inode");
+        self.addrs[inum as usize] = addr.map_or(0, BlockNo::into_u32);
+    }
+
+    /// Returns a free inode number, or None if there is none.
+    pub fn free_inum(&self) -> Option<u32> {
+        (1..NIMAP)
+            .find(|inum| self.addrs[*inum] == 0)
+            .map(|inum| inum as u32)
+    }
+
+    /// Copies block `i` of the map into `data` for the checkpoint.
+    pub fn save(&self, i: usize, data: &mut [u8; BSIZE]) {
+        let bytes = self.addrs.as_bytes();
+        let from = &bytes[i * BSIZE..cmp::min((i + 1) * BSIZE, bytes.len())];
+        data[..from.len()].copy_from_slice(from);
+        data[from.len()..].fill(0);
+    }
+
+    /// Loads block `i` of the map from the checkpoint in `data`.
+    pub fn load(&mut self, i: usize, data: &[u8; BSIZE]) {
+        let bytes = self.addrs.as_bytes_mut();
+        let end = cmp::min((i + 1) * BSIZE, bytes.len());
+        let to = &mut bytes[i * BSIZE..end];
+        let n = to.len();
+        to.copy_from_slice(&data[..n]);
+    }
+}
+
+ktest! {
+    fn imap_get_set(ctx) {
+        let mut imap = Imap::new();
+        ktest_assert!(imap.get_inode(1).is_none());
+        ktest_assert!(imap.free_inum() == Some(1));
+        imap.set_inode(1, Some(BlockNo::new(40)));
+        imap.set_inode(NIMAP as u32 - 1, Some(BlockNo::new(41)));
+        ktest_assert!(imap.get_inode(1) == Some(BlockNo::new(40)));
+        ktest_assert!(imap.free_inum() == Some(2));
+
+        let mut data = [0; BSIZE];
+        let mut copy = Imap::new();
+        for i in 0..IMAP_BLOCKS {
+            imap.save(i, &mut data);
+            copy.load(i, &data);
+        }
+        ktest_assert!(copy.get_inode(NIMAP as u32 - 1) == Some(BlockNo::new(41)));
+        imap.set_inode(1, None);
+        ktest_assert!(imap.get_inode(1).is_none());
+    }
+}
@@ -0,0 +1,929 @@
+//! Inodes of LFS, and how they map their blocks.
+//!
+//! An inode maps its first NDIRECT blocks directly, and the next NINDIRECT blocks through an
+//! indirect block, as in UFS. Unlike in UFS, no block is written in place: writing a data block
+//! moves it to a new address in the current segment, which changes the address in the inode or
+//! in the indirect block, which then moves too, and so does the inode, which the inode map finds.
+//! A block that a transaction already moved into the pending partial segment stays there when it
+//! is written again, so a transaction writes each block at most once.
+//!
+//! The summary entry of an indirect block names it by the offset `SUMMARY_INDIRECT`, so the
+//! cleaner and roll-forward can tell from the inode whether the block is still in use.
+//!
+//! As in UFS, the inode table holds the in-memory copies of the inodes, and the sleep-lock of an
+//! inode protects its copy. The cleaner repoints a block that it moves in the copy, under the lock.
+
+use core::{
+    cmp, mem,
+    ops::{Deref, Range},
+};
+
+use rv6_abi::stat::{T_DEVICE, T_DIR, T_FILE};
+use static_assertions::const_assert;
+use zerocopy::{AsBytes, FromBytes, LayoutVerified};
+
+use super::{
+    segment::{SumEntry, SUMMARY_INDIRECT, SUMMARY_INODE},
+    Lfs, LfsTx,
+};
+use crate::{
+    arch::addr::UVAddr,
+    arena::{Arena, ArenaObject, ArrayArena},
+    audit::AuditEvent,
+    bio::Buf,
+    error::KernelError,
+    failinject::FaultSite,
+    fs::{Inode, InodeGuard, InodeType, Itable, RcInode, Stat},
+    hal::hal,
+    lock::{SleepLock, SpinLock},
+    param::{BSIZE, NINODE},
+    proc::KernelCtx,
+    some_or,
+    util::{
+        branded::{BlockNo, DevNo, Inum},
+        strong_pin::StrongPin,
+    },
+};
+
+/// root i-number
+pub const ROOTINO: Inum = Inum::new(1);
+
+const NDIRECT: usize = 12;
+const NINDIRECT: usize = BSIZE / mem::size_of::<u32>();
+const MAXFILE: usize = NDIRECT + NINDIRECT;
+
+pub struct InodeInner {
+    /// inode has been read from disk?
+    pub valid: bool,
+    /// copy of disk inode
+    pub typ: InodeType,
+    pub nlink: i16,
+    pub size: u32,
+    pub mode: u16,
+    pub uid: u16,
+    pub gid: u16,
+    pub addr_direct: [u32; NDIRECT],
+    pub addr_indirect: u32,
+}
+
+impl const Default for Inode<InodeInner> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArenaObject for Inode<InodeInner> {
+    type Ctx<'a, 'id: 'a> = (&'a LfsTx<'a>, &'a KernelCtx<'id, 'a>);
+
+    /// Drop a reference to an in-memory inode.
+    /// If that was the last reference and the inode has no links
+    /// to it, free the inode (and its content) on disk.
+    /// All calls to Inode::put() must be inside a transaction in
+    /// case it has to free the inode.
+    #[allow(clippy::needless_lifetimes)]
+    fn finalize<'a, 'id: 'a, A: Arena>(&mut self, ctx: Self::Ctx<'a, 'id>) {
+        let (tx, ctx) = ctx;
+        let inner = self.inner.get_mut();
+        // A free inode that was only looked up has no type.
+        if inner.valid && inner.nlink == 0 && inner.typ != InodeType::None {
+            // inode has no links and no other references: truncate and free.
+
+            // self->ref == 1 means no other process can have self locked,
+            // so this acquiresleep() won't block (or deadlock).
+            let mut ip = self.lock(ctx);
+
+            ip.itrunc(tx, ctx);
+            // Roll-forward frees the inode again when it finds the inode with no type.
+            ip.deref_inner_mut().typ = InodeType::None;
+            ip.update(tx, ctx);
+            tx.free_inode(ip.inum);
+            ip.deref_inner_mut().valid = false;
+
+            ip.free(ctx);
+        }
+    }
+}
+
+/// On-disk inode structure, alone at the start of its block.
+#[repr(C)]
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+pub struct Dinode {
+    /// File type: T_DIR, T_FILE, T_DEVICE, or 0 if the inode is free
+    typ: i16,
+
+    /// Major device number (T_DEVICE only)
+    major: u16,
+
+    /// Minor device number (T_DEVICE only)
+    minor: u16,
+
+    /// Number of links to inode in file system
+    nlink: i16,
+
+    /// Size of file (bytes)
+    size: u32,
+
+    /// Permission bits, with I_SUID and I_SGID
+    mode: u16,
+
+    /// Owner's user ID
+    uid: u16,
+
+    /// Group ID
+    gid: u16,
+
+    _unused: u16,
+
+    /// Direct data block addresses
+    addr_direct: [u32; NDIRECT],
+
+    /// Indirect data block address
+    addr_indirect: u32,
+}
+
+const_assert!(mem::size_of::<Dinode>() <= BSIZE);
+
+/// The addresses in an indirect block.
+#[repr(C)]
+#[derive(AsBytes, FromBytes)]
+struct Indirect {
+    addrs: [u32; NINDIRECT],
+}
+
+const_assert!(mem::size_of::<Indirect>() == BSIZE);
+
+/// Returns the addresses in the indirect block `buf`.
+fn indirect(buf: &mut Buf) -> &mut Indirect {
+    LayoutVerified::<_, Indirect>::new(&mut buf.deref_inner_mut().data[..])
+        .expect("indirect: Buf data unaligned")
+        .into_mut()
+}
+
+/// Reads a block into the buffer cache, for `InodeGuard::read_ahead`. The upper half of `arg` is
+/// the device, and the lower half is the block number.
+fn read_block(ctx: &KernelCtx<'_, '_>, arg: usize) {
+    let dev = DevNo::new((arg >> 32) as u32);
+    let bno = BlockNo::new(arg as u32);
+    hal().disk().read(dev, bno, ctx).free(ctx);
+}
+
+/// Returns the address `addr` of a block, or None if it is 0, as for no block.
+fn block_no(addr: u32) -> Option<BlockNo> {
+    Some(addr).filter(|addr| *addr != 0).map(BlockNo::new)
+}
+
+impl Lfs {
+    /// Returns the address of the block that `entry` names, as the inode map and the inodes on
+    /// `dev` map it: the inode's block if `entry.off` is `SUMMARY_INODE`, its indirect block if
+    /// it is `SUMMARY_INDIRECT`, and its block at `entry.off` otherwise. Returns None if the
+    /// inode or the block does not exist anymore.
+    // Transactions write an inode whenever they change it, so its block in the buffer cache or
+    // on the disk is up to date once no transaction is outstanding.
+    pub fn block_addr(
+        &self,
+        dev: DevNo,
+        entry: &SumEntry,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Option<BlockNo> {
+        let inode = self.imap.lock().get_inode(entry.inum)?;
+        if entry.off == SUMMARY_INODE {
+            return Some(inode);
+        }
+        let buf = hal().disk().read(dev, inode, ctx);
+        let dip = LayoutVerified::<_, Dinode>::new_from_prefix(&buf.deref_inner().data[..])
+            .map(|(dip, _)| *dip);
+        buf.free(ctx);
+        let dip = dip.filter(|dip| dip.typ != 0)?;
+        let bn = entry.off as usize;
+        if entry.off == SUMMARY_INDIRECT {
+            block_no(dip.addr_indirect)
+        } else if bn < NDIRECT {
+            block_no(dip.addr_direct[bn])
+        } else if bn < MAXFILE {
+            let mut buf = hal().disk().read(dev, block_no(dip.addr_indirect)?, ctx);
+            let addr = indirect(&mut buf).addrs[bn - NDIRECT];
+            buf.free(ctx);
+            block_no(addr)
+        } else {
+            None
+        }
+    }
+
+    /// Returns whether the inode block at `b` on `dev` holds an inode with no type, which the
+    /// transaction that freed the inode wrote.
+    pub fn is_freed_inode(&self, dev: DevNo, b: BlockNo, ctx: &KernelCtx<'_, '_>) -> bool {
+        let buf = hal().disk().read(dev, b, ctx);
+        let dip = LayoutVerified::<_, Dinode>::new_from_prefix(&buf.deref_inner().data[..])
+            .map(|(dip, _)| *dip);
+        buf.free(ctx);
+        dip.map_or(false, |dip| dip.typ == 0)
+    }
+}
+
+impl Lfs {
+    /// Returns the in-memory copy of inode `inum` on `dev`, without locking it or reading it from
+    /// disk, or Err(KernelError::NoEntry) if the inode map has no address for it, as it is free.
+    pub fn get_inode(
+        self: StrongPin<'_, Self>,
+        dev: DevNo,
+        inum: Inum,
+    ) -> Result<RcInode<InodeInner>, KernelError> {
+        if self.imap.lock().get_inode(inum.into_u32()).is_none() {
+            return Err(KernelError::NoEntry);
+        }
+        Ok(self.itable().get_inode(dev, inum))
+    }
+
+    /// Allocate an inode on device dev, by giving a free number in the inode map a type and
+    /// writing it, which maps it.
+    /// Returns an unlocked but allocated and referenced inode, or Err(KernelError::NoSpace) if the
+    /// inode map is full.
+    pub fn alloc_inode(
+        self: StrongPin<'_, Self>,
+        dev: DevNo,
+        typ: InodeType,
+        tx: &LfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<InodeInner>, KernelError> {
+        loop {
+            let inum = self.imap.lock().free_inum().ok_or(KernelError::NoSpace)?;
+            let ptr = self.itable().get_inode(dev, Inum::new(inum));
+            let mut ip = ptr.lock(ctx);
+            if ip.deref_inner().typ != InodeType::None {
+                // Another transaction took the number first.
+                ip.free(ctx);
+                ptr.free((tx, ctx));
+                continue;
+            }
+            let inner = ip.deref_inner_mut();
+            inner.typ = typ;
+            inner.nlink = 0;
+            inner.size = 0;
+            inner.mode = 0;
+            inner.uid = 0;
+            inner.gid = 0;
+            inner.addr_direct = [0; NDIRECT];
+            inner.addr_indirect = 0;
+            ip.update(tx, ctx);
+            ip.free(ctx);
+            return Ok(ptr);
+        }
+    }
+}
+
+impl Inode<InodeInner> {
+    /// Lock the given inode.
+    /// Reads the inode from disk if necessary. Its type is `InodeType::None` if the inode map has
+    /// no address for it.
+    pub fn lock(&self, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'_, InodeInner> {
+        let mut guard = self.inner.lock(ctx);
+        if !guard.valid {
+            let addr = ctx
+                .kernel()
+                .vfs()
+                .lfs()
+                .imap
+                .lock()
+                .get_inode(self.inum.into_u32());
+            let dip = match addr {
+                Some(addr) => {
+                    let bp = hal().disk().read(self.dev, addr, ctx);
+                    let dip =
+                        LayoutVerified::<_, Dinode>::new_from_prefix(&bp.deref_inner().data[..])
+                            .map(|(dip, _)| *dip)
+                            .expect("Inode::lock: Buf data unaligned");
+                    bp.free(ctx);
+                    dip
+                }
+                None => Dinode::default(),
+            };
+            guard.typ = match dip.typ {
+                T_DIR => InodeType::Dir,
+                T_FILE => InodeType::File,
+                T_DEVICE => {
+                    InodeType::Device {
+                        major: dip.major,
+                        minor: dip.minor,
+                    }
+                }
+                _ => InodeType::None,
+            };
+            guard.nlink = dip.nlink;
+            guard.size = dip.size;
+            guard.mode = dip.mode;
+            guard.uid = dip.uid;
+            guard.gid = dip.gid;
+            guard.addr_direct = dip.addr_direct;
+            guard.addr_indirect = dip.addr_indirect;
+            guard.valid = true;
+        }
+        mem::forget(guard);
+        InodeGuard { inode: self }
+    }
+
+    pub const fn new() -> Self {
+        Self {
+            dev: DevNo::new(0),
+            inum: Inum::new(0),
+            inner: SleepLock::new(
+                "inode",
+                InodeInner {
+                    valid: false,
+                    typ: InodeType::None,
+                    nlink: 0,
+                    size: 0,
+                    mode: 0,
+                    uid: 0,
+                    gid: 0,
+                    addr_direct: [0; NDIRECT],
+                    addr_indirect: 0,
+                },
+            ),
+        }
+    }
+
+    /// Copy stat information from inode.
+    pub fn stat(&self, ctx: &KernelCtx<'_, '_>) -> Stat {
+        let inner = self.inner.lock(ctx);
+        let st = Stat {
+            dev: self.dev.into_u32() as i32,
+            ino: self.inum.into_u32(),
+            r#type: match inner.typ {
+                InodeType::None => 0,
+                InodeType::Dir => T_DIR,
+                InodeType::File => T_FILE,
+                InodeType::Device { .. } => T_DEVICE,
+            },
+            nlink: inner.nlink,
+            mode: inner.mode as u32,
+            size: inner.size as u64,
+            uid: inner.uid as u32,
+            gid: inner.gid as u32,
+        };
+        inner.free(ctx);
+        st
+    }
+}
+
+impl Itable<InodeInner> {
+    pub const fn new_itable() -> Self {
+        SpinLock::new(
+            "LFS_ITABLE",
+            ArrayArena::<Inode<InodeInner>, NINODE>::new("lfs_itable"),
+        )
+    }
+
+    /// Find the inode with number inum on device dev
+    /// and return the in-memory copy. Does not lock
+    /// the inode and does not read it from disk.
+    pub fn get_inode(self: StrongPin<'_, Self>, dev: DevNo, inum: Inum) -> RcInode<InodeInner> {
+        self.find_or_alloc_keyed(
+            &(dev, inum),
+            |inode| inode.dev == dev && inode.inum == inum,
+            |inode| {
+                inode.dev = dev;
+                inode.inum = inum;
+                inode.inner.get_mut().valid = false;
+            },
+        )
+        .unwrap_or_else(|| panic!("[Itable::get_inode] no inodes ({})", self.stats()))
+    }
+}
+
+impl InodeGuard<'_, InodeInner> {
+    /// Returns Ok(()) if the current process has all the rights in `access` to this inode, or
+    /// Err(KernelError::PermissionDenied) otherwise, which is audited.
+    pub fn check_access(&self, access: u16, ctx: &KernelCtx<'_, '_>) -> Result<(), KernelError> {
+        let inner = self.deref_inner();
+        if ctx.may_access(inner.mode, inner.uid, inner.gid, access) {
+            Ok(())
+        } else {
+            let inum = self.inum.into_u32() as u64;
+            ctx.audit(
+                AuditEvent::Denied,
+                inum,
+                Some(KernelError::PermissionDenied),
+            );
+            Err(KernelError::PermissionDenied)
+        }
+    }
+
+    /// Copy a modified in-memory inode to disk, which moves it to a new address and maps it
+    /// there in the inode map.
+    /// This must be called after every change to an ip->xxx field.
+    /// Caller must hold ip->lock.
+    pub fn update(&self, tx: &LfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        let inum = self.inum.into_u32();
+        let old = tx.fs.imap.lock().get_inode(inum);
+        let b = tx.alloc(inum, SUMMARY_INODE, old, ctx);
+        let mut bp = ctx.kernel().bcache().get_buf(self.dev, b).lock(ctx);
+
+        let inner = self.deref_inner();
+        let (typ, major, minor) = match inner.typ {
+            InodeType::None => (0, 0, 0),
+            InodeType::Dir => (T_DIR, 0, 0),
+            InodeType::File => (T_FILE, 0, 0),
+            InodeType::Device { major, minor } => (T_DEVICE, major, minor),
+        };
+        let dip = Dinode {
+            typ,
+            major,
+            minor,
+            nlink: inner.nlink,
+            size: inner.size,
+            mode: inner.mode,
+            uid: inner.uid,
+            gid: inner.gid,
+            _unused: 0,
+            addr_direct: inner.addr_direct,
+            addr_indirect: inner.addr_indirect,
+        };
+        let data = &mut bp.deref_inner_mut().data;
+        data[..mem::size_of::<Dinode>()].copy_from_slice(dip.as_bytes());
+        data[mem::size_of::<Dinode>()..].fill(0);
+        bp.deref_inner_mut().valid = true;
+        tx.write(bp, ctx);
+        tx.fs.imap.lock().set_inode(inum, Some(b));
+    }
+
+    /// Truncate inode (discard contents).
+    /// This function is called with Inode's lock is held.
+    pub fn itrunc(&mut self, tx: &LfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        self.shrink(0, tx, ctx);
+    }
+
+    /// Truncates the file to `size` bytes, and accounts its blocks past them as dead.
+    /// This function is called with Inode's lock is held.
+    pub fn shrink(&mut self, size: u32, tx: &LfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        assert!(size <= self.deref_inner().size, "shrink: growing");
+        hal()
+            .pagecache()
+            .invalidate(self.dev, self.inum, hal().kmem());
+        let nblocks = (size as usize + BSIZE - 1) / BSIZE;
+        for addr in &mut self.deref_inner_mut().addr_direct[cmp::min(nblocks, NDIRECT)..] {
+            if let Some(b) = block_no(*addr) {
+                tx.kill(b);
+                *addr = 0;
+            }
+        }
+
+        if let Some(old) = block_no(self.deref_inner().addr_indirect) {
+            let first = nblocks.saturating_sub(NDIRECT);
+            if first > 0 {
+                // The indirect block moves without the addresses past the end.
+                let mut bp = self.relocate(SUMMARY_INDIRECT, Some(old), tx, ctx);
+                for addr in &mut indirect(&mut bp).addrs[first..] {
+                    if let Some(b) = block_no(*addr) {
+                        tx.kill(b);
+                        *addr = 0;
+                    }
+                }
+                let new = bp.blockno;
+                tx.write(bp, ctx);
+                self.deref_inner_mut().addr_indirect = new.into_u32();
+            } else {
+                let mut bp = hal().disk().read(self.dev, old, ctx);
+                for b in indirect(&mut bp)
+                    .addrs
+                    .iter()
+                    .filter_map(|addr| block_no(*addr))
+                {
+                    tx.kill(b);
+                }
+                bp.free(ctx);
+                tx.kill(old);
+                self.deref_inner_mut().addr_indirect = 0;
+            }
+        }
+
+        self.deref_inner_mut().size = size;
+        self.update(tx, ctx);
+    }
+
+    /// Copy data into `dst` from the content of inode at offset `off`.
+    /// Return Ok(()) on success, Err(KernelError::Io) on a short read or write.
+    pub fn read_kernel<T: AsBytes + FromBytes>(
+        &mut self,
+        dst: &mut T,
+        off: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let bytes = self.read_bytes_kernel(dst.as_bytes_mut(), off, ctx);
+        if bytes == mem::size_of::<T>() {
+            Ok(())
+        } else {
+            Err(KernelError::Io)
+        }
+    }
+
+    /// Copy data into `dst` from the content of inode at offset `off`.
+    /// Return the number of bytes copied.
+    pub fn read_bytes_kernel(
+        &mut self,
+        dst: &mut [u8],
+        off: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> usize {
+        self.read_internal(
+            off,
+            dst.len() as u32,
+            |off, src, _| {
+                dst[off as usize..off as usize + src.len()].clone_from_slice(src);
+                Ok(())
+            },
+            ctx,
+        )
+        .expect("read: should never fail")
+    }
+
+    /// Copy data into virtual address `dst` of the current process by `n` bytes
+    /// from the content of inode at offset `off`.
+    /// Returns Ok(number of bytes copied) on success, Err(KernelError::Fault) on failure due to
+    /// accessing an invalid virtual address, or Err(KernelError::Io) if a fault is injected.
+    pub fn read_user(
+        &mut self,
+        dst: UVAddr,
+        off: u32,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        self.read_internal(
+            off,
+            n,
+            |off, src, ctx| {
+                if hal().faults().should_fail(FaultSite::Disk) {
+                    return Err(KernelError::Io);
+                }
+                ctx.proc_mut()
+                    .memory_mut()
+                    .copy_out_bytes(dst + off as usize, src)
+            },
+            ctx,
+        )
+    }
+
+    /// Pass the content of inode from offset `off`, up to `n` bytes, to `f` a block at a time,
+    /// straight from the buffer cache, until `f` takes less than it is given. `f(src, ctx)`
+    /// returns the number of bytes at the front of `src` that it took.
+    /// Returns Ok(number of bytes taken) on success, Err(KernelError) if `f` failed.
+    pub fn read_into<'id, 's, F>(
+        &mut self,
+        off: u32,
+        n: u32,
+        mut f: F,
+        ctx: &mut KernelCtx<'id, 's>,
+    ) -> Result<usize, KernelError>
+    where
+        F: FnMut(&[u8], &mut KernelCtx<'id, 's>) -> Result<usize, KernelError>,
+    {
+        let mut taken = 0;
+        let mut full = false;
+        let res = self.read_internal(
+            off,
+            n,
+            |_, src, ctx| {
+                let m = f(src, ctx)?;
+                taken += m;
+                if m < src.len() {
+                    // Stop reading blocks.
+                    full = true;
+                    return Err(KernelError::TryAgain);
+                }
+                Ok(())
+            },
+            ctx,
+        );
+        match res {
+            Err(_) if full => Ok(taken),
+            res => res,
+        }
+    }
+
+    /// Read data from inode.
+    ///
+    /// `f` takes an offset and a slice as arguments. `f(off, src, ctx)` should copy
+    /// the content of `src` to the interval beginning at `off`th byte of the
+    /// destination, which the caller of this method knows.
+    #[inline]
+    fn read_internal<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), KernelError>,
+    >(
+        &mut self,
+        mut off: u32,
+        mut n: u32,
+        mut f: F,
+        mut k: K,
+    ) -> Result<usize, KernelError> {
+        let inner = self.deref_inner();
+        if off > inner.size || off.wrapping_add(n) < off {
+            return Ok(0);
+        }
+        if off + n > inner.size {
+            n = inner.size - off;
+        }
+        let mut tot: u32 = 0;
+        while tot < n {
+            let m = cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
+            let begin = (off % BSIZE as u32) as usize;
+            let end = begin + m as usize;
+            let res = match self.bmap(off as usize / BSIZE, &k) {
+                Some(bno) => {
+                    let bp = hal().disk().read(self.dev, bno, &k);
+                    let res = f(tot, &bp.deref_inner().data[begin..end], &mut k);
+                    bp.free(&k);
+                    res
+                }
+                // A block that a failed write never filled reads as zeros.
+                None => f(tot, &[0; BSIZE][begin..end], &mut k),
+            };
+            res?;
+            tot += m;
+            off += m;
+        }
+        Ok(tot as usize)
+    }
+
+    /// Copy data from `src` into the inode at offset `off`.
+    /// Return Ok(()) on success, Err(KernelError::Io) on a short read or write.
+    pub fn write_kernel<T: AsBytes>(
+        &mut self,
+        src: &T,
+        off: u32,
+        tx: &LfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let bytes = self.write_bytes_kernel(src.as_bytes(), off, tx, ctx)?;
+        if bytes == mem::size_of::<T>() {
+            Ok(())
+        } else {
+            Err(KernelError::Io)
+        }
+    }
+
+    /// Copy data from `src` into the inode at offset `off`.
+    /// Returns Ok(number of bytes copied) on success, Err(KernelError) on failure.
+    pub fn write_bytes_kernel(
+        &mut self,
+        src: &[u8],
+        off: u32,
+        tx: &LfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        self.write_internal(
+            off,
+            src.len() as u32,
+            |off, dst, _| {
+                dst.clone_from_slice(&src[off as usize..off as usize + dst.len()]);
+                Ok(())
+            },
+            tx,
+            ctx,
+        )
+    }
+
+    /// Copy data from virtual address `src` of the current process by `n` bytes
+    /// into the inode at offset `off`.
+    /// Returns Ok(number of bytes copied) on success, Err(KernelError) on failure.
+    pub fn write_user(
+        &mut self,
+        src: UVAddr,
+        off: u32,
+        n: u32,
+        ctx: &mut KernelCtx<'_, '_>,
+        tx: &LfsTx<'_>,
+    ) -> Result<usize, KernelError> {
+        self.write_internal(
+            off,
+            n,
+            |off, dst, ctx| {
+                // An injected fault stops the write short, as a failed copy does.
+                if hal().faults().should_fail(FaultSite::Disk) {
+                    return Err(KernelError::Io);
+                }
+                ctx.proc_mut()
+                    .memory_mut()
+                    .copy_in_bytes(dst, src + off as usize)
+            },
+            tx,
+            ctx,
+        )
+    }
+
+    /// Fill `n` bytes of inode from offset `off` with `f` a block at a time, straight into the
+    /// buffer cache. `f(dst, ctx)` fills all of `dst`, or fails to stop the write.
+    /// Returns Ok(number of bytes written) on success, Err(KernelError) if `f` failed at once.
+    pub fn write_from<'id, 's, F>(
+        &mut self,
+        off: u32,
+        n: u32,
+        mut f: F,
+        tx: &LfsTx<'_>,
+        ctx: &mut KernelCtx<'id, 's>,
+    ) -> Result<usize, KernelError>
+    where
+        F: FnMut(&mut [u8], &mut KernelCtx<'id, 's>) -> Result<(), KernelError>,
+    {
+        self.write_internal(off, n, |_, dst, ctx| f(dst, ctx), tx, ctx)
+    }
+
+    /// Write data to inode. Returns the number of bytes successfully written.
+    /// If the return value is less than the requested n, there was an error of
+    /// some kind. If no byte was written, the error is returned instead.
+    ///
+    /// `f` takes an offset and a slice as arguments. `f(off, dst)` should copy
+    /// the content beginning at the `off`th byte of the source, which the
+    /// caller of this method knows, to `dst`.
+    #[inline]
+    fn write_internal<
+        'id,
+        's,
+        K: Deref<Target = KernelCtx<'id, 's>>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), KernelError>,
+    >(
+        &mut self,
+        mut off: u32,
+        n: u32,
+        mut f: F,
+        tx: &LfsTx<'_>,
+        mut k: K,
+    ) -> Result<usize, KernelError> {
+        if off > self.deref_inner().size {
+            return Err(KernelError::InvalidArgument);
+        }
+        if off.checked_add(n).ok_or(KernelError::FileTooLarge)? as usize > MAXFILE * BSIZE {
+            return Err(KernelError::FileTooLarge);
+        }
+        // exec must not map the old content of the file anymore.
+        hal()
+            .pagecache()
+            .invalidate(self.dev, self.inum, hal().kmem());
+
+        let mut tot: u32 = 0;
+        let mut err = None;
+        while tot < n {
+            let bn = off as usize / BSIZE;
+            let old = self.bmap(bn, &k);
+            let mut bp = self.relocate(bn as u32, old, tx, &k);
+            let m = cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
+            let begin = (off % BSIZE as u32) as usize;
+            let end = begin + m as usize;
+            if let Err(e) = f(tot, &mut bp.deref_inner_mut().data[begin..end], &mut k) {
+                // The block has its new address already, so it is written anyway.
+                err = Some(e);
+            }
+            let new = bp.blockno;
+            tx.write(bp, &k);
+            if old != Some(new) {
+                self.set_bmap(bn, new, tx, &k);
+            }
+            if err.is_some() {
+                break;
+            }
+            tot += m;
+            off += m;
+        }
+
+        if off > self.deref_inner().size {
+            self.deref_inner_mut().size = off;
+        }
+
+        // Write the i-node back to disk even if the size didn't change, because the loop above
+        // moved the blocks that it wrote.
+        self.update(tx, &k);
+        match err {
+            Some(e) if tot == 0 => Err(e),
+            _ => Ok(tot as usize),
+        }
+    }
+
+    /// Returns the buffer of the block at the address that `tx.alloc` returns for block `off` of
+    /// the inode, which was at `old`, holding the block's content, or zeros if `old` is None.
+    /// The caller passes it to `tx.write`.
+    fn relocate(
+        &self,
+        off: u32,
+        old: Option<BlockNo>,
+        tx: &LfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Buf {
+        let new = tx.alloc(self.inum.into_u32(), off, old, ctx);
+        if old == Some(new) {
+            return hal().disk().read(self.dev, new, ctx);
+        }
+        let mut bp = ctx.kernel().bcache().get_buf(self.dev, new).lock(ctx);
+        match old {
+            Some(old) => {
+                let from = hal().disk().read(self.dev, old, ctx);
+                bp.deref_inner_mut()
+                    .data
+                    .copy_from_slice(&from.deref_inner().data[..]);
+                from.free(ctx);
+            }
+            None => bp.deref_inner_mut().data.fill(0),
+        }
+        bp.deref_inner_mut().valid = true;
+        bp
+    }
+
+    /// Inode content
+    ///
+    /// The content (data) associated with each inode is stored
+    /// in blocks on the disk. The first NDIRECT block numbers
+    /// are listed in self->addrs[].  The next NINDIRECT blocks are
+    /// listed in block self->addr_indirect.
+    /// Return the disk block address of the nth block in inode ip,
+    /// or None if there is no such block yet.
+    fn bmap(&self, bn: usize, ctx: &KernelCtx<'_, '_>) -> Option<BlockNo> {
+        let inner = self.deref_inner();
+        if bn < NDIRECT {
+            return block_no(inner.addr_direct[bn]);
+        }
+        let bn = bn - NDIRECT;
+        assert!(bn < NINDIRECT, "bmap: out of range");
+        let mut bp = hal()
+            .disk()
+            .read(self.dev, block_no(inner.addr_indirect)?, ctx);
+        let addr = indirect(&mut bp).addrs[bn];
+        bp.free(ctx);
+        block_no(addr)
+    }
+
+    /// Queues the blocks `blocks` of the file, up to the end of the file, to be read into the
+    /// buffer cache by the workers of the workqueue. Blocks that find the queue full are not read,
+    /// and neither are holes.
+    pub fn read_ahead(&mut self, blocks: Range<u32>, ctx: &KernelCtx<'_, '_>) {
+        let size = self.deref_inner().size;
+        for bn in blocks.take_while(|bn| (*bn as usize * BSIZE) < size as usize) {
+            let bno = some_or!(self.bmap(bn as usize, ctx), continue);
+            let arg = (self.dev.into_u32() as usize) << 32 | bno.into_u32() as usize;
+            if ctx
+                .kernel()
+                .workqueue()
+                .queue(read_block, arg, ctx.kernel())
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Drops the blocks `blocks` of the file from the buffer cache, except those in use.
+    pub fn evict_blocks(&mut self, blocks: Range<u32>, ctx: &KernelCtx<'_, '_>) {
+        let size = self.deref_inner().size;
+        for bn in blocks.take_while(|bn| (*bn as usize * BSIZE) < size as usize) {
+            if let Some(bno) = self.bmap(bn as usize, ctx) {
+                ctx.kernel().bcache().evict(self.dev, bno, ctx);
+            }
+        }
+    }
+
+    /// Points block `off` of the inode, or its indirect block if `off` is `SUMMARY_INDIRECT`, from
+    /// `old` to `new`, where the cleaner moved it, and writes the inode. Returns false, and
+    /// changes nothing, if the block is not at `old` anymore.
+    pub fn repoint(
+        &mut self,
+        off: u32,
+        old: BlockNo,
+        new: BlockNo,
+        tx: &LfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> bool {
+        if self.deref_inner().typ == InodeType::None {
+            return false;
+        }
+        if off == SUMMARY_INDIRECT {
+            if block_no(self.deref_inner().addr_indirect) != Some(old) {
+                return false;
+            }
+            self.deref_inner_mut().addr_indirect = new.into_u32();
+        } else {
+            let bn = off as usize;
+            if bn >= MAXFILE || self.bmap(bn, ctx) != Some(old) {
+                return false;
+            }
+            self.set_bmap(bn, new, tx, ctx);
+        }
+        self.update(tx, ctx);
+        true
+    }
+
+    /// Maps the nth block of the inode to `addr`, moving the indirect block if the block is
+    /// listed there. The caller writes the inode.
+    fn set_bmap(&mut self, bn: usize, addr: BlockNo, tx: &LfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        if bn < NDIRECT {
+            self.deref_inner_mut().addr_direct[bn] = addr.into_u32();
+            return;
+        }
+        let bn = bn - NDIRECT;
+        assert!(bn < NINDIRECT, "bmap: out of range");
+        let old = block_no(self.deref_inner().addr_indirect);
+        let mut bp = self.relocate(SUMMARY_INDIRECT, old, tx, ctx);
+        indirect(&mut bp).addrs[bn] = addr.into_u32();
+        let new = bp.blockno;
+        tx.write(bp, ctx);
+        self.deref_inner_mut().addr_indirect = new.into_u32();
+    }
+}
@@ -0,0 +1,560 @@
+use core::{cell::UnsafeCell, mem, sync::atomic::AtomicU32};
+
+use rv6_abi::dirent::Dirent;
+
+pub use self::inode::InodeInner;
+use self::{imap::Imap, inode::ROOTINO, segment::SegManager, segtable::SegTable};
+use super::{
+    FcntlFlags, FileName, FileSystem, FsInode, InodeGuard, InodeType, Itable, Path, RcInode,
+    DEFAULT_DEVICE_MODE, DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, I_SGID, I_SUID, MAY_EXEC, MAY_READ,
+    MAY_WRITE, S_IALL,
+};
+use crate::{
+    arena::{Arena, ArenaStats},
+    audit::AuditEvent,
+    bio::Buf,
+    cred::Caps,
+    error::KernelError,
+    file::{FileType, InodeFileType, Readahead},
+    lock::{SleepLock, SleepableLock, SpinLock},
+    param::{BSIZE, ROOTDEV},
+    proc::KernelCtx,
+    util::{
+        branded::{BlockNo, DevNo, Inum},
+        strong_pin::StrongPin,
+    },
+};
+
+mod checkpoint;
+mod cleaner;
+mod dir;
+mod imap;
+mod inode;
+mod recovery;
+mod segment;
+mod segtable;
+
+pub struct Lfs {
+    /// Live bytes and age of each segment, which the segment writer updates on every block it
+    /// writes or kills.
+    segtable: SpinLock<SegTable>,
+
+    /// Collects the blocks that transactions write into partial segments.
+    segmanager: SleepableLock<SegManager>,
+
+    /// Maps each inode number to the address of the inode.
+    imap: SpinLock<Imap>,
+
+    /// Number of the last checkpoint, which the checkpoint regions alternate by.
+    checkpoint_serial: AtomicU32,
+
+    /// Held while the cleaner runs, so that only one process cleans at a time.
+    cleaner: SleepLock<()>,
+
+    /// The in-memory copies of the inodes.
+    itable: Itable<InodeInner>,
+}
+
+/// Returns the time for the segment usage table, in seconds since the Unix epoch.
+fn now(ctx: &KernelCtx<'_, '_>) -> u32 {
+    (ctx.kernel().clocks().realtime_ns() / 1_000_000_000) as u32
+}
+
+impl FileSystem for Lfs {
+    type Dirent = Dirent;
+    type InodeInner = InodeInner;
+    type Tx<'s> = LfsTx<'s>;
+
+    fn init(&self, dev: DevNo, ctx: &KernelCtx<'_, '_>) {
+        let serial = self.recover(dev, ctx);
+        let mut segmanager = self.segmanager.lock();
+        segmanager.init(dev, serial, &self.segtable.lock());
+        let seg = segmanager.segment();
+        drop(segmanager);
+        self.write_checkpoint(dev, serial, seg, 0, ctx);
+    }
+
+    fn begin_tx(&self, ctx: &KernelCtx<'_, '_>) -> Self::Tx<'_> {
+        self.clean_if_short(ctx);
+        self.segmanager.begin_op(ctx);
+        LfsTx { fs: self }
+    }
+
+    fn root(self: StrongPin<'_, Self>) -> RcInode<Self::InodeInner> {
+        self.itable().get_inode(ROOTDEV, ROOTINO)
+    }
+
+    fn namei(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcInode<Self::InodeInner>, KernelError> {
+        Lfs::namei(self, path, tx, ctx)
+    }
+
+    fn link(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        path: &Path,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let inode = scopeguard::guard(inode, |ptr| ptr.free((tx, ctx)));
+        let ip = inode.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        if ip.deref_inner().typ == InodeType::Dir {
+            return Err(KernelError::NotPermitted);
+        }
+        ip.deref_inner_mut().nlink += 1;
+        ip.update(tx, ctx);
+        drop(ip);
+
+        let res = self.nameiparent(path, tx, ctx).and_then(|(ptr2, name)| {
+            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+            let dp = ptr2.lock(ctx);
+            let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+            if dp.dev != inode.dev {
+                return Err(KernelError::CrossDevice);
+            }
+            dp.check_access(MAY_WRITE | MAY_EXEC, ctx)?;
+            dp.dirlink(name, inode.inum, tx, ctx)
+        });
+        if res.is_ok() {
+            return res;
+        }
+
+        let ip = inode.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        ip.deref_inner_mut().nlink -= 1;
+        ip.update(tx, ctx);
+        res
+    }
+
+    fn unlink(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let (ptr, name) = self.nameiparent(path, tx, ctx)?;
+        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
+        let dp = ptr.lock(ctx);
+        let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+        dp.check_access(MAY_WRITE | MAY_EXEC, ctx)?;
+
+        // Cannot unlink "." or "..".
+        if name.as_bytes() == b"." || name.as_bytes() == b".." {
+            return Err(KernelError::InvalidArgument);
+        }
+
+        let (ptr2, off) = dp.dirlookup(name, ctx)?;
+        let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+        let ip = ptr2.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        assert!(ip.deref_inner().nlink >= 1, "unlink: nlink < 1");
+
+        if ip.deref_inner().typ == InodeType::Dir && !ip.is_dir_empty(ctx) {
+            return Err(KernelError::NotEmpty);
+        }
+
+        dp.dirunlink(off, tx, ctx);
+        if ip.deref_inner().typ == InodeType::Dir {
+            dp.deref_inner_mut().nlink -= 1;
+            dp.update(tx, ctx);
+        }
+        drop(dp);
+        drop(ptr);
+        ip.deref_inner_mut().nlink -= 1;
+        ip.update(tx, ctx);
+        Ok(())
+    }
+
+    fn create<F, T>(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        typ: InodeType,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+        f: F,
+    ) -> Result<(RcInode<Self::InodeInner>, T), KernelError>
+    where
+        F: FnOnce(&mut InodeGuard<'_, Self::InodeInner>, bool) -> T,
+    {
+        let (ptr, name) = self.nameiparent(path, tx, ctx)?;
+        let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
+        let dp = ptr.lock(ctx);
+        let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+        if let Ok((ptr2, _)) = dp.dirlookup(name, ctx) {
+            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+            drop(dp);
+            if typ != InodeType::File {
+                return Err(KernelError::Exists);
+            }
+            let ip = ptr2.lock(ctx);
+            let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+            if let InodeType::None | InodeType::Dir = ip.deref_inner().typ {
+                return Err(KernelError::IsDir);
+            }
+            let ret = f(&mut ip, false);
+            drop(ip);
+            return Ok((scopeguard::ScopeGuard::into_inner(ptr2), ret));
+        }
+        dp.check_access(MAY_WRITE | MAY_EXEC, ctx)?;
+        let data = ctx.proc().deref_data();
+        let ptr2 = self.alloc_inode(dp.dev, typ, tx, ctx)?;
+        let ip = ptr2.lock(ctx);
+        let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+        let mode = match typ {
+            InodeType::Dir => DEFAULT_DIR_MODE & !(data.umask as u16),
+            InodeType::Device { major, .. } => {
+                ctx.kernel()
+                    .devsw()
+                    .get(major as usize)
+                    .map_or(DEFAULT_DEVICE_MODE, |devsw| devsw.mode)
+            }
+            _ => DEFAULT_FILE_MODE & !(data.umask as u16),
+        };
+        let inner = ip.deref_inner_mut();
+        inner.nlink = 1;
+        inner.mode = mode;
+        inner.uid = data.cred.euid() as u16;
+        inner.gid = data.cred.egid as u16;
+        ip.update(tx, ctx);
+
+        // Create . and .. entries.
+        if typ == InodeType::Dir {
+            // for ".."
+            dp.deref_inner_mut().nlink += 1;
+            dp.update(tx, ctx);
+
+            let inum = ip.inum;
+            // No ip->nlink++ for ".": avoid cyclic ref count.
+            // SAFETY: b"." does not contain any NUL characters.
+            ip.dirlink(unsafe { FileName::from_bytes(b".") }, inum, tx, ctx)
+                // SAFETY: b".." does not contain any NUL characters.
+                .and_then(|_| ip.dirlink(unsafe { FileName::from_bytes(b"..") }, dp.inum, tx, ctx))
+                .expect("create dots");
+        }
+        dp.dirlink(name, ip.inum, tx, ctx).expect("create: dirlink");
+        let ret = f(&mut ip, true);
+        drop(ip);
+        Ok((ptr2, ret))
+    }
+
+    fn open(
+        self: StrongPin<'_, Self>,
+        path: &Path,
+        omode: FcntlFlags,
+        tx: &Self::Tx<'_>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let mut access = 0;
+        if !omode.intersects(FcntlFlags::O_WRONLY) {
+            access |= MAY_READ;
+        }
+        if omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR | FcntlFlags::O_TRUNC) {
+            access |= MAY_WRITE;
+        }
+        let (ip, typ) = if omode.contains(FcntlFlags::O_CREATE) {
+            let (ip, res) = self.create(path, InodeType::File, tx, ctx, |ip, created| {
+                // The creator may open a new file regardless of its mode.
+                if !created {
+                    ip.check_access(access, ctx)?;
+                }
+                Ok(ip.deref_inner().typ)
+            })?;
+            match res {
+                Ok(typ) => (ip, typ),
+                Err(e) => {
+                    ip.free((tx, ctx));
+                    return Err(e);
+                }
+            }
+        } else {
+            let ptr = self.namei(path, tx, ctx)?;
+            let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
+            let ip = ptr.lock(ctx);
+            let ip = scopeguard::guard(ip, |ip| ip.free(ctx));
+            let typ = ip.deref_inner().typ;
+
+            if typ == InodeType::Dir
+                && omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR | FcntlFlags::O_TRUNC)
+            {
+                return Err(KernelError::IsDir);
+            }
+            ip.check_access(access, ctx)?;
+            drop(ip);
+            (scopeguard::ScopeGuard::into_inner(ptr), typ)
+        };
+
+        let ip = FsInode::Lfs(ip);
+        let filetype = match typ {
+            InodeType::Device { major, .. } => FileType::Device { ip, major },
+            _ => {
+                FileType::Inode {
+                    inner: InodeFileType {
+                        ip,
+                        off: UnsafeCell::new(0),
+                        readahead: UnsafeCell::new(Readahead::new()),
+                    },
+                }
+            }
+        };
+
+        let f = ctx
+            .kernel()
+            .ftable()
+            .alloc_file(
+                filetype,
+                !omode.intersects(FcntlFlags::O_WRONLY),
+                omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR),
+            )
+            .map_err(|filetype| {
+                match filetype {
+                    FileType::Device {
+                        ip: FsInode::Lfs(ip),
+                        ..
+                    }
+                    | FileType::Inode {
+                        inner:
+                            InodeFileType {
+                                ip: FsInode::Lfs(ip),
+                                ..
+                            },
+                    } => ip.free((tx, ctx)),
+                    _ => (),
+                }
+                KernelError::FileTableFull
+            })?;
+
+        if omode.contains(FcntlFlags::O_TRUNC) && typ == InodeType::File {
+            match &f.typ {
+                // It is safe to call itrunc because ip.lock() is held
+                FileType::Device {
+                    ip: FsInode::Lfs(ip),
+                    ..
+                }
+                | FileType::Inode {
+                    inner:
+                        InodeFileType {
+                            ip: FsInode::Lfs(ip),
+                            ..
+                        },
+                } => {
+                    let mut ip = ip.lock(ctx);
+                    ip.itrunc(tx, ctx);
+                    ip.free(ctx);
+                }
+                _ => panic!("sys_open : Not reach"),
+            };
+        }
+        f.set_status_flags(omode);
+        let fd = f.fdalloc(ctx)?;
+        if omode.contains(FcntlFlags::O_CLOEXEC) {
+            ctx.proc_mut()
+                .deref_mut_data()
+                .fds
+                .set_cloexec(fd as usize, true);
+        }
+        Ok(fd as usize)
+    }
+
+    fn chdir(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        tx: &Self::Tx<'_>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let ip = inode.lock(ctx);
+        let typ = ip.deref_inner().typ;
+        let res = ip.check_access(MAY_EXEC, ctx);
+        ip.free(ctx);
+        if typ != InodeType::Dir {
+            inode.free((tx, ctx));
+            return Err(KernelError::NotDir);
+        }
+        if let Err(e) = res {
+            inode.free((tx, ctx));
+            return Err(e);
+        }
+        match mem::replace(ctx.proc_mut().cwd_mut(), FsInode::Lfs(inode)) {
+            FsInode::Lfs(old) => old.free((tx, ctx)),
+            old => old.free(ctx),
+        }
+        Ok(())
+    }
+
+    fn chroot(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        tx: &Self::Tx<'_>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let ip = inode.lock(ctx);
+        let typ = ip.deref_inner().typ;
+        let res = ip.check_access(MAY_EXEC, ctx);
+        ip.free(ctx);
+        if typ != InodeType::Dir {
+            inode.free((tx, ctx));
+            return Err(KernelError::NotDir);
+        }
+        if let Err(e) = res {
+            inode.free((tx, ctx));
+            return Err(e);
+        }
+        let root = if inode.inum == ROOTINO {
+            // Chrooting to the file system's root undoes chroot.
+            inode.free((tx, ctx));
+            None
+        } else {
+            Some(FsInode::Lfs(inode))
+        };
+        match mem::replace(&mut ctx.proc_mut().deref_mut_data().root, root) {
+            Some(FsInode::Lfs(old)) => old.free((tx, ctx)),
+            Some(old) => old.free(ctx),
+            None => (),
+        }
+        Ok(())
+    }
+
+    fn chmod(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        mode: u16,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let mut ip = inode.lock(ctx);
+        let res = if ctx.capable(Caps::FOWNER)
+            || ip.deref_inner().uid as u32 == ctx.proc().deref_data().cred.euid()
+        {
+            ip.deref_inner_mut().mode = mode & S_IALL;
+            ip.update(tx, ctx);
+            Ok(())
+        } else {
+            let inum = ip.inum.into_u32() as u64;
+            ctx.audit(AuditEvent::Denied, inum, Some(KernelError::NotPermitted));
+            Err(KernelError::NotPermitted)
+        };
+        ip.free(ctx);
+        inode.free((tx, ctx));
+        res
+    }
+
+    fn chown(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        uid: u16,
+        gid: u16,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let res = ctx.require_cap(Caps::CHOWN).map(|_| {
+            let mut ip = inode.lock(ctx);
+            let inner = ip.deref_inner_mut();
+            inner.uid = uid;
+            inner.gid = gid;
+            // The new owner did not grant its rights to the program.
+            inner.mode &= !(I_SUID | I_SGID);
+            ip.update(tx, ctx);
+            ip.free(ctx);
+        });
+        inode.free((tx, ctx));
+        res
+    }
+
+    fn getcwd(
+        self: StrongPin<'_, Self>,
+        buf: &mut [u8],
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let cwd = ctx.proc().cwd().as_lfs().ok_or(KernelError::CrossDevice)?;
+        self.path_of(cwd, buf, tx, ctx)
+    }
+}
+
+pub struct LfsTx<'s> {
+    fs: &'s Lfs,
+}
+
+impl Lfs {
+    pub const fn new() -> Self {
+        Self {
+            segtable: SpinLock::new("SEGTABLE", SegTable::new()),
+            segmanager: SleepableLock::new("SEGMANAGER", SegManager::new()),
+            imap: SpinLock::new("IMAP", Imap::new()),
+            checkpoint_serial: AtomicU32::new(0),
+            cleaner: SleepLock::new("CLEANER", ()),
+            itable: Itable::<InodeInner>::new_itable(),
+        }
+    }
+
+    fn itable(&self) -> StrongPin<'_, Itable<InodeInner>> {
+        // SAFETY: the kernel holds the only instance of `Lfs`, pinned in its `Vfs`.
+        unsafe { StrongPin::new_unchecked(&self.itable) }
+    }
+
+    /// Returns the usage statistics of the inode table.
+    pub fn itable_stats(self: StrongPin<'_, Self>) -> ArenaStats {
+        self.itable().stats()
+    }
+}
+
+impl Drop for LfsTx<'_> {
+    fn drop(&mut self) {
+        // HACK(@efenniht): we really need linear type here:
+        // https://github.com/rust-lang/rfcs/issues/814
+        panic!("LfsTx must never drop.");
+    }
+}
+
+impl LfsTx<'_> {
+    /// Returns a new address in the current segment for block `off` of inode `inum`, or for the
+    /// inode itself if `off` is `segment::SUMMARY_INODE`, and accounts the old address `old` of
+    /// the block as dead. If `old` is in the pending partial segment, returns `old` instead, as
+    /// the block has not been written yet. The caller fills the block at the address and passes
+    /// it to `write`.
+    fn alloc(&self, inum: u32, off: u32, old: Option<BlockNo>, ctx: &KernelCtx<'_, '_>) -> BlockNo {
+        let mut segmanager = self.fs.segmanager.lock();
+        match old {
+            Some(old) if segmanager.is_pending(old) => return old,
+            Some(old) => segmanager.kill(old, &self.fs.segtable),
+            None => (),
+        }
+        segmanager.alloc(inum, off, &self.fs.segtable, now(ctx))
+    }
+
+    /// Accounts the address `b` of a block as dead, as the block was deleted or written
+    /// elsewhere.
+    fn kill(&self, b: BlockNo) {
+        self.fs.segmanager.lock().kill(b, &self.fs.segtable);
+    }
+
+    /// Frees inode `inum` in the inode map, and accounts its block as dead.
+    fn free_inode(&self, inum: Inum) {
+        let mut imap = self.fs.imap.lock();
+        let b = imap.get_inode(inum.into_u32()).expect("free_inode");
+        imap.set_inode(inum.into_u32(), None);
+        drop(imap);
+        self.fs.segtable.lock().delete(b, BSIZE as u32);
+    }
+
+    /// Caller has filled `b`, whose address `alloc` returned, and is done with the buffer.
+    /// The segment writer writes it when the last outstanding transaction ends.
+    fn write(&self, b: Buf, ctx: &KernelCtx<'_, '_>) {
+        self.fs.segmanager.lock().write(b, ctx);
+    }
+
+    /// Called at the end of each FS system call.
+    pub fn end(self, ctx: &KernelCtx<'_, '_>) {
+        self.fs.segmanager.end_op(
+            &self.fs.segtable,
+            |segmanager| self.fs.checkpoint(segmanager, ctx),
+            ctx,
+        );
+        self.fs.clean_in_background(ctx);
+        mem::forget(self);
+    }
+}
@@ -0,0 +1,143 @@
+//! Crash recovery, which brings the inode map and the segment usage table of the last checkpoint
+//! up to date with the partial segments written after it.
+//!
+//! The checkpoint names the first partial segment that the segment writer wrote after it, and
+//! the summary of each partial segment names the segment of the next one, so roll-forward
+//! follows them in the order they were written. It stops at the first partial segment whose
+//! summary is not there, or whose serial number is not the next one, as the block holds
+//! something older, or one of whose blocks does not match its checksum, as a crash cut its write
+//! short. The partial segments up to there hold whole transactions, since the segment writer
+//! writes them only while no transaction is outstanding.
+//!
+//! Mounting then checkpoints, since the segment writer does not go on where roll-forward stopped
+//! but starts in a free segment, which the old checkpoint does not lead to.
+
+use arrayvec::ArrayVec;
+
+use super::{
+    now,
+    segment::{SegSummary, SumEntry, NPENDING, SUMMARY_INODE},
+    segtable::{NSEG, SEGSIZE},
+    Lfs,
+};
+use crate::{
+    hal::hal,
+    param::BSIZE,
+    proc::KernelCtx,
+    util::{
+        branded::{BlockNo, DevNo},
+        hash::crc32c,
+    },
+};
+
+impl Lfs {
+    /// Loads the last checkpoint of `dev` and rolls forward from it. Returns the serial number of
+    /// the next partial segment. `Vfs::mount` made sure that `dev` has a valid checkpoint.
+    pub fn recover(&self, dev: DevNo, ctx: &KernelCtx<'_, '_>) -> u32 {
+        let header = self
+            .load_checkpoint(dev, ctx)
+            .expect("Lfs::recover: no valid checkpoint");
+        self.roll_forward(
+            dev,
+            header.next,
+            header.seg as usize,
+            header.start as usize,
+            ctx,
+        )
+    }
+
+    /// Applies partial segment `serial`, which starts at offset `start` of segment `seg`, and
+    /// those that follow it. Returns the serial number of the partial segment after the last one
+    /// applied.
+    fn roll_forward(
+        &self,
+        dev: DevNo,
+        mut serial: u32,
+        mut seg: usize,
+        mut start: usize,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> u32 {
+        let now = now(ctx);
+        while (1..NSEG).contains(&seg) && start < SEGSIZE {
+            let summary = BlockNo::new((seg * SEGSIZE + start) as u32);
+            let buf = hal().disk().read(dev, summary, ctx);
+            let sum = SegSummary::read(&buf.deref_inner().data[..]);
+            buf.free(ctx);
+            let sum = match sum
+                .filter(|sum| sum.serial == serial && start + 1 + sum.entries().len() <= SEGSIZE)
+            {
+                Some(sum) => sum,
+                None => break,
+            };
+            if !self.is_whole(dev, summary, &sum, ctx) {
+                break;
+            }
+
+            // Look the old addresses up before replaying any block, since an inode that the
+            // partial segment holds may come before the blocks that it maps. An inode freed and
+            // allocated again in the partial segment has two blocks with the same old address,
+            // which dies only once.
+            let mut olds = ArrayVec::<Option<BlockNo>, NPENDING>::new();
+            for entry in sum.entries() {
+                let old = self
+                    .block_addr(dev, entry, ctx)
+                    .filter(|old| !olds.contains(&Some(*old)));
+                olds.push(old);
+            }
+            for (i, (entry, old)) in sum.entries().iter().zip(olds).enumerate() {
+                let b = summary + (1 + i as u32);
+                let freed = entry.inum != 0
+                    && entry.off == SUMMARY_INODE
+                    && self.is_freed_inode(dev, b, ctx);
+                self.replay(entry, b, old, freed, now);
+            }
+            serial = serial.wrapping_add(1);
+            if sum.next_seg as usize == seg {
+                start += 1 + sum.entries().len();
+            } else {
+                seg = sum.next_seg as usize;
+                start = 0;
+            }
+        }
+        serial
+    }
+
+    /// Returns whether each block of the partial segment whose summary `sum` is at `summary`
+    /// matches its checksum.
+    fn is_whole(
+        &self,
+        dev: DevNo,
+        summary: BlockNo,
+        sum: &SegSummary,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> bool {
+        sum.entries().iter().enumerate().all(|(i, entry)| {
+            let buf = hal().disk().read(dev, summary + (1 + i as u32), ctx);
+            let crc = crc32c(&buf.deref_inner().data[..]);
+            buf.free(ctx);
+            crc == entry.crc
+        })
+    }
+
+    /// Accounts the block at `b`, which `entry` names, as written at `now`, and its old address
+    /// `old` as dead, and maps its inode to it if it is an inode. If `freed`, `b` holds an inode
+    /// that was freed, so `b` is dead too and the inode is unmapped.
+    fn replay(&self, entry: &SumEntry, b: BlockNo, old: Option<BlockNo>, freed: bool, now: u32) {
+        if entry.inum == 0 {
+            // The block died before it was written.
+            return;
+        }
+        let mut segtable = self.segtable.lock();
+        if !freed {
+            segtable.write(b, BSIZE as u32, now);
+        }
+        if let Some(old) = old {
+            segtable.delete(old, BSIZE as u32);
+        }
+        if entry.off == SUMMARY_INODE {
+            self.imap
+                .lock()
+                .set_inode(entry.inum, Some(b).filter(|_| !freed));
+        }
+    }
+}
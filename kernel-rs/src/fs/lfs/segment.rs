@@ -0,0 +1,362 @@
+//! The segment writer, which collects the blocks that transactions write and writes them out
+//! together into the current segment, instead of writing each block back to its old address.
+//!
+//! A transaction asks `SegManager::alloc` for a new address for each data or inode block that it
+//! changes, fills the block at that address in the buffer cache, and hands it to
+//! `SegManager::write`. The old address of the block dies, which `SegManager::kill` accounts in
+//! the segment usage table. When the last outstanding transaction ends, the blocks that the
+//! transactions wrote go to disk as a partial segment: a summary block, which names the inode and
+//! the offset of each block after it with its checksum, and the blocks themselves, in the order of
+//! their addresses. The summary goes last, so that a partial segment whose summary is on disk is
+//! whole, and roll-forward can check each block against its checksum anyway.
+//!
+//! The writer fills a segment with partial segments until the next one might not fit, and then
+//! moves to a segment that is free in the usage table. Each summary names the segment of the
+//! next partial segment, so that roll-forward can follow the partial segments from the
+//! checkpoint. Like the log of UFS, `begin_op` waits
+//! while the blocks of the outstanding transactions might not fit into the current segment, so a
+//! transaction never has to wait for a new segment halfway.
+
+use core::mem;
+
+use arrayvec::ArrayVec;
+use static_assertions::const_assert;
+use zerocopy::{AsBytes, FromBytes, LayoutVerified};
+
+use super::segtable::{SegTable, NSEG, SEGSIZE};
+use crate::{
+    bio::{Buf, BufUnlocked},
+    hal::hal,
+    ktest, ktest_assert,
+    lock::{SleepableLock, SpinLock},
+    param::{BSIZE, MAXOPBLOCKS},
+    proc::KernelCtx,
+    util::{
+        branded::{BlockNo, DevNo},
+        hash::crc32c,
+    },
+};
+
+/// Magic number of a segment summary block.
+pub const SUMMARY_MAGIC: u32 = 0x5345_4753;
+
+/// The offset in a summary entry that marks an inode block instead of a data block.
+pub const SUMMARY_INODE: u32 = u32::MAX;
+
+/// The offset in a summary entry that marks the indirect block of an inode.
+pub const SUMMARY_INDIRECT: u32 = u32::MAX - 1;
+
+/// Maximum number of blocks in a partial segment after its summary, which stay pinned in the
+/// buffer cache until it is written, so that they leave room in the cache for other blocks.
+pub const NPENDING: usize = MAXOPBLOCKS * 2;
+
+const_assert!(NPENDING < SEGSIZE);
+
+/// What a block of a partial segment holds, as its summary records it.
+#[repr(C)]
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+pub struct SumEntry {
+    /// The inode that the block belongs to, or 0 if the block died before the partial segment
+    /// was written.
+    pub inum: u32,
+
+    /// The block's offset in the inode, in blocks, `SUMMARY_INODE` for the inode itself, or
+    /// `SUMMARY_INDIRECT` for its indirect block.
+    pub off: u32,
+
+    /// CRC32C of the block.
+    pub crc: u32,
+}
+
+/// The summary block at the start of each partial segment.
+#[repr(C)]
+#[derive(Copy, Clone, AsBytes, FromBytes)]
+pub struct SegSummary {
+    pub magic: u32,
+
+    /// Serial number of the partial segment, which grows with every partial segment written.
+    pub serial: u32,
+
+    /// Number of blocks that follow the summary.
+    pub nblocks: u32,
+
+    /// The segment that holds the next partial segment: this one, right after this partial
+    /// segment, or another one, from its start.
+    pub next_seg: u32,
+
+    pub entries: [SumEntry; NPENDING],
+}
+
+const_assert!(mem::size_of::<SegSummary>() <= BSIZE);
+
+impl SegSummary {
+    /// Returns the summary in the block `data`, or None if the block holds no summary.
+    pub fn read(data: &[u8]) -> Option<Self> {
+        let (sum, _) = LayoutVerified::<_, Self>::new_from_prefix(data)?;
+        Some(*sum).filter(|sum| sum.magic == SUMMARY_MAGIC && sum.nblocks as usize <= NPENDING)
+    }
+
+    /// Returns the entries of the blocks that follow the summary.
+    pub fn entries(&self) -> &[SumEntry] {
+        &self.entries[..self.nblocks as usize]
+    }
+}
+
+pub struct SegManager {
+    dev: DevNo,
+
+    /// The segment being written, or 0 before `init`.
+    seg: usize,
+
+    /// Offset in the segment of the summary of the next partial segment.
+    start: usize,
+
+    /// Serial number of the next partial segment.
+    serial: u32,
+
+    /// How many transactions are executing?
+    outstanding: usize,
+
+    /// In flush(), please wait.
+    flushing: bool,
+
+    /// What each block after the summary holds, in the order of their addresses.
+    entries: ArrayVec<SumEntry, NPENDING>,
+
+    /// The blocks that transactions wrote, pinned in the cache until the partial segment is
+    /// written.
+    bufs: ArrayVec<BufUnlocked, NPENDING>,
+}
+
+impl SegManager {
+    pub const fn new() -> Self {
+        Self {
+            dev: DevNo::new(0),
+            seg: 0,
+            start: 0,
+            serial: 0,
+            outstanding: 0,
+            flushing: false,
+            entries: ArrayVec::new_const(),
+            bufs: ArrayVec::new_const(),
+        }
+    }
+
+    /// Starts writing into a free segment of `dev`, with partial segments numbered from `serial`.
+    pub fn init(&mut self, dev: DevNo, serial: u32, segtable: &SegTable) {
+        self.dev = dev;
+        self.serial = serial;
+        self.next_segment(segtable);
+    }
+
+    pub fn dev(&self) -> DevNo {
+        self.dev
+    }
+
+    /// Returns the segment being written.
+    pub fn segment(&self) -> usize {
+        self.seg
+    }
+
+    /// Returns the offset in the segment of the summary of the next partial segment.
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    /// Returns the serial number of the next partial segment.
+    pub fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    /// Moves to the next segment after the current one that is free in `segtable`.
+    /// Panics if there is none.
+    fn next_segment(&mut self, segtable: &SegTable) {
+        let cur = self.seg;
+        self.seg = (cur + 1..NSEG)
+            .chain(1..cur)
+            .find(|seg| segtable.is_free(*seg))
+            .expect("SegManager: out of segments");
+        self.start = 0;
+    }
+
+    /// Returns the address of the `i`th block after the summary of the pending partial segment.
+    fn block(&self, i: usize) -> BlockNo {
+        BlockNo::new((self.seg * SEGSIZE + self.start + 1 + i) as u32)
+    }
+
+    /// Returns the index of `b` among the blocks of the pending partial segment, if it is one.
+    fn pending(&self, b: BlockNo) -> Option<usize> {
+        b.into_u32()
+            .checked_sub(self.block(0).into_u32())
+            .map(|i| i as usize)
+            .filter(|i| *i < self.entries.len())
+    }
+
+    /// Returns whether `b` is a block of the pending partial segment, which is not written yet.
+    pub fn is_pending(&self, b: BlockNo) -> bool {
+        self.pending(b).is_some()
+    }
+
+    /// Returns whether `n` more blocks fit into the pending partial segment.
+    fn fits(&self, n: usize) -> bool {
+        self.entries.len() + n <= NPENDING && self.start + 1 + self.entries.len() + n <= SEGSIZE
+    }
+
+    /// Returns a new address for block `off` of inode `inum`, or for the inode itself if `off`
+    /// is `SUMMARY_INODE`. The caller fills the block at the address, and passes it to `write`
+    /// before its transaction ends.
+    pub fn alloc(
+        &mut self,
+        inum: u32,
+        off: u32,
+        segtable: &SpinLock<SegTable>,
+        now: u32,
+    ) -> BlockNo {
+        assert!(self.outstanding >= 1, "alloc outside of trans");
+        assert!(self.fits(1), "too big a transaction");
+        let b = self.block(self.entries.len());
+        self.entries.push(SumEntry { inum, off, crc: 0 });
+        segtable.lock().write(b, BSIZE as u32, now);
+        b
+    }
+
+    /// Caller has filled `b`, whose address `alloc` returned, and is done with the buffer.
+    /// Pins it in the cache until flush() writes it.
+    pub fn write(&mut self, b: Buf, ctx: &KernelCtx<'_, '_>) {
+        assert!(self.outstanding >= 1, "write outside of trans");
+        let i = self
+            .pending(b.blockno)
+            .expect("SegManager::write: not allocated");
+        self.entries[i].crc = crc32c(&b.deref_inner().data[..]);
+        if self.bufs.iter().all(|buf| buf.blockno != b.blockno) {
+            self.bufs.push(b.unlock(ctx));
+        } else {
+            b.free(ctx);
+        }
+    }
+
+    /// Accounts the old address `b` of a block that was written elsewhere or deleted.
+    pub fn kill(&mut self, b: BlockNo, segtable: &SpinLock<SegTable>) {
+        segtable.lock().delete(b, BSIZE as u32);
+        // If the block is pending, roll-forward must not take it for the inode's.
+        if let Some(i) = self.pending(b) {
+            self.entries[i].inum = 0;
+        }
+    }
+
+    /// Writes the pending partial segment, moving to a new segment first if the next one might
+    /// not fit into this one, so that the summary can name it.
+    fn flush(&mut self, segtable: &SpinLock<SegTable>, ctx: &KernelCtx<'_, '_>) {
+        if !self.entries.is_empty() {
+            assert!(
+                self.bufs.len() == self.entries.len(),
+                "SegManager::flush: allocated block not written"
+            );
+            for buf in self.bufs.drain(..) {
+                let mut buf = buf.lock(ctx);
+                hal().disk().write(&mut buf, ctx);
+                buf.free(ctx);
+            }
+
+            let summary = BlockNo::new((self.seg * SEGSIZE + self.start) as u32);
+            self.start += 1 + self.entries.len();
+            // Leave room for the summary and the blocks of a transaction.
+            if self.start + 1 + MAXOPBLOCKS > SEGSIZE {
+                self.next_segment(&segtable.lock());
+            }
+
+            let mut buf = ctx.kernel().bcache().get_buf(self.dev, summary).lock(ctx);
+            let mut sum = SegSummary {
+                magic: SUMMARY_MAGIC,
+                serial: self.serial,
+                nblocks: self.entries.len() as u32,
+                next_seg: self.seg as u32,
+                entries: [SumEntry::default(); NPENDING],
+            };
+            sum.entries[..self.entries.len()].copy_from_slice(&self.entries);
+            let data = &mut buf.deref_inner_mut().data;
+            data[..mem::size_of::<SegSummary>()].copy_from_slice(sum.as_bytes());
+            data[mem::size_of::<SegSummary>()..].fill(0);
+            buf.deref_inner_mut().valid = true;
+            hal().disk().write(&mut buf, ctx);
+            buf.free(ctx);
+
+            self.serial += 1;
+            self.entries.clear();
+        }
+    }
+}
+
+impl SleepableLock<SegManager> {
+    /// Called at the start of each FS system call.
+    pub fn begin_op(&self, ctx: &KernelCtx<'_, '_>) {
+        let mut guard = self.lock();
+        loop {
+            if guard.flushing ||
+            // This op might not fit into the segment; wait for flush.
+            !guard.fits((guard.outstanding + 1) * MAXOPBLOCKS)
+            {
+                guard.sleep(ctx);
+            } else {
+                guard.outstanding += 1;
+                break;
+            }
+        }
+    }
+
+    /// Called at the end of each FS system call.
+    /// Writes the pending partial segment if this was the last outstanding operation, and then
+    /// calls `checkpoint`, before any transaction begins.
+    pub fn end_op<F: FnOnce(&SegManager)>(
+        &self,
+        segtable: &SpinLock<SegTable>,
+        checkpoint: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) {
+        let mut guard = self.lock();
+        guard.outstanding -= 1;
+        assert!(!guard.flushing, "guard.flushing");
+
+        if guard.outstanding == 0 {
+            // Since outstanding is 0, no ongoing transaction exists, and the lock is still held.
+            guard.flushing = true;
+
+            // Flush w/o holding locks, since not allowed to sleep with locks.
+            guard.reacquire_after(|| {
+                // SAFETY: there is no another transaction, so `inner` cannot be read or written.
+                let segmanager = unsafe { &mut *self.get_mut_raw() };
+                segmanager.flush(segtable, ctx);
+                checkpoint(segmanager);
+            });
+
+            guard.flushing = false;
+        }
+
+        // begin_op() may be waiting for room in the segment.
+        guard.wakeup(ctx.kernel());
+    }
+}
+
+ktest! {
+    fn segmanager_alloc(ctx) {
+        let segtable = SpinLock::new("SEGTABLE", SegTable::new());
+        // Segment 1 is in use, so the writer starts at segment 2.
+        segtable.lock().write(BlockNo::new(SEGSIZE as u32), BSIZE as u32, 0);
+        let mut segmanager = SegManager::new();
+        segmanager.init(DevNo::new(1), 0, &segtable.lock());
+        ktest_assert!(segmanager.segment() == 2);
+
+        // Blocks follow the summary at the start of the segment.
+        segmanager.outstanding = 1;
+        let b = segmanager.alloc(1, 0, &segtable, 10);
+        ktest_assert!(b.into_u32() as usize == 2 * SEGSIZE + 1);
+        let b = segmanager.alloc(1, SUMMARY_INODE, &segtable, 10);
+        ktest_assert!(b.into_u32() as usize == 2 * SEGSIZE + 2);
+        ktest_assert!(segtable.lock().live(2) == 2 * BSIZE as u32);
+        ktest_assert!(segmanager.is_pending(b));
+        segmanager.kill(b, &segtable);
+        ktest_assert!(segmanager.entries[1].inum == 0);
+        ktest_assert!(segtable.lock().live(2) == BSIZE as u32);
+        ktest_assert!(!segmanager.fits(NPENDING));
+    }
+}
@@ -0,0 +1,200 @@
+//! The segment usage table, which counts the live bytes of each segment and remembers when it was
+//! last written, so that the cleaner can pick the segments most worth cleaning.
+//!
+//! Writing a block into a segment adds to its live bytes, and deleting or overwriting a block,
+//! which leaves its old copy dead, takes them away again. The table lives in memory, and the
+//! checkpoint writes it out in SEGTABLE_BLOCKS blocks once it has changed and SEGTABLE_INTERVAL
+//! seconds have passed, so that the cleaner does not need to scan every segment after a reboot.
+//! Blocks written since the last checkpoint are accounted again when they are rolled forward.
+//! A segment that becomes free makes a checkpoint due at once, since roll-forward from the last
+//! checkpoint may need to read it, and the segment writer must not overwrite it before the next
+//! checkpoint.
+//!
+//! The cleaner picks the segment with the highest benefit-to-cost ratio, as Sprite LFS did:
+//! cleaning a segment whose fraction `u` of bytes is live costs reading it and writing the live
+//! bytes back, `1 + u`, and frees `1 - u` of a segment for a time that grows with the age of its
+//! data, since old data rarely changes. So cold segments are cleaned even while fairly full, and
+//! hot segments are left to empty themselves.
+
+use core::{cmp, mem};
+
+use static_assertions::const_assert;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{ktest, ktest_assert, param::BSIZE, util::branded::BlockNo};
+
+/// Number of blocks in a segment.
+pub const SEGSIZE: usize = 32;
+
+/// Number of segments, counting from block 0. Segment 0 holds the boot block, the superblock,
+/// and the checkpoint, and is never written or cleaned.
+pub const NSEG: usize = 128;
+
+/// Number of blocks that the table takes in the checkpoint.
+pub const SEGTABLE_BLOCKS: usize = (NSEG * mem::size_of::<SegUsage>() + BSIZE - 1) / BSIZE;
+
+/// Minimum number of seconds between two checkpoints of the table.
+const SEGTABLE_INTERVAL: u32 = 30;
+
+const_assert!(BSIZE % mem::size_of::<SegUsage>() == 0);
+
+/// Usage of a segment, as the checkpoint holds it.
+#[repr(C)]
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+pub struct SegUsage {
+    /// Number of live bytes in the segment.
+    live: u32,
+
+    /// When a block was last written into the segment, in seconds since the Unix epoch.
+    mtime: u32,
+}
+
+pub struct SegTable {
+    segs: [SegUsage; NSEG],
+
+    /// Whether the table changed since the last checkpoint.
+    dirty: bool,
+
+    /// Whether a segment became free since the last checkpoint.
+    freed: bool,
+
+    /// When the table was last checkpointed, in seconds since the Unix epoch.
+    checkpointed: u32,
+}
+
+/// Returns the segment that holds block `b`.
+pub fn seg_of(b: BlockNo) -> usize {
+    let seg = b.into_u32() as usize / SEGSIZE;
+    assert!(
+        seg > 0 && seg < NSEG,
+        "seg_of: {} is in no segment",
+        b.into_u32()
+    );
+    seg
+}
+
+impl SegTable {
+    pub const fn new() -> Self {
+        Self {
+            segs: [SegUsage { live: 0, mtime: 0 }; NSEG],
+            dirty: false,
+            freed: false,
+            checkpointed: 0,
+        }
+    }
+
+    /// Returns the number of live bytes in segment `seg`.
+    pub fn live(&self, seg: usize) -> u32 {
+        self.segs[seg].live
+    }
+
+    /// Returns whether the segment writer may write segment `seg` from its start, as it holds no
+    /// live bytes.
+    pub fn is_free(&self, seg: usize) -> bool {
+        self.segs[seg].live == 0
+    }
+
+    /// Returns the number of segments that the segment writer may write.
+    pub fn nfree(&self) -> usize {
+        (1..NSEG).filter(|seg| self.is_free(*seg)).count()
+    }
+
+    /// Accounts `bytes` written into block `b` at `now`.
+    pub fn write(&mut self, b: BlockNo, bytes: u32, now: u32) {
+        let usage = &mut self.segs[seg_of(b)];
+        usage.live += bytes;
+        assert!(usage.live as usize <= SEGSIZE * BSIZE, "SegTable::write");
+        usage.mtime = now;
+        self.dirty = true;
+    }
+
+    /// Accounts `bytes` of block `b` that died, as the block was deleted or written elsewhere.
+    pub fn delete(&mut self, b: BlockNo, bytes: u32) {
+        let usage = &mut self.segs[seg_of(b)];
+        usage.live = usage
+            .live
+            .checked_sub(bytes)
+            .expect("SegTable::delete: more than live");
+        if usage.live == 0 {
+            self.freed = true;
+        }
+        self.dirty = true;
+    }
+
+    /// Returns the segment most worth cleaning at `now`, skipping empty segments, which are free
+    /// already, and segment `current`, which is being written. Returns None if there is none.
+    pub fn victim(&self, now: u32, current: usize) -> Option<usize> {
+        const SEGBYTES: u64 = (SEGSIZE * BSIZE) as u64;
+        (1..NSEG)
+            .filter(|seg| *seg != current && self.segs[*seg].live > 0)
+            .max_by_key(|seg| {
+                let usage = &self.segs[*seg];
+                let live = usage.live as u64;
+                let age = now.saturating_sub(usage.mtime) as u64 + 1;
+                // (1 - u) * age / (1 + u), scaled by SEGBYTES.
+                (SEGBYTES - live) * age * SEGBYTES / (SEGBYTES + live)
+            })
+    }
+
+    /// Returns whether the checkpoint at `now` should write the table.
+    pub fn checkpoint_due(&self, now: u32) -> bool {
+        self.freed || self.dirty && now.saturating_sub(self.checkpointed) >= SEGTABLE_INTERVAL
+    }
+
+    /// Copies block `i` of the table into `data` for the checkpoint at `now`. Copying the last
+    /// block marks the table clean.
+    pub fn save(&mut self, i: usize, data: &mut [u8; BSIZE], now: u32) {
+        let bytes = self.segs.as_bytes();
+        let from = &bytes[i * BSIZE..cmp::min((i + 1) * BSIZE, bytes.len())];
+        data[..from.len()].copy_from_slice(from);
+        data[from.len()..].fill(0);
+        if i == SEGTABLE_BLOCKS - 1 {
+            self.dirty = false;
+            self.freed = false;
+            self.checkpointed = now;
+        }
+    }
+
+    /// Loads block `i` of the table from the checkpoint in `data`.
+    pub fn load(&mut self, i: usize, data: &[u8; BSIZE]) {
+        let bytes = self.segs.as_bytes_mut();
+        let end = cmp::min((i + 1) * BSIZE, bytes.len());
+        let to = &mut bytes[i * BSIZE..end];
+        let n = to.len();
+        to.copy_from_slice(&data[..n]);
+        self.dirty = false;
+        self.freed = false;
+    }
+}
+
+ktest! {
+    fn segtable_victim(ctx) {
+        let mut table = SegTable::new();
+        let block = |seg: usize| BlockNo::new((seg * SEGSIZE) as u32);
+        ktest_assert!(table.victim(100, 1).is_none());
+        ktest_assert!(table.nfree() == NSEG - 1);
+
+        // Segment 1 is half full of old data, segment 2 is half full of new data, and segment 3
+        // is nearly empty of new data.
+        table.write(block(1), (SEGSIZE * BSIZE / 2) as u32, 0);
+        table.write(block(2), (SEGSIZE * BSIZE / 2) as u32, 90);
+        table.write(block(3), BSIZE as u32, 90);
+        ktest_assert!(table.nfree() == NSEG - 4);
+        ktest_assert!(table.victim(100, 0) == Some(1));
+        ktest_assert!(table.victim(100, 1) == Some(3));
+
+        table.delete(block(3), BSIZE as u32);
+        ktest_assert!(table.live(3) == 0);
+        ktest_assert!(table.victim(100, 1) == Some(2));
+
+        ktest_assert!(table.checkpoint_due(100));
+        let mut data = [0; BSIZE];
+        let mut copy = SegTable::new();
+        for i in 0..SEGTABLE_BLOCKS {
+            table.save(i, &mut data, 100);
+            copy.load(i, &data);
+        }
+        ktest_assert!(!table.checkpoint_due(110));
+        ktest_assert!(copy.live(1) == table.live(1) && copy.live(2) == table.live(2));
+    }
+}
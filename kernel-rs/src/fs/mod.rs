@@ -4,21 +4,28 @@ use bitflags::bitflags;
 
 use crate::{
     arena::{ArenaObject, ArenaRc, ArrayArena},
+    error::KernelError,
     lock::{SleepLock, SpinLock},
     param::NINODE,
     proc::KernelCtx,
-    util::strong_pin::StrongPin,
+    util::{
+        branded::{DevNo, Inum},
+        strong_pin::StrongPin,
+    },
 };
 
 mod lfs;
 mod path;
-mod stat;
+mod perm;
 mod ufs;
+mod vfs;
 
 pub use lfs::Lfs;
 pub use path::{FileName, Path};
-pub use stat::Stat;
+pub use perm::*;
+pub use rv6_abi::stat::Stat;
 pub use ufs::Ufs;
+pub use vfs::{FsInode, FsRef, Vfs};
 
 bitflags! {
     pub struct FcntlFlags: i32 {
@@ -27,6 +34,9 @@ bitflags! {
         const O_RDWR = 0x2;
         const O_CREATE = 0x200;
         const O_TRUNC = 0x400;
+        const O_NONBLOCK = 0x800;
+        const O_APPEND = 0x1000;
+        const O_CLOEXEC = 0x80000;
     }
 }
 
@@ -93,10 +103,10 @@ impl<I> Drop for InodeGuard<'_, I> {
 /// in-memory copy of an inode
 pub struct Inode<I> {
     /// Device number
-    pub dev: u32,
+    pub dev: DevNo,
 
     /// Inode number
-    pub inum: u32,
+    pub inum: Inum,
 
     pub inner: SleepLock<I>,
 }
@@ -116,7 +126,7 @@ where
     type Tx<'s>;
 
     /// Initializes the file system (loading from the disk).
-    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>);
+    fn init(&self, dev: DevNo, ctx: &KernelCtx<'_, '_>);
 
     /// Called for each FS system call.
     fn begin_tx(&self, ctx: &KernelCtx<'_, '_>) -> Self::Tx<'_>;
@@ -130,29 +140,30 @@ where
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Self::InodeInner>, ()>;
+    ) -> Result<RcInode<Self::InodeInner>, KernelError>;
 
     /// Create another name(newname) for the file oldname.
-    /// Returns Ok(()) on success, Err(()) on error.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
     fn link(
         self: StrongPin<'_, Self>,
         inode: RcInode<Self::InodeInner>,
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()>;
+    ) -> Result<(), KernelError>;
 
     /// Remove a file(filename).
-    /// Returns Ok(()) on success, Err(()) on error.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
     fn unlink(
         self: StrongPin<'_, Self>,
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()>;
+    ) -> Result<(), KernelError>;
 
-    /// Create an inode with given type.
-    /// Returns Ok(created inode, result of given function f) on success, Err(()) on error.
+    /// Create an inode with given type, or find the existing file if `typ` is `InodeType::File`.
+    /// `f` gets the locked inode and whether it was created.
+    /// Returns Ok(created inode, result of given function f) on success, Err(KernelError) on error.
     fn create<F, T>(
         self: StrongPin<'_, Self>,
         path: &Path,
@@ -160,26 +171,65 @@ where
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
         f: F,
-    ) -> Result<(RcInode<Self::InodeInner>, T), ()>
+    ) -> Result<(RcInode<Self::InodeInner>, T), KernelError>
     where
-        F: FnOnce(&mut InodeGuard<'_, Self::InodeInner>) -> T;
+        F: FnOnce(&mut InodeGuard<'_, Self::InodeInner>, bool) -> T;
 
     /// Open a file; omode indicate read/write.
-    /// Returns Ok(file descriptor) on success, Err(()) on error.
+    /// Returns Ok(file descriptor) on success, Err(KernelError) on error.
     fn open(
         self: StrongPin<'_, Self>,
         path: &Path,
         omode: FcntlFlags,
         tx: &Self::Tx<'_>,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()>;
+    ) -> Result<usize, KernelError>;
 
     /// Change the current directory.
-    /// Returns Ok(()) on success, Err(()) on error.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
     fn chdir(
         self: StrongPin<'_, Self>,
         inode: RcInode<Self::InodeInner>,
         tx: &Self::Tx<'_>,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<(), ()>;
+    ) -> Result<(), KernelError>;
+
+    /// Change the root directory for absolute paths.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    fn chroot(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        tx: &Self::Tx<'_>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError>;
+
+    /// Change the permission bits of a file. Only its owner may, or a process with `Caps::FOWNER`.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    fn chmod(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        mode: u16,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError>;
+
+    /// Change the owner and the group of a file. Needs `Caps::CHOWN`.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    fn chown(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<Self::InodeInner>,
+        uid: u16,
+        gid: u16,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError>;
+
+    /// Write the absolute path of the current directory into `buf`, without a NUL terminator.
+    /// Returns Ok(length of the path) on success, Err(KernelError) on error.
+    fn getcwd(
+        self: StrongPin<'_, Self>,
+        buf: &mut [u8],
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError>;
 }
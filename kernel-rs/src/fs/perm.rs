@@ -0,0 +1,53 @@
+//! File permissions.
+//!
+//! An inode has an owner, a group, and a mode, whose low nine bits grant read, write, and
+//! execute access to the owner, the group, and everyone else. For a directory, execute access
+//! allows looking up names in it.
+//!
+//! Opening a device file checks its mode like any other file's. A device file gets its mode from
+//! its device's `Devsw` instead of the umask, so that only the devices meant for everyone, like
+//! the console, are open to everyone unless the superuser grants more.
+
+use rv6_abi::stat;
+
+use crate::{cred::Caps, proc::KernelCtx};
+
+/// Mode bit: exec() sets the effective user ID to the file's owner.
+pub const I_SUID: u16 = stat::I_SUID as u16;
+/// Mode bit: exec() sets the effective group ID to the file's group.
+pub const I_SGID: u16 = stat::I_SGID as u16;
+/// All the mode bits that chmod() can set.
+pub const S_IALL: u16 = 0o7777;
+
+/// The initial mode of files, before the umask applies.
+pub const DEFAULT_FILE_MODE: u16 = 0o666;
+/// The initial mode of directories, before the umask applies.
+pub const DEFAULT_DIR_MODE: u16 = 0o777;
+/// The mode of device files of devices that give none, such as a raw disk, to which the umask
+/// does not apply. Only the owner, the superuser who made them, may use them.
+pub const DEFAULT_DEVICE_MODE: u16 = 0o600;
+
+/// Access rights, as the bits of each class in a mode.
+pub const MAY_READ: u16 = 0o4;
+pub const MAY_WRITE: u16 = 0o2;
+pub const MAY_EXEC: u16 = 0o1;
+
+impl KernelCtx<'_, '_> {
+    /// Returns whether the current process has all the rights in `access` to a file with the
+    /// given `mode`, owner `uid`, and group `gid`.
+    /// A process with `Caps::DAC_OVERRIDE` has every right.
+    pub fn may_access(&self, mode: u16, uid: u16, gid: u16, access: u16) -> bool {
+        if self.capable(Caps::DAC_OVERRIDE) {
+            return true;
+        }
+        let cred = &self.proc().deref_data().cred;
+        let granted = if cred.euid() == uid as u32 {
+            mode >> 6
+        } else if cred.egid == gid as u32 {
+            mode >> 3
+        } else {
+            mode
+        };
+        granted & access == access
+    }
+}
@@ -0,0 +1,193 @@
+//! Extents, which map the blocks of a file in runs of consecutive disk blocks instead of one by
+//! one, on a file system with FS_EXTENTS.
+//!
+//! The block addresses of an inode then hold NEXTENT_INLINE extents, followed by the addresses
+//! of NEXTENT_BLOCKS extent blocks, each holding EXTENTS_PER_BLOCK more extents. The extents
+//! map the blocks of the file in order, and end at the first one of length 0. A file only grows
+//! at its end, so a new block extends the last extent if the disk block after it is free, and
+//! starts a new extent otherwise. A file on contiguous blocks thus needs no block but its data,
+//! and bmap reads no block to find its blocks.
+
+use core::{cmp, mem};
+
+use static_assertions::const_assert;
+use zerocopy::{AsBytes, FromBytes};
+
+use super::{InodeInner, UfsTx, MAXFILE, NDIRECT};
+use crate::{fs::InodeGuard, hal::hal, param::BSIZE, proc::KernelCtx, util::branded::BlockNo};
+
+/// Number of extents in the inode.
+const NEXTENT_INLINE: usize = 4;
+
+/// Number of extent blocks, whose addresses follow the extents in the inode.
+const NEXTENT_BLOCKS: usize = 3;
+
+/// Number of extents in an extent block.
+const EXTENTS_PER_BLOCK: usize = BSIZE / mem::size_of::<Extent>();
+
+/// Number of extents of an inode.
+const NEXTENT: usize = NEXTENT_INLINE + NEXTENT_BLOCKS * EXTENTS_PER_BLOCK;
+
+// The extents and the extent block addresses fill the block addresses of the inode, and even a
+// file whose blocks are all apart fits.
+const_assert!(NEXTENT_INLINE * 2 + NEXTENT_BLOCKS == NDIRECT + 1);
+const_assert!(NEXTENT >= MAXFILE);
+
+/// A run of `len` disk blocks from `start`.
+#[repr(C)]
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+struct Extent {
+    start: u32,
+    len: u32,
+}
+
+impl InodeInner {
+    /// Returns the address of extent block `i`, or 0 if there is none.
+    fn extent_block(&self, i: usize) -> u32 {
+        match NEXTENT_INLINE * 2 + i {
+            NDIRECT => self.addr_indirect,
+            j => self.addr_direct[j],
+        }
+    }
+
+    fn set_extent_block(&mut self, i: usize, addr: u32) {
+        match NEXTENT_INLINE * 2 + i {
+            NDIRECT => self.addr_indirect = addr,
+            j => self.addr_direct[j] = addr,
+        }
+    }
+}
+
+impl InodeGuard<'_, InodeInner> {
+    /// Returns extent `i`.
+    fn extent(&self, i: usize, ctx: &KernelCtx<'_, '_>) -> Extent {
+        let inner = self.deref_inner();
+        if i < NEXTENT_INLINE {
+            return Extent {
+                start: inner.addr_direct[2 * i],
+                len: inner.addr_direct[2 * i + 1],
+            };
+        }
+        let i = i - NEXTENT_INLINE;
+        let addr = inner.extent_block(i / EXTENTS_PER_BLOCK);
+        if addr == 0 {
+            return Extent::default();
+        }
+        let bp = hal().disk().read(self.dev, BlockNo::new(addr), ctx);
+        // SAFETY: Extent does not have internal structure.
+        let (prefix, extents, _) = unsafe { bp.deref_inner().data.align_to::<Extent>() };
+        debug_assert_eq!(prefix.len(), 0, "extent: Buf data unaligned");
+        let extent = extents[i % EXTENTS_PER_BLOCK];
+        bp.free(ctx);
+        extent
+    }
+
+    /// Sets extent `i` to `extent`, allocating its extent block if needed.
+    fn set_extent(&mut self, i: usize, extent: Extent, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        if i < NEXTENT_INLINE {
+            let inner = self.deref_inner_mut();
+            inner.addr_direct[2 * i] = extent.start;
+            inner.addr_direct[2 * i + 1] = extent.len;
+            return;
+        }
+        let i = i - NEXTENT_INLINE;
+        let mut addr = self.deref_inner().extent_block(i / EXTENTS_PER_BLOCK);
+        if addr == 0 {
+            addr = tx.balloc(self.dev, ctx).into_u32();
+            self.deref_inner_mut()
+                .set_extent_block(i / EXTENTS_PER_BLOCK, addr);
+        }
+        let mut bp = hal().disk().read(self.dev, BlockNo::new(addr), ctx);
+        // SAFETY: Extent does not have internal structure.
+        let (prefix, extents, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<Extent>() };
+        debug_assert_eq!(prefix.len(), 0, "set_extent: Buf data unaligned");
+        extents[i % EXTENTS_PER_BLOCK] = extent;
+        tx.write(bp, ctx);
+    }
+
+    /// Returns the disk block address of the nth block of the file, given FS_EXTENTS. If there
+    /// is no such block and `tx_opt` is given, allocates one, next to the last block if it can.
+    pub(super) fn bmap_extent(
+        &mut self,
+        bn: usize,
+        tx_opt: Option<&UfsTx<'_>>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> BlockNo {
+        let bn = bn as u32;
+        // The first block of the file that extent i maps.
+        let mut first = 0;
+        let mut last: Option<Extent> = None;
+        for i in 0..NEXTENT {
+            let extent = self.extent(i, ctx);
+            if extent.len == 0 {
+                let tx = tx_opt.expect("bmap: out of range");
+                assert_eq!(bn, first, "bmap: out of range");
+                if let Some(mut last) = last {
+                    let next = BlockNo::new(last.start + last.len);
+                    if tx.balloc_at(self.dev, next, ctx) {
+                        last.len += 1;
+                        self.set_extent(i - 1, last, tx, ctx);
+                        return next;
+                    }
+                }
+                let start = tx.balloc(self.dev, ctx);
+                let extent = Extent {
+                    start: start.into_u32(),
+                    len: 1,
+                };
+                self.set_extent(i, extent, tx, ctx);
+                return start;
+            }
+            if bn < first + extent.len {
+                return BlockNo::new(extent.start + (bn - first));
+            }
+            first += extent.len;
+            last = Some(extent);
+        }
+        panic!("bmap: out of range");
+    }
+
+    /// Frees the blocks of the file from the `nblocks`th on, and the extent blocks that no longer
+    /// hold an extent, given FS_EXTENTS.
+    pub(super) fn shrink_extent(&mut self, nblocks: u32, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        // The first block of the file that extent i maps.
+        let mut first = 0;
+        // Number of extents that still map a block, and number of extents.
+        let mut kept = 0;
+        let mut n = 0;
+        for i in 0..NEXTENT {
+            let mut extent = self.extent(i, ctx);
+            if extent.len == 0 {
+                break;
+            }
+            n = i + 1;
+            let keep = cmp::min(nblocks.saturating_sub(first), extent.len);
+            for b in extent.start + keep..extent.start + extent.len {
+                tx.bfree(self.dev, BlockNo::new(b), ctx);
+            }
+            first += extent.len;
+            if keep > 0 {
+                kept = i + 1;
+                if keep < extent.len {
+                    extent.len = keep;
+                    self.set_extent(i, extent, tx, ctx);
+                }
+            }
+        }
+
+        // Empty the extents past the kept ones, so that the extents end after them, and free the
+        // extent blocks that hold none of them.
+        let blocks_kept =
+            (kept.saturating_sub(NEXTENT_INLINE) + EXTENTS_PER_BLOCK - 1) / EXTENTS_PER_BLOCK;
+        for i in kept..cmp::min(n, NEXTENT_INLINE + blocks_kept * EXTENTS_PER_BLOCK) {
+            self.set_extent(i, Extent::default(), tx, ctx);
+        }
+        for i in blocks_kept..NEXTENT_BLOCKS {
+            let addr = self.deref_inner().extent_block(i);
+            if addr != 0 {
+                tx.bfree(self.dev, BlockNo::new(addr), ctx);
+                self.deref_inner_mut().set_extent_block(i, 0);
+            }
+        }
+    }
+}
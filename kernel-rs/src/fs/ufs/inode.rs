@@ -68,32 +68,45 @@
 //! read or write that inode's ip->valid, ip->size, ip->type, &c.
 
 use core::{
+    cmp,
     iter::StepBy,
     mem,
     ops::{Deref, Range},
     ptr,
 };
 
+/// Directory is a file containing a sequence of Dirent structures.
+pub use rv6_abi::dirent::DIRSIZ;
+use rv6_abi::stat::{T_DEVICE, T_DIR, T_FILE};
 use static_assertions::const_assert;
 use zerocopy::{AsBytes, FromBytes};
 
-use super::{FileName, Path, Stat, UfsTx, IPB, MAXFILE, NDIRECT, NINDIRECT, ROOTINO};
+use super::{
+    quota::size_blocks, FileName, Path, Stat, Superblock, UfsTx, IPB, MAXFILE, MAY_EXEC, NDIRECT,
+    NINDIRECT, ROOTINO,
+};
 use crate::{
     arch::addr::UVAddr,
     arena::{Arena, ArenaObject, ArrayArena},
+    audit::AuditEvent,
     bio::BufData,
+    cred::Caps,
+    error::KernelError,
+    failinject::FaultSite,
     fs::{Inode, InodeGuard, InodeType, Itable, RcInode},
     hal::hal,
+    kmsg::Level,
     lock::{SleepLock, SpinLock},
+    log,
     param::ROOTDEV,
     param::{BSIZE, NINODE},
     proc::KernelCtx,
-    util::strong_pin::StrongPin,
+    util::{
+        branded::{BlockNo, DevNo, Inum},
+        strong_pin::StrongPin,
+    },
 };
 
-/// Directory is a file containing a sequence of Dirent structures.
-pub const DIRSIZ: usize = 14;
-
 /// dirent size
 pub const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
 
@@ -113,6 +126,9 @@ pub struct InodeInner {
     pub typ: InodeType,
     pub nlink: i16,
     pub size: u32,
+    pub mode: u16,
+    pub uid: u16,
+    pub gid: u16,
     pub addr_direct: [u32; NDIRECT],
     pub addr_indirect: u32,
 }
@@ -139,6 +155,17 @@ pub struct Dinode {
     /// Size of file (bytes)
     size: u32,
 
+    /// Permission bits, with I_SUID and I_SGID
+    mode: u16,
+
+    /// Owner's user ID
+    uid: u16,
+
+    /// Group ID
+    gid: u16,
+
+    _unused: u16,
+
     /// Direct data block addresses
     addr_direct: [u32; NDIRECT],
 
@@ -153,12 +180,14 @@ pub struct Dirent {
     name: [u8; DIRSIZ],
 }
 
+const_assert!(DIRENT_SIZE == mem::size_of::<rv6_abi::dirent::Dirent>());
+
 impl Dirent {
     fn new(
         ip: &mut InodeGuard<'_, InodeInner>,
         off: u32,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<Dirent, ()> {
+    ) -> Result<Dirent, KernelError> {
         let mut dirent = Dirent::default();
         ip.read_kernel(&mut dirent, off, ctx)?;
         Ok(dirent)
@@ -199,7 +228,8 @@ impl Iterator for DirentIter<'_, '_, '_> {
 
     fn next(&mut self) -> Option<Self::Item> {
         let off = self.iter.next()?;
-        let dirent = Dirent::new(self.guard, off, self.ctx).expect("DirentIter");
+        // A corrupt directory block ends the directory.
+        let dirent = Dirent::new(self.guard, off, self.ctx).ok()?;
         Some((dirent, off))
     }
 }
@@ -221,59 +251,125 @@ impl InodeGuard<'_, InodeInner> {
     pub fn dirlink(
         &mut self,
         name: &FileName<{ DIRSIZ }>,
-        inum: u32,
+        inum: Inum,
         tx: &UfsTx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
         // Check that name is not present.
         if let Ok((ip, _)) = self.dirlookup(name, ctx) {
             ip.free((tx, ctx));
-            return Err(());
+            return Err(KernelError::Exists);
         };
 
-        // Look for an empty Dirent.
-        let (mut de, off) = self
+        // Look for an empty Dirent, skipping those that hold checksums.
+        let superblock = *ctx.kernel().vfs().ufs().superblock();
+        let (mut de, mut off) = self
             .iter_dirents(ctx)
-            .find(|(de, _)| de.inum == 0)
+            .find(|(de, off)| de.inum == 0 && !superblock.is_checksum_dirent(*off))
             .unwrap_or((Default::default(), self.deref_inner().size));
-        de.inum = inum as _;
+        if superblock.is_checksum_dirent(off) {
+            // Append past the checksum of the last block.
+            self.write_kernel(&Dirent::default(), off, tx, ctx)
+                .expect("dirlink");
+            off += DIRENT_SIZE as u32;
+        }
+        de.inum = inum.into_u32() as _;
         de.set_name(name);
+        ctx.kernel()
+            .vfs()
+            .ufs()
+            .ncache
+            .lock()
+            .remove(self.dev, self.inum, name);
         self.write_kernel(&de, off, tx, ctx).expect("dirlink");
         Ok(())
     }
 
+    /// Empty the directory entry at byte offset `off` of the directory. If no entry follows it,
+    /// also shrink the directory to end after the last entry in use, so that a directory which
+    /// many files come and go through does not keep growing.
+    pub fn dirunlink(&mut self, off: u32, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        self.write_kernel(&Dirent::default(), off, tx, ctx)
+            .expect("dirunlink");
+
+        let superblock = *ctx.kernel().vfs().ufs().superblock();
+        let mut size = self.deref_inner().size;
+        // Keep "." and "..".
+        while size > 2 * DIRENT_SIZE as u32 {
+            let off = size - DIRENT_SIZE as u32;
+            let mut de: Dirent = Default::default();
+            if !superblock.is_checksum_dirent(off) {
+                self.read_kernel(&mut de, off, ctx).expect("dirunlink");
+            }
+            if de.inum != 0 {
+                break;
+            }
+            size = off;
+        }
+        if size < self.deref_inner().size {
+            self.shrink(size, tx, ctx);
+        }
+    }
+
     /// Look for a directory entry in a directory.
     /// If found, return the entry and byte offset of entry.
+    /// Names that it did not find go to the negative lookup cache, which answers for them next time.
     pub fn dirlookup(
         &mut self,
         name: &FileName<{ DIRSIZ }>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<InodeInner>, u32), ()> {
+    ) -> Result<(RcInode<InodeInner>, u32), KernelError> {
         assert_eq!(self.deref_inner().typ, InodeType::Dir, "dirlookup not DIR");
 
-        self.iter_dirents(ctx)
+        let ncache = &ctx.kernel().vfs().ufs().ncache;
+        if ncache.lock().contains(self.dev, self.inum, name) {
+            return Err(KernelError::NoEntry);
+        }
+        let found = self
+            .iter_dirents(ctx)
             .find(|(de, _)| de.inum != 0 && de.get_name() == name)
             .map(|(de, off)| {
                 (
                     ctx.kernel()
-                        .fs()
+                        .vfs()
+                        .ufs()
                         .itable()
-                        .get_inode(self.dev, de.inum as u32),
+                        .get_inode(self.dev, Inum::new(de.inum as u32)),
                     off,
                 )
-            })
-            .ok_or(())
+            });
+        if found.is_none() {
+            ncache.lock().insert(self.dev, self.inum, name);
+        }
+        found.ok_or(KernelError::NoEntry)
     }
 }
 
 impl InodeGuard<'_, InodeInner> {
+    /// Returns Ok(()) if the current process has all the rights in `access` to this inode, or
+    /// Err(KernelError::PermissionDenied) otherwise, which is audited.
+    pub fn check_access(&self, access: u16, ctx: &KernelCtx<'_, '_>) -> Result<(), KernelError> {
+        let inner = self.deref_inner();
+        if ctx.may_access(inner.mode, inner.uid, inner.gid, access) {
+            Ok(())
+        } else {
+            let inum = self.inum.into_u32() as u64;
+            ctx.audit(
+                AuditEvent::Denied,
+                inum,
+                Some(KernelError::PermissionDenied),
+            );
+            Err(KernelError::PermissionDenied)
+        }
+    }
+
     /// Copy a modified in-memory inode to disk.
     /// Must be called after every change to an ip->xxx field
     /// that lives on disk.
     pub fn update(&self, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
         let mut bp = hal().disk().read(
             self.dev,
-            ctx.kernel().fs().superblock().iblock(self.inum),
+            ctx.kernel().vfs().ufs().superblock().iblock(self.inum),
             ctx,
         );
 
@@ -285,7 +381,7 @@ impl InodeGuard<'_, InodeInner> {
         // * dip will not be read.
         let dip = unsafe {
             &mut *(bp.deref_inner_mut().data.as_mut_ptr() as *mut Dinode)
-                .add(self.inum as usize % IPB)
+                .add(self.inum.into_u32() as usize % IPB)
         };
 
         let inner = self.deref_inner();
@@ -314,56 +410,94 @@ impl InodeGuard<'_, InodeInner> {
 
         (*dip).nlink = inner.nlink;
         (*dip).size = inner.size;
+        (*dip).mode = inner.mode;
+        (*dip).uid = inner.uid;
+        (*dip).gid = inner.gid;
         (*dip).addr_direct.copy_from_slice(&inner.addr_direct);
         (*dip).addr_indirect = inner.addr_indirect;
+        ctx.kernel()
+            .vfs()
+            .ufs()
+            .superblock()
+            .seal(&mut bp.deref_inner_mut().data);
         tx.write(bp, ctx);
     }
 
     /// Truncate inode (discard contents).
     /// This function is called with Inode's lock is held.
     pub fn itrunc(&mut self, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        self.shrink(0, tx, ctx);
+    }
+
+    /// Truncates the file to `size` bytes, freeing its blocks past them.
+    /// This function is called with Inode's lock is held.
+    pub fn shrink(&mut self, size: u32, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) {
+        assert!(size <= self.deref_inner().size, "shrink: growing");
+        hal()
+            .pagecache()
+            .invalidate(self.dev, self.inum, hal().kmem());
+        let inner = self.deref_inner();
+        ctx.kernel().vfs().ufs().quota.lock().uncharge(
+            inner.uid,
+            size_blocks(inner.size) - size_blocks(size),
+            0,
+        );
+        let nblocks = (size as usize + BSIZE - 1) / BSIZE;
+        if ctx.kernel().vfs().ufs().superblock().extents() {
+            self.shrink_extent(nblocks as u32, tx, ctx);
+            self.deref_inner_mut().size = size;
+            self.update(tx, ctx);
+            return;
+        }
         let dev = self.dev;
-        for addr in &mut self.deref_inner_mut().addr_direct {
+        for addr in &mut self.deref_inner_mut().addr_direct[cmp::min(nblocks, NDIRECT)..] {
             if *addr != 0 {
-                tx.bfree(dev, *addr, ctx);
+                tx.bfree(dev, BlockNo::new(*addr), ctx);
                 *addr = 0;
             }
         }
 
         if self.deref_inner().addr_indirect != 0 {
-            let mut bp = hal()
-                .disk()
-                .read(dev, self.deref_inner().addr_indirect, ctx);
+            let first = nblocks.saturating_sub(NDIRECT);
+            let mut bp =
+                hal()
+                    .disk()
+                    .read(dev, BlockNo::new(self.deref_inner().addr_indirect), ctx);
             // SAFETY: u32 does not have internal structure.
             let (prefix, data, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<u32>() };
-            debug_assert_eq!(prefix.len(), 0, "itrunc: Buf data unaligned");
-            for a in data {
+            debug_assert_eq!(prefix.len(), 0, "shrink: Buf data unaligned");
+            for a in &mut data[first..] {
                 if *a != 0 {
-                    tx.bfree(dev, *a, ctx);
+                    tx.bfree(dev, BlockNo::new(*a), ctx);
+                    *a = 0;
                 }
             }
-            bp.free(ctx);
-            tx.bfree(dev, self.deref_inner().addr_indirect, ctx);
-            self.deref_inner_mut().addr_indirect = 0
+            if first > 0 {
+                tx.write(bp, ctx);
+            } else {
+                bp.free(ctx);
+                tx.bfree(dev, BlockNo::new(self.deref_inner().addr_indirect), ctx);
+                self.deref_inner_mut().addr_indirect = 0
+            }
         }
 
-        self.deref_inner_mut().size = 0;
+        self.deref_inner_mut().size = size;
         self.update(tx, ctx);
     }
 
     /// Copy data into `dst` from the content of inode at offset `off`.
-    /// Return Ok(()) on success, Err(()) on failure.
+    /// Return Ok(()) on success, Err(KernelError::Io) on a short read or write.
     pub fn read_kernel<T: AsBytes + FromBytes>(
         &mut self,
         dst: &mut T,
         off: u32,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
         let bytes = self.read_bytes_kernel(dst.as_bytes_mut(), off, ctx);
         if bytes == mem::size_of::<T>() {
             Ok(())
         } else {
-            Err(())
+            Err(KernelError::Io)
         }
     }
 
@@ -389,19 +523,22 @@ impl InodeGuard<'_, InodeInner> {
 
     /// Copy data into virtual address `dst` of the current process by `n` bytes
     /// from the content of inode at offset `off`.
-    /// Returns Ok(number of bytes copied) on success, Err(()) on failure due to
-    /// accessing an invalid virtual address.
+    /// Returns Ok(number of bytes copied) on success, Err(KernelError::Fault) on failure due to
+    /// accessing an invalid virtual address, or Err(KernelError::Io) if a fault is injected.
     pub fn read_user(
         &mut self,
         dst: UVAddr,
         off: u32,
         n: u32,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, KernelError> {
         self.read_internal(
             off,
             n,
             |off, src, ctx| {
+                if hal().faults().should_fail(FaultSite::Disk) {
+                    return Err(KernelError::Io);
+                }
                 ctx.proc_mut()
                     .memory_mut()
                     .copy_out_bytes(dst + off as usize, src)
@@ -410,6 +547,43 @@ impl InodeGuard<'_, InodeInner> {
         )
     }
 
+    /// Pass the content of inode from offset `off`, up to `n` bytes, to `f` a block at a time,
+    /// straight from the buffer cache, until `f` takes less than it is given. `f(src, ctx)`
+    /// returns the number of bytes at the front of `src` that it took.
+    /// Returns Ok(number of bytes taken) on success, Err(KernelError) if `f` failed.
+    pub fn read_into<'id, 's, F>(
+        &mut self,
+        off: u32,
+        n: u32,
+        mut f: F,
+        ctx: &mut KernelCtx<'id, 's>,
+    ) -> Result<usize, KernelError>
+    where
+        F: FnMut(&[u8], &mut KernelCtx<'id, 's>) -> Result<usize, KernelError>,
+    {
+        let mut taken = 0;
+        let mut full = false;
+        let res = self.read_internal(
+            off,
+            n,
+            |_, src, ctx| {
+                let m = f(src, ctx)?;
+                taken += m;
+                if m < src.len() {
+                    // Stop reading blocks.
+                    full = true;
+                    return Err(KernelError::TryAgain);
+                }
+                Ok(())
+            },
+            ctx,
+        );
+        match res {
+            Err(_) if full => Ok(taken),
+            res => res,
+        }
+    }
+
     /// Read data from inode.
     ///
     /// `f` takes an offset and a slice as arguments. `f(off, src, ctx)` should copy
@@ -426,14 +600,14 @@ impl InodeGuard<'_, InodeInner> {
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &[u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &[u8], &mut K) -> Result<(), KernelError>,
     >(
         &mut self,
         mut off: u32,
         mut n: u32,
         mut f: F,
         mut k: K,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, KernelError> {
         let inner = self.deref_inner();
         if off > inner.size || off.wrapping_add(n) < off {
             return Ok(0);
@@ -441,11 +615,23 @@ impl InodeGuard<'_, InodeInner> {
         if off + n > inner.size {
             n = inner.size - off;
         }
+        let is_dir = inner.typ == InodeType::Dir;
         let mut tot: u32 = 0;
         while tot < n {
-            let bp = hal()
-                .disk()
-                .read(self.dev, self.bmap(off as usize / BSIZE, &k), &k);
+            let bno = self.bmap(off as usize / BSIZE, &k);
+            let bp = hal().disk().read(self.dev, bno, &k);
+            if is_dir
+                && !k
+                    .kernel()
+                    .vfs()
+                    .ufs()
+                    .superblock()
+                    .verify(&bp.deref_inner().data)
+            {
+                bp.free(&k);
+                report_corrupt(self.dev, bno);
+                return Err(KernelError::Io);
+            }
             let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
             let begin = (off % BSIZE as u32) as usize;
             let end = begin + m as usize;
@@ -459,31 +645,31 @@ impl InodeGuard<'_, InodeInner> {
     }
 
     /// Copy data from `src` into the inode at offset `off`.
-    /// Return Ok(()) on success, Err(()) on failure.
+    /// Return Ok(()) on success, Err(KernelError::Io) on a short read or write.
     pub fn write_kernel<T: AsBytes>(
         &mut self,
         src: &T,
         off: u32,
         tx: &UfsTx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
         let bytes = self.write_bytes_kernel(src.as_bytes(), off, tx, ctx)?;
         if bytes == mem::size_of::<T>() {
             Ok(())
         } else {
-            Err(())
+            Err(KernelError::Io)
         }
     }
 
     /// Copy data from `src` into the inode at offset `off`.
-    /// Returns Ok(number of bytes copied) on success, Err(()) on failure.
+    /// Returns Ok(number of bytes copied) on success, Err(KernelError) on failure.
     pub fn write_bytes_kernel(
         &mut self,
         src: &[u8],
         off: u32,
         tx: &UfsTx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, KernelError> {
         self.write_internal(
             off,
             src.len() as u32,
@@ -498,7 +684,7 @@ impl InodeGuard<'_, InodeInner> {
 
     /// Copy data from virtual address `src` of the current process by `n` bytes
     /// into the inode at offset `off`.
-    /// Returns Ok(number of bytes copied) on success, Err(()) on failure.
+    /// Returns Ok(number of bytes copied) on success, Err(KernelError) on failure.
     pub fn write_user(
         &mut self,
         src: UVAddr,
@@ -506,11 +692,15 @@ impl InodeGuard<'_, InodeInner> {
         n: u32,
         ctx: &mut KernelCtx<'_, '_>,
         tx: &UfsTx<'_>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, KernelError> {
         self.write_internal(
             off,
             n,
             |off, dst, ctx| {
+                // An injected fault stops the write short, as a failed copy does.
+                if hal().faults().should_fail(FaultSite::Disk) {
+                    return Err(KernelError::Io);
+                }
                 ctx.proc_mut()
                     .memory_mut()
                     .copy_in_bytes(dst, src + off as usize)
@@ -520,9 +710,26 @@ impl InodeGuard<'_, InodeInner> {
         )
     }
 
+    /// Fill `n` bytes of inode from offset `off` with `f` a block at a time, straight into the
+    /// buffer cache. `f(dst, ctx)` fills all of `dst`, or fails to stop the write.
+    /// Returns Ok(number of bytes written) on success, Err(KernelError) if `f` failed at once.
+    pub fn write_from<'id, 's, F>(
+        &mut self,
+        off: u32,
+        n: u32,
+        mut f: F,
+        tx: &UfsTx<'_>,
+        ctx: &mut KernelCtx<'id, 's>,
+    ) -> Result<usize, KernelError>
+    where
+        F: FnMut(&mut [u8], &mut KernelCtx<'id, 's>) -> Result<(), KernelError>,
+    {
+        self.write_internal(off, n, |_, dst, ctx| f(dst, ctx), tx, ctx)
+    }
+
     /// Write data to inode. Returns the number of bytes successfully written.
     /// If the return value is less than the requested n, there was an error of
-    /// some kind.
+    /// some kind. If no byte was written, the error is returned instead.
     ///
     /// `f` takes an offset and a slice as arguments. `f(off, dst)` should copy
     /// the content beginning at the `off`th byte of the source, which the
@@ -538,7 +745,7 @@ impl InodeGuard<'_, InodeInner> {
         'id,
         's,
         K: Deref<Target = KernelCtx<'id, 's>>,
-        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), ()>,
+        F: FnMut(u32, &mut [u8], &mut K) -> Result<(), KernelError>,
     >(
         &mut self,
         mut off: u32,
@@ -546,29 +753,57 @@ impl InodeGuard<'_, InodeInner> {
         mut f: F,
         tx: &UfsTx<'_>,
         mut k: K,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, KernelError> {
         if off > self.deref_inner().size {
-            return Err(());
+            return Err(KernelError::InvalidArgument);
         }
-        if off.checked_add(n).ok_or(())? as usize > MAXFILE * BSIZE {
-            return Err(());
+        if off.checked_add(n).ok_or(KernelError::FileTooLarge)? as usize > MAXFILE * BSIZE {
+            return Err(KernelError::FileTooLarge);
         }
+        // exec must not map the old content of the file anymore.
+        hal()
+            .pagecache()
+            .invalidate(self.dev, self.inum, hal().kmem());
+        let is_dir = self.deref_inner().typ == InodeType::Dir;
+        let superblock = *k.kernel().vfs().ufs().superblock();
+
+        // Charge the new blocks up front, and refund those that a short write leaves unused.
+        let uid = self.deref_inner().uid;
+        let old_blocks = size_blocks(self.deref_inner().size);
+        let charged = size_blocks(cmp::max(off + n, self.deref_inner().size)) - old_blocks;
+        k.kernel().vfs().ufs().quota.lock().charge(
+            uid,
+            charged,
+            0,
+            !is_dir && !k.capable(Caps::SYS_RESOURCE),
+            k.kernel().clocks().ticks(),
+        )?;
+
         let mut tot: u32 = 0;
+        let mut err = None;
         while tot < n {
-            let mut bp = hal().disk().read(
-                self.dev,
-                self.bmap_or_alloc(off as usize / BSIZE, tx, &k),
-                &k,
-            );
+            // Blocks past the end of the file are new, and have no checksums yet.
+            let existing = off - off % (BSIZE as u32) < self.deref_inner().size;
+            let bno = self.bmap_or_alloc(off as usize / BSIZE, tx, &k);
+            let mut bp = hal().disk().read(self.dev, bno, &k);
+            if is_dir && existing && !superblock.verify(&bp.deref_inner().data) {
+                bp.free(&k);
+                report_corrupt(self.dev, bno);
+                err = Some(KernelError::Io);
+                break;
+            }
             let m = core::cmp::min(n - tot, BSIZE as u32 - off % BSIZE as u32);
             let begin = (off % BSIZE as u32) as usize;
             let end = begin + m as usize;
-            if f(tot, &mut bp.deref_inner_mut().data[begin..end], &mut k).is_ok() {
-                tx.write(bp, &k);
-            } else {
+            if let Err(e) = f(tot, &mut bp.deref_inner_mut().data[begin..end], &mut k) {
                 bp.free(&k);
+                err = Some(e);
                 break;
             }
+            if is_dir {
+                superblock.seal(&mut bp.deref_inner_mut().data);
+            }
+            tx.write(bp, &k);
             tot += m;
             off += m;
         }
@@ -576,12 +811,22 @@ impl InodeGuard<'_, InodeInner> {
         if off > self.deref_inner().size {
             self.deref_inner_mut().size = off;
         }
+        let used = size_blocks(self.deref_inner().size) - old_blocks;
+        k.kernel()
+            .vfs()
+            .ufs()
+            .quota
+            .lock()
+            .uncharge(uid, charged - used, 0);
 
         // Write the i-node back to disk even if the size didn't change
         // because the loop above might have called bmap() and added a new
         // block to self->addrs[].
         self.update(tx, &k);
-        Ok(tot as usize)
+        match err {
+            Some(e) if tot == 0 => Err(e),
+            _ => Ok(tot as usize),
+        }
     }
 
     /// Inode content
@@ -589,14 +834,15 @@ impl InodeGuard<'_, InodeInner> {
     /// The content (data) associated with each inode is stored
     /// in blocks on the disk. The first NDIRECT block numbers
     /// are listed in self->addrs[].  The next NINDIRECT blocks are
-    /// listed in block self->addr_indirect.
+    /// listed in block self->addr_indirect. On a file system with
+    /// FS_EXTENTS, extents map the blocks instead (see extent.rs).
     /// Return the disk block address of the nth block in inode self.
     /// If there is no such block, bmap allocates one.
-    fn bmap_or_alloc(&mut self, bn: usize, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) -> u32 {
+    fn bmap_or_alloc(&mut self, bn: usize, tx: &UfsTx<'_>, ctx: &KernelCtx<'_, '_>) -> BlockNo {
         self.bmap_internal(bn, Some(tx), ctx)
     }
 
-    fn bmap(&mut self, bn: usize, ctx: &KernelCtx<'_, '_>) -> u32 {
+    fn bmap(&mut self, bn: usize, ctx: &KernelCtx<'_, '_>) -> BlockNo {
         self.bmap_internal(bn, None, ctx)
     }
 
@@ -605,39 +851,75 @@ impl InodeGuard<'_, InodeInner> {
         bn: usize,
         tx_opt: Option<&UfsTx<'_>>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> u32 {
+    ) -> BlockNo {
+        if ctx.kernel().vfs().ufs().superblock().extents() {
+            return self.bmap_extent(bn, tx_opt, ctx);
+        }
         let inner = self.deref_inner();
 
         if bn < NDIRECT {
             let mut addr = inner.addr_direct[bn];
             if addr == 0 {
-                addr = tx_opt.expect("bmap: out of range").balloc(self.dev, ctx);
+                addr = tx_opt
+                    .expect("bmap: out of range")
+                    .balloc(self.dev, ctx)
+                    .into_u32();
                 self.deref_inner_mut().addr_direct[bn] = addr;
             }
-            addr
+            BlockNo::new(addr)
         } else {
             let bn = bn - NDIRECT;
             assert!(bn < NINDIRECT, "bmap: out of range");
 
             let mut indirect = inner.addr_indirect;
             if indirect == 0 {
-                indirect = tx_opt.expect("bmap: out of range").balloc(self.dev, ctx);
+                indirect = tx_opt
+                    .expect("bmap: out of range")
+                    .balloc(self.dev, ctx)
+                    .into_u32();
                 self.deref_inner_mut().addr_indirect = indirect;
             }
 
-            let mut bp = hal().disk().read(self.dev, indirect, ctx);
+            let mut bp = hal().disk().read(self.dev, BlockNo::new(indirect), ctx);
             let (prefix, data, _) = unsafe { bp.deref_inner_mut().data.align_to_mut::<u32>() };
             debug_assert_eq!(prefix.len(), 0, "bmap: Buf data unaligned");
             let mut addr = data[bn];
             if addr == 0 {
                 let tx = tx_opt.expect("bmap: out of range");
-                addr = tx.balloc(self.dev, ctx);
+                addr = tx.balloc(self.dev, ctx).into_u32();
                 data[bn] = addr;
                 tx.write(bp, ctx);
             } else {
                 bp.free(ctx);
             }
-            addr
+            BlockNo::new(addr)
+        }
+    }
+
+    /// Queues the blocks `blocks` of the file, up to the end of the file, to be read into the
+    /// buffer cache by the workers of the workqueue. Blocks that find the queue full are not read.
+    pub fn read_ahead(&mut self, blocks: Range<u32>, ctx: &KernelCtx<'_, '_>) {
+        let size = self.deref_inner().size;
+        for bn in blocks.take_while(|bn| (*bn as usize * BSIZE) < size as usize) {
+            let bno = self.bmap(bn as usize, ctx);
+            let arg = (self.dev.into_u32() as usize) << 32 | bno.into_u32() as usize;
+            if ctx
+                .kernel()
+                .workqueue()
+                .queue(read_block, arg, ctx.kernel())
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    /// Drops the blocks `blocks` of the file from the buffer cache, except those in use.
+    pub fn evict_blocks(&mut self, blocks: Range<u32>, ctx: &KernelCtx<'_, '_>) {
+        let size = self.deref_inner().size;
+        for bn in blocks.take_while(|bn| (*bn as usize * BSIZE) < size as usize) {
+            let bno = self.bmap(bn as usize, ctx);
+            ctx.kernel().bcache().evict(self.dev, bno, ctx);
         }
     }
 
@@ -655,6 +937,57 @@ impl InodeGuard<'_, InodeInner> {
     }
 }
 
+/// Logs that block `bno` of `dev` does not match its checksum.
+/// Calls `f` with the owner and the size of each allocated inode of `dev`, skipping the inodes of
+/// corrupt blocks.
+pub fn scan_inodes<F: FnMut(u16, u32)>(
+    dev: DevNo,
+    superblock: &Superblock,
+    ctx: &KernelCtx<'_, '_>,
+    mut f: F,
+) {
+    for first in num_iter::range_step(0, superblock.ninodes, IPB as u32) {
+        let bno = superblock.iblock(Inum::new(first));
+        let bp = hal().disk().read(dev, bno, ctx);
+        if !superblock.verify(&bp.deref_inner().data) {
+            bp.free(ctx);
+            report_corrupt(dev, bno);
+            continue;
+        }
+        for i in 0..cmp::min(IPB as u32, superblock.ninodes - first) {
+            // SAFETY: dip is inside bp.data.
+            let dip = unsafe { (bp.deref_inner().data.as_ptr() as *const Dinode).add(i as usize) };
+            // SAFETY: i16 does not have internal structure.
+            let t = unsafe { *(dip as *const i16) };
+            if t <= 0 || t >= core::mem::variant_count::<DInodeType>() as i16 {
+                continue;
+            }
+            // SAFETY: dip is aligned properly and t < #(variants of DInodeType).
+            let dip = unsafe { &*dip };
+            f(dip.uid, dip.size);
+        }
+        bp.free(ctx);
+    }
+}
+
+/// Reads a block into the buffer cache, for `InodeGuard::read_ahead`. The upper half of `arg` is
+/// the device, and the lower half is the block number.
+fn read_block(ctx: &KernelCtx<'_, '_>, arg: usize) {
+    let dev = DevNo::new((arg >> 32) as u32);
+    let bno = BlockNo::new(arg as u32);
+    hal().disk().read(dev, bno, ctx).free(ctx);
+}
+
+fn report_corrupt(dev: DevNo, bno: BlockNo) {
+    log!(
+        Level::Error,
+        "fs::ufs",
+        "ufs: checksum mismatch in block {} of dev {}",
+        bno.into_u32(),
+        dev.into_u32()
+    );
+}
+
 impl const Default for Inode<InodeInner> {
     fn default() -> Self {
         Self::new()
@@ -681,8 +1014,11 @@ impl ArenaObject for Inode<InodeInner> {
             let mut ip = self.lock(ctx);
 
             ip.itrunc(tx, ctx);
+            let uid = ip.deref_inner().uid;
+            ctx.kernel().vfs().ufs().quota.lock().uncharge(uid, 0, 1);
             ip.deref_inner_mut().typ = InodeType::None;
             ip.update(tx, ctx);
+            tx.ifree(ip.dev, ip.inum, ctx);
             ip.deref_inner_mut().valid = false;
 
             ip.free(ctx);
@@ -696,16 +1032,23 @@ impl Inode<InodeInner> {
     pub fn lock(&self, ctx: &KernelCtx<'_, '_>) -> InodeGuard<'_, InodeInner> {
         let mut guard = self.inner.lock(ctx);
         if !guard.valid {
-            let mut bp = hal().disk().read(
-                self.dev,
-                ctx.kernel().fs().superblock().iblock(self.inum),
-                ctx,
-            );
+            let bno = ctx.kernel().vfs().ufs().superblock().iblock(self.inum);
+            let mut bp = hal().disk().read(self.dev, bno, ctx);
+            if !ctx
+                .kernel()
+                .vfs()
+                .ufs()
+                .superblock()
+                .verify(&bp.deref_inner().data)
+            {
+                report_corrupt(self.dev, bno);
+                panic!("Inode::lock: inode {} is corrupt", self.inum.into_u32());
+            }
 
             // SAFETY: dip is inside bp.data.
             let dip = unsafe {
                 (bp.deref_inner_mut().data.as_mut_ptr() as *mut Dinode)
-                    .add(self.inum as usize % IPB)
+                    .add(self.inum.into_u32() as usize % IPB)
             };
             // SAFETY: i16 does not have internal structure.
             let t = unsafe { *(dip as *const i16) };
@@ -727,6 +1070,9 @@ impl Inode<InodeInner> {
             }
             guard.nlink = dip.nlink;
             guard.size = dip.size;
+            guard.mode = dip.mode;
+            guard.uid = dip.uid;
+            guard.gid = dip.gid;
             guard.addr_direct.copy_from_slice(&dip.addr_direct);
             guard.addr_indirect = dip.addr_indirect;
             bp.free(ctx);
@@ -739,8 +1085,8 @@ impl Inode<InodeInner> {
 
     pub const fn new() -> Self {
         Self {
-            dev: 0,
-            inum: 0,
+            dev: DevNo::new(0),
+            inum: Inum::new(0),
             inner: SleepLock::new(
                 "inode",
                 InodeInner {
@@ -748,6 +1094,9 @@ impl Inode<InodeInner> {
                     typ: InodeType::None,
                     nlink: 0,
                     size: 0,
+                    mode: 0,
+                    uid: 0,
+                    gid: 0,
                     addr_direct: [0; NDIRECT],
                     addr_indirect: 0,
                 },
@@ -759,17 +1108,19 @@ impl Inode<InodeInner> {
     pub fn stat(&self, ctx: &KernelCtx<'_, '_>) -> Stat {
         let inner = self.inner.lock(ctx);
         let st = Stat {
-            dev: self.dev as i32,
-            ino: self.inum,
-            typ: match inner.typ {
+            dev: self.dev.into_u32() as i32,
+            ino: self.inum.into_u32(),
+            r#type: match inner.typ {
                 InodeType::None => 0,
-                InodeType::Dir => 1,
-                InodeType::File => 2,
-                InodeType::Device { .. } => 3,
+                InodeType::Dir => T_DIR,
+                InodeType::File => T_FILE,
+                InodeType::Device { .. } => T_DEVICE,
             },
             nlink: inner.nlink,
-            _padding: 0,
-            size: inner.size as usize,
+            mode: inner.mode as u32,
+            size: inner.size as u64,
+            uid: inner.uid as u32,
+            gid: inner.gid as u32,
         };
         inner.free(ctx);
         st
@@ -778,14 +1129,18 @@ impl Inode<InodeInner> {
 
 impl Itable<InodeInner> {
     pub const fn new_itable() -> Self {
-        SpinLock::new("ITABLE", ArrayArena::<Inode<InodeInner>, NINODE>::new())
+        SpinLock::new(
+            "ITABLE",
+            ArrayArena::<Inode<InodeInner>, NINODE>::new("itable"),
+        )
     }
 
     /// Find the inode with number inum on device dev
     /// and return the in-memory copy. Does not lock
     /// the inode and does not read it from disk.
-    pub fn get_inode(self: StrongPin<'_, Self>, dev: u32, inum: u32) -> RcInode<InodeInner> {
-        self.find_or_alloc(
+    pub fn get_inode(self: StrongPin<'_, Self>, dev: DevNo, inum: Inum) -> RcInode<InodeInner> {
+        self.find_or_alloc_keyed(
+            &(dev, inum),
             |inode| inode.dev == dev && inode.inum == inum,
             |inode| {
                 inode.dev = dev;
@@ -793,29 +1148,41 @@ impl Itable<InodeInner> {
                 inode.inner.get_mut().valid = false;
             },
         )
-        .expect("[Itable::get_inode] no inodes")
+        .unwrap_or_else(|| panic!("[Itable::get_inode] no inodes ({})", self.stats()))
     }
 
     /// Allocate an inode on device dev.
-    /// Mark it as allocated by giving it type.
+    /// Mark it as allocated in the inode bit map, and by giving it type.
     /// Returns an unlocked but allocated and referenced inode.
     pub fn alloc_inode(
         self: StrongPin<'_, Self>,
-        dev: u32,
+        dev: DevNo,
         typ: InodeType,
         tx: &UfsTx<'_>,
         ctx: &KernelCtx<'_, '_>,
     ) -> RcInode<InodeInner> {
-        for inum in 1..ctx.kernel().fs().superblock().ninodes {
-            let mut bp = hal()
-                .disk()
-                .read(dev, ctx.kernel().fs().superblock().iblock(inum), ctx);
+        let superblock = *ctx.kernel().vfs().ufs().superblock();
+        while let Some(inum) = tx.ialloc(dev, ctx) {
+            // mkfs marks the inodes that hold checksums allocated.
+            assert!(
+                !superblock.is_checksum_inode(inum),
+                "alloc_inode: checksum inode"
+            );
+            let bno = superblock.iblock(inum);
+            let mut bp = hal().disk().read(dev, bno, ctx);
+            if !superblock.verify(&bp.deref_inner().data) {
+                // Leave the inodes of a corrupt block alone, marked allocated.
+                bp.free(ctx);
+                report_corrupt(dev, bno);
+                continue;
+            }
 
             const_assert!(IPB <= mem::size_of::<BufData>() / mem::size_of::<Dinode>());
             const_assert!(mem::align_of::<BufData>() % mem::align_of::<Dinode>() == 0);
             // SAFETY: dip is inside bp.data.
             let dip = unsafe {
-                (bp.deref_inner_mut().data.as_mut_ptr() as *mut Dinode).add(inum as usize % IPB)
+                (bp.deref_inner_mut().data.as_mut_ptr() as *mut Dinode)
+                    .add(inum.into_u32() as usize % IPB)
             };
             // SAFETY: i16 does not have internal structure.
             let t = unsafe { *(dip as *const i16) };
@@ -824,26 +1191,23 @@ impl Itable<InodeInner> {
             // SAFETY: dip is aligned properly and t < #(variants of DInodeType).
             let dip = unsafe { &mut *dip };
 
-            // a free inode
-            if dip.typ == DInodeType::None {
-                unsafe { ptr::write_bytes(dip as _, 0, 1) };
-                match typ {
-                    InodeType::None => dip.typ = DInodeType::None,
-                    InodeType::Dir => dip.typ = DInodeType::Dir,
-                    InodeType::File => dip.typ = DInodeType::File,
-                    InodeType::Device { major, minor } => {
-                        dip.typ = DInodeType::Device;
-                        dip.major = major;
-                        dip.minor = minor
-                    }
+            assert_eq!(dip.typ, DInodeType::None, "alloc_inode: inode in use");
+            unsafe { ptr::write_bytes(dip as _, 0, 1) };
+            match typ {
+                InodeType::None => dip.typ = DInodeType::None,
+                InodeType::Dir => dip.typ = DInodeType::Dir,
+                InodeType::File => dip.typ = DInodeType::File,
+                InodeType::Device { major, minor } => {
+                    dip.typ = DInodeType::Device;
+                    dip.major = major;
+                    dip.minor = minor
                 }
-
-                // mark it allocated on the disk
-                tx.write(bp, ctx);
-                return self.get_inode(dev, inum);
-            } else {
-                bp.free(ctx);
             }
+
+            // mark it allocated on the disk
+            superblock.seal(&mut bp.deref_inner_mut().data);
+            tx.write(bp, ctx);
+            return self.get_inode(dev, inum);
         }
         panic!("[Itable::alloc_inode] no inodes");
     }
@@ -857,7 +1221,7 @@ impl Itable<InodeInner> {
         path: &Path,
         tx: &UfsTx<'_>,
         proc: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<InodeInner>, ()> {
+    ) -> Result<RcInode<InodeInner>, KernelError> {
         Ok(self.namex(path, false, tx, proc)?.0)
     }
 
@@ -866,39 +1230,133 @@ impl Itable<InodeInner> {
         path: &'s Path,
         tx: &UfsTx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<InodeInner>, &'s FileName<{ DIRSIZ }>), ()> {
+    ) -> Result<(RcInode<InodeInner>, &'s FileName<{ DIRSIZ }>), KernelError> {
         let (ip, name_in_path) = self.namex(path, true, tx, ctx)?;
-        let name_in_path = name_in_path.ok_or(())?;
+        let name_in_path = name_in_path.ok_or(KernelError::NoEntry)?;
         Ok((ip, name_in_path))
     }
 
+    /// Writes the absolute path of the directory `dir` into `buf`, without a NUL terminator.
+    /// Since directories do not record their names, walks up through ".." entries and looks
+    /// for each directory's entry in its parent, until reaching the process's root directory.
+    /// Returns Ok(length of the path) on success, Err(KernelError) on error.
+    pub fn path_of(
+        self: StrongPin<'_, Self>,
+        dir: &RcInode<InodeInner>,
+        buf: &mut [u8],
+        tx: &UfsTx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        // The path is built backwards from the end of `buf`.
+        let mut start = buf.len();
+        let top = ctx
+            .proc()
+            .deref_data()
+            .root
+            .as_ref()
+            .map(|root| root.inum());
+        let mut ptr = dir.clone();
+        while ptr.inum != ROOTINO && Some(ptr.inum) != top {
+            let mut ip = ptr.lock(ctx);
+            // SAFETY: b".." does not contain any NUL characters.
+            let parent = ip.dirlookup(unsafe { FileName::from_bytes(b"..") }, ctx);
+            ip.free(ctx);
+            let inum = ptr.inum;
+            ptr.free((tx, ctx));
+            ptr = parent?.0;
+
+            let mut name = [0; DIRSIZ];
+            let mut dp = ptr.lock(ctx);
+            let len = dp
+                .iter_dirents(ctx)
+                .find(|(de, _)| {
+                    de.inum as u32 == inum.into_u32()
+                        && !matches!(de.get_name().as_bytes(), b"." | b"..")
+                })
+                .map(|(de, _)| {
+                    let de_name = de.get_name().as_bytes();
+                    name[..de_name.len()].copy_from_slice(de_name);
+                    de_name.len()
+                });
+            dp.free(ctx);
+            let res = match len {
+                // The directory was removed if its parent has no entry for it.
+                None => Err(KernelError::NoEntry),
+                Some(len) if len >= start => Err(KernelError::Range),
+                Some(len) => Ok(len),
+            };
+            let len = match res {
+                Ok(len) => len,
+                Err(e) => {
+                    ptr.free((tx, ctx));
+                    return Err(e);
+                }
+            };
+            buf[start - len..start].copy_from_slice(&name[..len]);
+            start -= len + 1;
+            buf[start] = b'/';
+        }
+        ptr.free((tx, ctx));
+
+        if start == buf.len() {
+            // `dir` is the root.
+            if buf.is_empty() {
+                return Err(KernelError::Range);
+            }
+            start -= 1;
+            buf[start] = b'/';
+        }
+        let len = buf.len() - start;
+        buf.copy_within(start.., 0);
+        Ok(len)
+    }
+
     fn namex<'s>(
         self: StrongPin<'_, Self>,
         mut path: &'s Path,
         parent: bool,
         tx: &UfsTx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(RcInode<InodeInner>, Option<&'s FileName<{ DIRSIZ }>>), ()> {
+    ) -> Result<(RcInode<InodeInner>, Option<&'s FileName<{ DIRSIZ }>>), KernelError> {
+        // The directories of the process are on this file system, as it is the only one mounted.
+        let root = ctx.proc().deref_data().root.as_ref();
+        let root = root
+            .map(|root| root.as_ufs().ok_or(KernelError::CrossDevice))
+            .transpose()?;
         let mut ptr = if path.is_absolute() {
-            self.root()
+            root.map_or_else(|| self.root(), |root| root.clone())
         } else {
-            ctx.proc().cwd().clone()
+            let cwd = ctx.proc().cwd().as_ufs().ok_or(KernelError::CrossDevice)?;
+            cwd.clone()
         };
 
         while let Some((new_path, name)) = path.skipelem() {
             path = new_path;
 
+            // ".." of the process's root directory is itself, as for the file system's root.
+            if name.as_bytes() == b".."
+                && root.map_or(false, |root| root.inum == ptr.inum)
+                && !(parent && path.is_empty_string())
+            {
+                continue;
+            }
+
             let mut ip = ptr.lock(ctx);
             if ip.deref_inner().typ != InodeType::Dir {
                 ip.free(ctx);
                 ptr.free((tx, ctx));
-                return Err(());
+                return Err(KernelError::NotDir);
             }
             if parent && path.is_empty_string() {
                 // Stop one level early.
                 ip.free(ctx);
                 return Ok((ptr, Some(name)));
             }
+            if let Err(e) = ip.check_access(MAY_EXEC, ctx) {
+                ip.free(ctx);
+                ptr.free((tx, ctx));
+                return Err(e);
+            }
             let next = ip.dirlookup(name, ctx);
             ip.free(ctx);
             ptr.free((tx, ctx));
@@ -906,7 +1364,7 @@ impl Itable<InodeInner> {
         }
         if parent {
             ptr.free((tx, ctx));
-            return Err(());
+            return Err(KernelError::NoEntry);
         }
         Ok((ptr, None))
     }
@@ -32,11 +32,12 @@ use crate::{
     lock::SleepableLock,
     param::{BSIZE, LOGSIZE, MAXOPBLOCKS},
     proc::KernelCtx,
+    util::branded::{BlockNo, DevNo},
 };
 
 pub struct Log {
-    dev: u32,
-    start: i32,
+    dev: DevNo,
+    start: BlockNo,
     size: i32,
 
     /// How many FS sys calls are executing?
@@ -56,7 +57,7 @@ struct LogHeader {
 }
 
 impl Log {
-    pub fn new(dev: u32, start: i32, size: i32, ctx: &KernelCtx<'_, '_>) -> Self {
+    pub fn new(dev: DevNo, start: BlockNo, size: i32, ctx: &KernelCtx<'_, '_>) -> Self {
         let mut log = Self {
             dev,
             start,
@@ -76,9 +77,7 @@ impl Log {
 
         for (tail, dbuf) in self.bufs.drain(..).enumerate() {
             // Read log block.
-            let lbuf = hal()
-                .disk()
-                .read(dev, (start + tail as i32 + 1) as u32, ctx);
+            let lbuf = hal().disk().read(dev, start + (tail as u32 + 1), ctx);
 
             // Read dst.
             let mut dbuf = dbuf.lock(ctx);
@@ -98,7 +97,7 @@ impl Log {
 
     /// Read the log header from disk into the in-memory log header.
     fn read_head(&mut self, ctx: &KernelCtx<'_, '_>) {
-        let mut buf = hal().disk().read(self.dev, self.start as u32, ctx);
+        let mut buf = hal().disk().read(self.dev, self.start, ctx);
 
         const_assert!(mem::size_of::<LogHeader>() <= BSIZE);
         const_assert!(mem::align_of::<BufData>() % mem::align_of::<LogHeader>() == 0);
@@ -111,7 +110,10 @@ impl Log {
         buf.free(ctx);
 
         for b in &lh.block[0..lh.n as usize] {
-            let buf = hal().disk().read(self.dev, *b, ctx).unlock(ctx);
+            let buf = hal()
+                .disk()
+                .read(self.dev, BlockNo::new(*b), ctx)
+                .unlock(ctx);
             self.bufs.push(buf);
         }
     }
@@ -120,7 +122,7 @@ impl Log {
     /// This is the true point at which the
     /// current transaction commits.
     fn write_head(&mut self, ctx: &KernelCtx<'_, '_>) {
-        let mut buf = hal().disk().read(self.dev, self.start as u32, ctx);
+        let mut buf = hal().disk().read(self.dev, self.start, ctx);
 
         const_assert!(mem::size_of::<LogHeader>() <= BSIZE);
         const_assert!(mem::align_of::<BufData>() % mem::align_of::<LogHeader>() == 0);
@@ -133,7 +135,7 @@ impl Log {
 
         lh.n = self.bufs.len() as u32;
         for (db, b) in izip!(&mut lh.block, &self.bufs) {
-            *db = b.blockno;
+            *db = b.blockno.into_u32();
         }
         hal().disk().write(&mut buf, ctx);
         buf.free(ctx);
@@ -155,7 +157,7 @@ impl Log {
             // Log block.
             let mut to = hal()
                 .disk()
-                .read(self.dev, (self.start + tail as i32 + 1) as u32, ctx);
+                .read(self.dev, self.start + (tail as u32 + 1), ctx);
 
             // Cache block.
             let from = hal().disk().read(self.dev, from.blockno, ctx);
@@ -15,31 +15,48 @@ use core::cell::UnsafeCell;
 use core::{cmp, mem};
 
 use pin_project::pin_project;
+use rv6_abi::quota::Dqblk;
 use spin::Once;
 
 use self::log::Log;
-use super::{FcntlFlags, FileName, FileSystem, InodeGuard, InodeType, Itable, Path, RcInode, Stat};
-use crate::util::strong_pin::StrongPin;
+use self::ncache::NegativeCache;
+use self::quota::{size_blocks, QuotaTable};
+use super::{
+    FcntlFlags, FileName, FileSystem, FsInode, InodeGuard, InodeType, Itable, Path, RcInode, Stat,
+    DEFAULT_DEVICE_MODE, DEFAULT_DIR_MODE, DEFAULT_FILE_MODE, I_SGID, I_SUID, MAY_EXEC, MAY_READ,
+    MAY_WRITE, S_IALL,
+};
+use crate::util::{
+    branded::{BlockNo, DevNo, Inum},
+    strong_pin::StrongPin,
+};
 use crate::{
+    arena::{Arena, ArenaStats},
+    audit::AuditEvent,
     bio::Buf,
-    file::{FileType, InodeFileType},
+    cred::Caps,
+    error::KernelError,
+    file::{FileType, InodeFileType, Readahead},
     hal::hal,
-    lock::SleepableLock,
+    lock::{SleepableLock, SpinLock},
     param::BSIZE,
     proc::KernelCtx,
 };
 
+mod extent;
 mod inode;
 mod log;
+mod ncache;
+mod quota;
 mod superblock;
 
 pub use inode::{Dinode, Dirent, InodeInner, DIRENT_SIZE, DIRSIZ};
 pub use superblock::{Superblock, BPB, IPB};
 
 /// root i-number
-const ROOTINO: u32 = 1;
+const ROOTINO: Inum = Inum::new(1);
 
-const NDIRECT: usize = 12;
+const NDIRECT: usize = 10;
 const NINDIRECT: usize = BSIZE.wrapping_div(mem::size_of::<u32>());
 const MAXFILE: usize = NDIRECT.wrapping_add(NINDIRECT);
 
@@ -49,6 +66,8 @@ pub struct Ufs {
     /// There should be one superblock per disk device, but we run with only one device.
     superblock: Once<Superblock>,
     log: Once<SleepableLock<Log>>,
+    ncache: SpinLock<NegativeCache>,
+    quota: SpinLock<QuotaTable>,
     #[pin]
     itable: Itable<InodeInner>,
 }
@@ -58,17 +77,29 @@ impl FileSystem for Ufs {
     type InodeInner = InodeInner;
     type Tx<'s> = UfsTx<'s>;
 
-    fn init(&self, dev: u32, ctx: &KernelCtx<'_, '_>) {
+    fn init(&self, dev: DevNo, ctx: &KernelCtx<'_, '_>) {
         if !self.superblock.is_completed() {
-            let buf = hal().disk().read(dev, 1, ctx);
+            let buf = hal().disk().read(dev, BlockNo::new(1), ctx);
             let superblock = self.superblock.call_once(|| Superblock::new(&buf));
             buf.free(ctx);
             let _ = self.log.call_once(|| {
                 SleepableLock::new(
                     "LOG",
-                    Log::new(dev, superblock.logstart as i32, superblock.nlog as i32, ctx),
+                    Log::new(
+                        dev,
+                        BlockNo::new(superblock.logstart),
+                        superblock.nlog as i32,
+                        ctx,
+                    ),
                 )
             });
+            let now = ctx.kernel().clocks().ticks();
+            inode::scan_inodes(dev, superblock, ctx, |uid, size| {
+                self.quota
+                    .lock()
+                    .charge(uid, size_blocks(size), 1, false, now)
+                    .expect("init: too many users for quotas");
+            });
         }
     }
 
@@ -86,7 +117,7 @@ impl FileSystem for Ufs {
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<RcInode<Self::InodeInner>, ()> {
+    ) -> Result<RcInode<Self::InodeInner>, KernelError> {
         self.itable().namei(path, tx, ctx)
     }
 
@@ -96,31 +127,39 @@ impl FileSystem for Ufs {
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
         let inode = scopeguard::guard(inode, |ptr| ptr.free((tx, ctx)));
         let ip = inode.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         if ip.deref_inner().typ == InodeType::Dir {
-            return Err(());
+            return Err(KernelError::NotPermitted);
         }
         ip.deref_inner_mut().nlink += 1;
         ip.update(tx, ctx);
         drop(ip);
 
-        if let Ok((ptr2, name)) = self.itable().nameiparent(path, tx, ctx) {
-            let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
-            let dp = ptr2.lock(ctx);
-            let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
-            if dp.dev == inode.dev && dp.dirlink(name, inode.inum, tx, ctx).is_ok() {
-                return Ok(());
-            }
+        let res = self
+            .itable()
+            .nameiparent(path, tx, ctx)
+            .and_then(|(ptr2, name)| {
+                let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
+                let dp = ptr2.lock(ctx);
+                let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+                if dp.dev != inode.dev {
+                    return Err(KernelError::CrossDevice);
+                }
+                dp.check_access(MAY_WRITE | MAY_EXEC, ctx)?;
+                dp.dirlink(name, inode.inum, tx, ctx)
+            });
+        if res.is_ok() {
+            return res;
         }
 
         let ip = inode.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
         ip.deref_inner_mut().nlink -= 1;
         ip.update(tx, ctx);
-        Err(())
+        res
     }
 
     fn unlink(
@@ -128,15 +167,16 @@ impl FileSystem for Ufs {
         path: &Path,
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
         let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
         let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
         let dp = ptr.lock(ctx);
         let mut dp = scopeguard::guard(dp, |ip| ip.free(ctx));
+        dp.check_access(MAY_WRITE | MAY_EXEC, ctx)?;
 
         // Cannot unlink "." or "..".
         if name.as_bytes() == b"." || name.as_bytes() == b".." {
-            return Err(());
+            return Err(KernelError::InvalidArgument);
         }
 
         let (ptr2, off) = dp.dirlookup(name, ctx)?;
@@ -146,11 +186,10 @@ impl FileSystem for Ufs {
         assert!(ip.deref_inner().nlink >= 1, "unlink: nlink < 1");
 
         if ip.deref_inner().typ == InodeType::Dir && !ip.is_dir_empty(ctx) {
-            return Err(());
+            return Err(KernelError::NotEmpty);
         }
 
-        dp.write_kernel(&Dirent::default(), off, tx, ctx)
-            .expect("unlink: writei");
+        dp.dirunlink(off, tx, ctx);
         if ip.deref_inner().typ == InodeType::Dir {
             dp.deref_inner_mut().nlink -= 1;
             dp.update(tx, ctx);
@@ -169,9 +208,9 @@ impl FileSystem for Ufs {
         tx: &Self::Tx<'_>,
         ctx: &KernelCtx<'_, '_>,
         f: F,
-    ) -> Result<(RcInode<Self::InodeInner>, T), ()>
+    ) -> Result<(RcInode<Self::InodeInner>, T), KernelError>
     where
-        F: FnOnce(&mut InodeGuard<'_, Self::InodeInner>) -> T,
+        F: FnOnce(&mut InodeGuard<'_, Self::InodeInner>, bool) -> T,
     {
         let (ptr, name) = self.itable().nameiparent(path, tx, ctx)?;
         let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
@@ -181,21 +220,44 @@ impl FileSystem for Ufs {
             let ptr2 = scopeguard::guard(ptr2, |ptr| ptr.free((tx, ctx)));
             drop(dp);
             if typ != InodeType::File {
-                return Err(());
+                return Err(KernelError::Exists);
             }
             let ip = ptr2.lock(ctx);
             let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
             if let InodeType::None | InodeType::Dir = ip.deref_inner().typ {
-                return Err(());
+                return Err(KernelError::IsDir);
             }
-            let ret = f(&mut ip);
+            let ret = f(&mut ip, false);
             drop(ip);
             return Ok((scopeguard::ScopeGuard::into_inner(ptr2), ret));
         }
+        dp.check_access(MAY_WRITE | MAY_EXEC, ctx)?;
+        let data = ctx.proc().deref_data();
+        self.quota.lock().charge(
+            data.cred.euid() as u16,
+            0,
+            1,
+            !ctx.capable(Caps::SYS_RESOURCE),
+            ctx.kernel().clocks().ticks(),
+        )?;
         let ptr2 = self.itable().alloc_inode(dp.dev, typ, tx, ctx);
         let ip = ptr2.lock(ctx);
         let mut ip = scopeguard::guard(ip, |ip| ip.free(ctx));
-        ip.deref_inner_mut().nlink = 1;
+        let mode = match typ {
+            InodeType::Dir => DEFAULT_DIR_MODE & !(data.umask as u16),
+            InodeType::Device { major, .. } => {
+                ctx.kernel()
+                    .devsw()
+                    .get(major as usize)
+                    .map_or(DEFAULT_DEVICE_MODE, |devsw| devsw.mode)
+            }
+            _ => DEFAULT_FILE_MODE & !(data.umask as u16),
+        };
+        let inner = ip.deref_inner_mut();
+        inner.nlink = 1;
+        inner.mode = mode;
+        inner.uid = data.cred.euid() as u16;
+        inner.gid = data.cred.egid as u16;
         ip.update(tx, ctx);
 
         // Create . and .. entries.
@@ -213,7 +275,7 @@ impl FileSystem for Ufs {
                 .expect("create dots");
         }
         dp.dirlink(name, ip.inum, tx, ctx).expect("create: dirlink");
-        let ret = f(&mut ip);
+        let ret = f(&mut ip, true);
         drop(ip);
         Ok((ptr2, ret))
     }
@@ -224,9 +286,29 @@ impl FileSystem for Ufs {
         omode: FcntlFlags,
         tx: &Self::Tx<'_>,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, ()> {
+    ) -> Result<usize, KernelError> {
+        let mut access = 0;
+        if !omode.intersects(FcntlFlags::O_WRONLY) {
+            access |= MAY_READ;
+        }
+        if omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR | FcntlFlags::O_TRUNC) {
+            access |= MAY_WRITE;
+        }
         let (ip, typ) = if omode.contains(FcntlFlags::O_CREATE) {
-            self.create(path, InodeType::File, tx, ctx, |ip| ip.deref_inner().typ)?
+            let (ip, res) = self.create(path, InodeType::File, tx, ctx, |ip, created| {
+                // The creator may open a new file regardless of its mode.
+                if !created {
+                    ip.check_access(access, ctx)?;
+                }
+                Ok(ip.deref_inner().typ)
+            })?;
+            match res {
+                Ok(typ) => (ip, typ),
+                Err(e) => {
+                    ip.free((tx, ctx));
+                    return Err(e);
+                }
+            }
         } else {
             let ptr = self.itable().namei(path, tx, ctx)?;
             let ptr = scopeguard::guard(ptr, |ptr| ptr.free((tx, ctx)));
@@ -234,13 +316,17 @@ impl FileSystem for Ufs {
             let ip = scopeguard::guard(ip, |ip| ip.free(ctx));
             let typ = ip.deref_inner().typ;
 
-            if typ == InodeType::Dir && omode != FcntlFlags::O_RDONLY {
-                return Err(());
+            if typ == InodeType::Dir
+                && omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR | FcntlFlags::O_TRUNC)
+            {
+                return Err(KernelError::IsDir);
             }
+            ip.check_access(access, ctx)?;
             drop(ip);
             (scopeguard::ScopeGuard::into_inner(ptr), typ)
         };
 
+        let ip = FsInode::Ufs(ip);
         let filetype = match typ {
             InodeType::Device { major, .. } => FileType::Device { ip, major },
             _ => {
@@ -248,23 +334,51 @@ impl FileSystem for Ufs {
                     inner: InodeFileType {
                         ip,
                         off: UnsafeCell::new(0),
+                        readahead: UnsafeCell::new(Readahead::new()),
                     },
                 }
             }
         };
 
-        let f = ctx.kernel().ftable().alloc_file(
-            filetype,
-            !omode.intersects(FcntlFlags::O_WRONLY),
-            omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR),
-        )?;
+        let f = ctx
+            .kernel()
+            .ftable()
+            .alloc_file(
+                filetype,
+                !omode.intersects(FcntlFlags::O_WRONLY),
+                omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR),
+            )
+            .map_err(|filetype| {
+                match filetype {
+                    FileType::Device {
+                        ip: FsInode::Ufs(ip),
+                        ..
+                    }
+                    | FileType::Inode {
+                        inner:
+                            InodeFileType {
+                                ip: FsInode::Ufs(ip),
+                                ..
+                            },
+                    } => ip.free((tx, ctx)),
+                    _ => (),
+                }
+                KernelError::FileTableFull
+            })?;
 
         if omode.contains(FcntlFlags::O_TRUNC) && typ == InodeType::File {
             match &f.typ {
                 // It is safe to call itrunc because ip.lock() is held
-                FileType::Device { ip, .. }
+                FileType::Device {
+                    ip: FsInode::Ufs(ip),
+                    ..
+                }
                 | FileType::Inode {
-                    inner: InodeFileType { ip, .. },
+                    inner:
+                        InodeFileType {
+                            ip: FsInode::Ufs(ip),
+                            ..
+                        },
                 } => {
                     let mut ip = ip.lock(ctx);
                     ip.itrunc(tx, ctx);
@@ -273,7 +387,14 @@ impl FileSystem for Ufs {
                 _ => panic!("sys_open : Not reach"),
             };
         }
+        f.set_status_flags(omode);
         let fd = f.fdalloc(ctx)?;
+        if omode.contains(FcntlFlags::O_CLOEXEC) {
+            ctx.proc_mut()
+                .deref_mut_data()
+                .fds
+                .set_cloexec(fd as usize, true);
+        }
         Ok(fd as usize)
     }
 
@@ -282,17 +403,118 @@ impl FileSystem for Ufs {
         inode: RcInode<InodeInner>,
         tx: &Self::Tx<'_>,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
+        let ip = inode.lock(ctx);
+        let typ = ip.deref_inner().typ;
+        let res = ip.check_access(MAY_EXEC, ctx);
+        ip.free(ctx);
+        if typ != InodeType::Dir {
+            inode.free((tx, ctx));
+            return Err(KernelError::NotDir);
+        }
+        if let Err(e) = res {
+            inode.free((tx, ctx));
+            return Err(e);
+        }
+        match mem::replace(ctx.proc_mut().cwd_mut(), FsInode::Ufs(inode)) {
+            FsInode::Ufs(old) => old.free((tx, ctx)),
+            old => old.free(ctx),
+        }
+        Ok(())
+    }
+
+    fn chroot(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<InodeInner>,
+        tx: &Self::Tx<'_>,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
         let ip = inode.lock(ctx);
         let typ = ip.deref_inner().typ;
+        let res = ip.check_access(MAY_EXEC, ctx);
         ip.free(ctx);
         if typ != InodeType::Dir {
             inode.free((tx, ctx));
-            return Err(());
+            return Err(KernelError::NotDir);
+        }
+        if let Err(e) = res {
+            inode.free((tx, ctx));
+            return Err(e);
+        }
+        let root = if inode.inum == ROOTINO {
+            // Chrooting to the file system's root undoes chroot.
+            inode.free((tx, ctx));
+            None
+        } else {
+            Some(FsInode::Ufs(inode))
+        };
+        match mem::replace(&mut ctx.proc_mut().deref_mut_data().root, root) {
+            Some(FsInode::Ufs(old)) => old.free((tx, ctx)),
+            Some(old) => old.free(ctx),
+            None => (),
         }
-        mem::replace(ctx.proc_mut().cwd_mut(), inode).free((tx, ctx));
         Ok(())
     }
+
+    fn chmod(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<InodeInner>,
+        mode: u16,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let mut ip = inode.lock(ctx);
+        let res = if ctx.capable(Caps::FOWNER)
+            || ip.deref_inner().uid as u32 == ctx.proc().deref_data().cred.euid()
+        {
+            ip.deref_inner_mut().mode = mode & S_IALL;
+            ip.update(tx, ctx);
+            Ok(())
+        } else {
+            let inum = ip.inum.into_u32() as u64;
+            ctx.audit(AuditEvent::Denied, inum, Some(KernelError::NotPermitted));
+            Err(KernelError::NotPermitted)
+        };
+        ip.free(ctx);
+        inode.free((tx, ctx));
+        res
+    }
+
+    fn chown(
+        self: StrongPin<'_, Self>,
+        inode: RcInode<InodeInner>,
+        uid: u16,
+        gid: u16,
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let res = ctx.require_cap(Caps::CHOWN).and_then(|_| {
+            let mut ip = inode.lock(ctx);
+            let inner = ip.deref_inner_mut();
+            let res = self.move_quota(inner.uid, uid, size_blocks(inner.size), ctx);
+            if res.is_ok() {
+                inner.uid = uid;
+                inner.gid = gid;
+                // The new owner did not grant its rights to the program.
+                inner.mode &= !(I_SUID | I_SGID);
+                ip.update(tx, ctx);
+            }
+            ip.free(ctx);
+            res
+        });
+        inode.free((tx, ctx));
+        res
+    }
+
+    fn getcwd(
+        self: StrongPin<'_, Self>,
+        buf: &mut [u8],
+        tx: &Self::Tx<'_>,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let cwd = ctx.proc().cwd().as_ufs().ok_or(KernelError::CrossDevice)?;
+        self.itable().path_of(cwd, buf, tx, ctx)
+    }
 }
 
 pub struct UfsTx<'s> {
@@ -304,7 +526,9 @@ impl Ufs {
         Self {
             superblock: Once::new(),
             log: Once::new(),
-            itable: Itable::new_itable(),
+            ncache: SpinLock::new("NCACHE", NegativeCache::new()),
+            quota: SpinLock::new("QUOTA", QuotaTable::new()),
+            itable: Itable::<InodeInner>::new_itable(),
         }
     }
 
@@ -320,6 +544,56 @@ impl Ufs {
     fn itable<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Itable<InodeInner>> {
         unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().itable) }
     }
+
+    /// Returns whether the device `dev` holds UFS.
+    pub fn probe(dev: DevNo, ctx: &KernelCtx<'_, '_>) -> bool {
+        let buf = hal().disk().read(dev, BlockNo::new(1), ctx);
+        let ufs = Superblock::is_ufs(&buf);
+        buf.free(ctx);
+        ufs
+    }
+
+    /// Returns the usage statistics of the inode table.
+    pub fn itable_stats(self: StrongPin<'_, Self>) -> ArenaStats {
+        self.itable().stats()
+    }
+
+    /// Returns the disk quota limits and usage of `uid`.
+    pub fn get_quota(&self, uid: u16, ctx: &KernelCtx<'_, '_>) -> Dqblk {
+        self.quota
+            .lock()
+            .get_quota(uid, ctx.kernel().clocks().ticks())
+    }
+
+    /// Sets the disk quota limits of `uid` to those of `dqblk`.
+    /// Returns Ok(()) on success, Err(KernelError::QuotaExceeded) if too many users have quotas.
+    pub fn set_quota(
+        &self,
+        uid: u16,
+        dqblk: &Dqblk,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        self.quota
+            .lock()
+            .set_quota(uid, dqblk, ctx.kernel().clocks().ticks())
+    }
+
+    /// Moves an inode of `blocks` blocks from the quota of `from` to that of `to`, regardless of
+    /// the limits of `to`, as only the superuser changes owners.
+    fn move_quota(
+        &self,
+        from: u16,
+        to: u16,
+        blocks: u32,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        if from != to {
+            let mut quota = self.quota.lock();
+            quota.charge(to, blocks, 1, false, ctx.kernel().clocks().ticks())?;
+            quota.uncharge(from, blocks, 1);
+        }
+        Ok(())
+    }
 }
 
 impl Drop for UfsTx<'_> {
@@ -344,7 +618,7 @@ impl UfsTx<'_> {
     }
 
     /// Zero a block.
-    fn bzero(&self, dev: u32, bno: u32, ctx: &KernelCtx<'_, '_>) {
+    fn bzero(&self, dev: DevNo, bno: BlockNo, ctx: &KernelCtx<'_, '_>) {
         let mut buf = ctx.kernel().bcache().get_buf(dev, bno).lock(ctx);
         buf.deref_inner_mut().data.fill(0);
         buf.deref_inner_mut().valid = true;
@@ -353,10 +627,11 @@ impl UfsTx<'_> {
 
     /// Blocks.
     /// Allocate a zeroed disk block.
-    fn balloc(&self, dev: u32, ctx: &KernelCtx<'_, '_>) -> u32 {
+    fn balloc(&self, dev: DevNo, ctx: &KernelCtx<'_, '_>) -> BlockNo {
         for b in num_iter::range_step(0, self.fs.superblock().size, BPB as u32) {
+            let b = BlockNo::new(b);
             let mut bp = hal().disk().read(dev, self.fs.superblock().bblock(b), ctx);
-            for bi in 0..cmp::min(BPB as u32, self.fs.superblock().size - b) {
+            for bi in 0..cmp::min(BPB as u32, self.fs.superblock().size - b.into_u32()) {
                 let m = 1 << (bi % 8);
                 if bp.deref_inner_mut().data[(bi / 8) as usize] & m == 0 {
                     // Is block free?
@@ -372,10 +647,29 @@ impl UfsTx<'_> {
         panic!("balloc: out of blocks");
     }
 
+    /// Allocate disk block `b` zeroed, if it is free.
+    /// Returns whether it was.
+    fn balloc_at(&self, dev: DevNo, b: BlockNo, ctx: &KernelCtx<'_, '_>) -> bool {
+        if b.into_u32() >= self.fs.superblock().size {
+            return false;
+        }
+        let mut bp = hal().disk().read(dev, self.fs.superblock().bblock(b), ctx);
+        let bi = b.into_u32() as usize % BPB;
+        let m = 1u8 << (bi % 8);
+        if bp.deref_inner_mut().data[bi / 8] & m != 0 {
+            bp.free(ctx);
+            return false;
+        }
+        bp.deref_inner_mut().data[bi / 8] |= m;
+        self.write(bp, ctx);
+        self.bzero(dev, b, ctx);
+        true
+    }
+
     /// Free a disk block.
-    fn bfree(&self, dev: u32, b: u32, ctx: &KernelCtx<'_, '_>) {
+    fn bfree(&self, dev: DevNo, b: BlockNo, ctx: &KernelCtx<'_, '_>) {
         let mut bp = hal().disk().read(dev, self.fs.superblock().bblock(b), ctx);
-        let bi = b as usize % BPB;
+        let bi = b.into_u32() as usize % BPB;
         let m = 1u8 << (bi % 8);
         assert_ne!(
             bp.deref_inner_mut().data[bi / 8] & m,
@@ -386,6 +680,43 @@ impl UfsTx<'_> {
         self.write(bp, ctx);
     }
 
+    /// Inodes.
+    /// Mark a free inode allocated in the inode bit map.
+    /// Returns Some(its number), or None if every inode is allocated.
+    fn ialloc(&self, dev: DevNo, ctx: &KernelCtx<'_, '_>) -> Option<Inum> {
+        let ninodes = self.fs.superblock().ninodes;
+        for i in num_iter::range_step(0, ninodes, BPB as u32) {
+            let i = Inum::new(i);
+            let mut bp = hal().disk().read(dev, self.fs.superblock().ibblock(i), ctx);
+            for bi in 0..cmp::min(BPB as u32, ninodes - i.into_u32()) {
+                let m = 1 << (bi % 8);
+                if bp.deref_inner_mut().data[(bi / 8) as usize] & m == 0 {
+                    bp.deref_inner_mut().data[(bi / 8) as usize] |= m;
+                    self.write(bp, ctx);
+                    return Some(Inum::new(i.into_u32() + bi));
+                }
+            }
+            bp.free(ctx);
+        }
+        None
+    }
+
+    /// Mark an inode free in the inode bit map.
+    fn ifree(&self, dev: DevNo, inum: Inum, ctx: &KernelCtx<'_, '_>) {
+        let mut bp = hal()
+            .disk()
+            .read(dev, self.fs.superblock().ibblock(inum), ctx);
+        let bi = inum.into_u32() as usize % BPB;
+        let m = 1u8 << (bi % 8);
+        assert_ne!(
+            bp.deref_inner_mut().data[bi / 8] & m,
+            0,
+            "freeing free inode"
+        );
+        bp.deref_inner_mut().data[bi / 8] &= !m;
+        self.write(bp, ctx);
+    }
+
     /// Called at the end of each FS system call.
     /// Commits if this was the last outstanding operation.
     pub fn end(self, ctx: &KernelCtx<'_, '_>) {
@@ -0,0 +1,77 @@
+//! The negative lookup cache, which remembers names that lookups did not find in directories, so
+//! that looking them up again, as the shell does in each directory of its search path, does not
+//! scan the directories again.
+//!
+//! Looking up and adding names in a directory both hold the lock of its inode. So `dirlookup`
+//! adds an entry when it found nothing, and `dirlink` removes the entry of a name before adding
+//! the name, without racing each other. Removing a name from a directory cannot make a cached
+//! name present, so `unlink` leaves the cache alone.
+
+use super::DIRSIZ;
+use crate::{
+    fs::FileName,
+    param::NNEGATIVE,
+    util::branded::{DevNo, Inum},
+};
+
+/// A name that is not in a directory.
+#[derive(Clone, Copy)]
+struct Negative {
+    dev: DevNo,
+    dir: Inum,
+    name: [u8; DIRSIZ],
+}
+
+impl Negative {
+    fn new(dev: DevNo, dir: Inum, name: &FileName<{ DIRSIZ }>) -> Self {
+        let mut bytes = [0; DIRSIZ];
+        bytes[..name.as_bytes().len()].copy_from_slice(name.as_bytes());
+        Self {
+            dev,
+            dir,
+            name: bytes,
+        }
+    }
+
+    fn is(&self, other: &Self) -> bool {
+        self.dev == other.dev && self.dir == other.dir && self.name == other.name
+    }
+}
+
+pub struct NegativeCache {
+    entries: [Option<Negative>; NNEGATIVE],
+
+    /// The entry that the next name replaces, going round the entries.
+    hand: usize,
+}
+
+impl NegativeCache {
+    pub const fn new() -> Self {
+        Self {
+            entries: [None; NNEGATIVE],
+            hand: 0,
+        }
+    }
+
+    /// Returns whether `name` is known not to be in directory `dir` of `dev`.
+    pub fn contains(&self, dev: DevNo, dir: Inum, name: &FileName<{ DIRSIZ }>) -> bool {
+        let key = Negative::new(dev, dir, name);
+        self.entries.iter().flatten().any(|entry| entry.is(&key))
+    }
+
+    /// Remembers that `name` is not in directory `dir` of `dev`.
+    pub fn insert(&mut self, dev: DevNo, dir: Inum, name: &FileName<{ DIRSIZ }>) {
+        self.entries[self.hand] = Some(Negative::new(dev, dir, name));
+        self.hand = (self.hand + 1) % NNEGATIVE;
+    }
+
+    /// Forgets that `name` is not in directory `dir` of `dev`, as it is about to be.
+    pub fn remove(&mut self, dev: DevNo, dir: Inum, name: &FileName<{ DIRSIZ }>) {
+        let key = Negative::new(dev, dir, name);
+        for entry in &mut self.entries {
+            if entry.map_or(false, |entry| entry.is(&key)) {
+                *entry = None;
+            }
+        }
+    }
+}
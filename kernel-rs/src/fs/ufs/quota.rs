@@ -0,0 +1,198 @@
+//! Disk quotas, which limit the data blocks and inodes that the files of each user take.
+//!
+//! The table counts the usage of each user ID that owns a file, from a scan of the inodes at
+//! mount, and follows the files as they grow, shrink, change owners, and are created and freed.
+//! The superuser sets the limits with quotactl(), and they last until reboot. Files of the
+//! superuser, and allocations by processes with `Caps::SYS_RESOURCE`, are never refused.
+//!
+//! Allocations are checked before the file system changes anything, so that refusing one leaves
+//! nothing to undo. Directories are charged but never refused, as their entries are added once
+//! the inode they name exists.
+
+use rv6_abi::quota::Dqblk;
+
+use crate::{
+    error::KernelError,
+    param::{BSIZE, NQUOTA},
+    time::TICK_NS,
+};
+
+/// How long a soft limit may be exceeded, in ticks: 7 days.
+const GRACE_TICKS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000 / TICK_NS;
+
+/// Returns the number of data blocks of a file of `size` bytes.
+pub const fn size_blocks(size: u32) -> u32 {
+    (size + BSIZE as u32 - 1) / BSIZE as u32
+}
+
+/// A limit on blocks or inodes, and what is in use.
+#[derive(Copy, Clone, Default)]
+struct Limit {
+    hard: u32,
+    soft: u32,
+    cur: u32,
+
+    /// When the soft limit starts to act as a hard limit, in ticks, if it is exceeded.
+    grace: Option<u64>,
+}
+
+impl Limit {
+    /// Adds `n` to the usage at tick `now`, or fails with KernelError::QuotaExceeded if `enforce`
+    /// and it goes past a limit.
+    fn charge(&mut self, n: u32, enforce: bool, now: u64) -> Result<(), KernelError> {
+        let cur = self.cur.saturating_add(n);
+        let over_soft = self.soft != 0 && cur > self.soft;
+        if enforce && n > 0 {
+            let expired = over_soft && self.grace.map_or(false, |grace| now >= grace);
+            if (self.hard != 0 && cur > self.hard) || expired {
+                return Err(KernelError::QuotaExceeded);
+            }
+        }
+        self.cur = cur;
+        if over_soft && self.grace.is_none() {
+            self.grace = Some(now + GRACE_TICKS);
+        }
+        Ok(())
+    }
+
+    /// Takes `n` from the usage.
+    fn uncharge(&mut self, n: u32) {
+        self.cur = self.cur.checked_sub(n).expect("Limit::uncharge");
+        if self.soft == 0 || self.cur <= self.soft {
+            self.grace = None;
+        }
+    }
+
+    /// Sets the limits at tick `now`, restarting the grace period.
+    fn set(&mut self, hard: u32, soft: u32, now: u64) {
+        self.hard = hard;
+        self.soft = soft;
+        self.grace = None;
+        self.charge(0, false, now).expect("Limit::set");
+    }
+
+    /// Returns the ticks left in the grace period at `now`, or 0 if there is none.
+    fn left(&self, now: u64) -> u32 {
+        self.grace
+            .map_or(0, |grace| grace.saturating_sub(now) as u32)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.hard == 0 && self.soft == 0 && self.cur == 0
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Quota {
+    uid: u16,
+    blocks: Limit,
+    inodes: Limit,
+}
+
+pub struct QuotaTable {
+    entries: [Option<Quota>; NQUOTA],
+}
+
+impl QuotaTable {
+    pub const fn new() -> Self {
+        Self {
+            entries: [None; NQUOTA],
+        }
+    }
+
+    fn get(&self, uid: u16) -> Option<&Quota> {
+        self.entries.iter().flatten().find(|quota| quota.uid == uid)
+    }
+
+    /// Returns the entry of `uid`, adding one if it has none.
+    /// Returns Err(KernelError::QuotaExceeded) if the table is full.
+    fn get_or_insert(&mut self, uid: u16) -> Result<&mut Quota, KernelError> {
+        let i = self
+            .entries
+            .iter()
+            .position(|entry| entry.map_or(false, |quota| quota.uid == uid))
+            .or_else(|| self.entries.iter().position(|entry| entry.is_none()))
+            .ok_or(KernelError::QuotaExceeded)?;
+        Ok(self.entries[i].get_or_insert(Quota {
+            uid,
+            blocks: Limit::default(),
+            inodes: Limit::default(),
+        }))
+    }
+
+    /// Drops the entry of `uid` if it has neither limits nor usage.
+    fn shrink(&mut self, uid: u16) {
+        let unused =
+            |quota: Quota| quota.uid == uid && quota.blocks.is_empty() && quota.inodes.is_empty();
+        for entry in &mut self.entries {
+            if entry.map_or(false, unused) {
+                *entry = None;
+            }
+        }
+    }
+
+    /// Charges `blocks` blocks and `inodes` inodes to `uid` at tick `now`, unless `enforce` and
+    /// that exceeds a limit of `uid`, in which case this charges nothing.
+    /// Returns Ok(()) on success, Err(KernelError::QuotaExceeded) on error.
+    pub fn charge(
+        &mut self,
+        uid: u16,
+        blocks: u32,
+        inodes: u32,
+        enforce: bool,
+        now: u64,
+    ) -> Result<(), KernelError> {
+        if blocks == 0 && inodes == 0 {
+            return Ok(());
+        }
+        let quota = self.get_or_insert(uid)?;
+        let enforce = enforce && uid != 0;
+        let saved = *quota;
+        let res = quota
+            .blocks
+            .charge(blocks, enforce, now)
+            .and_then(|_| quota.inodes.charge(inodes, enforce, now));
+        if res.is_err() {
+            *quota = saved;
+            self.shrink(uid);
+        }
+        res
+    }
+
+    /// Takes `blocks` blocks and `inodes` inodes from the usage of `uid`.
+    pub fn uncharge(&mut self, uid: u16, blocks: u32, inodes: u32) {
+        if blocks == 0 && inodes == 0 {
+            return;
+        }
+        let quota = self.get_or_insert(uid).expect("uncharge: no quota");
+        quota.blocks.uncharge(blocks);
+        quota.inodes.uncharge(inodes);
+        self.shrink(uid);
+    }
+
+    /// Returns the limits and usage of `uid` at tick `now`.
+    pub fn get_quota(&self, uid: u16, now: u64) -> Dqblk {
+        self.get(uid).map_or(Dqblk::default(), |quota| {
+            Dqblk {
+                bhardlimit: quota.blocks.hard,
+                bsoftlimit: quota.blocks.soft,
+                curblocks: quota.blocks.cur,
+                ihardlimit: quota.inodes.hard,
+                isoftlimit: quota.inodes.soft,
+                curinodes: quota.inodes.cur,
+                btime: quota.blocks.left(now),
+                itime: quota.inodes.left(now),
+            }
+        })
+    }
+
+    /// Sets the limits of `uid` to those of `dqblk` at tick `now`.
+    /// Returns Ok(()) on success, Err(KernelError::QuotaExceeded) if the table is full.
+    pub fn set_quota(&mut self, uid: u16, dqblk: &Dqblk, now: u64) -> Result<(), KernelError> {
+        let quota = self.get_or_insert(uid)?;
+        quota.blocks.set(dqblk.bhardlimit, dqblk.bsoftlimit, now);
+        quota.inodes.set(dqblk.ihardlimit, dqblk.isoftlimit, now);
+        self.shrink(uid);
+        Ok(())
+    }
+}
@@ -2,16 +2,31 @@ use core::{mem, ptr};
 
 use static_assertions::const_assert;
 
-use super::Dinode;
+use super::{Dinode, DIRENT_SIZE};
 use crate::{
     bio::{Buf, BufData},
     param::BSIZE,
+    util::{
+        branded::{BlockNo, Inum},
+        hash::crc32c,
+    },
 };
 
-const FSMAGIC: u32 = 0x10203040;
+const FSMAGIC: u32 = 0x10203041;
+
+/// Superblock flag: the superblock, the inode blocks, and the directory blocks have checksums.
+pub const FS_CHECKSUMS: u32 = 1;
+
+/// Superblock flag: inodes map their blocks with extents instead of block addresses.
+pub const FS_EXTENTS: u32 = 2;
+
+/// Offset of the checksum of an inode block or a directory block, in its last 4 bytes, given
+/// FS_CHECKSUMS. The last inode of an inode block and the last entry of a directory block are not
+/// used, to make room for it.
+const BLOCK_CHECKSUM: usize = BSIZE - mem::size_of::<u32>();
 
 /// Disk layout:
-/// [ boot block | super block | log | inode blocks |
+/// [ boot block | super block | log | inode blocks | inode bit map |
 ///                                          free bit map | data blocks]
 ///
 /// mkfs computes the super block and builds an initial file system. The
@@ -40,8 +55,17 @@ pub struct Superblock {
     /// Block number of first inode block
     pub inodestart: u32,
 
+    /// Block number of first inode bit map block
+    pub ibmapstart: u32,
+
     /// Block number of first free map block
     pub bmapstart: u32,
+
+    /// FS_* flags
+    flags: u32,
+
+    /// CRC32C of the fields above, given FS_CHECKSUMS
+    checksum: u32,
 }
 
 /// Inodes per block.
@@ -62,16 +86,70 @@ impl Superblock {
         // * buf is locked, so we can access it exclusively.
         let result = unsafe { ptr::read(buf.deref_inner().data.as_ptr() as *const Superblock) };
         assert_eq!(result.magic, FSMAGIC, "invalid file system");
+        let fields =
+            &buf.deref_inner().data[..mem::size_of::<Superblock>() - mem::size_of::<u32>()];
+        assert!(
+            !result.checksums() || crc32c(fields) == result.checksum,
+            "invalid file system: superblock checksum mismatch"
+        );
         result
     }
 
+    /// Returns whether `buf` holds a super block of UFS, by its magic number.
+    pub fn is_ufs(buf: &Buf) -> bool {
+        buf.deref_inner().data[..mem::size_of::<u32>()] == FSMAGIC.to_ne_bytes()
+    }
+
+    /// Returns whether the file system has checksums.
+    pub const fn checksums(&self) -> bool {
+        self.flags & FS_CHECKSUMS != 0
+    }
+
+    /// Returns whether inodes map their blocks with extents.
+    pub const fn extents(&self) -> bool {
+        self.flags & FS_EXTENTS != 0
+    }
+
+    /// Returns whether inode `i` holds the checksum of its block instead of an inode.
+    pub const fn is_checksum_inode(&self, i: Inum) -> bool {
+        self.checksums() && i.into_u32() as usize % IPB == IPB - 1
+    }
+
+    /// Returns whether the directory entry at offset `off` holds the checksum of its block
+    /// instead of an entry.
+    pub const fn is_checksum_dirent(&self, off: u32) -> bool {
+        self.checksums() && off as usize % BSIZE == BSIZE - DIRENT_SIZE
+    }
+
+    /// Writes the checksum of a modified inode block or directory block into it, if the file
+    /// system has checksums.
+    pub fn seal(&self, data: &mut BufData) {
+        if self.checksums() {
+            let checksum = crc32c(&data[..BLOCK_CHECKSUM]);
+            data[BLOCK_CHECKSUM..].copy_from_slice(&checksum.to_le_bytes());
+        }
+    }
+
+    /// Returns whether the checksum of an inode block or directory block matches its contents, or
+    /// true if the file system has no checksums.
+    pub fn verify(&self, data: &BufData) -> bool {
+        let mut checksum = [0; mem::size_of::<u32>()];
+        checksum.copy_from_slice(&data[BLOCK_CHECKSUM..]);
+        !self.checksums() || crc32c(&data[..BLOCK_CHECKSUM]) == u32::from_le_bytes(checksum)
+    }
+
     /// Block containing inode i
-    pub const fn iblock(self, i: u32) -> u32 {
-        i / IPB as u32 + self.inodestart
+    pub const fn iblock(self, i: Inum) -> BlockNo {
+        BlockNo::new(i.into_u32() / IPB as u32 + self.inodestart)
     }
 
     /// Block of free map containing bit for block b
-    pub const fn bblock(self, b: u32) -> u32 {
-        b / BPB as u32 + self.bmapstart
+    pub const fn bblock(self, b: BlockNo) -> BlockNo {
+        BlockNo::new(b.into_u32() / BPB as u32 + self.bmapstart)
+    }
+
+    /// Block of inode bit map containing bit for inode i
+    pub const fn ibblock(self, i: Inum) -> BlockNo {
+        BlockNo::new(i.into_u32() / BPB as u32 + self.ibmapstart)
     }
 }
@@ -0,0 +1,248 @@
+//! The virtual file system, which lets UFS and LFS coexist.
+//!
+//! The kernel has one instance of each file system, and the mount table records the device that
+//! each of them is mounted on. Mounting a device finds out which file system it holds: one whose
+//! second block is a superblock of UFS holds UFS, and one with a valid checkpoint region holds
+//! LFS, which keeps its state there instead. Mounting a device that holds neither fails, and
+//! writes nothing to it. `KernelRef::fs` returns the file system mounted on the root
+//! device, and the system calls on paths run their code on it, with its own types, by `with_fs!`.
+//!
+//! The open files and the directories of the processes hold `FsInode`s, inodes of the file system
+//! that they came from, and `with_inode!` runs code on one with the types of its file system.
+//! The initial process gets its current directory once it mounts the root device. `mkfs -l`
+//! builds an image of LFS, which `make qemu LFS=yes` boots from.
+
+use arrayvec::ArrayVec;
+use pin_project::pin_project;
+
+use super::{FileSystem, Lfs, RcInode, Stat, Ufs};
+use crate::{
+    error::KernelError,
+    ktest, ktest_assert,
+    lock::SpinLock,
+    param::ROOTDEV,
+    proc::KernelCtx,
+    util::{
+        branded::{DevNo, Inum},
+        strong_pin::StrongPin,
+    },
+};
+
+/// Maximum number of mounts, as each file system is mounted at most once.
+const NMOUNT: usize = 2;
+
+/// A kind of file system.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum FsType {
+    Ufs,
+    Lfs,
+}
+
+struct Mount {
+    dev: DevNo,
+    typ: FsType,
+}
+
+/// A file system that is mounted.
+#[derive(Copy, Clone)]
+pub enum FsRef<'s> {
+    Ufs(StrongPin<'s, Ufs>),
+    Lfs(StrongPin<'s, Lfs>),
+}
+
+/// An inode of the file system mounted on the root device, which the open files and the
+/// directories of the processes hold.
+#[derive(Clone)]
+pub enum FsInode {
+    Ufs(RcInode<<Ufs as FileSystem>::InodeInner>),
+    Lfs(RcInode<<Lfs as FileSystem>::InodeInner>),
+}
+
+#[pin_project]
+pub struct Vfs {
+    #[pin]
+    ufs: Ufs,
+
+    #[pin]
+    lfs: Lfs,
+
+    /// The mounted devices.
+    mounts: SpinLock<ArrayVec<Mount, NMOUNT>>,
+}
+
+/// Runs `$body` with `$fs` bound to the file system that the `FsRef` `$vfs` refers to, as a
+/// `StrongPin` of its own type.
+#[macro_export]
+macro_rules! with_fs {
+    ($vfs:expr, $fs:ident => $body:expr) => {
+        match $vfs {
+            $crate::fs::FsRef::Ufs($fs) => $body,
+            $crate::fs::FsRef::Lfs($fs) => $body,
+        }
+    };
+}
+
+/// Runs `$body` with `$ip` bound to the `RcInode` that the `FsInode` `$inode` holds, and `$fs`, if
+/// given, to the file system of the inode in the `Vfs` `$vfs`, both of their own types.
+#[macro_export]
+macro_rules! with_inode {
+    ($inode:expr, $ip:pat => $body:expr) => {
+        match $inode {
+            $crate::fs::FsInode::Ufs($ip) => $body,
+            $crate::fs::FsInode::Lfs($ip) => $body,
+        }
+    };
+    ($inode:expr, $vfs:expr,($ip:pat, $fs:ident) => $body:expr) => {
+        match $inode {
+            $crate::fs::FsInode::Ufs($ip) => {
+                let $fs = $vfs.ufs();
+                $body
+            }
+            $crate::fs::FsInode::Lfs($ip) => {
+                let $fs = $vfs.lfs();
+                $body
+            }
+        }
+    };
+}
+
+impl<'s> FsRef<'s> {
+    /// Returns the file system if it is UFS, for the operations that only UFS has.
+    /// Returns Err(KernelError::NoSys) if it is not.
+    pub fn ufs(self) -> Result<StrongPin<'s, Ufs>, KernelError> {
+        match self {
+            FsRef::Ufs(fs) => Ok(fs),
+            FsRef::Lfs(_) => Err(KernelError::NoSys),
+        }
+    }
+}
+
+impl FsInode {
+    pub fn inum(&self) -> Inum {
+        with_inode!(self, ip => ip.inum)
+    }
+
+    /// Returns the inode if it is of UFS.
+    pub fn as_ufs(&self) -> Option<&RcInode<<Ufs as FileSystem>::InodeInner>> {
+        match self {
+            FsInode::Ufs(ip) => Some(ip),
+            FsInode::Lfs(_) => None,
+        }
+    }
+
+    /// Returns the inode if it is of LFS.
+    pub fn as_lfs(&self) -> Option<&RcInode<<Lfs as FileSystem>::InodeInner>> {
+        match self {
+            FsInode::Lfs(ip) => Some(ip),
+            FsInode::Ufs(_) => None,
+        }
+    }
+
+    /// Copy stat information from inode.
+    pub fn stat(&self, ctx: &KernelCtx<'_, '_>) -> Stat {
+        with_inode!(self, ip => ip.stat(ctx))
+    }
+
+    /// Drops the reference, in a transaction of its own. A caller in a transaction of the file
+    /// system of the inode must drop the `RcInode` in that transaction instead.
+    pub fn free(self, ctx: &KernelCtx<'_, '_>) {
+        with_inode!(self, ctx.kernel().vfs(), (ip, fs) => {
+            let tx = fs.as_pin().get_ref().begin_tx(ctx);
+            ip.free((&tx, ctx));
+            tx.end(ctx);
+        })
+    }
+}
+
+impl From<RcInode<<Ufs as FileSystem>::InodeInner>> for FsInode {
+    fn from(ip: RcInode<<Ufs as FileSystem>::InodeInner>) -> Self {
+        FsInode::Ufs(ip)
+    }
+}
+
+impl From<RcInode<<Lfs as FileSystem>::InodeInner>> for FsInode {
+    fn from(ip: RcInode<<Lfs as FileSystem>::InodeInner>) -> Self {
+        FsInode::Lfs(ip)
+    }
+}
+
+impl Vfs {
+    pub const fn new() -> Self {
+        Self {
+            ufs: Ufs::new(),
+            lfs: Lfs::new(),
+            mounts: SpinLock::new("MOUNTS", ArrayVec::new_const()),
+        }
+    }
+
+    /// Returns the instance of UFS, whether it is mounted or not.
+    #[allow(clippy::needless_lifetimes)]
+    pub fn ufs<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Ufs> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().ufs) }
+    }
+
+    /// Returns the instance of LFS, whether it is mounted or not.
+    #[allow(clippy::needless_lifetimes)]
+    pub fn lfs<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, Lfs> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().lfs) }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn get<'s>(self: StrongPin<'s, Self>, typ: FsType) -> FsRef<'s> {
+        match typ {
+            FsType::Ufs => FsRef::Ufs(self.ufs()),
+            FsType::Lfs => FsRef::Lfs(self.lfs()),
+        }
+    }
+
+    /// Returns the file system mounted on the root device.
+    #[allow(clippy::needless_lifetimes)]
+    pub fn root<'s>(self: StrongPin<'s, Self>) -> FsRef<'s> {
+        let mounts = self.mounts.lock();
+        let mount = mounts
+            .iter()
+            .find(|mount| mount.dev == ROOTDEV)
+            .expect("root device not mounted");
+        let typ = mount.typ;
+        drop(mounts);
+        self.get(typ)
+    }
+
+    /// Mounts the file system that the device `dev` holds, and returns its type.
+    /// Returns Err(KernelError::Busy) if `dev` is mounted, or the file system is mounted on
+    /// another device, and Err(KernelError::InvalidArgument) if `dev` holds no file system.
+    pub fn mount(
+        self: StrongPin<'_, Self>,
+        dev: DevNo,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<FsType, KernelError> {
+        if self.mounts.lock().iter().any(|mount| mount.dev == dev) {
+            return Err(KernelError::Busy);
+        }
+        let typ = if Ufs::probe(dev, ctx) {
+            FsType::Ufs
+        } else if Lfs::probe(dev, ctx) {
+            FsType::Lfs
+        } else {
+            return Err(KernelError::InvalidArgument);
+        };
+        let mut mounts = self.mounts.lock();
+        if mounts
+            .iter()
+            .any(|mount| mount.dev == dev || mount.typ == typ)
+        {
+            return Err(KernelError::Busy);
+        }
+        mounts.push(Mount { dev, typ });
+        drop(mounts);
+        with_fs!(self.get(typ), fs => fs.init(dev, ctx));
+        Ok(typ)
+    }
+}
+
+ktest! {
+    fn vfs_mount(ctx) {
+        let vfs = ctx.kernel().vfs();
+        ktest_assert!(matches!(vfs.mount(ROOTDEV, ctx), Err(KernelError::Busy)));
+    }
+}
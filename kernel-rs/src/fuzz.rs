@@ -0,0 +1,102 @@
+//! Hooks for fuzzing the system calls, built in with the `fuzz` feature (`make FUZZ=yes`).
+//!
+//! A fuzzer such as the fuzz program makes system calls with random numbers and arguments, and
+//! relies on the kernel to reject the bad ones with errors. In this mode, the kernel counts the
+//! system calls that returned, so that fuzzinfo() tells how far a run got without crashing the
+//! kernel. It also refuses, with `KernelError::NotPermitted`, the system calls that would end the
+//! run without finding anything, even for the superuser:
+//!
+//! * poweroff().
+//! * suspend(), which may wait for a key that nobody presses.
+//! * kill() of the initial process, which the kernel cannot run without.
+
+#![cfg_attr(not(feature = "fuzz"), allow(dead_code, unused_imports))]
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use rv6_abi::syscall::{SYS_KILL, SYS_POWEROFF, SYS_SUSPEND};
+use zerocopy::AsBytes;
+
+use crate::{arch::addr::UVAddr, error::KernelError, hal::hal, proc::KernelCtx};
+
+/// Pid of the initial process, which is allocated first.
+const INIT_PID: i32 = 1;
+
+/// `struct fuzzinfo` of user programs.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+pub struct FuzzInfo {
+    /// Number of system calls that returned since boot, including those that failed.
+    calls: u64,
+    /// Number of them that failed.
+    errors: u64,
+    /// Number of them that failed with `KernelError::Fault`, for a bad user pointer.
+    faults: u64,
+    /// Number of system calls that were refused, which are not counted above.
+    refused: u64,
+}
+
+pub struct FuzzCounters {
+    calls: AtomicU64,
+    errors: AtomicU64,
+    faults: AtomicU64,
+    refused: AtomicU64,
+}
+
+impl FuzzCounters {
+    pub const fn new() -> Self {
+        Self {
+            calls: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            faults: AtomicU64::new(0),
+            refused: AtomicU64::new(0),
+        }
+    }
+
+    /// Counts a system call that returned `ret`.
+    pub fn record(&self, ret: &Result<usize, KernelError>) {
+        let _ = self.calls.fetch_add(1, Ordering::Relaxed);
+        if let Err(e) = ret {
+            let _ = self.errors.fetch_add(1, Ordering::Relaxed);
+            if *e == KernelError::Fault {
+                let _ = self.faults.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Returns whether the fuzzing mode refuses the system call `num` with its arguments, and
+    /// counts it if so. Always `false` without the `fuzz` feature.
+    pub fn fuzz_refuses(&self, num: i32) -> bool {
+        if !cfg!(feature = "fuzz") {
+            return false;
+        }
+        let refused = match num {
+            SYS_POWEROFF | SYS_SUSPEND => true,
+            SYS_KILL => self.proc().argint(0) == Ok(INIT_PID),
+            _ => false,
+        };
+        if refused {
+            let _ = hal().fuzz().refused.fetch_add(1, Ordering::Relaxed);
+        }
+        refused
+    }
+
+    /// Copy the counters of the fuzzing mode to the `struct fuzzinfo` at `addr`.
+    /// Returns Ok(()) on success, Err(KernelError) on error, which is Err(KernelError::NoSys) if
+    /// the kernel was built without the `fuzz` feature.
+    pub fn fuzz_info(&mut self, addr: UVAddr) -> Result<(), KernelError> {
+        if !cfg!(feature = "fuzz") {
+            return Err(KernelError::NoSys);
+        }
+        let counters = hal().get_ref().fuzz();
+        let info = FuzzInfo {
+            calls: counters.calls.load(Ordering::Relaxed),
+            errors: counters.errors.load(Ordering::Relaxed),
+            faults: counters.faults.load(Ordering::Relaxed),
+            refused: counters.refused.load(Ordering::Relaxed),
+        };
+        self.proc_mut().memory_mut().copy_out(addr, &info)
+    }
+}
@@ -0,0 +1,616 @@
+//! A stub of GDB's remote serial protocol, with which GDB debugs the kernel over the second uart
+//! on machines that have no debugger of their own, unlike QEMU with `-s`.
+//!
+//! QEMU gives the second uart to the stub, instead of to /dev/ttyS1, when it passes the file
+//! `opt/rv6/gdb`, as `make qemu GDBSTUB=yes` does. The kernel then stops for GDB once it is
+//! initialized. It also stops when it executes an `ebreak`, when it reaches a breakpoint that
+//! GDB set, and when GDB sends ^C, as soon as the CPU that takes the uart's interrupt returns from
+//! it. A stopped CPU runs nothing but the stub, with interrupts disabled, until GDB continues it,
+//! while the other CPUs keep running. GDB can read and write the registers of the stopped CPU and
+//! the kernel's memory, which includes its text.
+//!
+//! RISC-V has no single-step mode that the supervisor can use, so, as for ptrace(), the stub puts
+//! `c.ebreak`s at the instructions that can come next, and restores them at the next stop.
+//! Instructions written by the stub are fenced only on the stopped CPU.
+
+use core::{
+    cmp,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    arch::addr::{Addr, KVAddr},
+    arch::memlayout::{KERNBASE, PHYSTOP},
+    arch::riscv::{ebreak, fence_i},
+    kernel::KernelRef,
+    kmsg::Level,
+    lock::SpinLock,
+    log,
+    proc::{UserRegs, C_EBREAK},
+    trap::KernelFrame,
+    uart::Uart,
+    vm::PteFlags,
+};
+
+/// Maximum number of breakpoints that GDB can set.
+const BREAKPOINTS: usize = 16;
+
+/// Maximum size of the data of a packet.
+const PACKET_SIZE: usize = 1024;
+
+/// Number of registers in the `g` packet: `x0` to `x31`, and the program counter.
+const REGS: usize = 33;
+
+/// Signals that stop replies report.
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// What GDB asked the stopped CPU to do.
+enum Resume {
+    /// Stay stopped.
+    Stop,
+    /// Continue running.
+    Continue,
+    /// Execute one instruction, and stop again.
+    Step,
+    /// Continue running, without GDB waiting for the next stop.
+    Detach,
+}
+
+/// The GDB stub, and the uart it talks to GDB over.
+pub struct GdbStub {
+    uart: Uart,
+
+    /// Whether GDB sent ^C while the kernel was running.
+    break_request: AtomicBool,
+
+    state: SpinLock<StubState>,
+}
+
+struct StubState {
+    /// Breakpoints that GDB set, and the instructions they replaced.
+    breakpoints: [Option<(usize, u16)>; BREAKPOINTS],
+
+    /// Breakpoints put for single-stepping, and the instructions they replaced.
+    step_breakpoints: [Option<(usize, u16)>; 2],
+
+    /// Whether GDB waits for the kernel to stop.
+    attached: bool,
+
+    /// Signal of the last stop.
+    signal: u8,
+
+    packet: [u8; PACKET_SIZE],
+    reply: Reply,
+}
+
+/// The data of a reply packet.
+struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Reply {
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn put(&mut self, data: &[u8]) {
+        let len = cmp::min(data.len(), PACKET_SIZE - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&data[..len]);
+        self.len += len;
+    }
+
+    fn put_hex(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.put(&[HEX[(b >> 4) as usize], HEX[(b & 0xf) as usize]]);
+        }
+    }
+}
+
+impl GdbStub {
+    /// # Safety
+    ///
+    /// uart..(uart + 8) are owned addresses.
+    pub unsafe fn new(uart: usize) -> Self {
+        Self {
+            // SAFETY: uart..(uart + 8) are owned addresses.
+            uart: unsafe { Uart::new(uart) },
+            break_request: AtomicBool::new(false),
+            state: SpinLock::new(
+                "gdbstub",
+                StubState {
+                    breakpoints: [None; BREAKPOINTS],
+                    step_breakpoints: [None; 2],
+                    attached: false,
+                    signal: SIGTRAP,
+                    packet: [0; PACKET_SIZE],
+                    reply: Reply {
+                        buf: [0; PACKET_SIZE],
+                        len: 0,
+                    },
+                },
+            ),
+        }
+    }
+
+    pub fn init(&self) {
+        self.uart.init_polled();
+    }
+
+    /// Handles an interrupt of the uart. GDB sends nothing while the kernel runs but ^C.
+    pub fn intr(&self) {
+        while let Ok(c) = self.uart.getc() {
+            if c == 0x03 {
+                self.break_request.store(true, Ordering::Release);
+            }
+        }
+    }
+
+    /// Returns `true` if GDB sent ^C since the last call.
+    pub fn take_break_request(&self) -> bool {
+        self.break_request.swap(false, Ordering::AcqRel)
+    }
+
+    /// Stops the kernel until GDB continues it.
+    pub fn wait(&self) {
+        log!(
+            Level::Info,
+            "gdb",
+            "waiting for gdb on the second serial port"
+        );
+        ebreak();
+    }
+
+    /// Stops this CPU, which trapped from the kernel at `pc` with the registers saved in `frame`,
+    /// until GDB continues it. `breakpoint` tells if the trap is a breakpoint exception rather
+    /// than ^C. Must be called with interrupts disabled.
+    /// Returns the program counter to return to.
+    pub fn stop(
+        &self,
+        kernel: KernelRef<'_, '_>,
+        frame: &mut KernelFrame,
+        mut pc: usize,
+        breakpoint: bool,
+    ) -> usize {
+        let mut state = self.state.lock();
+        let stepped = state.remove_step_breakpoints(kernel, pc);
+        if breakpoint
+            && !stepped
+            && !state
+                .breakpoints
+                .iter()
+                .flatten()
+                .any(|(addr, _)| *addr == pc)
+        {
+            // An `ebreak` in the kernel's code. Skip it, or continuing would stop at it again.
+            let mut inst = [0; 2];
+            if read_memory(kernel, pc, &mut inst).is_some() {
+                pc += inst_len(u16::from_le_bytes(inst));
+            }
+        }
+        state.signal = if breakpoint { SIGTRAP } else { SIGINT };
+        if state.attached {
+            state.stop_reply();
+            self.send(state.reply.as_bytes());
+        }
+
+        loop {
+            let len = self.recv(&mut state.packet);
+            let resume = state.handle(kernel, frame, &mut pc, len);
+            match resume {
+                Resume::Stop => self.send(state.reply.as_bytes()),
+                Resume::Continue => {
+                    state.attached = true;
+                    break;
+                }
+                Resume::Step => {
+                    state.attached = true;
+                    state.insert_step_breakpoints(kernel, frame, pc);
+                    break;
+                }
+                Resume::Detach => {
+                    if !state.reply.as_bytes().is_empty() {
+                        self.send(state.reply.as_bytes());
+                    }
+                    state.remove_breakpoints(kernel);
+                    state.attached = false;
+                    break;
+                }
+            }
+        }
+        pc
+    }
+
+    fn getc(&self) -> u8 {
+        loop {
+            if let Ok(c) = self.uart.getc() {
+                return c as u8;
+            }
+        }
+    }
+
+    fn putc(&self, c: u8) {
+        while self.uart.is_full() {}
+        self.uart.putc(c);
+    }
+
+    /// Receives a packet into `buf`, and acknowledges it.
+    /// Returns the length of its data.
+    fn recv(&self, buf: &mut [u8]) -> usize {
+        loop {
+            while self.getc() != b'$' {}
+            let mut len = 0;
+            let mut sum = 0u8;
+            let mut overflow = false;
+            loop {
+                let c = self.getc();
+                if c == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(c);
+                match buf.get_mut(len) {
+                    Some(b) => *b = c,
+                    None => overflow = true,
+                }
+                len += 1;
+            }
+            let checksum = parse_hex(&[self.getc(), self.getc()]);
+            if !overflow && checksum == Some(sum as usize) {
+                self.putc(b'+');
+                return len;
+            }
+            self.putc(b'-');
+        }
+    }
+
+    /// Sends a packet with `data`, until GDB acknowledges it.
+    fn send(&self, data: &[u8]) {
+        let sum = data.iter().fold(0u8, |sum, c| sum.wrapping_add(*c));
+        loop {
+            self.putc(b'$');
+            for c in data {
+                self.putc(*c);
+            }
+            self.putc(b'#');
+            self.putc(HEX[(sum >> 4) as usize]);
+            self.putc(HEX[(sum & 0xf) as usize]);
+            loop {
+                match self.getc() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+impl StubState {
+    /// Handles the packet of `len` bytes in `self.packet`, and puts the reply in `self.reply`.
+    fn handle(
+        &mut self,
+        kernel: KernelRef<'_, '_>,
+        frame: &mut KernelFrame,
+        pc: &mut usize,
+        len: usize,
+    ) -> Resume {
+        self.reply.clear();
+        let packet = &self.packet[..len];
+        let (&cmd, args) = match packet.split_first() {
+            Some(split) => split,
+            None => return Resume::Stop,
+        };
+        match cmd {
+            b'?' => self.stop_reply(),
+            b'g' => {
+                for n in 0..REGS {
+                    let value = reg(frame, *pc, n).unwrap_or(0);
+                    self.reply.put_hex(&value.to_le_bytes());
+                }
+            }
+            b'G' => {
+                let mut value = [0; 8];
+                for (n, hex) in args.chunks(16).take(REGS).enumerate() {
+                    if parse_hex_bytes(hex, &mut value).is_some() {
+                        let _ = set_reg(frame, pc, n, usize::from_le_bytes(value));
+                    }
+                }
+                self.reply.put(b"OK");
+            }
+            b'p' => {
+                match parse_hex(args).and_then(|n| reg(frame, *pc, n)) {
+                    Some(value) => self.reply.put_hex(&value.to_le_bytes()),
+                    None => self.reply.put(b"xxxxxxxxxxxxxxxx"),
+                }
+            }
+            b'P' => {
+                let mut value = [0; 8];
+                let set = split(args, b'=').and_then(|(n, hex)| {
+                    parse_hex_bytes(hex, &mut value)?;
+                    set_reg(frame, pc, parse_hex(n)?, usize::from_le_bytes(value))
+                });
+                self.reply.put(if set.is_some() { b"OK" } else { b"E01" });
+            }
+            b'm' => {
+                let reply = &mut self.reply;
+                let read = split(args, b',').and_then(|(addr, len)| {
+                    let addr = parse_hex(addr)?;
+                    for i in 0..cmp::min(parse_hex(len)?, PACKET_SIZE / 2) {
+                        let mut b = [0];
+                        read_memory(kernel, addr.checked_add(i)?, &mut b)?;
+                        reply.put_hex(&b);
+                    }
+                    Some(())
+                });
+                if read.is_none() {
+                    self.reply.clear();
+                    self.reply.put(b"E14");
+                }
+            }
+            b'M' => {
+                let written = split(args, b',').and_then(|(addr, rest)| {
+                    let (len, hex) = split(rest, b':')?;
+                    let addr = parse_hex(addr)?;
+                    if hex.len() != parse_hex(len)? * 2 {
+                        return None;
+                    }
+                    for (i, hex) in hex.chunks(2).enumerate() {
+                        let b = parse_hex(hex)? as u8;
+                        write_memory(kernel, addr.checked_add(i)?, &[b])?;
+                    }
+                    Some(())
+                });
+                self.reply
+                    .put(if written.is_some() { b"OK" } else { b"E14" });
+            }
+            b'c' | b's' => {
+                if let Some(addr) = parse_hex(args) {
+                    *pc = addr;
+                }
+                return if cmd == b'c' {
+                    Resume::Continue
+                } else {
+                    Resume::Step
+                };
+            }
+            b'Z' | b'z' => {
+                let addr = args
+                    .strip_prefix(b"0,")
+                    .and_then(|rest| split(rest, b','))
+                    .and_then(|(addr, _kind)| parse_hex(addr));
+                // Only software breakpoints are supported.
+                if let Some(addr) = addr {
+                    let done = if cmd == b'Z' {
+                        self.insert_breakpoint(kernel, addr)
+                    } else {
+                        self.remove_breakpoint(kernel, addr)
+                    };
+                    self.reply.put(if done.is_some() { b"OK" } else { b"E01" });
+                }
+            }
+            b'D' => {
+                self.reply.put(b"OK");
+                return Resume::Detach;
+            }
+            b'k' => return Resume::Detach,
+            b'H' => self.reply.put(b"OK"),
+            b'q' => {
+                if args.starts_with(b"Supported") {
+                    self.reply.put(b"PacketSize=400");
+                } else if args.starts_with(b"Attached") {
+                    self.reply.put(b"1");
+                }
+            }
+            _ => {}
+        }
+        Resume::Stop
+    }
+
+    /// Puts the reply to `?`, which tells why the kernel stopped.
+    fn stop_reply(&mut self) {
+        self.reply.clear();
+        self.reply.put(b"S");
+        self.reply.put_hex(&[self.signal]);
+    }
+
+    fn insert_breakpoint(&mut self, kernel: KernelRef<'_, '_>, addr: usize) -> Option<()> {
+        if self.breakpoints.iter().flatten().any(|(a, _)| *a == addr) {
+            return Some(());
+        }
+        let slot = self.breakpoints.iter_mut().find(|bp| bp.is_none())?;
+        *slot = Some((addr, put_ebreak(kernel, addr)?));
+        Some(())
+    }
+
+    fn remove_breakpoint(&mut self, kernel: KernelRef<'_, '_>, addr: usize) -> Option<()> {
+        let slot = self
+            .breakpoints
+            .iter_mut()
+            .find(|bp| bp.map_or(false, |(a, _)| a == addr))?;
+        let (addr, orig) = slot.take()?;
+        write_memory(kernel, addr, &orig.to_le_bytes())
+    }
+
+    /// Removes the breakpoints that GDB left behind.
+    fn remove_breakpoints(&mut self, kernel: KernelRef<'_, '_>) {
+        for (addr, orig) in self.breakpoints.iter_mut().filter_map(Option::take) {
+            let _ = write_memory(kernel, addr, &orig.to_le_bytes());
+        }
+    }
+
+    /// Puts a `c.ebreak` at each instruction that can come after the one at `pc`.
+    fn insert_step_breakpoints(
+        &mut self,
+        kernel: KernelRef<'_, '_>,
+        frame: &KernelFrame,
+        pc: usize,
+    ) {
+        let mut inst = [0; 4];
+        if read_memory(kernel, pc, &mut inst[..2]).is_none() {
+            // The CPU will fault at once.
+            return;
+        }
+        if inst_len(u16::from_le_bytes([inst[0], inst[1]])) == 4 {
+            let _ = read_memory(kernel, pc + 2, &mut inst[2..]);
+        }
+        let mut regs = UserRegs { pc, x: frame.x };
+        regs.x[1] = frame.sp();
+
+        for (i, next) in regs.next_pcs(u32::from_le_bytes(inst)).iter().enumerate() {
+            if let Some(next) = *next {
+                if i == 1 && self.step_breakpoints[0].map_or(false, |(addr, _)| addr == next) {
+                    continue;
+                }
+                self.step_breakpoints[i] = put_ebreak(kernel, next).map(|orig| (next, orig));
+            }
+        }
+    }
+
+    /// Restores the instructions that `insert_step_breakpoints` replaced.
+    /// Returns `true` if the CPU stopped at one of them.
+    fn remove_step_breakpoints(&mut self, kernel: KernelRef<'_, '_>, pc: usize) -> bool {
+        let mut stepped = false;
+        for (addr, orig) in self.step_breakpoints.iter_mut().filter_map(Option::take) {
+            let _ = write_memory(kernel, addr, &orig.to_le_bytes());
+            stepped |= addr == pc;
+        }
+        stepped
+    }
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+/// Returns the value of register `n` in GDB's numbering, or `None` if the stub has no such
+/// register.
+fn reg(frame: &KernelFrame, pc: usize, n: usize) -> Option<usize> {
+    match n {
+        0 => Some(0),
+        2 => Some(frame.sp()),
+        1..=31 => Some(frame.x[n - 1]),
+        32 => Some(pc),
+        _ => None,
+    }
+}
+
+/// Sets register `n` in GDB's numbering to `value`. `sp` and `tp` cannot be set, and the
+/// writes to them are ignored.
+/// Returns `None` if the stub has no such register.
+fn set_reg(frame: &mut KernelFrame, pc: &mut usize, n: usize, value: usize) -> Option<()> {
+    match n {
+        0 | 2 | 4 => {}
+        1..=31 => frame.x[n - 1] = value,
+        32 => *pc = value,
+        _ => return None,
+    }
+    Some(())
+}
+
+/// Returns the length of the instruction whose lowest 16 bits are `inst`.
+fn inst_len(inst: u16) -> usize {
+    if inst & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
+}
+
+/// Puts a `c.ebreak` at `addr`.
+/// Returns the instruction it replaced.
+fn put_ebreak(kernel: KernelRef<'_, '_>, addr: usize) -> Option<u16> {
+    let mut orig = [0; 2];
+    read_memory(kernel, addr, &mut orig)?;
+    write_memory(kernel, addr, &C_EBREAK.to_le_bytes())?;
+    Some(u16::from_le_bytes(orig))
+}
+
+/// Returns the physical address that the kernel address `va` is mapped to, if it is in RAM.
+/// Other physical addresses are of devices, whose registers GDB must not touch.
+fn ram_address(kernel: KernelRef<'_, '_>, va: usize) -> Option<usize> {
+    let (pa, flags) = kernel.memory().translate(KVAddr::from(va))?;
+    let pa = pa.into_usize();
+    if (KERNBASE..PHYSTOP).contains(&pa) && flags.contains(PteFlags::R) {
+        Some(pa)
+    } else {
+        None
+    }
+}
+
+/// Copies the kernel's memory at `addr` into `dst`.
+/// Returns `None` if some of it is not mapped to RAM.
+fn read_memory(kernel: KernelRef<'_, '_>, addr: usize, dst: &mut [u8]) -> Option<()> {
+    for (i, b) in dst.iter_mut().enumerate() {
+        let pa = ram_address(kernel, addr.checked_add(i)?)?;
+        // SAFETY: RAM is mapped at the same addresses, and GDB may read any of it.
+        *b = unsafe { (pa as *const u8).read_volatile() };
+    }
+    Some(())
+}
+
+/// Copies `src` to the kernel's memory at `addr`, even if it is read-only.
+/// Returns `None` if some of it is not mapped to RAM.
+fn write_memory(kernel: KernelRef<'_, '_>, addr: usize, src: &[u8]) -> Option<()> {
+    for (i, b) in src.iter().enumerate() {
+        let pa = ram_address(kernel, addr.checked_add(i)?)?;
+        // SAFETY: GDB may write any of RAM.
+        unsafe { write_physical(pa, *b) };
+    }
+    // SAFETY: the instructions of this CPU must see the writes, in case they were code.
+    unsafe { fence_i() };
+    Some(())
+}
+
+/// Writes `value` at the physical address `pa`, with paging turned off so that the page's
+/// permissions do not matter.
+///
+/// # Safety
+///
+/// `pa` is in RAM, and nothing uses the byte at `pa` meanwhile. Interrupts are disabled.
+unsafe fn write_physical(pa: usize, value: u8) {
+    // The kernel's code and RAM are mapped at the same addresses, so this code keeps running
+    // without paging. It touches no memory but `pa` meanwhile.
+    unsafe {
+        asm!(
+            "csrrw {satp}, satp, zero",
+            "sfence.vma zero, zero",
+            "sb {value}, 0({pa})",
+            "csrw satp, {satp}",
+            "sfence.vma zero, zero",
+            satp = out(reg) _,
+            value = in(reg) value as usize,
+            pa = in(reg) pa,
+        );
+    }
+}
+
+/// Returns the value of the hexadecimal number `s`, or `None` if it is empty or not a number.
+fn parse_hex(s: &[u8]) -> Option<usize> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+    s.iter().try_fold(0, |value, c| {
+        Some(value << 4 | (*c as char).to_digit(16)? as usize)
+    })
+}
+
+/// Parses `s`, pairs of hexadecimal digits, into `dst`.
+/// Returns `None` if `s` is not exactly as long as `dst`.
+fn parse_hex_bytes(s: &[u8], dst: &mut [u8]) -> Option<()> {
+    if s.len() != dst.len() * 2 {
+        return None;
+    }
+    for (b, hex) in dst.iter_mut().zip(s.chunks(2)) {
+        *b = parse_hex(hex)? as u8;
+    }
+    Some(())
+}
+
+/// Splits `s` at the first `sep`.
+fn split(s: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let i = s.iter().position(|c| *c == sep)?;
+    Some((&s[..i], &s[i + 1..]))
+}
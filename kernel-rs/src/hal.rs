@@ -3,12 +3,30 @@ use core::pin::Pin;
 use pin_project::pin_project;
 
 use crate::{
-    arch::memlayout::UART0,
-    console::{Console, Printer},
+    arch::memlayout::{UART0, UART0_IRQ, VIRTIO0},
+    console::{console_intr, Console},
     cpu::Cpus,
+    cpuidle::CpuIdle,
+    cputime::CpuTimes,
+    driver::probe_devices,
+    failinject::FaultInjector,
+    fuzz::FuzzCounters,
+    gdbstub::GdbStub,
+    ipi::{ipi_intr, Ipis},
+    irq::{IrqChip, IrqHandler, Irqs, CPU_SOFTWARE},
     kalloc::Kmem,
+    kmsg::KernelLog,
     lock::{SleepableLock, SpinLock},
-    virtio::VirtioDisk,
+    pagecache::PageCache,
+    profile::Profiler,
+    psi::Psi,
+    softirq::SoftIrqs,
+    suspend::Suspend,
+    tracepoint::TraceBuffers,
+    trap::timer_intr,
+    virtio::{virtio_device_id, VirtioDisk, VirtioRng, VIRTIO_ID_BLOCK},
+    vsock::Vsock,
+    watchdog::Watchdog,
 };
 
 static mut HAL: Hal = unsafe { Hal::new() };
@@ -40,15 +58,55 @@ pub struct Hal {
     /// Sleeps waiting for there are some input in console buffer.
     console: Console,
 
-    printer: Printer,
+    /// The second uart, /dev/ttyS1, if the machine has one.
+    serial: Option<Console>,
+
+    /// The GDB stub, which has the second uart instead of /dev/ttyS1 if QEMU says so.
+    gdb: Option<GdbStub>,
+
+    log: KernelLog,
+
+    trace: TraceBuffers,
+
+    faults: FaultInjector,
+
+    fuzz: FuzzCounters,
+
+    profiler: Profiler,
+
+    watchdog: Watchdog,
+
+    #[pin]
+    suspend: Suspend,
 
     #[pin]
     kmem: SpinLock<Kmem>,
 
+    #[pin]
+    pagecache: SpinLock<PageCache>,
+
     cpus: Cpus,
 
+    cputimes: CpuTimes,
+
+    psi: Psi,
+
+    cpuidle: CpuIdle,
+
+    ipis: Ipis,
+
+    irqs: Irqs,
+
+    softirqs: SoftIrqs,
+
     #[pin]
     disk: SleepableLock<VirtioDisk>,
+
+    #[pin]
+    rng: SpinLock<VirtioRng>,
+
+    #[pin]
+    vsock: SleepableLock<Vsock>,
 }
 
 impl Hal {
@@ -58,10 +116,27 @@ impl Hal {
     const unsafe fn new() -> Self {
         Self {
             console: unsafe { Console::new(UART0) },
-            printer: Printer::new(),
+            serial: None,
+            gdb: None,
+            log: KernelLog::new(),
+            trace: TraceBuffers::new(),
+            faults: FaultInjector::new(),
+            fuzz: FuzzCounters::new(),
+            profiler: Profiler::new(),
+            watchdog: Watchdog::new(),
+            suspend: Suspend::new(),
             kmem: SpinLock::new("KMEM", unsafe { Kmem::new() }),
+            pagecache: SpinLock::new("PAGECACHE", unsafe { PageCache::new() }),
             cpus: Cpus::new(),
+            cputimes: CpuTimes::new(),
+            psi: Psi::new(),
+            cpuidle: CpuIdle::new(),
+            ipis: Ipis::new(),
+            irqs: Irqs::new(),
+            softirqs: SoftIrqs::new(),
             disk: SleepableLock::new("DISK", unsafe { VirtioDisk::new() }),
+            rng: SpinLock::new("RNG", VirtioRng::new()),
+            vsock: SleepableLock::new("VSOCK", Vsock::new()),
         }
     }
 
@@ -70,24 +145,100 @@ impl Hal {
     /// # Safety
     ///
     /// This method must be called only once.
-    unsafe fn init(self: Pin<&mut Self>) {
-        let this = self.project();
+    unsafe fn init(mut self: Pin<&mut Self>) {
+        let this = self.as_mut().project();
 
         // Console.
         this.console.init();
+        // SAFETY: this function is called only once.
+        unsafe { this.console.init_output() };
+        this.irqs
+            .request(IrqChip::Plic, UART0_IRQ, "uart0", console_intr);
+
+        // The supervisor software interrupt, which timervec raises for timer interrupts and IPIs.
+        this.irqs
+            .request(IrqChip::Cpu, CPU_SOFTWARE, "ipi", ipi_intr);
+        this.irqs
+            .request(IrqChip::Cpu, CPU_SOFTWARE, "timer", timer_intr);
 
         // Physical page allocator.
         unsafe { this.kmem.get_pin_mut().init() };
+        this.pagecache.get_pin_mut().init();
 
-        this.disk.get_pin_mut().as_ref().init();
+        // The other devices, by the drivers registered for them.
+        probe_devices(self);
+
+        // The root file system is on the disk.
+        assert!(
+            virtio_device_id(VIRTIO0) == Some(VIRTIO_ID_BLOCK),
+            "could not find virtio disk"
+        );
+    }
+
+    /// Gives the second uart to /dev/ttyS1.
+    pub fn set_serial(self: Pin<&mut Self>, serial: Console) {
+        *self.project().serial = Some(serial);
+    }
+
+    /// Gives the second uart to the GDB stub.
+    pub fn set_gdb(self: Pin<&mut Self>, gdb: GdbStub) {
+        *self.project().gdb = Some(gdb);
     }
 
     pub fn console(&self) -> &Console {
         &self.console
     }
 
-    pub fn printer(&self) -> &Printer {
-        &self.printer
+    pub fn serial(&self) -> Option<&Console> {
+        self.serial.as_ref()
+    }
+
+    /// Adds `handler`, named `name`, to the handlers of `line` of `chip`.
+    pub fn request_irq(
+        self: Pin<&mut Self>,
+        chip: IrqChip,
+        line: usize,
+        name: &'static str,
+        handler: IrqHandler,
+    ) {
+        self.project().irqs.request(chip, line, name, handler);
+    }
+
+    pub fn irqs(&self) -> &Irqs {
+        &self.irqs
+    }
+
+    pub fn gdb(&self) -> Option<&GdbStub> {
+        self.gdb.as_ref()
+    }
+
+    pub fn log(&self) -> &KernelLog {
+        &self.log
+    }
+
+    pub fn trace(&self) -> &TraceBuffers {
+        &self.trace
+    }
+
+    pub fn faults(&self) -> &FaultInjector {
+        &self.faults
+    }
+
+    pub fn fuzz(&self) -> &FuzzCounters {
+        &self.fuzz
+    }
+
+    pub fn profiler(&self) -> &Profiler {
+        &self.profiler
+    }
+
+    pub fn watchdog(&self) -> &Watchdog {
+        &self.watchdog
+    }
+
+    pub fn suspend(self: Pin<&Self>) -> Pin<&Suspend> {
+        // SAFETY: `HAL` is never moved inside this module, and only shared references are exposed.
+        unsafe { Pin::new_unchecked(&self.get_ref().suspend) }
     }
 
     pub fn kmem(self: Pin<&Self>) -> Pin<&SpinLock<Kmem>> {
@@ -95,12 +246,59 @@ impl Hal {
         unsafe { Pin::new_unchecked(&self.get_ref().kmem) }
     }
 
+    pub fn pagecache(self: Pin<&Self>) -> Pin<&SpinLock<PageCache>> {
+        // SAFETY: `HAL` is never moved inside this module, and only shared references are exposed.
+        unsafe { Pin::new_unchecked(&self.get_ref().pagecache) }
+    }
+
     pub fn cpus(&self) -> &Cpus {
         &self.cpus
     }
 
+    pub fn cputimes(&self) -> &CpuTimes {
+        &self.cputimes
+    }
+
+    pub fn psi(&self) -> &Psi {
+        &self.psi
+    }
+
+    pub fn cpuidle(&self) -> &CpuIdle {
+        &self.cpuidle
+    }
+
+    pub fn ipis(&self) -> &Ipis {
+        &self.ipis
+    }
+
+    pub fn softirqs(&self) -> &SoftIrqs {
+        &self.softirqs
+    }
+
     pub fn disk(self: Pin<&Self>) -> Pin<&SleepableLock<VirtioDisk>> {
         // SAFETY: `HAL` is never moved inside this module, and only shared references are exposed.
         unsafe { Pin::new_unchecked(&self.get_ref().disk) }
     }
+
+    pub fn rng(self: Pin<&Self>) -> Pin<&SpinLock<VirtioRng>> {
+        // SAFETY: `HAL` is never moved inside this module, and only shared references are exposed.
+        unsafe { Pin::new_unchecked(&self.get_ref().rng) }
+    }
+
+    pub fn vsock(self: Pin<&Self>) -> Pin<&SleepableLock<Vsock>> {
+        // SAFETY: `HAL` is never moved inside this module, and only shared references are exposed.
+        unsafe { Pin::new_unchecked(&self.get_ref().vsock) }
+    }
+
+    pub fn disk_mut(self: Pin<&mut Self>) -> Pin<&mut SleepableLock<VirtioDisk>> {
+        self.project().disk
+    }
+
+    pub fn rng_mut(self: Pin<&mut Self>) -> Pin<&mut SpinLock<VirtioRng>> {
+        self.project().rng
+    }
+
+    pub fn vsock_mut(self: Pin<&mut Self>) -> Pin<&mut SleepableLock<Vsock>> {
+        self.project().vsock
+    }
 }
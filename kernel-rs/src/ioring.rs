@@ -0,0 +1,118 @@
+//! Submission and completion rings, through which a process queues reads, writes and fsyncs
+//! without a system call for each. See `rv6_abi::ioring` for the layout of the ring page.
+//!
+//! ioring_setup() maps the ring page into the process, and ioring_enter() runs the queued
+//! operations one after another in the process, and posts their results. There are no kernel
+//! threads to run them in the background, so ioring_enter() returns once they all have run.
+//!
+//! The process may write anything into the ring page, so the kernel copies each submission
+//! entry out before running it, and reads the indices again after each operation, which may
+//! have read into the ring page.
+
+use core::mem;
+
+use rv6_abi::ioring::{
+    IoringCqe, IoringHeader, IoringSqe, IORING_ADDR, IORING_CQ_ENTRIES, IORING_CQ_OFF,
+    IORING_OP_FSYNC, IORING_OP_NOP, IORING_OP_READ, IORING_OP_WRITE, IORING_SQ_ENTRIES,
+    IORING_SQ_OFF,
+};
+use static_assertions::const_assert_eq;
+use zerocopy::{AsBytes, FromBytes, LayoutVerified};
+
+use crate::{
+    arch::{addr::UVAddr, memlayout::IORING},
+    error::KernelError,
+    file::RcFile,
+    hal::hal,
+    page::Page,
+    proc::KernelCtx,
+};
+
+const_assert_eq!(IORING_ADDR, IORING);
+
+/// Returns the `T` at `off` in the ring page.
+fn at<T: AsBytes + FromBytes>(page: &mut Page, off: usize) -> &mut T {
+    LayoutVerified::<_, T>::new(&mut page[off..off + mem::size_of::<T>()])
+        .expect("ioring: unaligned")
+        .into_mut()
+}
+
+impl KernelCtx<'_, '_> {
+    /// Maps the ring page into the current process.
+    /// Returns Ok(its user address) on success, Err(KernelError) on error.
+    pub fn ioring_setup(&mut self) -> Result<usize, KernelError> {
+        self.proc_mut().memory_mut().map_ioring(hal().kmem())?;
+        Ok(IORING)
+    }
+
+    /// Runs up to `to_submit` queued operations, and posts their results. Stops early when the
+    /// submission ring is empty or the completion ring is full.
+    /// Returns Ok(number of operations run) on success, Err(KernelError) on error.
+    pub fn ioring_enter(&mut self, to_submit: usize) -> Result<usize, KernelError> {
+        let mut submitted = 0;
+        while submitted < to_submit {
+            let ring = self
+                .proc_mut()
+                .memory_mut()
+                .ioring_mut()
+                .ok_or(KernelError::InvalidArgument)?;
+            let header = *at::<IoringHeader>(ring, 0);
+            if header.sq_head == header.sq_tail {
+                break;
+            }
+            if header.cq_tail.wrapping_sub(header.cq_head) >= IORING_CQ_ENTRIES {
+                if submitted == 0 {
+                    return Err(KernelError::Busy);
+                }
+                break;
+            }
+            let i = (header.sq_head % IORING_SQ_ENTRIES) as usize;
+            let sqe = *at::<IoringSqe>(ring, IORING_SQ_OFF + i * mem::size_of::<IoringSqe>());
+            at::<IoringHeader>(ring, 0).sq_head = header.sq_head.wrapping_add(1);
+
+            let res = match self.ioring_run(&sqe) {
+                Ok(n) => n as i32,
+                Err(e) => -e.errno(),
+            };
+
+            let ring = self.proc_mut().memory_mut().ioring_mut().expect("ioring");
+            let cq_tail = at::<IoringHeader>(ring, 0).cq_tail;
+            let i = (cq_tail % IORING_CQ_ENTRIES) as usize;
+            *at::<IoringCqe>(ring, IORING_CQ_OFF + i * mem::size_of::<IoringCqe>()) = IoringCqe {
+                user_data: sqe.user_data,
+                res,
+                flags: 0,
+            };
+            at::<IoringHeader>(ring, 0).cq_tail = cq_tail.wrapping_add(1);
+            submitted += 1;
+        }
+        Ok(submitted)
+    }
+
+    /// Runs the operation of `sqe`.
+    /// Returns Ok(what its system call would return) on success, Err(KernelError) on error.
+    fn ioring_run(&mut self, sqe: &IoringSqe) -> Result<usize, KernelError> {
+        if sqe.opcode == IORING_OP_NOP {
+            return Ok(0);
+        }
+        let f = self
+            .proc()
+            .deref_data()
+            .fds
+            .get(sqe.fd as usize)
+            .ok_or(KernelError::BadFd)? as *const RcFile;
+        if sqe.len > i32::MAX as u32 {
+            return Err(KernelError::InvalidArgument);
+        }
+        let addr = UVAddr::from(sqe.addr as usize);
+        // SAFETY: read, write and fsync will not access proc's fds.
+        unsafe {
+            match sqe.opcode {
+                IORING_OP_READ => (*f).read(addr, sqe.len as i32, self),
+                IORING_OP_WRITE => (*f).write(addr, sqe.len as i32, self),
+                IORING_OP_FSYNC => (*f).fsync(self),
+                _ => Err(KernelError::InvalidArgument),
+            }
+        }
+    }
+}
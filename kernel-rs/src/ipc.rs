@@ -0,0 +1,264 @@
+//! The registry of System V IPC objects of a kind, such as semaphore sets, which processes find by
+//! key and then use by identifier. See `rv6_abi::ipc` for the keys and flags.
+//!
+//! Each kind of object has an `IpcRegistry`, whose objects live in an arena. The registry holds a
+//! reference to each object until the object is removed, and a process that uses an object, e.g.
+//! while it waits on it, holds another. Removing an object makes its key and identifier find
+//! nothing, and wakes up the processes that wait on it. The object itself lives until the last of
+//! them frees its reference, and only then is finalized and its entry reused. Identifiers are not
+//! reused until the counter wraps around, so a stale identifier does not reach a new object.
+
+use core::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+
+use arrayvec::ArrayVec;
+use rv6_abi::ipc::*;
+
+use crate::{
+    arena::{Arena, ArenaObject, ArenaRc, ArrayArena},
+    cred::Caps,
+    error::KernelError,
+    kernel::KernelRef,
+    ktest, ktest_assert,
+    lock::SpinLock,
+    proc::KernelCtx,
+    util::strong_pin::StrongPin,
+};
+
+/// The data of a kind of IPC object.
+pub trait IpcData: 'static + Send + Sync + Unpin {
+    /// The data of an unused entry.
+    const INIT: Self;
+
+    /// Called when the object is removed, while processes may still use it. Wakes them up.
+    fn remove(&self, kernel: KernelRef<'_, '_>);
+
+    /// Called when the last reference to the object is freed.
+    fn finalize(&mut self);
+}
+
+pub struct IpcObject<T> {
+    key: i32,
+    id: i32,
+
+    /// Owner, group, and permissions, as those of a file.
+    uid: u16,
+    gid: u16,
+    mode: u16,
+
+    /// Whether the object was removed.
+    removed: AtomicBool,
+
+    pub data: T,
+}
+
+impl<T: IpcData> const Default for IpcObject<T> {
+    fn default() -> Self {
+        Self {
+            key: IPC_PRIVATE,
+            id: -1,
+            uid: 0,
+            gid: 0,
+            mode: 0,
+            removed: AtomicBool::new(false),
+            data: T::INIT,
+        }
+    }
+}
+
+impl<T: IpcData> ArenaObject for IpcObject<T> {
+    type Ctx<'a, 'id: 'a> = ();
+
+    #[allow(clippy::needless_lifetimes)]
+    fn finalize<'a, 'id: 'a, A: Arena>(&mut self, _: ()) {
+        self.data.finalize();
+    }
+}
+
+impl<T> IpcObject<T> {
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    /// Returns whether the object was removed, which processes that waited on it check once
+    /// they wake up.
+    pub fn is_removed(&self) -> bool {
+        self.removed.load(Ordering::Acquire)
+    }
+}
+
+pub type IpcTable<T, const N: usize> = SpinLock<ArrayArena<IpcObject<T>, N>>;
+
+/// A reference counted pointer to an IPC object.
+pub type RcIpc<T, const N: usize> = ArenaRc<IpcTable<T, N>>;
+
+pub struct IpcRegistry<T: IpcData, const N: usize> {
+    objects: IpcTable<T, N>,
+
+    /// The registry's references to the objects that are not removed.
+    live: SpinLock<ArrayVec<RcIpc<T, N>, N>>,
+
+    /// The identifier of the next object.
+    next_id: AtomicI32,
+}
+
+impl<T: IpcData, const N: usize> IpcRegistry<T, N> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            objects: SpinLock::new(name, ArrayArena::<IpcObject<T>, N>::new(name)),
+            live: SpinLock::new(name, ArrayVec::new_const()),
+            next_id: AtomicI32::new(0),
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    fn objects<'s>(self: StrongPin<'s, Self>) -> StrongPin<'s, IpcTable<T, N>> {
+        unsafe { StrongPin::new_unchecked(&self.as_pin().get_ref().objects) }
+    }
+
+    /// Returns the object with `key`, to which the current process must have the rights in
+    /// `access`. If there is none and `flags` has IPC_CREAT, or `key` is IPC_PRIVATE, makes one
+    /// whose data `init` returns, which the current process owns with the permissions in the low
+    /// bits of `flags`.
+    /// Returns the error of `init` if it fails,
+    /// Err(KernelError::NoEntry) if there is none and `flags` lacks IPC_CREAT,
+    /// Err(KernelError::Exists) if there is one and `flags` has IPC_CREAT and IPC_EXCL,
+    /// Err(KernelError::PermissionDenied) if the process lacks a right in `access`,
+    /// Err(KernelError::NoSpace) if the registry is full.
+    pub fn get<F: FnOnce() -> Result<T, KernelError>>(
+        self: StrongPin<'_, Self>,
+        key: i32,
+        flags: i32,
+        access: u16,
+        init: F,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcIpc<T, N>, KernelError> {
+        let mut live = self.live.lock();
+        if key != IPC_PRIVATE {
+            if let Some(obj) = live.iter().find(|obj| obj.key == key) {
+                if flags & (IPC_CREAT | IPC_EXCL) == IPC_CREAT | IPC_EXCL {
+                    return Err(KernelError::Exists);
+                }
+                if !ctx.may_access(obj.mode, obj.uid, obj.gid, access) {
+                    return Err(KernelError::PermissionDenied);
+                }
+                return Ok(obj.clone());
+            }
+            if flags & IPC_CREAT == 0 {
+                return Err(KernelError::NoEntry);
+            }
+        }
+        if live.is_full() {
+            return Err(KernelError::NoSpace);
+        }
+
+        let data = init()?;
+        let cred = &ctx.proc().deref_data().cred;
+        let (uid, gid) = (cred.euid() as u16, cred.egid as u16);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) & i32::MAX;
+        let obj = self
+            .objects()
+            .alloc(|| {
+                IpcObject {
+                    key,
+                    id,
+                    uid,
+                    gid,
+                    mode: (flags & 0o777) as u16,
+                    removed: AtomicBool::new(false),
+                    data,
+                }
+            })
+            .ok_or(KernelError::NoSpace)?;
+        live.push(obj.clone());
+        Ok(obj)
+    }
+
+    /// Returns the object `id`, to which the current process must have the rights in `access`.
+    /// Returns Err(KernelError::InvalidArgument) if there is no such object,
+    /// Err(KernelError::PermissionDenied) if the process lacks a right in `access`.
+    pub fn lookup(
+        &self,
+        id: i32,
+        access: u16,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<RcIpc<T, N>, KernelError> {
+        let live = self.live.lock();
+        let obj = live
+            .iter()
+            .find(|obj| obj.id == id)
+            .ok_or(KernelError::InvalidArgument)?;
+        if !ctx.may_access(obj.mode, obj.uid, obj.gid, access) {
+            return Err(KernelError::PermissionDenied);
+        }
+        Ok(obj.clone())
+    }
+
+    /// Calls `f` on each object that is not removed.
+    pub fn for_each<F: FnMut(&IpcObject<T>)>(&self, mut f: F) {
+        for obj in self.live.lock().iter() {
+            f(obj);
+        }
+    }
+
+    /// Removes the object `id`, which the current process must own unless it has
+    /// `Caps::SYS_ADMIN`. The object is finalized once the processes that use it free it.
+    /// Returns Err(KernelError::InvalidArgument) if there is no such object,
+    /// Err(KernelError::NotPermitted) if the process may not remove it.
+    pub fn remove(&self, id: i32, ctx: &KernelCtx<'_, '_>) -> Result<(), KernelError> {
+        let mut live = self.live.lock();
+        let i = live
+            .iter()
+            .position(|obj| obj.id == id)
+            .ok_or(KernelError::InvalidArgument)?;
+        let owner = ctx.proc().deref_data().cred.euid() == live[i].uid as u32;
+        if !owner && !ctx.capable(Caps::SYS_ADMIN) {
+            return Err(KernelError::NotPermitted);
+        }
+        let obj = live.swap_remove(i);
+        drop(live);
+        obj.removed.store(true, Ordering::Release);
+        obj.data.remove(ctx.kernel());
+        obj.free(());
+        Ok(())
+    }
+}
+
+ktest! {
+    fn ipc_registry(ctx) {
+        use core::sync::atomic::AtomicUsize;
+
+        static FINALIZED: AtomicUsize = AtomicUsize::new(0);
+        struct Data;
+        impl IpcData for Data {
+            const INIT: Self = Data;
+            fn remove(&self, _kernel: KernelRef<'_, '_>) {}
+            fn finalize(&mut self) {
+                let _ = FINALIZED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        static REGISTRY: IpcRegistry<Data, 2> = IpcRegistry::new("ktest");
+        // SAFETY: `REGISTRY` is static, so it never moves.
+        let registry = unsafe { StrongPin::new_unchecked(&REGISTRY) };
+
+        let obj = registry.get(5, IPC_CREAT | 0o600, 0o6, || Ok(Data), ctx).unwrap();
+        let id = obj.id();
+        obj.free(());
+        let obj = registry.get(5, 0, 0o6, || Ok(Data), ctx).unwrap();
+        ktest_assert!(obj.id() == id);
+        obj.free(());
+        ktest_assert!(matches!(
+            registry.get(5, IPC_CREAT | IPC_EXCL, 0, || Ok(Data), ctx),
+            Err(KernelError::Exists)
+        ));
+        ktest_assert!(matches!(registry.get(6, 0, 0, || Ok(Data), ctx), Err(KernelError::NoEntry)));
+
+        // A removed object lives until its last user frees it.
+        let obj = registry.lookup(id, 0o6, ctx).unwrap();
+        ktest_assert!(registry.remove(id, ctx).is_ok());
+        ktest_assert!(obj.is_removed());
+        ktest_assert!(registry.lookup(id, 0, ctx).is_err());
+        ktest_assert!(FINALIZED.load(Ordering::Relaxed) == 0);
+        obj.free(());
+        ktest_assert!(FINALIZED.load(Ordering::Relaxed) == 1);
+    }
+}
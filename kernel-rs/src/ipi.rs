@@ -0,0 +1,146 @@
+//! Interprocessor interrupts, through which a CPU asks the others to do something for it.
+//!
+//! A CPU interrupts another by writing the other's MSIP register in the CLINT, which raises a
+//! machine-mode software interrupt there. timervec in kernelvec.S takes it even if the CPU has
+//! interrupts disabled, takes a snapshot of where the CPU is if `start::request_snapshot` asked
+//! for one, and forwards it as a supervisor software interrupt, which the CPU takes once it
+//! enables interrupts. The machine has no SBI firmware to send IPIs through, so the kernel
+//! writes the CLINT itself.
+//!
+//! `Ipis::call` queues a function and its argument for another CPU and interrupts it, and the
+//! CPU runs its queued calls from the supervisor software interrupt, with interrupts disabled.
+//! The caller does not wait for them, since the other CPU may be spinning with interrupts
+//! disabled on a lock that the caller holds. A call that is queued already is not queued again,
+//! so a CPU that is slow to take the interrupt does not fill its queue with the same call.
+//!
+//! A CPU that only needs to wake up, such as one that idles in `wfi` while a process became
+//! runnable, is just interrupted with `send_ipi`.
+
+use core::{
+    hint, mem, ptr,
+    sync::atomic::{self, AtomicBool, AtomicUsize, Ordering},
+};
+
+use array_macro::array;
+use arrayvec::ArrayVec;
+
+use crate::{
+    arch::memlayout::clint_msip,
+    cpu::cpuid,
+    hal::hal,
+    irq::IrqReturn,
+    kernel::KernelRef,
+    ktest, ktest_assert,
+    lock::SpinLock,
+    param::NCPU,
+    time::{ktime_now, TICK_NS},
+};
+
+/// Maximum number of calls queued for a CPU.
+const NCALL: usize = 8;
+
+/// A function queued to run on another CPU, with its argument.
+#[derive(Copy, Clone, PartialEq, Eq)]
+struct Call {
+    func: fn(usize),
+    arg: usize,
+}
+
+pub struct Ipis {
+    /// Whether each CPU takes IPIs yet.
+    online: [AtomicBool; NCPU],
+
+    /// The calls queued for each CPU.
+    queues: [SpinLock<ArrayVec<Call, NCALL>>; NCPU],
+}
+
+/// Interrupts the CPU `id` in machine mode.
+pub fn send_ipi(id: usize) {
+    // Make what the CPU is interrupted for visible to it before it takes the interrupt.
+    atomic::fence(Ordering::SeqCst);
+    // SAFETY: the CLINT is identically mapped from physical address, and writing 1 to the MSIP
+    // register of a CPU only raises its machine-mode software interrupt.
+    unsafe { ptr::write_volatile(clint_msip(id) as *mut u32, 1) };
+}
+
+/// Handles the supervisor software interrupt, by running the calls queued for this CPU.
+pub fn ipi_intr(_kernel: KernelRef<'_, '_>) -> IrqReturn {
+    if hal().ipis().handle() {
+        IrqReturn::Handled
+    } else {
+        IrqReturn::None
+    }
+}
+
+impl Ipis {
+    pub const fn new() -> Self {
+        Self {
+            online: array![_ => AtomicBool::new(false); NCPU],
+            queues: array![_ => SpinLock::new("ipi", ArrayVec::new_const()); NCPU],
+        }
+    }
+
+    /// Marks this CPU as taking IPIs. Called by each CPU before it turns on paging.
+    pub fn set_online(&self) {
+        self.online[cpuid()].store(true, Ordering::Release);
+    }
+
+    /// Returns the CPUs other than this one that take IPIs. Interrupts must be off.
+    fn others(&self) -> impl Iterator<Item = usize> + '_ {
+        let me = cpuid();
+        (0..NCPU).filter(move |id| *id != me && self.online[*id].load(Ordering::Acquire))
+    }
+
+    /// Queues `func(arg)` to run on the CPU `id`, and interrupts it.
+    /// Panics if the CPU has `NCALL` other calls queued.
+    pub fn call(&self, id: usize, func: fn(usize), arg: usize) {
+        let call = Call { func, arg };
+        let mut queue = self.queues[id].lock();
+        if !queue.contains(&call) {
+            queue.try_push(call).expect("Ipis::call: queue full");
+        }
+        drop(queue);
+        send_ipi(id);
+    }
+
+    /// Queues `func(arg)` to run on every other CPU that takes IPIs, and interrupts them.
+    pub fn call_others(&self, func: fn(usize), arg: usize) {
+        let intr = hal().cpus().push_off();
+        for id in self.others() {
+            self.call(id, func, arg);
+        }
+        // SAFETY: interrupts were disabled by the `push_off` above.
+        unsafe { hal().cpus().pop_off(intr) };
+    }
+
+    /// Runs the calls queued for this CPU, with interrupts disabled.
+    /// Returns whether there were any.
+    fn handle(&self) -> bool {
+        let calls = mem::take(&mut *self.queues[cpuid()].lock());
+        let any = !calls.is_empty();
+        for call in calls {
+            (call.func)(call.arg);
+        }
+        any
+    }
+}
+
+ktest! {
+    fn ipi_call_self(ctx) {
+        static ARG: AtomicUsize = AtomicUsize::new(0);
+        fn set(arg: usize) {
+            ARG.store(arg, Ordering::Release);
+        }
+
+        // The call runs once this CPU takes the interrupt, as interrupts are enabled here.
+        let intr = hal().cpus().push_off();
+        hal().ipis().call(cpuid(), set, 42);
+        // SAFETY: interrupts were disabled by the `push_off` above.
+        unsafe { hal().cpus().pop_off(intr) };
+        let deadline = ktime_now() + 10 * TICK_NS;
+        while ARG.load(Ordering::Acquire) != 42 && ktime_now() < deadline {
+            hint::spin_loop();
+        }
+        ktest_assert!(ARG.load(Ordering::Acquire) == 42);
+    }
+}
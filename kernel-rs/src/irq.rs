@@ -0,0 +1,366 @@
+//! Routing of interrupts to the handlers that drivers request for them, with statistics of each
+//! interrupt line, which /proc/interrupts reports.
+//!
+//! A line is named by its controller and its number there: the PLIC's lines are the device
+//! interrupts, and the CPU's own line `CPU_SOFTWARE` is the supervisor software interrupt, which
+//! timervec raises for timer interrupts and IPIs. Drivers request their lines while the `Hal` is
+//! initialized, before interrupts are enabled, so the table does not change once it is in use.
+//!
+//! Several handlers may share a line. An interrupt runs all of them, as the line cannot tell which
+//! device raised it, and each returns whether its device had something to do.
+//!
+//! Each line of the PLIC has an affinity, the CPUs for which it is enabled, and the PLIC sends
+//! an interrupt of the line to one of them. Every `BALANCE_NS`, a timer of the first CPU spreads
+//! the lines over the CPUs by the number of interrupts they raised since the last time, so that no
+//! CPU takes all the device interrupts. A line whose affinity was written to
+//! /proc/irq/<n>/affinity is pinned there, and the balancer only counts its load.
+
+use core::{
+    cmp, fmt,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use array_macro::array;
+use arrayvec::ArrayVec;
+use static_assertions::const_assert;
+
+use crate::{
+    arch::plic::{plic_complete, plic_set_enabled, plicinithart},
+    cpu::cpuid,
+    error::KernelError,
+    hal::hal,
+    kernel::KernelRef,
+    lock::SpinLock,
+    param::NCPU,
+    time::ktime_now,
+    timer::Timer,
+};
+
+/// Maximum number of lines that have handlers.
+const NIRQLINE: usize = 16;
+
+/// Maximum number of handlers that share a line.
+const NSHARED: usize = 4;
+
+/// Time between two balancings of the lines of the PLIC, in nanoseconds.
+pub const BALANCE_NS: u64 = 2_000_000_000;
+
+// An affinity has a bit for each CPU.
+const_assert!(NCPU <= 64);
+
+/// The affinity of every CPU.
+const ALL_CPUS: u64 = u64::MAX >> (64 - NCPU);
+
+/// The line of the CPU's controller that is the supervisor software interrupt.
+pub const CPU_SOFTWARE: usize = 1;
+
+/// An interrupt controller.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum IrqChip {
+    /// The Platform Level Interrupt Controller, which the devices interrupt through.
+    Plic,
+    /// The CPU itself.
+    Cpu,
+}
+
+impl fmt::Display for IrqChip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Plic => "plic",
+            Self::Cpu => "cpu",
+        })
+    }
+}
+
+/// What a handler did about an interrupt.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum IrqReturn {
+    /// Its device did not interrupt.
+    None,
+    /// It handled the interrupt of its device.
+    Handled,
+    /// It handled the interrupt, and the time slice of the CPU is over.
+    SliceOver,
+}
+
+/// A handler of an interrupt line.
+pub type IrqHandler = fn(KernelRef<'_, '_>) -> IrqReturn;
+
+/// A line and the handlers that drivers requested for it.
+struct IrqLine {
+    chip: IrqChip,
+    line: usize,
+
+    /// The handlers of the line, with their names.
+    actions: ArrayVec<(&'static str, IrqHandler), NSHARED>,
+
+    /// Number of the line's interrupts that each CPU took.
+    count: [AtomicU64; NCPU],
+
+    /// Time spent in the handlers of the line, in nanoseconds.
+    time: AtomicU64,
+
+    /// The CPUs that the PLIC may send the line's interrupts to, one bit each. Changes only while
+    /// holding `Irqs::online`.
+    affinity: AtomicU64,
+
+    /// Whether the affinity was set by hand, so that the balancer does not move the line.
+    pinned: AtomicBool,
+
+    /// Number of the line's interrupts, of all CPUs, at the last balancing.
+    balanced: AtomicU64,
+}
+
+impl IrqLine {
+    /// Sets the affinity of the line, a line of the PLIC, to `mask`, and its enable bits for the
+    /// CPUs in `online` to match. The caller holds `Irqs::online`.
+    fn route(&self, mask: u64, online: u64) {
+        let old = self.affinity.swap(mask, Ordering::Relaxed);
+        // Enable the line for its new CPUs before disabling it for its old ones, so that an
+        // interrupt raised meanwhile still goes to some CPU.
+        for &(cpus, enabled) in [(mask & !old, true), (old & !mask, false)].iter() {
+            for hart in (0..NCPU).filter(|id| cpus & online & 1 << id != 0) {
+                // SAFETY: the caller holds `Irqs::online`, under which the enable bits change.
+                unsafe { plic_set_enabled(hart, self.line, enabled) };
+            }
+        }
+    }
+}
+
+/// Statistics of an interrupt line.
+pub struct IrqStats<'a> {
+    pub chip: IrqChip,
+    pub line: usize,
+
+    /// The names of the handlers, separated by commas.
+    pub names: IrqNames<'a>,
+
+    /// Number of interrupts that each CPU took.
+    pub count: [u64; NCPU],
+
+    /// Time spent in the handlers, in nanoseconds.
+    pub time: u64,
+}
+
+/// Formats the names of the handlers of a line, separated by commas.
+pub struct IrqNames<'a>(&'a [(&'static str, IrqHandler)]);
+
+impl fmt::Display for IrqNames<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (name, _)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(name)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Irqs {
+    lines: ArrayVec<IrqLine, NIRQLINE>,
+
+    /// The CPUs that enabled their lines of the PLIC, one bit each. Held while the enable bits of
+    /// the lines change.
+    online: SpinLock<u64>,
+}
+
+impl Irqs {
+    pub const fn new() -> Self {
+        Self {
+            lines: ArrayVec::new_const(),
+            online: SpinLock::new("irqs", 0),
+        }
+    }
+
+    /// Adds `handler`, named `name`, to the handlers of `line` of `chip`.
+    /// Panics if there is no room for it.
+    pub fn request(&mut self, chip: IrqChip, line: usize, name: &'static str, handler: IrqHandler) {
+        let i = match self
+            .lines
+            .iter()
+            .position(|l| l.chip == chip && l.line == line)
+        {
+            Some(i) => i,
+            None => {
+                self.lines
+                    .try_push(IrqLine {
+                        chip,
+                        line,
+                        actions: ArrayVec::new(),
+                        count: array![_ => AtomicU64::new(0); NCPU],
+                        time: AtomicU64::new(0),
+                        affinity: AtomicU64::new(ALL_CPUS),
+                        pinned: AtomicBool::new(false),
+                        balanced: AtomicU64::new(0),
+                    })
+                    .expect("Irqs::request: too many lines");
+                self.lines.len() - 1
+            }
+        };
+        self.lines[i]
+            .actions
+            .try_push((name, handler))
+            .expect("Irqs::request: line shared too much");
+    }
+
+    /// Returns the lines of `chip` that have handlers.
+    pub fn lines(&self, chip: IrqChip) -> impl Iterator<Item = usize> + '_ {
+        self.lines
+            .iter()
+            .filter(move |l| l.chip == chip)
+            .map(|l| l.line)
+    }
+
+    fn find(&self, chip: IrqChip, line: usize) -> Option<&IrqLine> {
+        self.lines.iter().find(|l| l.chip == chip && l.line == line)
+    }
+
+    /// Runs the handlers of `line` of `chip` for an interrupt that this CPU took.
+    /// Returns what the handlers did, the most of them, or None if the line has no handlers.
+    pub fn handle(
+        &self,
+        chip: IrqChip,
+        line: usize,
+        kernel: KernelRef<'_, '_>,
+    ) -> Option<IrqReturn> {
+        let l = self.find(chip, line)?;
+        let start = ktime_now();
+        let ret = l
+            .actions
+            .iter()
+            .map(|(_, handler)| handler(kernel))
+            .fold(IrqReturn::None, Ord::max);
+        let _ = l.count[cpuid()].fetch_add(1, Ordering::Relaxed);
+        let _ = l.time.fetch_add(ktime_now() - start, Ordering::Relaxed);
+        Some(ret)
+    }
+
+    /// Returns the statistics of the lines that have handlers.
+    pub fn stats(&self) -> impl Iterator<Item = IrqStats<'_>> {
+        self.lines.iter().map(|l| {
+            IrqStats {
+                chip: l.chip,
+                line: l.line,
+                names: IrqNames(&l.actions),
+                count: array![id => l.count[id].load(Ordering::Relaxed); NCPU],
+                time: l.time.load(Ordering::Relaxed),
+            }
+        })
+    }
+
+    /// Enables the lines of the PLIC whose affinity has this CPU, on this CPU.
+    ///
+    /// # Safety
+    ///
+    /// This method should be called only once by each CPU, after `plicinit`.
+    pub unsafe fn init_hart(&self) {
+        let mut online = self.online.lock();
+        let id = cpuid();
+        *online |= 1 << id;
+        let lines = self
+            .lines
+            .iter()
+            .filter(move |l| {
+                l.chip == IrqChip::Plic && l.affinity.load(Ordering::Relaxed) & 1 << id != 0
+            })
+            .map(|l| l.line);
+        unsafe { plicinithart(lines) };
+    }
+
+    /// Tells the PLIC that this CPU served `line` of the PLIC, so that its device may interrupt
+    /// again. The PLIC ignores the completion of a line that is not enabled for the CPU, so a line
+    /// that moved to other CPUs since this one claimed it is enabled around the completion.
+    ///
+    /// # Safety
+    ///
+    /// This CPU must have claimed an interrupt of `line`, and not completed it yet.
+    pub unsafe fn complete(&self, line: usize) {
+        let _online = self.online.lock();
+        let id = cpuid();
+        let moved = self
+            .find(IrqChip::Plic, line)
+            .map_or(false, |l| l.affinity.load(Ordering::Relaxed) & 1 << id == 0);
+        unsafe {
+            if moved {
+                plic_set_enabled(id, line, true);
+            }
+            plic_complete(line as u32);
+            if moved {
+                plic_set_enabled(id, line, false);
+            }
+        }
+    }
+
+    /// Returns the affinity of `line` of the PLIC, or None if the line has no handlers.
+    pub fn affinity(&self, line: usize) -> Option<u64> {
+        self.find(IrqChip::Plic, line)
+            .map(|l| l.affinity.load(Ordering::Relaxed))
+    }
+
+    /// Sends the interrupts of `line` of the PLIC to the CPUs in `mask` from now on, and pins the
+    /// line there.
+    /// Returns Err(KernelError::NoEntry) if the line has no handlers,
+    /// Err(KernelError::InvalidArgument) if no CPU in `mask` has enabled its lines.
+    pub fn set_affinity(&self, line: usize, mask: u64) -> Result<(), KernelError> {
+        let online = self.online.lock();
+        let l = self.find(IrqChip::Plic, line).ok_or(KernelError::NoEntry)?;
+        let mask = mask & ALL_CPUS;
+        if mask & *online == 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        l.pinned.store(true, Ordering::Relaxed);
+        l.route(mask, *online);
+        Ok(())
+    }
+
+    /// Moves each line of the PLIC that is not pinned to a single CPU, by the number of interrupts
+    /// that the lines raised since the last balancing. From the busiest line on, each goes to the
+    /// CPU with the fewest interrupts so far, then the fewest lines, then the line's current one.
+    /// A pinned line adds its interrupts to its CPUs in equal shares.
+    fn balance(&self) {
+        let online = self.online.lock();
+        let mut load = [0; NCPU];
+        let mut nlines = [0; NCPU];
+        let mut unpinned = ArrayVec::<(u64, &IrqLine), NIRQLINE>::new();
+        for l in self.lines.iter().filter(|l| l.chip == IrqChip::Plic) {
+            let count = l
+                .count
+                .iter()
+                .map(|c| c.load(Ordering::Relaxed))
+                .sum::<u64>();
+            let rate = count - l.balanced.swap(count, Ordering::Relaxed);
+            if l.pinned.load(Ordering::Relaxed) {
+                let cpus = l.affinity.load(Ordering::Relaxed) & *online;
+                for id in (0..NCPU).filter(|id| cpus & 1 << id != 0) {
+                    load[id] += rate / cpus.count_ones() as u64;
+                    nlines[id] += 1;
+                }
+            } else {
+                unpinned.push((rate, l));
+            }
+        }
+
+        unpinned.sort_unstable_by_key(|(rate, l)| (cmp::Reverse(*rate), l.line));
+        for (rate, l) in unpinned {
+            let affinity = l.affinity.load(Ordering::Relaxed);
+            let best = (0..NCPU)
+                .filter(|id| *online & 1 << id != 0)
+                .min_by_key(|id| (load[*id], nlines[*id], affinity & 1 << id == 0, *id));
+            if let Some(id) = best {
+                load[id] += rate;
+                nlines[id] += 1;
+                if affinity != 1 << id {
+                    l.route(1 << id, *online);
+                }
+            }
+        }
+    }
+
+    /// The function of the balancing timer, which comes every `BALANCE_NS`.
+    pub fn rebalance(_timer: Pin<&Timer>, now: u64, _kernel: KernelRef<'_, '_>) -> Option<u64> {
+        hal().irqs().balance();
+        Some(now + BALANCE_NS)
+    }
+}
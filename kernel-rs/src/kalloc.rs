@@ -1,17 +1,28 @@
 //! Physical memory allocator, for user processes,
 //! kernel stacks, page-table pages,
 //! and pipe buffers. Allocates whole 4096-byte pages.
+#[cfg(feature = "leak-check")]
+use core::panic::Location;
 use core::{mem, pin::Pin};
 
 use pin_project::pin_project;
 
+#[cfg(feature = "leak-check")]
+use crate::{arch::addr::Addr, leak::LeakTable};
 use crate::{
     arch::addr::{pgrounddown, pgroundup, PGSIZE},
     arch::memlayout::PHYSTOP,
+    failinject::FaultSite,
+    hal::hal,
     lock::SpinLock,
     page::Page,
     util::intrusive_list::{List, ListEntry, ListNode},
 };
+#[cfg(feature = "kasan")]
+use crate::{
+    kasan::{self, Quarantine},
+    some_or,
+};
 
 extern "C" {
     // first address after kernel.
@@ -19,6 +30,9 @@ extern "C" {
     pub static mut end: [u8; 0];
 }
 
+/// What freed pages are filled with, to catch dangling refs.
+pub const JUNK_FREE: u8 = 1;
+
 #[repr(transparent)]
 #[pin_project]
 struct Run {
@@ -65,6 +79,14 @@ unsafe impl ListNode for Run {
 pub struct Kmem {
     #[pin]
     runs: List<Run>,
+
+    /// Who asked for each allocated page.
+    #[cfg(feature = "leak-check")]
+    leaks: LeakTable,
+
+    /// The most recently freed pages, unmapped.
+    #[cfg(feature = "kasan")]
+    quarantine: Quarantine,
 }
 
 impl Kmem {
@@ -74,6 +96,10 @@ impl Kmem {
     pub const unsafe fn new() -> Self {
         Self {
             runs: unsafe { List::new() },
+            #[cfg(feature = "leak-check")]
+            leaks: LeakTable::new(),
+            #[cfg(feature = "kasan")]
+            quarantine: Quarantine::new(),
         }
     }
 
@@ -101,7 +127,7 @@ impl Kmem {
 
     pub fn free(self: Pin<&Self>, mut page: Page) {
         // Fill with junk to catch dangling refs.
-        page.write_bytes(1);
+        page.write_bytes(JUNK_FREE);
 
         let run = page.as_uninit_mut();
         // SAFETY: `run` will be initialized by the following `init`.
@@ -118,6 +144,8 @@ impl Kmem {
         let run = self.runs().pop_front()?;
         // SAFETY: the invariant of `Kmem`.
         let mut page = unsafe { Page::from_usize(run as _) };
+        #[cfg(feature = "kasan")]
+        kasan::check_poison(&page, mem::size_of::<Run>());
         // fill with junk
         page.write_bytes(5);
         Some(page)
@@ -126,14 +154,58 @@ impl Kmem {
     fn runs(self: Pin<&Self>) -> Pin<&List<Run>> {
         unsafe { Pin::new_unchecked(&self.get_ref().runs) }
     }
+
+    #[cfg(feature = "leak-check")]
+    pub fn leaks(&self) -> &LeakTable {
+        &self.leaks
+    }
 }
 
 impl SpinLock<Kmem> {
     pub fn free(self: Pin<&Self>, page: Page) {
-        self.pinned_lock().get_pin_mut().as_ref().free(page);
+        let mut kmem = self.pinned_lock();
+        #[cfg(feature = "leak-check")]
+        kmem.get_pin_mut()
+            .project()
+            .leaks
+            .record_free(page.addr().into_usize());
+        #[cfg(feature = "kasan")]
+        let page = some_or!(kmem.get_pin_mut().project().quarantine.push(page), return);
+        kmem.get_pin_mut().as_ref().free(page);
     }
 
+    /// Allocates a page, unless a fault is injected. With the `leak-check` feature, the page is
+    /// tagged with the caller. If no page is left, frees the pages of the page cache that no
+    /// process maps, and tries again.
+    #[track_caller]
     pub fn alloc(self: Pin<&Self>) -> Option<Page> {
-        self.pinned_lock().get_pin_mut().as_ref().alloc()
+        if hal().faults().should_fail(FaultSite::Kalloc) {
+            return None;
+        }
+        match self.try_alloc() {
+            Some(page) => Some(page),
+            None if hal().pagecache().shrink(self) => self.try_alloc(),
+            None => None,
+        }
+    }
+
+    #[track_caller]
+    fn try_alloc(self: Pin<&Self>) -> Option<Page> {
+        let mut kmem = self.pinned_lock();
+        let page = kmem.get_pin_mut().as_ref().alloc();
+        // Take back the oldest page of the quarantine if no other page is left.
+        #[cfg(feature = "kasan")]
+        let page = page.or_else(|| {
+            let oldest = kmem.get_pin_mut().project().quarantine.pop()?;
+            kmem.get_pin_mut().as_ref().free(oldest);
+            kmem.get_pin_mut().as_ref().alloc()
+        });
+        let page = page?;
+        #[cfg(feature = "leak-check")]
+        kmem.get_pin_mut()
+            .project()
+            .leaks
+            .record_alloc(page.addr().into_usize(), Location::caller());
+        Some(page)
     }
 }
@@ -0,0 +1,104 @@
+//! Checks of the kernel allocators, built in with the `kasan` feature (`make KASAN=yes`), so that
+//! memory corruption faults where it happens instead of corrupting unrelated state.
+//!
+//! * A freed page is filled with `JUNK_FREE` and unmapped from the kernel's direct map, and waits
+//!   in a quarantine of the `QUARANTINE_LEN` most recently freed pages. Using it meanwhile takes a
+//!   page fault, which `kernel_trap` reports as a use after free. Other CPUs may still reach the
+//!   page until they take the TLB shootdown IPI. When the page leaves the quarantine it is mapped again, and
+//!   the allocator panics on allocating it unless it is still filled with `JUNK_FREE`.
+//! * Each entry in the chunks of a `ChunkedArena` is followed by a redzone of at least `REDZONE`
+//!   bytes filled with `REDZONE_BYTE`. The arena panics if the redzones around an entry are
+//!   overwritten when it allocates or frees the entry, and checks every redzone of a chunk before
+//!   it frees the chunk. The statically allocated entries of arenas have no redzones.
+
+#![cfg_attr(not(feature = "kasan"), allow(dead_code, unused_imports))]
+
+use crate::{
+    arch::addr::{Addr, KVAddr},
+    arch::memlayout::{KERNBASE, PHYSTOP},
+    kalloc::JUNK_FREE,
+    page::Page,
+    vm::{set_kernel_mapped, KernelMemory},
+};
+
+/// Number of freed pages that the quarantine keeps unmapped.
+const QUARANTINE_LEN: usize = 64;
+
+/// Number of bytes of the redzone after each entry of a `ChunkedArena` chunk.
+pub const REDZONE: usize = if cfg!(feature = "kasan") { 16 } else { 0 };
+
+/// What redzones are filled with.
+pub const REDZONE_BYTE: u8 = 0xbb;
+
+/// The most recently freed pages, unmapped, kept with the page allocator.
+pub struct Quarantine {
+    /// Addresses of the pages, in a ring, oldest first from `head`.
+    pages: [usize; QUARANTINE_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl Quarantine {
+    pub const fn new() -> Self {
+        Self {
+            pages: [0; QUARANTINE_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Poisons `page`, unmaps it, and keeps it. Returns the oldest page, mapped again, if the
+    /// quarantine was full, or `page` itself if paging is not on yet.
+    pub fn push(&mut self, mut page: Page) -> Option<Page> {
+        page.write_bytes(JUNK_FREE);
+        // SAFETY: we own the page, and it is not used until it leaves the quarantine.
+        if !unsafe { set_kernel_mapped(page.addr(), false) } {
+            return Some(page);
+        }
+        let oldest = if self.len == QUARANTINE_LEN {
+            self.pop()
+        } else {
+            None
+        };
+        self.pages[(self.head + self.len) % QUARANTINE_LEN] = page.into_usize();
+        self.len += 1;
+        oldest
+    }
+
+    /// Maps the oldest page again, and returns it.
+    pub fn pop(&mut self) -> Option<Page> {
+        if self.len == 0 {
+            return None;
+        }
+        let pa = self.pages[self.head];
+        self.head = (self.head + 1) % QUARANTINE_LEN;
+        self.len -= 1;
+        // SAFETY: the page was unmapped by `push`.
+        let _ = unsafe { set_kernel_mapped(pa.into(), true) };
+        // SAFETY: `pa` is the address of a page that `push` took.
+        Some(unsafe { Page::from_usize(pa) })
+    }
+}
+
+/// Panics unless the free `page` is still filled with `JUNK_FREE`, past its first `skip` bytes,
+/// which the page allocator uses.
+pub fn check_poison(page: &Page, skip: usize) {
+    if let Some(off) = page[skip..].iter().position(|b| *b != JUNK_FREE) {
+        panic!(
+            "kasan: page {:#x} written after free, at offset {:#x}",
+            page.addr().into_usize(),
+            skip + off
+        );
+    }
+}
+
+/// Panics if the page fault at `va` of `scause` is in a freed page of RAM, which only the
+/// quarantine unmaps.
+pub fn check_fault(memory: &KernelMemory, scause: usize, va: usize, sepc: usize) {
+    if matches!(scause, 13 | 15)
+        && (KERNBASE..PHYSTOP).contains(&va)
+        && memory.translate(KVAddr::from(va)).is_none()
+    {
+        panic!("kasan: use after free at {:#x}, sepc={:#x}", va, sepc);
+    }
+}
@@ -0,0 +1,282 @@
+//! Crash dumps, which the CPU that panicked writes to the dump partition of the disk, so that
+//! panics that nobody watched, such as in CI, can be analyzed afterwards.
+//!
+//! Given `-fw_cfg name=opt/rv6/kdump,string=on`, as `make qemu KDUMP=yes` does, a panic writes a
+//! dump to the last `DUMPSIZE` blocks of the disk, which mkfs reserves past the file system and
+//! marks with `KDUMP_EMPTY`. Given `string=poweroff`, the machine powers off afterwards. The
+//! dump is a `DumpHeader` in the first block, with the panic message and the registers of the
+//! CPU, followed by the kernel log, the stack of the CPU, and as much of the kernel's data as
+//! fits. `make dump` prints it from fs.img with kdump/kdump on the host.
+//!
+//! The disk may be in any state when the kernel panics, so the CPU resets it, and polls it
+//! instead of waiting for its interrupts. The other CPUs stop at their next interrupt.
+
+use core::{
+    cmp,
+    fmt::{self, Write},
+    panic::PanicInfo,
+    pin::Pin,
+    slice,
+};
+
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::{
+        addr::{pgrounddown, PGSIZE},
+        fw_cfg::FwCfgFile,
+        poweroff::machine_poweroff,
+        riscv::{r_fp, r_satp, r_scause, r_sepc, r_sp, r_stval, r_tp, Sstatus},
+    },
+    cpu::cpuid,
+    hal::hal,
+    kalloc::end,
+    kernel::Kernel,
+    param::{BSIZE, DUMPSIZE},
+    some_or,
+    time::ktime_now,
+    virtio::VirtioDisk,
+};
+
+extern "C" {
+    // kernel.ld sets this to end of kernel code.
+    static etext: [u8; 0];
+}
+
+/// Name of the QEMU file that turns crash dumps on.
+const KDUMP_OPTION: &str = "opt/rv6/kdump";
+
+/// Magic of a dump partition without a dump.
+const KDUMP_EMPTY: u32 = 0x6b647030;
+/// Magic of a dump.
+const KDUMP_MAGIC: u32 = 0x6b647031;
+
+const NREGS: usize = 8;
+const NREGION: usize = 2;
+const MSG_LEN: usize = 256;
+
+/// `struct kdumpregion` of kernel/kdump.h.
+#[derive(Copy, Clone, Default, AsBytes)]
+#[repr(C)]
+struct DumpRegion {
+    addr: u64,
+    len: u64,
+    /// First block in the partition.
+    block: u64,
+}
+
+/// `struct kdumphdr` of kernel/kdump.h.
+#[derive(AsBytes)]
+#[repr(C)]
+struct DumpHeader {
+    magic: u32,
+    cpu: u32,
+    /// Time since boot, in nanoseconds.
+    uptime: u64,
+    /// sp, fp, tp, sepc, scause, stval, sstatus, and satp.
+    regs: [u64; NREGS],
+    log_block: u64,
+    log_len: u64,
+    nregion: u32,
+    pad: u32,
+    /// The stack, and the kernel's data.
+    regions: [DumpRegion; NREGION],
+    /// The panic message, NUL-terminated if shorter.
+    msg: [u8; MSG_LEN],
+}
+
+/// The block that the dump is written from. The disk reads it by DMA, so it is in the kernel's
+/// data, which is identically mapped, rather than on the stack.
+static mut BLOCK: [u8; BSIZE] = [0; BSIZE];
+
+/// Formats the panic message into a buffer, truncating it.
+struct MsgWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Write for MsgWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Leave the last byte for the NUL.
+        let len = cmp::min(s.len(), self.buf.len() - 1 - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+/// Writes the dump to the partition, block after block.
+struct DumpWriter<'a> {
+    disk: Pin<&'a mut VirtioDisk>,
+
+    /// The first block of the partition on the disk.
+    start: u32,
+
+    /// The next block to write, in the partition.
+    next: usize,
+}
+
+impl DumpWriter<'_> {
+    /// Writes `BLOCK` to the block `block` of the partition.
+    fn write_block(&mut self, block: usize) -> Result<(), ()> {
+        // SAFETY: only the CPU that panicked uses `BLOCK`.
+        let data = unsafe { &mut BLOCK };
+        if self
+            .disk
+            .as_mut()
+            .rw_polled(self.start + block as u32, data, true)
+        {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Writes as much of `data` as fits from the next block on, zero-padding the last block.
+    /// Returns its region.
+    fn write(&mut self, data: &[u8]) -> Result<DumpRegion, ()> {
+        let data = &data[..cmp::min(data.len(), (DUMPSIZE - self.next) * BSIZE)];
+        let region = DumpRegion {
+            addr: data.as_ptr() as u64,
+            len: data.len() as u64,
+            block: self.next as u64,
+        };
+        for chunk in data.chunks(BSIZE) {
+            // SAFETY: only the CPU that panicked uses `BLOCK`.
+            let block = unsafe { &mut BLOCK };
+            block[..chunk.len()].copy_from_slice(chunk);
+            block[chunk.len()..].fill(0);
+            self.write_block(self.next)?;
+            self.next += 1;
+        }
+        Ok(region)
+    }
+
+    /// Writes the kernel log from the next block on.
+    /// Returns its length.
+    fn write_log(&mut self) -> Result<u64, ()> {
+        let mut off = 0;
+        let mut len = 0;
+        while self.next < DUMPSIZE {
+            // SAFETY: only the CPU that panicked uses `BLOCK`. The other CPUs do not write the
+            // log once a CPU panicked, as the kernel prints directly to the console.
+            let (start, n) = unsafe { hal().log().read_unlocked(off, &mut BLOCK) };
+            if n == 0 {
+                break;
+            }
+            // SAFETY: only the CPU that panicked uses `BLOCK`.
+            unsafe { BLOCK[n..].fill(0) };
+            self.write_block(self.next)?;
+            self.next += 1;
+            off = start + n as u64;
+            len += n as u64;
+        }
+        Ok(len)
+    }
+}
+
+/// Writes a crash dump for the panic `info`, if QEMU says so. Must be called only by the CPU
+/// that panicked, with interrupts disabled.
+pub fn dump(kernel: Pin<&Kernel>, info: &PanicInfo<'_>) {
+    let file = some_or!(FwCfgFile::find(KDUMP_OPTION), return);
+    let mut option = [0; 8];
+    let len = cmp::min(file.size(), option.len());
+    file.read(&mut option[..len]);
+
+    let mut header = DumpHeader {
+        magic: KDUMP_MAGIC,
+        cpu: cpuid() as u32,
+        uptime: ktime_now(),
+        regs: [
+            r_sp() as u64,
+            r_fp() as u64,
+            r_tp() as u64,
+            r_sepc() as u64,
+            r_scause() as u64,
+            r_stval() as u64,
+            Sstatus::read().bits() as u64,
+            r_satp() as u64,
+        ],
+        log_block: 0,
+        log_len: 0,
+        nregion: NREGION as u32,
+        pad: 0,
+        regions: [DumpRegion::default(); NREGION],
+        msg: [0; MSG_LEN],
+    };
+    let _ = write!(
+        MsgWriter {
+            buf: &mut header.msg,
+            len: 0
+        },
+        "{}",
+        info
+    );
+
+    match write_dump(&mut header) {
+        Ok(blocks) => kernel.write_fmt(format_args!("kdump: wrote {} blocks\n", blocks)),
+        Err(()) => kernel.write_str("kdump: failed\n"),
+    }
+    if &option == b"poweroff" {
+        machine_poweroff(1);
+    }
+}
+
+/// Writes the dump with `header`.
+/// Returns the number of blocks written.
+fn write_dump(header: &mut DumpHeader) -> Result<usize, ()> {
+    // SAFETY: the other CPUs stop before they use the disk again, as a CPU panicked.
+    let mut disk = unsafe { Pin::new_unchecked(&mut *hal().disk().get_mut_raw()) };
+    unsafe { disk.as_mut().reset() };
+    let capacity = disk.capacity() as usize;
+    if capacity < DUMPSIZE {
+        return Err(());
+    }
+    let mut writer = DumpWriter {
+        disk,
+        start: (capacity - DUMPSIZE) as u32,
+        next: 1,
+    };
+
+    // Write only to a partition that mkfs marked.
+    // SAFETY: only the CPU that panicked uses `BLOCK`.
+    if !writer
+        .disk
+        .as_mut()
+        .rw_polled(writer.start, unsafe { &mut BLOCK }, false)
+    {
+        return Err(());
+    }
+    // SAFETY: only the CPU that panicked uses `BLOCK`.
+    let magic = u32::from_le_bytes(unsafe { [BLOCK[0], BLOCK[1], BLOCK[2], BLOCK[3]] });
+    if magic != KDUMP_EMPTY && magic != KDUMP_MAGIC {
+        return Err(());
+    }
+
+    // Until the header is written, the partition has no dump.
+    // SAFETY: only the CPU that panicked uses `BLOCK`.
+    unsafe {
+        BLOCK.fill(0);
+        BLOCK[..4].copy_from_slice(&KDUMP_EMPTY.to_le_bytes());
+    }
+    writer.write_block(0)?;
+
+    header.log_block = writer.next as u64;
+    header.log_len = writer.write_log()?;
+
+    let sp = r_sp();
+    // SAFETY: the kernel stack of this CPU is a page, which contains sp.
+    let stack = unsafe { slice::from_raw_parts(pgrounddown(sp) as *const u8, PGSIZE) };
+    header.regions[0] = writer.write(stack)?;
+    // SAFETY: kernel.ld places the kernel's data between `etext` and `end`.
+    let data = unsafe {
+        let start = etext.as_ptr();
+        slice::from_raw_parts(start, end.as_ptr().offset_from(start) as usize)
+    };
+    header.regions[1] = writer.write(data)?;
+
+    let blocks = writer.next;
+    writer.next = 0;
+    let _ = writer.write(header.as_bytes())?;
+    Ok(blocks)
+}
@@ -2,29 +2,47 @@ use core::fmt::{self, Write};
 use core::mem::MaybeUninit;
 use core::ops::Deref;
 use core::pin::Pin;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use pin_project::pin_project;
 
 use crate::util::strong_pin::StrongPin;
 use crate::{
-    arch::plic::{plicinit, plicinithart},
+    arch::plic::plicinit,
+    arch::riscv::intr_off,
+    audit::AuditLog,
+    backtrace::print_backtrace,
     bio::Bcache,
-    console::{console_read, console_write},
+    chardev::init_devsw,
+    console::Printer,
     cpu::cpuid,
     file::{Devsw, FileTable},
-    fs::{FileSystem, Ufs},
+    fs::{FsRef, Vfs},
     hal::{hal, hal_init},
+    irq::IrqChip,
     kalloc::Kmem,
-    lock::{SleepableLock, SpinLock},
+    kdump,
+    kmsg::Level,
+    lock::SpinLock,
+    log,
+    mqueue::MqRegistry,
     param::NDEV,
+    pipe::PipeTable,
+    poll::PollQueue,
     proc::Procs,
+    random::Random,
+    sem::SemRegistry,
+    stack_protector::{random_guard, set_stack_guard},
+    time::Clocks,
+    timer::TimerQueue,
     trap::{trapinit, trapinithart},
     util::{branded::Branded, spin_loop},
     vm::KernelMemory,
+    workqueue::{worker, WorkQueue, NWORKER},
 };
 
-const CONSOLE_IN_DEVSW: usize = 1;
+/// Value of `Kernel::panicked` while no CPU has panicked.
+const NOT_PANICKED: usize = usize::MAX;
 
 /// The kernel.
 static mut KERNEL: Kernel = unsafe { Kernel::new() };
@@ -67,12 +85,28 @@ unsafe fn kernel_mut_unchecked<'s>() -> Pin<&'s mut Kernel> {
 /// the `Proc` in `CurrentProc` is always valid while the `Kernel` is alive.
 #[pin_project]
 pub struct Kernel {
-    panicked: AtomicBool,
+    /// The CPU that panicked, or `NOT_PANICKED`.
+    panicked: AtomicUsize,
 
     /// The kernel's memory manager.
     memory: MaybeUninit<KernelMemory>,
 
-    ticks: SleepableLock<u32>,
+    clocks: Clocks,
+
+    random: Random,
+
+    /// Security-relevant events.
+    audit: AuditLog,
+
+    /// Processes waiting in poll().
+    poll_queue: PollQueue,
+
+    /// Work queued for the kernel's workers.
+    workqueue: WorkQueue,
+
+    /// Armed kernel timers.
+    #[pin]
+    timers: TimerQueue,
 
     /// Current process system.
     #[pin]
@@ -87,7 +121,18 @@ pub struct Kernel {
     ftable: FileTable,
 
     #[pin]
-    file_system: Ufs,
+    pipes: PipeTable,
+
+    /// System V semaphore sets.
+    #[pin]
+    sems: SemRegistry,
+
+    /// Message queues.
+    #[pin]
+    mqueues: MqRegistry,
+
+    #[pin]
+    vfs: Vfs,
 }
 
 /// A branded reference to a `Kernel`.
@@ -118,9 +163,40 @@ impl<'id, 's> KernelRef<'id, 's> {
         self.0.into_inner().as_pin()
     }
 
-    /// Returns a reference to the kernel's ticks.
-    pub fn ticks(&self) -> &'s SleepableLock<u32> {
-        &self.0.as_pin().get_ref().ticks
+    /// Returns a reference to the kernel's `Clocks`.
+    pub fn clocks(&self) -> &'s Clocks {
+        &self.0.as_pin().get_ref().clocks
+    }
+
+    /// Returns a reference to the kernel's `KernelMemory`.
+    pub fn memory(&self) -> &'s KernelMemory {
+        // SAFETY: a `KernelRef` exists only after the kernel is initialized.
+        unsafe { self.0.as_pin().get_ref().memory.assume_init_ref() }
+    }
+
+    /// Returns a reference to the kernel's `Random`.
+    pub fn random(&self) -> &'s Random {
+        &self.0.as_pin().get_ref().random
+    }
+
+    /// Returns a reference to the kernel's `AuditLog`.
+    pub fn audit(&self) -> &'s AuditLog {
+        &self.0.as_pin().get_ref().audit
+    }
+
+    /// Returns a reference to the kernel's `PollQueue`.
+    pub fn poll_queue(&self) -> &'s PollQueue {
+        &self.0.as_pin().get_ref().poll_queue
+    }
+
+    /// Returns a reference to the kernel's `WorkQueue`.
+    pub fn workqueue(&self) -> &'s WorkQueue {
+        &self.0.as_pin().get_ref().workqueue
+    }
+
+    /// Returns a reference to the kernel's `TimerQueue`.
+    pub fn timers(&self) -> Pin<&'s TimerQueue> {
+        unsafe { Pin::new_unchecked(&self.0.as_pin().get_ref().timers) }
     }
 
     pub fn ps(&self) -> Pin<&'s Procs> {
@@ -136,14 +212,33 @@ impl<'id, 's> KernelRef<'id, 's> {
         &self.0.as_pin().get_ref().devsw
     }
 
-    /// Returns a reference to the kernel's `FileSystem`.
-    pub fn fs(&self) -> StrongPin<'s, Ufs> {
-        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().file_system) }
+    /// Returns the kernel's virtual file system.
+    pub fn vfs(&self) -> StrongPin<'s, Vfs> {
+        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().vfs) }
+    }
+
+    /// Returns the file system mounted on the root device.
+    pub fn fs(&self) -> FsRef<'s> {
+        self.vfs().root()
     }
 
     pub fn ftable(&self) -> StrongPin<'s, FileTable> {
         unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().ftable) }
     }
+
+    pub fn pipes(&self) -> StrongPin<'s, PipeTable> {
+        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().pipes) }
+    }
+
+    /// Returns the kernel's registry of semaphore sets.
+    pub fn sems(&self) -> StrongPin<'s, SemRegistry> {
+        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().sems) }
+    }
+
+    /// Returns the kernel's registry of message queues.
+    pub fn mqueues(&self) -> StrongPin<'s, MqRegistry> {
+        unsafe { StrongPin::new_unchecked(&self.0.as_pin().get_ref().mqueues) }
+    }
 }
 
 impl<'id, 's> Deref for KernelRef<'id, 's> {
@@ -160,17 +255,22 @@ impl Kernel {
     /// Must be used only after initializing it with `Kernel::init`.
     const unsafe fn new() -> Self {
         Self {
-            panicked: AtomicBool::new(false),
+            panicked: AtomicUsize::new(NOT_PANICKED),
             memory: MaybeUninit::uninit(),
-            ticks: SleepableLock::new("time", 0),
+            clocks: Clocks::new(),
+            random: Random::new(),
+            audit: AuditLog::new(),
+            poll_queue: PollQueue::new(),
+            workqueue: WorkQueue::new(),
+            timers: TimerQueue::new(),
             procs: Procs::new(),
             bcache: unsafe { Bcache::new_bcache() },
-            devsw: [Devsw {
-                read: None,
-                write: None,
-            }; NDEV],
-            ftable: FileTable::new_ftable(),
-            file_system: Ufs::new(),
+            devsw: [Devsw::NONE; NDEV],
+            ftable: unsafe { FileTable::new_ftable() },
+            pipes: unsafe { PipeTable::new_pipes() },
+            sems: SemRegistry::new("semsets"),
+            mqueues: MqRegistry::new("mqueues"),
+            vfs: Vfs::new(),
         }
     }
 
@@ -184,18 +284,24 @@ impl Kernel {
 
         let mut this = self.project();
 
-        // Connect read and write system calls to consoleread and consolewrite.
-        this.devsw[CONSOLE_IN_DEVSW] = Devsw {
-            read: Some(console_read),
-            write: Some(console_write),
-        };
+        // Connect the device files to their drivers.
+        init_devsw(this.devsw);
 
         // Create kernel memory manager.
         let memory = KernelMemory::new(allocator).expect("PageTable::new failed");
 
+        // Take IPIs, such as TLB shootdowns, from now on.
+        hal().ipis().set_online();
+
         // Turn on paging.
         unsafe { this.memory.write(memory).init_hart() };
 
+        // Wall-clock time of boot.
+        this.clocks.init();
+
+        // Random number generator.
+        this.random.init();
+
         // Process system.
         this.procs.as_mut().init();
 
@@ -205,18 +311,37 @@ impl Kernel {
         // Install kernel trap vector.
         unsafe { trapinithart() };
 
+        // Clock tick.
+        this.timers.as_ref().start_tick();
+
+        // Averages of the pressure stall information.
+        this.timers.as_ref().start_psi();
+
         // Set up interrupt controller.
-        unsafe { plicinit() };
+        unsafe { plicinit(hal().irqs().lines(IrqChip::Plic)) };
 
         // Ask PLIC for device interrupts.
-        unsafe { plicinithart() };
+        unsafe { hal().irqs().init_hart() };
+
+        // Spread the device interrupts over the CPUs.
+        this.timers.as_ref().start_balance();
 
         // Buffer cache.
-        this.bcache.get_pin_mut().init();
+        this.bcache.init();
+
+        // File table and pipes.
+        this.ftable.get_pin_mut().init();
+        this.pipes.get_pin_mut().init();
 
         // First user process.
-        let fs = unsafe { StrongPin::new_unchecked(this.file_system.as_ref().get_ref()) };
-        this.procs.user_proc_init(fs.root(), allocator);
+        this.procs.as_mut().user_proc_init(allocator);
+
+        // Workers of the workqueue.
+        for i in 0..NWORKER {
+            let mut name = *b"kworker/0\x00";
+            name[8] += i as u8;
+            this.procs.as_ref().spawn_kthread(&name, worker, allocator);
+        }
     }
 
     /// Initializes the kernel for a hart.
@@ -225,7 +350,10 @@ impl Kernel {
     ///
     /// This method should be called only once by each hart.
     unsafe fn inithart(self: Pin<&Self>) {
-        self.write_fmt(format_args!("hart {} starting\n", cpuid()));
+        log!(Level::Info, "kernel", "hart {} starting", cpuid());
+
+        // Take IPIs, such as TLB shootdowns, from now on.
+        hal().ipis().set_online();
 
         // Turn on paging.
         unsafe { self.memory.assume_init_ref().init_hart() };
@@ -234,25 +362,36 @@ impl Kernel {
         unsafe { trapinithart() };
 
         // Ask PLIC for device interrupts.
-        unsafe { plicinithart() };
+        unsafe { hal().irqs().init_hart() };
     }
 
-    fn panic(self: Pin<&Self>) {
-        self.panicked.store(true, Ordering::Release);
+    /// Marks the kernel as panicked by this CPU, which must have interrupts disabled.
+    /// Returns `false` if a CPU already panicked.
+    fn panic(self: Pin<&Self>) -> bool {
+        self.panicked
+            .compare_exchange(NOT_PANICKED, cpuid(), Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
     }
 
     pub fn is_panicked(self: Pin<&Self>) -> bool {
-        self.panicked.load(Ordering::Acquire)
+        self.panicked.load(Ordering::Acquire) != NOT_PANICKED
     }
 
-    /// Prints the given formatted string with the Printer.
+    /// Returns `true` if a CPU other than this one panicked, so this one must stop.
+    /// Interrupts must be disabled.
+    pub fn is_panicked_elsewhere(self: Pin<&Self>) -> bool {
+        let cpu = self.panicked.load(Ordering::Acquire);
+        cpu != NOT_PANICKED && cpu != cpuid()
+    }
+
+    /// Prints the given formatted string to the kernel log, and the console.
     pub fn write_fmt(self: Pin<&Self>, args: fmt::Arguments<'_>) {
-        let mut guard = if self.is_panicked() {
-            hal().get_ref().printer().without_lock(self)
+        if self.is_panicked() {
+            let _ = Printer.write_fmt(args);
         } else {
-            hal().get_ref().printer().lock(self)
-        };
-        let _ = guard.write_fmt(args);
+            hal().log().write_fmt(args);
+            hal().log().drain(self);
+        }
     }
 
     pub fn write_str(self: Pin<&Self>, s: &str) {
@@ -260,17 +399,35 @@ impl Kernel {
     }
 }
 
-/// Handles panic by freezing other CPUs.
+/// Handles panic by freezing other CPUs, and printing the message with a backtrace.
+/// A panic while another CPU's panic is printed only freezes the CPU. A panic while this CPU's
+/// panic is printed prints its message once, and freezes the CPU.
 #[cfg(not(test))]
 #[panic_handler]
 fn panic_handler(info: &core::panic::PanicInfo<'_>) -> ! {
+    static NESTED: AtomicBool = AtomicBool::new(false);
+
+    intr_off();
     let kernel = kernel().as_pin();
-    kernel.panic();
-    kernel.write_fmt(format_args!("{}\n", info));
+    if kernel.panic() {
+        kernel.write_fmt(format_args!("{}\n", info));
+        print_backtrace(kernel);
+        kdump::dump(kernel, info);
+    } else if !kernel.is_panicked_elsewhere() && !NESTED.swap(true, Ordering::Relaxed) {
+        let _ = Printer.write_fmt(format_args!("\nnested {}\n", info));
+    }
 
     spin_loop()
 }
 
+/// Prints a message of `level` about `target` to the kernel log, unless the log filter drops it.
+/// Use `log!` instead.
+pub fn log_fmt(level: Level, target: &str, args: fmt::Arguments<'_>) {
+    if hal().log().enabled(level, target) {
+        kernel().as_pin().write_fmt(format_args!("{}\n", args));
+    }
+}
+
 /// start() jumps here in supervisor mode on all CPUs.
 pub unsafe fn main() -> ! {
     static INITED: AtomicBool = AtomicBool::new(false);
@@ -282,6 +439,16 @@ pub unsafe fn main() -> ! {
         unsafe {
             kernel_mut_unchecked().init(hal().kmem());
         }
+        // Check the kernel's invariants before any process runs, if QEMU says so.
+        unsafe { kernel_ref(|kernel| kernel.selftest()) };
+        // Protect stacks with a random guard from now on. Only this frame, which never returns,
+        // holds the old one.
+        let guard = unsafe { kernel_ref(random_guard) };
+        unsafe { set_stack_guard(guard) };
+        // Stop for GDB before the other CPUs start, if it debugs the kernel.
+        if let Some(gdb) = hal().gdb() {
+            gdb.wait();
+        }
         INITED.store(true, Ordering::Release);
     } else {
         while !INITED.load(Ordering::Acquire) {
@@ -0,0 +1,275 @@
+//! The kernel log, which keeps the most recent `KMSG_SIZE` bytes the kernel printed.
+//!
+//! Printing appends to the log, and then prints to the console what the console has not
+//! printed yet, unless another CPU is doing so, in which case that CPU prints it too. What is
+//! left behind is printed from the clock tick. So a slow console delays no CPU but one, and
+//! messages stay in the log even if the console falls behind. User programs read the log with
+//! dmesg(), at byte offsets that count from boot.
+//!
+//! Messages printed with `log!` have a `Level` and a target, the module they are about, such as
+//! "fs::lfs". The log filter drops the messages that are less severe than the level it sets for
+//! their target, or for the closest enclosing module that has one, or by default. The superuser
+//! sets the filter with logfilter(), to a string such as "warn,fs::lfs=debug".
+
+use core::{
+    cmp, fmt,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::addr::UVAddr, cred::Caps, error::KernelError, hal::hal, kernel::Kernel, lock::SpinLock,
+    proc::KernelCtx, util::static_vec::StaticVec,
+};
+
+/// Prints a message to the kernel log at a `Level`, about a target such as "fs::lfs", unless
+/// the log filter drops it. A newline is added to the message.
+///
+/// e.g. `log!(Level::Debug, "fs::lfs", "segment {} is full", seg);`
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $target:expr, $($arg:tt)+) => {
+        $crate::kernel::log_fmt($level, $target, format_args!($($arg)+))
+    };
+}
+
+/// Number of bytes the log keeps.
+const KMSG_SIZE: usize = 16384;
+
+/// Maximum number of bytes copied out while holding the lock.
+const CHUNK: usize = 128;
+
+/// Maximum number of targets the log filter sets a level for.
+const FILTER_TARGETS: usize = 8;
+
+/// Maximum length of a target in the log filter.
+const TARGET_LEN: usize = 32;
+
+/// The severity of a message, from the most severe.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"error" => Some(Self::Error),
+            b"warn" => Some(Self::Warn),
+            b"info" => Some(Self::Info),
+            b"debug" => Some(Self::Debug),
+            b"trace" => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+/// Which messages the log keeps.
+struct LogFilter {
+    /// Level of the targets that `targets` does not cover.
+    default: Level,
+
+    /// Targets with their own levels, and the length of each target.
+    targets: StaticVec<([u8; TARGET_LEN], usize, Level), FILTER_TARGETS>,
+}
+
+impl LogFilter {
+    const fn new() -> Self {
+        Self {
+            default: Level::Info,
+            targets: StaticVec::new(),
+        }
+    }
+
+    /// Returns the level of `target`.
+    fn level(&self, target: &str) -> Level {
+        let target = target.as_bytes();
+        self.targets
+            .iter()
+            .filter(|(name, len, _)| {
+                target.starts_with(&name[..*len])
+                    && (target.len() == *len || target[*len..].starts_with(b"::"))
+            })
+            .max_by_key(|(_, len, _)| *len)
+            .map_or(self.default, |(_, _, level)| *level)
+    }
+
+    /// Parses `spec`, a comma-separated list of a default level and `target=level` pairs.
+    fn parse(spec: &[u8]) -> Result<Self, KernelError> {
+        let mut filter = Self::new();
+        for item in spec.split(|c| *c == b',').filter(|item| !item.is_empty()) {
+            match item.iter().position(|c| *c == b'=') {
+                None => {
+                    filter.default = Level::from_name(item).ok_or(KernelError::InvalidArgument)?;
+                }
+                Some(i) => {
+                    let level =
+                        Level::from_name(&item[i + 1..]).ok_or(KernelError::InvalidArgument)?;
+                    let mut name = [0; TARGET_LEN];
+                    name.get_mut(..i)
+                        .ok_or(KernelError::InvalidArgument)?
+                        .copy_from_slice(&item[..i]);
+                    filter
+                        .targets
+                        .push((name, i, level))
+                        .map_err(|_| KernelError::InvalidArgument)?;
+                }
+            }
+        }
+        Ok(filter)
+    }
+}
+
+/// The bytes of the log, in a ring.
+struct LogRing {
+    buf: [u8; KMSG_SIZE],
+
+    /// Number of bytes appended since boot.
+    end: u64,
+}
+
+impl LogRing {
+    /// Copies the bytes of the log from the offset `off`, or from the oldest byte the log keeps if
+    /// it is later, into `dst`.
+    /// Returns the offset of the first byte copied, and the number of bytes copied.
+    fn read(&self, off: u64, dst: &mut [u8]) -> (u64, usize) {
+        let off = cmp::max(off, self.end.saturating_sub(KMSG_SIZE as u64));
+        let len = cmp::min(dst.len() as u64, self.end.saturating_sub(off)) as usize;
+        for (i, b) in dst[..len].iter_mut().enumerate() {
+            *b = self.buf[(off as usize + i) % KMSG_SIZE];
+        }
+        (off, len)
+    }
+}
+
+impl fmt::Write for LogRing {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for b in s.bytes() {
+            self.buf[self.end as usize % KMSG_SIZE] = b;
+            self.end += 1;
+        }
+        Ok(())
+    }
+}
+
+pub struct KernelLog {
+    ring: SpinLock<LogRing>,
+
+    /// Offset up to which the console printed the log. Written only while `draining`.
+    drained: AtomicU64,
+
+    /// Whether a CPU is printing the log to the console.
+    draining: AtomicBool,
+
+    filter: SpinLock<LogFilter>,
+}
+
+impl KernelLog {
+    pub const fn new() -> Self {
+        Self {
+            ring: SpinLock::new(
+                "kmsg",
+                LogRing {
+                    buf: [0; KMSG_SIZE],
+                    end: 0,
+                },
+            ),
+            drained: AtomicU64::new(0),
+            draining: AtomicBool::new(false),
+            filter: SpinLock::new("logfilter", LogFilter::new()),
+        }
+    }
+
+    /// Returns `true` if the log filter keeps messages of `level` about `target`.
+    pub fn enabled(&self, level: Level, target: &str) -> bool {
+        level <= self.filter.lock().level(target)
+    }
+
+    /// Appends the given formatted string to the log.
+    pub fn write_fmt(&self, args: fmt::Arguments<'_>) {
+        let _ = fmt::Write::write_fmt(&mut *self.ring.lock(), args);
+    }
+
+    /// Copies the bytes of the log from the offset `off`, or from the oldest byte the log keeps if
+    /// it is later, into `dst`, without taking the lock of the log, which a frozen CPU may hold.
+    /// Returns the offset of the first byte copied, and the number of bytes copied.
+    ///
+    /// # Safety
+    ///
+    /// No other CPU may write the log meanwhile.
+    pub unsafe fn read_unlocked(&self, off: u64, dst: &mut [u8]) -> (u64, usize) {
+        unsafe { (*self.ring.get_mut_raw()).read(off, dst) }
+    }
+
+    /// Prints what the console has not printed yet, unless another CPU is doing so.
+    pub fn drain(&self, kernel: Pin<&Kernel>) {
+        while !self.draining.swap(true, Ordering::Acquire) {
+            let mut buf = [0; CHUNK];
+            loop {
+                let (off, len) = self
+                    .ring
+                    .lock()
+                    .read(self.drained.load(Ordering::Relaxed), &mut buf);
+                if len == 0 {
+                    break;
+                }
+                for c in &buf[..len] {
+                    hal().console().putc_spin(*c, kernel);
+                }
+                self.drained.store(off + len as u64, Ordering::Relaxed);
+            }
+            self.draining.store(false, Ordering::Release);
+
+            // Another CPU may have appended after we found nothing left, and seen us draining.
+            if self.ring.lock().end == self.drained.load(Ordering::Relaxed) {
+                break;
+            }
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Copy up to `n` bytes of the kernel log to `addr`, from the offset in the `uint64` at
+    /// `offaddr`, or from the oldest byte the log keeps if it is later. The offset is then
+    /// advanced past the bytes copied.
+    /// Returns Ok(number of bytes copied) on success, Err(KernelError) on error.
+    pub fn dmesg(&mut self, addr: UVAddr, n: usize, offaddr: UVAddr) -> Result<usize, KernelError> {
+        let mut off = 0u64;
+        self.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(off.as_bytes_mut(), offaddr)?;
+        let mut buf = [0; CHUNK];
+        let mut copied = 0;
+        while copied < n {
+            let (start, len) = hal()
+                .log()
+                .ring
+                .lock()
+                .read(off, &mut buf[..cmp::min(CHUNK, n - copied)]);
+            if len == 0 {
+                break;
+            }
+            self.proc_mut()
+                .memory_mut()
+                .copy_out_bytes(addr + copied, &buf[..len])?;
+            copied += len;
+            off = start + len as u64;
+        }
+        self.proc_mut().memory_mut().copy_out(offaddr, &off)?;
+        Ok(copied)
+    }
+
+    /// Set the log filter to `spec`, such as "warn,fs::lfs=debug". Needs `Caps::SYSLOG`.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn set_log_filter(&self, spec: &[u8]) -> Result<(), KernelError> {
+        self.require_cap(Caps::SYSLOG)?;
+        *hal().log().filter.lock() = LogFilter::parse(spec)?;
+        Ok(())
+    }
+}
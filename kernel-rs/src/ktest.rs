@@ -0,0 +1,191 @@
+//! In-kernel tests, built in with the `ktest` feature (`make KTEST=yes`).
+//!
+//! A test is a function registered with `ktest!` next to the code it tests, which places it in
+//! the .ktest section that kernel.ld gathers between `ktest_start` and `ktest_end`. The runner
+//! runs the tests whose names start with a filter, such as "vm::" or "", in the context of a
+//! process, and prints a line for each to the console, followed by a summary:
+//!
+//! ```text
+//! ktest: PASS vm::page_table_insert
+//! ktest: FAIL bio::buf_cached: src/bio.rs:250: buf.deref_inner().valid
+//! ktest: SUMMARY passed=3 failed=1
+//! ```
+//!
+//! The superuser runs them with ktest(). Given `-fw_cfg name=opt/rv6/ktest,string=<filter>`, as
+//! `make qemu KTEST=yes KTESTBOOT=<filter>` does, the first process runs them at boot instead,
+//! and powers off the machine with the number of failures as its exit code. There, the filter
+//! "all" stands for "", which QEMU does not take.
+
+#![cfg_attr(not(feature = "ktest"), allow(dead_code, unused_imports))]
+
+use core::{
+    cmp, slice,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::{
+    arch::{fw_cfg::FwCfgFile, poweroff::machine_poweroff},
+    cred::Caps,
+    error::KernelError,
+    proc::KernelCtx,
+    some_or,
+};
+
+/// Registers a kernel test, which passes unless it returns the `Failure` of a `ktest_assert!`.
+/// The test is compiled without the `ktest` feature too, so that it keeps building, but only
+/// registered with it.
+///
+/// e.g.
+/// ```rust,no_run
+/// ktest! {
+///     fn page_table_insert(ctx) {
+///         ktest_assert!(...);
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! ktest {
+    (fn $name:ident($ctx:ident) $body:block) => {
+        const _: () = {
+            #[allow(dead_code, unused_variables)]
+            fn $name(
+                $ctx: &mut $crate::proc::KernelCtx<'_, '_>,
+            ) -> Result<(), $crate::ktest::Failure> {
+                $body
+                Ok(())
+            }
+
+            #[cfg(feature = "ktest")]
+            #[used]
+            #[link_section = ".ktest"]
+            static KTEST: $crate::ktest::KTest = $crate::ktest::KTest {
+                name: concat!(module_path!(), "::", stringify!($name)),
+                run: $name,
+            };
+        };
+    };
+}
+
+/// Fails the kernel test unless `cond` holds.
+#[macro_export]
+macro_rules! ktest_assert {
+    ($cond:expr) => {
+        if !$cond {
+            return Err($crate::ktest::Failure {
+                file: file!(),
+                line: line!(),
+                cond: stringify!($cond),
+            });
+        }
+    };
+}
+
+/// Name of the QEMU file whose contents are the filter of the tests to run at boot.
+const KTEST_OPTION: &str = "opt/rv6/ktest";
+
+/// Maximum length of the filter.
+const FILTER_LEN: usize = 64;
+
+/// A test registered with `ktest!`.
+pub struct KTest {
+    /// The module path of the test, such as "rv6_kernel::vm::page_table_insert".
+    pub name: &'static str,
+    pub run: fn(&mut KernelCtx<'_, '_>) -> Result<(), Failure>,
+}
+
+/// Why a test failed.
+pub struct Failure {
+    pub file: &'static str,
+    pub line: u32,
+    /// The condition that did not hold.
+    pub cond: &'static str,
+}
+
+extern "C" {
+    // kernel.ld
+    static ktest_start: [u8; 0];
+    static ktest_end: [u8; 0];
+}
+
+/// Returns the registered tests.
+fn ktests() -> &'static [KTest] {
+    // SAFETY: kernel.ld places the `KTest`s between `ktest_start` and `ktest_end`.
+    unsafe {
+        let start = ktest_start.as_ptr() as *const KTest;
+        let end = ktest_end.as_ptr() as *const KTest;
+        slice::from_raw_parts(start, end.offset_from(start) as usize)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Run the kernel tests whose names, without the crate's name, start with `filter`.
+    /// Returns the number of tests that failed.
+    fn run_ktests(&mut self, filter: &[u8]) -> usize {
+        let (mut passed, mut failed) = (0, 0);
+        for test in ktests() {
+            let name = test
+                .name
+                .split_once("::")
+                .map_or(test.name, |(_, name)| name);
+            if !name.as_bytes().starts_with(filter) {
+                continue;
+            }
+            match (test.run)(self) {
+                Ok(()) => {
+                    passed += 1;
+                    self.kernel()
+                        .as_ref()
+                        .write_fmt(format_args!("ktest: PASS {}\n", name));
+                }
+                Err(failure) => {
+                    failed += 1;
+                    self.kernel().as_ref().write_fmt(format_args!(
+                        "ktest: FAIL {}: {}:{}: {}\n",
+                        name, failure.file, failure.line, failure.cond
+                    ));
+                }
+            }
+        }
+        self.kernel().as_ref().write_fmt(format_args!(
+            "ktest: SUMMARY passed={} failed={}\n",
+            passed, failed
+        ));
+        failed
+    }
+
+    /// Run the kernel tests whose names start with `filter`, such as "vm::". Running them
+    /// needs `Caps::SYS_ADMIN`.
+    /// Returns Ok(number of tests that failed) on success, Err(KernelError) on error, which is
+    /// Err(KernelError::NoSys) if the kernel was built without the `ktest` feature.
+    pub fn ktest(&mut self, filter: &[u8]) -> Result<usize, KernelError> {
+        self.require_cap(Caps::SYS_ADMIN)?;
+        if cfg!(feature = "ktest") {
+            Ok(self.run_ktests(filter))
+        } else {
+            Err(KernelError::NoSys)
+        }
+    }
+
+    /// Run the kernel tests and power off, if QEMU says so. Called by each new process, but runs
+    /// the tests only the first time.
+    pub fn ktest_boot(&mut self) {
+        static STARTED: AtomicBool = AtomicBool::new(false);
+        if !cfg!(feature = "ktest") || STARTED.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let file = some_or!(FwCfgFile::find(KTEST_OPTION), return);
+        let mut filter = [0; FILTER_LEN];
+        let len = cmp::min(file.size(), filter.len());
+        file.read(&mut filter[..len]);
+        let len = filter[..len]
+            .iter()
+            .position(|c| *c == 0 || *c == b'\n')
+            .unwrap_or(len);
+        let filter = match &filter[..len] {
+            b"all" => &[],
+            filter => filter,
+        };
+        let failed = self.run_ktests(filter);
+        machine_poweroff(cmp::min(failed, u16::MAX as usize) as u16);
+    }
+}
@@ -0,0 +1,148 @@
+//! The kernel memory leak detector, built in with the `leak-check` feature
+//! (`make LEAKCHECK=yes`).
+//!
+//! The page allocator tags each page it hands out with the code that asked for it and the time.
+//! Since the arenas grow by pages too, this covers every allocation of the kernel. A page that
+//! is still allocated long after it was asked for, once the kernel has settled, is likely a
+//! leak: leakcheck() reports the pages older than a given age, grouped by the code that asked
+//! for them, so that a leak shows up as a caller whose pages keep piling up.
+
+#![cfg_attr(not(feature = "leak-check"), allow(dead_code, unused_imports))]
+
+use core::{cmp, mem, panic::Location};
+
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::addr::{UVAddr, PGSIZE},
+    arch::memlayout::{KERNBASE, PHYSTOP},
+    cred::Caps,
+    error::KernelError,
+    hal::hal,
+    proc::KernelCtx,
+    time::ktime_now,
+    util::static_vec::StaticVec,
+};
+
+/// Number of pages of RAM.
+const NPAGES: usize = (PHYSTOP - KERNBASE) / PGSIZE;
+
+/// Maximum number of callers leakcheck() reports.
+const MAX_CALLERS: usize = 32;
+
+/// Length of the file names in `LeakRecord`s.
+const FILE_LEN: usize = 48;
+
+/// `struct leakrec` of user programs.
+#[derive(Copy, Clone, AsBytes)]
+#[repr(C)]
+pub struct LeakRecord {
+    /// Source file of the caller, truncated from the front, and NUL-terminated if shorter.
+    file: [u8; FILE_LEN],
+    line: u32,
+    /// Number of pages that the caller asked for and that are still allocated.
+    pages: u32,
+    /// Age of the oldest of them, in nanoseconds.
+    age: u64,
+}
+
+/// Who asked for a page, and when.
+#[derive(Copy, Clone)]
+struct PageTag {
+    caller: &'static Location<'static>,
+    time: u64,
+}
+
+/// Tags of the allocated pages, kept with the page allocator.
+pub struct LeakTable {
+    pages: [Option<PageTag>; NPAGES],
+}
+
+impl LeakTable {
+    pub const fn new() -> Self {
+        Self {
+            pages: [None; NPAGES],
+        }
+    }
+
+    /// Records that `caller` was given the page at `pa`.
+    pub fn record_alloc(&mut self, pa: usize, caller: &'static Location<'static>) {
+        self.pages[(pa - KERNBASE) / PGSIZE] = Some(PageTag {
+            caller,
+            time: ktime_now(),
+        });
+    }
+
+    /// Records that the page at `pa` was freed.
+    pub fn record_free(&mut self, pa: usize) {
+        self.pages[(pa - KERNBASE) / PGSIZE] = None;
+    }
+
+    /// Groups the pages allocated before `before` by their callers, with the number of pages and
+    /// the time of the oldest one. Callers beyond `MAX_CALLERS` are left out.
+    fn report(
+        &self,
+        before: u64,
+    ) -> StaticVec<(&'static Location<'static>, u32, u64), MAX_CALLERS> {
+        let mut callers = StaticVec::new();
+        for tag in self.pages.iter().flatten().filter(|tag| tag.time < before) {
+            match callers
+                .iter_mut()
+                .find(|(caller, _, _)| *caller == tag.caller)
+            {
+                Some((_, pages, oldest)) => {
+                    *pages += 1;
+                    *oldest = cmp::min(*oldest, tag.time);
+                }
+                None => {
+                    let _ = callers.push((tag.caller, 1, tag.time));
+                }
+            }
+        }
+        callers
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Copy to the `struct leakrec` array at `addr` the callers of up to `n` of the page
+    /// allocator, whose pages allocated more than `age` nanoseconds ago are still allocated.
+    /// Needs `Caps::SYS_ADMIN`.
+    /// Returns Ok(number of records copied) on success, Err(KernelError) on error, which is
+    /// Err(KernelError::NoSys) if the kernel was built without the `leak-check` feature.
+    #[cfg(feature = "leak-check")]
+    pub fn leak_check(&mut self, addr: UVAddr, n: usize, age: u64) -> Result<usize, KernelError> {
+        self.require_cap(Caps::SYS_ADMIN)?;
+        let now = ktime_now();
+        let callers = hal()
+            .kmem()
+            .pinned_lock()
+            .leaks()
+            .report(now.saturating_sub(age));
+        for (i, (caller, pages, oldest)) in callers.iter().take(n).enumerate() {
+            let mut record = LeakRecord {
+                file: [0; FILE_LEN],
+                line: caller.line(),
+                pages: *pages,
+                age: now - oldest,
+            };
+            let file = caller.file().as_bytes();
+            let file = &file[file.len().saturating_sub(FILE_LEN)..];
+            record.file[..file.len()].copy_from_slice(file);
+            self.proc_mut()
+                .memory_mut()
+                .copy_out(addr + i * mem::size_of::<LeakRecord>(), &record)?;
+        }
+        Ok(cmp::min(callers.len(), n))
+    }
+
+    #[cfg(not(feature = "leak-check"))]
+    pub fn leak_check(
+        &mut self,
+        _addr: UVAddr,
+        _n: usize,
+        _age: u64,
+    ) -> Result<usize, KernelError> {
+        self.require_cap(Caps::SYS_ADMIN)?;
+        Err(KernelError::NoSys)
+    }
+}
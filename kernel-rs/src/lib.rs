@@ -52,26 +52,74 @@
 #![feature(try_blocks)]
 #![feature(variant_count)]
 
+mod ansi;
 mod arch;
 mod arena;
+mod audit;
+mod backtrace;
 mod bio;
+mod chardev;
 mod console;
 mod cpu;
+mod cpuidle;
+mod cputime;
+mod cred;
+mod driver;
+mod error;
+mod eventfd;
 mod exec;
+mod failinject;
 mod file;
+mod font;
 mod fs;
+mod fuzz;
+mod gdbstub;
 mod hal;
+mod ioring;
+mod ipc;
+mod ipi;
+mod irq;
 mod kalloc;
+mod kasan;
+mod kdump;
 mod kernel;
+mod kmsg;
+mod ktest;
+mod leak;
 mod lock;
+mod memdev;
+mod mqueue;
 mod page;
+mod pagecache;
 mod param;
+mod pidfd;
 mod pipe;
+mod poll;
 mod proc;
+mod procfs;
+mod profile;
+mod psi;
+mod ramfb;
+mod random;
+mod selftest;
+mod sem;
+mod softirq;
+mod stack_protector;
 mod start;
+mod suspend;
 mod syscall;
+mod sysinfo;
+mod time;
+mod timer;
+mod timerfd;
+mod trace;
+mod tracepoint;
 mod trap;
 mod uart;
 mod util;
+mod vdso;
 mod virtio;
 mod vm;
+mod vsock;
+mod watchdog;
+mod workqueue;
@@ -1,5 +1,6 @@
 //! Spin locks
 use core::cell::{Cell, UnsafeCell};
+use core::marker::PhantomData;
 use core::mem::MaybeUninit;
 use core::ptr;
 use core::sync::atomic::{AtomicPtr, Ordering};
@@ -8,6 +9,7 @@ use super::{Guard, Lock, RawLock};
 use crate::{
     cpu::{Cpu, HeldInterrupts},
     hal::hal,
+    ktest, ktest_assert,
 };
 
 /// Mutual exclusion lock that busy waits (spin).
@@ -43,6 +45,27 @@ impl RawSpinLock {
     fn holding(&self) -> bool {
         self.locked.load(Ordering::Relaxed) == hal().cpus().current_raw()
     }
+
+    /// Acquires the lock if it is free, without spinning.
+    /// Returns `false` if this CPU or another holds it.
+    fn try_acquire(&self) -> bool {
+        let intr = hal().cpus().push_off();
+        if self
+            .locked
+            .compare_exchange(
+                ptr::null_mut(),
+                hal().cpus().current_raw(),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            unsafe { hal().cpus().pop_off(intr) };
+            return false;
+        }
+        self.intr.set(MaybeUninit::new(intr));
+        true
+    }
 }
 
 impl RawLock for RawSpinLock {
@@ -116,4 +139,28 @@ impl<T> SpinLock<T> {
             data: UnsafeCell::new(data),
         }
     }
+
+    /// Acquires the lock if it is free, and returns the lock guard.
+    /// Returns `None` instead of spinning or panicking if this CPU or another holds it, for
+    /// callers that may run inside the lock, such as the panic handler.
+    pub fn try_lock(&self) -> Option<SpinLockGuard<'_, T>> {
+        if !self.lock.try_acquire() {
+            return None;
+        }
+        Some(Guard {
+            lock: self,
+            _marker: PhantomData,
+        })
+    }
+}
+
+ktest! {
+    fn spinlock_try_lock(ctx) {
+        let lock = SpinLock::new("try_lock", 0);
+        let mut guard = lock.try_lock().expect("free lock");
+        *guard = 1;
+        ktest_assert!(lock.try_lock().is_none());
+        drop(guard);
+        ktest_assert!(lock.try_lock().map(|guard| *guard) == Some(1));
+    }
 }
@@ -0,0 +1,97 @@
+//! The memory pseudo-devices, which have no hardware behind them.
+//!
+//! * /dev/null reads as end of file, and takes and drops everything written to it.
+//! * /dev/zero reads as an endless run of zero bytes, and takes writes as /dev/null does.
+//! * /dev/full reads as /dev/zero does, and refuses every write with ENOSPC, as a full disk would.
+//!
+//! None of them ever blocks, so poll() always finds them ready for reading and writing, even
+//! /dev/full, whose writes fail at once instead of waiting.
+
+use core::cmp;
+
+use crate::{
+    arch::addr::UVAddr, error::KernelError, file::Devsw, poll::PollEvents, proc::KernelCtx,
+    register_chardev,
+};
+
+/// Major device number of /dev/null, DEVNULL in kernel/file.h.
+const NULL_MAJOR: u16 = 4;
+
+/// Major device number of /dev/zero, DEVZERO in kernel/file.h.
+const ZERO_MAJOR: u16 = 5;
+
+/// Major device number of /dev/full, DEVFULL in kernel/file.h.
+const FULL_MAJOR: u16 = 6;
+
+/// Zero bytes, copied out to the reader in chunks of this size.
+static ZEROS: [u8; 256] = [0; 256];
+
+register_chardev!(
+    NULL_MAJOR,
+    "null",
+    Devsw {
+        mode: 0o666,
+        read: Some(null_read),
+        write: Some(null_write),
+        poll: Some(mem_poll),
+        ..Devsw::NONE
+    }
+);
+
+register_chardev!(
+    ZERO_MAJOR,
+    "zero",
+    Devsw {
+        mode: 0o666,
+        read: Some(zero_read),
+        write: Some(null_write),
+        poll: Some(mem_poll),
+        ..Devsw::NONE
+    }
+);
+
+register_chardev!(
+    FULL_MAJOR,
+    "full",
+    Devsw {
+        mode: 0o666,
+        read: Some(zero_read),
+        write: Some(full_write),
+        poll: Some(mem_poll),
+        ..Devsw::NONE
+    }
+);
+
+/// User read()s from /dev/null go here.
+fn null_read(_dst: UVAddr, _n: i32, _ctx: &mut KernelCtx<'_, '_>) -> Result<usize, KernelError> {
+    Ok(0)
+}
+
+/// User write()s to /dev/null and /dev/zero go here.
+fn null_write(_src: UVAddr, n: i32, _ctx: &mut KernelCtx<'_, '_>) -> Result<usize, KernelError> {
+    Ok(n as usize)
+}
+
+/// User read()s from /dev/zero and /dev/full go here.
+fn zero_read(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, KernelError> {
+    let n = n as usize;
+    let mut off = 0;
+    while off < n {
+        let len = cmp::min(n - off, ZEROS.len());
+        ctx.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(dst + off, &ZEROS[..len])?;
+        off += len;
+    }
+    Ok(n)
+}
+
+/// User write()s to /dev/full go here.
+fn full_write(_src: UVAddr, _n: i32, _ctx: &mut KernelCtx<'_, '_>) -> Result<usize, KernelError> {
+    Err(KernelError::NoSpace)
+}
+
+/// User poll()s on the memory devices go here.
+fn mem_poll(_ctx: &KernelCtx<'_, '_>) -> PollEvents {
+    PollEvents::POLLIN | PollEvents::POLLOUT
+}
@@ -0,0 +1,347 @@
+//! Message queues. See `rv6_abi::mqueue` for the system calls.
+//!
+//! Queues are found by key in an `IpcRegistry`, as semaphore sets are, but processes use them
+//! through file descriptors, each of whose files holds a reference to its queue. Unlinking a
+//! queue removes it from the registry, so that its key finds nothing, and the queue lives on for
+//! the descriptors that are open until the last of them is closed. A sender waits while the queue
+//! is full and a receiver while it is empty, both on the queue's wait channel, and every message
+//! sent or received wakes them up to try again, along with the processes in poll().
+
+use arrayvec::ArrayVec;
+use rv6_abi::{ipc::*, mqueue::*};
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::addr::{Addr, UVAddr},
+    error::KernelError,
+    file::FileType,
+    fs::{FcntlFlags, MAY_READ, MAY_WRITE},
+    ipc::{IpcData, IpcRegistry, RcIpc},
+    kernel::KernelRef,
+    lock::SpinLock,
+    param::NMQUEUE,
+    poll::PollEvents,
+    proc::{KernelCtx, WaitChannel},
+};
+
+const MAXMSG: usize = MQ_MAXMSG as usize;
+const MSGSIZE: usize = MQ_MSGSIZE as usize;
+
+pub type MqRegistry = IpcRegistry<MsgQueue, NMQUEUE>;
+
+/// A reference counted pointer to a message queue.
+pub type RcMsgQueue = RcIpc<MsgQueue, NMQUEUE>;
+
+struct Msg {
+    prio: u32,
+    len: usize,
+    data: [u8; MSGSIZE],
+}
+
+struct MsgQueueInner {
+    maxmsg: usize,
+    msgsize: usize,
+
+    /// The messages, from the one to receive first.
+    msgs: ArrayVec<Msg, MAXMSG>,
+}
+
+pub struct MsgQueue {
+    inner: SpinLock<MsgQueueInner>,
+
+    /// WaitChannel saying a message was sent or received.
+    waitchannel: WaitChannel,
+}
+
+impl IpcData for MsgQueue {
+    // Each use makes a new queue, as intended.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Self {
+        inner: SpinLock::new(
+            "mqueue",
+            MsgQueueInner {
+                maxmsg: 0,
+                msgsize: 0,
+                msgs: ArrayVec::new_const(),
+            },
+        ),
+        waitchannel: WaitChannel::new(),
+    };
+
+    fn remove(&self, _kernel: KernelRef<'_, '_>) {
+        // The processes that wait on the queue go on using it through their descriptors.
+    }
+
+    fn finalize(&mut self) {
+        self.inner.get_mut().msgs.clear();
+    }
+}
+
+impl MsgQueue {
+    fn new(maxmsg: usize, msgsize: usize) -> Self {
+        let mut queue = Self::INIT;
+        let inner = queue.inner.get_mut();
+        inner.maxmsg = maxmsg;
+        inner.msgsize = msgsize;
+        queue
+    }
+
+    /// Adds the message `data` of priority `prio` after the messages of the same or a higher
+    /// priority. If the queue is full, sleeps until it is not, or returns
+    /// `Err(KernelError::TryAgain)` if `nonblock` is set.
+    /// Returns Err(KernelError::MessageTooLong) if `data` is longer than the queue's messages.
+    fn send(
+        &self,
+        data: &[u8],
+        prio: u32,
+        nonblock: bool,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let mut inner = self.inner.lock();
+        if data.len() > inner.msgsize {
+            return Err(KernelError::MessageTooLong);
+        }
+        while inner.msgs.len() == inner.maxmsg {
+            if nonblock {
+                return Err(KernelError::TryAgain);
+            }
+            if ctx.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            self.waitchannel.sleep(&mut inner, ctx);
+        }
+        let mut msg = Msg {
+            prio,
+            len: data.len(),
+            data: [0; MSGSIZE],
+        };
+        msg.data[..data.len()].copy_from_slice(data);
+        let i = inner
+            .msgs
+            .iter()
+            .position(|m| m.prio < prio)
+            .unwrap_or_else(|| inner.msgs.len());
+        inner.msgs.insert(i, msg);
+        drop(inner);
+        self.wakeup(ctx.kernel());
+        Ok(())
+    }
+
+    /// Takes the first message into a buffer of `n` bytes. If the queue is empty, sleeps until
+    /// it is not, or returns `Err(KernelError::TryAgain)` if `nonblock` is set.
+    /// Returns Err(KernelError::MessageTooLong) if the buffer is shorter than the queue's
+    /// messages may be.
+    fn receive(
+        &self,
+        n: usize,
+        nonblock: bool,
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<Msg, KernelError> {
+        let mut inner = self.inner.lock();
+        if n < inner.msgsize {
+            return Err(KernelError::MessageTooLong);
+        }
+        while inner.msgs.is_empty() {
+            if nonblock {
+                return Err(KernelError::TryAgain);
+            }
+            if ctx.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            self.waitchannel.sleep(&mut inner, ctx);
+        }
+        let msg = inner.msgs.remove(0);
+        drop(inner);
+        self.wakeup(ctx.kernel());
+        Ok(msg)
+    }
+
+    /// Returns the readiness of the queue.
+    pub fn poll(&self) -> PollEvents {
+        let inner = self.inner.lock();
+        let mut events = PollEvents::empty();
+        if !inner.msgs.is_empty() {
+            events |= PollEvents::POLLIN;
+        }
+        if inner.msgs.len() < inner.maxmsg {
+            events |= PollEvents::POLLOUT;
+        }
+        events
+    }
+
+    fn wakeup(&self, kernel: KernelRef<'_, '_>) {
+        self.waitchannel.wakeup(kernel);
+        kernel.poll_queue().wakeup(kernel);
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Opens the message queue with `key` for the access mode in `flags`, and allocates a file
+    /// descriptor for it. `flags` may also contain O_CREATE, O_NONBLOCK, and O_CLOEXEC. A new
+    /// queue has the permissions `mode`, and the limits in the MqAttr at `attr`, or MQ_MAXMSG
+    /// messages of MQ_MSGSIZE bytes if `attr` is null.
+    /// Returns Ok(file descriptor) on success, Err(KernelError) on error.
+    pub fn mq_open(
+        &mut self,
+        key: i32,
+        flags: i32,
+        mode: u16,
+        attr: UVAddr,
+    ) -> Result<usize, KernelError> {
+        let status = FcntlFlags::from_bits(flags)
+            .filter(|status| {
+                (FcntlFlags::O_WRONLY
+                    | FcntlFlags::O_RDWR
+                    | FcntlFlags::O_CREATE
+                    | FcntlFlags::O_NONBLOCK
+                    | FcntlFlags::O_CLOEXEC)
+                    .contains(*status)
+            })
+            .ok_or(KernelError::InvalidArgument)?;
+        let readable = !status.contains(FcntlFlags::O_WRONLY);
+        let writable = status.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR);
+        let mut access = 0;
+        if readable {
+            access |= MAY_READ;
+        }
+        if writable {
+            access |= MAY_WRITE;
+        }
+
+        let mut limits = MqAttr {
+            mq_maxmsg: MQ_MAXMSG,
+            mq_msgsize: MQ_MSGSIZE,
+            ..Default::default()
+        };
+        if !attr.is_null() {
+            self.proc_mut()
+                .memory_mut()
+                .copy_in_bytes(limits.as_bytes_mut(), attr)?;
+        }
+        let mut ipc_flags = (mode & 0o777) as i32;
+        if status.contains(FcntlFlags::O_CREATE) {
+            ipc_flags |= IPC_CREAT;
+        }
+        let queue = self.kernel().mqueues().get(
+            key,
+            ipc_flags,
+            access,
+            || {
+                if !(1..=MQ_MAXMSG).contains(&limits.mq_maxmsg)
+                    || !(1..=MQ_MSGSIZE).contains(&limits.mq_msgsize)
+                {
+                    return Err(KernelError::InvalidArgument);
+                }
+                Ok(MsgQueue::new(
+                    limits.mq_maxmsg as usize,
+                    limits.mq_msgsize as usize,
+                ))
+            },
+            self,
+        )?;
+
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::MsgQueue { queue }, readable, writable)
+            .map_err(|typ| {
+                if let FileType::MsgQueue { queue } = typ {
+                    queue.free(());
+                }
+                KernelError::FileTableFull
+            })?;
+        f.set_status_flags(status & (FcntlFlags::O_NONBLOCK | FcntlFlags::O_CLOEXEC));
+        let fd = f.fdalloc(self)?;
+        if status.contains(FcntlFlags::O_CLOEXEC) {
+            self.proc_mut()
+                .deref_mut_data()
+                .fds
+                .set_cloexec(fd as usize, true);
+        }
+        Ok(fd as usize)
+    }
+
+    /// Removes the message queue with `key`, which the current process must own unless it has
+    /// `Caps::SYS_ADMIN`. The descriptors that are open keep using the queue.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn mq_unlink(&self, key: i32) -> Result<(), KernelError> {
+        let mqueues = self.kernel().mqueues();
+        let queue = mqueues.get(key, 0, 0, || Err(KernelError::NoEntry), self)?;
+        let id = queue.id();
+        queue.free(());
+        mqueues.remove(id, self)
+    }
+
+    /// Sends the `len` bytes at `msg` with priority `prio` to `queue`, which a descriptor with
+    /// the status flags `status` refers to.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn mq_send(
+        &mut self,
+        queue: &RcMsgQueue,
+        msg: UVAddr,
+        len: usize,
+        prio: u32,
+        status: FcntlFlags,
+    ) -> Result<(), KernelError> {
+        if !status.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR) {
+            return Err(KernelError::BadFd);
+        }
+        if prio >= MQ_PRIO_MAX {
+            return Err(KernelError::InvalidArgument);
+        }
+        if len > MSGSIZE {
+            return Err(KernelError::MessageTooLong);
+        }
+        let mut data = [0; MSGSIZE];
+        self.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(&mut data[..len], msg)?;
+        let nonblock = status.contains(FcntlFlags::O_NONBLOCK);
+        queue.data.send(&data[..len], prio, nonblock, self)
+    }
+
+    /// Receives the first message of `queue`, which a descriptor with the status flags `status`
+    /// refers to, into the buffer of `len` bytes at `buf`, and its priority into `prio` unless
+    /// `prio` is null.
+    /// Returns Ok(the length of the message) on success, Err(KernelError) on error.
+    pub fn mq_receive(
+        &mut self,
+        queue: &RcMsgQueue,
+        buf: UVAddr,
+        len: usize,
+        prio: UVAddr,
+        status: FcntlFlags,
+    ) -> Result<usize, KernelError> {
+        if status.contains(FcntlFlags::O_WRONLY) {
+            return Err(KernelError::BadFd);
+        }
+        let nonblock = status.contains(FcntlFlags::O_NONBLOCK);
+        let msg = queue.data.receive(len, nonblock, self)?;
+        let memory = self.proc_mut().memory_mut();
+        memory.copy_out_bytes(buf, &msg.data[..msg.len])?;
+        if !prio.is_null() {
+            memory.copy_out(prio, &msg.prio)?;
+        }
+        Ok(msg.len)
+    }
+
+    /// Copies the attributes of `queue`, which a descriptor with the status flags `status`
+    /// refers to, into the MqAttr at `attr`.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn mq_getattr(
+        &mut self,
+        queue: &RcMsgQueue,
+        attr: UVAddr,
+        status: FcntlFlags,
+    ) -> Result<(), KernelError> {
+        let inner = queue.data.inner.lock();
+        let value = MqAttr {
+            mq_flags: (status & FcntlFlags::O_NONBLOCK).bits(),
+            mq_maxmsg: inner.maxmsg as i32,
+            mq_msgsize: inner.msgsize as i32,
+            mq_curmsgs: inner.msgs.len() as i32,
+        };
+        drop(inner);
+        self.proc_mut().memory_mut().copy_out(attr, &value)
+    }
+}
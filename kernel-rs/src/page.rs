@@ -29,6 +29,9 @@ pub struct Page {
     inner: NonNull<RawPage>,
 }
 
+// SAFETY: a `Page` exclusively owns its memory, since two different pages never overlap.
+unsafe impl Send for Page {}
+
 impl RawPage {
     pub fn write_bytes(&mut self, value: u8) {
         unsafe {
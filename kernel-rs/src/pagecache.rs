@@ -0,0 +1,360 @@
+//! The page cache, which keeps whole pages of the programs that exec loads, so that exec maps
+//! them into the new process instead of reading the file into new pages again.
+//!
+//! A cached page holds the PGSIZE bytes of a file from an offset, and the device, the inode
+//! number and the offset name it. exec maps a cached page read-only and copy-on-write, and the
+//! page counts the page table entries that map it. A write to such a page gives the process a
+//! copy of its own first (`UserMemory::break_cow`), which unmaps the cached page.
+//!
+//! Writing or truncating a file drops its pages from the cache, under the lock of its inode, which
+//! exec holds while it fills the cache. The cache indexes its pages by their files, so that it
+//! finds the pages of a file without looking at the others. A dropped page that processes still
+//! map stays theirs until they unmap it. The pages that no process maps go when the cache is full,
+//! least recently used first, or when the page allocator runs out of pages.
+
+use core::pin::Pin;
+
+use array_macro::array;
+use pin_project::pin_project;
+
+use crate::{
+    arch::addr::{Addr, PAddr, PGSIZE},
+    kalloc::Kmem,
+    ktest, ktest_assert,
+    lock::SpinLock,
+    page::Page,
+    param::NPAGECACHE,
+    util::{
+        branded::{DevNo, Inum},
+        intrusive_hash_map::{HashMap, HashNode},
+        intrusive_list::{ListEntry, ListNode},
+        pinned_array::{get_pin_mut, IterPinMut},
+    },
+};
+
+/// The file and the offset in it that a cached page holds.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub struct PageKey {
+    pub dev: DevNo,
+    pub inum: Inum,
+    pub off: u32,
+}
+
+/// A slot of the cache. While the slot is in `PageCache::index`, its page is cached. Otherwise,
+/// its page was dropped from the cache but processes still map it, or it has no page.
+#[repr(C)]
+#[pin_project]
+struct CachedPage {
+    #[pin]
+    entry: ListEntry,
+
+    /// What the page holds, while the slot is in the index.
+    key: PageKey,
+
+    page: Option<Page>,
+
+    /// Number of page table entries that map the page.
+    maps: usize,
+
+    /// The `PageCache::clock` when the page was last looked up.
+    used: u64,
+}
+
+impl CachedPage {
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `CachedPage::init`.
+    const unsafe fn new() -> Self {
+        Self {
+            entry: unsafe { ListEntry::new() },
+            key: PageKey {
+                dev: DevNo::new(0),
+                inum: Inum::new(0),
+                off: 0,
+            },
+            page: None,
+            maps: 0,
+            used: 0,
+        }
+    }
+
+    fn init(self: Pin<&mut Self>) {
+        self.project().entry.init();
+    }
+}
+
+// SAFETY: `CachedPage` owns a `ListEntry`, at its beginning.
+unsafe impl ListNode for CachedPage {
+    fn get_list_entry(self: Pin<&Self>) -> Pin<&ListEntry> {
+        unsafe { Pin::new_unchecked(&self.get_ref().entry) }
+    }
+
+    fn from_list_entry(list_entry: *const ListEntry) -> *const Self {
+        list_entry as _
+    }
+}
+
+// SAFETY: `CachedPage` is a `ListNode`, and `PageCache` changes `key` only while the slot is not
+// in the index.
+unsafe impl HashNode for CachedPage {
+    /// The file whose page the slot holds, so that all the pages of a file share a bucket.
+    type Key = (DevNo, Inum);
+
+    fn key(&self) -> Self::Key {
+        (self.key.dev, self.key.inum)
+    }
+}
+
+#[pin_project]
+pub struct PageCache {
+    #[pin]
+    pages: [CachedPage; NPAGECACHE],
+
+    /// The slots of the cached pages, by their files.
+    #[pin]
+    index: HashMap<CachedPage, NPAGECACHE>,
+
+    /// Counts the lookups, to tell the least recently used page.
+    clock: u64,
+}
+
+impl PageCache {
+    /// # Safety
+    ///
+    /// It must be used only after initializing it with `PageCache::init`.
+    pub const unsafe fn new() -> Self {
+        Self {
+            pages: array![_ => unsafe { CachedPage::new() }; NPAGECACHE],
+            index: unsafe { HashMap::new("PAGECACHE_INDEX") },
+            clock: 0,
+        }
+    }
+
+    pub fn init(self: Pin<&mut Self>) {
+        let this = self.project();
+        for page in IterPinMut::from(this.pages) {
+            page.init();
+        }
+        this.index.init();
+    }
+
+    fn index(self: Pin<&Self>) -> Pin<&HashMap<CachedPage, NPAGECACHE>> {
+        self.project_ref().index
+    }
+
+    /// Returns the slot at index `i`, pinned.
+    fn node(self: Pin<&Self>, i: usize) -> Pin<&CachedPage> {
+        // SAFETY: we're just projecting from a pinned array to its pinned element.
+        unsafe { self.map_unchecked(|this| &this.pages[i]) }
+    }
+
+    /// Returns the slot at index `i`.
+    fn slot(self: Pin<&mut Self>, i: usize) -> Pin<&mut CachedPage> {
+        get_pin_mut(self.project().pages, i).expect("PageCache::slot")
+    }
+
+    /// Returns the index of the slot `node` in `pages`.
+    fn index_of(&self, node: *const CachedPage) -> usize {
+        // SAFETY: the index holds only the slots in `pages`.
+        unsafe { node.offset_from(self.pages.as_ptr()) as usize }
+    }
+
+    /// Returns the index of the slot whose page `key` names, if it is cached.
+    fn find(self: Pin<&Self>, key: PageKey) -> Option<usize> {
+        let bucket = self.index().lock_bucket(&(key.dev, key.inum));
+        // SAFETY: the slots in the bucket stay in `pages` while we hold the lock of the cache.
+        let node = bucket.iter().find(|node| unsafe { (**node).key } == key)?;
+        Some(self.index_of(node))
+    }
+
+    /// Maps the page that `key` names once more, and returns its address, if it is cached.
+    fn map(mut self: Pin<&mut Self>, key: PageKey) -> Option<PAddr> {
+        *self.as_mut().project().clock += 1;
+        let clock = self.clock;
+        let i = self.as_ref().find(key)?;
+        let cached = self.slot(i).project();
+        *cached.maps += 1;
+        *cached.used = clock;
+        cached.page.as_ref().map(Page::addr)
+    }
+
+    /// Drops the page in the slot at index `i` from the index, and returns the page if no process
+    /// maps it.
+    fn drop_page(self: Pin<&mut Self>, i: usize) -> Option<Page> {
+        self.as_ref().index().remove(self.as_ref().node(i));
+        let cached = self.slot(i).project();
+        if *cached.maps == 0 {
+            cached.page.take()
+        } else {
+            None
+        }
+    }
+
+    /// Returns the index of the least recently used cached page that no process maps, if any.
+    fn victim(self: Pin<&Self>) -> Option<usize> {
+        self.pages
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.page.is_some() && p.maps == 0)
+            .min_by_key(|(_, p)| p.used)
+            .map(|(i, _)| i)
+    }
+}
+
+impl SpinLock<PageCache> {
+    /// Returns the address of the page that `key` names, mapped once more, which the caller maps
+    /// copy-on-write and passes to `unmap` when it unmaps it. If the page is not cached, calls
+    /// `fill` to read it into a new page, and caches that. Returns None if `fill` fails, or no
+    /// page is left for it.
+    /// The caller holds the lock of the file's inode.
+    pub fn get(
+        self: Pin<&Self>,
+        key: PageKey,
+        fill: impl FnOnce(&mut Page) -> bool,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Option<PAddr> {
+        if let Some(pa) = self.pinned_lock().get_pin_mut().map(key) {
+            return Some(pa);
+        }
+
+        let mut page = allocator.alloc()?;
+        if !fill(&mut page) {
+            allocator.free(page);
+            return None;
+        }
+
+        let mut cache = self.pinned_lock();
+        // The lock of the inode keeps anyone else from filling the page meanwhile.
+        assert!(
+            cache.get_pin_mut().map(key).is_none(),
+            "PageCache::get: filled twice"
+        );
+        let i = match cache.pages.iter().position(|p| p.page.is_none()) {
+            Some(i) => i,
+            None => {
+                match cache.get_pin_mut().as_ref().victim() {
+                    Some(i) => {
+                        let old = cache.get_pin_mut().drop_page(i).expect("PageCache::get");
+                        allocator.free(old);
+                        i
+                    }
+                    None => {
+                        drop(cache);
+                        allocator.free(page);
+                        return None;
+                    }
+                }
+            }
+        };
+        let pa = page.addr();
+        let used = cache.clock;
+        let cached = cache.get_pin_mut().slot(i).project();
+        *cached.key = key;
+        *cached.page = Some(page);
+        *cached.maps = 1;
+        *cached.used = used;
+        let this = cache.get_pin_mut().into_ref();
+        this.index().insert(this.node(i));
+        Some(pa)
+    }
+
+    /// Unmaps the cached page at `pa` once, which `get` returned, and frees it if it was dropped
+    /// from the cache and nothing maps it anymore.
+    pub fn unmap(self: Pin<&Self>, pa: PAddr, allocator: Pin<&SpinLock<Kmem>>) {
+        let mut cache = self.pinned_lock();
+        let i = cache
+            .pages
+            .iter()
+            .position(|p| {
+                p.page
+                    .as_ref()
+                    .map_or(false, |page| page.addr().into_usize() == pa.into_usize())
+            })
+            .expect("PageCache::unmap: not cached");
+        let cached = cache.get_pin_mut().slot(i);
+        let dropped = cached.as_ref().get_list_entry().is_unlinked();
+        let cached = cached.project();
+        *cached.maps -= 1;
+        if *cached.maps == 0 && dropped {
+            allocator.free(cached.page.take().expect("PageCache::unmap"));
+        }
+    }
+
+    /// Drops the pages of the inode `inum` on `dev` from the cache, as its content changes.
+    /// The caller holds the lock of the inode.
+    pub fn invalidate(self: Pin<&Self>, dev: DevNo, inum: Inum, allocator: Pin<&SpinLock<Kmem>>) {
+        let mut cache = self.pinned_lock();
+        loop {
+            let node = cache.get_pin_mut().as_ref().index().find(&(dev, inum));
+            let i = match node {
+                Some(node) => cache.index_of(node),
+                None => break,
+            };
+            if let Some(page) = cache.get_pin_mut().drop_page(i) {
+                allocator.free(page);
+            }
+        }
+    }
+
+    /// Frees the cached pages that no process maps. Returns whether it freed any.
+    pub fn shrink(self: Pin<&Self>, allocator: Pin<&SpinLock<Kmem>>) -> bool {
+        let mut cache = self.pinned_lock();
+        let mut freed = false;
+        while let Some(i) = cache.get_pin_mut().as_ref().victim() {
+            if let Some(page) = cache.get_pin_mut().drop_page(i) {
+                allocator.free(page);
+                freed = true;
+            }
+        }
+        freed
+    }
+}
+
+ktest! {
+    fn page_cache_get(ctx) {
+        let allocator = crate::hal::hal().kmem();
+        let cache = crate::hal::hal().pagecache();
+        // No device has this number, so that no file shares the pages.
+        let key = PageKey {
+            dev: DevNo::new(u32::MAX),
+            inum: Inum::new(1),
+            off: 0,
+        };
+        let fill = |page: &mut Page| {
+            page.write_bytes(7);
+            true
+        };
+
+        let pa = cache.get(key, fill, allocator);
+        // A cached page is not filled again.
+        let again = cache.get(key, |_| false, allocator);
+        // SAFETY: `pa` is the address of the cached page.
+        let filled = pa.map_or(false, |pa| {
+            unsafe { core::slice::from_raw_parts(pa.into_usize() as *const u8, PGSIZE) }
+                .iter()
+                .all(|b| *b == 7)
+        });
+
+        // A dropped page stays until its last mapping goes.
+        cache.invalidate(key.dev, key.inum, allocator);
+        let dropped = cache.get(key, |_| false, allocator);
+        if let Some(pa) = pa {
+            cache.unmap(pa, allocator);
+        }
+        let held = || {
+            cache.pinned_lock().pages.iter().any(|p| {
+                p.page.as_ref().map(Page::addr).map(Addr::into_usize) == pa.map(Addr::into_usize)
+            })
+        };
+        let mapped = held();
+        if let Some(pa) = again {
+            cache.unmap(pa, allocator);
+        }
+        let unmapped = held();
+
+        ktest_assert!(pa.is_some() && again.map(Addr::into_usize) == pa.map(Addr::into_usize));
+        ktest_assert!(filled);
+        ktest_assert!(dropped.is_none());
+        ktest_assert!(mapped && !unmapped);
+    }
+}
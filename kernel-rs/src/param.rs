@@ -1,23 +1,55 @@
+use crate::util::branded::DevNo;
+
 /// Maximum number of processes.
 pub const NPROC: usize = 64;
 
 /// Maximum number of CPUs.
 pub const NCPU: usize = 8;
 
-/// Open files per process.
+/// How long a process runs before it is preempted, in nanoseconds.
+pub const TIMESLICE_NS: u64 = 100_000_000;
+
+/// Statically allocated open files per process.
+/// More are allocated from the page allocator on demand, up to NFDPAGE pages of them.
 pub const NOFILE: usize = 16;
 
-/// Open files per system.
+/// Maximum number of pages of open files per process, past NOFILE.
+pub const NFDPAGE: usize = 2;
+
+/// Statically allocated open files per system.
+/// More open files are allocated from the page allocator on demand.
 pub const NFILE: usize = 100;
 
+/// Statically allocated pipes per system.
+/// More pipes are allocated from the page allocator on demand.
+pub const NPIPE: usize = 8;
+
+/// Maximum number of semaphore sets.
+pub const NSEMSET: usize = 16;
+
+/// Maximum number of message queues.
+pub const NMQUEUE: usize = 8;
+
+/// Maximum number of sockets, including the connections that wait for accept().
+pub const NSOCK: usize = 8;
+
 /// Maximum number of active i-nodes.
 pub const NINODE: usize = 50;
 
+/// Number of failed lookups that the negative lookup cache remembers.
+pub const NNEGATIVE: usize = 32;
+
+/// Maximum number of users that own files or have disk quotas.
+pub const NQUOTA: usize = 32;
+
 /// Maximum major device number.
 pub const NDEV: usize = 10;
 
 /// Device number of file system root disk.
-pub const ROOTDEV: u32 = 1;
+pub const ROOTDEV: DevNo = DevNo::new(1);
+
+/// Maximum number of pages of programs that the page cache keeps.
+pub const NPAGECACHE: usize = 64;
 
 /// Max exec arguments.
 pub const MAXARG: usize = 32;
@@ -25,6 +57,9 @@ pub const MAXARG: usize = 32;
 /// Block Size.
 pub const BSIZE: usize = 1024;
 
+/// Size of the crash dump partition, at the end of the disk, in blocks.
+pub const DUMPSIZE: usize = 2048;
+
 /// Max # of blocks any FS op writes.
 /// Will be handled in #31.
 pub const MAXOPBLOCKS: usize = 10;
@@ -32,11 +67,17 @@ pub const MAXOPBLOCKS: usize = 10;
 /// Max data blocks in on-disk log.
 pub const LOGSIZE: usize = MAXOPBLOCKS * 3;
 
-/// Size of disk block cache.
-pub const NBUF: usize = MAXOPBLOCKS * 3;
+/// Size of the disk block cache, split across its shards.
+pub const NBUF: usize = MAXOPBLOCKS * 6;
+
+/// Number of shards of the disk block cache.
+pub const NBUFSHARD: usize = 4;
 
 /// Maximum file path name.
 pub const MAXPATH: usize = 128;
 
 /// Maximum length of process name.
 pub const MAXPROCNAME: usize = 16;
+
+/// File mode creation mask of the first process.
+pub const DEFAULT_UMASK: u32 = 0o022;
@@ -0,0 +1,81 @@
+//! Process file descriptors, through which a process waits for another process to exit with
+//! poll(), along with its other files, which wait() cannot do.
+//!
+//! pidfd_open() returns a file that refers to a process by its pid, which is never reused. The
+//! file becomes readable when the process exits: poll() reports POLLIN, and read() returns end
+//! of file, after sleeping until then unless the file is nonblocking. The process need not be a
+//! child of the caller, and its parent still reaps it with wait(), before or after. An exiting
+//! process wakes up the pollers, and readers sleep on the poll queue as well.
+
+use crate::{
+    error::KernelError, file::FileType, fs::FcntlFlags, kernel::KernelRef, poll::PollEvents,
+    proc::KernelCtx,
+};
+
+pub struct PidFd {
+    /// The process.
+    pid: i32,
+}
+
+impl PidFd {
+    /// Returns end of file once the process has exited. Until then, sleeps, or returns
+    /// `Err(KernelError::TryAgain)` if `nonblock` is set.
+    pub fn read(&self, nonblock: bool, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, KernelError> {
+        loop {
+            let generation = ctx.kernel().poll_queue().generation();
+            if !ctx.kernel().procs().alive(self.pid) {
+                return Ok(0);
+            }
+            if nonblock {
+                return Err(KernelError::TryAgain);
+            }
+            if ctx.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            ctx.kernel().poll_queue().wait(generation, ctx);
+        }
+    }
+
+    /// Returns the readiness of the file, which is readable once the process has exited.
+    pub fn poll(&self, kernel: KernelRef<'_, '_>) -> PollEvents {
+        if kernel.procs().alive(self.pid) {
+            PollEvents::empty()
+        } else {
+            PollEvents::POLLIN
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Allocate a file descriptor that refers to the process `pid`. `flags` may contain
+    /// O_NONBLOCK and O_CLOEXEC.
+    /// Returns Ok(file descriptor) on success, Err(KernelError) on error.
+    pub fn pidfd_open(&mut self, pid: i32, flags: i32) -> Result<usize, KernelError> {
+        let status = FcntlFlags::from_bits(flags)
+            .filter(|status| (FcntlFlags::O_NONBLOCK | FcntlFlags::O_CLOEXEC).contains(*status))
+            .ok_or(KernelError::InvalidArgument)?;
+        if pid <= 0 || !self.kernel().procs().alive(pid) {
+            return Err(KernelError::NoProcess);
+        }
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(
+                FileType::PidFd {
+                    pidfd: PidFd { pid },
+                },
+                true,
+                false,
+            )
+            .map_err(|_| KernelError::FileTableFull)?;
+        f.set_status_flags(status);
+        let fd = f.fdalloc(self)?;
+        if status.contains(FcntlFlags::O_CLOEXEC) {
+            self.proc_mut()
+                .deref_mut_data()
+                .fds
+                .set_cloexec(fd as usize, true);
+        }
+        Ok(fd as usize)
+    }
+}
@@ -1,24 +1,196 @@
-use core::{mem, ops::Deref, ptr::NonNull};
+use core::{cmp, mem, ops::Deref, ptr};
 
 use crate::{
-    arch::addr::UVAddr,
+    arch::addr::{pgroundup, UVAddr, PGSIZE},
+    arena::{Arena, ArenaObject, ArenaRc, ChunkedArena},
+    error::KernelError,
     file::{FileType, RcFile},
+    fs::FcntlFlags,
     hal::hal,
     lock::SpinLock,
     page::Page,
+    param::NPIPE,
+    poll::PollEvents,
     proc::{KernelCtx, WaitChannel},
+    util::static_vec::StaticVec,
 };
 
-const PIPESIZE: usize = 512;
+/// Maximum number of pages in a pipe's buffer.
+const PIPE_MAX_PAGES: usize = 16;
 
-struct PipeInner {
-    data: [u8; PIPESIZE],
+/// A ring buffer of bytes, backed by pages from `Kmem`.
+/// A new pipe gets one page, and `fcntl(F_SETPIPE_SZ)` can change the number of pages.
+///
+/// # Safety
+///
+/// * `head < capacity()` unless there are no pages, in which case `head == len == 0`.
+/// * `len <= capacity()`.
+/// * The bytes at offsets `(head + i) % capacity()` for `0 <= i < len` are the data, where the
+///   offset `off` is the byte at `off % PGSIZE` in `pages[off / PGSIZE]`.
+struct PipeBuffer {
+    pages: StaticVec<Page, PIPE_MAX_PAGES>,
+    head: usize,
+    len: usize,
+}
+
+impl PipeBuffer {
+    const fn new() -> Self {
+        Self {
+            pages: StaticVec::new(),
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.pages.len() * PGSIZE
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == self.capacity()
+    }
+
+    /// Returns the bytes in the page at `off`, starting from `off`.
+    fn page_from(pages: &mut [Page], off: usize) -> &mut [u8] {
+        &mut pages[off / PGSIZE][off % PGSIZE..]
+    }
+
+    /// Returns the data at the front, up to the end of its page.
+    fn front(&mut self) -> &[u8] {
+        if self.is_empty() {
+            return &[];
+        }
+        let len = self.len;
+        let data = Self::page_from(&mut self.pages, self.head);
+        let n = cmp::min(data.len(), len);
+        &data[..n]
+    }
+
+    /// Removes `n` bytes from the front. `n` must not exceed `front().len()`.
+    /// An emptied buffer starts over at the beginning of its first page, so that the data of
+    /// page-sized writes stays in whole pages.
+    fn consume(&mut self, n: usize) {
+        assert!(n <= self.len, "PipeBuffer::consume");
+        if n > 0 {
+            self.head = (self.head + n) % self.capacity();
+            self.len -= n;
+            if self.len == 0 {
+                self.head = 0;
+            }
+        }
+    }
+
+    /// Returns the offset just after the data.
+    fn tail(&self) -> usize {
+        (self.head + self.len) % self.capacity()
+    }
+
+    /// Returns the free space after the back, up to the end of its page.
+    fn back_space(&mut self) -> &mut [u8] {
+        if self.is_full() {
+            return &mut [];
+        }
+        let free = self.capacity() - self.len;
+        let tail = self.tail();
+        let space = Self::page_from(&mut self.pages, tail);
+        let n = cmp::min(space.len(), free);
+        &mut space[..n]
+    }
+
+    /// Appends the first `n` bytes of `back_space()` to the data.
+    fn commit(&mut self, n: usize) {
+        assert!(self.len + n <= self.capacity(), "PipeBuffer::commit");
+        self.len += n;
+    }
+
+    /// Moves up to `n` bytes from the front to the back of `to`. When a whole page of data starts
+    /// the front and a whole free page follows the back of `to`, the two pages swap places
+    /// instead of the data being copied.
+    /// Returns the number of bytes moved.
+    fn move_to(&mut self, to: &mut Self, n: usize) -> usize {
+        let mut moved = 0;
+        while moved < n && !self.is_empty() && !to.is_full() {
+            if n - moved >= PGSIZE
+                && self.len >= PGSIZE
+                && self.head % PGSIZE == 0
+                && to.capacity() - to.len >= PGSIZE
+                && to.tail() % PGSIZE == 0
+            {
+                let (i, j) = (self.head / PGSIZE, to.tail() / PGSIZE);
+                mem::swap(&mut self.pages[i], &mut to.pages[j]);
+                self.consume(PGSIZE);
+                to.commit(PGSIZE);
+                moved += PGSIZE;
+                continue;
+            }
+            let src = self.front();
+            let dst = to.back_space();
+            let m = cmp::min(cmp::min(src.len(), dst.len()), n - moved);
+            dst[..m].copy_from_slice(&src[..m]);
+            self.consume(m);
+            to.commit(m);
+            moved += m;
+        }
+        moved
+    }
+
+    /// Replaces the pages with `npages` new pages, keeping the data.
+    fn resize(&mut self, npages: usize) -> Result<(), KernelError> {
+        if npages == 0 || npages > PIPE_MAX_PAGES {
+            return Err(KernelError::InvalidArgument);
+        }
+        if self.len > npages * PGSIZE {
+            return Err(KernelError::Busy);
+        }
+
+        let mut pages = StaticVec::<Page, PIPE_MAX_PAGES>::new();
+        while pages.len() < npages {
+            match hal().kmem().alloc() {
+                Some(page) => {
+                    let _ = pages.push(page);
+                }
+                None => {
+                    while let Some(page) = pages.pop() {
+                        hal().kmem().free(page);
+                    }
+                    return Err(KernelError::NoMemory);
+                }
+            }
+        }
+
+        let len = self.len;
+        let mut copied = 0;
+        while copied < len {
+            let src = self.front();
+            let dst = Self::page_from(&mut pages, copied);
+            let n = cmp::min(src.len(), dst.len());
+            dst[..n].copy_from_slice(&src[..n]);
+            self.consume(n);
+            copied += n;
+        }
+
+        self.free();
+        self.pages = pages;
+        self.len = len;
+        Ok(())
+    }
 
-    /// Number of bytes read.
-    nread: u32,
+    /// Returns all pages to `Kmem`, discarding the data.
+    fn free(&mut self) {
+        while let Some(page) = self.pages.pop() {
+            hal().kmem().free(page);
+        }
+        self.head = 0;
+        self.len = 0;
+    }
+}
 
-    /// Number of bytes written.
-    nwrite: u32,
+struct PipeInner {
+    data: PipeBuffer,
 
     /// Read fd is still open.
     readopen: bool,
@@ -37,25 +209,63 @@ pub struct Pipe {
     write_waitchannel: WaitChannel,
 }
 
+pub type PipeTable = SpinLock<ChunkedArena<Pipe, NPIPE>>;
+
+impl const Default for Pipe {
+    fn default() -> Self {
+        Self {
+            inner: SpinLock::new(
+                "pipe",
+                PipeInner {
+                    data: PipeBuffer::new(),
+                    readopen: true,
+                    writeopen: true,
+                },
+            ),
+            read_waitchannel: WaitChannel::new(),
+            write_waitchannel: WaitChannel::new(),
+        }
+    }
+}
+
+impl ArenaObject for Pipe {
+    type Ctx<'a, 'id: 'a> = ();
+
+    #[allow(clippy::needless_lifetimes)]
+    fn finalize<'a, 'id: 'a, A: Arena>(&mut self, _: ()) {
+        // The rest of the pipe will be reinitialized when it gets allocated again.
+        self.inner.get_mut().data.free();
+    }
+}
+
 impl Pipe {
     /// Tries to read up to `n` bytes using `Pipe::try_read()`.
     /// If successfully read i > 0 bytes, wakeups the `write_waitchannel` and returns `Ok(i: usize)`.
-    /// If the pipe was empty, sleeps at `read_waitchannel` and tries again after wakeup.
-    /// If an error happened, returns `Err(())`.
-    pub fn read(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    /// If the pipe was empty, sleeps at `read_waitchannel` and tries again after wakeup,
+    /// or returns `Err(KernelError::TryAgain)` if `nonblock` is set.
+    /// If an error happened, returns `Err(KernelError)`.
+    pub fn read(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
         let mut inner = self.inner.lock();
         loop {
             match inner.try_read(addr, n, ctx) {
                 Ok(r) => {
                     //DOC: piperead-wakeup
                     self.write_waitchannel.wakeup(ctx.kernel());
+                    ctx.kernel().poll_queue().wakeup(ctx.kernel());
                     return Ok(r);
                 }
+                Err(PipeError::WaitForIO) if nonblock => return Err(KernelError::TryAgain),
                 Err(PipeError::WaitForIO) => {
                     //DOC: piperead-sleep
                     self.read_waitchannel.sleep(&mut inner, ctx);
                 }
-                _ => return Err(()),
+                Err(e) => return Err(e.into()),
             }
         }
     }
@@ -65,31 +275,167 @@ impl Pipe {
     /// After successfully writing i >= 0 bytes, returns `Ok(i)`.
     /// Note that we may have i < `n` if an copy-in error happened.
     /// If the pipe was full, sleeps at `write_waitchannel` and tries again after wakeup.
-    /// If an error happened, returns `Err(())`.
-    pub fn write(&self, addr: UVAddr, n: usize, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, ()> {
+    /// If `nonblock` is set, returns `Ok(i)` instead, or `Err(KernelError::TryAgain)` if i = 0.
+    /// If an error happened, returns `Err(KernelError)`.
+    pub fn write(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
         let mut written = 0;
         let mut inner = self.inner.lock();
         loop {
-            match inner.try_write(addr + written, n - written, ctx) {
+            let copy = |dst: &mut [u8], off: usize, ctx: &mut KernelCtx<'_, '_>| {
+                ctx.proc_mut()
+                    .memory_mut()
+                    .copy_in_bytes(dst, addr + written + off)
+            };
+            match inner.try_write(n - written, copy, ctx) {
                 Ok(r) => {
                     written += r;
                     self.read_waitchannel.wakeup(ctx.kernel());
-                    if written < n {
+                    ctx.kernel().poll_queue().wakeup(ctx.kernel());
+                    if written == n {
+                        return Ok(written);
+                    } else if !nonblock {
                         self.write_waitchannel.sleep(&mut inner, ctx);
-                    } else {
+                    } else if written > 0 {
                         return Ok(written);
+                    } else {
+                        return Err(KernelError::TryAgain);
                     }
                 }
                 Err(PipeError::InvalidCopyin(i)) => {
                     self.read_waitchannel.wakeup(ctx.kernel());
+                    ctx.kernel().poll_queue().wakeup(ctx.kernel());
                     return Ok(written + i);
                 }
-                _ => return Err(()),
+                Err(e) => return Err(e.into()),
             }
         }
     }
 
-    fn close(&self, writable: bool, ctx: &KernelCtx<'_, '_>) -> bool {
+    /// Writes as much of `src` as the pipe has room for, without sleeping.
+    /// Returns Ok(number of bytes written) on success, Err(KernelError) on error.
+    pub fn write_kernel(
+        &self,
+        src: &[u8],
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let mut inner = self.inner.lock();
+        let copy = |dst: &mut [u8], off: usize, _: &mut KernelCtx<'_, '_>| {
+            dst.copy_from_slice(&src[off..off + dst.len()]);
+            Ok(())
+        };
+        let written = inner.try_write(src.len(), copy, ctx)?;
+        if written > 0 {
+            self.read_waitchannel.wakeup(ctx.kernel());
+            ctx.kernel().poll_queue().wakeup(ctx.kernel());
+        }
+        Ok(written)
+    }
+
+    /// Sleeps until the pipe has unread bytes or its write end is closed, or returns
+    /// `Err(KernelError::TryAgain)` instead if `nonblock` is set.
+    /// Returns Ok(number of unread bytes, 0 if the write end was closed) on success,
+    /// Err(KernelError) on error.
+    pub fn wait_unread(
+        &self,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let mut inner = self.inner.lock();
+        while inner.data.is_empty() && inner.writeopen {
+            if nonblock {
+                return Err(KernelError::TryAgain);
+            }
+            if ctx.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            self.read_waitchannel.sleep(&mut inner, ctx);
+        }
+        Ok(inner.data.len)
+    }
+
+    /// Reads exactly `dst.len()` bytes into `dst`, without sleeping.
+    /// Returns Ok(()) on success, or Err(KernelError::TryAgain) if the pipe has fewer bytes,
+    /// in which case it reads none.
+    pub fn read_exact_kernel(
+        &self,
+        dst: &mut [u8],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let mut inner = self.inner.lock();
+        if inner.data.len < dst.len() {
+            return Err(KernelError::TryAgain);
+        }
+        let mut read = 0;
+        while read < dst.len() {
+            let src = inner.data.front();
+            let n = cmp::min(src.len(), dst.len() - read);
+            dst[read..read + n].copy_from_slice(&src[..n]);
+            inner.data.consume(n);
+            read += n;
+        }
+        drop(inner);
+        self.write_waitchannel.wakeup(ctx.kernel());
+        ctx.kernel().poll_queue().wakeup(ctx.kernel());
+        Ok(())
+    }
+
+    /// Moves up to `n` bytes from this pipe to pipe `to`, handing over whole pages of data
+    /// instead of copying them where it can. Sleeps while this pipe is empty or `to` is full,
+    /// unless `nonblock` is set.
+    /// Returns Ok(number of bytes moved, 0 if the write end of this pipe was closed) on success,
+    /// Err(KernelError) on error.
+    pub fn splice(
+        &self,
+        to: &Pipe,
+        n: usize,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        if ptr::eq(self, to) {
+            return Err(KernelError::InvalidArgument);
+        }
+        loop {
+            let generation = ctx.kernel().poll_queue().generation();
+            // Lock the pipes in the order of their addresses, so that splices between the same
+            // pipes in opposite directions do not deadlock.
+            let (mut from, mut into) = if (self as *const Self) < (to as *const Self) {
+                let from = self.inner.lock();
+                (from, to.inner.lock())
+            } else {
+                let into = to.inner.lock();
+                (self.inner.lock(), into)
+            };
+            if !into.readopen {
+                return Err(KernelError::BrokenPipe);
+            }
+            if from.data.is_empty() && !from.writeopen {
+                return Ok(0);
+            }
+            let moved = from.data.move_to(&mut into.data, n);
+            drop(from);
+            drop(into);
+            if moved > 0 {
+                self.write_waitchannel.wakeup(ctx.kernel());
+                to.read_waitchannel.wakeup(ctx.kernel());
+                ctx.kernel().poll_queue().wakeup(ctx.kernel());
+                return Ok(moved);
+            } else if nonblock {
+                return Err(KernelError::TryAgain);
+            } else if ctx.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            // Wait for data in this pipe or room in `to`.
+            ctx.kernel().poll_queue().wait(generation, ctx);
+        }
+    }
+
+    fn close(&self, writable: bool, ctx: &KernelCtx<'_, '_>) {
         let mut inner = self.inner.lock();
 
         if writable {
@@ -99,139 +445,185 @@ impl Pipe {
             inner.readopen = false;
             self.write_waitchannel.wakeup(ctx.kernel());
         }
+        drop(inner);
+        ctx.kernel().poll_queue().wakeup(ctx.kernel());
+    }
 
-        // Return whether pipe should be freed or not.
-        !inner.readopen && !inner.writeopen
+    /// Returns the size of the pipe's buffer in bytes.
+    pub fn capacity(&self) -> usize {
+        self.inner.lock().data.capacity()
     }
-}
 
-/// # Safety
-///
-/// `ptr` always refers to a `Pipe`.
-/// Also, for a single `Pipe`, we have a single read-only `AllocatedPipe` and a single write-only `AllocatedPipe`.
-/// The `PipeInner`'s readopen/writeopen field denotes whether the read-only/write-only `AllocatedPipe` is still open,
-/// and hence, we can safely free the `Pipe` only after both the readopen/writeopen field is false, since this means
-/// all `AllocatedPipe`s were closed.
-pub struct AllocatedPipe {
-    ptr: NonNull<Pipe>,
+    /// Resizes the pipe's buffer to hold at least `size` bytes, keeping the unread data.
+    /// Returns Ok(new size in bytes) on success, Err(KernelError) on error.
+    pub fn resize(&self, size: usize, ctx: &KernelCtx<'_, '_>) -> Result<usize, KernelError> {
+        let npages = pgroundup(cmp::max(size, 1)) / PGSIZE;
+        let mut inner = self.inner.lock();
+        inner.data.resize(npages)?;
+        let capacity = inner.data.capacity();
+        drop(inner);
+        self.write_waitchannel.wakeup(ctx.kernel());
+        ctx.kernel().poll_queue().wakeup(ctx.kernel());
+        Ok(capacity)
+    }
+
+    /// Returns the readiness of both ends of the pipe.
+    pub fn poll(&self) -> PollEvents {
+        let inner = self.inner.lock();
+        let mut events = PollEvents::empty();
+        if !inner.data.is_empty() {
+            events |= PollEvents::POLLIN;
+        }
+        if !inner.writeopen {
+            events |= PollEvents::POLLIN | PollEvents::POLLHUP;
+        }
+        if !inner.readopen {
+            events |= PollEvents::POLLOUT | PollEvents::POLLERR;
+        } else if !inner.data.is_full() {
+            events |= PollEvents::POLLOUT;
+        }
+        events
+    }
 }
 
-// `AllocatedPipe` is `Send` because we access `PipeInner` only after acquring a lock
-// and because `AllocatedPipe` does not point to thread-local data.
-unsafe impl Send for AllocatedPipe {}
+/// A reference to a `Pipe` allocated from the `PipeTable`.
+/// For a single `Pipe`, we have a single read-only `AllocatedPipe` and a single write-only `AllocatedPipe`.
+/// The `PipeInner`'s readopen/writeopen field denotes whether the read-only/write-only `AllocatedPipe` is still open.
+/// The `Pipe` returns to the `PipeTable` after all `AllocatedPipe`s were closed.
+pub struct AllocatedPipe(ArenaRc<PipeTable>);
 
 impl Deref for AllocatedPipe {
     type Target = Pipe;
 
     fn deref(&self) -> &Self::Target {
-        // SAFETY: `ptr` always refers to a `Pipe`.
-        unsafe { self.ptr.as_ref() }
+        &self.0
     }
 }
 
-impl KernelCtx<'_, '_> {
-    pub fn allocate_pipe(&self) -> Result<(RcFile, RcFile), ()> {
-        let allocator = hal().kmem();
-        let page = allocator.alloc().ok_or(())?;
-        let mut page = scopeguard::guard(page, |page| allocator.free(page));
-        let ptr = page.as_uninit_mut();
+impl PipeTable {
+    /// # Safety
+    ///
+    /// Must be used only after initializing it with `ChunkedArena::init`.
+    pub const unsafe fn new_pipes() -> Self {
+        SpinLock::new("PIPES", unsafe {
+            ChunkedArena::<Pipe, NPIPE>::new("pipes")
+        })
+    }
+}
 
+impl KernelCtx<'_, '_> {
+    pub fn allocate_pipe(&self) -> Result<(RcFile, RcFile), KernelError> {
         // TODO(https://github.com/kaist-cp/rv6/issues/367):
-        // Since Pipe is a huge struct, need to check whether stack is used to fill `*ptr`.
-        let ptr = NonNull::from(ptr.write(Pipe {
-            inner: SpinLock::new(
-                "pipe",
-                PipeInner {
-                    data: [0; PIPESIZE],
-                    nwrite: 0,
-                    nread: 0,
-                    readopen: true,
-                    writeopen: true,
-                },
-            ),
-            read_waitchannel: WaitChannel::new(),
-            write_waitchannel: WaitChannel::new(),
-        }));
-        let f0 = self.kernel().ftable().alloc_file(
-            FileType::Pipe {
-                pipe: AllocatedPipe { ptr },
-            },
-            true,
-            false,
-        )?;
+        // Since Pipe is a huge struct, need to check whether stack is used to fill the entry.
+        let pipe = self
+            .kernel()
+            .pipes()
+            .alloc(Pipe::default)
+            .ok_or(KernelError::FileTableFull)?;
+        let res = pipe.inner.lock().data.resize(1);
+        if let Err(e) = res {
+            pipe.free(());
+            return Err(e);
+        }
+        let reader = AllocatedPipe(pipe.clone());
+        let writer = AllocatedPipe(pipe);
+        let writer = scopeguard::guard(writer, |writer| writer.close(true, self));
+        let close = |typ: FileType, writable: bool| {
+            if let FileType::Pipe { pipe } = typ {
+                pipe.close(writable, self);
+            }
+        };
+
+        let f0 = self
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::Pipe { pipe: reader }, true, false)
+            .map_err(|typ| {
+                close(typ, false);
+                KernelError::FileTableFull
+            })?;
         let f0 = scopeguard::guard(f0, |f0| f0.free(self));
-        let f1 = self.kernel().ftable().alloc_file(
-            FileType::Pipe {
-                pipe: AllocatedPipe { ptr },
-            },
-            false,
-            true,
-        )?;
-
-        // Since files have been created successfully, prevent the page from being deallocated.
-        mem::forget(scopeguard::ScopeGuard::into_inner(page));
+        let writer = scopeguard::ScopeGuard::into_inner(writer);
+        let f1 = self
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::Pipe { pipe: writer }, false, true)
+            .map_err(|typ| {
+                close(typ, true);
+                KernelError::FileTableFull
+            })?;
         Ok((scopeguard::ScopeGuard::into_inner(f0), f1))
     }
 }
 
 impl AllocatedPipe {
-    pub fn close(self, writable: bool, ctx: &KernelCtx<'_, '_>) -> Option<Page> {
-        if self.deref().close(writable, ctx) {
-            // SAFETY:
-            // If `Pipe::close()` returned true, this means all `AllocatedPipe`s were closed.
-            // Hence, we can free the `Pipe`.
-            // Also, the following is safe since `ptr` holds a `Pipe` stored in a valid page allocated from `Kmem::alloc`.
-            Some(unsafe { Page::from_usize(self.ptr.as_ptr() as _) })
-        } else {
-            None
-        }
+    pub fn close(self, writable: bool, ctx: &KernelCtx<'_, '_>) {
+        self.deref().close(writable, ctx);
+        self.0.free(());
     }
 }
 
 pub enum PipeError {
     WaitForIO,
-    InvalidStatus,
+    Closed,
+    Killed,
     InvalidCopyin(usize),
 }
 
+impl From<PipeError> for KernelError {
+    fn from(e: PipeError) -> Self {
+        match e {
+            PipeError::WaitForIO => KernelError::TryAgain,
+            PipeError::Closed => KernelError::BrokenPipe,
+            PipeError::Killed => KernelError::Interrupted,
+            PipeError::InvalidCopyin(_) => KernelError::Fault,
+        }
+    }
+}
+
 impl PipeInner {
     /// Tries to write up to `n` bytes.
-    /// If the process was killed, returns `Err(InvalidStatus)`.
+    /// `copy(dst, off, ctx)` should copy the source from its `off`th byte to `dst`.
+    /// If the read end was closed, returns `Err(Closed)`.
+    /// If the process was killed, returns `Err(Killed)`.
     /// If an copy-in error happened after successfully writing i >= 0 bytes, returns `Err(InvalidCopyIn(i))`.
     /// Otherwise, returns `Ok(i)` after successfully writing i >= 0 bytes.
-    fn try_write(
+    fn try_write<F>(
         &mut self,
-        addr: UVAddr,
         n: usize,
+        mut copy: F,
         ctx: &mut KernelCtx<'_, '_>,
-    ) -> Result<usize, PipeError> {
-        let mut ch = [0u8];
-        if !self.readopen || ctx.proc().killed() {
-            return Err(PipeError::InvalidStatus);
+    ) -> Result<usize, PipeError>
+    where
+        F: FnMut(&mut [u8], usize, &mut KernelCtx<'_, '_>) -> Result<(), KernelError>,
+    {
+        if !self.readopen {
+            return Err(PipeError::Closed);
         }
-        for i in 0..n {
-            if self.nwrite == self.nread.wrapping_add(PIPESIZE as u32) {
+        if ctx.proc().killed() {
+            return Err(PipeError::Killed);
+        }
+        let mut written = 0;
+        while written < n {
+            let space = self.data.back_space();
+            if space.is_empty() {
                 //DOC: pipewrite-full
-                return Ok(i);
+                break;
             }
-            if ctx
-                .proc_mut()
-                .memory_mut()
-                .copy_in_bytes(&mut ch, addr + i)
-                .is_err()
-            {
-                return Err(PipeError::InvalidCopyin(i));
+            let len = cmp::min(space.len(), n - written);
+            if copy(&mut space[..len], written, ctx).is_err() {
+                return Err(PipeError::InvalidCopyin(written));
             }
-            self.data[self.nwrite as usize % PIPESIZE] = ch[0];
-            self.nwrite = self.nwrite.wrapping_add(1);
+            self.data.commit(len);
+            written += len;
         }
-        Ok(n)
+        Ok(written)
     }
 
     /// Tries to read up to `n` bytes.
     /// If successful read i > 0 bytes, returns `Ok(i: usize)`.
     /// If the pipe was empty, returns `Err(WaitForIO)`.
-    /// If the process was killed, returns `Err(InvalidStatus)`.
+    /// If the process was killed, returns `Err(Killed)`.
     fn try_read(
         &mut self,
         addr: UVAddr,
@@ -239,56 +631,76 @@ impl PipeInner {
         ctx: &mut KernelCtx<'_, '_>,
     ) -> Result<usize, PipeError> {
         //DOC: pipe-empty
-        if self.nread == self.nwrite && self.writeopen {
+        if self.data.is_empty() && self.writeopen {
             if ctx.proc().killed() {
-                return Err(PipeError::InvalidStatus);
+                return Err(PipeError::Killed);
             }
             return Err(PipeError::WaitForIO);
         }
 
         //DOC: piperead-copy
-        for i in 0..n {
-            if self.nread == self.nwrite {
-                return Ok(i);
+        let mut read = 0;
+        while read < n {
+            let data = self.data.front();
+            if data.is_empty() {
+                break;
             }
-            let ch = [self.data[self.nread as usize % PIPESIZE]];
-            self.nread = self.nread.wrapping_add(1);
+            let len = cmp::min(data.len(), n - read);
             if ctx
                 .proc_mut()
                 .memory_mut()
-                .copy_out_bytes(addr + i, &ch)
+                .copy_out_bytes(addr + read, &data[..len])
                 .is_err()
             {
-                return Ok(i);
+                break;
             }
+            self.data.consume(len);
+            read += len;
         }
-        Ok(n)
+        Ok(read)
     }
 }
 
 impl KernelCtx<'_, '_> {
     /// Create a pipe, put read/write file descriptors in fd0 and fd1.
-    /// Returns Ok(()) on success, Err(()) on error.
-    pub fn pipe(&mut self, fdarray: UVAddr) -> Result<(), ()> {
+    /// `flags` may contain O_NONBLOCK, which is set on both files, and O_CLOEXEC, which is set on
+    /// both descriptors.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn pipe(&mut self, fdarray: UVAddr, flags: FcntlFlags) -> Result<(), KernelError> {
+        if !(FcntlFlags::O_NONBLOCK | FcntlFlags::O_CLOEXEC).contains(flags) {
+            return Err(KernelError::InvalidArgument);
+        }
         let (pipereader, pipewriter) = self.allocate_pipe()?;
+        pipereader.set_status_flags(flags);
+        pipewriter.set_status_flags(flags);
 
-        let fd1 = if let Ok(fd) = pipereader.fdalloc(self) {
-            fd
-        } else {
-            pipewriter.free(self);
-            return Err(());
+        let fd1 = match pipereader.fdalloc(self) {
+            Ok(fd) => fd,
+            Err(e) => {
+                pipewriter.free(self);
+                return Err(e);
+            }
         };
 
-        let fd2 = if let Ok(fd) = pipewriter.fdalloc(self) {
-            fd
-        } else {
-            self.proc_mut().deref_mut_data().open_files[fd1 as usize]
-                .take()
-                .unwrap()
-                .free(self);
-            return Err(());
+        let fd2 = match pipewriter.fdalloc(self) {
+            Ok(fd) => fd,
+            Err(e) => {
+                self.proc_mut()
+                    .deref_mut_data()
+                    .fds
+                    .take(fd1 as usize)
+                    .unwrap()
+                    .free(self);
+                return Err(e);
+            }
         };
 
+        if flags.contains(FcntlFlags::O_CLOEXEC) {
+            let data = self.proc_mut().deref_mut_data();
+            data.fds.set_cloexec(fd1 as usize, true);
+            data.fds.set_cloexec(fd2 as usize, true);
+        }
+
         self.proc_mut().memory_mut().copy_out(fdarray, &[fd1, fd2])
     }
 }
@@ -0,0 +1,161 @@
+//! Waiting for any of several files to become ready.
+//!
+//! A process can sleep on only one wait channel at a time, so pollers don't register on each
+//! file's own wait channels. Instead, every pollable object calls `PollQueue::wakeup` whenever
+//! its readiness may have changed, and pollers sleep on the kernel-wide `PollQueue`.
+//! The queue keeps a generation number that each wakeup increments, so that a poller that
+//! checked its files at some generation never misses a change that happened afterwards.
+
+use bitflags::bitflags;
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    arch::addr::UVAddr, error::KernelError, kernel::KernelRef, lock::SleepableLock, param::NOFILE,
+    proc::KernelCtx, some_or,
+};
+
+bitflags! {
+    /// Readiness events of a file.
+    pub struct PollEvents: i16 {
+        /// There is data to read, or reading would not block.
+        const POLLIN = 0x1;
+        /// Writing would not block.
+        const POLLOUT = 0x4;
+        /// Writing would fail, e.g. because the pipe has no readers. Always reported.
+        const POLLERR = 0x8;
+        /// The pipe has no writers. Always reported.
+        const POLLHUP = 0x10;
+        /// The file descriptor is not open. Always reported.
+        const POLLNVAL = 0x20;
+    }
+}
+
+/// `struct pollfd` of user programs.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct PollFd {
+    /// The file descriptor to poll, or a negative number to ignore this entry.
+    fd: i32,
+    /// The requested events.
+    events: i16,
+    /// The returned events.
+    revents: i16,
+}
+
+struct PollQueueInner {
+    /// Incremented whenever a pollable object's readiness may have changed.
+    generation: u64,
+    /// Number of processes sleeping in `PollQueue::wait`.
+    waiters: usize,
+}
+
+/// The kernel-wide wait queue of processes sleeping in poll().
+pub struct PollQueue {
+    inner: SleepableLock<PollQueueInner>,
+}
+
+impl PollQueue {
+    pub const fn new() -> Self {
+        Self {
+            inner: SleepableLock::new(
+                "poll",
+                PollQueueInner {
+                    generation: 0,
+                    waiters: 0,
+                },
+            ),
+        }
+    }
+
+    /// Returns the current generation.
+    /// Check the files only after calling this, and pass the result to `PollQueue::wait`.
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().generation
+    }
+
+    /// Sleeps until a wakeup happens after `generation`, or until the next clock tick.
+    /// Returns immediately if a wakeup already happened after `generation`.
+    pub fn wait(&self, generation: u64, ctx: &KernelCtx<'_, '_>) {
+        let mut inner = self.inner.lock();
+        if inner.generation == generation {
+            inner.waiters += 1;
+            inner.sleep(ctx);
+            inner.waiters -= 1;
+        }
+    }
+
+    /// Wakes up pollers because a pollable object's readiness may have changed.
+    /// Call this after changing the state the readiness depends on.
+    pub fn wakeup(&self, kernel: KernelRef<'_, '_>) {
+        let mut inner = self.inner.lock();
+        inner.generation = inner.generation.wrapping_add(1);
+        if inner.waiters > 0 {
+            inner.wakeup(kernel);
+        }
+    }
+
+    /// Wakes up pollers so that they can check their timeouts.
+    /// Called on every clock tick.
+    pub fn tick(&self, kernel: KernelRef<'_, '_>) {
+        let inner = self.inner.lock();
+        if inner.waiters > 0 {
+            inner.wakeup(kernel);
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Wait until one of the `nfds` files described by the `struct pollfd` array at `addr` is
+    /// ready, or until `timeout` ticks passed. Waits forever if `timeout` is negative.
+    /// Returns Ok(number of entries with nonzero revents) on success, Err(KernelError) on error.
+    pub fn poll(&mut self, addr: UVAddr, nfds: usize, timeout: i32) -> Result<usize, KernelError> {
+        if nfds > NOFILE {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut fds = [PollFd::default(); NOFILE];
+        let fds = &mut fds[..nfds];
+        self.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(fds.as_bytes_mut(), addr)?;
+
+        let ticks0 = self.kernel().clocks().ticks();
+        let ready = loop {
+            let generation = self.kernel().poll_queue().generation();
+            let mut ready = 0;
+            for pollfd in fds.iter_mut() {
+                pollfd.revents = self.poll_one(pollfd).bits();
+                if pollfd.revents != 0 {
+                    ready += 1;
+                }
+            }
+            if ready > 0 || timeout == 0 {
+                break ready;
+            }
+            if timeout > 0 && self.kernel().clocks().ticks() - ticks0 >= timeout as u64 {
+                break 0;
+            }
+            if self.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            self.kernel().poll_queue().wait(generation, self);
+        };
+
+        self.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(addr, fds.as_bytes())?;
+        Ok(ready)
+    }
+
+    /// Returns the events of `pollfd` that are ready.
+    fn poll_one(&self, pollfd: &PollFd) -> PollEvents {
+        if pollfd.fd < 0 {
+            return PollEvents::empty();
+        }
+        let f = self.proc().deref_data().fds.get(pollfd.fd as usize);
+        let f = some_or!(f, return PollEvents::POLLNVAL);
+        let requested = PollEvents::from_bits_truncate(pollfd.events)
+            | PollEvents::POLLERR
+            | PollEvents::POLLHUP;
+        f.poll(self) & requested
+    }
+}
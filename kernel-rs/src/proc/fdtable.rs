@@ -0,0 +1,199 @@
+//! The file descriptor table of a process.
+//!
+//! The first NOFILE descriptors live in the table itself. When they are all in use, the table
+//! takes a page from the page allocator for FDS_PER_PAGE more, up to NFDPAGE pages, and keeps the
+//! pages until the process exits.
+
+use core::{
+    mem,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+use array_macro::array;
+
+use crate::{
+    arch::addr::PGSIZE,
+    error::KernelError,
+    file::RcFile,
+    hal::hal,
+    page::{Page, RawPage},
+    param::{NFDPAGE, NOFILE},
+    some_or,
+    util::static_vec::StaticVec,
+};
+
+/// Number of descriptors in a page.
+const FDS_PER_PAGE: usize = PGSIZE / mem::size_of::<FdEntry>();
+
+/// A file descriptor.
+struct FdEntry {
+    file: Option<RcFile>,
+
+    /// Whether exec() closes the descriptor.
+    cloexec: bool,
+}
+
+impl FdEntry {
+    const fn new() -> Self {
+        Self {
+            file: None,
+            cloexec: false,
+        }
+    }
+}
+
+pub struct FdTable {
+    entries: [FdEntry; NOFILE],
+
+    /// Pages of the descriptors past NOFILE, each holding FDS_PER_PAGE `FdEntry`s.
+    pages: StaticVec<Page, NFDPAGE>,
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        Self {
+            entries: array![_ => FdEntry::new(); NOFILE],
+            pages: StaticVec::new(),
+        }
+    }
+
+    /// Returns the number of descriptors, in use or not.
+    pub fn len(&self) -> usize {
+        NOFILE + self.pages.len() * FDS_PER_PAGE
+    }
+
+    fn page(page: &Page) -> &[FdEntry; FDS_PER_PAGE] {
+        // SAFETY: a page in `pages` holds FDS_PER_PAGE initialized `FdEntry`s, and is aligned.
+        unsafe { &*(page.deref() as *const RawPage as *const [FdEntry; FDS_PER_PAGE]) }
+    }
+
+    fn page_mut(page: &mut Page) -> &mut [FdEntry; FDS_PER_PAGE] {
+        // SAFETY: a page in `pages` holds FDS_PER_PAGE initialized `FdEntry`s, and is aligned.
+        unsafe { &mut *(page.deref_mut() as *mut RawPage as *mut [FdEntry; FDS_PER_PAGE]) }
+    }
+
+    fn entry(&self, fd: usize) -> Option<&FdEntry> {
+        if fd < NOFILE {
+            return Some(&self.entries[fd]);
+        }
+        let fd = fd - NOFILE;
+        let page = self.pages.get(fd / FDS_PER_PAGE)?;
+        Some(&Self::page(page)[fd % FDS_PER_PAGE])
+    }
+
+    fn entry_mut(&mut self, fd: usize) -> Option<&mut FdEntry> {
+        if fd < NOFILE {
+            return Some(&mut self.entries[fd]);
+        }
+        let fd = fd - NOFILE;
+        let page = self.pages.get_mut(fd / FDS_PER_PAGE)?;
+        Some(&mut Self::page_mut(page)[fd % FDS_PER_PAGE])
+    }
+
+    /// Returns the open file of descriptor `fd`.
+    pub fn get(&self, fd: usize) -> Option<&RcFile> {
+        self.entry(fd)?.file.as_ref()
+    }
+
+    /// Closes descriptor `fd`, and returns its open file for the caller to free.
+    pub fn take(&mut self, fd: usize) -> Option<RcFile> {
+        let entry = self.entry_mut(fd)?;
+        entry.cloexec = false;
+        entry.file.take()
+    }
+
+    /// Returns whether exec() closes descriptor `fd`.
+    pub fn cloexec(&self, fd: usize) -> bool {
+        self.entry(fd).map_or(false, |entry| entry.cloexec)
+    }
+
+    /// Sets whether exec() closes descriptor `fd`, which must be open.
+    pub fn set_cloexec(&mut self, fd: usize, cloexec: bool) {
+        let entry = self.entry_mut(fd).expect("set_cloexec");
+        assert!(entry.file.is_some(), "set_cloexec");
+        entry.cloexec = cloexec;
+    }
+
+    /// Makes `file` the lowest closed descriptor, adding a page of descriptors if none is.
+    /// Returns Ok(the descriptor) on success, or Err(`file` and KernelError) on error.
+    pub fn insert(&mut self, file: RcFile) -> Result<usize, (RcFile, KernelError)> {
+        let fd = match (0..self.len()).find(|fd| self.get(*fd).is_none()) {
+            Some(fd) => fd,
+            None => {
+                if self.pages.is_full() {
+                    return Err((file, KernelError::TooManyFiles));
+                }
+                let page = some_or!(
+                    hal().kmem().alloc(),
+                    return Err((file, KernelError::NoMemory))
+                );
+                self.push_page(page);
+                self.len() - FDS_PER_PAGE
+            }
+        };
+        let entry = self.entry_mut(fd).expect("insert");
+        entry.file = Some(file);
+        entry.cloexec = false;
+        Ok(fd)
+    }
+
+    /// Adds a page of closed descriptors.
+    fn push_page(&mut self, mut page: Page) {
+        let entries = page.as_uninit_mut::<[FdEntry; FDS_PER_PAGE]>().as_mut_ptr() as *mut FdEntry;
+        for i in 0..FDS_PER_PAGE {
+            // SAFETY: the page has room for FDS_PER_PAGE `FdEntry`s.
+            unsafe { ptr::write(entries.add(i), FdEntry::new()) };
+        }
+        let _ = self.pages.push(page);
+    }
+
+    /// Allocates as many pages as this table has, for a copy of it.
+    /// Returns Ok(the pages) on success, Err(KernelError::NoMemory) on error.
+    pub fn alloc_pages(&self) -> Result<StaticVec<Page, NFDPAGE>, KernelError> {
+        let mut pages = StaticVec::new();
+        while pages.len() < self.pages.len() {
+            match hal().kmem().alloc() {
+                Some(page) => {
+                    let _ = pages.push(page);
+                }
+                None => {
+                    Self::free_pages(pages);
+                    return Err(KernelError::NoMemory);
+                }
+            }
+        }
+        Ok(pages)
+    }
+
+    /// Returns pages from `alloc_pages` to `Kmem`.
+    pub fn free_pages(mut pages: StaticVec<Page, NFDPAGE>) {
+        while let Some(page) = pages.pop() {
+            hal().kmem().free(page);
+        }
+    }
+
+    /// Makes this table, which must be empty, a copy of `other` for a child process, with
+    /// `pages` from `other.alloc_pages()`. The copy refers to the same open files.
+    pub fn clone_from(&mut self, other: &Self, mut pages: StaticVec<Page, NFDPAGE>) {
+        assert!(self.pages.is_empty(), "clone_from");
+        while let Some(page) = pages.pop() {
+            self.push_page(page);
+        }
+        for fd in 0..self.len() {
+            if let Some(file) = other.get(fd) {
+                let entry = self.entry_mut(fd).expect("clone_from");
+                entry.file = Some(file.clone());
+                entry.cloexec = other.cloexec(fd);
+            }
+        }
+    }
+
+    /// Returns the pages to `Kmem`, once every descriptor is closed.
+    pub fn shrink(&mut self) {
+        assert!((0..self.len()).all(|fd| self.get(fd).is_none()), "shrink");
+        while let Some(page) = self.pages.pop() {
+            hal().kmem().free(page);
+        }
+    }
+}
@@ -2,7 +2,7 @@ use core::ops::Deref;
 
 use super::*;
 use crate::{
-    fs::{FileSystem, Ufs},
+    fs::FsInode,
     kernel::{kernel_ref, KernelRef},
     vm::UserMemory,
 };
@@ -56,7 +56,7 @@ impl<'id, 'p> KernelCtx<'id, 'p> {
     // Its name cannot be `yield` because `yield` is a reserved keyword.
     pub fn yield_cpu(&self) {
         let mut guard = self.proc.lock();
-        guard.deref_mut_info().state = Procstate::RUNNABLE;
+        guard.deref_mut_info().set_runnable();
         unsafe { guard.sched() };
     }
 }
@@ -118,16 +118,18 @@ impl<'id, 'p> CurrentProc<'id, 'p> {
         unsafe { self.deref_mut_data().memory.assume_init_mut() }
     }
 
-    pub fn cwd(&self) -> &RcInode<<Ufs as FileSystem>::InodeInner> {
-        // SAFETY: cwd has been initialized according to the invariants
-        // of Proc and CurrentProc.
-        unsafe { self.deref_data().cwd.assume_init_ref() }
+    pub fn cwd(&self) -> &FsInode {
+        self.deref_data()
+            .cwd
+            .as_ref()
+            .expect("cwd: no current directory")
     }
 
-    pub fn cwd_mut(&mut self) -> &mut RcInode<<Ufs as FileSystem>::InodeInner> {
-        // SAFETY: cwd has been initialized according to the invariants
-        // of Proc and CurrentProc.
-        unsafe { self.deref_mut_data().cwd.assume_init_mut() }
+    pub fn cwd_mut(&mut self) -> &mut FsInode {
+        self.deref_mut_data()
+            .cwd
+            .as_mut()
+            .expect("cwd: no current directory")
     }
 }
 
@@ -3,29 +3,34 @@ use core::{
     mem::{self, MaybeUninit},
     ops::Deref,
     ptr, str,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
 };
 
-use array_macro::array;
-
 use crate::{
     arch::riscv::intr_get,
-    file::RcFile,
-    fs::{FileSystem, RcInode, Ufs},
+    cred::Cred,
+    fs::FsInode,
     hal::hal,
     lock::SpinLock,
     page::Page,
-    param::{MAXPROCNAME, NOFILE},
+    param::{DEFAULT_UMASK, MAXPROCNAME},
+    psi::{PsiResource, PsiState},
     util::branded::Branded,
     vm::UserMemory,
 };
 
+mod fdtable;
 mod kernel_ctx;
 mod procs;
+mod ptrace;
+mod signal;
 mod wait_channel;
 
+pub use fdtable::*;
 pub use kernel_ctx::*;
 pub use procs::*;
+pub use ptrace::*;
+pub use signal::*;
 pub use wait_channel::*;
 
 extern "C" {
@@ -62,8 +67,9 @@ pub struct Context {
 /// uservec in trampoline.S saves user registers in the trapframe,
 /// then initializes registers from the trapframe's
 /// kernel_sp, kernel_hartid, kernel_satp, and jumps to kernel_trap.
-/// usertrapret() and userret in trampoline.S set up
-/// the trapframe's kernel_*, restore user registers from the
+/// user_trap_ret() sets up the trapframe's kernel_* once after fork(),
+/// return_to_user() updates kernel_hartid before each return,
+/// and userret in trampoline.S restores user registers from the
 /// trapframe, switch to the user page table, and enter user space.
 /// The trapframe includes callee-saved user registers like s0-s11 because the
 /// return-to-user path via usertrapret() doesn't return through
@@ -187,6 +193,7 @@ pub enum Procstate {
     SLEEPING,
     UNUSED,
     USED,
+    STOPPED,
 }
 
 type Pid = i32;
@@ -204,6 +211,25 @@ pub struct ProcInfo {
 
     /// Process ID.
     pid: Pid,
+
+    /// Process group ID.
+    pgid: Pid,
+
+    /// If true, the parent traces this process with ptrace().
+    traced: bool,
+
+    /// If not `None`, the process stops for this reason before it returns to user space.
+    stop_request: Option<StopReason>,
+
+    /// If true, the process stops after executing an instruction once resumed.
+    stepping: bool,
+
+    /// Why the process stopped, until the parent's wait() reports it.
+    stop_report: Option<StopReason>,
+
+    /// If not `None`, the process is a kernel thread, which runs this function instead of
+    /// returning to user space.
+    kthread: Option<fn(KernelCtx<'_, '_>) -> !>,
 }
 
 /// Proc::data are private to the process, so lock need not be held.
@@ -221,10 +247,26 @@ pub struct ProcData {
     context: Context,
 
     /// Open files.
-    pub open_files: [Option<RcFile>; NOFILE],
+    pub fds: FdTable,
 
-    /// Current directory.
-    cwd: MaybeUninit<RcInode<<Ufs as FileSystem>::InodeInner>>,
+    /// Current directory, or `None` for kernel threads and for the initial process until it
+    /// mounts the root device.
+    cwd: Option<FsInode>,
+
+    /// Root directory for absolute paths, or `None` for the file system's root.
+    pub root: Option<FsInode>,
+
+    /// File mode bits to clear when creating files.
+    pub umask: u32,
+
+    /// User and group IDs and capabilities.
+    pub cred: Cred,
+
+    /// The system calls to trace, as a bit per system call number.
+    pub trace_mask: u64,
+
+    /// Addresses and original halfwords of the breakpoints that single-stepping put.
+    step_breakpoints: [Option<(usize, u16)>; 2],
 
     /// Process name (debugging).
     pub name: [u8; MAXPROCNAME],
@@ -238,7 +280,6 @@ pub struct ProcData {
 ///   - `data.trap_frame` is a valid pointer, and `Page::from_usize(data.trap_frame)` is safe.
 ///   - `data.memory` has been initialized.
 /// * If `info.state` ∉ { `UNUSED`, `USED` }, then
-///   - `data.cwd` has been initialized.
 ///   - `parent` contains null or a valid pointer. `parent` can be null only when `self` is the same
 ///     as `initial_proc` of `Procs` that contains `self`.
 pub struct Proc {
@@ -254,6 +295,15 @@ pub struct Proc {
 
     /// If true, the process have been killed.
     killed: AtomicBool,
+
+    /// Signals that the process ignored since sigpending() last returned them, a bit for each.
+    pending: AtomicU32,
+
+    /// CPU time the process spent in user mode, in nanoseconds.
+    utime: AtomicU64,
+
+    /// CPU time the process spent in the kernel, in nanoseconds.
+    stime: AtomicU64,
 }
 
 /// A branded reference to a `Proc`.
@@ -299,10 +349,21 @@ impl Procstate {
             Procstate::RUNNABLE => "runble",
             Procstate::RUNNING => "run   ",
             Procstate::ZOMBIE => "zombie",
+            Procstate::STOPPED => "stop  ",
         }
     }
 }
 
+impl ProcInfo {
+    /// Makes the process runnable, so that it waits for a CPU.
+    pub fn set_runnable(&mut self) {
+        self.state = Procstate::RUNNABLE;
+        hal()
+            .psi()
+            .change(None, Some(PsiState::Stalled(PsiResource::Cpu)));
+    }
+}
+
 impl ProcData {
     const fn new() -> Self {
         Self {
@@ -310,8 +371,13 @@ impl ProcData {
             trap_frame: ptr::null_mut(),
             memory: MaybeUninit::uninit(),
             context: Context::new(),
-            open_files: array![_ => None; NOFILE],
-            cwd: MaybeUninit::uninit(),
+            fds: FdTable::new(),
+            cwd: None,
+            root: None,
+            umask: DEFAULT_UMASK,
+            cred: Cred::root(),
+            trace_mask: 0,
+            step_breakpoints: [None; 2],
             name: [0; MAXPROCNAME],
         }
     }
@@ -328,11 +394,20 @@ impl Proc {
                     waitchannel: ptr::null(),
                     xstate: 0,
                     pid: 0,
+                    pgid: 0,
+                    traced: false,
+                    stop_request: None,
+                    stepping: false,
+                    stop_report: None,
+                    kthread: None,
                 },
             ),
             data: UnsafeCell::new(ProcData::new()),
             child_waitchannel: WaitChannel::new(),
             killed: AtomicBool::new(false),
+            pending: AtomicU32::new(0),
+            utime: AtomicU64::new(0),
+            stime: AtomicU64::new(0),
         }
     }
 }
@@ -346,6 +421,30 @@ impl Proc {
     pub fn killed(&self) -> bool {
         self.killed.load(Ordering::Acquire)
     }
+
+    /// Returns the pid of the process without its lock.
+    ///
+    /// # Safety
+    ///
+    /// The process must be running on this CPU, which keeps its pid from changing.
+    pub unsafe fn running_pid(&self) -> Pid {
+        unsafe { (*self.info.get_mut_raw()).pid }
+    }
+
+    /// Adds `ns` nanoseconds to the CPU time of the process, in user mode if `user`, or in the
+    /// kernel otherwise.
+    pub fn add_cpu_time(&self, user: bool, ns: u64) {
+        let time = if user { &self.utime } else { &self.stime };
+        let _ = time.fetch_add(ns, Ordering::Relaxed);
+    }
+
+    /// Returns the CPU time of the process in user mode and in the kernel, in nanoseconds.
+    pub fn cpu_time(&self) -> (u64, u64) {
+        (
+            self.utime.load(Ordering::Relaxed),
+            self.stime.load(Ordering::Relaxed),
+        )
+    }
 }
 
 impl<'id, 's> ProcRef<'id, 's> {
@@ -446,6 +545,7 @@ impl<'id> ProcGuard<'id, '_> {
 
         // Clear the name.
         data.name[0] = 0;
+        data.step_breakpoints = [None; 2];
 
         // Clear the process's parent field.
         *self.get_mut_parent(&mut parent_guard) = ptr::null_mut();
@@ -455,16 +555,26 @@ impl<'id> ProcGuard<'id, '_> {
         let info = self.deref_mut_info();
         info.waitchannel = ptr::null();
         info.pid = 0;
+        info.pgid = 0;
         info.xstate = 0;
+        info.traced = false;
+        info.stop_request = None;
+        info.stepping = false;
+        info.stop_report = None;
+        info.kthread = None;
         info.state = Procstate::UNUSED;
 
         self.killed.store(false, Ordering::Release);
+        self.pending.store(0, Ordering::Relaxed);
+        self.utime.store(0, Ordering::Relaxed);
+        self.stime.store(0, Ordering::Relaxed);
     }
 
     /// Wake process from sleep().
     fn wakeup(&mut self) {
         if self.state() == Procstate::SLEEPING {
-            self.deref_mut_info().state = Procstate::RUNNABLE;
+            self.deref_mut_info().set_runnable();
+            hal().cpuidle().kick();
         }
     }
 
@@ -7,7 +7,6 @@ use core::{
 };
 
 use array_macro::array;
-use itertools::izip;
 use pin_project::pin_project;
 
 use super::*;
@@ -15,6 +14,9 @@ use crate::{
     arch::addr::{Addr, UVAddr, PGSIZE},
     arch::memlayout::kstack,
     arch::riscv::intr_on,
+    cputime::CpuState,
+    cred::Cred,
+    error::KernelError,
     fs::FileSystem,
     hal::hal,
     kalloc::Kmem,
@@ -22,10 +24,29 @@ use crate::{
     lock::{SpinLock, SpinLockGuard},
     page::Page,
     param::{NPROC, ROOTDEV},
+    psi::{PsiResource, PsiState},
+    trace_event,
+    tracepoint::TraceEvent,
     util::branded::Branded,
-    vm::UserMemory,
+    vm::{PteFlags, UserMemory},
+    with_fs,
 };
 
+/// A snapshot of a process, for /proc/<pid>/stat.
+pub struct ProcStat {
+    pub pid: Pid,
+    pub state: Procstate,
+    /// The pid of the parent, or 0 for the initial process.
+    pub ppid: Pid,
+    pub name: [u8; MAXPROCNAME],
+    /// Size of the user memory, in bytes.
+    pub size: usize,
+    /// CPU time in user mode, in nanoseconds.
+    pub utime: u64,
+    /// CPU time in the kernel, in nanoseconds.
+    pub stime: u64,
+}
+
 /// A user program that calls exec("/init").
 /// od -t xC initcode
 const INITCODE: [u8; 52] = [
@@ -64,7 +85,7 @@ pub struct Procs {
 /// A `ProcsRef<'id, 's>` can be created only from a `KernelRef<'id, 's>` that has the same `'id` tag.
 pub struct ProcsRef<'id, 's>(Branded<'id, Pin<&'s Procs>>);
 
-struct ProcIter<'id, 'a>(Branded<'id, core::slice::Iter<'a, Proc>>);
+pub(super) struct ProcIter<'id, 'a>(Branded<'id, core::slice::Iter<'a, Proc>>);
 
 /// A branded type that holds the guard of a `Procs::wait_lock`.
 ///
@@ -96,11 +117,7 @@ impl Procs {
     }
 
     /// Set up first user process.
-    pub fn user_proc_init(
-        self: Pin<&mut Self>,
-        cwd: RcInode<<Ufs as FileSystem>::InodeInner>,
-        allocator: Pin<&SpinLock<Kmem>>,
-    ) {
+    pub fn user_proc_init(self: Pin<&mut Self>, allocator: Pin<&SpinLock<Kmem>>) {
         let initial_proc = Branded::new(self.as_ref(), |procs| {
             let procs = ProcsRef(procs);
 
@@ -134,9 +151,8 @@ impl Procs {
 
             let name = b"initcode\x00";
             (&mut data.name[..name.len()]).copy_from_slice(name);
-            let _ = data.cwd.write(cwd);
-            // It's safe because cwd now has been initialized.
-            guard.deref_mut_info().state = Procstate::RUNNABLE;
+            // Its current directory is set in forkret, once it mounts the root device.
+            guard.deref_mut_info().set_runnable();
 
             guard.deref().deref() as *const _
         });
@@ -147,6 +163,50 @@ impl Procs {
         *self.project().initial_proc = initial_proc;
     }
 
+    /// Starts a kernel thread named `name`, which runs `entry` in the kernel and never returns to
+    /// user space. Its parent is the initial process, so `user_proc_init` must have been called.
+    /// `name` must end with a NUL byte.
+    pub fn spawn_kthread(
+        self: Pin<&Self>,
+        name: &[u8],
+        entry: fn(KernelCtx<'_, '_>) -> !,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) {
+        Branded::new(self, |procs| {
+            let procs = ProcsRef(procs);
+
+            let trap_frame =
+                scopeguard::guard(allocator.alloc().expect("spawn_kthread: alloc"), |page| {
+                    allocator.free(page)
+                });
+
+            // A kernel thread has no user memory, but every process owns a page table.
+            let memory = UserMemory::new(trap_frame.addr(), None, allocator)
+                .expect("spawn_kthread: UserMemory::new");
+
+            let mut guard = procs
+                .alloc(scopeguard::ScopeGuard::into_inner(trap_frame), memory)
+                .expect("spawn_kthread: Procs::alloc");
+
+            // SAFETY: this process cannot be the current process yet.
+            let data = unsafe { guard.deref_mut_data() };
+
+            // Start at kthreadret instead of forkret.
+            data.context.ra = kthreadret as usize;
+            (&mut data.name[..name.len()]).copy_from_slice(name);
+
+            // The lock order is `wait_lock` -> `Proc::info`.
+            guard.reacquire_after(|p| {
+                let mut parent_guard = procs.wait_guard();
+                *p.get_mut_parent(&mut parent_guard) = procs.0.initial_proc();
+            });
+
+            let info = guard.deref_mut_info();
+            info.kthread = Some(entry);
+            info.set_runnable();
+        });
+    }
+
     fn initial_proc(self: Pin<&Self>) -> &Proc {
         assert!(!self.initial_proc.is_null());
         // SAFETY: invariant
@@ -159,13 +219,13 @@ impl Procs {
 }
 
 impl<'id, 's> ProcsRef<'id, 's> {
-    fn process_pool(&self) -> ProcIter<'id, 's> {
+    pub(super) fn process_pool(&self) -> ProcIter<'id, 's> {
         ProcIter::new(self)
     }
 
     /// Acquires the wait_lock of this `Procs` and returns the `WaitGuard`.
     /// You can access any of this `Procs`'s `Proc::parent` field only after acquiring the `WaitGuard`.
-    fn wait_guard(&self) -> WaitGuard<'id, 's> {
+    pub(super) fn wait_guard(&self) -> WaitGuard<'id, 's> {
         WaitGuard(self.0.brand(self.0.get_ref().wait_lock.lock()))
     }
 
@@ -173,7 +233,11 @@ impl<'id, 's> ProcsRef<'id, 's> {
     /// If found, initialize state required to run in the kernel,
     /// and return with p->lock held.
     /// If there are no free procs, or a memory allocation fails, return Err.
-    fn alloc(&self, trap_frame: Page, memory: UserMemory) -> Result<ProcGuard<'id, '_>, ()> {
+    fn alloc(
+        &self,
+        trap_frame: Page,
+        memory: UserMemory,
+    ) -> Result<ProcGuard<'id, '_>, KernelError> {
         for p in self.process_pool() {
             let mut guard = p.lock();
             if guard.deref_info().state == Procstate::UNUSED {
@@ -192,6 +256,7 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
                 let info = guard.deref_mut_info();
                 info.pid = self.0.allocpid();
+                info.pgid = info.pid;
                 // It's safe because trap_frame and memory now have been initialized.
                 info.state = Procstate::USED;
 
@@ -202,7 +267,7 @@ impl<'id, 's> ProcsRef<'id, 's> {
         let allocator = hal().kmem();
         allocator.free(trap_frame);
         memory.free(allocator);
-        Err(())
+        Err(KernelError::TryAgain)
     }
 
     /// Wake up all processes in the pool sleeping on waitchannel.
@@ -231,6 +296,7 @@ impl<'id, 's> ProcsRef<'id, 's> {
             let parent = pp.get_mut_parent(parent_guard);
             if *parent == proc {
                 *parent = self.0.initial_proc();
+                pp.lock().untrace();
                 self.0.initial_proc().child_waitchannel.wakeup(kernel);
             }
         }
@@ -238,25 +304,34 @@ impl<'id, 's> ProcsRef<'id, 's> {
 
     /// Create a new process, copying the parent.
     /// Sets up child kernel stack to return as if from fork() system call.
-    /// Returns Ok(new process id) on success, Err(()) on error.
+    /// Returns Ok(new process id) on success, Err(KernelError) on error.
     ///
     /// # Note
     ///
     /// `self` and `ctx` must have the same `'id` tag attached.
     /// Otherwise, UB may happen if the new `Proc` tries to read its `parent` field
     /// that points to a `Proc` that already dropped.
-    pub fn fork(&self, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, ()> {
+    pub fn fork(&self, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, KernelError> {
         let allocator = hal().kmem();
         // Allocate trap frame.
         let trap_frame =
-            scopeguard::guard(allocator.alloc().ok_or(())?, |page| allocator.free(page));
+            scopeguard::guard(allocator.alloc().ok_or(KernelError::NoMemory)?, |page| {
+                allocator.free(page)
+            });
 
         // Copy user memory from parent to child.
         let memory = ctx
             .proc_mut()
             .memory_mut()
             .clone(trap_frame.addr(), allocator)
-            .ok_or(())?;
+            .ok_or(KernelError::NoMemory)?;
+
+        // Allocate pages for the child's file descriptors past NOFILE.
+        let fd_pages = scopeguard::guard(ctx.proc().deref_data().fds.alloc_pages()?, |pages| {
+            FdTable::free_pages(pages)
+        });
+
+        let pgid = ctx.proc().lock().deref_info().pgid;
 
         // Allocate process.
         let mut np = self.alloc(scopeguard::ScopeGuard::into_inner(trap_frame), memory)?;
@@ -272,15 +347,15 @@ impl<'id, 's> ProcsRef<'id, 's> {
         unsafe { (*npdata.trap_frame).a0 = 0 };
 
         // Increment reference counts on open file descriptors.
-        for (nf, f) in izip!(
-            npdata.open_files.iter_mut(),
-            ctx.proc().deref_data().open_files.iter()
-        ) {
-            if let Some(file) = f {
-                *nf = Some(file.clone());
-            }
-        }
-        let _ = npdata.cwd.write(ctx.proc().cwd().clone());
+        npdata.fds.clone_from(
+            &ctx.proc().deref_data().fds,
+            scopeguard::ScopeGuard::into_inner(fd_pages),
+        );
+        npdata.cwd = ctx.proc().deref_data().cwd.clone();
+        npdata.root = ctx.proc().deref_data().root.clone();
+        npdata.umask = ctx.proc().deref_data().umask;
+        npdata.cred = ctx.proc().deref_data().cred;
+        npdata.trace_mask = ctx.proc().deref_data().trace_mask;
 
         npdata.name.copy_from_slice(&ctx.proc().deref_data().name);
 
@@ -295,15 +370,17 @@ impl<'id, 's> ProcsRef<'id, 's> {
         });
 
         // Set the process's state to RUNNABLE.
-        // It does not break the invariant because cwd now has been initialized.
-        np.deref_mut_info().state = Procstate::RUNNABLE;
+        let info = np.deref_mut_info();
+        info.pgid = pgid;
+        info.set_runnable();
+        hal().cpuidle().kick();
 
         Ok(pid)
     }
 
     /// Wait for a child process to exit and return its pid.
-    /// Return Err(()) if this process has no children.
-    pub fn wait(&self, addr: UVAddr, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, ()> {
+    /// Return Err(KernelError::NoChild) if this process has no children.
+    pub fn wait(&self, addr: UVAddr, ctx: &mut KernelCtx<'id, '_>) -> Result<Pid, KernelError> {
         let mut parent_guard = self.wait_guard();
 
         loop {
@@ -318,26 +395,36 @@ impl<'id, 's> ProcsRef<'id, 's> {
                     havekids = true;
                     if np.state() == Procstate::ZOMBIE {
                         let pid = np.deref_mut_info().pid;
-                        if !addr.is_null()
-                            && ctx
-                                .proc_mut()
+                        if !addr.is_null() {
+                            ctx.proc_mut()
                                 .memory_mut()
-                                .copy_out(addr, &np.deref_info().xstate)
-                                .is_err()
-                        {
-                            return Err(());
+                                .copy_out(addr, &np.deref_info().xstate)?;
                         }
                         // Reap the zombie child process.
                         // SAFETY: np.state() equals ZOMBIE.
                         unsafe { np.clear(parent_guard) };
                         return Ok(pid);
                     }
+                    if let Some(reason) = np.deref_info().stop_report {
+                        // Report a child's stop, once.
+                        let pid = np.deref_info().pid;
+                        if !addr.is_null() {
+                            ctx.proc_mut()
+                                .memory_mut()
+                                .copy_out(addr, &reason.wait_status())?;
+                        }
+                        np.deref_mut_info().stop_report = None;
+                        return Ok(pid);
+                    }
                 }
             }
 
             // No point waiting if we don't have any children.
-            if !havekids || ctx.proc().killed() {
-                return Err(());
+            if !havekids {
+                return Err(KernelError::NoChild);
+            }
+            if ctx.proc().killed() {
+                return Err(KernelError::Interrupted);
             }
 
             // Wait for a child to exit.
@@ -346,20 +433,128 @@ impl<'id, 's> ProcsRef<'id, 's> {
         }
     }
 
+    /// Returns whether the process `pid` exists and has not exited.
+    pub fn alive(&self, pid: Pid) -> bool {
+        let _parent_guard = self.wait_guard();
+        self.process_pool().any(|p| {
+            let guard = p.lock();
+            guard.deref_info().pid == pid
+                && !matches!(guard.state(), Procstate::UNUSED | Procstate::ZOMBIE)
+        })
+    }
+
     /// Kill the process with the given pid.
     /// The victim won't exit until it tries to return
     /// to user space (see usertrap() in trap.c).
-    /// Returns Ok(()) on success, Err(()) on error.
-    pub fn kill(&self, pid: Pid) -> Result<(), ()> {
+    /// A stopped victim gets resumed so that it can exit.
+    /// The sender, whose credentials are `sender`, must be allowed to signal the victim.
+    /// Returns Ok(()) on success, Err(KernelError::NoProcess) if no such process exists, or
+    /// Err(KernelError::NotPermitted) if the sender may not kill it.
+    pub fn kill(&self, pid: Pid, sender: &Cred) -> Result<(), KernelError> {
         for p in self.process_pool() {
             let mut guard = p.lock();
             if guard.deref_info().pid == pid {
+                // SAFETY: as in `ProcsRef::signal`.
+                if guard.state() != Procstate::UNUSED
+                    && !sender.may_signal(unsafe { &(*p.data.get()).cred })
+                {
+                    return Err(KernelError::NotPermitted);
+                }
+                // Kernel threads are not killed.
+                if guard.deref_info().kthread.is_some() {
+                    return Ok(());
+                }
                 p.kill();
                 guard.wakeup();
+                if guard.state() == Procstate::STOPPED {
+                    guard.deref_mut_info().set_runnable();
+                }
                 return Ok(());
             }
         }
-        Err(())
+        Err(KernelError::NoProcess)
+    }
+
+    /// Returns the pid of the process in the `slot`th entry of the process table, or None if the
+    /// entry is unused or out of the table.
+    pub fn pid_at(&self, slot: usize) -> Option<Pid> {
+        let p = self.process_pool().nth(slot)?;
+        let guard = p.lock();
+        if guard.state() == Procstate::UNUSED {
+            None
+        } else {
+            Some(guard.deref_info().pid)
+        }
+    }
+
+    /// Returns the number of processes that are running or runnable.
+    pub fn count_runnable(&self) -> usize {
+        self.process_pool()
+            .filter(|p| matches!(p.lock().state(), Procstate::RUNNING | Procstate::RUNNABLE))
+            .count()
+    }
+
+    /// Returns a snapshot of the process with the given pid, or None if no such process exists.
+    pub fn stat(&self, pid: Pid) -> Option<ProcStat> {
+        let mut parent_guard = self.wait_guard();
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.state() == Procstate::UNUSED || guard.deref_info().pid != pid {
+                continue;
+            }
+            let parent = *p.get_mut_parent(&mut parent_guard);
+            // SAFETY: the parent cannot exit and be freed while we hold the wait lock, so its pid
+            // does not change.
+            let ppid = unsafe { parent.as_ref() }
+                .map_or(0, |parent| unsafe { (*parent.info.get_mut_raw()).pid });
+            // SAFETY: the memory of a used process is initialized, and is not freed while we hold
+            // its lock. As in `dump`, the process may be changing its name or memory while we
+            // read them, which only garbles the snapshot.
+            let (name, size) = unsafe {
+                let data = &*p.data.get();
+                (data.name, data.memory.assume_init_ref().size())
+            };
+            let (utime, stime) = p.cpu_time();
+            return Some(ProcStat {
+                pid,
+                state: guard.state(),
+                ppid,
+                name,
+                size,
+                utime,
+                stime,
+            });
+        }
+        None
+    }
+
+    /// Calls `f` with each run of pages of the process with the given pid, as
+    /// `UserMemory::for_each_region` does. `current` is the pid of the caller.
+    /// Returns Ok(()) on success, Err(KernelError::NoProcess) if no such process exists, and
+    /// Err(KernelError::TryAgain) if it is running on another CPU.
+    pub fn for_each_region(
+        &self,
+        pid: Pid,
+        current: Pid,
+        f: impl FnMut(usize, usize, PteFlags),
+    ) -> Result<(), KernelError> {
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.state() == Procstate::UNUSED || guard.deref_info().pid != pid {
+                continue;
+            }
+            // A process changes its memory only while it runs, and never across a sleep, so
+            // unless it is the caller, its memory stays put while it does not run and we hold its
+            // lock, which keeps it from being scheduled.
+            if pid != current && guard.state() == Procstate::RUNNING {
+                return Err(KernelError::TryAgain);
+            }
+            // SAFETY: the memory of a used process is initialized, is not freed while we hold its
+            // lock, and does not change, as above.
+            unsafe { (*p.data.get()).memory.assume_init_ref() }.for_each_region(f);
+            return Ok(());
+        }
+        Err(KernelError::NoProcess)
     }
 
     /// Exit the current process.  Does not return.
@@ -372,20 +567,22 @@ impl<'id, 's> ProcsRef<'id, 's> {
             "init exiting"
         );
 
-        for i in 0..NOFILE {
-            let files = &mut ctx.proc_mut().deref_mut_data().open_files;
-            if let Some(f) = unsafe { files.get_unchecked_mut(i) }.take() {
+        for fd in 0..ctx.proc().deref_data().fds.len() {
+            if let Some(f) = ctx.proc_mut().deref_mut_data().fds.take(fd) {
                 f.free(ctx);
             }
         }
+        ctx.proc_mut().deref_mut_data().fds.shrink();
 
-        let tx = ctx.kernel().fs().as_pin().get_ref().begin_tx(ctx);
-        // SAFETY:
-        // * CurrentProc's cwd has been initialized.
-        // * It's ok to take cwd because proc will not be used any longer.
-        let cwd = unsafe { ctx.proc_mut().deref_mut_data().cwd.assume_init_read() };
-        cwd.free((&tx, ctx));
-        tx.end(ctx);
+        // Release the semaphores that the process holds.
+        ctx.sem_exit();
+
+        if let Some(cwd) = ctx.proc_mut().deref_mut_data().cwd.take() {
+            cwd.free(ctx);
+        }
+        if let Some(root) = ctx.proc_mut().deref_mut_data().root.take() {
+            root.free(ctx);
+        }
 
         // Give all children to init.
         let mut parent_guard = self.wait_guard();
@@ -398,6 +595,11 @@ impl<'id, 's> ProcsRef<'id, 's> {
         // * `parent` is a valid pointer according to the invariants of
         //   `Proc` and `CurrentProc`.
         unsafe { (*parent).child_waitchannel.wakeup(ctx.kernel()) };
+        // SAFETY: the same as above.
+        unsafe { (*parent).ignore_signal(SIGCHLD) };
+        // Pollers of the process's pidfds check whether it exited with `ProcsRef::alive`, which
+        // waits for the wait lock, until the process is a zombie.
+        ctx.kernel().poll_queue().wakeup(ctx.kernel());
 
         let mut guard = ctx.proc().lock();
 
@@ -424,19 +626,46 @@ impl Deref for ProcsRef<'_, '_> {
 
 /// A fork child's very first scheduling by scheduler() will swtch to forkret.
 unsafe fn forkret() -> ! {
-    let forkret_inner = |ctx: KernelCtx<'_, '_>| {
+    let forkret_inner = |mut ctx: KernelCtx<'_, '_>| {
         // Still holding p->lock from scheduler.
         unsafe { ctx.proc().info.unlock() };
         // File system initialization must be run in the context of a
         // regular process (e.g., because it calls sleep), and thus cannot
-        // be run from main().
-        ctx.kernel().fs().init(ROOTDEV, &ctx);
+        // be run from main(). The first process mounts the root device, and
+        // the others find it mounted.
+        let vfs = ctx.kernel().vfs();
+        match vfs.mount(ROOTDEV, &ctx) {
+            Ok(_) => {
+                let root = with_fs!(vfs.root(), fs => fs.root().into());
+                ctx.proc_mut().deref_mut_data().cwd = Some(root);
+            }
+            Err(KernelError::Busy) => (),
+            Err(e) => panic!("forkret: cannot mount the root device: {:?}", e),
+        }
+        ctx.ktest_boot();
         unsafe { ctx.user_trap_ret() }
     };
 
     unsafe { kernel_ctx(forkret_inner) }
 }
 
+/// A kernel thread's very first scheduling by scheduler() will swtch to kthreadret.
+unsafe fn kthreadret() -> ! {
+    let kthreadret_inner = |ctx: KernelCtx<'_, '_>| {
+        // Still holding p->lock from scheduler.
+        unsafe { ctx.proc().info.unlock() };
+        let entry = ctx
+            .proc()
+            .lock()
+            .deref_info()
+            .kthread
+            .expect("kthreadret: not a kernel thread");
+        entry(ctx)
+    };
+
+    unsafe { kernel_ctx(kthreadret_inner) }
+}
+
 impl<'id, 's> ProcIter<'id, 's> {
     fn new(procs: &ProcsRef<'id, 's>) -> Self {
         Self(procs.0.brand(procs.0.get_ref().process_pool.iter()))
@@ -468,25 +697,53 @@ impl<'id, 's> KernelRef<'id, 's> {
         // SAFETY: this function never moves to another CPU.
         let cpu = unsafe { hal().get_ref().cpus().current_unchecked() };
         cpu.set_proc(ptr::null_mut());
+        self.timers().start_watchdog();
         loop {
             // Avoid deadlock by ensuring that devices can interrupt.
             unsafe { intr_on() };
+            hal().watchdog().beat();
 
+            // The machine is suspended. Run nothing until it wakes up.
+            if hal().suspend().is_asleep() {
+                hal().suspend().sleep_cpu();
+                continue;
+            }
+
+            let mut ran = false;
             for p in self.procs().process_pool() {
                 let mut guard = p.lock();
                 if guard.state() == Procstate::RUNNABLE {
+                    ran = true;
                     // Switch to chosen process.  It is the process's job
                     // to release its lock and then reacquire it
                     // before jumping back to us.
                     guard.deref_mut_info().state = Procstate::RUNNING;
+                    hal().psi().change(
+                        Some(PsiState::Stalled(PsiResource::Cpu)),
+                        Some(PsiState::Running),
+                    );
                     cpu.set_proc(p.deref());
+                    self.timers().start_slice();
+                    let pid = guard.deref_info().pid;
+                    trace_event!(TraceEvent::SchedSwitch, 0, pid);
+                    // SAFETY: interrupts are disabled while we hold the lock of `p`.
+                    let _ = unsafe { hal().cputimes().switch(CpuState::System) };
                     unsafe { swtch(cpu.context_raw_mut(), &mut guard.deref_mut_data().context) };
+                    // SAFETY: interrupts are disabled while we hold the lock of `p`. The process is
+                    // still the current one, so it gets the time it ran in the kernel.
+                    let _ = unsafe { hal().cputimes().switch(CpuState::Idle) };
+                    hal().psi().change(Some(PsiState::Running), None);
+                    trace_event!(TraceEvent::SchedSwitch, pid, 0);
 
                     // Process is done running for now.
                     // It should have changed its p->state before coming back.
                     cpu.set_proc(ptr::null_mut());
                 }
             }
+
+            if !ran {
+                hal().cpuidle().idle(self);
+            }
         }
     }
 
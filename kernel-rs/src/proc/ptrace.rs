@@ -0,0 +1,402 @@
+//! Process tracing, enough to write a debugger for user programs.
+//!
+//! A parent traces a child either by attaching to it, or by the child asking for it with
+//! PTRACE_TRACEME before it calls exec(). A traced child stops before it returns to user space
+//! when it is attached, when it calls exec(), when it executes an `ebreak`, and after it
+//! executes an instruction in single-step mode. Its parent's wait() reports each stop once, and
+//! while the child is stopped, the parent can read its registers, read and write its memory,
+//! and resume it.
+//!
+//! RISC-V has no single-step mode that the supervisor can use, so the child puts `c.ebreak`s at
+//! the instructions that can come next when it resumes, and restores them when it stops.
+
+use core::mem;
+
+use zerocopy::{AsBytes, FromBytes};
+
+use super::*;
+use crate::{arch::addr::UVAddr, arch::riscv::fence_i, cred::Caps, error::KernelError, some_or};
+
+/// Requests of ptrace().
+pub const PTRACE_TRACEME: i32 = 0;
+pub const PTRACE_PEEKDATA: i32 = 2;
+pub const PTRACE_POKEDATA: i32 = 5;
+pub const PTRACE_CONT: i32 = 7;
+pub const PTRACE_SINGLESTEP: i32 = 9;
+pub const PTRACE_GETREGS: i32 = 12;
+pub const PTRACE_ATTACH: i32 = 16;
+pub const PTRACE_DETACH: i32 = 17;
+
+/// The `c.ebreak` instruction.
+pub const C_EBREAK: u16 = 0x9002;
+
+/// Why a process stopped.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum StopReason {
+    /// The parent attached to the process.
+    Attach = 1,
+    /// The process called exec().
+    Exec = 2,
+    /// The process executed an `ebreak`.
+    Breakpoint = 3,
+    /// The process executed an instruction in single-step mode.
+    Step = 4,
+    /// The process got SIGSTOP or SIGTSTP. Need not be traced.
+    Suspend = 5,
+}
+
+impl StopReason {
+    /// Returns the status that wait() reports for a process stopped for this reason.
+    /// The low byte is 0x7f, which tells it apart from the exit status of most programs.
+    pub fn wait_status(self) -> i32 {
+        (self as i32) << 8 | 0x7f
+    }
+}
+
+/// `struct user_regs` of user programs: the program counter, then `x1` to `x31`.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct UserRegs {
+    pub pc: usize,
+    pub x: [usize; 31],
+}
+
+impl TrapFrame {
+    /// Returns the user registers saved in this trap frame.
+    pub fn user_regs(&self) -> UserRegs {
+        UserRegs {
+            pc: self.epc,
+            x: [
+                self.ra, self.sp, self.gp, self.tp, self.t0, self.t1, self.t2, self.s0, self.s1,
+                self.a0, self.a1, self.a2, self.a3, self.a4, self.a5, self.a6, self.a7, self.s2,
+                self.s3, self.s4, self.s5, self.s6, self.s7, self.s8, self.s9, self.s10, self.s11,
+                self.t3, self.t4, self.t5, self.t6,
+            ],
+        }
+    }
+}
+
+impl UserRegs {
+    /// Returns the value of register `x{n}`.
+    fn reg(&self, n: usize) -> usize {
+        if n == 0 {
+            0
+        } else {
+            self.x[n - 1]
+        }
+    }
+
+    /// Returns the addresses of the instructions that can come after `inst`, which is at the
+    /// program counter. Returns two addresses only for conditional branches.
+    pub fn next_pcs(&self, inst: u32) -> [Option<usize>; 2] {
+        let pc = self.pc;
+        // Sign-extends the lowest `bits` bits of `x`, and adds it to `pc`.
+        let rel = |x: u32, bits: u32| {
+            pc.wrapping_add(((x << (32 - bits)) as i32 >> (32 - bits)) as usize)
+        };
+        let bit = |from: u32, to: u32| (inst >> from & 1) << to;
+
+        if inst & 0b11 != 0b11 {
+            // A compressed instruction.
+            let funct3 = inst >> 13 & 0b111;
+            let rs1 = (inst >> 7 & 0b11111) as usize;
+            let rs2 = inst >> 2 & 0b11111;
+            match (inst & 0b11, funct3) {
+                // c.j
+                (0b01, 0b101) => {
+                    let imm = bit(12, 11)
+                        | bit(11, 4)
+                        | (inst >> 9 & 0b11) << 8
+                        | bit(8, 10)
+                        | bit(7, 6)
+                        | bit(6, 7)
+                        | (inst >> 3 & 0b111) << 1
+                        | bit(2, 5);
+                    [Some(rel(imm, 12)), None]
+                }
+                // c.beqz, c.bnez
+                (0b01, 0b110) | (0b01, 0b111) => {
+                    let imm = bit(12, 8)
+                        | (inst >> 10 & 0b11) << 3
+                        | (inst >> 5 & 0b11) << 6
+                        | (inst >> 3 & 0b11) << 1
+                        | bit(2, 5);
+                    [Some(pc + 2), Some(rel(imm, 9))]
+                }
+                // c.jr, c.jalr
+                (0b10, 0b100) if rs1 != 0 && rs2 == 0 => [Some(self.reg(rs1) & !1), None],
+                _ => [Some(pc + 2), None],
+            }
+        } else {
+            let rs1 = (inst >> 15 & 0b11111) as usize;
+            match inst & 0x7f {
+                // jal
+                0x6f => {
+                    let imm = bit(31, 20)
+                        | (inst >> 21 & 0x3ff) << 1
+                        | bit(20, 11)
+                        | (inst >> 12 & 0xff) << 12;
+                    [Some(rel(imm, 21)), None]
+                }
+                // jalr
+                0x67 => {
+                    let imm = (inst as i32 >> 20) as usize;
+                    [Some(self.reg(rs1).wrapping_add(imm) & !1), None]
+                }
+                // Conditional branches.
+                0x63 => {
+                    let imm = bit(31, 12)
+                        | (inst >> 25 & 0x3f) << 5
+                        | (inst >> 8 & 0xf) << 1
+                        | bit(7, 11);
+                    [Some(pc + 4), Some(rel(imm, 13))]
+                }
+                _ => [Some(pc + 4), None],
+            }
+        }
+    }
+}
+
+impl<'id> ProcGuard<'id, '_> {
+    /// Resumes the process if it is stopped.
+    pub(super) fn resume(&mut self) {
+        let info = self.deref_mut_info();
+        info.stop_report = None;
+        if info.state == Procstate::STOPPED {
+            info.set_runnable();
+        }
+    }
+
+    /// Stops tracing the process, and resumes it if it is stopped.
+    pub(super) fn untrace(&mut self) {
+        let info = self.deref_mut_info();
+        info.traced = false;
+        info.stop_request = None;
+        info.stepping = false;
+        self.resume();
+    }
+}
+
+impl<'id, 's> ProcsRef<'id, 's> {
+    /// Returns the locked child of the current process whose pid is `pid`.
+    pub(super) fn child(
+        &self,
+        pid: Pid,
+        parent_guard: &mut WaitGuard<'id, '_>,
+        ctx: &KernelCtx<'id, '_>,
+    ) -> Result<ProcGuard<'id, 's>, KernelError> {
+        for p in self.process_pool() {
+            if *p.get_mut_parent(parent_guard) == ctx.proc().deref().deref() as *const _ {
+                let guard = p.lock();
+                if guard.deref_info().pid == pid && guard.state() != Procstate::ZOMBIE {
+                    return Ok(guard);
+                }
+            }
+        }
+        Err(KernelError::NoProcess)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Stops the current process for `reason`, and sleeps until it is resumed.
+    fn ptrace_stop(&mut self, reason: StopReason) {
+        let kernel = self.kernel();
+        let procs = kernel.procs();
+        let mut parent_guard = procs.wait_guard();
+
+        // The parent might be sleeping in wait().
+        let parent = *self.proc().get_mut_parent(&mut parent_guard);
+        // SAFETY: `parent` is valid since the current process is not the initial process, which
+        // no one can trace or stop with a signal.
+        unsafe { (*parent).child_waitchannel.wakeup(kernel) };
+
+        let mut guard = self.proc().lock();
+        let info = guard.deref_mut_info();
+        info.stop_request = None;
+        info.stop_report = Some(reason);
+        info.state = Procstate::STOPPED;
+        drop(parent_guard);
+        unsafe { guard.sched() };
+        let stepping = mem::replace(&mut guard.deref_mut_info().stepping, false);
+        drop(guard);
+
+        if stepping {
+            self.insert_step_breakpoints();
+        }
+        // The parent may have written instructions.
+        unsafe { fence_i() };
+    }
+
+    /// Stops the current process if it was asked to, by its tracer or by a signal.
+    /// Called before returning to user space.
+    pub fn check_stop(&mut self) {
+        let stop_request = self.proc().lock().deref_info().stop_request;
+        if let Some(reason) = stop_request {
+            self.ptrace_stop(reason);
+        }
+    }
+
+    /// Asks that the current process stop before it runs the new program, if it is traced.
+    /// Called from exec().
+    pub fn ptrace_exec(&mut self) {
+        self.proc_mut().deref_mut_data().step_breakpoints = [None; 2];
+        let mut guard = self.proc().lock();
+        if guard.deref_info().traced {
+            guard.deref_mut_info().stop_request = Some(StopReason::Exec);
+        }
+    }
+
+    /// Returns whether the current process is traced.
+    pub fn is_ptraced(&self) -> bool {
+        self.proc().lock().deref_info().traced
+    }
+
+    /// Handles a breakpoint exception of the current process.
+    /// Returns `false` if it should be killed instead, since no one traces it.
+    pub fn ptrace_breakpoint(&mut self) -> bool {
+        let stepped = self.remove_step_breakpoints();
+        if self.is_ptraced() {
+            self.ptrace_stop(if stepped {
+                StopReason::Step
+            } else {
+                StopReason::Breakpoint
+            });
+            true
+        } else {
+            // The tracer left while the process was single-stepping.
+            stepped
+        }
+    }
+
+    /// Puts a `c.ebreak` at each instruction that can come after the current one.
+    fn insert_step_breakpoints(&mut self) {
+        let regs = self.proc().trap_frame().user_regs();
+        let mut inst = 0u32;
+        let mem = self.proc_mut().memory_mut();
+        if mem
+            .copy_in_bytes(&mut inst.as_bytes_mut()[..2], regs.pc.into())
+            .is_err()
+        {
+            // The process will fault at once.
+            return;
+        }
+        if inst & 0b11 == 0b11 {
+            let _ = mem.copy_in_bytes(&mut inst.as_bytes_mut()[2..], (regs.pc + 2).into());
+        }
+
+        let mut saved = [None; 2];
+        for (i, next) in regs.next_pcs(inst).iter().enumerate() {
+            let next = some_or!(*next, continue);
+            if i == 1 && saved[0].map_or(false, |(addr, _)| addr == next) {
+                continue;
+            }
+            let mut orig = 0u16;
+            if mem.copy_in_bytes(orig.as_bytes_mut(), next.into()).is_ok()
+                && mem.copy_out(next.into(), &C_EBREAK).is_ok()
+            {
+                saved[i] = Some((next, orig));
+            }
+        }
+        self.proc_mut().deref_mut_data().step_breakpoints = saved;
+    }
+
+    /// Restores the instructions that `insert_step_breakpoints` replaced.
+    /// Returns `true` if the process stopped at one of them.
+    fn remove_step_breakpoints(&mut self) -> bool {
+        let epc = self.proc().trap_frame().epc;
+        let saved = mem::take(&mut self.proc_mut().deref_mut_data().step_breakpoints);
+        let mut stepped = false;
+        for (addr, orig) in saved.iter().flatten() {
+            let _ = self
+                .proc_mut()
+                .memory_mut()
+                .copy_out(UVAddr::from(*addr), orig);
+            stepped |= *addr == epc;
+        }
+        stepped
+    }
+
+    /// Trace or control the child `pid` as `request` says. See the `PTRACE_*` constants.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn ptrace(
+        &mut self,
+        request: i32,
+        pid: Pid,
+        addr: UVAddr,
+        data: usize,
+    ) -> Result<usize, KernelError> {
+        let kernel = self.kernel();
+        let procs = kernel.procs();
+        let mut parent_guard = procs.wait_guard();
+
+        if request == PTRACE_TRACEME {
+            // The initial process has no parent to trace it.
+            if self.proc().get_mut_parent(&mut parent_guard).is_null() {
+                return Err(KernelError::NotPermitted);
+            }
+            let mut guard = self.proc().lock();
+            if guard.deref_info().traced {
+                return Err(KernelError::NotPermitted);
+            }
+            guard.deref_mut_info().traced = true;
+            return Ok(0);
+        }
+
+        // A child may have gained privileges from a set-user-ID program, so attaching to one
+        // needs `Caps::SYS_PTRACE`. A child that asks for PTRACE_TRACEME runs set-ID programs
+        // without their privileges.
+        if request == PTRACE_ATTACH {
+            self.require_cap(Caps::SYS_PTRACE)?;
+        }
+        let mut child = procs.child(pid, &mut parent_guard, self)?;
+        if request == PTRACE_ATTACH {
+            if child.deref_info().traced {
+                return Err(KernelError::NotPermitted);
+            }
+            let info = child.deref_mut_info();
+            info.traced = true;
+            info.stop_request = Some(StopReason::Attach);
+            return Ok(0);
+        }
+
+        // Other requests need the child to be stopped.
+        if !child.deref_info().traced || child.state() != Procstate::STOPPED {
+            return Err(KernelError::NoProcess);
+        }
+        match request {
+            PTRACE_PEEKDATA | PTRACE_POKEDATA => {
+                // SAFETY: the child is stopped, so it does not use its `ProcData` until we
+                // resume it, and we hold its lock.
+                let mem = unsafe { child.deref_mut_data().memory.assume_init_mut() };
+                if request == PTRACE_PEEKDATA {
+                    let mut word = 0usize;
+                    mem.copy_in_bytes(word.as_bytes_mut(), addr)?;
+                    drop(child);
+                    drop(parent_guard);
+                    self.proc_mut()
+                        .memory_mut()
+                        .copy_out(UVAddr::from(data), &word)?;
+                } else {
+                    mem.copy_out(addr, &data)?;
+                }
+            }
+            PTRACE_GETREGS => {
+                // SAFETY: the child is stopped, and `trap_frame` is valid since it is not UNUSED.
+                let regs = unsafe { (*child.deref_mut_data().trap_frame).user_regs() };
+                drop(child);
+                drop(parent_guard);
+                self.proc_mut()
+                    .memory_mut()
+                    .copy_out(UVAddr::from(data), &regs)?;
+            }
+            PTRACE_CONT => child.resume(),
+            PTRACE_SINGLESTEP => {
+                child.deref_mut_info().stepping = true;
+                child.resume();
+            }
+            PTRACE_DETACH => child.untrace(),
+            _ => return Err(KernelError::InvalidArgument),
+        }
+        Ok(0)
+    }
+}
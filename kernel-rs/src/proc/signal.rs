@@ -0,0 +1,187 @@
+//! Signals and process groups, enough for job control.
+//!
+//! Signals have only their default actions, since processes cannot handle them: SIGINT,
+//! SIGTERM, and SIGKILL kill the process, SIGSTOP and SIGTSTP stop it before it returns to user
+//! space, and SIGCONT resumes it. Its parent's wait() reports a stop once, like the stops of a
+//! traced process.
+//!
+//! SIGCHLD, which a process gets when one of its children exits, is ignored, as it is by default
+//! elsewhere. An ignored signal is only remembered, until sigpending() returns it, so that a
+//! process can tell whether a child exited without waiting. To wait for a child's exit along
+//! with other files, a process polls a pidfd of the child instead; see `crate::pidfd`.
+//!
+//! A process may signal only the processes that share a user ID with it, unless it has
+//! `Caps::KILL`. Signals from the kernel, such as those of the console, are always sent.
+//!
+//! Every process belongs to a process group, which it inherits on fork. A shell puts each job
+//! in its own group, so that the console can send the job a signal when the user types the
+//! interrupt or the suspend character.
+
+use super::*;
+use crate::{cred::Cred, error::KernelError};
+
+pub const SIGINT: i32 = 2;
+pub const SIGKILL: i32 = 9;
+pub const SIGTERM: i32 = 15;
+pub const SIGCONT: i32 = 18;
+pub const SIGCHLD: i32 = 17;
+pub const SIGSTOP: i32 = 19;
+pub const SIGTSTP: i32 = 20;
+
+impl Proc {
+    /// Remembers that the process ignored the signal `sig`, for sigpending().
+    pub fn ignore_signal(&self, sig: i32) {
+        let _ = self.pending.fetch_or(1 << sig, Ordering::AcqRel);
+    }
+}
+
+impl<'id, 's> ProcsRef<'id, 's> {
+    /// Sends the signal `sig` to the process `pid`, or to every process in the group `-pid` if
+    /// `pid` is negative. `sender` is the credentials of the sending process, or `None` if the
+    /// kernel sends it. The initial process and kernel threads ignore signals.
+    /// Returns Ok(()) on success, Err(KernelError::NoProcess) if no such process exists, or
+    /// Err(KernelError::NotPermitted) if `sender` may signal none of them.
+    pub fn signal(&self, pid: Pid, sig: i32, sender: Option<&Cred>) -> Result<(), KernelError> {
+        if ![SIGINT, SIGKILL, SIGTERM, SIGCHLD, SIGCONT, SIGSTOP, SIGTSTP].contains(&sig) {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut parent_guard = self.wait_guard();
+        let mut found = false;
+        let mut sent = false;
+        for p in self.process_pool() {
+            // Only the initial process has no parent.
+            let initial = p.get_mut_parent(&mut parent_guard).is_null();
+            let mut guard = p.lock();
+            let info = guard.deref_info();
+            let matches = if pid < 0 {
+                info.pgid == -pid
+            } else {
+                info.pid == pid
+            };
+            if !matches || matches!(info.state, Procstate::UNUSED | Procstate::ZOMBIE) {
+                continue;
+            }
+            found = true;
+            // SAFETY: the data of a used process is initialized, and is not freed while we hold
+            // its lock. As in `proc_stat`, the process may be changing its credentials while we
+            // read them, which only races with the change.
+            let target = unsafe { (*p.data.get()).cred };
+            if !sender.map_or(true, |sender| sender.may_signal(&target)) {
+                continue;
+            }
+            sent = true;
+            if initial || info.kthread.is_some() {
+                continue;
+            }
+            match sig {
+                SIGCHLD => p.ignore_signal(sig),
+                SIGCONT => {
+                    let info = guard.deref_mut_info();
+                    if info.stop_request == Some(StopReason::Suspend) {
+                        info.stop_request = None;
+                    }
+                    // A traced process is resumed by its tracer.
+                    if !info.traced {
+                        guard.resume();
+                    }
+                }
+                SIGSTOP | SIGTSTP => {
+                    let info = guard.deref_mut_info();
+                    if info.state != Procstate::STOPPED && info.stop_request.is_none() {
+                        info.stop_request = Some(StopReason::Suspend);
+                    }
+                    // Let a process sleeping for input stop. See `KernelCtx::stop_requested`.
+                    guard.wakeup();
+                }
+                _ => {
+                    p.kill();
+                    guard.wakeup();
+                    guard.resume();
+                }
+            }
+        }
+        if sent {
+            Ok(())
+        } else if found {
+            Err(KernelError::NotPermitted)
+        } else {
+            Err(KernelError::NoProcess)
+        }
+    }
+
+    /// Returns the process group of the process `pid`.
+    fn pgid(&self, pid: Pid) -> Result<Pid, KernelError> {
+        for p in self.process_pool() {
+            let guard = p.lock();
+            if guard.deref_info().pid == pid && guard.state() != Procstate::UNUSED {
+                return Ok(guard.deref_info().pgid);
+            }
+        }
+        Err(KernelError::NoProcess)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Send the signal `sig` to the process `pid`, or to the process group `-pid`.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn sigsend(&self, pid: Pid, sig: i32) -> Result<(), KernelError> {
+        if pid == 0 || pid == Pid::MIN {
+            return Err(KernelError::InvalidArgument);
+        }
+        let cred = self.proc().deref_data().cred;
+        self.kernel().procs().signal(pid, sig, Some(&cred))
+    }
+
+    /// Returns the signals that the current process ignored since the last call, a bit
+    /// `1 << sig` for each, and forgets them.
+    pub fn sigpending(&self) -> u32 {
+        self.proc().pending.swap(0, Ordering::AcqRel)
+    }
+
+    /// Returns whether the current process was asked to stop, so that code sleeping for input
+    /// can stop it with `KernelCtx::check_stop` instead of sleeping on.
+    pub fn stop_requested(&self) -> bool {
+        self.proc().lock().deref_info().stop_request.is_some()
+    }
+
+    /// Returns the process group of the process `pid`, or of the current process if `pid` is 0.
+    pub fn getpgid(&self, pid: Pid) -> Result<Pid, KernelError> {
+        if pid == 0 {
+            Ok(self.proc().lock().deref_info().pgid)
+        } else {
+            self.kernel().procs().pgid(pid)
+        }
+    }
+
+    /// Put the process `pid`, which is the current process or one of its children, in the
+    /// process group `pgid`. If `pid` is 0, it means the current process, and if `pgid` is 0,
+    /// it means the group whose ID is the process's pid. The group must be a new group named
+    /// after the process, or an existing group.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn setpgid(&self, pid: Pid, pgid: Pid) -> Result<(), KernelError> {
+        if pid < 0 || pgid < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        let kernel = self.kernel();
+        let procs = kernel.procs();
+        let pid = if pid == 0 { self.proc().pid() } else { pid };
+        let pgid = if pgid == 0 { pid } else { pgid };
+        if pgid != pid
+            && !procs
+                .process_pool()
+                .any(|p| p.lock().deref_info().pgid == pgid)
+        {
+            return Err(KernelError::NotPermitted);
+        }
+        if pid == self.proc().pid() {
+            self.proc().lock().deref_mut_info().pgid = pgid;
+        } else {
+            let mut parent_guard = procs.wait_guard();
+            procs
+                .child(pid, &mut parent_guard, self)?
+                .deref_mut_info()
+                .pgid = pgid;
+        }
+        Ok(())
+    }
+}
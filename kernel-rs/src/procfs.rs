@@ -0,0 +1,591 @@
+//! /proc, which exposes how the CPUs and the processes spend their time as text files, so that
+//! tools like `ps` and `top` need no system calls of their own.
+//!
+//! ```text
+//! /proc              a directory of the following, and of a directory per process
+//! /proc/stat         "cpu <user> <system> <idle> <irq>" for all CPUs, then a "cpuN" line for
+//!                    each CPU that started, in milliseconds
+//! /proc/idle         "cpuN <state> <usage> <time> ..." for each CPU that started, with the
+//!                    number of times the CPU entered each idle state and the milliseconds it
+//!                    stayed there
+//! /proc/interrupts   "<controller> <line> <count> ... <time> <names>" for each interrupt line that
+//!                    has handlers, with the number of its interrupts that each CPU that started
+//!                    took, the microseconds spent in its handlers, and the names of the handlers,
+//!                    separated by commas. The controller is "plic" for the devices, or "cpu" for
+//!                    line 1, the software interrupt of the timers and IPIs
+//! /proc/irq          a directory of a directory per line of the PLIC that has handlers
+//! /proc/irq/<n>      a directory of the following
+//! /proc/irq/<n>/affinity
+//!                    "<mask>" in hexadecimal, with a bit for each CPU that the line's interrupts
+//!                    may go to. Writing a mask as root sends them to its CPUs from then on, and
+//!                    keeps the balancer from moving the line; a mask without a CPU that started
+//!                    fails with EINVAL. This is the only file of /proc that can be written
+//! /proc/pressure     a directory of "cpu", "memory", and "io", each of which reads
+//!                    "some avg10=<percent> avg60=<percent> avg300=<percent> total=<time>", for
+//!                    the time that some processes were stalled on the resource, then a "full"
+//!                    line of the same for the time that all the processes that had work were.
+//!                    The percents are averages of the share of time stalled over 10, 60, and
+//!                    300 seconds, and the totals are in microseconds
+//! /proc/self         the directory of the calling process
+//! /proc/<pid>        a directory of the following
+//! /proc/<pid>/stat   "<pid> (<name>) <state> <ppid> <size> <utime> <stime>", where the state is
+//!                    one of R (runnable or running), S (sleeping), T (stopped), Z (zombie), and
+//!                    U (being created), the size is that of the user memory in bytes, and the
+//!                    times are in milliseconds
+//! /proc/<pid>/maps   "<start>-<end> <perms> 00000000 00:00 0 [<name>]" for each run of pages of
+//!                    the process with the same permissions, in address order, where the range
+//!                    is in hexadecimal, the permissions are "rwxp" with "-" for those missing,
+//!                    and only the page shared with the kernel, [vdso], and the ring page of
+//!                    ioring_setup(), [ioring], have names. Pages that the process cannot
+//!                    touch, like the guard page below the stack, read "---p". The process must
+//!                    not be running on another CPU.
+//! ```
+//!
+//! The files are not on the disk. `open` intercepts absolute paths in /proc, and a read formats
+//! the file anew each time, so that a reader sees the current values when it reads from offset
+//! 0. A directory reads as `struct dirent`s, as on the disk, with an entry per slot of the
+//! process table, whose inum is 0 if the slot is unused.
+
+use core::{
+    cmp,
+    fmt::{self, Write},
+    mem, str,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use rv6_abi::{
+    dirent::Dirent,
+    stat::{T_DIR, T_FILE},
+};
+
+use crate::{
+    arch::{
+        addr::UVAddr,
+        memlayout::{IORING, VDSO},
+    },
+    cpuidle::IDLE_STATES,
+    cputime::{CpuState, NSTATE},
+    error::KernelError,
+    file::FileType,
+    fs::{FcntlFlags, Path, Stat, MAY_WRITE},
+    hal::hal,
+    irq::IrqChip,
+    param::{NCPU, NPROC},
+    proc::{KernelCtx, Procstate},
+    psi::PsiResource,
+    vm::PteFlags,
+};
+
+/// Maximum number of bytes of a text file that a read returns.
+const CHUNK: usize = 256;
+
+/// Number of entries of /proc that precede the processes: ".", "..", "stat", "idle",
+/// "interrupts", "irq", "pressure", and "self".
+const NFIXED: usize = 8;
+
+/// The files of /proc/pressure.
+const PRESSURE_FILES: [(&str, PsiResource); 3] = [
+    ("cpu", PsiResource::Cpu),
+    ("memory", PsiResource::Memory),
+    ("io", PsiResource::Io),
+];
+
+/// Inode number of /proc/irq/0, after those of the processes.
+const IRQ_INO: u32 = 16 + 3 * 21000;
+
+/// Device number in the `Stat`s of /proc, which is on no disk.
+const PROC_DEV: i32 = 0;
+
+/// A file or directory of /proc.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum ProcNode {
+    Root,
+    Stat,
+    Idle,
+    Interrupts,
+    Irq,
+    IrqLine(usize),
+    IrqAffinity(usize),
+    Pressure,
+    PressureOf(PsiResource),
+    Pid(i32),
+    PidStat(i32),
+    PidMaps(i32),
+}
+
+/// An open file of /proc.
+pub struct ProcFile {
+    node: ProcNode,
+
+    /// Offset in the file.
+    off: AtomicUsize,
+}
+
+/// Formats the part of a file at and after the offset `skip` into `buf`, and drops the rest.
+struct Window<'a> {
+    buf: &'a mut [u8],
+    skip: usize,
+    len: usize,
+}
+
+impl Write for Window<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let s = s.as_bytes();
+        let skipped = cmp::min(self.skip, s.len());
+        self.skip -= skipped;
+        let s = &s[skipped..];
+        let len = cmp::min(s.len(), self.buf.len() - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+impl ProcNode {
+    /// Returns the inode number of the node, which fits in a `struct dirent`.
+    fn ino(self) -> u32 {
+        match self {
+            Self::Root => 1,
+            Self::Stat => 2,
+            Self::Idle => 3,
+            Self::Interrupts => 4,
+            Self::Irq => 5,
+            Self::IrqLine(line) => IRQ_INO + 2 * line as u32,
+            Self::IrqAffinity(line) => IRQ_INO + 2 * line as u32 + 1,
+            Self::Pressure => 6,
+            Self::PressureOf(resource) => 7 + resource as u32,
+            Self::Pid(pid) => 16 + 3 * (pid as u32 % 21000),
+            Self::PidStat(pid) => 17 + 3 * (pid as u32 % 21000),
+            Self::PidMaps(pid) => 18 + 3 * (pid as u32 % 21000),
+        }
+    }
+
+    fn is_dir(self) -> bool {
+        matches!(
+            self,
+            Self::Root | Self::Irq | Self::IrqLine(_) | Self::Pressure | Self::Pid(_)
+        )
+    }
+
+    fn mode(self) -> u16 {
+        match self {
+            _ if self.is_dir() => 0o555,
+            Self::IrqAffinity(_) => 0o644,
+            _ => 0o444,
+        }
+    }
+
+    /// Returns the `i`th entry of the directory, or None if there is no such entry. An entry
+    /// of an unused slot of the process table has inum 0.
+    fn dirent(self, i: usize, ctx: &KernelCtx<'_, '_>) -> Option<Dirent> {
+        let mut dirent = Dirent::default();
+        let mut name = Window {
+            buf: &mut dirent.name,
+            skip: 0,
+            len: 0,
+        };
+        let ino = match (self, i) {
+            (_, 0) => {
+                let _ = name.write_str(".");
+                self.ino()
+            }
+            (_, 1) => {
+                let _ = name.write_str("..");
+                match self {
+                    Self::IrqLine(_) => Self::Irq.ino(),
+                    _ => Self::Root.ino(),
+                }
+            }
+            (Self::Root, 2) | (Self::Pid(_), 2) => {
+                let _ = name.write_str("stat");
+                match self {
+                    Self::Pid(pid) => Self::PidStat(pid).ino(),
+                    _ => Self::Stat.ino(),
+                }
+            }
+            (Self::Pid(pid), 3) => {
+                let _ = name.write_str("maps");
+                Self::PidMaps(pid).ino()
+            }
+            (Self::Root, 3) => {
+                let _ = name.write_str("idle");
+                Self::Idle.ino()
+            }
+            (Self::Root, 4) => {
+                let _ = name.write_str("interrupts");
+                Self::Interrupts.ino()
+            }
+            (Self::Root, 5) => {
+                let _ = name.write_str("irq");
+                Self::Irq.ino()
+            }
+            (Self::Root, 6) => {
+                let _ = name.write_str("pressure");
+                Self::Pressure.ino()
+            }
+            (Self::Root, 7) => {
+                let _ = name.write_str("self");
+                Self::Pid(ctx.proc().pid()).ino()
+            }
+            (Self::Root, i) if i < NFIXED + NPROC => {
+                match ctx.kernel().procs().pid_at(i - NFIXED) {
+                    Some(pid) => {
+                        let _ = write!(name, "{}", pid);
+                        Self::Pid(pid).ino()
+                    }
+                    None => 0,
+                }
+            }
+            (Self::Irq, i) => {
+                let line = hal().get_ref().irqs().lines(IrqChip::Plic).nth(i - 2)?;
+                let _ = write!(name, "{}", line);
+                Self::IrqLine(line).ino()
+            }
+            (Self::IrqLine(line), 2) => {
+                let _ = name.write_str("affinity");
+                Self::IrqAffinity(line).ino()
+            }
+            (Self::Pressure, i) => {
+                let (file, resource) = PRESSURE_FILES.get(i - 2)?;
+                let _ = name.write_str(file);
+                Self::PressureOf(*resource).ino()
+            }
+            _ => return None,
+        };
+        dirent.inum = ino as u16;
+        Some(dirent)
+    }
+
+    /// Formats the text file from the offset `off` into `buf`.
+    /// Returns the number of bytes formatted.
+    fn format(
+        self,
+        off: usize,
+        buf: &mut [u8],
+        ctx: &KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let mut w = Window {
+            buf,
+            skip: off,
+            len: 0,
+        };
+        match self {
+            Self::Stat => {
+                let cputimes = hal().get_ref().cputimes();
+                let cpus = (0..NCPU).filter(|id| cputimes.started(*id));
+                let mut total = [0; NSTATE];
+                for id in cpus.clone() {
+                    for (total, time) in total.iter_mut().zip(cputimes.times(id).iter()) {
+                        *total += time;
+                    }
+                }
+                let _ = write_times(&mut w, format_args!("cpu "), &total);
+                for id in cpus {
+                    let _ = write_times(&mut w, format_args!("cpu{}", id), &cputimes.times(id));
+                }
+            }
+            Self::Idle => {
+                let cputimes = hal().get_ref().cputimes();
+                for id in (0..NCPU).filter(|id| cputimes.started(*id)) {
+                    let _ = write!(w, "cpu{}", id);
+                    for (state, stats) in IDLE_STATES
+                        .iter()
+                        .zip(hal().get_ref().cpuidle().stats(id).iter())
+                    {
+                        let _ = write!(
+                            w,
+                            " {} {} {}",
+                            state.name,
+                            stats.usage,
+                            stats.time / 1_000_000
+                        );
+                    }
+                    let _ = writeln!(w);
+                }
+            }
+            Self::Interrupts => {
+                let cputimes = hal().get_ref().cputimes();
+                for stats in hal().get_ref().irqs().stats() {
+                    let _ = write!(w, "{} {}", stats.chip, stats.line);
+                    for id in (0..NCPU).filter(|id| cputimes.started(*id)) {
+                        let _ = write!(w, " {}", stats.count[id]);
+                    }
+                    let _ = writeln!(w, " {} {}", stats.time / 1_000, stats.names);
+                }
+            }
+            Self::IrqAffinity(line) => {
+                let affinity = hal()
+                    .get_ref()
+                    .irqs()
+                    .affinity(line)
+                    .ok_or(KernelError::NoEntry)?;
+                let _ = writeln!(w, "{:x}", affinity);
+            }
+            Self::PressureOf(resource) => {
+                let stats = hal().get_ref().psi().stats(resource);
+                let _ = writeln!(w, "some {}", stats.some);
+                let _ = writeln!(w, "full {}", stats.full);
+            }
+            Self::PidStat(pid) => {
+                let stat = ctx
+                    .kernel()
+                    .procs()
+                    .stat(pid)
+                    .ok_or(KernelError::NoProcess)?;
+                let len = stat
+                    .name
+                    .iter()
+                    .position(|c| *c == 0)
+                    .unwrap_or(stat.name.len());
+                let state = match stat.state {
+                    Procstate::RUNNABLE | Procstate::RUNNING => 'R',
+                    Procstate::SLEEPING => 'S',
+                    Procstate::STOPPED => 'T',
+                    Procstate::ZOMBIE => 'Z',
+                    Procstate::USED | Procstate::UNUSED => 'U',
+                };
+                let _ = writeln!(
+                    w,
+                    "{} ({}) {} {} {} {} {}",
+                    stat.pid,
+                    str::from_utf8(&stat.name[..len]).unwrap_or("???"),
+                    state,
+                    stat.ppid,
+                    stat.size,
+                    stat.utime / 1_000_000,
+                    stat.stime / 1_000_000
+                );
+            }
+            Self::PidMaps(pid) => {
+                let current = ctx.proc().pid();
+                ctx.kernel()
+                    .procs()
+                    .for_each_region(pid, current, |start, end, perm| {
+                        let _ = writeln!(
+                            w,
+                            "{:08x}-{:08x} {}{}{}p 00000000 00:00 0{}",
+                            start,
+                            end,
+                            if perm.contains(PteFlags::R) { 'r' } else { '-' },
+                            if perm.contains(PteFlags::W) { 'w' } else { '-' },
+                            if perm.contains(PteFlags::X) { 'x' } else { '-' },
+                            match start {
+                                VDSO => " [vdso]",
+                                IORING => " [ioring]",
+                                _ => "",
+                            }
+                        );
+                    })?;
+            }
+            Self::Root | Self::Irq | Self::IrqLine(_) | Self::Pressure | Self::Pid(_) => {
+                unreachable!("ProcNode::format")
+            }
+        }
+        Ok(w.len)
+    }
+}
+
+/// Writes a line of /proc/stat, with the `times` of `CpuState`s in milliseconds.
+fn write_times(w: &mut Window<'_>, name: fmt::Arguments<'_>, times: &[u64; NSTATE]) -> fmt::Result {
+    writeln!(
+        w,
+        "{} {} {} {} {}",
+        name,
+        times[CpuState::User as usize] / 1_000_000,
+        times[CpuState::System as usize] / 1_000_000,
+        times[CpuState::Idle as usize] / 1_000_000,
+        times[CpuState::Irq as usize] / 1_000_000
+    )
+}
+
+impl ProcFile {
+    /// Returns metadata about the file.
+    pub fn stat(&self) -> Stat {
+        let dir = self.node.is_dir();
+        Stat {
+            dev: PROC_DEV,
+            ino: self.node.ino(),
+            r#type: if dir { T_DIR } else { T_FILE },
+            nlink: 1,
+            mode: self.node.mode() as u32,
+            size: 0,
+            uid: 0,
+            gid: 0,
+        }
+    }
+
+    /// Reads up to `n` bytes from the file into `addr`, from its offset on.
+    /// A directory reads only whole `struct dirent`s.
+    /// Returns Ok(number of bytes read) on success, Err(KernelError) on error.
+    pub fn read(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let off = self.off.load(Ordering::Relaxed);
+        let mut read = 0;
+        if self.node.is_dir() {
+            const DIRENT_SIZE: usize = mem::size_of::<Dirent>();
+            while n - read >= DIRENT_SIZE {
+                let dirent = match self.node.dirent((off + read) / DIRENT_SIZE, ctx) {
+                    Some(dirent) => dirent,
+                    None => break,
+                };
+                ctx.proc_mut().memory_mut().copy_out(addr + read, &dirent)?;
+                read += DIRENT_SIZE;
+            }
+        } else {
+            let mut buf = [0; CHUNK];
+            let len = cmp::min(n, CHUNK);
+            read = self.node.format(off, &mut buf[..len], ctx)?;
+            ctx.proc_mut()
+                .memory_mut()
+                .copy_out_bytes(addr, &buf[..read])?;
+        }
+        let _ = self.off.fetch_add(read, Ordering::Relaxed);
+        Ok(read)
+    }
+
+    /// Writes the `n` bytes at `addr` to the file, which must be /proc/irq/<n>/affinity, as a
+    /// whole. The offset does not matter.
+    /// Returns Ok(n) on success, Err(KernelError) on error.
+    pub fn write(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let line = match self.node {
+            ProcNode::IrqAffinity(line) => line,
+            _ => return Err(KernelError::BadFd),
+        };
+        if n > CHUNK {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut buf = [0; CHUNK];
+        ctx.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(&mut buf[..n], addr)?;
+        let mask = str::from_utf8(&buf[..n])
+            .ok()
+            .and_then(|s| u64::from_str_radix(s.trim(), 16).ok())
+            .ok_or(KernelError::InvalidArgument)?;
+        hal().get_ref().irqs().set_affinity(line, mask)?;
+        Ok(n)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Opens the file of /proc at `path`, and allocates a file descriptor for it. `omode` may
+    /// contain O_CLOEXEC, and must not ask for writing unless the file is writable.
+    /// Returns None if `path` is not an absolute path in /proc, and Some(Ok(file descriptor)) or
+    /// Some(Err(KernelError)) otherwise.
+    pub fn open_proc(
+        &mut self,
+        path: &Path,
+        omode: FcntlFlags,
+    ) -> Option<Result<usize, KernelError>> {
+        if !path.is_absolute() {
+            return None;
+        }
+        // Resolve "." and "..", keeping up to 4 names.
+        let mut names: [&[u8]; 4] = [&[]; 4];
+        let mut len: usize = 0;
+        for name in path.as_bytes().split(|c| *c == b'/') {
+            match name {
+                b"" | b"." => (),
+                b".." => len = len.saturating_sub(1),
+                _ if len == names.len() => {
+                    return (names[0] == b"proc").then(|| Err(KernelError::NoEntry))
+                }
+                _ => {
+                    names[len] = name;
+                    len += 1;
+                }
+            }
+        }
+        if len == 0 || names[0] != b"proc" {
+            return None;
+        }
+        Some(self.open_proc_node(&names[1..len], omode))
+    }
+
+    /// Opens the node of /proc at `names`, the names after "proc".
+    fn open_proc_node(&mut self, names: &[&[u8]], omode: FcntlFlags) -> Result<usize, KernelError> {
+        let pid = |ctx: &Self, name: &[u8]| -> Result<i32, KernelError> {
+            let pid = if name == b"self" {
+                ctx.proc().pid()
+            } else {
+                str::from_utf8(name)
+                    .ok()
+                    .and_then(|name| name.parse().ok())
+                    .ok_or(KernelError::NoEntry)?
+            };
+            ctx.kernel()
+                .procs()
+                .stat(pid)
+                .map(|_| pid)
+                .ok_or(KernelError::NoEntry)
+        };
+        let line = |name: &[u8]| -> Result<usize, KernelError> {
+            str::from_utf8(name)
+                .ok()
+                .and_then(|name| name.parse().ok())
+                .filter(|line| hal().get_ref().irqs().affinity(*line).is_some())
+                .ok_or(KernelError::NoEntry)
+        };
+        let node = match names {
+            [] => ProcNode::Root,
+            [b"stat"] => ProcNode::Stat,
+            [b"idle"] => ProcNode::Idle,
+            [b"interrupts"] => ProcNode::Interrupts,
+            [b"irq"] => ProcNode::Irq,
+            [b"irq", name] => ProcNode::IrqLine(line(name)?),
+            [b"irq", name, b"affinity"] => ProcNode::IrqAffinity(line(name)?),
+            [b"pressure"] => ProcNode::Pressure,
+            [b"pressure", name] => {
+                PRESSURE_FILES
+                    .iter()
+                    .find(|(file, _)| file.as_bytes() == *name)
+                    .map(|(_, resource)| ProcNode::PressureOf(*resource))
+                    .ok_or(KernelError::NoEntry)?
+            }
+            [name] => ProcNode::Pid(pid(self, name)?),
+            [name, b"stat"] => ProcNode::PidStat(pid(self, name)?),
+            [name, b"maps"] => ProcNode::PidMaps(pid(self, name)?),
+            _ => return Err(KernelError::NoEntry),
+        };
+        if omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR | FcntlFlags::O_TRUNC) {
+            if node.is_dir() {
+                return Err(KernelError::IsDir);
+            }
+            if !matches!(node, ProcNode::IrqAffinity(_))
+                || !self.may_access(node.mode(), 0, 0, MAY_WRITE)
+            {
+                return Err(KernelError::PermissionDenied);
+            }
+        }
+        let readable = !omode.contains(FcntlFlags::O_WRONLY);
+        let writable = omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR);
+
+        let file = ProcFile {
+            node,
+            off: AtomicUsize::new(0),
+        };
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::Proc { file }, readable, writable)
+            .map_err(|_| KernelError::FileTableFull)?;
+        f.set_status_flags(omode);
+        let fd = f.fdalloc(self)?;
+        if omode.contains(FcntlFlags::O_CLOEXEC) {
+            self.proc_mut()
+                .deref_mut_data()
+                .fds
+                .set_cloexec(fd as usize, true);
+        }
+        Ok(fd as usize)
+    }
+}
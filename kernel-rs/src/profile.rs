@@ -0,0 +1,220 @@
+//! A sampling profiler, which finds where the CPUs spend their time, in the kernel and in user
+//! programs.
+//!
+//! While the profiler is on, each CPU that runs processes takes a sample every `PROFILE_NS`,
+//! from the interrupt of a timer that the CPU arms when it starts a time slice. A sample is the
+//! interrupted program counter, whether it was in user mode, and the process that was running,
+//! and goes into the buffer of its CPU, a ring that overwrites its oldest sample when it is
+//! full. A CPU takes only the lock of its own buffer, as for tracepoints. The superuser turns
+//! the profiler on and off, and drains the buffers, with profile(). A sample of the kernel
+//! comes out with the name of its function, from the symbol table of backtraces.
+
+use core::{
+    cmp, mem,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use array_macro::array;
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::addr::UVAddr,
+    arch::riscv::{r_sepc, Sstatus},
+    backtrace,
+    cpu::cpuid,
+    cred::Caps,
+    error::KernelError,
+    hal::hal,
+    kernel::KernelRef,
+    lock::SpinLock,
+    param::NCPU,
+    proc::KernelCtx,
+    timer::Timer,
+    util::ring_buffer::RingBuffer,
+};
+
+/// Time between the samples of a CPU, in nanoseconds.
+pub const PROFILE_NS: u64 = 10_000_000;
+
+/// Number of samples the buffer of each CPU keeps.
+const PROFILE_LEN: usize = 1024;
+
+/// Maximum number of samples copied out while holding the lock.
+const CHUNK: usize = 8;
+
+/// Length of the function names in `ProfSample`s.
+const FUNC_LEN: usize = 48;
+
+/// Commands of profile().
+const PROF_OFF: i32 = 0;
+const PROF_ON: i32 = 1;
+const PROF_READ: i32 = 2;
+
+/// A sample in the buffer of a CPU.
+#[derive(Copy, Clone)]
+struct Sample {
+    pc: usize,
+    /// The running process, or 0 for the scheduler.
+    pid: i32,
+    user: bool,
+}
+
+impl Sample {
+    const fn zero() -> Self {
+        Self {
+            pc: 0,
+            pid: 0,
+            user: false,
+        }
+    }
+}
+
+/// `struct profsample` of user programs.
+#[derive(Copy, Clone, AsBytes)]
+#[repr(C)]
+struct ProfSample {
+    pc: u64,
+    pid: i32,
+    cpu: u16,
+    /// 1 if the CPU was in user mode, or 0 if in the kernel.
+    user: u16,
+    /// Function of a sample of the kernel, truncated from the front, and NUL-terminated if
+    /// shorter. Empty for a sample of user mode.
+    func: [u8; FUNC_LEN],
+}
+
+/// Samples of a CPU, oldest first.
+struct ProfileRing(RingBuffer<Sample, PROFILE_LEN>);
+
+impl ProfileRing {
+    /// Appends `sample`, overwriting the oldest one if the ring is full.
+    fn push(&mut self, sample: Sample) {
+        if self.0.is_full() {
+            let _ = self.0.pop();
+        }
+        let _ = self.0.push(sample);
+    }
+
+    /// Moves the oldest samples into `out`.
+    /// Returns the number of samples moved.
+    fn pop(&mut self, out: &mut [Sample]) -> usize {
+        let mut n = 0;
+        for slot in out {
+            match self.0.pop() {
+                Some(sample) => *slot = sample,
+                None => break,
+            }
+            n += 1;
+        }
+        n
+    }
+}
+
+/// The sample buffers of the CPUs.
+pub struct Profiler {
+    /// Whether the profiler is on.
+    enabled: AtomicBool,
+
+    cpus: [SpinLock<ProfileRing>; NCPU],
+}
+
+impl Profiler {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            cpus: array![_ => SpinLock::new(
+                "profile",
+                ProfileRing(RingBuffer::new(Sample::zero())),
+            ); NCPU],
+        }
+    }
+
+    /// Returns whether the profiler is on.
+    pub fn is_on(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// The function of the profiling timer of each CPU. Takes a sample of the code that the
+    /// timer interrupt interrupted, and rearms the timer while the profiler is on.
+    pub fn sample(_timer: Pin<&Timer>, now: u64, kernel: KernelRef<'_, '_>) -> Option<u64> {
+        let this = hal().get_ref().profiler();
+        if !this.is_on() {
+            return None;
+        }
+        // SAFETY: the current process is running on this CPU, whose interrupts are disabled, so it
+        // stays valid until we return.
+        let pid = unsafe { kernel.current_proc().as_ref() }
+            .map_or(0, |proc| unsafe { proc.running_pid() });
+        this.cpus[cpuid()].lock().push(Sample {
+            pc: r_sepc(),
+            pid,
+            user: !Sstatus::read().contains(Sstatus::SPP),
+        });
+        Some(now + PROFILE_NS)
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Turn the profiler on, discarding the samples taken so far, or off, or copy up to `n`
+    /// samples of the buffer of `cpu` to the `struct profsample` array at `addr`, removing them
+    /// from the buffer, as `cmd` says. Profiling needs `Caps::SYS_ADMIN`.
+    /// Returns Ok(number of samples copied, or 0 for the other commands) on success,
+    /// Err(KernelError) on error.
+    pub fn profile(
+        &mut self,
+        cmd: i32,
+        cpu: usize,
+        addr: UVAddr,
+        n: usize,
+    ) -> Result<usize, KernelError> {
+        self.require_cap(Caps::SYS_ADMIN)?;
+        let profiler = hal().get_ref().profiler();
+        match cmd {
+            PROF_OFF => {
+                profiler.enabled.store(false, Ordering::Relaxed);
+                Ok(0)
+            }
+            PROF_ON => {
+                for ring in &profiler.cpus {
+                    ring.lock().0.clear();
+                }
+                profiler.enabled.store(true, Ordering::Relaxed);
+                Ok(0)
+            }
+            PROF_READ => {
+                let ring = profiler.cpus.get(cpu).ok_or(KernelError::InvalidArgument)?;
+                let mut buf = [Sample::zero(); CHUNK];
+                let mut copied = 0;
+                while copied < n {
+                    let len = ring.lock().pop(&mut buf[..cmp::min(CHUNK, n - copied)]);
+                    if len == 0 {
+                        break;
+                    }
+                    for sample in &buf[..len] {
+                        let mut out = ProfSample {
+                            pc: sample.pc as u64,
+                            pid: sample.pid,
+                            cpu: cpu as u16,
+                            user: sample.user as u16,
+                            func: [0; FUNC_LEN],
+                        };
+                        if let Some((func, _)) =
+                            backtrace::lookup(sample.pc).filter(|_| !sample.user)
+                        {
+                            let func = func.as_bytes();
+                            let func = &func[func.len().saturating_sub(FUNC_LEN)..];
+                            out.func[..func.len()].copy_from_slice(func);
+                        }
+                        self.proc_mut()
+                            .memory_mut()
+                            .copy_out(addr + copied * mem::size_of::<ProfSample>(), &out)?;
+                        copied += 1;
+                    }
+                }
+                Ok(copied)
+            }
+            _ => Err(KernelError::InvalidArgument),
+        }
+    }
+}
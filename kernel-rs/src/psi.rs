@@ -0,0 +1,239 @@
+//! Pressure stall information, which tells how much time processes lose waiting for the CPUs,
+//! memory, and the disk, so that the effects of tuning the scheduler and the other subsystems can
+//! be observed. /proc/pressure reports it.
+//!
+//! The scheduler, and the code where processes wait for a resource, report what each process
+//! starts and stops doing: running, waiting for a CPU while runnable, or waiting for another
+//! resource. From the numbers of processes doing each, two times of each resource count up: the
+//! time that some processes were stalled on it, and the time that all the processes that had
+//! work, those running or runnable, were. Every `PSI_PERIOD_NS`, a timer of the first CPU folds
+//! the share of the period that each time grew by into averages over 10, 60, and 300 seconds, as
+//! the load average of Unix is computed. All the processes wait for the CPUs only until an idle
+//! CPU picks one of them, so the CPUs have no such time.
+//!
+//! Nothing stalls on memory yet, as the kernel does not reclaim memory: an allocation that finds
+//! no free page fails at once.
+
+use core::{fmt, pin::Pin};
+
+use crate::{
+    hal::hal, kernel::KernelRef, ktest, ktest_assert, lock::SpinLock, time::ktime_now, timer::Timer,
+};
+
+/// Time between two updates of the averages, in nanoseconds.
+pub const PSI_PERIOD_NS: u64 = 2_000_000_000;
+
+/// Number of averages of a time.
+const NAVG: usize = 3;
+
+/// The periods of the averages, in seconds.
+const AVG_PERIODS: [u64; NAVG] = [10, 60, 300];
+
+/// 1.0 in the fixed point of the averages.
+const FIXED_1: u64 = 1 << 11;
+
+/// How much of each average stays at an update, exp(-PSI_PERIOD_NS / its period), in fixed point.
+const AVG_DECAY: [u64; NAVG] = [1677, 1981, 2034];
+
+/// A resource that processes stall on.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum PsiResource {
+    Cpu = 0,
+    Memory = 1,
+    Io = 2,
+}
+
+/// Number of `PsiResource`s.
+const NRESOURCE: usize = 3;
+
+/// What a process does, as far as the pressure stall information is concerned.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum PsiState {
+    /// Running on a CPU.
+    Running,
+    /// Waiting for a resource. A process waits for the CPU while it is runnable.
+    Stalled(PsiResource),
+}
+
+/// A stall time of a resource.
+#[derive(Copy, Clone)]
+pub struct PsiTime {
+    /// The time, in nanoseconds.
+    total: u64,
+
+    /// `total` at the last update of the averages.
+    last: u64,
+
+    /// The averages of the share of the time that it grew by, in percent, in fixed point.
+    avg: [u64; NAVG],
+}
+
+/// The stall times of a resource.
+#[derive(Copy, Clone)]
+pub struct PsiStats {
+    /// The time that some processes were stalled on the resource.
+    pub some: PsiTime,
+
+    /// The time that all the processes that had work were stalled on the resource, which stays 0
+    /// for the CPUs.
+    pub full: PsiTime,
+}
+
+struct PsiInner {
+    /// Number of processes running.
+    running: usize,
+
+    /// Number of processes stalled on each resource, indexed by `PsiResource`.
+    stalled: [usize; NRESOURCE],
+
+    /// The monotonic time until which the stall times are counted.
+    since: u64,
+
+    /// The stall times of each resource, indexed by `PsiResource`.
+    stats: [PsiStats; NRESOURCE],
+}
+
+pub struct Psi {
+    inner: SpinLock<PsiInner>,
+}
+
+impl PsiTime {
+    /// Folds the share of the last period that the time grew by into its averages.
+    fn update(&mut self) {
+        let grown = (self.total - self.last).min(PSI_PERIOD_NS);
+        self.last = self.total;
+        let share = grown * 100 * FIXED_1 / PSI_PERIOD_NS;
+        for (avg, decay) in self.avg.iter_mut().zip(AVG_DECAY.iter()) {
+            *avg = (*avg * decay + share * (FIXED_1 - decay)) / FIXED_1;
+        }
+    }
+}
+
+impl fmt::Display for PsiTime {
+    /// Formats the time as "avg10=<percent> avg60=<percent> avg300=<percent> total=<us>", with
+    /// the percents rounded to hundredths.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (period, avg) in AVG_PERIODS.iter().zip(self.avg.iter()) {
+            let avg = avg + FIXED_1 / 200;
+            write!(
+                f,
+                "avg{}={}.{:02} ",
+                period,
+                avg / FIXED_1,
+                avg % FIXED_1 * 100 / FIXED_1
+            )?;
+        }
+        write!(f, "total={}", self.total / 1_000)
+    }
+}
+
+impl PsiInner {
+    fn count(&mut self, state: PsiState) -> &mut usize {
+        match state {
+            PsiState::Running => &mut self.running,
+            PsiState::Stalled(resource) => &mut self.stalled[resource as usize],
+        }
+    }
+
+    /// Counts the stall times up to `now`.
+    fn account(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.since);
+        self.since = self.since.max(now);
+        let working = self.running + self.stalled[PsiResource::Cpu as usize];
+        for (i, stats) in self.stats.iter_mut().enumerate() {
+            if self.stalled[i] > 0 {
+                stats.some.total += elapsed;
+                if i != PsiResource::Cpu as usize && working == 0 {
+                    stats.full.total += elapsed;
+                }
+            }
+        }
+    }
+}
+
+impl Psi {
+    pub const fn new() -> Self {
+        Self {
+            inner: SpinLock::new(
+                "psi",
+                PsiInner {
+                    running: 0,
+                    stalled: [0; NRESOURCE],
+                    since: 0,
+                    stats: [PsiStats {
+                        some: PsiTime {
+                            total: 0,
+                            last: 0,
+                            avg: [0; NAVG],
+                        },
+                        full: PsiTime {
+                            total: 0,
+                            last: 0,
+                            avg: [0; NAVG],
+                        },
+                    }; NRESOURCE],
+                },
+            ),
+        }
+    }
+
+    /// Tells that a process stops doing `from` and starts doing `to`, where None stands for
+    /// anything else, such as sleeping for no resource.
+    pub fn change(&self, from: Option<PsiState>, to: Option<PsiState>) {
+        let mut inner = self.inner.lock();
+        inner.account(ktime_now());
+        if let Some(from) = from {
+            *inner.count(from) -= 1;
+        }
+        if let Some(to) = to {
+            *inner.count(to) += 1;
+        }
+    }
+
+    /// Returns the stall times of `resource`, counted up to now.
+    pub fn stats(&self, resource: PsiResource) -> PsiStats {
+        let mut inner = self.inner.lock();
+        inner.account(ktime_now());
+        inner.stats[resource as usize]
+    }
+
+    /// Updates the averages of the stall times, counted up to `now`.
+    fn update(&self, now: u64) {
+        let mut inner = self.inner.lock();
+        inner.account(now);
+        for stats in inner.stats.iter_mut() {
+            stats.some.update();
+            stats.full.update();
+        }
+    }
+
+    /// The function of the averaging timer, which comes every `PSI_PERIOD_NS`.
+    pub fn average(_timer: Pin<&Timer>, now: u64, _kernel: KernelRef<'_, '_>) -> Option<u64> {
+        hal().psi().update(now);
+        Some(now + PSI_PERIOD_NS)
+    }
+}
+
+ktest! {
+    fn psi_account(ctx) {
+        let psi = Psi::new();
+        let mut inner = psi.inner.lock();
+        let io = PsiState::Stalled(PsiResource::Io);
+        inner.since = 1_000;
+        *inner.count(io) += 1;
+        inner.account(2_000);
+        // Another process running makes the stall partial.
+        *inner.count(PsiState::Running) += 1;
+        inner.account(5_000);
+        let stats = inner.stats[PsiResource::Io as usize];
+        ktest_assert!(stats.some.total == 4_000 && stats.full.total == 1_000);
+        ktest_assert!(inner.stats[PsiResource::Cpu as usize].some.total == 0);
+
+        // A period that was all stalled moves each average toward 100% by its share.
+        let mut time = stats.some;
+        time.total = time.last + PSI_PERIOD_NS;
+        time.update();
+        ktest_assert!(time.avg[0] == 100 * (FIXED_1 - AVG_DECAY[0]));
+        ktest_assert!(time.avg[2] < time.avg[1] && time.avg[1] < time.avg[0]);
+    }
+}
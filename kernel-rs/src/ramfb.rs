@@ -0,0 +1,103 @@
+//! QEMU's ramfb display, a framebuffer in guest memory that QEMU shows in its window.
+//!
+//! The guest tells QEMU where the framebuffer is and its format by writing the "etc/ramfb" file
+//! of the firmware configuration device. The display exists only if QEMU runs with
+//! `-device ramfb`.
+
+use zerocopy::AsBytes;
+
+use crate::{
+    ansi::{Cell, Color, TextDisplay},
+    arch::fw_cfg::FwCfgFile,
+    font,
+};
+
+/// Size of the framebuffer, in pixels.
+const WIDTH: usize = 640;
+const HEIGHT: usize = 480;
+
+/// Pixel format of the framebuffer: 32 bits of 0x00RRGGBB, in little-endian.
+const DRM_FORMAT_XRGB8888: u32 = u32::from_le_bytes(*b"XR24");
+
+/// The pixels of the 16 ANSI colors.
+const PALETTE: [u32; 16] = [
+    0x000000, 0xaa0000, 0x00aa00, 0xaa5500, 0x0000aa, 0xaa00aa, 0x00aaaa, 0xaaaaaa, 0x555555,
+    0xff5555, 0x55ff55, 0xffff55, 0x5555ff, 0xff55ff, 0x55ffff, 0xffffff,
+];
+
+/// The framebuffer. It is zero-initialized, so that it does not take space in the kernel image.
+static mut PIXELS: [u32; WIDTH * HEIGHT] = [0; WIDTH * HEIGHT];
+
+/// `struct RAMFBCfg` of QEMU, in big-endian.
+#[derive(AsBytes)]
+#[repr(C, packed)]
+struct RamFbCfg {
+    addr: u64,
+    fourcc: u32,
+    flags: u32,
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+/// The ramfb display, which shows text in the font of `font`.
+pub struct RamFb {
+    pixels: &'static mut [u32; WIDTH * HEIGHT],
+}
+
+impl RamFb {
+    /// Sets up the display, or returns `None` if QEMU does not have one.
+    ///
+    /// # Safety
+    ///
+    /// This function must be called only once.
+    pub unsafe fn new() -> Option<Self> {
+        // SAFETY: this function is called only once.
+        let pixels = unsafe { &mut PIXELS };
+        let cfg = RamFbCfg {
+            addr: (pixels.as_ptr() as u64).to_be(),
+            fourcc: DRM_FORMAT_XRGB8888.to_be(),
+            flags: 0,
+            width: (WIDTH as u32).to_be(),
+            height: (HEIGHT as u32).to_be(),
+            stride: ((WIDTH * 4) as u32).to_be(),
+        };
+        FwCfgFile::find("etc/ramfb")?.write(cfg.as_bytes()).ok()?;
+        Some(Self { pixels })
+    }
+
+    /// Returns the size of the display, in pixels.
+    pub fn resolution(&self) -> (usize, usize) {
+        (WIDTH, HEIGHT)
+    }
+}
+
+impl TextDisplay for RamFb {
+    fn size(&self) -> (usize, usize) {
+        (HEIGHT / font::HEIGHT, WIDTH / font::WIDTH)
+    }
+
+    fn draw(&mut self, row: usize, col: usize, cell: Cell) {
+        let glyph = font::glyph(cell.c);
+        let color = |c: Color| PALETTE[c as usize % PALETTE.len()];
+        let (fg, bg) = (color(cell.fg), color(cell.bg));
+        for (y, bits) in glyph.iter().enumerate() {
+            let start = (row * font::HEIGHT + y) * WIDTH + col * font::WIDTH;
+            for (x, pixel) in self.pixels[start..start + font::WIDTH]
+                .iter_mut()
+                .enumerate()
+            {
+                *pixel = if bits & (0x80 >> x) != 0 { fg } else { bg };
+            }
+        }
+    }
+
+    fn scroll_up(&mut self, blank: Cell) {
+        let line = font::HEIGHT * WIDTH;
+        let (rows, cols) = self.size();
+        self.pixels.copy_within(line..rows * line, 0);
+        for col in 0..cols {
+            self.draw(rows - 1, col, blank);
+        }
+    }
+}
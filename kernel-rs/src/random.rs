@@ -0,0 +1,476 @@
+//! Kernel random number generator.
+//!
+//! Entropy from the virtio entropy source, the clocks, and the timing of interrupts is mixed
+//! into a pool, which is folded into the key of a ChaCha20-based generator from time to time.
+//! The generator replaces its key after every request, so that its state never reveals
+//! earlier outputs.
+//!
+//! The pool is a sponge: inputs are xored into the first half of its state, which the ChaCha20
+//! permutation stirs whenever that half is full, and the key takes that half after a last stir.
+//! Each interrupt, from the UART, the disk, or the timer, first mixes its cycle count, its time,
+//! its number, and the interrupted pc into the fast pool of its CPU with SipHash rounds, which
+//! goes into the pool every `FAST_POOL_INTERRUPTS` interrupts.
+//!
+//! The generator counts the entropy it is given: every byte of the virtio entropy source, and a
+//! bit for every `INTERRUPTS_PER_BIT` interrupts whose cycle count was not predictable from the
+//! ones before, i.e., had nonzero first, second, and third order differences. Until `SEED_BITS`
+//! bits have come in, getrandom() waits, so that a machine without the virtio entropy source does
+//! not hand out predictable bytes after boot. /dev/urandom never waits.
+
+use core::cmp;
+
+use array_macro::array;
+
+use crate::{
+    arch::{
+        addr::UVAddr,
+        riscv::{r_cycle, r_sepc, r_time},
+        rtc::rtc_read,
+    },
+    cpu::cpuid,
+    error::KernelError,
+    file::Devsw,
+    hal::hal,
+    kernel::KernelRef,
+    ktest, ktest_assert,
+    lock::{SleepableLock, SpinLock},
+    param::NCPU,
+    proc::KernelCtx,
+    register_chardev,
+};
+
+/// Number of entropy inputs after which the pool is folded into the key.
+const RESEED_INPUTS: u32 = 64;
+
+/// Maximum number of bytes generated while holding the lock.
+const CHUNK: usize = 256;
+
+/// Number of bits of entropy that the generator needs before getrandom() returns.
+const SEED_BITS: u32 = 128;
+
+/// Number of interrupts after which the fast pool of a CPU goes into the pool.
+const FAST_POOL_INTERRUPTS: u32 = 16;
+
+/// Number of unpredictable interrupts that count as a bit of entropy.
+const INTERRUPTS_PER_BIT: u32 = 4;
+
+/// getrandom() flag: fail with EAGAIN instead of waiting for the generator to be seeded.
+const GRND_NONBLOCK: i32 = 1;
+/// getrandom() flag: use the blocking pool. Ignored, since there is only one pool.
+const GRND_RANDOM: i32 = 2;
+
+/// "expand 32-byte k"
+const SIGMA: [u32; 4] = [0x61707865, 0x3320646e, 0x79622d32, 0x6b206574];
+
+// The names follow RFC 8439.
+#[allow(clippy::many_single_char_names)]
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+/// The 20 rounds of ChaCha20, which permute `s`.
+fn chacha20_rounds(s: &mut [u32; 16]) {
+    for _ in 0..10 {
+        quarter_round(s, 0, 4, 8, 12);
+        quarter_round(s, 1, 5, 9, 13);
+        quarter_round(s, 2, 6, 10, 14);
+        quarter_round(s, 3, 7, 11, 15);
+        quarter_round(s, 0, 5, 10, 15);
+        quarter_round(s, 1, 6, 11, 12);
+        quarter_round(s, 2, 7, 8, 13);
+        quarter_round(s, 3, 4, 9, 14);
+    }
+}
+
+/// Returns the ChaCha20 block of `key`, the block counter `counter`, and the nonce `nonce`.
+fn chacha20_block(key: &[u32; 8], counter: u64, nonce: u64) -> [u32; 16] {
+    let mut input = [0; 16];
+    input[..4].copy_from_slice(&SIGMA);
+    input[4..12].copy_from_slice(key);
+    input[12] = counter as u32;
+    input[13] = (counter >> 32) as u32;
+    input[14] = nonce as u32;
+    input[15] = (nonce >> 32) as u32;
+
+    let mut s = input;
+    chacha20_rounds(&mut s);
+    for (s, input) in s.iter_mut().zip(input.iter()) {
+        *s = s.wrapping_add(*input);
+    }
+    s
+}
+
+/// One round of SipHash, which mixes `v`.
+fn sip_round(v: &mut [u64; 4]) {
+    v[0] = v[0].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(13) ^ v[0];
+    v[0] = v[0].rotate_left(32);
+    v[2] = v[2].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(16) ^ v[2];
+    v[0] = v[0].wrapping_add(v[3]);
+    v[3] = v[3].rotate_left(21) ^ v[0];
+    v[2] = v[2].wrapping_add(v[1]);
+    v[1] = v[1].rotate_left(17) ^ v[2];
+    v[2] = v[2].rotate_left(32);
+}
+
+/// The interrupts of a CPU that have not gone into the pool yet.
+struct FastPool {
+    state: [u64; 4],
+
+    /// The cycle count of the last interrupt, and its first and second order differences.
+    last: u64,
+    delta: u64,
+    delta2: u64,
+
+    /// Number of interrupts mixed in, and how many of them were unpredictable.
+    interrupts: u32,
+    unpredictable: u32,
+}
+
+impl FastPool {
+    const fn new() -> Self {
+        Self {
+            state: [0; 4],
+            last: 0,
+            delta: 0,
+            delta2: 0,
+            interrupts: 0,
+            unpredictable: 0,
+        }
+    }
+
+    /// Mixes `a` and `b` into the state, as SipHash mixes a word of its message.
+    fn mix(&mut self, a: u64, b: u64) {
+        self.state[3] ^= a;
+        sip_round(&mut self.state);
+        self.state[0] ^= a;
+        self.state[3] ^= b;
+        sip_round(&mut self.state);
+        self.state[0] ^= b;
+    }
+
+    /// Mixes in an interrupt from `irq` at the cycle count `cycles` and the time `time`, which
+    /// interrupted the code at `pc`. Returns whether the fast pool should go into the pool.
+    fn add(&mut self, cycles: u64, time: u64, irq: u32, pc: usize) -> bool {
+        self.mix(
+            cycles ^ time.rotate_left(32),
+            (irq as u64) << 32 ^ pc as u64,
+        );
+        let delta = cycles.wrapping_sub(self.last);
+        let delta2 = delta.wrapping_sub(self.delta);
+        let delta3 = delta2.wrapping_sub(self.delta2);
+        if delta != 0 && delta2 != 0 && delta3 != 0 {
+            self.unpredictable += 1;
+        }
+        self.last = cycles;
+        self.delta = delta;
+        self.delta2 = delta2;
+        self.interrupts += 1;
+        self.interrupts >= FAST_POOL_INTERRUPTS
+    }
+
+    /// Empties the fast pool. Returns its state, and the bits of entropy it had.
+    fn take(&mut self) -> ([u64; 4], u32) {
+        let bits = self.unpredictable / INTERRUPTS_PER_BIT;
+        self.interrupts = 0;
+        self.unpredictable = 0;
+        (self.state, bits)
+    }
+}
+
+struct RandomInner {
+    /// Key of the generator.
+    key: [u32; 8],
+
+    /// Entropy not yet folded into the key, a sponge whose first half takes the inputs.
+    pool: [u32; 16],
+
+    /// The word of `pool` that the next input is xored into.
+    pool_pos: usize,
+
+    /// Number of inputs mixed into `pool` since it was last folded into the key.
+    pool_inputs: u32,
+
+    /// Bits of entropy given to the generator, up to `SEED_BITS`.
+    entropy: u32,
+}
+
+impl RandomInner {
+    const fn new() -> Self {
+        Self {
+            key: [0; 8],
+            pool: [0; 16],
+            pool_pos: 0,
+            pool_inputs: 0,
+            entropy: 0,
+        }
+    }
+
+    /// Returns whether the generator has been given `SEED_BITS` bits of entropy.
+    fn seeded(&self) -> bool {
+        self.entropy >= SEED_BITS
+    }
+
+    fn mix(&mut self, word: u32) {
+        self.pool[self.pool_pos] ^= word;
+        self.pool_pos += 1;
+        if self.pool_pos == 8 {
+            chacha20_rounds(&mut self.pool);
+            self.pool_pos = 0;
+        }
+        self.pool_inputs += 1;
+        if self.pool_inputs >= RESEED_INPUTS {
+            self.reseed();
+        }
+    }
+
+    /// Counts `bits` bits of entropy that were mixed in, and folds the pool into the key as soon
+    /// as there are `SEED_BITS` of them. Returns whether the generator became seeded.
+    fn credit(&mut self, bits: u32) -> bool {
+        if self.seeded() {
+            return false;
+        }
+        self.entropy = cmp::min(self.entropy + bits, SEED_BITS);
+        if self.seeded() {
+            self.reseed();
+        }
+        self.seeded()
+    }
+
+    /// Folds the pool into the key.
+    fn reseed(&mut self) {
+        chacha20_rounds(&mut self.pool);
+        let mut key = self.key;
+        for (k, p) in key.iter_mut().zip(self.pool.iter()) {
+            *k ^= *p;
+        }
+        // Forget the half of the pool that went into the key, so that the pool does not reveal
+        // it. The other half keeps what came in before.
+        self.pool[..8].copy_from_slice(&[0; 8]);
+        self.pool_pos = 0;
+        self.pool_inputs = 0;
+        // The nonce separates reseeding from generating.
+        let block = chacha20_block(&key, 0, u64::MAX);
+        self.key.copy_from_slice(&block[..8]);
+    }
+
+    fn fill(&mut self, out: &mut [u8]) {
+        let mut counter = 0;
+        for chunk in out.chunks_mut(64) {
+            let block = chacha20_block(&self.key, counter, 0);
+            counter += 1;
+            for (bytes, word) in chunk.chunks_mut(4).zip(block.iter()) {
+                bytes.copy_from_slice(&word.to_le_bytes()[..bytes.len()]);
+            }
+        }
+        // Replace the key with output that nobody sees.
+        let block = chacha20_block(&self.key, counter, 0);
+        self.key.copy_from_slice(&block[..8]);
+    }
+}
+
+/// The kernel's random number generator.
+pub struct Random {
+    inner: SleepableLock<RandomInner>,
+
+    /// The fast pool of each CPU.
+    fast: [SpinLock<FastPool>; NCPU],
+}
+
+impl Random {
+    pub const fn new() -> Self {
+        Self {
+            inner: SleepableLock::new("random", RandomInner::new()),
+            fast: array![_ => SpinLock::new("random_fast", FastPool::new()); NCPU],
+        }
+    }
+
+    /// Seeds the generator from the virtio entropy source and the clocks.
+    pub fn init(&self) {
+        let mut seed = [0; 32];
+        let mut len = 0;
+        // The device may return fewer bytes than asked for.
+        for _ in 0..8 {
+            if len == seed.len() {
+                break;
+            }
+            let n = hal()
+                .rng()
+                .pinned_lock()
+                .get_pin_mut()
+                .read(&mut seed[len..]);
+            if n == 0 {
+                break;
+            }
+            len += n;
+        }
+        self.add_entropy(&seed[..len]);
+        self.add_entropy(&rtc_read().to_le_bytes());
+        self.add_entropy(&r_time().to_le_bytes());
+        let mut inner = self.inner.lock();
+        // Without the device, the generator waits for interrupts.
+        let _ = inner.credit(len as u32 * 8);
+        inner.reseed();
+    }
+
+    /// Mixes `data` into the entropy pool, without counting it as entropy.
+    pub fn add_entropy(&self, data: &[u8]) {
+        let mut inner = self.inner.lock();
+        for bytes in data.chunks(4) {
+            let mut word = [0; 4];
+            word[..bytes.len()].copy_from_slice(bytes);
+            inner.mix(u32::from_le_bytes(word));
+        }
+    }
+
+    /// Mixes the timing of an interrupt from `irq`, or 0 for the timer, into the fast pool of
+    /// this CPU, and the fast pool into the pool every `FAST_POOL_INTERRUPTS` interrupts.
+    /// Called by the interrupt handler.
+    pub fn add_interrupt(&self, irq: u32, kernel: KernelRef<'_, '_>) {
+        let mut fast = self.fast[cpuid()].lock();
+        if !fast.add(r_cycle(), r_time(), irq, r_sepc()) {
+            return;
+        }
+        let (state, bits) = fast.take();
+        drop(fast);
+
+        let mut inner = self.inner.lock();
+        for word in state.iter() {
+            inner.mix(*word as u32);
+            inner.mix((*word >> 32) as u32);
+        }
+        if inner.credit(bits) {
+            inner.wakeup(kernel);
+        }
+    }
+
+    /// Waits until the generator is seeded, or returns `Err(KernelError::TryAgain)` if it is not
+    /// and `nonblock` is set.
+    fn wait_seeded(&self, nonblock: bool, ctx: &KernelCtx<'_, '_>) -> Result<(), KernelError> {
+        let mut inner = self.inner.lock();
+        while !inner.seeded() {
+            if nonblock {
+                return Err(KernelError::TryAgain);
+            }
+            if ctx.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            inner.sleep(ctx);
+        }
+        Ok(())
+    }
+
+    /// Fills `out` with random bytes.
+    pub fn fill(&self, out: &mut [u8]) {
+        self.inner.lock().fill(out);
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Copy `n` random bytes to `addr`, once the generator is seeded. `flags` may contain
+    /// GRND_NONBLOCK and GRND_RANDOM.
+    /// Returns Ok(n) on success, Err(KernelError) on error.
+    pub fn getrandom(&mut self, addr: UVAddr, n: usize, flags: i32) -> Result<usize, KernelError> {
+        if flags & !(GRND_NONBLOCK | GRND_RANDOM) != 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        self.kernel()
+            .random()
+            .wait_seeded(flags & GRND_NONBLOCK != 0, self)?;
+        self.copy_random(addr, n)
+    }
+
+    /// Copy `n` random bytes to `addr`, whether the generator is seeded or not.
+    /// Returns Ok(n) on success, Err(KernelError) on error.
+    fn copy_random(&mut self, addr: UVAddr, n: usize) -> Result<usize, KernelError> {
+        let mut buf = [0; CHUNK];
+        let mut off = 0;
+        while off < n {
+            let len = cmp::min(n - off, CHUNK);
+            self.kernel().random().fill(&mut buf[..len]);
+            self.proc_mut()
+                .memory_mut()
+                .copy_out_bytes(addr + off, &buf[..len])?;
+            off += len;
+        }
+        Ok(n)
+    }
+}
+
+/// Major device number of /dev/urandom, URANDOM in kernel/file.h.
+const URANDOM_MAJOR: u16 = 2;
+
+register_chardev!(
+    URANDOM_MAJOR,
+    "urandom",
+    Devsw {
+        mode: 0o666,
+        read: Some(urandom_read),
+        write: Some(urandom_write),
+        ..Devsw::NONE
+    }
+);
+
+/// User read()s from /dev/urandom go here.
+/// Unlike getrandom(), they do not wait for the generator to be seeded.
+fn urandom_read(dst: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, KernelError> {
+    ctx.copy_random(dst, n as usize)
+}
+
+/// User write()s to /dev/urandom go here.
+/// The data is mixed into the entropy pool.
+fn urandom_write(src: UVAddr, n: i32, ctx: &mut KernelCtx<'_, '_>) -> Result<usize, KernelError> {
+    let n = n as usize;
+    let mut buf = [0; CHUNK];
+    let mut off = 0;
+    while off < n {
+        let len = cmp::min(n - off, CHUNK);
+        ctx.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(&mut buf[..len], src + off)?;
+        ctx.kernel().random().add_entropy(&buf[..len]);
+        off += len;
+    }
+    Ok(n)
+}
+
+ktest! {
+    fn seed_credit(ctx) {
+        let mut inner = RandomInner::new();
+        let key = inner.key;
+        let early = inner.credit(SEED_BITS - 1);
+        let unseeded_key = inner.key;
+        let seeded = inner.credit(1);
+        ktest_assert!(!early && unseeded_key == key);
+        ktest_assert!(seeded && inner.seeded() && inner.key != key);
+        ktest_assert!(!inner.credit(SEED_BITS));
+    }
+}
+
+ktest! {
+    fn fast_pool_jitter(ctx) {
+        // The interrupts of a clock that ticks at a fixed rate are predictable, except the first
+        // two, which have no history.
+        let mut regular = FastPool::new();
+        let mut jittery = FastPool::new();
+        let mut full = false;
+        for i in 0..FAST_POOL_INTERRUPTS as u64 {
+            let _ = regular.add(1000 * i + 1, 0, 0, 0);
+            full = jittery.add(1000 * i + i * i * i % 7 + 1, 0, 0, 0);
+        }
+        let (regular_state, regular_bits) = regular.take();
+        let (jittery_state, jittery_bits) = jittery.take();
+        ktest_assert!(full);
+        ktest_assert!(regular_bits == 0);
+        ktest_assert!(jittery_bits >= 2);
+        ktest_assert!(regular_state != jittery_state);
+    }
+}
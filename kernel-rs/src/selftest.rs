@@ -0,0 +1,336 @@
+//! Self tests that the kernel runs at boot, after it is initialized and before the first process
+//! runs, to check the invariants that the rest of the kernel takes for granted.
+//!
+//! Unlike the tests of `ktest!`, they are always built in, need no process, and check what a port
+//! to another machine or compiler is most likely to break: that page tables map what they should,
+//! that the buffer cache and the arenas count references right, and that the structures that the
+//! assembly code accesses are laid out as the assembly expects. Given
+//! `-fw_cfg name=opt/rv6/selftest,string=on`, as `make qemu SELFTEST=yes` does, the kernel runs
+//! them and prints a line for each, followed by a summary, and panics if any failed:
+//!
+//! ```text
+//! selftest: ok kernel_map
+//! selftest: FAIL trap_frame: src/selftest.rs:150: offset_of!(TrapFrame, a0) == 112
+//! selftest: SUMMARY passed=5 failed=1
+//! ```
+
+use core::{
+    mem, ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{
+    arch::{
+        addr::{Addr, KVAddr, UVAddr, PGSIZE},
+        fw_cfg::FwCfgFile,
+        memlayout::{KERNBASE, TRAMPOLINE},
+    },
+    arena::{Arena, ArenaObject, ArrayArena},
+    hal::hal,
+    kernel::KernelRef,
+    ktest::Failure,
+    ktest_assert,
+    lock::SpinLock,
+    page::Page,
+    param::ROOTDEV,
+    proc::{Context, TrapFrame},
+    trap::KernelFrame,
+    util::{branded::BlockNo, strong_pin::StrongPin},
+    vm::{PteFlags, UserMemory},
+};
+
+/// Name of the QEMU file whose presence makes the kernel run the self tests at boot.
+const SELFTEST_OPTION: &str = "opt/rv6/selftest";
+
+/// Returns the offset of `$field` in `$ty`.
+macro_rules! offset_of {
+    ($ty:ty, $field:ident) => {{
+        let value = mem::MaybeUninit::<$ty>::uninit();
+        let base = value.as_ptr();
+        // SAFETY: only the address of the field is taken, without reading it.
+        (unsafe { &raw const (*base).$field }) as usize - base as usize
+    }};
+}
+
+/// Fails the self test unless each field of `$ty` is at the given offset.
+macro_rules! assert_offsets {
+    ($ty:ty { $($field:ident: $offset:expr),* $(,)? }) => {
+        $(ktest_assert!(offset_of!($ty, $field) == $offset);)*
+    };
+}
+
+extern "C" {
+    // kernel.ld sets this to end of kernel code.
+    static etext: [u8; 0];
+
+    // kernel.ld sets this to end of the kernel's read-only data.
+    static erodata: [u8; 0];
+
+    // trampoline.S
+    static trampoline: [u8; 0];
+}
+
+/// A self test.
+struct SelfTest {
+    name: &'static str,
+    run: fn(KernelRef<'_, '_>) -> Result<(), Failure>,
+}
+
+const SELFTESTS: [SelfTest; 6] = [
+    SelfTest {
+        name: "kernel_map",
+        run: kernel_map,
+    },
+    SelfTest {
+        name: "user_memory",
+        run: user_memory,
+    },
+    SelfTest {
+        name: "bcache",
+        run: bcache,
+    },
+    SelfTest {
+        name: "arena_refcount",
+        run: arena_refcount,
+    },
+    SelfTest {
+        name: "trap_frame",
+        run: trap_frame,
+    },
+    SelfTest {
+        name: "context",
+        run: context,
+    },
+];
+
+/// The kernel's page table maps its text, read-only data, data, and the trampoline as kernel.ld
+/// and trampoline.S place them, with only text executable and only data writable.
+fn kernel_map(kernel: KernelRef<'_, '_>) -> Result<(), Failure> {
+    let memory = kernel.memory();
+    // SAFETY: we assume that reading the addresses of etext, erodata, and trampoline is safe.
+    let (et, ero, tramp) = unsafe {
+        (
+            etext.as_ptr() as usize,
+            erodata.as_ptr() as usize,
+            trampoline.as_ptr() as usize,
+        )
+    };
+    let maps = |va: usize, pa: usize, flags: PteFlags| {
+        memory
+            .translate(KVAddr::from(va))
+            .map_or(false, |(mapped, mapped_flags)| {
+                mapped.into_usize() == pa && mapped_flags == PteFlags::V | flags
+            })
+    };
+    ktest_assert!(maps(KERNBASE, KERNBASE, PteFlags::R | PteFlags::X));
+    ktest_assert!(maps(et, et, PteFlags::R));
+    ktest_assert!(maps(ero, ero, PteFlags::R | PteFlags::W));
+    ktest_assert!(maps(TRAMPOLINE, tramp, PteFlags::R | PteFlags::X));
+    Ok(())
+}
+
+/// Bytes written to a user page table across a page boundary read back the same, and unmapped
+/// pages cannot be accessed.
+fn user_memory(_kernel: KernelRef<'_, '_>) -> Result<(), Failure> {
+    const DATA: &[u8] = b"rv6 selftest across pages";
+
+    let allocator = hal().kmem();
+    let trap_frame = allocator.alloc().expect("selftest").into_usize();
+    let mut memory =
+        UserMemory::new(trap_frame.into(), None, allocator).expect("selftest: UserMemory::new");
+    let grown = memory.alloc(2 * PGSIZE, allocator);
+    let va = UVAddr::from(PGSIZE - DATA.len() / 2);
+    let written = memory.copy_out_bytes(va, DATA).is_ok();
+    let mut read = [0; DATA.len()];
+    let same = memory.copy_in_bytes(&mut read, va).is_ok() && read == DATA;
+    let beyond = memory.copy_in_bytes(&mut read, UVAddr::from(2 * PGSIZE));
+    let _ = memory.dealloc(PGSIZE, allocator);
+    let shrunk = memory.copy_in_bytes(&mut read, va);
+    memory.free(allocator);
+    // SAFETY: `trap_frame` is the page allocated above, which `memory` no longer maps.
+    allocator.free(unsafe { Page::from_usize(trap_frame) });
+
+    ktest_assert!(grown == Ok(2 * PGSIZE));
+    ktest_assert!(written);
+    ktest_assert!(same);
+    ktest_assert!(beyond.is_err());
+    ktest_assert!(shrunk.is_err());
+    Ok(())
+}
+
+/// The buffer cache gives the same buffer for the same block, and a different one for another
+/// block, and counts them until they are released.
+fn bcache(kernel: KernelRef<'_, '_>) -> Result<(), Failure> {
+    let bcache = kernel.bcache();
+    let before = bcache.stats().in_use;
+    let first = bcache.get_buf(ROOTDEV, BlockNo::new(1));
+    let again = bcache.get_buf(ROOTDEV, BlockNo::new(1));
+    let other = bcache.get_buf(ROOTDEV, BlockNo::new(2));
+    let same = ptr::eq(&*first, &*again);
+    let different = !ptr::eq(&*first, &*other);
+    let keyed = first.blockno == BlockNo::new(1) && other.blockno == BlockNo::new(2);
+    let in_use = bcache.stats().in_use;
+    drop(first);
+    drop(again);
+    drop(other);
+
+    ktest_assert!(same);
+    ktest_assert!(different);
+    ktest_assert!(keyed);
+    ktest_assert!(in_use == before + 2);
+    ktest_assert!(bcache.stats().in_use == before);
+    Ok(())
+}
+
+/// Number of times entries of `arena_refcount`'s arena were finalized.
+static FINALIZED: AtomicUsize = AtomicUsize::new(0);
+
+/// An entry of `arena_refcount`'s arena.
+#[derive(Default)]
+struct Counted(usize);
+
+impl ArenaObject for Counted {
+    type Ctx<'a, 'id: 'a> = ();
+
+    #[allow(clippy::needless_lifetimes)]
+    fn finalize<'a, 'id: 'a, A: Arena>(&mut self, _: ()) {
+        let _ = FINALIZED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// An arena finalizes an entry when its last reference is freed, finds it while it is alive,
+/// and fails to allocate when it is full.
+fn arena_refcount(_kernel: KernelRef<'_, '_>) -> Result<(), Failure> {
+    let arena = SpinLock::new(
+        "selftest",
+        ArrayArena::<Counted, 2>::new::<Counted>("selftest"),
+    );
+    // SAFETY: `arena` is not moved until it is dropped.
+    let arena = unsafe { StrongPin::new_unchecked(&arena) };
+    let before = FINALIZED.load(Ordering::Relaxed);
+
+    let first = arena.alloc(|| Counted(1)).expect("selftest");
+    let clone = first.clone();
+    clone.free(());
+    let alive = FINALIZED.load(Ordering::Relaxed) == before;
+    let found = arena
+        .find_or_alloc(|entry| entry.0 == 1, |_| ())
+        .expect("selftest");
+    let same = ptr::eq(&*found, &*first);
+    found.free(());
+    let second = arena.alloc(Counted::default).expect("selftest");
+    let full = arena.alloc(Counted::default);
+    let failed = full.is_none();
+    if let Some(full) = full {
+        full.free(());
+    }
+    first.free(());
+    let finalized = FINALIZED.load(Ordering::Relaxed) - before;
+    second.free(());
+    let stats = arena.stats();
+
+    ktest_assert!(alive);
+    ktest_assert!(same);
+    ktest_assert!(failed);
+    ktest_assert!(finalized == 1);
+    ktest_assert!(stats.in_use == 0 && stats.failed == 1);
+    Ok(())
+}
+
+/// `TrapFrame` and `KernelFrame` are laid out as trampoline.S and kernelvec.S expect.
+fn trap_frame(_kernel: KernelRef<'_, '_>) -> Result<(), Failure> {
+    assert_offsets!(TrapFrame {
+        kernel_satp: 0,
+        kernel_sp: 8,
+        kernel_trap: 16,
+        epc: 24,
+        kernel_hartid: 32,
+        ra: 40,
+        sp: 48,
+        gp: 56,
+        tp: 64,
+        t0: 72,
+        t1: 80,
+        t2: 88,
+        s0: 96,
+        s1: 104,
+        a0: 112,
+        a1: 120,
+        a2: 128,
+        a3: 136,
+        a4: 144,
+        a5: 152,
+        a6: 160,
+        a7: 168,
+        s2: 176,
+        s3: 184,
+        s4: 192,
+        s5: 200,
+        s6: 208,
+        s7: 216,
+        s8: 224,
+        s9: 232,
+        s10: 240,
+        s11: 248,
+        t3: 256,
+        t4: 264,
+        t5: 272,
+        t6: 280,
+    });
+    ktest_assert!(mem::size_of::<TrapFrame>() <= PGSIZE);
+    ktest_assert!(mem::size_of::<KernelFrame>() == 256);
+    Ok(())
+}
+
+/// `Context` is laid out as swtch.S expects.
+fn context(_kernel: KernelRef<'_, '_>) -> Result<(), Failure> {
+    assert_offsets!(Context {
+        ra: 0,
+        sp: 8,
+        s0: 16,
+        s1: 24,
+        s2: 32,
+        s3: 40,
+        s4: 48,
+        s5: 56,
+        s6: 64,
+        s7: 72,
+        s8: 80,
+        s9: 88,
+        s10: 96,
+        s11: 104,
+    });
+    Ok(())
+}
+
+impl KernelRef<'_, '_> {
+    /// Runs the self tests and prints their results, if QEMU says so. Called once at boot, by
+    /// the CPU that initialized the kernel, before the other CPUs start.
+    pub fn selftest(self) {
+        if FwCfgFile::find(SELFTEST_OPTION).is_none() {
+            return;
+        }
+        let (mut passed, mut failed) = (0, 0);
+        for test in &SELFTESTS {
+            match (test.run)(self) {
+                Ok(()) => {
+                    passed += 1;
+                    self.as_ref()
+                        .write_fmt(format_args!("selftest: ok {}\n", test.name));
+                }
+                Err(failure) => {
+                    failed += 1;
+                    self.as_ref().write_fmt(format_args!(
+                        "selftest: FAIL {}: {}:{}: {}\n",
+                        test.name, failure.file, failure.line, failure.cond
+                    ));
+                }
+            }
+        }
+        self.as_ref().write_fmt(format_args!(
+            "selftest: SUMMARY passed={} failed={}\n",
+            passed, failed
+        ));
+        assert!(failed == 0, "selftest: {} self tests failed", failed);
+    }
+}
@@ -0,0 +1,331 @@
+//! System V semaphore sets. See `rv6_abi::sem` for the system calls.
+//!
+//! A set keeps the values of its semaphores and the undo adjustments of the processes that
+//! applied operations with SEM_UNDO, under one lock. A process whose operations cannot be applied
+//! yet sleeps on the set's wait channel, and every change of a value wakes the sleepers up to
+//! try again. An exiting process adds its adjustments to the values in every set, so that the
+//! semaphores that it held are released even if it was killed.
+
+use arrayvec::ArrayVec;
+use rv6_abi::{ipc::*, sem::*};
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::addr::UVAddr,
+    error::KernelError,
+    fs::{MAY_READ, MAY_WRITE},
+    ipc::{IpcData, IpcObject, IpcRegistry},
+    kernel::KernelRef,
+    lock::SpinLock,
+    param::NSEMSET,
+    proc::{KernelCtx, WaitChannel},
+};
+
+/// Maximum number of undo adjustments in a set, of all processes together.
+const NSEMUNDO: usize = 32;
+
+pub type SemRegistry = IpcRegistry<SemSet, NSEMSET>;
+
+struct Sem {
+    val: i32,
+
+    /// Number of processes waiting for the value to grow.
+    ncnt: i32,
+}
+
+/// What to add to a semaphore when a process exits.
+struct SemUndo {
+    pid: i32,
+    num: usize,
+    adj: i32,
+}
+
+struct SemSetInner {
+    sems: ArrayVec<Sem, { SEMMSL as usize }>,
+    undo: ArrayVec<SemUndo, NSEMUNDO>,
+}
+
+pub struct SemSet {
+    inner: SpinLock<SemSetInner>,
+
+    /// WaitChannel saying a value changed, or the set was removed.
+    waitchannel: WaitChannel,
+}
+
+impl IpcData for SemSet {
+    // Each use makes a new set, as intended.
+    #[allow(clippy::declare_interior_mutable_const)]
+    const INIT: Self = Self {
+        inner: SpinLock::new(
+            "semset",
+            SemSetInner {
+                sems: ArrayVec::new_const(),
+                undo: ArrayVec::new_const(),
+            },
+        ),
+        waitchannel: WaitChannel::new(),
+    };
+
+    fn remove(&self, kernel: KernelRef<'_, '_>) {
+        // Sleepers check whether the set was removed while they hold the lock, so acquire it
+        // to wake up none of them before it sleeps.
+        drop(self.inner.lock());
+        self.waitchannel.wakeup(kernel);
+    }
+
+    fn finalize(&mut self) {
+        let inner = self.inner.get_mut();
+        inner.sems.clear();
+        inner.undo.clear();
+    }
+}
+
+impl SemSet {
+    fn new(nsems: usize) -> Self {
+        let mut set = Self::INIT;
+        set.inner
+            .get_mut()
+            .sems
+            .extend((0..nsems).map(|_| Sem { val: 0, ncnt: 0 }));
+        set
+    }
+}
+
+impl SemSetInner {
+    /// Applies `ops` if none of them has to wait. Returns Ok(None) if it did, and
+    /// Ok(Some(index of the first that has to wait)) if it did not.
+    fn apply(&mut self, ops: &[Sembuf]) -> Result<Option<usize>, KernelError> {
+        let mut vals = self
+            .sems
+            .iter()
+            .map(|sem| sem.val)
+            .collect::<ArrayVec<_, { SEMMSL as usize }>>();
+        for (i, op) in ops.iter().enumerate() {
+            let val = &mut vals[op.sem_num as usize];
+            let new = *val + op.sem_op as i32;
+            if new < 0 || op.sem_op == 0 && *val != 0 {
+                return Ok(Some(i));
+            }
+            if new > SEMVMX {
+                return Err(KernelError::Range);
+            }
+            *val = new;
+        }
+        for (sem, val) in self.sems.iter_mut().zip(vals) {
+            sem.val = val;
+        }
+        Ok(None)
+    }
+
+    /// Returns the number of adjustments that applying `ops` would add for process `pid`.
+    fn new_undos(&self, pid: i32, ops: &[Sembuf]) -> usize {
+        let undoes = |op: &Sembuf| op.sem_flg & SEM_UNDO != 0 && op.sem_op != 0;
+        ops.iter()
+            .enumerate()
+            .filter(|(i, op)| {
+                undoes(op)
+                    && !ops[..*i]
+                        .iter()
+                        .any(|o| undoes(o) && o.sem_num == op.sem_num)
+                    && !self
+                        .undo
+                        .iter()
+                        .any(|u| u.pid == pid && u.num == op.sem_num as usize)
+            })
+            .count()
+    }
+
+    /// Adds `adj` to the adjustment of process `pid` for semaphore `num`.
+    /// The caller checked with `new_undos` that there is room.
+    fn adjust(&mut self, pid: i32, num: usize, adj: i32) {
+        match self.undo.iter().position(|u| u.pid == pid && u.num == num) {
+            Some(i) => {
+                self.undo[i].adj += adj;
+                if self.undo[i].adj == 0 {
+                    let _ = self.undo.swap_remove(i);
+                }
+            }
+            None if adj != 0 => self.undo.push(SemUndo { pid, num, adj }),
+            None => (),
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Returns the identifier of the semaphore set with `key`, which must have at least `nsems`
+    /// semaphores. `flags` may contain IPC_CREAT and IPC_EXCL, and the permissions of a new set,
+    /// which has `nsems` semaphores of value 0.
+    /// Returns Ok(identifier) on success, Err(KernelError) on error.
+    pub fn semget(&self, key: i32, nsems: i32, flags: i32) -> Result<usize, KernelError> {
+        if !(0..=SEMMSL).contains(&nsems) {
+            return Err(KernelError::InvalidArgument);
+        }
+        // The rights that the permission bits of `flags` ask for, as any of owner, group, or
+        // other.
+        let access = ((flags >> 6 | flags >> 3 | flags) & 0o7) as u16;
+        let set = self.kernel().sems().get(
+            key,
+            flags,
+            access,
+            || {
+                if nsems == 0 {
+                    return Err(KernelError::InvalidArgument);
+                }
+                Ok(SemSet::new(nsems as usize))
+            },
+            self,
+        )?;
+        let len = set.data.inner.lock().sems.len();
+        let id = set.id();
+        set.free(());
+        if (nsems as usize) > len {
+            return Err(KernelError::InvalidArgument);
+        }
+        Ok(id as usize)
+    }
+
+    /// Applies the `nsops` operations at `sops` to the semaphore set `id`, all at once. Waits
+    /// until none of them has to wait, unless the first one that has to has IPC_NOWAIT.
+    /// Returns Ok(()) on success, Err(KernelError) on error, e.g.
+    /// Err(KernelError::TryAgain) if an operation with IPC_NOWAIT has to wait,
+    /// Err(KernelError::IdentifierRemoved) if the set was removed while the process waited,
+    /// Err(KernelError::Range) if a value would exceed SEMVMX,
+    /// Err(KernelError::NoSpace) if the set has no room for the undo adjustments.
+    pub fn semop(&mut self, id: i32, sops: UVAddr, nsops: usize) -> Result<(), KernelError> {
+        if nsops == 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        if nsops > SEMOPM as usize {
+            return Err(KernelError::ArgListTooLong);
+        }
+        let mut ops = [Sembuf::default(); SEMOPM as usize];
+        let ops = &mut ops[..nsops];
+        self.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(ops.as_bytes_mut(), sops)?;
+        let access = if ops.iter().any(|op| op.sem_op != 0) {
+            MAY_WRITE
+        } else {
+            MAY_READ
+        };
+        let set = self.kernel().sems().lookup(id, access, self)?;
+        let res = self.semop_set(&set, ops);
+        set.free(());
+        res
+    }
+
+    fn semop_set(&self, set: &IpcObject<SemSet>, ops: &[Sembuf]) -> Result<(), KernelError> {
+        let pid = self.proc().pid();
+        let mut inner = set.data.inner.lock();
+        if ops.iter().any(|op| op.sem_num as usize >= inner.sems.len()) {
+            return Err(KernelError::InvalidArgument);
+        }
+        loop {
+            if set.is_removed() {
+                return Err(KernelError::IdentifierRemoved);
+            }
+            if inner.undo.len() + inner.new_undos(pid, ops) > NSEMUNDO {
+                return Err(KernelError::NoSpace);
+            }
+            let op = match inner.apply(ops)? {
+                Some(i) => &ops[i],
+                None => break,
+            };
+            if op.sem_flg as i32 & IPC_NOWAIT != 0 {
+                return Err(KernelError::TryAgain);
+            }
+            if self.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            let num = op.sem_num as usize;
+            if op.sem_op < 0 {
+                inner.sems[num].ncnt += 1;
+            }
+            set.data.waitchannel.sleep(&mut inner, self);
+            if op.sem_op < 0 {
+                inner.sems[num].ncnt -= 1;
+            }
+        }
+        for op in ops.iter().filter(|op| op.sem_flg & SEM_UNDO != 0) {
+            inner.adjust(pid, op.sem_num as usize, -(op.sem_op as i32));
+        }
+        drop(inner);
+        if ops.iter().any(|op| op.sem_op != 0) {
+            set.data.waitchannel.wakeup(self.kernel());
+        }
+        Ok(())
+    }
+
+    /// Runs the command `cmd` on the semaphore set `id`: IPC_RMID, or, on its semaphore `num`,
+    /// GETVAL, GETNCNT, or SETVAL to `val`.
+    /// Returns Ok(the value for GETVAL and GETNCNT, 0 otherwise) on success,
+    /// Err(KernelError) on error.
+    pub fn semctl(&self, id: i32, num: i32, cmd: i32, val: i32) -> Result<usize, KernelError> {
+        let sems = self.kernel().sems();
+        let access = match cmd {
+            IPC_RMID => {
+                sems.remove(id, self)?;
+                return Ok(0);
+            }
+            GETVAL | GETNCNT => MAY_READ,
+            SETVAL => MAY_WRITE,
+            _ => return Err(KernelError::InvalidArgument),
+        };
+        let set = sems.lookup(id, access, self)?;
+        let res = self.semctl_set(&set, num, cmd, val);
+        set.free(());
+        res
+    }
+
+    fn semctl_set(
+        &self,
+        set: &IpcObject<SemSet>,
+        num: i32,
+        cmd: i32,
+        val: i32,
+    ) -> Result<usize, KernelError> {
+        let mut inner = set.data.inner.lock();
+        let sem = inner
+            .sems
+            .get_mut(num as usize)
+            .ok_or(KernelError::InvalidArgument)?;
+        match cmd {
+            GETVAL => Ok(sem.val as usize),
+            GETNCNT => Ok(sem.ncnt as usize),
+            _ => {
+                if !(0..=SEMVMX).contains(&val) {
+                    return Err(KernelError::Range);
+                }
+                sem.val = val;
+                inner.undo.retain(|u| u.num != num as usize);
+                drop(inner);
+                set.data.waitchannel.wakeup(self.kernel());
+                Ok(0)
+            }
+        }
+    }
+
+    /// Adds the undo adjustments of the current process, which is exiting, to the values of
+    /// their semaphores, and drops them.
+    pub fn sem_exit(&self) {
+        let pid = self.proc().pid();
+        self.kernel().sems().for_each(|set| {
+            let mut inner = set.data.inner.lock();
+            let SemSetInner { sems, undo } = &mut *inner;
+            let mut changed = false;
+            undo.retain(|u| {
+                if u.pid != pid {
+                    return true;
+                }
+                let sem = &mut sems[u.num];
+                sem.val = (sem.val + u.adj).clamp(0, SEMVMX);
+                changed = true;
+                false
+            });
+            drop(inner);
+            if changed {
+                set.data.waitchannel.wakeup(self.kernel());
+            }
+        });
+    }
+}
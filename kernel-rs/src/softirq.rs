@@ -0,0 +1,146 @@
+//! Deferred work of device interrupts, which runs with interrupts enabled shortly after them.
+//!
+//! A device interrupt handler only acknowledges the device, so that it stops interrupting, and
+//! raises the soft interrupt of the device on this CPU with `SoftIrqs::raise`. On its way out,
+//! the trap runs the soft interrupts raised on the CPU with `SoftIrqs::run`, with interrupts
+//! enabled, where the work of the device, such as waking up the processes whose disk requests
+//! completed, takes the locks it needs without making the other interrupts wait.
+//!
+//! A soft interrupt runs on one CPU at a time, as a tasklet does in Linux: if it is raised on a
+//! CPU while another runs it, the other runs it again once it finishes. So a device's work sees
+//! its input in order, even if its interrupts reach different CPUs. Interrupts that arrive while
+//! a CPU runs soft interrupts raise theirs, which the CPU runs before it leaves, and do not
+//! preempt the process, which may not sleep meanwhile.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use array_macro::array;
+
+use crate::{
+    arch::riscv::{intr_get, intr_off, intr_on},
+    cpu::cpuid,
+    hal::hal,
+    kernel::KernelRef,
+    param::NCPU,
+};
+
+/// The soft interrupts, one for each device whose interrupts defer work.
+#[derive(Copy, Clone)]
+pub enum SoftIrq {
+    /// Input from the console uart.
+    Console = 0,
+    /// Input from the second uart, /dev/ttyS1.
+    Serial = 1,
+    /// Completed disk requests.
+    Disk = 2,
+    /// Packets from the vsock device.
+    Vsock = 3,
+}
+
+/// Number of `SoftIrq`s.
+const NSOFTIRQ: usize = 4;
+
+const SOFTIRQS: [SoftIrq; NSOFTIRQ] = [
+    SoftIrq::Console,
+    SoftIrq::Serial,
+    SoftIrq::Disk,
+    SoftIrq::Vsock,
+];
+
+pub struct SoftIrqs {
+    /// The soft interrupts raised on each CPU, a bit for each.
+    pending: [AtomicUsize; NCPU],
+
+    /// Whether each CPU is running soft interrupts.
+    active: [AtomicBool; NCPU],
+
+    /// Whether each soft interrupt has work that no CPU started yet.
+    scheduled: [AtomicBool; NSOFTIRQ],
+
+    /// Whether a CPU runs each soft interrupt.
+    running: [AtomicBool; NSOFTIRQ],
+}
+
+impl SoftIrqs {
+    pub const fn new() -> Self {
+        Self {
+            pending: array![_ => AtomicUsize::new(0); NCPU],
+            active: array![_ => AtomicBool::new(false); NCPU],
+            scheduled: array![_ => AtomicBool::new(false); NSOFTIRQ],
+            running: array![_ => AtomicBool::new(false); NSOFTIRQ],
+        }
+    }
+
+    /// Raises `irq` on this CPU, which runs it when it leaves the trap. Called by device
+    /// interrupt handlers, with interrupts disabled.
+    pub fn raise(&self, irq: SoftIrq) {
+        let _ = self.pending[cpuid()].fetch_or(1 << irq as usize, Ordering::Relaxed);
+    }
+
+    /// Returns whether this CPU is running soft interrupts, so that the process must not give up
+    /// the CPU. Interrupts must be disabled.
+    pub fn is_active(&self) -> bool {
+        self.active[cpuid()].load(Ordering::Relaxed)
+    }
+
+    /// Runs the soft interrupts raised on this CPU, with interrupts enabled, until none are left.
+    /// Called at the end of a trap, with interrupts disabled, which they are again on return.
+    /// Does nothing if the trap interrupted soft interrupts, which run those it raised.
+    pub fn run(&self, kernel: KernelRef<'_, '_>) {
+        assert!(!intr_get(), "SoftIrqs::run: interruptible");
+        let id = cpuid();
+        if self.active[id].load(Ordering::Relaxed) {
+            return;
+        }
+        self.active[id].store(true, Ordering::Relaxed);
+        loop {
+            let pending = self.pending[id].swap(0, Ordering::Relaxed);
+            if pending == 0 {
+                break;
+            }
+            // SAFETY: this CPU is not preempted while it runs soft interrupts, so `id` stays
+            // this CPU, and the trap that this runs in saved its registers.
+            unsafe { intr_on() };
+            for irq in SOFTIRQS
+                .iter()
+                .filter(|irq| pending & (1 << **irq as usize) != 0)
+            {
+                self.run_one(*irq, kernel);
+            }
+            intr_off();
+        }
+        self.active[id].store(false, Ordering::Relaxed);
+    }
+
+    /// Runs `irq` unless another CPU runs it, which then runs it again.
+    fn run_one(&self, irq: SoftIrq, kernel: KernelRef<'_, '_>) {
+        let (scheduled, running) = (&self.scheduled[irq as usize], &self.running[irq as usize]);
+        scheduled.store(true, Ordering::SeqCst);
+        while !running.swap(true, Ordering::SeqCst) {
+            while scheduled.swap(false, Ordering::SeqCst) {
+                Self::work(irq, kernel);
+            }
+            running.store(false, Ordering::SeqCst);
+            // Work may have been scheduled after the last check, but before `running` was cleared.
+            if !scheduled.load(Ordering::SeqCst) {
+                break;
+            }
+        }
+    }
+
+    /// The deferred work of each soft interrupt.
+    fn work(irq: SoftIrq, kernel: KernelRef<'_, '_>) {
+        match irq {
+            // SAFETY: it's unsafe only when ctrl+p is pressed.
+            SoftIrq::Console => unsafe { hal().console().input(kernel) },
+            SoftIrq::Serial => {
+                if let Some(serial) = hal().serial() {
+                    // SAFETY: it's unsafe only when ctrl+p is pressed.
+                    unsafe { serial.input(kernel) };
+                }
+            }
+            SoftIrq::Disk => hal().disk().pinned_lock().get_pin_mut().complete(kernel),
+            SoftIrq::Vsock => hal().vsock().receive(kernel),
+        }
+    }
+}
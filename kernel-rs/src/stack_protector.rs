@@ -0,0 +1,84 @@
+//! Stack smashing protection.
+//!
+//! With `SSP=yes`, the Makefile builds the kernel with `-Z stack-protector=strong`, and stops if
+//! rustc does not support it. The compiler then puts a copy of `__stack_chk_guard` below the
+//! saved registers of each function that has an array or a local whose address is taken, and
+//! compares it with the guard before the function returns. An overflow of a buffer on the stack
+//! overwrites the copy before it reaches the return address, so the function calls
+//! `__stack_chk_fail` instead of returning, which panics with the name of the function.
+//!
+//! The guard starts as a fixed value, and the boot CPU replaces it with one from the virtio entropy
+//! source in `main`, once the HAL has found the device. The random number generator may not be
+//! seeded yet then, since it waits for interrupts on a machine without the device. The only frames
+//! that hold the old value then are those of `main` on each CPU, which never return.
+
+use core::ptr;
+
+use crate::{
+    arch::riscv::{r_cycle, r_fp},
+    backtrace::lookup,
+    hal::hal,
+    kernel::KernelRef,
+    kmsg::Level,
+    log,
+};
+
+/// The value that protected frames hold, which the compiler reads by this name.
+#[no_mangle]
+#[allow(non_upper_case_globals)]
+static mut __stack_chk_guard: usize = 0x595e_9fbd_94fd_a766;
+
+/// Called by a protected function whose copy of the guard was overwritten.
+#[no_mangle]
+extern "C" fn __stack_chk_fail() -> ! {
+    // SAFETY: the kernel is built with frame pointers, so the return address into the corrupted
+    // function is saved at fp - 8 on this function's frame, which is intact.
+    let ra = unsafe { *((r_fp() - 8) as *const usize) };
+    match lookup(ra) {
+        Some((name, off)) => panic!("stack smashing detected in {}+{:#x}", name, off),
+        None => panic!("stack smashing detected at {:#x}", ra),
+    }
+}
+
+/// Returns a random value for the guard, read from the virtio entropy source. Without the
+/// device, takes the bytes from the random number generator instead, mixed with the cycle count,
+/// and warns that the guard may be predictable.
+pub fn random_guard(kernel: KernelRef<'_, '_>) -> usize {
+    let mut bytes = [0; 8];
+    let mut len = 0;
+    // The device may return fewer bytes than asked for.
+    while len < bytes.len() {
+        let n = hal()
+            .rng()
+            .pinned_lock()
+            .get_pin_mut()
+            .read(&mut bytes[len..]);
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    if len < bytes.len() {
+        log!(
+            Level::Warn,
+            "stack_protector",
+            "no entropy source, the stack guard may be predictable"
+        );
+        kernel.random().fill(&mut bytes[len..]);
+        return usize::from_le_bytes(bytes) ^ r_cycle() as usize;
+    }
+    usize::from_le_bytes(bytes)
+}
+
+/// Replaces the guard with `guard`.
+///
+/// # Safety
+///
+/// No function on any stack may return after this, except those that started after this, since
+/// the copies of the guard in their frames would not match. It is inlined, so that calling it
+/// does not make a frame.
+#[inline(always)]
+pub unsafe fn set_stack_guard(guard: usize) {
+    // SAFETY: the other CPUs only read the guard, and they wait for the boot CPU meanwhile.
+    unsafe { ptr::write_volatile(&mut __stack_chk_guard, guard) };
+}
@@ -1,11 +1,15 @@
+use core::ptr;
+
 use crate::{
-    arch::memlayout::{clint_mtimecmp, CLINT_MTIME},
+    arch::memlayout::{clint_msip, clint_mtimecmp, CLINT_MTIME},
     arch::riscv::{
-        r_mhartid, w_medeleg, w_mepc, w_mideleg, w_mscratch, w_mtvec, w_satp, w_tp, Mstatus, MIE,
-        SIE,
+        r_mcounteren, r_mhartid, w_mcounteren, w_medeleg, w_mepc, w_mideleg, w_mscratch, w_mtvec,
+        w_satp, w_scounteren, w_tp, Mstatus, MCOUNTEREN_CY, MCOUNTEREN_TM, MIE, SCOUNTEREN_TM, SIE,
     },
+    ipi::send_ipi,
     kernel::main,
     param::NCPU,
+    time::{ns_to_cycles, TICK_NS},
 };
 
 extern "C" {
@@ -13,8 +17,8 @@ extern "C" {
     fn timervec();
 }
 
-/// entry.S needs one stack per CPU.
-#[repr(C, align(16))]
+/// entry.S needs one stack per CPU. Each is page-aligned, like the kernel stacks of processes.
+#[repr(C, align(4096))]
 pub struct Stack([[u8; 4096]; NCPU]);
 
 impl Stack {
@@ -26,8 +30,61 @@ impl Stack {
 #[no_mangle]
 pub static mut stack0: Stack = Stack::new();
 
-/// A scratch area per CPU for machine-mode timer interrupts.
-static mut TIMER_SCRATCH: [[usize; NCPU]; 5] = [[0; NCPU]; 5];
+/// A scratch area per CPU for machine-mode interrupts.
+static mut TIMER_SCRATCH: [[usize; 9]; NCPU] = [[0; 9]; NCPU];
+
+/// Index in the scratch area of the snapshot that timervec takes on a machine-mode software
+/// interrupt: the interrupted pc, frame pointer, and mstatus. The pc is written last.
+const SNAPSHOT_PC: usize = 5;
+const SNAPSHOT_FP: usize = 6;
+const SNAPSHOT_MSTATUS: usize = 7;
+
+/// Index in the scratch area of whether the next machine-mode software interrupt should take a
+/// snapshot, rather than only forward an IPI.
+const SNAPSHOT_REQUESTED: usize = 8;
+
+/// Where a CPU was when a machine-mode software interrupt interrupted it.
+#[derive(Clone, Copy)]
+pub struct Snapshot {
+    pub pc: usize,
+    /// The frame pointer, from which its stack can be walked.
+    pub fp: usize,
+    /// Whether it was in user mode.
+    pub user: bool,
+}
+
+/// Interrupts the CPU `id` in machine mode, which it takes even with interrupts disabled, so
+/// that it takes a snapshot of where it is for `snapshot`.
+pub fn request_snapshot(id: usize) {
+    // SAFETY: the snapshot in the scratch area of `id` is written only by timervec on `id`,
+    // which writes it after the interrupt below, and clears the request once it took it.
+    unsafe {
+        ptr::write_volatile(&mut TIMER_SCRATCH[id][SNAPSHOT_PC], 0);
+        ptr::write_volatile(&mut TIMER_SCRATCH[id][SNAPSHOT_REQUESTED], 1);
+    }
+    send_ipi(id);
+}
+
+/// Returns the snapshot that the CPU `id` took after `request_snapshot`, if it took one yet.
+pub fn snapshot(id: usize) -> Option<Snapshot> {
+    // SAFETY: the scratch area of `id` is written only as `request_snapshot` says.
+    let (pc, fp, mstatus) = unsafe {
+        let pc = ptr::read_volatile(&TIMER_SCRATCH[id][SNAPSHOT_PC]);
+        (
+            pc,
+            ptr::read_volatile(&TIMER_SCRATCH[id][SNAPSHOT_FP]),
+            ptr::read_volatile(&TIMER_SCRATCH[id][SNAPSHOT_MSTATUS]),
+        )
+    };
+    if pc == 0 {
+        return None;
+    }
+    Some(Snapshot {
+        pc,
+        fp,
+        user: !Mstatus::from_bits_truncate(mstatus).intersects(Mstatus::MPP_MASK),
+    })
+}
 
 /// entry.S jumps here in machine mode on stack0.
 #[no_mangle]
@@ -53,6 +110,12 @@ pub unsafe fn start() {
     x.insert(SIE::SSIE);
     unsafe { x.write() };
 
+    // allow supervisor mode to read the time and cycle CSRs.
+    unsafe { w_mcounteren(r_mcounteren() | MCOUNTEREN_TM | MCOUNTEREN_CY) };
+
+    // allow user mode to read the time CSR, for the clocks of the shared page.
+    unsafe { w_scounteren(SCOUNTEREN_TM) };
+
     // ask for clock interrupts.
     unsafe { timerinit() };
 
@@ -68,21 +131,24 @@ pub unsafe fn start() {
 /// set up to receive timer interrupts in machine mode,
 /// which arrive at timervec in kernelvec.S,
 /// which turns them into software interrupts for devintr() in trap.c.
+/// timervec also takes the machine-mode software interrupts of IPIs, see ipi.rs.
 unsafe fn timerinit() {
     // each CPU has a separate source of timer interrupts.
     let id = r_mhartid();
 
-    // ask the CLINT for a timer interrupt.
-    let interval: usize = 1_000_000; // cycles; about 1/10th second in qemu.
+    // ask the CLINT for a timer interrupt. later ones are programmed in supervisor mode.
+    let interval = ns_to_cycles(TICK_NS) as usize;
     unsafe { *(clint_mtimecmp(id) as *mut usize) = (*(CLINT_MTIME as *mut usize)) + interval };
 
     // prepare information in scratch[] for timervec.
     // scratch[0..2] : space for timervec to save registers.
     // scratch[3] : address of CLINT MTIMECMP register.
-    // scratch[4] : desired interval (in cycles) between timer interrupts.
+    // scratch[4] : address of CLINT MSIP register.
+    // scratch[5..7] : snapshot for `request_snapshot`.
+    // scratch[8] : whether `request_snapshot` asked for a snapshot.
     let scratch = unsafe { &mut TIMER_SCRATCH[id][..] };
     *unsafe { scratch.get_unchecked_mut(3) } = clint_mtimecmp(id);
-    *unsafe { scratch.get_unchecked_mut(4) } = interval;
+    *unsafe { scratch.get_unchecked_mut(4) } = clint_msip(id);
     unsafe { w_mscratch(&scratch[0] as *const _ as usize) };
 
     // set the machine-mode trap handler.
@@ -93,8 +159,9 @@ unsafe fn timerinit() {
     x.insert(Mstatus::MIE);
     unsafe { x.write() };
 
-    // enable machine-mode timer interrupts.
+    // enable machine-mode timer and software interrupts.
     let mut y = MIE::read();
     y.insert(MIE::MTIE);
+    y.insert(MIE::MSIE);
     unsafe { y.write() };
 }
@@ -0,0 +1,232 @@
+//! Suspend to RAM, which stops the machine until a key is pressed or a given time has passed,
+//! keeping its memory, and then resumes everything as it was.
+//!
+//! The superuser suspends the machine with suspend(), in these steps, undoing them in reverse
+//! order on wakeup or failure:
+//!
+//! 1. Freeze the user processes. A process that returns to user mode sleeps until the machine
+//!    resumes, and suspend() waits for the other processes to stop running, or fails with
+//!    `KernelError::Busy` if they do not within `FREEZE_TIMEOUT_NS`.
+//! 2. Suspend the devices of the device table, in order, with their suspend functions, which may
+//!    refuse. The consoles send out their buffered output, and keep only their input interrupts.
+//!    The disk is not in the device table; a request in flight completes while the machine sleeps.
+//! 3. Put the CPUs to sleep. The scheduler of each CPU saves its supervisor registers, and waits
+//!    for interrupts with `wfi` instead of running processes, since the machine has no firmware
+//!    for deeper sleep states. An interrupt of a console, or the timer of suspend(), wakes up
+//!    the machine.
+
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use pin_project::pin_project;
+
+use crate::{
+    arch::riscv::{r_satp, r_stvec, sfence_vma, w_satp, w_stvec, wfi, SIE},
+    chardev::chardev_name,
+    cred::Caps,
+    error::KernelError,
+    hal::hal,
+    kernel::KernelRef,
+    kmsg::Level,
+    lock::SpinLock,
+    log,
+    proc::{KernelCtx, WaitChannel},
+    time::ktime_now,
+    timer::Timer,
+};
+
+/// Time that the other processes have to freeze, in nanoseconds.
+const FREEZE_TIMEOUT_NS: u64 = 1_000_000_000;
+
+/// Time between the checks of whether the other processes froze, in nanoseconds.
+const FREEZE_POLL_NS: u64 = 10_000_000;
+
+/// The supervisor registers of a CPU, which it saves before it sleeps, and restores after.
+struct SavedCsrs {
+    sie: SIE,
+    stvec: usize,
+    satp: usize,
+}
+
+#[pin_project]
+pub struct Suspend {
+    /// Whether user processes must sleep before they return to user mode.
+    frozen: AtomicBool,
+
+    /// Whether the CPUs must sleep instead of running processes.
+    asleep: AtomicBool,
+
+    /// Taken to change `frozen` and `asleep`, and to sleep until they change.
+    lock: SpinLock<()>,
+
+    /// WaitChannel for saying `frozen` or `asleep` was cleared.
+    waitchannel: WaitChannel,
+
+    /// Wakes up the machine after the time given to suspend().
+    #[pin]
+    timer: Timer,
+}
+
+impl Suspend {
+    pub const fn new() -> Self {
+        Self {
+            frozen: AtomicBool::new(false),
+            asleep: AtomicBool::new(false),
+            lock: SpinLock::new("suspend", ()),
+            waitchannel: WaitChannel::new(),
+            timer: Timer::new(Self::expire),
+        }
+    }
+
+    /// Returns whether the CPUs must sleep instead of running processes.
+    pub fn is_asleep(&self) -> bool {
+        self.asleep.load(Ordering::Acquire)
+    }
+
+    /// Wakes up the machine, if it is asleep. Called from the interrupts that wake it up.
+    pub fn wake(&self, kernel: KernelRef<'_, '_>) {
+        if self.is_asleep() {
+            let _guard = self.lock.lock();
+            self.asleep.store(false, Ordering::Release);
+            self.waitchannel.wakeup(kernel);
+        }
+    }
+
+    /// The function of `self.timer`.
+    fn expire(_timer: Pin<&Timer>, _now: u64, kernel: KernelRef<'_, '_>) -> Option<u64> {
+        hal().suspend().wake(kernel);
+        None
+    }
+
+    /// Puts this CPU to sleep until the machine wakes up. Called by the scheduler, with
+    /// interrupts enabled, so that the CPU handles interrupts as they come.
+    pub fn sleep_cpu(&self) {
+        let saved = SavedCsrs {
+            sie: SIE::read(),
+            stvec: r_stvec(),
+            satp: r_satp(),
+        };
+        // Wake up if the machine woke up between the check and `wfi`, at the latest at the next
+        // timer interrupt, which the watchdog timer sends every second.
+        while self.is_asleep() {
+            hal().watchdog().beat();
+            wfi();
+        }
+        // SAFETY: the registers are those that the CPU had before it slept.
+        unsafe {
+            w_satp(saved.satp);
+            sfence_vma();
+            w_stvec(saved.stvec);
+            saved.sie.write();
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Sleeps until the machine resumes, if the user processes are frozen. Called before the
+    /// current process returns to user mode.
+    pub fn freeze(&self) {
+        let suspend = hal().suspend();
+        if !suspend.frozen.load(Ordering::Acquire) {
+            return;
+        }
+        let mut guard = suspend.lock.lock();
+        while suspend.frozen.load(Ordering::Acquire) && !self.proc().killed() {
+            suspend.waitchannel.sleep(&mut guard, self);
+        }
+    }
+
+    /// Suspend the machine until a key is pressed on a console, or `seconds` seconds have passed
+    /// if `seconds` is positive. Needs `Caps::SYS_BOOT`.
+    /// Returns Ok(0) after the machine resumes, Err(KernelError) if it could not be suspended.
+    pub fn suspend(&mut self, seconds: i32) -> Result<usize, KernelError> {
+        self.require_cap(Caps::SYS_BOOT)?;
+        let suspend = hal().suspend();
+        {
+            let _guard = suspend.lock.lock();
+            if suspend.frozen.load(Ordering::Acquire) {
+                return Err(KernelError::Busy);
+            }
+            suspend.frozen.store(true, Ordering::Release);
+        }
+
+        let res = self.suspend_frozen(seconds);
+
+        let _guard = suspend.lock.lock();
+        suspend.frozen.store(false, Ordering::Release);
+        suspend.waitchannel.wakeup(self.kernel());
+        res.map(|()| 0)
+    }
+
+    /// Suspends the machine once the user processes are freezing.
+    fn suspend_frozen(&mut self, seconds: i32) -> Result<(), KernelError> {
+        // Wait for the other processes to freeze. This process is running.
+        let deadline = ktime_now() + FREEZE_TIMEOUT_NS;
+        while self.kernel().procs().count_runnable() > 1 {
+            if ktime_now() >= deadline {
+                log!(Level::Warn, "suspend", "suspend: processes did not freeze");
+                return Err(KernelError::Busy);
+            }
+            self.sleep_until(ktime_now() + FREEZE_POLL_NS)?;
+        }
+
+        let devsw = self.kernel().devsw();
+        for (major, dev) in devsw.iter().enumerate() {
+            if let Err(e) = dev.suspend.map_or(Ok(()), |suspend| suspend(self.kernel())) {
+                log!(
+                    Level::Warn,
+                    "suspend",
+                    "suspend: device {} refused to suspend",
+                    chardev_name(major).unwrap_or("?")
+                );
+                self.resume_devices(major);
+                return Err(e);
+            }
+        }
+
+        let suspend = hal().suspend();
+        let start = ktime_now();
+        log!(Level::Info, "suspend", "suspend: asleep");
+        {
+            let _guard = suspend.lock.lock();
+            suspend.asleep.store(true, Ordering::Release);
+        }
+        if seconds > 0 {
+            self.kernel().timers().arm(
+                suspend.project_ref().timer,
+                start + seconds as u64 * 1_000_000_000,
+            );
+        }
+        let mut guard = suspend.lock.lock();
+        while suspend.is_asleep() {
+            suspend.waitchannel.sleep(&mut guard, self);
+        }
+        drop(guard);
+        self.kernel().timers().cancel(suspend.project_ref().timer);
+
+        self.resume_devices(devsw.len());
+        log!(
+            Level::Info,
+            "suspend",
+            "suspend: awake after {}ms",
+            (ktime_now() - start) / 1_000_000
+        );
+        Ok(())
+    }
+
+    /// Resumes the devices of the device table before `end`, in reverse order.
+    fn resume_devices(&self, end: usize) {
+        for (major, dev) in self.kernel().devsw()[..end].iter().enumerate().rev() {
+            if let Some(Err(_)) = dev.resume.map(|resume| resume(self.kernel())) {
+                log!(
+                    Level::Error,
+                    "suspend",
+                    "suspend: device {} failed to resume",
+                    chardev_name(major).unwrap_or("?")
+                );
+            }
+        }
+    }
+}
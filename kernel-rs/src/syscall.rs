@@ -8,32 +8,47 @@ use core::{mem, str};
 
 use arrayvec::ArrayVec;
 use cstr_core::CStr;
+use rv6_abi::{
+    quota::{Dqblk, Q_GETQUOTA, Q_SETQUOTA},
+    syscall::*,
+};
 
 use crate::{
     arch::{
         addr::{Addr, UVAddr},
         poweroff,
     },
-    file::RcFile,
+    audit::AuditEvent,
+    cred::{Caps, MAX_ID},
+    error::KernelError,
+    file::{
+        FileType, IoctlArg, RcFile, FD_CLOEXEC, F_GETFD, F_GETFL, F_GETPIPE_SZ, F_SETFD, F_SETFL,
+        F_SETPIPE_SZ,
+    },
     fs::{FcntlFlags, FileSystem, InodeType, Path},
     hal::hal,
-    ok_or,
+    kmsg::Level,
+    log,
+    mqueue::RcMsgQueue,
     page::Page,
     param::{MAXARG, MAXPATH},
     proc::{CurrentProc, KernelCtx},
     some_or,
+    time::{ktime_now, TICK_NS},
+    vsock::Socket,
+    with_fs,
 };
 
 impl CurrentProc<'_, '_> {
     /// Fetch the usize at addr from the current process.
-    /// Returns Ok(fetched integer) on success, Err(()) on error.
-    pub fn fetchaddr(&mut self, addr: UVAddr) -> Result<usize, ()> {
+    /// Returns Ok(fetched integer) on success, Err(KernelError::Fault) on error.
+    pub fn fetchaddr(&mut self, addr: UVAddr) -> Result<usize, KernelError> {
         let mut ip = 0;
         let sz = mem::size_of::<usize>();
         if addr.into_usize() >= self.memory().size()
             || addr.into_usize() + sz > self.memory().size()
         {
-            return Err(());
+            return Err(KernelError::Fault);
         }
         // SAFETY: usize does not have any internal structure.
         unsafe { self.memory_mut().copy_in(&mut ip, addr) }?;
@@ -42,7 +57,11 @@ impl CurrentProc<'_, '_> {
 
     /// Fetch the nul-terminated string at addr from the current process.
     /// Returns reference to the string in the buffer.
-    pub fn fetchstr<'a>(&mut self, addr: UVAddr, buf: &'a mut [u8]) -> Result<&'a CStr, ()> {
+    pub fn fetchstr<'a>(
+        &mut self,
+        addr: UVAddr,
+        buf: &'a mut [u8],
+    ) -> Result<&'a CStr, KernelError> {
         self.memory_mut().copy_in_str(buf, addr)?;
 
         // SAFETY: buf contains '\0' as copy_in_str has succeeded.
@@ -62,326 +81,1098 @@ impl CurrentProc<'_, '_> {
     }
 
     /// Fetch the nth 32-bit system call argument.
-    pub fn argint(&self, n: usize) -> Result<i32, ()> {
+    pub fn argint(&self, n: usize) -> Result<i32, KernelError> {
         Ok(self.argraw(n) as i32)
     }
 
+    /// Fetch the nth system call argument as a user or group ID.
+    /// Returns Err(KernelError::InvalidArgument) if it is above `cred::MAX_ID`.
+    pub fn argid(&self, n: usize) -> Result<u16, KernelError> {
+        let id = self.argint(n)? as u32;
+        if id > MAX_ID {
+            return Err(KernelError::InvalidArgument);
+        }
+        Ok(id as u16)
+    }
+
     /// Retrieve an argument as a pointer.
     /// Doesn't check for legality, since
     /// copyin/copyout will do that.
-    pub fn argaddr(&self, n: usize) -> Result<usize, ()> {
+    pub fn argaddr(&self, n: usize) -> Result<usize, KernelError> {
         Ok(self.argraw(n))
     }
 
     /// Fetch the nth word-sized system call argument as a null-terminated string.
     /// Copies into buf, at most max.
     /// Returns reference to the string in the buffer.
-    pub fn argstr<'a>(&mut self, n: usize, buf: &'a mut [u8]) -> Result<&'a CStr, ()> {
+    pub fn argstr<'a>(&mut self, n: usize, buf: &'a mut [u8]) -> Result<&'a CStr, KernelError> {
         let addr = self.argaddr(n)?;
         self.fetchstr(addr.into(), buf)
     }
 
     /// Fetch the nth word-sized system call argument as a file descriptor
     /// and return both the descriptor and the corresponding struct file.
-    fn argfd(&self, n: usize) -> Result<(i32, &RcFile), ()> {
+    fn argfd(&self, n: usize) -> Result<(i32, &RcFile), KernelError> {
         let fd = self.argint(n)?;
         let f = self
             .deref_data()
-            .open_files
+            .fds
             .get(fd as usize)
-            .ok_or(())?
-            .as_ref()
-            .ok_or(())?;
+            .ok_or(KernelError::BadFd)?;
         Ok((fd, f))
     }
+
+    /// Fetch the nth word-sized system call argument as a file descriptor of a socket,
+    /// and return the socket.
+    /// Returns Ok(socket, whether its file is nonblocking) on success, Err(KernelError) on error.
+    fn argsocket(&self, n: usize) -> Result<(Socket, bool), KernelError> {
+        let (_, f) = self.argfd(n)?;
+        match &f.typ {
+            FileType::Socket { socket } => {
+                Ok((*socket, f.status_flags().contains(FcntlFlags::O_NONBLOCK)))
+            }
+            _ => Err(KernelError::NotSocket),
+        }
+    }
+
+    /// Fetch the nth word-sized system call argument as a file descriptor of a message queue,
+    /// and return the queue, which the caller frees.
+    /// Returns Ok(queue, status flags of its file) on success, Err(KernelError) on error.
+    fn argmqueue(&self, n: usize) -> Result<(RcMsgQueue, FcntlFlags), KernelError> {
+        let (_, f) = self.argfd(n)?;
+        match &f.typ {
+            FileType::MsgQueue { queue } => Ok((queue.clone(), f.status_flags())),
+            _ => Err(KernelError::BadFd),
+        }
+    }
 }
 
 impl KernelCtx<'_, '_> {
-    pub fn syscall(&mut self, num: i32) -> Result<usize, ()> {
-        match num {
-            1 => self.sys_fork(),
-            2 => self.sys_exit(),
-            3 => self.sys_wait(),
-            4 => self.sys_pipe(),
-            5 => self.sys_read(),
-            6 => self.sys_kill(),
-            7 => self.sys_exec(),
-            8 => self.sys_fstat(),
-            9 => self.sys_chdir(),
-            10 => self.sys_dup(),
-            11 => self.sys_getpid(),
-            12 => self.sys_sbrk(),
-            13 => self.sys_sleep(),
-            14 => self.sys_uptime(),
-            15 => self.sys_open(),
-            16 => self.sys_write(),
-            17 => self.sys_mknod(),
-            18 => self.sys_unlink(),
-            19 => self.sys_link(),
-            20 => self.sys_mkdir(),
-            21 => self.sys_close(),
-            22 => self.sys_poweroff(),
+    pub fn syscall(&mut self, num: i32) -> Result<usize, KernelError> {
+        if !self.is_traced(num) {
+            return self.dispatch(num);
+        }
+        let line = self.trace_call(num);
+        if num == 2 {
+            // exit does not return.
+            self.kernel().as_ref().write_fmt(format_args!("{}\n", line));
+        }
+        let ret = self.dispatch(num);
+        self.trace_ret(&line, ret);
+        ret
+    }
+
+    fn dispatch(&mut self, num: i32) -> Result<usize, KernelError> {
+        if self.fuzz_refuses(num) {
+            return Err(KernelError::NotPermitted);
+        }
+        let ret = match num {
+            SYS_FORK => self.sys_fork(),
+            SYS_EXIT => self.sys_exit(),
+            SYS_WAIT => self.sys_wait(),
+            SYS_PIPE => self.sys_pipe(),
+            SYS_READ => self.sys_read(),
+            SYS_KILL => self.sys_kill(),
+            SYS_EXEC => self.sys_exec(),
+            SYS_FSTAT => self.sys_fstat(),
+            SYS_CHDIR => self.sys_chdir(),
+            SYS_DUP => self.sys_dup(),
+            SYS_GETPID => self.sys_getpid(),
+            SYS_SBRK => self.sys_sbrk(),
+            SYS_SLEEP => self.sys_sleep(),
+            SYS_UPTIME => self.sys_uptime(),
+            SYS_OPEN => self.sys_open(),
+            SYS_WRITE => self.sys_write(),
+            SYS_MKNOD => self.sys_mknod(),
+            SYS_UNLINK => self.sys_unlink(),
+            SYS_LINK => self.sys_link(),
+            SYS_MKDIR => self.sys_mkdir(),
+            SYS_CLOSE => self.sys_close(),
+            SYS_POWEROFF => self.sys_poweroff(),
+            SYS_SYSINFO => self.sys_sysinfo(),
+            SYS_FCNTL => self.sys_fcntl(),
+            SYS_IOCTL => self.sys_ioctl(),
+            SYS_POLL => self.sys_poll(),
+            SYS_PIPE2 => self.sys_pipe2(),
+            SYS_EVENTFD => self.sys_eventfd(),
+            SYS_TIMERFD_CREATE => self.sys_timerfd_create(),
+            SYS_TIMERFD_SETTIME => self.sys_timerfd_settime(),
+            SYS_TIMERFD_GETTIME => self.sys_timerfd_gettime(),
+            SYS_GETCWD => self.sys_getcwd(),
+            SYS_CHROOT => self.sys_chroot(),
+            SYS_UMASK => self.sys_umask(),
+            SYS_CLOCK_GETTIME => self.sys_clock_gettime(),
+            SYS_GETRANDOM => self.sys_getrandom(),
+            SYS_TRACE => self.sys_trace(),
+            SYS_PTRACE => self.sys_ptrace(),
+            SYS_GETUID => self.sys_getuid(),
+            SYS_GETEUID => self.sys_geteuid(),
+            SYS_GETGID => self.sys_getgid(),
+            SYS_GETEGID => self.sys_getegid(),
+            SYS_SETUID => self.sys_setuid(),
+            SYS_SETGID => self.sys_setgid(),
+            SYS_CHMOD => self.sys_chmod(),
+            SYS_CHOWN => self.sys_chown(),
+            SYS_AUDIT => self.sys_audit(),
+            SYS_SIGSEND => self.sys_sigsend(),
+            SYS_SETPGID => self.sys_setpgid(),
+            SYS_GETPGID => self.sys_getpgid(),
+            SYS_CLOCK_SETTIME => self.sys_clock_settime(),
+            SYS_ADJTIME => self.sys_adjtime(),
+            SYS_DMESG => self.sys_dmesg(),
+            SYS_LOGFILTER => self.sys_logfilter(),
+            SYS_TRACEON => self.sys_traceon(),
+            SYS_TRACEREAD => self.sys_traceread(),
+            SYS_LEAKCHECK => self.sys_leakcheck(),
+            SYS_FAILINJECT => self.sys_failinject(),
+            SYS_KTEST => self.sys_ktest(),
+            SYS_FUZZINFO => self.sys_fuzzinfo(),
+            SYS_PROFILE => self.sys_profile(),
+            SYS_SUSPEND => self.sys_suspend(),
+            SYS_SOCKET => self.sys_socket(),
+            SYS_BIND => self.sys_bind(),
+            SYS_LISTEN => self.sys_listen(),
+            SYS_ACCEPT => self.sys_accept(),
+            SYS_CONNECT => self.sys_connect(),
+            SYS_FADVISE => self.sys_fadvise(),
+            SYS_SENDFILE => self.sys_sendfile(),
+            SYS_IORING_SETUP => self.sys_ioring_setup(),
+            SYS_IORING_ENTER => self.sys_ioring_enter(),
+            SYS_SPLICE => self.sys_splice(),
+            SYS_QUOTACTL => self.sys_quotactl(),
+            SYS_PIDFD_OPEN => self.sys_pidfd_open(),
+            SYS_SIGPENDING => self.sys_sigpending(),
+            SYS_CAPGET => self.sys_capget(),
+            SYS_CAPDROP => self.sys_capdrop(),
+            SYS_SEMGET => self.sys_semget(),
+            SYS_SEMOP => self.sys_semop(),
+            SYS_SEMCTL => self.sys_semctl(),
+            SYS_MQ_OPEN => self.sys_mq_open(),
+            SYS_MQ_SEND => self.sys_mq_send(),
+            SYS_MQ_RECEIVE => self.sys_mq_receive(),
+            SYS_MQ_UNLINK => self.sys_mq_unlink(),
+            SYS_MQ_GETATTR => self.sys_mq_getattr(),
             _ => {
-                self.kernel().as_ref().write_fmt(format_args!(
-                    "{} {}: unknown sys call {}",
-                    self.proc().pid(),
-                    str::from_utf8(&self.proc().deref_data().name).unwrap_or("???"),
-                    num
-                ));
-                Err(())
+                // A fuzzer makes too many of them to log.
+                if !cfg!(feature = "fuzz") {
+                    log!(
+                        Level::Warn,
+                        "syscall",
+                        "{} {}: unknown sys call {}",
+                        self.proc().pid(),
+                        str::from_utf8(&self.proc().deref_data().name).unwrap_or("???"),
+                        num
+                    );
+                }
+                Err(KernelError::NoSys)
             }
+        };
+        if cfg!(feature = "fuzz") {
+            hal().fuzz().record(&ret);
         }
+        ret
     }
 
     /// Terminate the current process; status reported to wait(). No return.
-    pub fn sys_exit(&mut self) -> Result<usize, ()> {
+    pub fn sys_exit(&mut self) -> Result<usize, KernelError> {
         let n = self.proc().argint(0)?;
         self.kernel().procs().exit_current(n, self);
     }
 
     /// Create a process.
-    /// Returns Ok(child’s PID) on success, Err(()) on error.
-    pub fn sys_fork(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(child’s PID) on success, Err(KernelError) on error.
+    pub fn sys_fork(&mut self) -> Result<usize, KernelError> {
         Ok(self.kernel().procs().fork(self)? as _)
     }
 
     /// Wait for a child to exit.
-    /// Returns Ok(child’s PID) on success, Err(()) on error.
-    pub fn sys_wait(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(child’s PID) on success, Err(KernelError) on error.
+    pub fn sys_wait(&mut self) -> Result<usize, KernelError> {
         let p = self.proc().argaddr(0)?;
         Ok(self.kernel().procs().wait(p.into(), self)? as _)
     }
 
     /// Return the current process’s PID.
-    pub fn sys_getpid(&self) -> Result<usize, ()> {
+    pub fn sys_getpid(&self) -> Result<usize, KernelError> {
         Ok(self.proc().pid() as _)
     }
 
+    /// Set the current process’s file mode creation mask.
+    /// Only the permission bits (0777) of the argument are used.
+    /// Returns Ok(previous mask).
+    pub fn sys_umask(&mut self) -> Result<usize, KernelError> {
+        let mask = self.proc().argint(0)? as u32 & 0o777;
+        let old = mem::replace(&mut self.proc_mut().deref_mut_data().umask, mask);
+        Ok(old as _)
+    }
+
+    /// Return the real user ID.
+    pub fn sys_getuid(&self) -> Result<usize, KernelError> {
+        Ok(self.proc().deref_data().cred.uid as _)
+    }
+
+    /// Return the effective user ID.
+    pub fn sys_geteuid(&self) -> Result<usize, KernelError> {
+        Ok(self.proc().deref_data().cred.euid() as _)
+    }
+
+    /// Return the real group ID.
+    pub fn sys_getgid(&self) -> Result<usize, KernelError> {
+        Ok(self.proc().deref_data().cred.gid as _)
+    }
+
+    /// Return the effective group ID.
+    pub fn sys_getegid(&self) -> Result<usize, KernelError> {
+        Ok(self.proc().deref_data().cred.egid as _)
+    }
+
+    /// Set the user IDs.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_setuid(&mut self) -> Result<usize, KernelError> {
+        let uid = self.proc().argint(0)?;
+        self.setuid(uid as u32)?;
+        Ok(0)
+    }
+
+    /// Read the audit log.
+    /// Returns Ok(number of records read) on success, Err(KernelError) on error.
+    pub fn sys_audit(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(0)?.into();
+        let n = self.proc().argint(1)?;
+        let seq = self.proc().argaddr(2)?;
+        if n < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        self.audit_read(addr, n as usize, seq as u64)
+    }
+
+    /// Set the group IDs.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_setgid(&mut self) -> Result<usize, KernelError> {
+        let gid = self.proc().argint(0)?;
+        self.setgid(gid as u32)?;
+        Ok(0)
+    }
+
+    /// Get the capabilities that the current process has.
+    /// Returns Ok(capabilities, a bit for each).
+    pub fn sys_capget(&self) -> Result<usize, KernelError> {
+        Ok(self.proc().deref_data().cred.caps().bits() as usize)
+    }
+
+    /// Drop capabilities for good, from the current process and the programs it runs.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_capdrop(&mut self) -> Result<usize, KernelError> {
+        let caps = self.proc().argaddr(0)?;
+        self.capdrop(caps as u64)?;
+        Ok(0)
+    }
+
+    /// Get the identifier of a semaphore set, making the set if asked to.
+    /// Returns Ok(identifier) on success, Err(KernelError) on error.
+    pub fn sys_semget(&self) -> Result<usize, KernelError> {
+        let key = self.proc().argint(0)?;
+        let nsems = self.proc().argint(1)?;
+        let flags = self.proc().argint(2)?;
+        self.semget(key, nsems, flags)
+    }
+
+    /// Apply operations to a semaphore set at once.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_semop(&mut self) -> Result<usize, KernelError> {
+        let id = self.proc().argint(0)?;
+        // user pointer to array of struct sembuf
+        let sops = self.proc().argaddr(1)?.into();
+        let nsops = self.proc().argint(2)?;
+        if nsops < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        self.semop(id, sops, nsops as usize)?;
+        Ok(0)
+    }
+
+    /// Control a semaphore set.
+    /// Returns Ok(command-specific value) on success, Err(KernelError) on error.
+    pub fn sys_semctl(&self) -> Result<usize, KernelError> {
+        let id = self.proc().argint(0)?;
+        let num = self.proc().argint(1)?;
+        let cmd = self.proc().argint(2)?;
+        let val = self.proc().argint(3)?;
+        self.semctl(id, num, cmd, val)
+    }
+
+    /// Open a message queue, and allocate a file descriptor for it.
+    /// Returns Ok(file descriptor) on success, Err(KernelError) on error.
+    pub fn sys_mq_open(&mut self) -> Result<usize, KernelError> {
+        let key = self.proc().argint(0)?;
+        let flags = self.proc().argint(1)?;
+        let mode = self.proc().argint(2)?;
+        // user pointer to struct mq_attr, or null
+        let attr = self.proc().argaddr(3)?.into();
+        self.mq_open(key, flags, mode as u16, attr)
+    }
+
+    /// Send a message to a message queue.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_mq_send(&mut self) -> Result<usize, KernelError> {
+        let msg = self.proc().argaddr(1)?.into();
+        let len = self.proc().argint(2)?;
+        let prio = self.proc().argint(3)?;
+        if len < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        let (queue, status) = self.proc().argmqueue(0)?;
+        let res = self.mq_send(&queue, msg, len as usize, prio as u32, status);
+        queue.free(());
+        res.map(|_| 0)
+    }
+
+    /// Receive a message from a message queue.
+    /// Returns Ok(length of the message) on success, Err(KernelError) on error.
+    pub fn sys_mq_receive(&mut self) -> Result<usize, KernelError> {
+        let buf = self.proc().argaddr(1)?.into();
+        let len = self.proc().argint(2)?;
+        // user pointer to unsigned int, or null
+        let prio = self.proc().argaddr(3)?.into();
+        if len < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        let (queue, status) = self.proc().argmqueue(0)?;
+        let res = self.mq_receive(&queue, buf, len as usize, prio, status);
+        queue.free(());
+        res
+    }
+
+    /// Remove a message queue.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_mq_unlink(&self) -> Result<usize, KernelError> {
+        let key = self.proc().argint(0)?;
+        self.mq_unlink(key)?;
+        Ok(0)
+    }
+
+    /// Get the attributes of a message queue.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_mq_getattr(&mut self) -> Result<usize, KernelError> {
+        // user pointer to struct mq_attr
+        let attr = self.proc().argaddr(1)?.into();
+        let (queue, status) = self.proc().argmqueue(0)?;
+        let res = self.mq_getattr(&queue, attr, status);
+        queue.free(());
+        res.map(|_| 0)
+    }
+
+    /// Get the time of a clock.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_clock_gettime(&mut self) -> Result<usize, KernelError> {
+        let clock = self.proc().argint(0)?;
+        let addr = self.proc().argaddr(1)?.into();
+        self.clock_gettime(clock, addr)?;
+        Ok(0)
+    }
+
+    /// Set the time of a clock.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_clock_settime(&mut self) -> Result<usize, KernelError> {
+        let clock = self.proc().argint(0)?;
+        let addr = self.proc().argaddr(1)?.into();
+        self.clock_settime(clock, addr)?;
+        Ok(0)
+    }
+
+    /// Slew the realtime clock.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_adjtime(&mut self) -> Result<usize, KernelError> {
+        let delta = self.proc().argaddr(0)?.into();
+        let olddelta = self.proc().argaddr(1)?.into();
+        self.adjtime(delta, olddelta)?;
+        Ok(0)
+    }
+
+    /// Read the kernel log.
+    /// Returns Ok(number of bytes read) on success, Err(KernelError) on error.
+    pub fn sys_dmesg(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(0)?.into();
+        let n = self.proc().argint(1)?;
+        let off = self.proc().argaddr(2)?.into();
+        if n < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        self.dmesg(addr, n as usize, off)
+    }
+
+    /// Set which messages the kernel log keeps.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_logfilter(&mut self) -> Result<usize, KernelError> {
+        let mut spec = [0; MAXPATH];
+        let spec = self.proc_mut().argstr(0, &mut spec)?;
+        self.set_log_filter(spec.to_bytes())?;
+        Ok(0)
+    }
+
+    /// Turn the kernel's tracepoints on or off.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_traceon(&mut self) -> Result<usize, KernelError> {
+        let on = self.proc().argint(0)?;
+        self.trace_on(on != 0)?;
+        Ok(0)
+    }
+
+    /// Read the trace buffer of a CPU.
+    /// Returns Ok(number of records read) on success, Err(KernelError) on error.
+    pub fn sys_traceread(&mut self) -> Result<usize, KernelError> {
+        let cpu = self.proc().argint(0)?;
+        let addr = self.proc().argaddr(1)?.into();
+        let n = self.proc().argint(2)?;
+        let seq = self.proc().argaddr(3)?;
+        if cpu < 0 || n < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        self.trace_read(cpu as usize, addr, n as usize, seq as u64)
+    }
+
+    /// Report the pages of the kernel that were allocated long ago and are still allocated.
+    /// Returns Ok(number of records read) on success, Err(KernelError) on error.
+    pub fn sys_leakcheck(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(0)?.into();
+        let n = self.proc().argint(1)?;
+        let age = self.proc().argaddr(2)?;
+        if n < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        self.leak_check(addr, n as usize, age as u64)
+    }
+
+    /// Make an operation of the kernel fail on purpose.
+    /// Returns Ok(number of operations the site had left) on success, Err(KernelError) on error.
+    pub fn sys_failinject(&mut self) -> Result<usize, KernelError> {
+        let site = self.proc().argint(0)?;
+        let nth = self.proc().argint(1)?;
+        if site < 0 || nth < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        self.fail_inject(site as usize, nth as usize)
+    }
+
+    /// Run the kernel's tests whose names start with a filter.
+    /// Returns Ok(number of tests that failed) on success, Err(KernelError) on error.
+    pub fn sys_ktest(&mut self) -> Result<usize, KernelError> {
+        let mut filter = [0; MAXPATH];
+        let filter = self.proc_mut().argstr(0, &mut filter)?;
+        self.ktest(filter.to_bytes())
+    }
+
+    /// Turn the profiler on or off, or read the samples of a CPU.
+    /// Returns Ok(number of samples read, or 0) on success, Err(KernelError) on error.
+    pub fn sys_profile(&mut self) -> Result<usize, KernelError> {
+        let cmd = self.proc().argint(0)?;
+        let cpu = self.proc().argint(1)?;
+        let addr = self.proc().argaddr(2)?.into();
+        let n = self.proc().argint(3)?;
+        if cpu < 0 || n < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        self.profile(cmd, cpu as usize, addr, n as usize)
+    }
+
+    /// Suspend the machine until a key is pressed, or for the given number of seconds.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_suspend(&mut self) -> Result<usize, KernelError> {
+        let seconds = self.proc().argint(0)?;
+        self.suspend(seconds)
+    }
+
+    /// Copy the counters of the fuzzing mode into struct fuzzinfo.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_fuzzinfo(&mut self) -> Result<usize, KernelError> {
+        // user pointer to struct fuzzinfo
+        let addr = self.proc().argaddr(0)?;
+        self.fuzz_info(addr.into())?;
+        Ok(0)
+    }
+
+    /// Fill a user buffer with random bytes.
+    /// Returns Ok(number of bytes) on success, Err(KernelError) on error.
+    pub fn sys_getrandom(&mut self) -> Result<usize, KernelError> {
+        let addr = self.proc().argaddr(0)?.into();
+        let n = self.proc().argint(1)?;
+        let flags = self.proc().argint(2)?;
+        if n < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        self.getrandom(addr, n as usize, flags)
+    }
+
+    /// Trace the system calls selected by a mask.
+    /// Returns Ok(0).
+    pub fn sys_trace(&mut self) -> Result<usize, KernelError> {
+        let mask = self.proc().argaddr(0)?;
+        self.trace(mask as u64)
+    }
+
+    /// Trace or control a child process.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_ptrace(&mut self) -> Result<usize, KernelError> {
+        let request = self.proc().argint(0)?;
+        let pid = self.proc().argint(1)?;
+        let addr = self.proc().argaddr(2)?;
+        let data = self.proc().argaddr(3)?;
+        self.ptrace(request, pid, addr.into(), data)
+    }
+
     /// Grow process’s memory by n bytes.
-    /// Returns Ok(start of new memory) on success, Err(()) on error.
-    pub fn sys_sbrk(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(start of new memory) on success, Err(KernelError) on error.
+    pub fn sys_sbrk(&mut self) -> Result<usize, KernelError> {
         let n = self.proc().argint(0)?;
         self.proc_mut().memory_mut().resize(n, hal().kmem())
     }
 
     /// Pause for n clock ticks.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_sleep(&self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_sleep(&self) -> Result<usize, KernelError> {
         let n = self.proc().argint(0)?;
-        let mut ticks = self.kernel().ticks().lock();
-        let ticks0 = *ticks;
-        while ticks.wrapping_sub(ticks0) < n as u32 {
-            if self.proc().killed() {
-                return Err(());
-            }
-            ticks.sleep(self);
-        }
+        self.sleep_until(ktime_now() + n.max(0) as u64 * TICK_NS)?;
         Ok(0)
     }
 
     /// Terminate process PID.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_kill(&self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_kill(&self) -> Result<usize, KernelError> {
+        let pid = self.proc().argint(0)?;
+        let cred = self.proc().deref_data().cred;
+        let res = self.kernel().procs().kill(pid, &cred);
+        self.audit(AuditEvent::Kill, pid as u64, res.err());
+        res.map(|_| 0)
+    }
+
+    /// Send a signal to a process or a process group.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_sigsend(&self) -> Result<usize, KernelError> {
+        let pid = self.proc().argint(0)?;
+        let sig = self.proc().argint(1)?;
+        let res = self.sigsend(pid, sig);
+        self.audit(AuditEvent::Kill, pid as u64, res.err());
+        res.map(|_| 0)
+    }
+
+    /// Return the signals that the process ignored since it last asked.
+    /// Returns Ok(a bit `1 << sig` for each signal).
+    pub fn sys_sigpending(&self) -> Result<usize, KernelError> {
+        Ok(self.sigpending() as usize)
+    }
+
+    /// Set the process group of a process.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_setpgid(&self) -> Result<usize, KernelError> {
         let pid = self.proc().argint(0)?;
-        self.kernel().procs().kill(pid)?;
+        let pgid = self.proc().argint(1)?;
+        self.setpgid(pid, pgid)?;
         Ok(0)
     }
 
+    /// Get the process group of a process.
+    /// Returns Ok(process group ID) on success, Err(KernelError) on error.
+    pub fn sys_getpgid(&self) -> Result<usize, KernelError> {
+        let pid = self.proc().argint(0)?;
+        Ok(self.getpgid(pid)? as usize)
+    }
+
     /// Return how many clock tick interrupts have occurred
     /// since start.
-    pub fn sys_uptime(&self) -> Result<usize, ()> {
-        Ok(*self.kernel().ticks().lock() as usize)
+    pub fn sys_uptime(&self) -> Result<usize, KernelError> {
+        Ok(self.kernel().clocks().ticks() as usize)
     }
 
     /// Shutdowns this machine, discarding all unsaved data. No return.
-    pub fn sys_poweroff(&self) -> Result<usize, ()> {
+    /// Needs `Caps::SYS_BOOT`.
+    pub fn sys_poweroff(&self) -> Result<usize, KernelError> {
+        self.require_cap(Caps::SYS_BOOT)?;
         let exitcode = self.proc().argint(0)?;
         poweroff::machine_poweroff(exitcode as _);
     }
 
+    /// Copy the kernel's resource usage statistics into struct sysinfo.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_sysinfo(&mut self) -> Result<usize, KernelError> {
+        // user pointer to struct sysinfo
+        let addr = self.proc().argaddr(0)?;
+        let info = self.kernel().sysinfo();
+        self.proc_mut().memory_mut().copy_out(addr.into(), &info)?;
+        Ok(0)
+    }
+
     /// Return a new file descriptor referring to the same file as given fd.
-    /// Returns Ok(new file descriptor) on success, Err(()) on error.
-    pub fn sys_dup(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(new file descriptor) on success, Err(KernelError) on error.
+    pub fn sys_dup(&mut self) -> Result<usize, KernelError> {
         let (_, f) = self.proc().argfd(0)?;
         let newfile = f.clone();
         let fd = newfile.fdalloc(self)?;
         Ok(fd as usize)
     }
 
+    /// Get or set the flags of a file descriptor or its open file.
+    /// Returns Ok(the requested flags) for F_GETFD and F_GETFL, Ok(0) for F_SETFD and F_SETFL,
+    /// and Err(KernelError) on error.
+    pub fn sys_fcntl(&mut self) -> Result<usize, KernelError> {
+        let (fd, f) = self.proc().argfd(0)?;
+        let cmd = self.proc().argint(1)?;
+        let arg = self.proc().argint(2)?;
+        match cmd {
+            F_GETFD => {
+                let cloexec = self.proc().deref_data().fds.cloexec(fd as usize);
+                Ok(if cloexec { FD_CLOEXEC as usize } else { 0 })
+            }
+            F_SETFD => {
+                self.proc_mut()
+                    .deref_mut_data()
+                    .fds
+                    .set_cloexec(fd as usize, arg & FD_CLOEXEC != 0);
+                Ok(0)
+            }
+            F_GETFL => Ok(f.status_flags().bits() as usize),
+            F_SETFL => {
+                f.set_status_flags(FcntlFlags::from_bits_truncate(arg));
+                Ok(0)
+            }
+            F_GETPIPE_SZ | F_SETPIPE_SZ => {
+                let pipe = match &f.typ {
+                    FileType::Pipe { pipe } => pipe,
+                    _ => return Err(KernelError::InvalidArgument),
+                };
+                if cmd == F_GETPIPE_SZ {
+                    Ok(pipe.capacity())
+                } else if arg < 0 {
+                    Err(KernelError::InvalidArgument)
+                } else {
+                    pipe.resize(arg as usize, self)
+                }
+            }
+            _ => Err(KernelError::InvalidArgument),
+        }
+    }
+
+    /// Tune the readahead of the open file of a file descriptor, or drop its cached blocks.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_fadvise(&mut self) -> Result<usize, KernelError> {
+        let (_, f) = self.proc().argfd(0)?;
+        let off = self.proc().argint(1)?;
+        let len = self.proc().argint(2)?;
+        let advice = self.proc().argint(3)?;
+        if off < 0 || len < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        f.fadvise(off as u32, len as u32, advice, self)
+    }
+
+    /// Send up to count bytes of a file to a pipe or a socket, without copying them through
+    /// user memory. If offset is not null, start at *offset and advance it instead of the
+    /// file offset.
+    /// Returns Ok(number of bytes sent) on success, Err(KernelError) on error.
+    pub fn sys_sendfile(&mut self) -> Result<usize, KernelError> {
+        let (_, out) = self.proc().argfd(0)?;
+        let (_, from) = self.proc().argfd(1)?;
+        let offp = self.proc().argaddr(2)?;
+        let count = self.proc().argint(3)?;
+        if count < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        // SAFETY: sendfile will not access proc's fds.
+        let (out, from) = unsafe { (&*(out as *const RcFile), &*(from as *const RcFile)) };
+        if offp == 0 {
+            return out.sendfile(from, None, count as usize, self);
+        }
+        let mut off: u32 = 0;
+        // SAFETY: u32 does not have internal structure.
+        unsafe { self.proc_mut().memory_mut().copy_in(&mut off, offp.into()) }?;
+        let res = out.sendfile(from, Some(&mut off), count as usize, self);
+        self.proc_mut().memory_mut().copy_out(offp.into(), &off)?;
+        res
+    }
+
+    /// Move up to len bytes between a pipe and another pipe or a file, without copying them
+    /// through user memory. If the offset of the file is not null, use and advance it instead of
+    /// the file offset.
+    /// Returns Ok(number of bytes moved) on success, Err(KernelError) on error.
+    pub fn sys_splice(&mut self) -> Result<usize, KernelError> {
+        let (_, from) = self.proc().argfd(0)?;
+        let off_inp = self.proc().argaddr(1)?;
+        let (_, out) = self.proc().argfd(2)?;
+        let off_outp = self.proc().argaddr(3)?;
+        let len = self.proc().argint(4)?;
+        if len < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        // SAFETY: splice will not access proc's fds.
+        let (from, out) = unsafe { (&*(from as *const RcFile), &*(out as *const RcFile)) };
+        let (mut off_in, mut off_out): (u32, u32) = (0, 0);
+        if off_inp != 0 {
+            // SAFETY: u32 does not have internal structure.
+            unsafe {
+                self.proc_mut()
+                    .memory_mut()
+                    .copy_in(&mut off_in, off_inp.into())
+            }?;
+        }
+        if off_outp != 0 {
+            // SAFETY: u32 does not have internal structure.
+            unsafe {
+                self.proc_mut()
+                    .memory_mut()
+                    .copy_in(&mut off_out, off_outp.into())
+            }?;
+        }
+        let res = from.splice(
+            out,
+            (off_inp != 0).then(|| &mut off_in),
+            (off_outp != 0).then(|| &mut off_out),
+            len as usize,
+            self,
+        );
+        if off_inp != 0 {
+            self.proc_mut()
+                .memory_mut()
+                .copy_out(off_inp.into(), &off_in)?;
+        }
+        if off_outp != 0 {
+            self.proc_mut()
+                .memory_mut()
+                .copy_out(off_outp.into(), &off_out)?;
+        }
+        res
+    }
+
+    /// Map the submission and completion rings into the process.
+    /// Returns Ok(their address) on success, Err(KernelError) on error.
+    pub fn sys_ioring_setup(&mut self) -> Result<usize, KernelError> {
+        self.ioring_setup()
+    }
+
+    /// Run up to to_submit operations queued in the submission ring.
+    /// Returns Ok(number of operations run) on success, Err(KernelError) on error.
+    pub fn sys_ioring_enter(&mut self) -> Result<usize, KernelError> {
+        let to_submit = self.proc().argint(0)?;
+        if to_submit < 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        self.ioring_enter(to_submit as usize)
+    }
+
+    /// Send a control request to the device of a file descriptor.
+    /// Returns Ok(request-specific value) on success, Err(KernelError) on error.
+    pub fn sys_ioctl(&mut self) -> Result<usize, KernelError> {
+        let (_, f) = self.proc().argfd(0)?;
+        let cmd = self.proc().argint(1)?;
+        let arg = self.proc().argaddr(2)?;
+        // SAFETY: ioctl will not access proc's fds.
+        unsafe { (*(f as *const RcFile)).ioctl(cmd as u32, IoctlArg::new(arg), self) }
+    }
+
+    /// Wait until one of the given file descriptors is ready, or until the timeout in ticks.
+    /// Returns Ok(number of ready file descriptors) on success, Err(KernelError) on error.
+    pub fn sys_poll(&mut self) -> Result<usize, KernelError> {
+        // user pointer to array of struct pollfd
+        let fds = self.proc().argaddr(0)?;
+        let nfds = self.proc().argint(1)?;
+        let timeout = self.proc().argint(2)?;
+        self.poll(fds.into(), nfds as usize, timeout)
+    }
+
     /// Read n bytes into buf.
-    /// Returns Ok(number read) on success, Err(()) on error.
-    pub fn sys_read(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(number read) on success, Err(KernelError) on error.
+    pub fn sys_read(&mut self) -> Result<usize, KernelError> {
         let (_, f) = self.proc().argfd(0)?;
         let n = self.proc().argint(2)?;
         let p = self.proc().argaddr(1)?;
-        // SAFETY: read will not access proc's open_files.
+        // SAFETY: read will not access proc's fds.
         unsafe { (*(f as *const RcFile)).read(p.into(), n, self) }
     }
 
     /// Write n bytes from buf to given file descriptor fd.
-    /// Returns Ok(n) on success, Err(()) on error.
-    pub fn sys_write(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(n) on success, Err(KernelError) on error.
+    pub fn sys_write(&mut self) -> Result<usize, KernelError> {
         let (_, f) = self.proc().argfd(0)?;
         let n = self.proc().argint(2)?;
         let p = self.proc().argaddr(1)?;
-        // SAFETY: write will not access proc's open_files.
+        // SAFETY: write will not access proc's fds.
         unsafe { (*(f as *const RcFile)).write(p.into(), n, self) }
     }
 
     /// Release open file fd.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_close(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_close(&mut self) -> Result<usize, KernelError> {
         let (fd, _) = self.proc().argfd(0)?;
-        if let Some(f) = self.proc_mut().deref_mut_data().open_files[fd as usize].take() {
+        if let Some(f) = self.proc_mut().deref_mut_data().fds.take(fd as usize) {
             f.free(self);
         }
         Ok(0)
     }
 
     /// Place info about an open file into struct stat.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_fstat(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_fstat(&mut self) -> Result<usize, KernelError> {
         let (_, f) = self.proc().argfd(0)?;
         // user pointer to struct stat
         let st = self.proc().argaddr(1)?;
-        // SAFETY: stat will not access proc's open_files.
+        // SAFETY: stat will not access proc's fds.
         unsafe { (*(f as *const RcFile)).stat(st.into(), self) }?;
         Ok(0)
     }
 
     /// Create the path new as a link to the same inode as old.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_link(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_link(&mut self) -> Result<usize, KernelError> {
         let mut new: [u8; MAXPATH] = [0; MAXPATH];
         let mut old: [u8; MAXPATH] = [0; MAXPATH];
         let old = Path::new(self.proc_mut().argstr(0, &mut old)?);
         let new = Path::new(self.proc_mut().argstr(1, &mut new)?);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = try {
-            let inode = self.kernel().fs().namei(old, &tx, self)?;
-            let _ = self.kernel().fs().link(inode, new, &tx, self)?;
-            0
-        };
-        tx.end(self);
-        res
+        with_fs!(self.kernel().fs(), fs => {
+            let tx = fs.as_pin().get_ref().begin_tx(self);
+            let res = try {
+                let inode = fs.namei(old, &tx, self)?;
+                let _ = fs.link(inode, new, &tx, self)?;
+                0
+            };
+            tx.end(self);
+            res
+        })
     }
 
     /// Remove a file.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_unlink(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_unlink(&mut self) -> Result<usize, KernelError> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self.kernel().fs().unlink(path, &tx, self).map(|_| 0);
-        tx.end(self);
-        res
+        with_fs!(self.kernel().fs(), fs => {
+            let tx = fs.as_pin().get_ref().begin_tx(self);
+            let res = fs.unlink(path, &tx, self).map(|_| 0);
+            tx.end(self);
+            res
+        })
     }
 
     /// Open a file.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_open(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_open(&mut self) -> Result<usize, KernelError> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let omode = self.proc().argint(1)?;
         let omode = FcntlFlags::from_bits_truncate(omode);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self.kernel().fs().open(path, omode, &tx, self);
-        tx.end(self);
-        res
+        if let Some(res) = self.open_proc(path, omode) {
+            return res;
+        }
+        with_fs!(self.kernel().fs(), fs => {
+            let tx = fs.as_pin().get_ref().begin_tx(self);
+            let res = fs.open(path, omode, &tx, self);
+            tx.end(self);
+            res
+        })
     }
 
     /// Create a new directory.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_mkdir(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_mkdir(&mut self) -> Result<usize, KernelError> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self
-            .kernel()
-            .fs()
-            .create(path, InodeType::Dir, &tx, self, |_| ())
-            .map(|(ptr, _)| {
-                ptr.free((&tx, self));
-                0
-            });
-        tx.end(self);
-        res
+        with_fs!(self.kernel().fs(), fs => {
+            let tx = fs.as_pin().get_ref().begin_tx(self);
+            let res = fs
+                .create(path, InodeType::Dir, &tx, self, |_, _| ())
+                .map(|(ptr, _)| {
+                    ptr.free((&tx, self));
+                    0
+                });
+            tx.end(self);
+            res
+        })
     }
 
-    /// Create a new device file.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_mknod(&mut self) -> Result<usize, ()> {
+    /// Create a new device file, with the mode that its device gives. Needs `Caps::SYS_ADMIN`.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_mknod(&mut self) -> Result<usize, KernelError> {
+        self.require_cap(Caps::SYS_ADMIN)?;
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let major = self.proc().argint(1)? as u16;
         let minor = self.proc().argint(2)? as u16;
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = self
-            .kernel()
-            .fs()
-            .create(path, InodeType::Device { major, minor }, &tx, self, |_| ())
-            .map(|(ptr, _)| {
-                ptr.free((&tx, self));
-                0
-            });
-        tx.end(self);
-        res
+        with_fs!(self.kernel().fs(), fs => {
+            let tx = fs.as_pin().get_ref().begin_tx(self);
+            let res = fs
+                .create(path, InodeType::Device { major, minor }, &tx, self, |_, _| ())
+                .map(|(ptr, _)| {
+                    ptr.free((&tx, self));
+                    0
+                });
+            tx.end(self);
+            res
+        })
     }
 
     /// Change the current directory.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_chdir(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_chdir(&mut self) -> Result<usize, KernelError> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
-        let tx = self.kernel().fs().as_pin().get_ref().begin_tx(self);
-        let res = try {
-            let inode = self.kernel().fs().namei(path, &tx, self)?;
-            let _ = self.kernel().fs().chdir(inode, &tx, self)?;
-            0
-        };
-        tx.end(self);
-        res
+        with_fs!(self.kernel().fs(), fs => {
+            let tx = fs.as_pin().get_ref().begin_tx(self);
+            let res = try {
+                let inode = fs.namei(path, &tx, self)?;
+                let _ = fs.chdir(inode, &tx, self)?;
+                0
+            };
+            tx.end(self);
+            res
+        })
+    }
+
+    /// Copy the absolute path of the current directory, terminated by NUL, to a user buffer.
+    /// Returns Ok(length of the path) on success, Err(KernelError) on error.
+    pub fn sys_getcwd(&mut self) -> Result<usize, KernelError> {
+        let addr: UVAddr = self.proc().argaddr(0)?.into();
+        let size = self.proc().argint(1)?;
+        let mut path = [0; MAXPATH];
+        let len = with_fs!(self.kernel().fs(), fs => {
+            let tx = fs.as_pin().get_ref().begin_tx(self);
+            // Leave room for the NUL terminator.
+            let res = fs.getcwd(&mut path[..MAXPATH - 1], &tx, self);
+            tx.end(self);
+            res
+        })?;
+        if len >= size as usize {
+            return Err(KernelError::Range);
+        }
+        self.proc_mut()
+            .memory_mut()
+            .copy_out_bytes(addr, &path[..len + 1])?;
+        Ok(len)
+    }
+
+    /// Change the root directory of the current process. Needs `Caps::SYS_ADMIN`.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_chroot(&mut self) -> Result<usize, KernelError> {
+        self.require_cap(Caps::SYS_ADMIN)?;
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        with_fs!(self.kernel().fs(), fs => {
+            let tx = fs.as_pin().get_ref().begin_tx(self);
+            let res = try {
+                let inode = fs.namei(path, &tx, self)?;
+                fs.chroot(inode, &tx, self)?;
+                0
+            };
+            tx.end(self);
+            res
+        })
+    }
+
+    /// Change the permission bits of a file.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_chmod(&mut self) -> Result<usize, KernelError> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let mode = self.proc().argint(1)?;
+        with_fs!(self.kernel().fs(), fs => {
+            let tx = fs.as_pin().get_ref().begin_tx(self);
+            let res = try {
+                let inode = fs.namei(path, &tx, self)?;
+                fs.chmod(inode, mode as u16, &tx, self)?;
+                0
+            };
+            tx.end(self);
+            res
+        })
+    }
+
+    /// Change the owner and the group of a file.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_chown(&mut self) -> Result<usize, KernelError> {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
+        let uid = self.proc().argid(1)?;
+        let gid = self.proc().argid(2)?;
+        with_fs!(self.kernel().fs(), fs => {
+            let tx = fs.as_pin().get_ref().begin_tx(self);
+            let res = try {
+                let inode = fs.namei(path, &tx, self)?;
+                fs.chown(inode, uid, gid, &tx, self)?;
+                0
+            };
+            tx.end(self);
+            res
+        })
+    }
+
+    /// Read or set the disk quota of a user ID, with Q_GETQUOTA or Q_SETQUOTA.
+    /// Setting quotas, or reading those of other users, needs `Caps::SYS_ADMIN`.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_quotactl(&mut self) -> Result<usize, KernelError> {
+        let cmd = self.proc().argint(0)?;
+        let uid = self.proc().argid(1)?;
+        let addr = self.proc().argaddr(2)?;
+        match cmd {
+            Q_GETQUOTA => {
+                if uid as u32 != self.proc().deref_data().cred.euid() {
+                    self.require_cap(Caps::SYS_ADMIN)?;
+                }
+                let dqblk = self.kernel().fs().ufs()?.get_quota(uid, self);
+                self.proc_mut().memory_mut().copy_out(addr.into(), &dqblk)?;
+            }
+            Q_SETQUOTA => {
+                self.require_cap(Caps::SYS_ADMIN)?;
+                let mut dqblk = Dqblk::default();
+                // SAFETY: Dqblk does not have internal structure.
+                unsafe {
+                    self.proc_mut()
+                        .memory_mut()
+                        .copy_in(&mut dqblk, addr.into())
+                }?;
+                self.kernel().fs().ufs()?.set_quota(uid, &dqblk, self)?;
+            }
+            _ => return Err(KernelError::InvalidArgument),
+        }
+        Ok(0)
     }
 
     /// Load a file and execute it with arguments.
-    /// Returns Ok(argc argument to user main) on success, Err(()) on error.
-    pub fn sys_exec(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(argc argument to user main) on success, Err(KernelError) on error.
+    pub fn sys_exec(&mut self) -> Result<usize, KernelError> {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let mut args = ArrayVec::<Page, MAXARG>::new();
         let path = Path::new(self.proc_mut().argstr(0, &mut path)?);
         let uargv = self.proc().argaddr(1)?;
         let allocator = hal().kmem();
 
-        let mut success = false;
+        let mut res = Err(KernelError::ArgListTooLong);
         for i in 0..MAXARG {
-            let uarg = ok_or!(
-                self.proc_mut()
-                    .fetchaddr((uargv + mem::size_of::<usize>() * i).into()),
-                break
-            );
+            let uarg = match uargv
+                .checked_add(mem::size_of::<usize>() * i)
+                .ok_or(KernelError::Fault)
+                .and_then(|addr| self.proc_mut().fetchaddr(addr.into()))
+            {
+                Ok(uarg) => uarg,
+                Err(e) => {
+                    res = Err(e);
+                    break;
+                }
+            };
 
             if uarg == 0 {
-                success = true;
+                res = Ok(());
                 break;
             }
 
-            let mut page = some_or!(allocator.alloc(), break);
-            if self
-                .proc_mut()
-                .fetchstr(uarg.into(), &mut page[..])
-                .is_err()
-            {
+            let mut page = some_or!(allocator.alloc(), {
+                res = Err(KernelError::NoMemory);
+                break;
+            });
+            if let Err(e) = self.proc_mut().fetchstr(uarg.into(), &mut page[..]) {
                 allocator.free(page);
+                res = Err(e);
                 break;
             }
             args.push(page);
         }
 
-        let ret = if success {
-            self.exec(path, &args)
-        } else {
-            Err(())
-        };
+        let ret = res.and_then(|_| self.exec(path, &args));
 
         for page in args.drain(..) {
             allocator.free(page);
@@ -391,11 +1182,110 @@ impl KernelCtx<'_, '_> {
     }
 
     /// Create a pipe.
-    /// Returns Ok(0) on success, Err(()) on error.
-    pub fn sys_pipe(&mut self) -> Result<usize, ()> {
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_pipe(&mut self) -> Result<usize, KernelError> {
+        // user pointer to array of two integers
+        let fdarray = self.proc().argaddr(0)?.into();
+        self.pipe(fdarray, FcntlFlags::empty())?;
+        Ok(0)
+    }
+
+    /// Create an event counter file.
+    /// Returns Ok(file descriptor) on success, Err(KernelError) on error.
+    pub fn sys_eventfd(&mut self) -> Result<usize, KernelError> {
+        let count = self.proc().argint(0)?;
+        let flags = self.proc().argint(1)?;
+        self.eventfd(count as u32, flags)
+    }
+
+    /// Create a file descriptor that becomes readable when a process exits.
+    /// Returns Ok(new file descriptor) on success, Err(KernelError) on error.
+    pub fn sys_pidfd_open(&mut self) -> Result<usize, KernelError> {
+        let pid = self.proc().argint(0)?;
+        let flags = self.proc().argint(1)?;
+        self.pidfd_open(pid, flags)
+    }
+
+    /// Create a pipe with O_NONBLOCK and O_CLOEXEC flags.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_pipe2(&mut self) -> Result<usize, KernelError> {
         // user pointer to array of two integers
         let fdarray = self.proc().argaddr(0)?.into();
-        self.pipe(fdarray)?;
+        let flags = self.proc().argint(1)?;
+        let flags = FcntlFlags::from_bits(flags).ok_or(KernelError::InvalidArgument)?;
+        self.pipe(fdarray, flags)?;
+        Ok(0)
+    }
+
+    /// Create a disarmed timer file.
+    /// Returns Ok(file descriptor) on success, Err(KernelError) on error.
+    pub fn sys_timerfd_create(&mut self) -> Result<usize, KernelError> {
+        let flags = self.proc().argint(0)?;
+        self.timerfd_create(flags)
+    }
+
+    /// Arm or disarm a timer file.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_timerfd_settime(&mut self) -> Result<usize, KernelError> {
+        let fd = self.proc().argint(0)?;
+        let flags = self.proc().argint(1)?;
+        let new = self.proc().argaddr(2)?.into();
+        let old = self.proc().argaddr(3)?.into();
+        self.timerfd_settime(fd, flags, new, old)?;
+        Ok(0)
+    }
+
+    /// Get the setting of a timer file.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_timerfd_gettime(&mut self) -> Result<usize, KernelError> {
+        let fd = self.proc().argint(0)?;
+        let cur = self.proc().argaddr(1)?.into();
+        self.timerfd_gettime(fd, cur)?;
+        Ok(0)
+    }
+
+    /// Create a socket.
+    /// Returns Ok(file descriptor) on success, Err(KernelError) on error.
+    pub fn sys_socket(&mut self) -> Result<usize, KernelError> {
+        let domain = self.proc().argint(0)?;
+        let typ = self.proc().argint(1)?;
+        let protocol = self.proc().argint(2)?;
+        self.socket(domain, typ, protocol)
+    }
+
+    /// Bind a socket to the address at the user pointer.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_bind(&mut self) -> Result<usize, KernelError> {
+        let (socket, _) = self.proc().argsocket(0)?;
+        let addr = self.proc().argaddr(1)?.into();
+        self.bind(socket, addr)?;
+        Ok(0)
+    }
+
+    /// Make a bound socket listen for connections.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_listen(&mut self) -> Result<usize, KernelError> {
+        let (socket, _) = self.proc().argsocket(0)?;
+        let backlog = self.proc().argint(1)?;
+        self.listen(socket, backlog)?;
+        Ok(0)
+    }
+
+    /// Take a connection to a listening socket, and copy the peer's address to the user
+    /// pointer, unless it is null.
+    /// Returns Ok(file descriptor) on success, Err(KernelError) on error.
+    pub fn sys_accept(&mut self) -> Result<usize, KernelError> {
+        let (socket, nonblock) = self.proc().argsocket(0)?;
+        let addr = self.proc().argaddr(1)?.into();
+        self.accept(socket, addr, nonblock)
+    }
+
+    /// Connect a socket to the address at the user pointer.
+    /// Returns Ok(0) on success, Err(KernelError) on error.
+    pub fn sys_connect(&mut self) -> Result<usize, KernelError> {
+        let (socket, _) = self.proc().argsocket(0)?;
+        let addr = self.proc().argaddr(1)?.into();
+        self.connect(socket, addr)?;
         Ok(0)
     }
 }
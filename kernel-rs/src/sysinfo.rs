@@ -0,0 +1,66 @@
+//! System information reported to user programs.
+
+use zerocopy::AsBytes;
+
+use crate::{
+    arena::{Arena, ArenaStats},
+    kernel::KernelRef,
+    with_fs,
+};
+
+/// Usage statistics of a kernel arena, as reported to user programs.
+#[derive(Copy, Clone, AsBytes)]
+#[repr(C)]
+pub struct ArenaInfo {
+    /// The number of entries of the arena.
+    pub capacity: u32,
+
+    /// The number of entries that are currently allocated.
+    pub in_use: u32,
+
+    /// The maximum number of entries that have ever been allocated at once.
+    pub high_water: u32,
+
+    /// The number of allocations that failed because the arena was full.
+    pub failed: u32,
+}
+
+impl From<ArenaStats> for ArenaInfo {
+    fn from(stats: ArenaStats) -> Self {
+        Self {
+            capacity: stats.capacity as u32,
+            in_use: stats.in_use as u32,
+            high_water: stats.high_water as u32,
+            failed: stats.failed as u32,
+        }
+    }
+}
+
+/// `struct sysinfo` of user programs.
+#[derive(Copy, Clone, AsBytes)]
+#[repr(C)]
+pub struct SysInfo {
+    /// The open file table.
+    pub ftable: ArenaInfo,
+
+    /// The in-memory inode table.
+    pub itable: ArenaInfo,
+
+    /// The buffer cache.
+    pub bcache: ArenaInfo,
+
+    /// The pipe table.
+    pub pipes: ArenaInfo,
+}
+
+impl KernelRef<'_, '_> {
+    /// Returns a snapshot of the kernel's resource usage.
+    pub fn sysinfo(&self) -> SysInfo {
+        SysInfo {
+            ftable: self.ftable().stats().into(),
+            itable: with_fs!(self.fs(), fs => fs.itable_stats()).into(),
+            bcache: self.bcache().stats().into(),
+            pipes: self.pipes().stats().into(),
+        }
+    }
+}
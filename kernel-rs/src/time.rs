@@ -0,0 +1,295 @@
+//! Timekeeping: the clock tick, and the monotonic and realtime clocks.
+//!
+//! Time is read from the `time` CSR, which counts cycles at a fixed frequency from boot, so it
+//! has a resolution of 100ns on QEMU. The monotonic clock, which `ktime_now` reads, is that
+//! counter converted to nanoseconds, and the realtime clock adds the wall-clock time of boot,
+//! which is read from the RTC once.
+//!
+//! The superuser can set the realtime clock, or slew it by a small adjustment, which is applied
+//! gradually at `SLEW_RATIO` so that the clock never jumps or goes backwards. Either way, the
+//! monotonic clock is unaffected, and the new time is written back to the RTC.
+//!
+//! The frequency of the counter is calibrated at boot against the RTC, which counts
+//! nanoseconds, so that the monotonic clock does not drift from the realtime clock.
+//!
+//! The clock tick comes every `TICK_NS` and advances the tick count, which is what uptime(),
+//! poll() timeouts, and timer files count in. It is a kernel timer; see `timer`.
+
+use core::{
+    cmp,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use rv6_abi::{
+    time::{Timespec, CLOCK_MONOTONIC, CLOCK_REALTIME},
+    vdso::{VdsoData, VDSO_SLEW_RATIO},
+};
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::addr::{Addr, UVAddr},
+    arch::{
+        riscv::r_time,
+        rtc::{rtc_read, rtc_write},
+    },
+    audit::AuditEvent,
+    cred::Caps,
+    error::KernelError,
+    lock::SpinLock,
+    proc::KernelCtx,
+};
+
+/// Nominal frequency of the `time` CSR, in Hz. QEMU's virt machine counts at 10MHz.
+const NOMINAL_FREQ: u64 = 10_000_000;
+
+/// How long the calibration measures the `time` CSR, in nanoseconds.
+const CALIBRATION_NS: u64 = 50_000_000;
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// A slew changes the realtime clock by at most 1ns every `SLEW_RATIO` ns, or 500ppm. Processes
+/// apply it themselves when they read the clock from the shared page.
+const SLEW_RATIO: u64 = VDSO_SLEW_RATIO;
+
+/// Length of a clock tick, in nanoseconds.
+pub const TICK_NS: u64 = NSEC_PER_SEC / 10;
+
+/// Frequency of the `time` CSR, in Hz, as calibrated.
+static TIMEBASE_FREQ: AtomicU64 = AtomicU64::new(NOMINAL_FREQ);
+
+/// Returns the time since boot, in nanoseconds.
+pub fn ktime_now() -> u64 {
+    cycles_to_ns(r_time())
+}
+
+/// Converts a number of cycles of the `time` CSR to nanoseconds.
+pub fn cycles_to_ns(cycles: u64) -> u64 {
+    let freq = TIMEBASE_FREQ.load(Ordering::Relaxed);
+    // Split the conversion so that it does not overflow.
+    cycles / freq * NSEC_PER_SEC + cycles % freq * NSEC_PER_SEC / freq
+}
+
+/// Converts nanoseconds to a number of cycles of the `time` CSR, rounding up.
+pub fn ns_to_cycles(nanos: u64) -> u64 {
+    let freq = TIMEBASE_FREQ.load(Ordering::Relaxed);
+    let rem = nanos % NSEC_PER_SEC * freq;
+    nanos / NSEC_PER_SEC * freq + (rem + NSEC_PER_SEC - 1) / NSEC_PER_SEC
+}
+
+/// Measures the frequency of the `time` CSR against the RTC, for `CALIBRATION_NS`.
+/// Returns the frequency in Hz, rounded to kHz, or `NOMINAL_FREQ` if the RTC does not advance.
+fn calibrate() -> u64 {
+    let (rtc0, cycles0) = (rtc_read(), r_time());
+    loop {
+        let (rtc1, cycles1) = (rtc_read(), r_time());
+        let (elapsed, cycles) = (rtc1.saturating_sub(rtc0), cycles1 - cycles0);
+        if elapsed >= CALIBRATION_NS {
+            let freq = cycles * NSEC_PER_SEC / elapsed;
+            return (freq + 500) / 1000 * 1000;
+        }
+        // Give up if the nominal frequency is far off, or the RTC is broken.
+        if cycles > NOMINAL_FREQ {
+            return NOMINAL_FREQ;
+        }
+    }
+}
+
+/// The realtime clock, as an offset from the monotonic clock.
+struct Realtime {
+    /// Wall-clock time when the monotonic clock was zero, in nanoseconds since the Unix epoch,
+    /// not counting `slew`.
+    offset: u64,
+
+    /// Adjustment to `offset` that is being applied since the monotonic time `slew_start`.
+    slew: i64,
+    slew_start: u64,
+}
+
+impl Realtime {
+    /// Returns the part of `slew` applied by the monotonic time `now`.
+    fn slewed(&self, now: u64) -> i64 {
+        let max = (now.saturating_sub(self.slew_start) / SLEW_RATIO) as i64;
+        cmp::min(cmp::max(self.slew, -max), max)
+    }
+
+    /// Returns the wall-clock time at the monotonic time `now`.
+    fn at(&self, now: u64) -> u64 {
+        self.offset
+            .wrapping_add(now)
+            .wrapping_add(self.slewed(now) as u64)
+    }
+
+    /// Applies the part of the current slew done by the monotonic time `now`, and starts slewing
+    /// by `slew` from then. Returns the part of the previous slew that was not applied.
+    fn restart_slew(&mut self, now: u64, slew: i64) -> i64 {
+        let slewed = self.slewed(now);
+        self.offset = self.offset.wrapping_add(slewed as u64);
+        let left = self.slew - slewed;
+        self.slew = slew;
+        self.slew_start = now;
+        left
+    }
+}
+
+/// The kernel's clocks.
+pub struct Clocks {
+    /// Number of clock ticks since boot. Tick n comes when the monotonic clock reaches
+    /// n * `TICK_NS`.
+    ticks: AtomicU64,
+
+    realtime: SpinLock<Realtime>,
+
+    /// Changes each time `realtime` does, while its lock is held.
+    realtime_seq: AtomicU64,
+}
+
+impl Clocks {
+    pub const fn new() -> Self {
+        Self {
+            ticks: AtomicU64::new(0),
+            realtime: SpinLock::new(
+                "realtime",
+                Realtime {
+                    offset: 0,
+                    slew: 0,
+                    slew_start: 0,
+                },
+            ),
+            realtime_seq: AtomicU64::new(1),
+        }
+    }
+
+    /// Calibrates the `time` CSR, and reads the wall-clock time of boot from the RTC.
+    /// Must be called before anything uses the monotonic clock.
+    pub fn init(&self) {
+        TIMEBASE_FREQ.store(calibrate(), Ordering::Relaxed);
+        let mut realtime = self.realtime.lock();
+        realtime.offset = rtc_read().saturating_sub(ktime_now());
+        let _ = self.realtime_seq.fetch_add(1, Ordering::Release);
+    }
+
+    /// Returns the number of clock ticks since boot.
+    pub fn ticks(&self) -> u64 {
+        self.ticks.load(Ordering::Acquire)
+    }
+
+    /// Advances the tick count to the monotonic time `now`. Called from the clock tick.
+    pub fn tick(&self, now: u64) {
+        self.ticks.store(now / TICK_NS, Ordering::Release);
+    }
+
+    /// Returns the wall-clock time, in nanoseconds since the Unix epoch.
+    pub fn realtime_ns(&self) -> u64 {
+        self.monotonic_to_realtime(ktime_now())
+    }
+
+    /// Converts a time of the monotonic clock to that of the realtime clock.
+    pub fn monotonic_to_realtime(&self, nanos: u64) -> u64 {
+        self.realtime.lock().at(nanos)
+    }
+
+    /// Sets the wall-clock time to `nanos` since the Unix epoch, canceling any slew.
+    /// Returns the change of the wall-clock time.
+    fn set_realtime(&self, nanos: u64) -> i64 {
+        let mut realtime = self.realtime.lock();
+        let now = ktime_now();
+        let old = realtime.at(now);
+        let _ = realtime.restart_slew(now, 0);
+        realtime.offset = nanos.wrapping_sub(now);
+        let _ = self.realtime_seq.fetch_add(1, Ordering::Release);
+        rtc_write(nanos);
+        nanos.wrapping_sub(old) as i64
+    }
+
+    /// Starts slewing the wall-clock time by `delta` nanoseconds, if it is `Some`, replacing the
+    /// current slew.
+    /// Returns the part of the current slew that was not applied yet.
+    fn adjust_realtime(&self, delta: Option<i64>) -> i64 {
+        let mut realtime = self.realtime.lock();
+        let now = ktime_now();
+        match delta {
+            Some(delta) => {
+                let left = realtime.restart_slew(now, delta);
+                let _ = self.realtime_seq.fetch_add(1, Ordering::Release);
+                rtc_write(realtime.at(now).wrapping_add(delta as u64));
+                left
+            }
+            None => realtime.slew - realtime.slewed(now),
+        }
+    }
+
+    /// Fills the clocks of a process's shared page. Copies the parameters of the realtime clock
+    /// only if they changed since `data` was last filled.
+    pub fn fill_vdso(&self, data: &mut VdsoData) {
+        data.ticks = self.ticks();
+        data.timebase_freq = TIMEBASE_FREQ.load(Ordering::Relaxed);
+        if data.realtime_seq != self.realtime_seq.load(Ordering::Acquire) {
+            let realtime = self.realtime.lock();
+            data.realtime_offset = realtime.offset;
+            data.slew = realtime.slew;
+            data.slew_start = realtime.slew_start;
+            data.realtime_seq = self.realtime_seq.load(Ordering::Relaxed);
+        }
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Copy the time of the clock `clock` to the `struct timespec` at `addr`.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn clock_gettime(&mut self, clock: i32, addr: UVAddr) -> Result<(), KernelError> {
+        let clocks = self.kernel().clocks();
+        let nanos = match clock {
+            CLOCK_REALTIME => clocks.realtime_ns(),
+            CLOCK_MONOTONIC => ktime_now(),
+            _ => return Err(KernelError::InvalidArgument),
+        };
+        self.proc_mut()
+            .memory_mut()
+            .copy_out(addr, &Timespec::from_nanos(nanos))
+    }
+
+    /// Set the clock `clock` to the time in the `struct timespec` at `addr`.
+    /// Only the realtime clock can be set, and only with `Caps::SYS_TIME`.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn clock_settime(&mut self, clock: i32, addr: UVAddr) -> Result<(), KernelError> {
+        self.require_cap(Caps::SYS_TIME)?;
+        if clock != CLOCK_REALTIME {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut spec = Timespec::default();
+        self.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(spec.as_bytes_mut(), addr)?;
+        let nanos = spec.to_nanos().ok_or(KernelError::InvalidArgument)?;
+        let change = self.kernel().clocks().set_realtime(nanos);
+        self.audit(AuditEvent::SetTime, change as u64, None);
+        Ok(())
+    }
+
+    /// Slew the realtime clock by the duration in the `struct timespec` at `delta`, unless it is
+    /// null, and copy the part of the previous slew that was not applied yet to `olddelta`,
+    /// unless it is null. Slewing the clock needs `Caps::SYS_TIME`.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn adjtime(&mut self, delta: UVAddr, olddelta: UVAddr) -> Result<(), KernelError> {
+        let delta = if delta.is_null() {
+            None
+        } else {
+            self.require_cap(Caps::SYS_TIME)?;
+            let mut spec = Timespec::default();
+            self.proc_mut()
+                .memory_mut()
+                .copy_in_bytes(spec.as_bytes_mut(), delta)?;
+            Some(spec.to_signed_nanos().ok_or(KernelError::InvalidArgument)?)
+        };
+        let left = self.kernel().clocks().adjust_realtime(delta);
+        if let Some(delta) = delta {
+            self.audit(AuditEvent::SetTime, delta as u64, None);
+        }
+        if !olddelta.is_null() {
+            self.proc_mut()
+                .memory_mut()
+                .copy_out(olddelta, &Timespec::from_signed_nanos(left))?;
+        }
+        Ok(())
+    }
+}
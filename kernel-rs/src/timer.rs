@@ -0,0 +1,359 @@
+//! Kernel timers, which call a function from the timer interrupt once the monotonic clock has
+//! reached a given deadline.
+//!
+//! Each CPU has its own queue of armed timers, kept in a pairing heap ordered by their deadlines,
+//! and a timer is armed in the queue of the CPU that arms it. Instead of interrupting at a fixed
+//! period, a CPU programs its timer interrupt for the earliest deadline in its queue, so the
+//! interrupt only comes when a timer has expired.
+//!
+//! The clock tick, the balancing of device interrupts, and the averaging of the pressure stall
+//! information are timers of the first CPU. Each CPU also has a slice timer, which the scheduler
+//! arms when it runs a process, and the process is preempted when it expires, a profiling timer,
+//! which it arms along with the slice timer while the profiler is on, and a watchdog timer, which
+//! checks the other CPUs for as long as the CPU runs.
+
+use core::{
+    cmp,
+    pin::Pin,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use array_macro::array;
+use pin_project::pin_project;
+
+use crate::{
+    arch::memlayout::clint_mtimecmp,
+    cpu::cpuid,
+    error::KernelError,
+    hal::hal,
+    irq::{Irqs, BALANCE_NS},
+    kernel::KernelRef,
+    lock::SpinLock,
+    param::{NCPU, TIMESLICE_NS},
+    proc::{KernelCtx, WaitChannel},
+    profile::{Profiler, PROFILE_NS},
+    psi::{Psi, PSI_PERIOD_NS},
+    time::{ktime_now, ns_to_cycles, TICK_NS},
+    util::intrusive_heap::{Heap, HeapEntry, HeapNode},
+    watchdog::{Watchdog, CHECK_NS},
+};
+
+/// A timer's function, called from the timer interrupt with the current monotonic time when the
+/// timer expires. It is called while holding the lock of the timer's queue, so it must not use
+/// `TimerQueue`. Instead, it returns the deadline to rearm the timer at, or `None` to leave it
+/// disarmed. A deadline that is not in the future is moved to the next nanosecond.
+pub type TimerFn = fn(Pin<&Timer>, u64, KernelRef<'_, '_>) -> Option<u64>;
+
+/// A timer that can be armed in the kernel's `TimerQueue`.
+///
+/// # Safety
+///
+/// * `entry` is at the beginning of `Timer`.
+/// * `entry` is accessed only while holding the lock of the queue of `cpu`.
+/// * A `Timer` must be disarmed with `TimerQueue::cancel` before it is moved or dropped.
+#[repr(C)]
+#[pin_project]
+pub struct Timer {
+    #[pin]
+    entry: HeapEntry,
+
+    func: TimerFn,
+
+    /// The CPU in whose queue the timer was last armed. It changes only while holding the lock
+    /// of that CPU's queue.
+    cpu: AtomicUsize,
+}
+
+// SAFETY: `entry` is accessed only while holding the lock of a queue.
+unsafe impl Send for Timer {}
+
+// SAFETY: `entry` is accessed only while holding the lock of a queue.
+unsafe impl Sync for Timer {}
+
+// SAFETY: `Timer` owns a `HeapEntry`.
+unsafe impl HeapNode for Timer {
+    fn get_heap_entry(self: Pin<&Self>) -> Pin<&HeapEntry> {
+        self.project_ref().entry
+    }
+
+    fn from_heap_entry(heap_entry: *const HeapEntry) -> *const Self {
+        heap_entry as _
+    }
+}
+
+impl Timer {
+    /// Returns a disarmed `Timer` that calls `func` when it expires.
+    pub const fn new(func: TimerFn) -> Self {
+        Self {
+            entry: HeapEntry::new(),
+            func,
+            cpu: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// The armed timers of a CPU, and its slice timer.
+#[pin_project]
+struct CpuTimers {
+    /// Armed timers, keyed by their deadlines.
+    #[pin]
+    heap: SpinLock<Heap<Timer>>,
+
+    /// Expires when the process running on the CPU has used up its time slice.
+    #[pin]
+    slice: Timer,
+
+    /// Takes samples for the profiler.
+    #[pin]
+    profile: Timer,
+
+    /// Checks the heartbeats of the other CPUs.
+    #[pin]
+    watchdog: Timer,
+}
+
+// SAFETY: the timers in `heap` are `Sync`, and `heap` is accessed only while holding the lock.
+unsafe impl Sync for CpuTimers {}
+
+/// The kernel's armed timers.
+#[pin_project]
+pub struct TimerQueue {
+    cpus: [CpuTimers; NCPU],
+
+    /// The clock tick.
+    #[pin]
+    tick: Timer,
+
+    /// The balancing of device interrupts.
+    #[pin]
+    balance: Timer,
+
+    /// The averaging of the pressure stall information.
+    #[pin]
+    psi: Timer,
+}
+
+impl TimerQueue {
+    pub const fn new() -> Self {
+        Self {
+            cpus: array![_ => CpuTimers {
+                heap: SpinLock::new("timers", Heap::new()),
+                slice: Timer::new(|_, _, _| None),
+                profile: Timer::new(Profiler::sample),
+                watchdog: Timer::new(Watchdog::check),
+            }; NCPU],
+            tick: Timer::new(Self::tick),
+            balance: Timer::new(Irqs::rebalance),
+            psi: Timer::new(Psi::average),
+        }
+    }
+
+    fn cpu(self: Pin<&Self>, id: usize) -> Pin<&CpuTimers> {
+        // SAFETY: `self` is pinned, and so are its elements.
+        unsafe { Pin::new_unchecked(&self.get_ref().cpus[id]) }
+    }
+
+    /// Starts the clock tick, on this CPU.
+    pub fn start_tick(self: Pin<&Self>) {
+        let now = ktime_now();
+        self.arm(self.project_ref().tick, now - now % TICK_NS + TICK_NS);
+    }
+
+    /// The function of the clock tick, which comes every `TICK_NS`.
+    fn tick(_timer: Pin<&Timer>, now: u64, kernel: KernelRef<'_, '_>) -> Option<u64> {
+        kernel.clocks().tick(now);
+        kernel.poll_queue().tick(kernel);
+        hal().log().drain(kernel.as_ref());
+        Some(now - now % TICK_NS + TICK_NS)
+    }
+
+    /// Starts balancing the device interrupts, on this CPU.
+    pub fn start_balance(self: Pin<&Self>) {
+        self.arm(self.project_ref().balance, ktime_now() + BALANCE_NS);
+    }
+
+    /// Starts averaging the pressure stall information, on this CPU.
+    pub fn start_psi(self: Pin<&Self>) {
+        self.arm(self.project_ref().psi, ktime_now() + PSI_PERIOD_NS);
+    }
+
+    /// Starts the watchdog timer of this CPU.
+    pub fn start_watchdog(self: Pin<&Self>) {
+        let cpu = self.cpu(cpuid()).project_ref();
+        self.arm(cpu.watchdog, ktime_now() + CHECK_NS);
+    }
+
+    /// Starts a time slice on this CPU, for the process that is about to run.
+    /// Must be called with interrupts disabled.
+    pub fn start_slice(self: Pin<&Self>) {
+        let cpu = self.cpu(cpuid()).project_ref();
+        let now = ktime_now();
+        self.arm(cpu.slice, now + TIMESLICE_NS);
+        if hal().profiler().is_on() && self.deadline(cpu.profile).is_none() {
+            self.arm(cpu.profile, now + PROFILE_NS);
+        }
+    }
+
+    /// Arms `timer` in the queue of this CPU so that it expires when the monotonic clock reaches
+    /// `deadline`, after disarming it. A timer whose deadline already passed expires at once.
+    pub fn arm(self: Pin<&Self>, timer: Pin<&Timer>, deadline: u64) {
+        self.cancel(timer);
+        let intr = hal().cpus().push_off();
+        let id = cpuid();
+        {
+            let mut heap = self.cpu(id).project_ref().heap.pinned_lock();
+            let heap = heap.get_pin_mut().into_ref();
+            timer.cpu.store(id, Ordering::Release);
+            heap.insert(timer, deadline);
+            if heap.first_key() == Some(deadline) {
+                set_next_interrupt(deadline);
+            }
+        }
+        // SAFETY: interrupts were disabled by the `push_off` above.
+        unsafe { hal().cpus().pop_off(intr) };
+    }
+
+    /// Returns the earliest deadline of the timers of this CPU, or `None` if it has none armed.
+    pub fn next_deadline(self: Pin<&Self>) -> Option<u64> {
+        let intr = hal().cpus().push_off();
+        let deadline = self
+            .cpu(cpuid())
+            .project_ref()
+            .heap
+            .pinned_lock()
+            .get_pin_mut()
+            .into_ref()
+            .first_key();
+        // SAFETY: interrupts were disabled by the `push_off` above.
+        unsafe { hal().cpus().pop_off(intr) };
+        deadline
+    }
+
+    /// Locks the queue that `timer` was last armed in, and runs `f` with it.
+    fn with_queue<R>(self: Pin<&Self>, timer: Pin<&Timer>, f: impl FnOnce() -> R) -> R {
+        loop {
+            let id = timer.cpu.load(Ordering::Acquire);
+            let _heap = self.cpu(id).project_ref().heap.pinned_lock();
+            // The timer may have been armed in another queue before we took the lock.
+            if timer.cpu.load(Ordering::Acquire) == id {
+                return f();
+            }
+        }
+    }
+
+    /// Disarms `timer`, if it is armed.
+    /// Once this returns, the timer's function is not running and will not be called.
+    pub fn cancel(self: Pin<&Self>, timer: Pin<&Timer>) {
+        self.with_queue(timer, || timer.get_heap_entry().remove());
+    }
+
+    /// Returns the deadline of `timer`, or `None` if it is not armed.
+    pub fn deadline(self: Pin<&Self>, timer: Pin<&Timer>) -> Option<u64> {
+        self.with_queue(timer, || {
+            let entry = timer.get_heap_entry();
+            if entry.is_unlinked() {
+                None
+            } else {
+                Some(entry.key())
+            }
+        })
+    }
+
+    /// Calls the functions of the expired timers of this CPU, and programs the next timer
+    /// interrupt. Called from the timer interrupt.
+    /// Returns `true` if the time slice of this CPU is over.
+    pub fn interrupt(self: Pin<&Self>, kernel: KernelRef<'_, '_>) -> bool {
+        let cpu = self.cpu(cpuid()).project_ref();
+        let mut heap = cpu.heap.pinned_lock();
+        let heap = heap.get_pin_mut().into_ref();
+        let mut slice_over = false;
+        loop {
+            let now = ktime_now();
+            if heap.first_key().map_or(true, |deadline| deadline > now) {
+                break;
+            }
+            let timer = heap.pop().unwrap();
+            // SAFETY: `timer` was armed, so it is not moved or dropped until it is canceled,
+            // which needs the lock we hold.
+            let timer = unsafe { Pin::new_unchecked(&*timer) };
+            slice_over |= ptr::eq(timer.get_ref(), cpu.slice.get_ref());
+            if let Some(deadline) = (timer.func)(timer, now, kernel) {
+                heap.insert(timer, cmp::max(deadline, now + 1));
+            }
+        }
+        set_next_interrupt(heap.first_key().unwrap_or(u64::MAX));
+        slice_over
+    }
+}
+
+/// Programs the timer interrupt of this CPU to come when the monotonic clock reaches `deadline`.
+/// If it already passed, the interrupt comes at once.
+fn set_next_interrupt(deadline: u64) {
+    // SAFETY: the CLINT is identically mapped from physical address, and the register of this
+    // CPU is written only by this CPU, and by timervec in kernelvec.S, which cannot run while
+    // this CPU runs in supervisor mode.
+    unsafe { ptr::write_volatile(clint_mtimecmp(cpuid()) as *mut u64, ns_to_cycles(deadline)) };
+}
+
+/// A timer that wakes up a process sleeping for it.
+///
+/// # Safety
+///
+/// `timer` is at the beginning of `Alarm`.
+#[repr(C)]
+#[pin_project]
+struct Alarm {
+    #[pin]
+    timer: Timer,
+
+    /// Whether the timer has expired.
+    expired: SpinLock<bool>,
+
+    waitchannel: WaitChannel,
+}
+
+impl Alarm {
+    const fn new() -> Self {
+        Self {
+            timer: Timer::new(Self::expire),
+            expired: SpinLock::new("alarm", false),
+            waitchannel: WaitChannel::new(),
+        }
+    }
+
+    /// The function of `self.timer`.
+    fn expire(timer: Pin<&Timer>, _now: u64, kernel: KernelRef<'_, '_>) -> Option<u64> {
+        // SAFETY: the only `Timer`s that use this function are inside an `Alarm`, at its
+        // beginning.
+        let this = unsafe { &*(timer.get_ref() as *const _ as *const Self) };
+        *this.expired.lock() = true;
+        this.waitchannel.wakeup(kernel);
+        None
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Sleep until the monotonic clock reaches `deadline` nanoseconds.
+    /// Returns Ok(()) on success, Err(KernelError::Interrupted) if the process was killed.
+    pub fn sleep_until(&self, deadline: u64) -> Result<(), KernelError> {
+        let alarm = Alarm::new();
+        // SAFETY: `alarm` is not moved, and its timer is canceled before it is dropped.
+        let alarm = unsafe { Pin::new_unchecked(&alarm) };
+        let timers = self.kernel().timers();
+        timers.arm(alarm.project_ref().timer, deadline);
+        let mut expired = alarm.expired.lock();
+        let res = loop {
+            if *expired {
+                break Ok(());
+            }
+            if self.proc().killed() {
+                break Err(KernelError::Interrupted);
+            }
+            alarm.waitchannel.sleep(&mut expired, self);
+        };
+        drop(expired);
+        timers.cancel(alarm.project_ref().timer);
+        res
+    }
+}
@@ -0,0 +1,269 @@
+//! Timers that user programs arm and wait for through file descriptors.
+//!
+//! A timer file counts the expirations of a kernel `Timer`. Reading it returns the count and
+//! resets it, and it is readable in poll() while the count is nonzero.
+
+use core::{mem, pin::Pin};
+
+use pin_project::{pin_project, UnsafeUnpin};
+use zerocopy::{AsBytes, FromBytes};
+
+use crate::{
+    arch::addr::{Addr, UVAddr},
+    error::KernelError,
+    file::FileType,
+    fs::FcntlFlags,
+    kernel::KernelRef,
+    lock::SpinLock,
+    poll::PollEvents,
+    proc::{KernelCtx, WaitChannel},
+    time::{ktime_now, TICK_NS},
+    timer::Timer,
+};
+
+/// Flag for timerfd_settime(): `value` is an absolute tick, as uptime() counts, instead of a
+/// relative one.
+pub const TFD_TIMER_ABSTIME: i32 = 1;
+
+/// `struct timerspec` of user programs.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct TimerSpec {
+    /// Ticks until the next expiration, or zero if the timer is disarmed.
+    value: u64,
+    /// Ticks between expirations, or zero if the timer expires only once.
+    interval: u64,
+}
+
+struct TimerFdState {
+    /// Number of expirations since the last read.
+    expirations: u64,
+    /// Ticks between expirations, or zero if the timer expires only once.
+    interval: u64,
+}
+
+/// # Safety
+///
+/// * `timer` is at the beginning of `TimerFd`.
+/// * `timer` is armed only while the `TimerFd` is in the file table, which does not move its
+///   files, and it is canceled when the file gets finalized.
+#[repr(C)]
+#[pin_project(UnsafeUnpin)]
+pub struct TimerFd {
+    #[pin]
+    timer: Timer,
+
+    state: SpinLock<TimerFdState>,
+
+    /// WaitChannel for saying the timer expired.
+    waitchannel: WaitChannel,
+}
+
+// SAFETY: a `TimerFd` may move only while `timer` is disarmed. See the invariant of `TimerFd`.
+unsafe impl UnsafeUnpin for TimerFd {}
+
+impl TimerFd {
+    pub const fn new() -> Self {
+        Self {
+            timer: Timer::new(Self::expire),
+            state: SpinLock::new(
+                "timerfd",
+                TimerFdState {
+                    expirations: 0,
+                    interval: 0,
+                },
+            ),
+            waitchannel: WaitChannel::new(),
+        }
+    }
+
+    /// The function of `self.timer`.
+    fn expire(timer: Pin<&Timer>, now: u64, kernel: KernelRef<'_, '_>) -> Option<u64> {
+        // SAFETY: the only `Timer`s that use this function are inside a `TimerFd`, at its beginning.
+        let this = unsafe { &*(timer.get_ref() as *const _ as *const Self) };
+        let mut state = this.state.lock();
+        state.expirations += 1;
+        let interval = state.interval;
+        drop(state);
+        this.waitchannel.wakeup(kernel);
+        kernel.poll_queue().wakeup(kernel);
+        if interval > 0 {
+            Some(now.saturating_add(interval.saturating_mul(TICK_NS)))
+        } else {
+            None
+        }
+    }
+
+    /// Reads the number of expirations as an 8-byte integer into `addr`, and resets it.
+    /// If the timer has not expired, sleeps until it does, or returns
+    /// `Err(KernelError::TryAgain)` if `nonblock` is set.
+    /// Returns Ok(8) on success, Err(KernelError) on error.
+    pub fn read(
+        &self,
+        addr: UVAddr,
+        n: usize,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        if n < mem::size_of::<u64>() {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut state = self.state.lock();
+        while state.expirations == 0 {
+            if nonblock {
+                return Err(KernelError::TryAgain);
+            }
+            if ctx.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            self.waitchannel.sleep(&mut state, ctx);
+        }
+        ctx.proc_mut()
+            .memory_mut()
+            .copy_out(addr, &state.expirations)?;
+        state.expirations = 0;
+        Ok(mem::size_of::<u64>())
+    }
+
+    /// Returns the readiness of the timer.
+    pub fn poll(&self) -> PollEvents {
+        if self.state.lock().expirations > 0 {
+            PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
+        }
+    }
+
+    /// Returns the time until the next expiration and the interval.
+    fn get(self: Pin<&Self>, kernel: KernelRef<'_, '_>) -> TimerSpec {
+        let now = ktime_now();
+        // Round up, so that an armed timer does not look disarmed.
+        let value = kernel
+            .timers()
+            .deadline(self.project_ref().timer)
+            .map_or(0, |deadline| {
+                (deadline.saturating_sub(now).saturating_add(TICK_NS - 1) / TICK_NS).max(1)
+            });
+        TimerSpec {
+            value,
+            interval: self.state.lock().interval,
+        }
+    }
+
+    /// Arms the timer as `spec` says, or disarms it if `spec.value` is zero.
+    /// Returns the previous setting.
+    fn set(
+        self: Pin<&Self>,
+        spec: TimerSpec,
+        abstime: bool,
+        kernel: KernelRef<'_, '_>,
+    ) -> TimerSpec {
+        let old = self.get(kernel);
+        let timers = kernel.timers();
+        // Cancel first, so that `expire` does not run while we update the state.
+        timers.cancel(self.project_ref().timer);
+        let mut state = self.state.lock();
+        state.expirations = 0;
+        state.interval = spec.interval;
+        drop(state);
+        if spec.value > 0 {
+            // Tick n comes when the monotonic clock reaches n * TICK_NS.
+            // A deadline too far to count in nanoseconds never comes.
+            let deadline = spec.value.saturating_mul(TICK_NS);
+            let deadline = if abstime {
+                deadline
+            } else {
+                ktime_now().saturating_add(deadline)
+            };
+            timers.arm(self.project_ref().timer, deadline);
+        }
+        old
+    }
+
+    /// Disarms the timer. Must be called before the `TimerFd` is moved or dropped.
+    pub fn cancel(self: Pin<&Self>, kernel: KernelRef<'_, '_>) {
+        kernel.timers().cancel(self.project_ref().timer);
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Create a disarmed timer file, and allocate a file descriptor for it.
+    /// `flags` may contain O_NONBLOCK and O_CLOEXEC.
+    /// Returns Ok(file descriptor) on success, Err(KernelError) on error.
+    pub fn timerfd_create(&mut self, flags: i32) -> Result<usize, KernelError> {
+        let flags = FcntlFlags::from_bits(flags)
+            .filter(|flags| (FcntlFlags::O_NONBLOCK | FcntlFlags::O_CLOEXEC).contains(*flags))
+            .ok_or(KernelError::InvalidArgument)?;
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(
+                FileType::TimerFd {
+                    timer: TimerFd::new(),
+                },
+                true,
+                false,
+            )
+            .map_err(|_| KernelError::FileTableFull)?;
+        f.set_status_flags(flags);
+        let fd = f.fdalloc(self)?;
+        if flags.contains(FcntlFlags::O_CLOEXEC) {
+            self.proc_mut()
+                .deref_mut_data()
+                .fds
+                .set_cloexec(fd as usize, true);
+        }
+        Ok(fd as usize)
+    }
+
+    /// Returns the timer file of the file descriptor `fd`.
+    fn timerfd(&self, fd: i32) -> Result<Pin<&TimerFd>, KernelError> {
+        let f = self
+            .proc()
+            .deref_data()
+            .fds
+            .get(fd as usize)
+            .ok_or(KernelError::BadFd)?;
+        match &f.typ {
+            // SAFETY: files do not move while they are in the file table.
+            FileType::TimerFd { timer } => Ok(unsafe { Pin::new_unchecked(timer) }),
+            _ => Err(KernelError::InvalidArgument),
+        }
+    }
+
+    /// Arm or disarm the timer file `fd` as the `struct timerspec` at `new` says, and copy the
+    /// previous setting to `old` unless it is null.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn timerfd_settime(
+        &mut self,
+        fd: i32,
+        flags: i32,
+        new: UVAddr,
+        old: UVAddr,
+    ) -> Result<(), KernelError> {
+        if flags & !TFD_TIMER_ABSTIME != 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut spec = TimerSpec::default();
+        self.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(spec.as_bytes_mut(), new)?;
+        // The file stays open while we use it, since only this process can close its descriptor.
+        let timer = self.timerfd(fd)?.get_ref() as *const TimerFd;
+        // SAFETY: see above.
+        let timer = unsafe { Pin::new_unchecked(&*timer) };
+        let prev = timer.set(spec, flags & TFD_TIMER_ABSTIME != 0, self.kernel());
+        if !old.is_null() {
+            self.proc_mut().memory_mut().copy_out(old, &prev)?;
+        }
+        Ok(())
+    }
+
+    /// Copy the time until the next expiration and the interval of the timer file `fd` to the
+    /// `struct timerspec` at `cur`.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn timerfd_gettime(&mut self, fd: i32, cur: UVAddr) -> Result<(), KernelError> {
+        let spec = self.timerfd(fd)?.get(self.kernel());
+        self.proc_mut().memory_mut().copy_out(cur, &spec)
+    }
+}
@@ -0,0 +1,209 @@
+//! System call tracing.
+//!
+//! A process can ask with trace() that the system calls selected by a mask be logged to the
+//! console, each with its decoded arguments and its return value. The mask is inherited across
+//! fork, and kept across exec, so that a program can trace the commands it runs.
+
+use core::fmt::Write;
+use core::str;
+
+use arrayvec::ArrayString;
+
+use crate::{error::KernelError, param::MAXPATH, proc::KernelCtx};
+
+/// How a system call argument is printed.
+#[derive(Copy, Clone)]
+enum Arg {
+    /// A signed integer, such as a file descriptor or a size.
+    Int,
+    /// Flags, in hexadecimal.
+    Hex,
+    /// A permission mask, in octal.
+    Oct,
+    /// A user address.
+    Addr,
+    /// A user address of a NUL-terminated string, which is printed with quotes.
+    Str,
+}
+
+use Arg::*;
+
+/// The name and arguments of each system call, indexed by its number.
+/// Keep in sync with `KernelCtx::syscall`.
+const SYSCALLS: [(&str, &[Arg]); 86] = [
+    ("", &[]),
+    ("fork", &[]),
+    ("exit", &[Int]),
+    ("wait", &[Addr]),
+    ("pipe", &[Addr]),
+    ("read", &[Int, Addr, Int]),
+    ("kill", &[Int]),
+    ("exec", &[Str, Addr]),
+    ("fstat", &[Int, Addr]),
+    ("chdir", &[Str]),
+    ("dup", &[Int]),
+    ("getpid", &[]),
+    ("sbrk", &[Int]),
+    ("sleep", &[Int]),
+    ("uptime", &[]),
+    ("open", &[Str, Hex]),
+    ("write", &[Int, Addr, Int]),
+    ("mknod", &[Str, Int, Int]),
+    ("unlink", &[Str]),
+    ("link", &[Str, Str]),
+    ("mkdir", &[Str]),
+    ("close", &[Int]),
+    ("poweroff", &[Int]),
+    ("sysinfo", &[Addr]),
+    ("fcntl", &[Int, Int, Hex]),
+    ("ioctl", &[Int, Hex, Addr]),
+    ("poll", &[Addr, Int, Int]),
+    ("pipe2", &[Addr, Hex]),
+    ("eventfd", &[Int, Hex]),
+    ("timerfd_create", &[Hex]),
+    ("timerfd_settime", &[Int, Hex, Addr, Addr]),
+    ("timerfd_gettime", &[Int, Addr]),
+    ("getcwd", &[Addr, Int]),
+    ("chroot", &[Str]),
+    ("umask", &[Oct]),
+    ("clock_gettime", &[Int, Addr]),
+    ("getrandom", &[Addr, Int, Hex]),
+    ("trace", &[Hex]),
+    ("ptrace", &[Int, Int, Addr, Hex]),
+    ("getuid", &[]),
+    ("geteuid", &[]),
+    ("getgid", &[]),
+    ("getegid", &[]),
+    ("setuid", &[Int]),
+    ("setgid", &[Int]),
+    ("chmod", &[Str, Oct]),
+    ("chown", &[Str, Int, Int]),
+    ("audit", &[Addr, Int, Int]),
+    ("sigsend", &[Int, Int]),
+    ("setpgid", &[Int, Int]),
+    ("getpgid", &[Int]),
+    ("clock_settime", &[Int, Addr]),
+    ("adjtime", &[Addr, Addr]),
+    ("dmesg", &[Addr, Int, Addr]),
+    ("logfilter", &[Str]),
+    ("traceon", &[Int]),
+    ("traceread", &[Int, Addr, Int, Int]),
+    ("leakcheck", &[Addr, Int, Int]),
+    ("failinject", &[Int, Int]),
+    ("ktest", &[Str]),
+    ("fuzzinfo", &[Addr]),
+    ("profile", &[Int, Int, Addr, Int]),
+    ("suspend", &[Int]),
+    ("socket", &[Int, Hex, Int]),
+    ("bind", &[Int, Addr]),
+    ("listen", &[Int, Int]),
+    ("accept", &[Int, Addr]),
+    ("connect", &[Int, Addr]),
+    ("fadvise", &[Int, Int, Int, Int]),
+    ("sendfile", &[Int, Int, Addr, Int]),
+    ("ioring_setup", &[]),
+    ("ioring_enter", &[Int]),
+    ("splice", &[Int, Addr, Int, Addr, Int]),
+    ("quotactl", &[Int, Int, Addr]),
+    ("pidfd_open", &[Int, Hex]),
+    ("sigpending", &[]),
+    ("capget", &[]),
+    ("capdrop", &[Hex]),
+    ("semget", &[Int, Int, Hex]),
+    ("semop", &[Int, Addr, Int]),
+    ("semctl", &[Int, Int, Int, Int]),
+    ("mq_open", &[Int, Hex, Oct, Addr]),
+    ("mq_send", &[Int, Addr, Int, Int]),
+    ("mq_receive", &[Int, Addr, Int, Addr]),
+    ("mq_unlink", &[Int]),
+    ("mq_getattr", &[Int, Addr]),
+];
+
+/// Maximum number of characters of a string argument that are printed.
+const MAX_STR: usize = 32;
+
+/// A line of the trace.
+type TraceLine = ArrayString<192>;
+
+impl KernelCtx<'_, '_> {
+    /// Returns whether the current process traces the system call `num`.
+    pub fn is_traced(&self, num: i32) -> bool {
+        (0..64).contains(&num) && self.proc().deref_data().trace_mask & (1 << num) != 0
+    }
+
+    /// Returns the process, the system call `num`, and its arguments, decoded as a line of the
+    /// trace without its return value.
+    /// Call this before the system call runs, since it may change the process's memory.
+    pub fn trace_call(&mut self, num: i32) -> TraceLine {
+        let mut line = TraceLine::new();
+        let name = &self.proc().deref_data().name;
+        let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+        let _ = write!(
+            line,
+            "{} {}: ",
+            self.proc().pid(),
+            str::from_utf8(&name[..len]).unwrap_or("???")
+        );
+        let (syscall, args) = SYSCALLS
+            .get(num as usize)
+            .copied()
+            .unwrap_or(("unknown", &[]));
+        let _ = write!(line, "{}(", syscall);
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                let _ = line.try_push_str(", ");
+            }
+            let _ = match arg {
+                Int => write!(line, "{}", self.proc().argint(i).unwrap_or(0)),
+                Hex => write!(line, "{:#x}", self.proc().argint(i).unwrap_or(0)),
+                Oct => write!(line, "{:#o}", self.proc().argint(i).unwrap_or(0)),
+                Addr => write!(line, "{:#x}", self.proc().argaddr(i).unwrap_or(0)),
+                Str => {
+                    let mut buf = [0; MAXPATH];
+                    match self.proc_mut().argstr(i, &mut buf) {
+                        Ok(s) => {
+                            let s = s.to_bytes();
+                            let shown = &s[..s.len().min(MAX_STR)];
+                            let ellipsis = if shown.len() < s.len() { "..." } else { "" };
+                            write!(
+                                line,
+                                "\"{}\"{}",
+                                str::from_utf8(shown).unwrap_or("???"),
+                                ellipsis
+                            )
+                        }
+                        Err(_) => write!(line, "{:#x}", self.proc().argaddr(i).unwrap_or(0)),
+                    }
+                }
+            };
+        }
+        let _ = line.try_push(')');
+        line
+    }
+
+    /// Prints `line`, made by `trace_call`, with the return value `ret` of the system call.
+    pub fn trace_ret(&self, line: &TraceLine, ret: Result<usize, KernelError>) {
+        match ret {
+            Ok(v) => {
+                self.kernel()
+                    .as_ref()
+                    .write_fmt(format_args!("{} = {}\n", line, v as isize))
+            }
+            Err(e) => {
+                self.kernel().as_ref().write_fmt(format_args!(
+                    "{} = -{} ({:?})\n",
+                    line,
+                    e.errno(),
+                    e
+                ))
+            }
+        }
+    }
+
+    /// Trace the system calls whose bits are set in `mask`, or stop tracing if it is zero.
+    /// Returns Ok(0).
+    pub fn trace(&mut self, mask: u64) -> Result<usize, KernelError> {
+        self.proc_mut().deref_mut_data().trace_mask = mask;
+        Ok(0)
+    }
+}
@@ -0,0 +1,202 @@
+//! Static tracepoints, which record events of the kernel for latency analysis.
+//!
+//! A tracepoint is a `trace_event!` in the kernel's code. While tracing is on, each one that runs
+//! writes a fixed-size record, with the time, the CPU, the event and two arguments, into the trace
+//! buffer of its CPU, a ring that overwrites its oldest record when it is full. A CPU takes only
+//! the lock of its own buffer, so tracepoints do not contend with each other. The superuser turns
+//! tracing on and off with traceon(), and reads each CPU's buffer with traceread(), by sequence
+//! numbers as for the audit log.
+
+use core::{
+    cmp, mem,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use array_macro::array;
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::addr::UVAddr, cpu::cpuid, cred::Caps, error::KernelError, hal::hal, lock::SpinLock,
+    param::NCPU, proc::KernelCtx, time::ktime_now,
+};
+
+/// Records an event in the trace buffer of this CPU, if tracing is on.
+///
+/// e.g. `trace_event!(TraceEvent::DiskIssue, blockno, write);`
+#[macro_export]
+macro_rules! trace_event {
+    ($event:expr, $arg0:expr, $arg1:expr) => {{
+        // The arguments may be of any integer type, including `u64`.
+        #[allow(trivial_numeric_casts)]
+        let args = ($arg0 as u64, $arg1 as u64);
+        $crate::hal::hal().trace().record($event, args.0, args.1)
+    }};
+}
+
+/// Number of records the buffer of each CPU keeps.
+const TRACE_LEN: usize = 256;
+
+/// Maximum number of records copied out while holding the lock.
+const CHUNK: usize = 8;
+
+/// What a trace record is about.
+#[derive(Copy, Clone)]
+#[repr(u32)]
+pub enum TraceEvent {
+    /// The CPU switched from a process to another. The arguments are their process IDs, where 0
+    /// stands for the scheduler.
+    SchedSwitch = 1,
+    /// A process made a system call. The arguments are the system call number, and its first
+    /// argument.
+    SyscallEnter = 2,
+    /// A system call returned. The arguments are the system call number, and its return value.
+    SyscallExit = 3,
+    /// A process took a page fault. The arguments are the faulting address, and the cause.
+    PageFault = 4,
+    /// A disk request was issued. The arguments are the block number, and 1 for a write or 0
+    /// for a read.
+    DiskIssue = 5,
+    /// A disk request completed. The arguments are as for `DiskIssue`.
+    DiskComplete = 6,
+}
+
+/// `struct tracerec` of user programs.
+#[derive(Copy, Clone, AsBytes)]
+#[repr(C)]
+pub struct TraceRecord {
+    /// Sequence number in the buffer of the CPU, counting from zero at boot.
+    seq: u64,
+    /// Time since boot, in nanoseconds.
+    time: u64,
+    cpu: u32,
+    /// A `TraceEvent`.
+    event: u32,
+    /// Depend on `event`.
+    args: [u64; 2],
+}
+
+impl TraceRecord {
+    const fn zero() -> Self {
+        Self {
+            seq: 0,
+            time: 0,
+            cpu: 0,
+            event: 0,
+            args: [0; 2],
+        }
+    }
+}
+
+struct TraceRing {
+    records: [TraceRecord; TRACE_LEN],
+
+    /// Sequence number of the next record.
+    next_seq: u64,
+}
+
+impl TraceRing {
+    /// Copies the records from the sequence number `seq` on into `out`, oldest first.
+    /// Starts from the oldest record kept if `seq` was overwritten.
+    /// Returns the number of records copied.
+    fn read(&self, seq: u64, out: &mut [TraceRecord]) -> usize {
+        let start = cmp::max(seq, self.next_seq.saturating_sub(TRACE_LEN as u64));
+        let n = cmp::min(self.next_seq.saturating_sub(start) as usize, out.len());
+        for (i, record) in out[..n].iter_mut().enumerate() {
+            *record = self.records[(start as usize + i) % TRACE_LEN];
+        }
+        n
+    }
+}
+
+/// The trace buffers of the CPUs.
+pub struct TraceBuffers {
+    /// Whether tracing is on.
+    enabled: AtomicBool,
+
+    cpus: [SpinLock<TraceRing>; NCPU],
+}
+
+impl TraceBuffers {
+    pub const fn new() -> Self {
+        Self {
+            enabled: AtomicBool::new(false),
+            cpus: array![_ => SpinLock::new(
+                "trace",
+                TraceRing {
+                    records: [TraceRecord::zero(); TRACE_LEN],
+                    next_seq: 0,
+                },
+            ); NCPU],
+        }
+    }
+
+    /// Appends a record of `event` to the buffer of this CPU, if tracing is on.
+    pub fn record(&self, event: TraceEvent, arg0: u64, arg1: u64) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let intr = hal().cpus().push_off();
+        let cpu = cpuid();
+        {
+            let mut ring = self.cpus[cpu].lock();
+            let seq = ring.next_seq;
+            ring.records[seq as usize % TRACE_LEN] = TraceRecord {
+                seq,
+                time: ktime_now(),
+                cpu: cpu as u32,
+                event: event as u32,
+                args: [arg0, arg1],
+            };
+            ring.next_seq += 1;
+        }
+        // SAFETY: interrupts were disabled by the `push_off` above.
+        unsafe { hal().cpus().pop_off(intr) };
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Turn tracing on if `on`, or off. Needs `Caps::SYS_ADMIN`.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn trace_on(&self, on: bool) -> Result<(), KernelError> {
+        self.require_cap(Caps::SYS_ADMIN)?;
+        hal().trace().enabled.store(on, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Copy up to `n` records of the trace buffer of `cpu`, from the sequence number `seq` on, to
+    /// the `struct tracerec` array at `addr`. Needs `Caps::SYS_ADMIN`.
+    /// Returns Ok(number of records copied) on success, Err(KernelError) on error.
+    pub fn trace_read(
+        &mut self,
+        cpu: usize,
+        addr: UVAddr,
+        n: usize,
+        seq: u64,
+    ) -> Result<usize, KernelError> {
+        self.require_cap(Caps::SYS_ADMIN)?;
+        let ring = hal()
+            .get_ref()
+            .trace()
+            .cpus
+            .get(cpu)
+            .ok_or(KernelError::InvalidArgument)?;
+        let mut buf = [TraceRecord::zero(); CHUNK];
+        let mut copied = 0;
+        let mut seq = seq;
+        while copied < n {
+            let len = ring
+                .lock()
+                .read(seq, &mut buf[..cmp::min(CHUNK, n - copied)]);
+            if len == 0 {
+                break;
+            }
+            let dst = addr + copied * mem::size_of::<TraceRecord>();
+            self.proc_mut()
+                .memory_mut()
+                .copy_out_bytes(dst, buf[..len].as_bytes())?;
+            copied += len;
+            seq = buf[len - 1].seq + 1;
+        }
+        Ok(copied)
+    }
+}
@@ -1,18 +1,27 @@
 use core::mem;
 
+#[cfg(feature = "kasan")]
+use crate::kasan;
 use crate::{
     arch::addr::PGSIZE,
-    arch::memlayout::{TRAMPOLINE, TRAPFRAME, UART0_IRQ, VIRTIO0_IRQ},
-    arch::plic::{plic_claim, plic_complete},
+    arch::memlayout::{TRAMPOLINE, TRAPFRAME},
+    arch::plic::plic_claim,
     arch::riscv::{
-        intr_get, intr_off, intr_on, r_satp, r_scause, r_sepc, r_sip, r_stval, r_tp, w_sepc, w_sip,
-        w_stvec, Sstatus,
+        ebreak, intr_get, intr_off, intr_on, r_satp, r_scause, r_sepc, r_sip, r_stval, r_tp,
+        w_sepc, w_sip, w_stvec, Sstatus,
     },
-    cpu::cpuid,
+    cputime::CpuState,
+    error::KernelError,
+    gdbstub::GdbStub,
     hal::hal,
+    irq::{IrqChip, IrqReturn, CPU_SOFTWARE},
     kernel::{kernel_ref, KernelRef},
-    ok_or,
+    kmsg::Level,
+    log,
     proc::{kernel_ctx, KernelCtx, Procstate},
+    trace_event,
+    tracepoint::TraceEvent,
+    util::spin_loop,
 };
 
 extern "C" {
@@ -27,6 +36,22 @@ extern "C" {
     fn kernelvec();
 }
 
+/// The registers that kernelvec.S saves on the stack when a trap comes from the kernel: `x1`
+/// to `x31`, in 256 bytes.
+#[repr(C)]
+pub struct KernelFrame {
+    pub x: [usize; 31],
+    _pad: usize,
+}
+
+impl KernelFrame {
+    /// Returns the stack pointer at the trap. kernelvec.S restores it from the frame's address,
+    /// not from `x[1]`.
+    pub fn sp(&self) -> usize {
+        self as *const _ as usize + mem::size_of::<Self>()
+    }
+}
+
 pub fn trapinit() {}
 
 /// Set up to take exceptions and traps while in the kernel.
@@ -45,11 +70,12 @@ pub unsafe extern "C" fn usertrap() {
 }
 
 /// Interrupts and exceptions from kernel code go here via kernelvec,
-/// on whatever the current kernel stack is.
+/// on whatever the current kernel stack is, with the registers saved in `frame`.
 #[no_mangle]
-pub unsafe fn kerneltrap() {
-    // SAFETY: kerneltrap can be reached only after the initialization of the kernel.
-    unsafe { kernel_ref(|kref| kref.kernel_trap()) };
+pub unsafe extern "C" fn kerneltrap(frame: *mut KernelFrame) {
+    // SAFETY: kerneltrap can be reached only after the initialization of the kernel, and
+    // `frame` is on the stack of this trap.
+    unsafe { kernel_ref(|kref| kref.kernel_trap(&mut *frame)) };
 }
 
 impl KernelCtx<'_, '_> {
@@ -64,62 +90,121 @@ impl KernelCtx<'_, '_> {
         // since we're now in the kernel.
         unsafe { w_stvec(kernelvec as _) };
 
+        // SAFETY: interrupts are disabled until we enable them below.
+        let _ = unsafe { hal().cputimes().switch(CpuState::System) };
+
         let mut which_dev: i32 = 0;
 
-        // Save user program counter.
-        self.proc_mut().trap_frame_mut().epc = r_sepc();
-        if r_scause() == 8 {
+        // Read the trap's registers once, before an interrupt can change them.
+        let scause = r_scause();
+        let sepc = r_sepc();
+
+        // Save user program counter. sepc points to the ecall instruction of a system call,
+        // but we want to return to the next instruction.
+        self.proc_mut().trap_frame_mut().epc = if scause == 8 {
+            sepc.wrapping_add(4)
+        } else {
+            sepc
+        };
+        if scause == 8 {
             // system call
 
             if self.proc().killed() {
                 self.kernel().procs().exit_current(-1, &mut self);
             }
 
-            // sepc points to the ecall instruction,
-            // but we want to return to the next instruction.
-            self.proc_mut().trap_frame_mut().epc = (self.proc().trap_frame().epc).wrapping_add(4);
-
             // An interrupt will change sstatus &c registers,
             // so don't enable until done with those registers.
             unsafe { intr_on() };
-            let syscall_no = self.proc_mut().trap_frame_mut().a7 as i32;
-            self.proc_mut().trap_frame_mut().a0 = ok_or!(self.syscall(syscall_no), usize::MAX);
+            let syscall_no = self.proc().trap_frame().a7 as i32;
+            trace_event!(
+                TraceEvent::SyscallEnter,
+                syscall_no,
+                self.proc().trap_frame().a0
+            );
+            let ret = self
+                .syscall(syscall_no)
+                .unwrap_or_else(KernelError::to_syscall_ret);
+            trace_event!(TraceEvent::SyscallExit, syscall_no, ret);
+            self.proc_mut().trap_frame_mut().a0 = ret;
+        } else if scause == 3 && self.ptrace_breakpoint() {
+            // A breakpoint of a traced process.
+        } else if scause == 15
+            && self
+                .proc_mut()
+                .memory_mut()
+                .break_cow(r_stval().into(), hal().kmem())
+                .is_ok()
+        {
+            // A store to a page mapped copy-on-write, which is now the process's own copy.
+            trace_event!(TraceEvent::PageFault, r_stval(), scause);
         } else {
             which_dev = unsafe { self.kernel().dev_intr() };
             if which_dev == 0 {
-                self.kernel().as_ref().write_fmt(format_args!(
-                    "usertrap(): unexpected scause {:018p} pid={}\n",
-                    r_scause() as *const u8,
-                    self.proc().pid()
-                ));
-                self.kernel().as_ref().write_fmt(format_args!(
-                    "            sepc={:018p} stval={:018p}\n",
-                    r_sepc() as *const u8,
+                if matches!(scause, 12 | 13 | 15) {
+                    trace_event!(TraceEvent::PageFault, r_stval(), scause);
+                }
+                log!(
+                    Level::Warn,
+                    "trap",
+                    "usertrap(): unexpected scause {:018p} pid={}\n            sepc={:018p} stval={:018p}",
+                    scause as *const u8,
+                    self.proc().pid(),
+                    sepc as *const u8,
                     r_stval() as *const u8
-                ));
+                );
                 self.proc().kill();
             }
+            hal().softirqs().run(self.kernel());
+        }
+
+        // GDB sent ^C. Stop in the kernel, which is what the stub debugs.
+        if hal().gdb().map_or(false, GdbStub::take_break_request) {
+            ebreak();
         }
 
+        self.check_stop();
+        self.freeze();
+
         if self.proc().killed() {
             self.kernel().procs().exit_current(-1, &mut self);
         }
 
-        // Give up the CPU if this is a timer interrupt.
+        // Give up the CPU if its time slice is over.
         if which_dev == 2 {
             self.yield_cpu();
         }
 
-        unsafe { self.user_trap_ret() }
+        unsafe { self.return_to_user() }
     }
 
-    /// Return to user space.
+    /// Return to user space for the first time since fork().
     pub unsafe fn user_trap_ret(mut self) -> ! {
+        // Set up trapframe values that uservec will need when
+        // the process next re-enters the kernel. They stay the same
+        // until the process exits, so later returns skip them.
+
+        // kernel page table
+        self.proc_mut().trap_frame_mut().kernel_satp = r_satp();
+
+        // process's kernel stack
+        self.proc_mut().trap_frame_mut().kernel_sp =
+            self.proc_mut().deref_mut_data().kstack + PGSIZE;
+        self.proc_mut().trap_frame_mut().kernel_trap = usertrap as usize;
+
+        unsafe { self.return_to_user() }
+    }
+
+    /// Return to user space.
+    unsafe fn return_to_user(mut self) -> ! {
         // We're about to switch the destination of traps from
         // kerneltrap() to usertrap(), so turn off interrupts until
         // we're back in user space, where usertrap() is correct.
         intr_off();
 
+        // SAFETY: interrupts are disabled.
+        let _ = unsafe { hal().cputimes().switch(CpuState::User) };
+
         // Send syscalls, interrupts, and exceptions to trampoline.S.
         unsafe {
             w_stvec(
@@ -129,20 +214,12 @@ impl KernelCtx<'_, '_> {
             )
         };
 
-        // Set up trapframe values that uservec will need when
-        // the process next re-enters the kernel.
-
-        // kernel page table
-        self.proc_mut().trap_frame_mut().kernel_satp = r_satp();
-
-        // process's kernel stack
-        self.proc_mut().trap_frame_mut().kernel_sp =
-            self.proc_mut().deref_mut_data().kstack + PGSIZE;
-        self.proc_mut().trap_frame_mut().kernel_trap = usertrap as usize;
-
-        // hartid for cpuid()
+        // hartid for cpuid(), which changes if the process moved to another CPU.
         self.proc_mut().trap_frame_mut().kernel_hartid = r_tp();
 
+        // Refresh the page that the process reads the clocks and its CPU from.
+        self.update_vdso();
+
         // Set up the registers that trampoline.S's sret will use
         // to get to user space.
 
@@ -172,10 +249,19 @@ impl KernelCtx<'_, '_> {
     }
 }
 
+/// Handles the supervisor software interrupt, by running the timers that are due.
+pub fn timer_intr(kernel: KernelRef<'_, '_>) -> IrqReturn {
+    if kernel.timers().interrupt(kernel) {
+        IrqReturn::SliceOver
+    } else {
+        IrqReturn::Handled
+    }
+}
+
 impl KernelRef<'_, '_> {
     /// `kernel_trap` can be reached from the kernel mode, so it is a method of `Kernel`.
-    unsafe fn kernel_trap(self) {
-        let sepc = r_sepc();
+    unsafe fn kernel_trap(self, frame: &mut KernelFrame) {
+        let mut sepc = r_sepc();
         let sstatus = Sstatus::read();
         let scause = r_scause();
 
@@ -185,6 +271,17 @@ impl KernelRef<'_, '_> {
         );
         assert!(!intr_get(), "kerneltrap: interrupts enabled");
 
+        if let Some(gdb) = hal().gdb().filter(|_| scause == 3) {
+            // A breakpoint, for GDB.
+            sepc = gdb.stop(self, frame, sepc, true);
+            unsafe { w_sepc(sepc) };
+            unsafe { sstatus.write() };
+            return;
+        }
+
+        #[cfg(feature = "kasan")]
+        kasan::check_fault(self.memory(), scause, r_stval(), sepc);
+
         let which_dev = unsafe { self.dev_intr() };
         if which_dev == 0 {
             self.as_ref()
@@ -196,9 +293,10 @@ impl KernelRef<'_, '_> {
             ));
             panic!("kerneltrap");
         }
+        hal().softirqs().run(self);
 
-        // Give up the CPU if this is a timer interrupt.
-        if which_dev == 2 {
+        // Give up the CPU if its time slice is over, unless the trap interrupted soft interrupts.
+        if which_dev == 2 && !hal().softirqs().is_active() {
             // TODO(https://github.com/kaist-cp/rv6/issues/517): safety?
             if let Some(ctx) = unsafe { self.get_ctx() } {
                 // SAFETY:
@@ -210,66 +308,89 @@ impl KernelRef<'_, '_> {
             }
         }
 
+        // GDB sent ^C.
+        if let Some(gdb) = hal().gdb().filter(|gdb| gdb.take_break_request()) {
+            sepc = gdb.stop(self, frame, sepc, false);
+        }
+
         // The yield may have caused some traps to occur,
         // so restore trap registers for use by kernelvec.S's sepc instruction.
         unsafe { w_sepc(sepc) };
         unsafe { sstatus.write() };
     }
 
-    fn clock_intr(self) {
-        let mut ticks = self.ticks().lock();
-        *ticks = ticks.wrapping_add(1);
-        ticks.wakeup(self);
-    }
-
     /// Check if it's an external interrupt or software interrupt,
     /// and handle it.
-    /// Returns 2 if timer interrupt,
+    /// Returns 2 if timer interrupt that ended the time slice,
     /// 1 if other device,
     /// 0 if not recognized.
     unsafe fn dev_intr(self) -> i32 {
+        if r_scause() & 0x8000000000000000 == 0 {
+            return unsafe { self.handle_dev_intr() };
+        }
+        // Account the time handling an interrupt to the interrupt, not to what it interrupted.
+        // SAFETY: interrupts are disabled while handling a trap.
+        let prev = unsafe { hal().cputimes().switch(CpuState::Irq) };
+        let which_dev = unsafe { self.handle_dev_intr() };
+        // SAFETY: interrupts are disabled while handling a trap.
+        let _ = unsafe { hal().cputimes().switch(prev) };
+        which_dev
+    }
+
+    /// The body of `dev_intr`.
+    unsafe fn handle_dev_intr(self) -> i32 {
         let scause: usize = r_scause();
 
-        if scause & 0x8000000000000000 != 0 && scause & 0xff == 9 {
+        // A CPU stops at its first interrupt after another CPU panicked, which may be writing
+        // a crash dump to the disk.
+        if self.as_ref().is_panicked_elsewhere() {
+            spin_loop();
+        }
+
+        let (chip, line) = if scause & 0x8000000000000000 != 0 && scause & 0xff == 9 {
             // This is a supervisor external interrupt, via PLIC.
 
             // irq indicates which device interrupted.
-            let irq = unsafe { plic_claim() };
-
-            if irq as usize == UART0_IRQ {
-                // SAFETY: it's unsafe only when ctrl+p is pressed.
-                unsafe { hal().console().intr(self) };
-            } else if irq as usize == VIRTIO0_IRQ {
-                hal().disk().pinned_lock().get_pin_mut().intr(self);
-            } else if irq != 0 {
-                // Use `panic!` instead of `println` to prevent stack overflow.
-                // https://github.com/kaist-cp/rv6/issues/311
-                panic!("unexpected interrupt irq={}\n", irq);
+            let irq = unsafe { plic_claim() } as usize;
+            if irq == 0 {
+                return 1;
             }
-
-            // The PLIC allows each device to raise at most one
-            // interrupt at a time; tell the PLIC the device is
-            // now allowed to interrupt again.
-            if irq != 0 {
-                unsafe { plic_complete(irq) };
-            }
-
-            1
+            (IrqChip::Plic, irq)
         } else if scause == 0x8000000000000001 {
-            // Software interrupt from a machine-mode timer interrupt,
-            // forwarded by timervec in selfvec.S.
-
-            if cpuid() == 0 {
-                self.clock_intr();
-            }
+            // Software interrupt from a machine-mode timer interrupt or IPI,
+            // forwarded by timervec in kernelvec.S.
 
             // Acknowledge the software interrupt by clearing
-            // the SSIP bit in sip.
+            // the SSIP bit in sip, before running the calls that
+            // it was raised for, so that one queued meanwhile
+            // raises it again.
             unsafe { w_sip(r_sip() & !2) };
+            (IrqChip::Cpu, CPU_SOFTWARE)
+        } else {
+            return 0;
+        };
+
+        let ret = hal()
+            .irqs()
+            .handle(chip, line, self)
+            // Use `panic!` instead of `println` to prevent stack overflow.
+            // https://github.com/kaist-cp/rv6/issues/311
+            .unwrap_or_else(|| panic!("unexpected interrupt {} {}\n", chip, line));
 
+        if chip == IrqChip::Plic {
+            // The PLIC allows each device to raise at most one
+            // interrupt at a time; tell the PLIC the device is
+            // now allowed to interrupt again.
+            unsafe { hal().irqs().complete(line) };
+            self.random().add_interrupt(line as u32, self);
+        } else {
+            self.random().add_interrupt(0, self);
+        }
+
+        if ret == IrqReturn::SliceOver {
             2
         } else {
-            0
+            1
         }
     }
 }
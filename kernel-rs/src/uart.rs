@@ -118,6 +118,17 @@ impl Uart {
         );
     }
 
+    /// Initializes the UART to be polled for output, so that only input interrupts.
+    pub fn init_polled(&self) {
+        self.init();
+        self.disable_tx_intr();
+    }
+
+    /// Stops the UART from interrupting when it can send, so that only input interrupts.
+    pub fn disable_tx_intr(&self) {
+        self.write(IER, UartRegBits::IERRxEnable.bits());
+    }
+
     /// Read one input character from the UART. Return Err(()) if none is waiting.
     pub fn getc(&self) -> Result<i32, ()> {
         if self.read(LSR) & 0x01 != 0 {
@@ -128,6 +139,11 @@ impl Uart {
         }
     }
 
+    /// Acknowledges the interrupt that the UART raises when it is ready for more output.
+    pub fn ack_intr(&self) {
+        let _ = self.read(ISR);
+    }
+
     /// Write one output character to the UART.
     pub fn putc(&self, c: u8) {
         self.write(THR, c);
@@ -0,0 +1,110 @@
+//! Fixed-size bitmap.
+
+use core::ops::Range;
+
+const BITS: usize = usize::BITS as usize;
+
+/// Returns the number of words that a `Bitmap` needs to hold `bits` bits.
+pub const fn words(bits: usize) -> usize {
+    (bits + BITS - 1) / BITS
+}
+
+/// A bitmap of `len` bits, which are initially all zero.
+/// It is stored in `WORDS` words, so `len` must not exceed `WORDS * usize::BITS`.
+/// Use `words` to get `WORDS`, e.g. `Bitmap<{ words(LEN) }>`.
+///
+/// # Safety
+///
+/// Bits at `len` or after are always zero.
+pub struct Bitmap<const WORDS: usize> {
+    words: [usize; WORDS],
+    len: usize,
+}
+
+impl<const WORDS: usize> Bitmap<WORDS> {
+    /// Returns a bitmap of `len` bits, which are all zero.
+    pub const fn new(len: usize) -> Self {
+        Self {
+            words: [0; WORDS],
+            len: if len < WORDS * BITS {
+                len
+            } else {
+                WORDS * BITS
+            },
+        }
+    }
+
+    /// Returns the number of bits.
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the value of the `index`th bit.
+    pub fn get(&self, index: usize) -> bool {
+        assert!(index < self.len, "Bitmap::get");
+        self.words[index / BITS] & (1 << (index % BITS)) != 0
+    }
+
+    /// Sets the `index`th bit to `value`, and returns its previous value.
+    pub fn set(&mut self, index: usize, value: bool) -> bool {
+        assert!(index < self.len, "Bitmap::set");
+        let word = &mut self.words[index / BITS];
+        let mask = 1 << (index % BITS);
+        let prev = *word & mask != 0;
+        if value {
+            *word |= mask;
+        } else {
+            *word &= !mask;
+        }
+        prev
+    }
+
+    /// Sets the bits in `range` to one.
+    pub fn set_range(&mut self, range: Range<usize>) {
+        for index in range {
+            let _ = self.set(index, true);
+        }
+    }
+
+    /// Sets the bits in `range` to zero.
+    pub fn clear_range(&mut self, range: Range<usize>) {
+        for index in range {
+            let _ = self.set(index, false);
+        }
+    }
+
+    /// Returns the index of the first zero bit, or `None` if every bit is one.
+    pub fn first_zero(&self) -> Option<usize> {
+        self.words
+            .iter()
+            .enumerate()
+            .find(|(_, word)| **word != usize::MAX)
+            .map(|(i, word)| i * BITS + word.trailing_ones() as usize)
+            .filter(|index| *index < self.len)
+    }
+
+    /// Returns the index of the first one bit, or `None` if every bit is zero.
+    pub fn first_one(&self) -> Option<usize> {
+        self.words
+            .iter()
+            .enumerate()
+            .find(|(_, word)| **word != 0)
+            .map(|(i, word)| i * BITS + word.trailing_zeros() as usize)
+    }
+
+    /// Sets the first zero bit to one, and returns its index.
+    /// Returns `None` if every bit is one.
+    pub fn alloc(&mut self) -> Option<usize> {
+        let index = self.first_zero()?;
+        let _ = self.set(index, true);
+        Some(index)
+    }
+
+    /// Returns the number of one bits.
+    pub fn count_ones(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+}
@@ -207,11 +207,17 @@
 //!
 //! That is, you should wrap the `Branded` with your own wrapper,
 //! and add invariants to the wrapper instead of the `Branded` itself.
+//!
+//! # Integer newtypes
+//!
+//! This module also provides `BlockNo`, `Inum`, and `DevNo`, which wrap the `u32`s used to identify
+//! disk blocks, inodes, and devices. They do not carry an `'id` tag, but still prevent passing one
+//! kind of number where another is expected.
 
 use core::{
     cell::Cell,
     marker::PhantomData,
-    ops::{Deref, DerefMut},
+    ops::{Add, Deref, DerefMut},
 };
 
 /// An invariant lifetime.
@@ -272,3 +278,44 @@ impl<'id, T> DerefMut for Branded<'id, T> {
         &mut self.inner
     }
 }
+
+macro_rules! define_id_type {
+    ($(#[$attr:meta])* $typ:ident) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub struct $typ(u32);
+
+        impl $typ {
+            pub const fn new(value: u32) -> Self {
+                Self(value)
+            }
+
+            pub const fn into_u32(self) -> u32 {
+                self.0
+            }
+        }
+    };
+}
+
+define_id_type!(
+    /// A disk block number.
+    BlockNo
+);
+
+define_id_type!(
+    /// An inode number.
+    Inum
+);
+
+define_id_type!(
+    /// A device number.
+    DevNo
+);
+
+impl Add<u32> for BlockNo {
+    type Output = Self;
+
+    fn add(self, rhs: u32) -> Self::Output {
+        Self(self.0 + rhs)
+    }
+}
@@ -0,0 +1,204 @@
+//! Intrusive hash map with a fixed number of buckets.
+//! Each bucket is a [`List`] protected by its own lock, and nodes are chained through their
+//! [`ListEntry`](super::intrusive_list::ListEntry).
+//!
+//! As with [`List`], the map never returns a reference to a node, and always returns a raw pointer
+//! instead. The caller should make sure the node is not under mutation or already dropped when
+//! dereferencing the raw pointer. Nodes must be removed from the map through `remove` before they
+//! get dropped, so that holding a `BucketGuard` is enough to access the nodes in the bucket.
+
+use core::hash::Hash;
+use core::pin::Pin;
+
+use array_macro::array;
+use pin_project::pin_project;
+
+use super::{
+    hash::hash,
+    intrusive_list::{List, ListEntry, ListNode},
+    pinned_array::IterPinMut,
+};
+use crate::{
+    ktest, ktest_assert,
+    lock::{SpinLock, SpinLockGuard},
+};
+
+/// Intrusive linked list nodes that can be inserted into a `HashMap`.
+///
+/// # Safety
+///
+/// Same as `ListNode`.
+pub unsafe trait HashNode: ListNode {
+    /// The key that identifies the node.
+    type Key: Hash + Eq;
+
+    /// Returns the key of this node.
+    /// It must not change while the node is inserted in a `HashMap`.
+    fn key(&self) -> Self::Key;
+}
+
+/// A hash map with `N` buckets.
+/// Use only after initialization.
+#[pin_project]
+pub struct HashMap<T: HashNode, const N: usize> {
+    #[pin]
+    buckets: [SpinLock<List<T>>; N],
+}
+
+/// A locked bucket of a `HashMap`.
+pub struct BucketGuard<'s, T: HashNode>(SpinLockGuard<'s, List<T>>);
+
+impl<T: HashNode, const N: usize> HashMap<T, N> {
+    /// Returns an uninitialized `HashMap`.
+    /// `name` is used as the name of the buckets' locks.
+    ///
+    /// # Safety
+    ///
+    /// Must be used only after initializing it with `HashMap::init`.
+    pub const unsafe fn new(name: &'static str) -> Self {
+        Self {
+            buckets: array![_ => SpinLock::new(name, unsafe { List::new() }); N],
+        }
+    }
+
+    pub fn init(self: Pin<&mut Self>) {
+        for bucket in IterPinMut::from(self.project().buckets) {
+            bucket.get_pin_mut().init();
+        }
+    }
+
+    /// Locks and returns the bucket that `key` belongs to.
+    pub fn lock_bucket(self: Pin<&Self>, key: &T::Key) -> BucketGuard<'_, T> {
+        let index = (hash(key) % N as u64) as usize;
+        // SAFETY: we're just projecting from a pinned array to its pinned element.
+        let bucket = unsafe { Pin::new_unchecked(&self.get_ref().buckets[index]) };
+        BucketGuard(bucket.pinned_lock())
+    }
+
+    /// Provides a raw pointer to a node whose key equals `key`, or `None` if no such node exists.
+    ///
+    /// # Note
+    ///
+    /// The bucket's lock is released when this method returns.
+    /// Use `lock_bucket` instead if the node may get removed concurrently.
+    pub fn find(self: Pin<&Self>, key: &T::Key) -> Option<*const T> {
+        self.lock_bucket(key).find(key)
+    }
+
+    /// Inserts `elt` into its bucket after removing it from its list.
+    pub fn insert(self: Pin<&Self>, elt: Pin<&T>) {
+        self.lock_bucket(&elt.key()).insert(elt);
+    }
+
+    /// Removes `elt` from its bucket.
+    pub fn remove(self: Pin<&Self>, elt: Pin<&T>) {
+        self.lock_bucket(&elt.key()).remove(elt);
+    }
+}
+
+impl<T: HashNode> BucketGuard<'_, T> {
+    fn list(&self) -> Pin<&List<T>> {
+        // SAFETY: the list is inside a pinned `HashMap`.
+        unsafe { Pin::new_unchecked(&*self.0) }
+    }
+
+    /// Provides a raw pointer to a node in this bucket whose key equals `key`,
+    /// or `None` if no such node exists.
+    pub fn find(&self, key: &T::Key) -> Option<*const T> {
+        // SAFETY: nodes are removed from this bucket only while holding its lock.
+        unsafe { self.list().iter_unchecked() }
+            .find(|node| node.key() == *key)
+            .map(|node| node as *const T)
+    }
+
+    /// Provides raw pointers to the nodes in this bucket, whichever keys they have.
+    pub fn iter(&self) -> impl Iterator<Item = *const T> + '_ {
+        // SAFETY: nodes are removed from this bucket only while holding its lock.
+        unsafe { self.list().iter_unchecked() }.map(|node| node as *const T)
+    }
+
+    /// Inserts `elt` into this bucket after removing it from its list.
+    /// `elt`'s key must belong to this bucket.
+    pub fn insert(&mut self, elt: Pin<&T>) {
+        self.list().push_back(elt);
+    }
+
+    /// Removes `elt` from this bucket.
+    /// `elt` must be in this bucket.
+    pub fn remove(&mut self, elt: Pin<&T>) {
+        elt.get_list_entry().remove();
+    }
+}
+
+ktest! {
+    fn hash_map_collisions(ctx) {
+        // More keys than buckets, so that some keys share a bucket.
+        const NBUCKET: usize = 4;
+        const NNODE: usize = 16;
+
+        #[repr(C)]
+        struct Node {
+            entry: ListEntry,
+            key: usize,
+        }
+
+        // SAFETY: `Node` owns a `ListEntry`, at its beginning.
+        unsafe impl ListNode for Node {
+            fn get_list_entry(self: Pin<&Self>) -> Pin<&ListEntry> {
+                unsafe { Pin::new_unchecked(&self.get_ref().entry) }
+            }
+
+            fn from_list_entry(list_entry: *const ListEntry) -> *const Self {
+                list_entry as _
+            }
+        }
+
+        // SAFETY: `Node` is a `ListNode`.
+        unsafe impl HashNode for Node {
+            type Key = usize;
+
+            fn key(&self) -> usize {
+                self.key
+            }
+        }
+
+        let mut map = unsafe { HashMap::<Node, NBUCKET>::new("hash_map_collisions") };
+        // SAFETY: `map` and `nodes` are not moved until they are dropped.
+        let mut map = unsafe { Pin::new_unchecked(&mut map) };
+        map.as_mut().init();
+        let map = map.as_ref();
+        let mut nodes = array![i => Node {
+            entry: unsafe { ListEntry::new() },
+            key: i * 10,
+        }; NNODE];
+        for node in &mut nodes {
+            unsafe { Pin::new_unchecked(&mut node.entry) }.init();
+        }
+        let nodes = unsafe { Pin::new_unchecked(&nodes) };
+        let node = |i: usize| unsafe { nodes.map_unchecked(|nodes| &nodes[i]) };
+        let found = |i: usize| map.find(&(i * 10)) == Some(&nodes[i] as *const Node);
+
+        for i in 0..NNODE {
+            map.insert(node(i));
+        }
+        ktest_assert!((0..NNODE).all(found));
+        ktest_assert!(map.find(&5).is_none());
+
+        // Removing a node leaves the others in its bucket.
+        for i in (0..NNODE).step_by(2) {
+            map.remove(node(i));
+        }
+        ktest_assert!((0..NNODE).all(|i| found(i) == (i % 2 == 1)));
+        ktest_assert!(map.lock_bucket(&20).find(&20).is_none());
+
+        // A node can be inserted again.
+        map.insert(node(2));
+        ktest_assert!(found(2));
+        ktest_assert!(map.lock_bucket(&30).find(&30) == Some(&nodes[3] as *const Node));
+
+        for i in (0..NNODE).filter(|i| found(*i)) {
+            map.remove(node(i));
+        }
+        ktest_assert!((0..NNODE).all(|i| !found(i)));
+    }
+}
@@ -0,0 +1,299 @@
+//! Intrusive min-heap, implemented as a pairing heap.
+//! Each node is inserted with a `u64` key, which is stored in its [`HeapEntry`], and the node with
+//! the smallest key comes out first. Nodes with equal keys come out in an unspecified order.
+//!
+//! Similarly to [`Tree`](super::intrusive_rbtree::Tree), a [`Heap`] does not use lifetimes and
+//! allows nodes to be mutated or dropped even when they are inserted in the heap.
+//! When a node gets dropped, we simply remove it from the heap.
+//! Instead, a [`Heap`] or [`HeapEntry`]'s methods never return a reference to a node or
+//! [`HeapEntry`], and always return a raw pointer instead.
+//! The caller should make sure the node is not under mutation or already dropped when
+//! dereferencing the raw pointer.
+//!
+//! Inserting a node takes O(1) time, and removing a node takes O(log n) amortized time.
+
+use core::cell::Cell;
+use core::marker::{PhantomData, PhantomPinned};
+use core::pin::Pin;
+use core::ptr;
+
+use pin_project::{pin_project, pinned_drop};
+
+/// A min-heap.
+/// Can only contain types that implement the `HeapNode` trait.
+///
+/// # Safety
+///
+/// * `root` is null, or a `HeapEntry` owned by a `T: HeapNode`.
+/// * Every `HeapEntry` reachable from `root` is owned by a `T: HeapNode`, and its `heap` is
+///   `&self.root`.
+/// * The key of each `HeapEntry` is not smaller than the key of its parent.
+#[pin_project(PinnedDrop)]
+pub struct Heap<T: HeapNode> {
+    root: Cell<*const HeapEntry>,
+    _marker: PhantomData<T>,
+    #[pin]
+    _pin: PhantomPinned, // `Heap` is `!Unpin`.
+}
+
+/// Intrusive heap nodes that can be inserted into a `Heap`.
+///
+/// # Safety
+///
+/// Only implement this for structs that own a `HeapEntry`.
+/// The required functions should provide conversion between the struct and its `HeapEntry`.
+pub unsafe trait HeapNode: Sized {
+    /// Returns a reference of this struct's `HeapEntry`.
+    fn get_heap_entry(self: Pin<&Self>) -> Pin<&HeapEntry>;
+
+    /// Returns a raw pointer which points to the struct that owns the given `heap_entry`.
+    /// You may want to use `offset_of!` to implement this.
+    fn from_heap_entry(heap_entry: *const HeapEntry) -> *const Self;
+}
+
+/// A low level primitive for intrusive heaps and nodes.
+///
+/// Children of an entry form a doubly linked list, which starts from its `child`.
+/// `prev` points to the previous sibling, or the parent if this is the first child.
+///
+/// # Safety
+///
+/// * If `heap` is null, the `HeapEntry` is not linked, and `child`, `next`, and `prev` are null.
+/// * Otherwise, `heap` points to the `root` of the `Heap` that contains the `HeapEntry`, and
+///   `child`, `next`, and `prev` are null or a valid `HeapEntry` in the same `Heap`.
+///   `prev` is null if and only if this is the root.
+#[pin_project(PinnedDrop)]
+pub struct HeapEntry {
+    key: Cell<u64>,
+    child: Cell<*const Self>,
+    next: Cell<*const Self>,
+    prev: Cell<*const Self>,
+    heap: Cell<*const Cell<*const Self>>,
+    #[pin]
+    _marker: PhantomPinned, // `HeapEntry` is `!Unpin`.
+}
+
+impl<T: HeapNode> Heap<T> {
+    /// Returns an empty `Heap`.
+    pub const fn new() -> Self {
+        Self {
+            root: Cell::new(ptr::null()),
+            _marker: PhantomData,
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// Returns `true` if this `Heap` is empty.
+    /// Otherwise, returns `false`.
+    pub fn is_empty(self: Pin<&Self>) -> bool {
+        self.root.get().is_null()
+    }
+
+    /// Provides a raw pointer to the node with the smallest key, or `None` if the heap is empty.
+    pub fn first(self: Pin<&Self>) -> Option<*const T> {
+        let root = self.root.get();
+        if root.is_null() {
+            None
+        } else {
+            Some(T::from_heap_entry(root))
+        }
+    }
+
+    /// Returns the smallest key, or `None` if the heap is empty.
+    pub fn first_key(self: Pin<&Self>) -> Option<u64> {
+        let root = self.root.get();
+        if root.is_null() {
+            None
+        } else {
+            // SAFETY: invariant
+            Some(unsafe { (*root).key.get() })
+        }
+    }
+
+    /// Inserts `elt` into the heap with the given `key`, after removing it from its heap.
+    pub fn insert(self: Pin<&Self>, elt: Pin<&T>, key: u64) {
+        let entry = elt.get_heap_entry();
+        entry.remove();
+
+        let entry = entry.get_ref();
+        entry.key.set(key);
+        entry.heap.set(&self.root);
+        let root = self.root.get();
+        self.root.set(if root.is_null() {
+            entry
+        } else {
+            // SAFETY: `root` and `entry` are roots of heaps.
+            unsafe { HeapEntry::meld(root, entry) }
+        });
+    }
+
+    /// Removes `elt` from the heap.
+    pub fn remove(self: Pin<&Self>, elt: Pin<&T>) {
+        let entry = elt.get_heap_entry();
+        debug_assert!(ptr::eq(entry.heap.get(), &self.root));
+        entry.remove();
+    }
+
+    /// Removes the node with the smallest key from the heap and returns a raw pointer to it,
+    /// or `None` if the heap is empty.
+    pub fn pop(self: Pin<&Self>) -> Option<*const T> {
+        let ptr = self.first()?;
+        // SAFETY: `ptr` is a node of this heap.
+        unsafe { Pin::new_unchecked(&*ptr) }
+            .get_heap_entry()
+            .remove();
+        Some(ptr)
+    }
+
+    /// Removes all nodes from the heap.
+    pub fn clear(self: Pin<&Self>) {
+        while self.pop().is_some() {}
+    }
+}
+
+#[pinned_drop]
+impl<T: HeapNode> PinnedDrop for Heap<T> {
+    fn drop(self: Pin<&mut Self>) {
+        self.as_ref().clear();
+    }
+}
+
+impl HeapEntry {
+    /// Returns an unlinked `HeapEntry`.
+    pub const fn new() -> Self {
+        Self {
+            key: Cell::new(0),
+            child: Cell::new(ptr::null()),
+            next: Cell::new(ptr::null()),
+            prev: Cell::new(ptr::null()),
+            heap: Cell::new(ptr::null()),
+            _marker: PhantomPinned,
+        }
+    }
+
+    /// Returns `true` if this `HeapEntry` is not inserted in any `Heap`.
+    /// Otherwise, returns `false`.
+    pub fn is_unlinked(self: Pin<&Self>) -> bool {
+        self.heap.get().is_null()
+    }
+
+    /// Returns the key this `HeapEntry` was inserted with.
+    /// The value is meaningless if it is not inserted in any `Heap`.
+    pub fn key(self: Pin<&Self>) -> u64 {
+        self.key.get()
+    }
+
+    /// Unlinks this `HeapEntry` from its `Heap`, if it is inserted in one.
+    pub fn remove(self: Pin<&Self>) {
+        if self.is_unlinked() {
+            return;
+        }
+
+        let x = self.get_ref();
+        // SAFETY: every pointer below is null or a valid `HeapEntry` of the same heap.
+        unsafe {
+            let root_cell = &*x.heap.get();
+            let children = Self::merge_pairs(x.child.get());
+            let prev = x.prev.get();
+            if prev.is_null() {
+                // `x` is the root.
+                root_cell.set(children);
+            } else {
+                let next = x.next.get();
+                if ptr::eq((*prev).child.get(), x) {
+                    (*prev).child.set(next);
+                } else {
+                    (*prev).next.set(next);
+                }
+                if !next.is_null() {
+                    (*next).prev.set(prev);
+                }
+                if !children.is_null() {
+                    root_cell.set(Self::meld(root_cell.get(), children));
+                }
+            }
+        }
+
+        x.child.set(ptr::null());
+        x.next.set(ptr::null());
+        x.prev.set(ptr::null());
+        x.heap.set(ptr::null());
+    }
+
+    /// Melds two heaps by making the root with the larger key the first child of the other root.
+    /// Returns the new root.
+    ///
+    /// # Safety
+    ///
+    /// `a` and `b` must be valid roots of heaps, i.e. their `prev` and `next` are null.
+    unsafe fn meld(a: *const Self, b: *const Self) -> *const Self {
+        // SAFETY: `a` and `b` are valid.
+        let (parent, child) = unsafe {
+            if (*b).key.get() < (*a).key.get() {
+                (&*b, &*a)
+            } else {
+                (&*a, &*b)
+            }
+        };
+        let first = parent.child.get();
+        child.next.set(first);
+        if !first.is_null() {
+            // SAFETY: `first` is a valid child of `parent`.
+            unsafe { (*first).prev.set(child) };
+        }
+        child.prev.set(parent);
+        parent.child.set(child);
+        parent
+    }
+
+    /// Melds the sibling list starting from `first` into a single heap, and returns its root.
+    /// Melds adjacent pairs from left to right, and then melds the results from right to left.
+    ///
+    /// # Safety
+    ///
+    /// `first` must be null or the first of a valid sibling list.
+    unsafe fn merge_pairs(mut first: *const Self) -> *const Self {
+        // SAFETY: every pointer below is null or a valid `HeapEntry`.
+        unsafe {
+            // The melded pairs, linked through `next` in reverse order.
+            let mut pairs: *const Self = ptr::null();
+            while !first.is_null() {
+                let a = &*first;
+                let b = a.next.get();
+                a.prev.set(ptr::null());
+                a.next.set(ptr::null());
+                let pair = if b.is_null() {
+                    first = ptr::null();
+                    a
+                } else {
+                    first = (*b).next.get();
+                    (*b).prev.set(ptr::null());
+                    (*b).next.set(ptr::null());
+                    &*Self::meld(a, b)
+                };
+                pair.next.set(pairs);
+                pairs = pair;
+            }
+
+            let mut root: *const Self = ptr::null();
+            while !pairs.is_null() {
+                let pair = pairs;
+                pairs = (*pair).next.get();
+                (*pair).next.set(ptr::null());
+                root = if root.is_null() {
+                    pair
+                } else {
+                    Self::meld(root, pair)
+                };
+            }
+            root
+        }
+    }
+}
+
+#[pinned_drop]
+impl PinnedDrop for HeapEntry {
+    fn drop(self: Pin<&mut Self>) {
+        self.as_ref().remove();
+    }
+}
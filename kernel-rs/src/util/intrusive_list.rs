@@ -24,6 +24,7 @@ use core::ptr;
 use pin_project::{pin_project, pinned_drop};
 
 use super::strong_pin::StrongPinMut;
+use crate::{ktest, ktest_assert};
 
 /// A doubly linked list.
 /// Can only contain types that implement the `ListNode` trait.
@@ -61,6 +62,16 @@ pub struct IterPinMut<'s, T: ListNode> {
     _marker: PhantomData<&'s mut T>,
 }
 
+/// A cursor over a `List`, which can also insert or remove nodes at its position.
+///
+/// The cursor points to a node, or to the "ghost" position between the back and the front of the
+/// list, where `current` returns `None`.
+pub struct CursorMut<'s, T: ListNode> {
+    head: *const ListEntry,
+    curr: *const ListEntry,
+    _marker: PhantomData<&'s mut T>,
+}
+
 /// Intrusive linked list nodes that can be inserted into a `List`.
 ///
 /// # Safety
@@ -254,6 +265,28 @@ impl<T: ListNode> List<T> {
         }
     }
 
+    /// Returns a cursor pointing to the front node, or the ghost position if the list is empty.
+    #[allow(clippy::needless_lifetimes)]
+    pub fn cursor_front_mut<'s>(self: StrongPinMut<'s, Self>) -> CursorMut<'s, T> {
+        let head = unsafe { &(*self.ptr().as_ptr()).head };
+        CursorMut {
+            head,
+            curr: unsafe { Pin::new_unchecked(head) }.next(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns a cursor pointing to the back node, or the ghost position if the list is empty.
+    #[allow(clippy::needless_lifetimes)]
+    pub fn cursor_back_mut<'s>(self: StrongPinMut<'s, Self>) -> CursorMut<'s, T> {
+        let head = unsafe { &(*self.ptr().as_ptr()).head };
+        CursorMut {
+            head,
+            curr: unsafe { Pin::new_unchecked(head) }.prev(),
+            _marker: PhantomData,
+        }
+    }
+
     pub unsafe fn iter_pin_mut_unchecked(self: Pin<&mut Self>) -> IterPinMut<'_, T> {
         IterPinMut {
             last: &self.head,
@@ -367,6 +400,93 @@ impl<'s, T: 's + ListNode> DoubleEndedIterator for IterPinMut<'s, T> {
     }
 }
 
+impl<'s, T: 's + ListNode> CursorMut<'s, T> {
+    fn curr(&self) -> Pin<&ListEntry> {
+        // SAFETY: `self.curr` is a valid, initialized `ListEntry` of the list.
+        unsafe { Pin::new_unchecked(&*self.curr) }
+    }
+
+    fn head(&self) -> Pin<&ListEntry> {
+        // SAFETY: `self.head` is the head of the list.
+        unsafe { Pin::new_unchecked(&*self.head) }
+    }
+
+    /// Returns the node at the cursor, or `None` if the cursor is at the ghost position.
+    pub fn current(&mut self) -> Option<StrongPinMut<'_, T>> {
+        if ptr::eq(self.curr, self.head) {
+            None
+        } else {
+            // Safe since `self.curr` is a `ListEntry` contained inside a `T`.
+            let ptr = T::from_list_entry(self.curr) as *mut T;
+            Some(unsafe { StrongPinMut::new_unchecked(ptr) })
+        }
+    }
+
+    /// Moves the cursor to the next node.
+    /// If the cursor is at the back node, it moves to the ghost position,
+    /// and if it is at the ghost position, it moves to the front node.
+    pub fn move_next(&mut self) {
+        self.curr = self.curr().next();
+    }
+
+    /// Moves the cursor to the previous node.
+    /// If the cursor is at the front node, it moves to the ghost position,
+    /// and if it is at the ghost position, it moves to the back node.
+    pub fn move_prev(&mut self) {
+        self.curr = self.curr().prev();
+    }
+
+    /// Removes the node at the cursor from the list and returns a raw pointer to it,
+    /// or `None` if the cursor is at the ghost position.
+    /// The cursor moves to the next node.
+    pub fn remove_current(&mut self) -> Option<*const T> {
+        if ptr::eq(self.curr, self.head) {
+            return None;
+        }
+        let curr = self.curr;
+        self.move_next();
+        unsafe { Pin::new_unchecked(&*curr) }.remove();
+        Some(T::from_list_entry(curr))
+    }
+
+    /// Moves the node at the cursor to the back of the list.
+    /// The cursor moves to the next node. Does nothing if the cursor is at the ghost position.
+    pub fn move_current_to_back(&mut self) {
+        if ptr::eq(self.curr, self.head) {
+            return;
+        }
+        let curr = self.curr;
+        self.move_next();
+        self.head().push_back(unsafe { Pin::new_unchecked(&*curr) });
+    }
+
+    /// Moves the node at the cursor to the front of the list.
+    /// The cursor moves to the next node. Does nothing if the cursor is at the ghost position.
+    pub fn move_current_to_front(&mut self) {
+        if ptr::eq(self.curr, self.head) {
+            return;
+        }
+        let curr = self.curr;
+        self.move_next();
+        self.head()
+            .push_front(unsafe { Pin::new_unchecked(&*curr) });
+    }
+
+    /// Inserts `elt` in front of the node at the cursor, after unlinking `elt`.
+    /// If the cursor is at the ghost position, `elt` is inserted at the back of the list.
+    /// `elt` must not be the node at the cursor.
+    pub fn insert_before(&mut self, elt: Pin<&T>) {
+        self.curr().push_back(elt.get_list_entry());
+    }
+
+    /// Inserts `elt` at the back of the node at the cursor, after unlinking `elt`.
+    /// If the cursor is at the ghost position, `elt` is inserted at the front of the list.
+    /// `elt` must not be the node at the cursor.
+    pub fn insert_after(&mut self, elt: Pin<&T>) {
+        self.curr().push_front(elt.get_list_entry());
+    }
+}
+
 impl ListEntry {
     /// Returns an uninitialized `ListEntry`,
     ///
@@ -459,3 +579,62 @@ impl PinnedDrop for ListEntry {
         self.as_ref().remove();
     }
 }
+
+ktest! {
+    fn list_push_pop(ctx) {
+        #[repr(C)]
+        struct Node {
+            entry: ListEntry,
+            value: usize,
+        }
+
+        // SAFETY: `Node` owns a `ListEntry`, at its beginning.
+        unsafe impl ListNode for Node {
+            fn get_list_entry(self: Pin<&Self>) -> Pin<&ListEntry> {
+                unsafe { Pin::new_unchecked(&self.get_ref().entry) }
+            }
+
+            fn from_list_entry(list_entry: *const ListEntry) -> *const Self {
+                list_entry as _
+            }
+        }
+
+        fn node(value: usize) -> Node {
+            Node {
+                entry: unsafe { ListEntry::new() },
+                value,
+            }
+        }
+
+        let mut list = unsafe { List::<Node>::new() };
+        // SAFETY: `list` and `nodes` are not moved until they are dropped.
+        let mut list = unsafe { Pin::new_unchecked(&mut list) };
+        list.as_mut().init();
+        let mut nodes = [node(0), node(1), node(2)];
+        for node in &mut nodes {
+            unsafe { Pin::new_unchecked(&mut node.entry) }.init();
+        }
+        let nodes = unsafe { Pin::new_unchecked(&nodes) };
+
+        let list = list.as_ref();
+        list.push_back(unsafe { nodes.map_unchecked(|nodes| &nodes[0]) });
+        list.push_back(unsafe { nodes.map_unchecked(|nodes| &nodes[1]) });
+        list.push_front(unsafe { nodes.map_unchecked(|nodes| &nodes[2]) });
+        // SAFETY: the nodes are alive.
+        let value = |node: Option<*const Node>| node.map(|node| unsafe { (*node).value });
+        ktest_assert!(value(list.front()) == Some(2));
+        ktest_assert!(value(list.back()) == Some(1));
+        ktest_assert!(value(list.pop_front()) == Some(2));
+        ktest_assert!(value(list.pop_back()) == Some(1));
+        ktest_assert!(value(list.pop_front()) == Some(0));
+        ktest_assert!(list.is_empty());
+
+        // A node leaves the list when it is pushed to another place.
+        list.push_back(unsafe { nodes.map_unchecked(|nodes| &nodes[0]) });
+        list.push_back(unsafe { nodes.map_unchecked(|nodes| &nodes[1]) });
+        list.push_back(unsafe { nodes.map_unchecked(|nodes| &nodes[0]) });
+        ktest_assert!(value(list.pop_front()) == Some(1));
+        ktest_assert!(value(list.pop_front()) == Some(0));
+        ktest_assert!(list.pop_front().is_none());
+    }
+}
@@ -0,0 +1,755 @@
+//! Intrusive red-black tree.
+//! Nodes are sorted by the key returned by `TreeNode::key`.
+//!
+//! Similarly to [`List`](super::intrusive_list::List), a [`Tree`] does not use lifetimes and
+//! allows nodes to be mutated or dropped even when they are inserted in the tree.
+//! When a node gets dropped, we simply remove it from the tree.
+//! Instead, a [`Tree`] or [`TreeEntry`]'s methods never return a reference to a node or
+//! [`TreeEntry`], and always return a raw pointer instead.
+//! The caller should make sure the node is not under mutation or already dropped when
+//! dereferencing the raw pointer. Also, the caller must not change a node's key while it is
+//! inserted in a tree.
+
+use core::cell::Cell;
+use core::cmp::Ordering;
+use core::marker::{PhantomData, PhantomPinned};
+use core::pin::Pin;
+use core::ptr;
+
+use array_macro::array;
+use pin_project::{pin_project, pinned_drop};
+
+use super::strong_pin::StrongPinMut;
+use crate::{ktest, ktest_assert};
+
+/// A red-black tree.
+/// Can only contain types that implement the `TreeNode` trait.
+///
+/// # Safety
+///
+/// * `root` is null, or a `TreeEntry` owned by a `T: TreeNode`.
+/// * Every `TreeEntry` reachable from `root` is owned by a `T: TreeNode`, and its `tree` is
+///   `&self.root`.
+#[pin_project(PinnedDrop)]
+pub struct Tree<T: TreeNode> {
+    root: Cell<*const TreeEntry>,
+    _marker: PhantomData<T>,
+    #[pin]
+    _pin: PhantomPinned, // `Tree` is `!Unpin`.
+}
+
+/// An in-order iterator over the elements of `Tree`.
+pub struct Iter<'s, T: TreeNode> {
+    curr: *const TreeEntry,
+    _marker: PhantomData<&'s T>,
+}
+
+/// An in-order iterator over the elements of `Tree`, that gives `StrongPinMut`s.
+pub struct IterStrongPinMut<'s, T: TreeNode> {
+    curr: *const TreeEntry,
+    _marker: PhantomData<&'s mut T>,
+}
+
+/// Intrusive red-black tree nodes that can be inserted into a `Tree`.
+///
+/// # Safety
+///
+/// Only implement this for structs that own a `TreeEntry`.
+/// The required functions should provide conversion between the struct and its `TreeEntry`.
+pub unsafe trait TreeNode: Sized {
+    /// The key that sorts the nodes.
+    type Key: Ord;
+
+    /// Returns a reference of this struct's `TreeEntry`.
+    fn get_tree_entry(self: Pin<&Self>) -> Pin<&TreeEntry>;
+
+    /// Returns a raw pointer which points to the struct that owns the given `tree_entry`.
+    /// You may want to use `offset_of!` to implement this.
+    fn from_tree_entry(tree_entry: *const TreeEntry) -> *const Self;
+
+    /// Returns the key of this node.
+    /// It must not change while the node is inserted in a `Tree`.
+    fn key(&self) -> Self::Key;
+}
+
+/// A low level primitive for intrusive red-black trees and nodes.
+///
+/// # Safety
+///
+/// * If `tree` is null, the `TreeEntry` is not linked, and `parent`, `left`, and `right` are null.
+/// * Otherwise, `tree` points to the `root` of the `Tree` that contains the `TreeEntry`, and
+///   `parent`, `left`, and `right` are null or a valid `TreeEntry` in the same `Tree`.
+#[pin_project(PinnedDrop)]
+pub struct TreeEntry {
+    parent: Cell<*const Self>,
+    left: Cell<*const Self>,
+    right: Cell<*const Self>,
+    red: Cell<bool>,
+    tree: Cell<*const Cell<*const Self>>,
+    #[pin]
+    _marker: PhantomPinned, // `TreeEntry` is `!Unpin`.
+}
+
+impl<T: TreeNode> Tree<T> {
+    /// Returns an empty `Tree`.
+    pub const fn new() -> Self {
+        Self {
+            root: Cell::new(ptr::null()),
+            _marker: PhantomData,
+            _pin: PhantomPinned,
+        }
+    }
+
+    /// Returns `true` if this `Tree` is empty.
+    /// Otherwise, returns `false`.
+    pub fn is_empty(self: Pin<&Self>) -> bool {
+        self.root.get().is_null()
+    }
+
+    fn node(entry: *const TreeEntry) -> Option<*const T> {
+        if entry.is_null() {
+            None
+        } else {
+            Some(T::from_tree_entry(entry))
+        }
+    }
+
+    fn key_of(entry: *const TreeEntry) -> T::Key {
+        // SAFETY: `entry` is a `TreeEntry` contained inside a `T`.
+        unsafe { (*T::from_tree_entry(entry)).key() }
+    }
+
+    /// Provides a raw pointer to the node with the smallest key, or `None` if the tree is empty.
+    pub fn first(self: Pin<&Self>) -> Option<*const T> {
+        let root = self.root.get();
+        if root.is_null() {
+            return None;
+        }
+        // SAFETY: invariant
+        Self::node(unsafe { TreeEntry::leftmost(root) })
+    }
+
+    /// Provides a raw pointer to the node with the largest key, or `None` if the tree is empty.
+    pub fn last(self: Pin<&Self>) -> Option<*const T> {
+        let root = self.root.get();
+        if root.is_null() {
+            return None;
+        }
+        // SAFETY: invariant
+        Self::node(unsafe { TreeEntry::rightmost(root) })
+    }
+
+    /// Provides a raw pointer to a node whose key equals `key`, or `None` if no such node exists.
+    pub fn find(self: Pin<&Self>, key: &T::Key) -> Option<*const T> {
+        let mut curr = self.root.get();
+        while !curr.is_null() {
+            // SAFETY: invariant
+            let entry = unsafe { &*curr };
+            curr = match key.cmp(&Self::key_of(curr)) {
+                Ordering::Less => entry.left.get(),
+                Ordering::Greater => entry.right.get(),
+                Ordering::Equal => return Self::node(curr),
+            };
+        }
+        None
+    }
+
+    /// Provides a raw pointer to the node with the largest key that is less than or equal to `key`,
+    /// or `None` if no such node exists.
+    pub fn floor(self: Pin<&Self>, key: &T::Key) -> Option<*const T> {
+        let mut curr = self.root.get();
+        let mut result = ptr::null();
+        while !curr.is_null() {
+            // SAFETY: invariant
+            let entry = unsafe { &*curr };
+            if Self::key_of(curr) <= *key {
+                result = curr;
+                curr = entry.right.get();
+            } else {
+                curr = entry.left.get();
+            }
+        }
+        Self::node(result)
+    }
+
+    /// Provides a raw pointer to the node with the smallest key that is greater than or equal to
+    /// `key`, or `None` if no such node exists.
+    pub fn ceil(self: Pin<&Self>, key: &T::Key) -> Option<*const T> {
+        let mut curr = self.root.get();
+        let mut result = ptr::null();
+        while !curr.is_null() {
+            // SAFETY: invariant
+            let entry = unsafe { &*curr };
+            if Self::key_of(curr) >= *key {
+                result = curr;
+                curr = entry.left.get();
+            } else {
+                curr = entry.right.get();
+            }
+        }
+        Self::node(result)
+    }
+
+    /// Inserts `elt` into the tree after removing it from its tree.
+    /// If nodes with the same key exist, `elt` is placed after them.
+    pub fn insert(self: Pin<&Self>, elt: Pin<&T>) {
+        let entry = elt.get_tree_entry();
+        entry.remove();
+
+        let key = elt.key();
+        let mut parent = ptr::null();
+        let mut link = &self.root;
+        while !link.get().is_null() {
+            parent = link.get();
+            // SAFETY: invariant
+            let p = unsafe { &*parent };
+            link = if key < Self::key_of(parent) {
+                &p.left
+            } else {
+                &p.right
+            };
+        }
+
+        let entry = entry.get_ref();
+        entry.parent.set(parent);
+        entry.red.set(true);
+        entry.tree.set(&self.root);
+        link.set(entry);
+        // SAFETY: `entry` has been linked to this tree.
+        unsafe { entry.insert_fixup() };
+    }
+
+    /// Removes `elt` from the tree.
+    pub fn remove(self: Pin<&Self>, elt: Pin<&T>) {
+        let entry = elt.get_tree_entry();
+        debug_assert!(ptr::eq(entry.tree.get(), &self.root));
+        entry.remove();
+    }
+
+    /// Removes the node with the smallest key from the tree and returns a raw pointer to it,
+    /// or `None` if the tree is empty.
+    pub fn pop_first(self: Pin<&Self>) -> Option<*const T> {
+        let ptr = self.first()?;
+        // SAFETY: `ptr` is a node of this tree.
+        unsafe { Pin::new_unchecked(&*ptr) }
+            .get_tree_entry()
+            .remove();
+        Some(ptr)
+    }
+
+    /// Removes all nodes from the tree.
+    pub fn clear(self: Pin<&Self>) {
+        while self.pop_first().is_some() {}
+    }
+
+    /// Provides an unsafe in-order iterator.
+    ///
+    /// # Safety
+    ///
+    /// The caller should be careful when removing, mutating or dropping nodes that are currently
+    /// accessed by iterators. This can lead to undefined behavior.
+    pub unsafe fn iter_unchecked(self: Pin<&Self>) -> Iter<'_, T> {
+        let root = self.root.get();
+        Iter {
+            curr: if root.is_null() {
+                root
+            } else {
+                // SAFETY: invariant
+                unsafe { TreeEntry::leftmost(root) }
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    #[allow(clippy::needless_lifetimes)]
+    pub fn iter_shared_mut<'s>(self: StrongPinMut<'s, Self>) -> IterStrongPinMut<'s, T> {
+        // SAFETY: the pointer is valid.
+        let root = unsafe { (*self.ptr().as_ptr()).root.get() };
+        IterStrongPinMut {
+            curr: if root.is_null() {
+                root
+            } else {
+                // SAFETY: invariant
+                unsafe { TreeEntry::leftmost(root) }
+            },
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[pinned_drop]
+impl<T: TreeNode> PinnedDrop for Tree<T> {
+    fn drop(self: Pin<&mut Self>) {
+        self.as_ref().clear();
+    }
+}
+
+impl<'s, T: 's + TreeNode> Iterator for Iter<'s, T> {
+    type Item = &'s T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.curr.is_null() {
+            return None;
+        }
+        // Safe since `self.curr` is a `TreeEntry` contained inside a `T`.
+        let res = Some(unsafe { &*T::from_tree_entry(self.curr) });
+        self.curr = unsafe { Pin::new_unchecked(&*self.curr) }.next();
+        res
+    }
+}
+
+impl<'s, T: 's + TreeNode> Iterator for IterStrongPinMut<'s, T> {
+    type Item = StrongPinMut<'s, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.curr.is_null() {
+            return None;
+        }
+        // Safe since `self.curr` is a `TreeEntry` contained inside a `T`.
+        let ptr = T::from_tree_entry(self.curr) as *mut T;
+        let res = Some(unsafe { StrongPinMut::new_unchecked(ptr) });
+        self.curr = unsafe { Pin::new_unchecked(&*self.curr) }.next();
+        res
+    }
+}
+
+impl TreeEntry {
+    /// Returns an unlinked `TreeEntry`.
+    pub const fn new() -> Self {
+        Self {
+            parent: Cell::new(ptr::null()),
+            left: Cell::new(ptr::null()),
+            right: Cell::new(ptr::null()),
+            red: Cell::new(false),
+            tree: Cell::new(ptr::null()),
+            _marker: PhantomPinned,
+        }
+    }
+
+    /// Returns `true` if this `TreeEntry` is not inserted in any `Tree`.
+    /// Otherwise, returns `false`.
+    pub fn is_unlinked(self: Pin<&Self>) -> bool {
+        self.tree.get().is_null()
+    }
+
+    /// Returns a raw pointer pointing to the `TreeEntry` that follows this one in key order,
+    /// or null if this is the last one.
+    pub fn next(self: Pin<&Self>) -> *const Self {
+        // SAFETY: invariant
+        unsafe {
+            let right = self.right.get();
+            if !right.is_null() {
+                return Self::leftmost(right);
+            }
+            let mut curr = self.get_ref() as *const Self;
+            let mut parent = self.parent.get();
+            while !parent.is_null() && ptr::eq((*parent).right.get(), curr) {
+                curr = parent;
+                parent = (*parent).parent.get();
+            }
+            parent
+        }
+    }
+
+    /// Returns a raw pointer pointing to the `TreeEntry` that precedes this one in key order,
+    /// or null if this is the first one.
+    pub fn prev(self: Pin<&Self>) -> *const Self {
+        // SAFETY: invariant
+        unsafe {
+            let left = self.left.get();
+            if !left.is_null() {
+                return Self::rightmost(left);
+            }
+            let mut curr = self.get_ref() as *const Self;
+            let mut parent = self.parent.get();
+            while !parent.is_null() && ptr::eq((*parent).left.get(), curr) {
+                curr = parent;
+                parent = (*parent).parent.get();
+            }
+            parent
+        }
+    }
+
+    /// Unlinks this `TreeEntry` from its `Tree`, if it is inserted in one.
+    pub fn remove(self: Pin<&Self>) {
+        if self.is_unlinked() {
+            return;
+        }
+
+        let z = self.get_ref();
+        // SAFETY: every pointer below is null or a valid `TreeEntry` of the same tree.
+        unsafe {
+            let removed_red;
+            let child;
+            let parent;
+            if z.left.get().is_null() || z.right.get().is_null() {
+                child = if z.left.get().is_null() {
+                    z.right.get()
+                } else {
+                    z.left.get()
+                };
+                parent = z.parent.get();
+                removed_red = z.red.get();
+                z.transplant(child);
+            } else {
+                // Replace `z` with its successor `y`, which has no left child.
+                let y = &*Self::leftmost(z.right.get());
+                removed_red = y.red.get();
+                child = y.right.get();
+                if ptr::eq(y.parent.get(), z) {
+                    parent = y;
+                } else {
+                    parent = y.parent.get();
+                    y.transplant(child);
+                    y.right.set(z.right.get());
+                    (*y.right.get()).parent.set(y);
+                }
+                z.transplant(y);
+                y.left.set(z.left.get());
+                (*y.left.get()).parent.set(y);
+                y.red.set(z.red.get());
+            }
+
+            if !removed_red {
+                Self::remove_fixup(&*z.tree.get(), child, parent);
+            }
+        }
+
+        z.parent.set(ptr::null());
+        z.left.set(ptr::null());
+        z.right.set(ptr::null());
+        z.tree.set(ptr::null());
+    }
+
+    fn is_red(entry: *const Self) -> bool {
+        // SAFETY: `entry` is null or valid.
+        !entry.is_null() && unsafe { (*entry).red.get() }
+    }
+
+    /// # Safety
+    ///
+    /// `entry` must be a valid `TreeEntry` of a tree.
+    unsafe fn leftmost(mut entry: *const Self) -> *const Self {
+        // SAFETY: invariant
+        unsafe {
+            while !(*entry).left.get().is_null() {
+                entry = (*entry).left.get();
+            }
+        }
+        entry
+    }
+
+    /// # Safety
+    ///
+    /// `entry` must be a valid `TreeEntry` of a tree.
+    unsafe fn rightmost(mut entry: *const Self) -> *const Self {
+        // SAFETY: invariant
+        unsafe {
+            while !(*entry).right.get().is_null() {
+                entry = (*entry).right.get();
+            }
+        }
+        entry
+    }
+
+    /// Makes the parent of `self` point to `new` instead of `self`.
+    ///
+    /// # Safety
+    ///
+    /// `self` must be linked to a tree, and `new` must be null or a valid `TreeEntry`.
+    unsafe fn transplant(&self, new: *const Self) {
+        let parent = self.parent.get();
+        // SAFETY: invariant
+        unsafe {
+            if parent.is_null() {
+                (*self.tree.get()).set(new);
+            } else if ptr::eq((*parent).left.get(), self) {
+                (*parent).left.set(new);
+            } else {
+                (*parent).right.set(new);
+            }
+            if !new.is_null() {
+                (*new).parent.set(parent);
+            }
+        }
+    }
+
+    /// Rotates the subtree rooted at `self` to the left.
+    ///
+    /// # Safety
+    ///
+    /// `self` must be linked to a tree and have a right child.
+    unsafe fn rotate_left(&self) {
+        // SAFETY: invariant
+        unsafe {
+            let y = &*self.right.get();
+            self.right.set(y.left.get());
+            if !y.left.get().is_null() {
+                (*y.left.get()).parent.set(self);
+            }
+            self.transplant(y);
+            y.left.set(self);
+            self.parent.set(y);
+        }
+    }
+
+    /// Rotates the subtree rooted at `self` to the right.
+    ///
+    /// # Safety
+    ///
+    /// `self` must be linked to a tree and have a left child.
+    unsafe fn rotate_right(&self) {
+        // SAFETY: invariant
+        unsafe {
+            let y = &*self.left.get();
+            self.left.set(y.right.get());
+            if !y.right.get().is_null() {
+                (*y.right.get()).parent.set(self);
+            }
+            self.transplant(y);
+            y.right.set(self);
+            self.parent.set(y);
+        }
+    }
+
+    /// Restores the red-black properties after inserting `self` as a red leaf.
+    ///
+    /// # Safety
+    ///
+    /// `self` must be linked to a tree.
+    unsafe fn insert_fixup(&self) {
+        let mut z = self;
+        // SAFETY: invariant. A red node always has a parent.
+        unsafe {
+            while Self::is_red(z.parent.get()) {
+                let p = &*z.parent.get();
+                let g = &*p.parent.get();
+                if ptr::eq(g.left.get(), p) {
+                    let u = g.right.get();
+                    if Self::is_red(u) {
+                        p.red.set(false);
+                        (*u).red.set(false);
+                        g.red.set(true);
+                        z = g;
+                    } else {
+                        if ptr::eq(p.right.get(), z) {
+                            p.rotate_left();
+                            z = p;
+                        }
+                        (*z.parent.get()).red.set(false);
+                        g.red.set(true);
+                        g.rotate_right();
+                    }
+                } else {
+                    let u = g.left.get();
+                    if Self::is_red(u) {
+                        p.red.set(false);
+                        (*u).red.set(false);
+                        g.red.set(true);
+                        z = g;
+                    } else {
+                        if ptr::eq(p.left.get(), z) {
+                            p.rotate_right();
+                            z = p;
+                        }
+                        (*z.parent.get()).red.set(false);
+                        g.red.set(true);
+                        g.rotate_left();
+                    }
+                }
+            }
+            (*(*self.tree.get()).get()).red.set(false);
+        }
+    }
+
+    /// Restores the red-black properties after removing a black node, whose place has been taken
+    /// by `x`, a child of `parent`.
+    ///
+    /// # Safety
+    ///
+    /// `x` must be null or a valid `TreeEntry` of the tree whose root is `root`,
+    /// and `parent` must be null or the parent of `x`.
+    unsafe fn remove_fixup(root: &Cell<*const Self>, mut x: *const Self, mut parent: *const Self) {
+        // SAFETY: invariant. Since a black node has been removed, the sibling of `x` is not null.
+        unsafe {
+            while !ptr::eq(x, root.get()) && !Self::is_red(x) {
+                let p = &*parent;
+                if ptr::eq(p.left.get(), x) {
+                    let mut w = &*p.right.get();
+                    if w.red.get() {
+                        w.red.set(false);
+                        p.red.set(true);
+                        p.rotate_left();
+                        w = &*p.right.get();
+                    }
+                    if !Self::is_red(w.left.get()) && !Self::is_red(w.right.get()) {
+                        w.red.set(true);
+                        x = p;
+                        parent = p.parent.get();
+                    } else {
+                        if !Self::is_red(w.right.get()) {
+                            (*w.left.get()).red.set(false);
+                            w.red.set(true);
+                            w.rotate_right();
+                            w = &*p.right.get();
+                        }
+                        w.red.set(p.red.get());
+                        p.red.set(false);
+                        (*w.right.get()).red.set(false);
+                        p.rotate_left();
+                        x = root.get();
+                    }
+                } else {
+                    let mut w = &*p.left.get();
+                    if w.red.get() {
+                        w.red.set(false);
+                        p.red.set(true);
+                        p.rotate_right();
+                        w = &*p.left.get();
+                    }
+                    if !Self::is_red(w.left.get()) && !Self::is_red(w.right.get()) {
+                        w.red.set(true);
+                        x = p;
+                        parent = p.parent.get();
+                    } else {
+                        if !Self::is_red(w.left.get()) {
+                            (*w.right.get()).red.set(false);
+                            w.red.set(true);
+                            w.rotate_left();
+                            w = &*p.left.get();
+                        }
+                        w.red.set(p.red.get());
+                        p.red.set(false);
+                        (*w.left.get()).red.set(false);
+                        p.rotate_right();
+                        x = root.get();
+                    }
+                }
+            }
+            if !x.is_null() {
+                (*x).red.set(false);
+            }
+        }
+    }
+}
+
+#[pinned_drop]
+impl PinnedDrop for TreeEntry {
+    fn drop(self: Pin<&mut Self>) {
+        self.as_ref().remove();
+    }
+}
+
+ktest! {
+    fn rbtree_insert_remove(ctx) {
+        const N: usize = 64;
+
+        #[repr(C)]
+        struct Node {
+            entry: TreeEntry,
+            key: usize,
+        }
+
+        // SAFETY: `Node` owns a `TreeEntry`, at its beginning.
+        unsafe impl TreeNode for Node {
+            type Key = usize;
+
+            fn get_tree_entry(self: Pin<&Self>) -> Pin<&TreeEntry> {
+                unsafe { Pin::new_unchecked(&self.get_ref().entry) }
+            }
+
+            fn from_tree_entry(tree_entry: *const TreeEntry) -> *const Self {
+                tree_entry as _
+            }
+
+            fn key(&self) -> usize {
+                self.key
+            }
+        }
+
+        /// Returns the black height of the subtree rooted at `entry`, whose parent is `parent`,
+        /// or None if a red node in it has a red child, two paths in it have different numbers
+        /// of black nodes, or a node does not link back to its parent.
+        fn black_height(entry: *const TreeEntry, parent: *const TreeEntry) -> Option<usize> {
+            if entry.is_null() {
+                return Some(1);
+            }
+            // SAFETY: `entry` is a node of the tree.
+            let e = unsafe { &*entry };
+            if !ptr::eq(e.parent.get(), parent) {
+                return None;
+            }
+            if e.red.get() && (TreeEntry::is_red(e.left.get()) || TreeEntry::is_red(e.right.get()))
+            {
+                return None;
+            }
+            let left = black_height(e.left.get(), entry)?;
+            let right = black_height(e.right.get(), entry)?;
+            if left != right {
+                return None;
+            }
+            Some(left + usize::from(!e.red.get()))
+        }
+
+        // Whether the tree is a red-black tree that holds the nodes in `present`, in order.
+        fn check(tree: Pin<&Tree<Node>>, present: &[bool; N]) -> bool {
+            let root = tree.root.get();
+            if TreeEntry::is_red(root) || black_height(root, ptr::null()).is_none() {
+                return false;
+            }
+            // SAFETY: the tree does not change while it is iterated.
+            let mut keys = unsafe { tree.iter_unchecked() }.map(|node| node.key);
+            (0..N).filter(|i| present[*i]).all(|i| keys.next() == Some(2 * i))
+                && keys.next().is_none()
+        }
+
+        let tree = Tree::<Node>::new();
+        // SAFETY: `tree` and `nodes` are not moved until they are dropped.
+        let tree = unsafe { Pin::new_unchecked(&tree) };
+        let nodes = array![i => Node { entry: TreeEntry::new(), key: 2 * i }; N];
+        let nodes = unsafe { Pin::new_unchecked(&nodes) };
+        let node = |i: usize| unsafe { nodes.map_unchecked(|nodes| &nodes[i]) };
+        // SAFETY: the nodes are alive.
+        let key = |node: Option<*const Node>| node.map(|node| unsafe { (*node).key });
+
+        // Insert and remove the nodes in scrambled orders.
+        let mut present = [false; N];
+        for j in 0..N {
+            let i = j * 37 % N;
+            tree.insert(node(i));
+            present[i] = true;
+            ktest_assert!(check(tree, &present));
+        }
+        for j in 0..N {
+            let i = (j * 23 + 5) % N;
+            if j % 3 != 0 {
+                tree.remove(node(i));
+                present[i] = false;
+                ktest_assert!(check(tree, &present));
+            }
+        }
+
+        for k in 0..2 * N + 1 {
+            let floor = (0..N).rev().find(|i| present[*i] && 2 * i <= k);
+            let ceil = (0..N).find(|i| present[*i] && 2 * i >= k);
+            ktest_assert!(key(tree.floor(&k)) == floor.map(|i| 2 * i));
+            ktest_assert!(key(tree.ceil(&k)) == ceil.map(|i| 2 * i));
+            let found = (0..N).find(|i| present[*i] && 2 * i == k);
+            ktest_assert!(key(tree.find(&k)) == found.map(|i| 2 * i));
+        }
+
+        // A node leaves the tree when it is dropped.
+        {
+            let extra = Node { entry: TreeEntry::new(), key: 1 };
+            // SAFETY: `extra` is not moved until it is dropped.
+            tree.insert(unsafe { Pin::new_unchecked(&extra) });
+            ktest_assert!(key(tree.first()) == Some(1));
+        }
+        ktest_assert!(check(tree, &present));
+
+        let first = (0..N).find(|i| present[*i]).map(|i| 2 * i);
+        ktest_assert!(key(tree.pop_first()) == first);
+        tree.clear();
+        ktest_assert!(tree.is_empty());
+    }
+}
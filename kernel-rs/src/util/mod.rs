@@ -3,11 +3,18 @@
 // Dead code is allowed in this file because not all components are used in the kernel.
 #![allow(dead_code)]
 
+pub mod bitmap;
 pub mod branded;
 pub mod etrace;
+pub mod hash;
+pub mod intrusive_hash_map;
+pub mod intrusive_heap;
 pub mod intrusive_list;
+pub mod intrusive_rbtree;
 pub mod pinned_array;
+pub mod ring_buffer;
 pub mod static_arc;
+pub mod static_vec;
 pub mod strong_pin;
 
 pub fn spin_loop() -> ! {
@@ -0,0 +1,156 @@
+//! Fixed-capacity ring buffer.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// A ring buffer that can hold up to `N` values of type `T`.
+///
+/// Usually, the buffer is accessed through `&mut self` (e.g. under a lock).
+/// Still, a single producer and a single consumer can concurrently access the buffer through
+/// `push_shared` and `pop_shared` without any lock. Since neither spins nor sleeps, one of them
+/// can be an interrupt handler.
+///
+/// # Safety
+///
+/// * `head` and `tail` are the number of values popped from the front and pushed to the back,
+///   respectively, wrapping around `usize::MAX`. Hence, `tail - head` is the number of values.
+/// * `buf[i % N]` is readable for `head <= i < tail`.
+pub struct RingBuffer<T: Copy, const N: usize> {
+    buf: UnsafeCell<[T; N]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// SAFETY: values are moved in and out of the buffer only by `push*` and `pop*`, which require
+// either `&mut self` or the single producer/consumer condition.
+unsafe impl<T: Copy + Send, const N: usize> Sync for RingBuffer<T, N> {}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    /// Returns an empty `RingBuffer` whose slots are filled with `fill`.
+    pub const fn new(fill: T) -> Self {
+        Self {
+            buf: UnsafeCell::new([fill; N]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the maximum number of values the buffer can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Returns the number of values in the buffer.
+    pub fn len(&self) -> usize {
+        self.tail
+            .load(Ordering::Acquire)
+            .wrapping_sub(self.head.load(Ordering::Acquire))
+    }
+
+    /// Returns the number of values that can be pushed before the buffer gets full.
+    pub fn available(&self) -> usize {
+        N - self.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len() == N
+    }
+
+    /// Returns `true` if the buffer holds at least `mark` values.
+    pub fn is_above(&self, mark: usize) -> bool {
+        self.len() >= mark
+    }
+
+    /// Returns `true` if the buffer holds at most `mark` values.
+    pub fn is_below(&self, mark: usize) -> bool {
+        self.len() <= mark
+    }
+
+    fn slot(&self, i: usize) -> *mut T {
+        // SAFETY: `i % N` is in bounds.
+        unsafe { (self.buf.get() as *mut T).add(i % N) }
+    }
+
+    /// Returns the value at the front, without popping it.
+    pub fn front(&self) -> Option<T> {
+        if self.is_empty() {
+            None
+        } else {
+            // SAFETY: invariant
+            Some(unsafe { *self.slot(self.head.load(Ordering::Acquire)) })
+        }
+    }
+
+    /// Returns the value at the back, i.e. the most recently pushed one.
+    pub fn back(&self) -> Option<T> {
+        if self.is_empty() {
+            None
+        } else {
+            let tail = self.tail.load(Ordering::Acquire);
+            // SAFETY: invariant
+            Some(unsafe { *self.slot(tail.wrapping_sub(1)) })
+        }
+    }
+
+    /// Pushes `value` at the back. If the buffer is full, returns `Err(value)`.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        // SAFETY: `&mut self` is the only producer and consumer.
+        unsafe { self.push_shared(value) }
+    }
+
+    /// Pops the value at the front, or returns `None` if the buffer is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        // SAFETY: `&mut self` is the only producer and consumer.
+        unsafe { self.pop_shared() }
+    }
+
+    /// Pops the value at the back, i.e. undoes the last `push`.
+    /// Returns `None` if the buffer is empty.
+    pub fn pop_back(&mut self) -> Option<T> {
+        let value = self.back()?;
+        let tail = self.tail.get_mut();
+        *tail = tail.wrapping_sub(1);
+        Some(value)
+    }
+
+    /// Removes all values.
+    pub fn clear(&mut self) {
+        *self.head.get_mut() = *self.tail.get_mut();
+    }
+
+    /// Same as `push`, but only needs `&self`.
+    ///
+    /// # Safety
+    ///
+    /// No other thread or interrupt handler may call `push_shared` concurrently.
+    pub unsafe fn push_shared(&self, value: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.load(Ordering::Acquire)) == N {
+            return Err(value);
+        }
+        // SAFETY: the consumer does not read the slot until we advance `tail`.
+        unsafe { *self.slot(tail) = value };
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Same as `pop`, but only needs `&self`.
+    ///
+    /// # Safety
+    ///
+    /// No other thread or interrupt handler may call `pop_shared` concurrently.
+    pub unsafe fn pop_shared(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        // SAFETY: the producer does not write the slot until we advance `head`.
+        let value = unsafe { *self.slot(head) };
+        self.head.store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+}
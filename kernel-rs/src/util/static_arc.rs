@@ -14,9 +14,12 @@ const BORROWED_MUT: usize = usize::MAX;
 /// * If `refcnt` equals n where n < `BORROWED_MUT`, n `Ref`s refer to `self`.
 /// * `RefMut` can mutate both `data` and `refcnt`.
 /// * `Ref` can mutate `refcnt` and read `data`.
+/// * If `weak` equals n, n `Weak`s refer to `self`.
+/// * `Weak` can mutate `refcnt` and `weak`.
 pub struct StaticArc<T> {
     data: T,
     refcnt: AtomicUsize,
+    weak: AtomicUsize,
 }
 
 /// # Safety
@@ -31,11 +34,20 @@ pub struct Ref<T>(NonNull<StaticArc<T>>);
 #[repr(transparent)]
 pub struct RefMut<T>(NonNull<StaticArc<T>>);
 
+/// A reference that does not allow reading `data`, but can be upgraded to a `Ref` if a `Ref` exists.
+///
+/// # Safety
+///
+/// * It holds a valid pointer.
+#[repr(transparent)]
+pub struct Weak<T>(NonNull<StaticArc<T>>);
+
 impl<T> StaticArc<T> {
     pub const fn new(data: T) -> Self {
         Self {
             data,
             refcnt: AtomicUsize::new(0),
+            weak: AtomicUsize::new(0),
         }
     }
 
@@ -49,6 +61,13 @@ impl<T> StaticArc<T> {
         self.rc().load(Ordering::Acquire) > 0
     }
 
+    /// Returns `true` if neither `Ref`, `RefMut`, nor `Weak` refers to `self`.
+    pub fn is_empty(mut self: StrongPinMut<'_, Self>) -> bool {
+        // SAFETY: invariant of StrongPinMut
+        let weak = unsafe { &(*self.ptr().as_ptr()).weak };
+        weak.load(Ordering::Acquire) == 0 && !self.as_mut().is_borrowed()
+    }
+
     #[allow(clippy::needless_lifetimes)]
     pub fn get_mut<'s>(mut self: StrongPinMut<'s, Self>) -> Option<&'s mut T> {
         if self.as_mut().is_borrowed() {
@@ -90,6 +109,11 @@ impl<T> Drop for StaticArc<T> {
             0,
             "dropped while borrowed"
         );
+        assert_eq!(
+            self.weak.load(Ordering::Acquire),
+            0,
+            "dropped while weakly referenced"
+        );
     }
 }
 
@@ -112,6 +136,12 @@ impl<T> Ref<T> {
         core::mem::forget(self);
         Ok(RefMut(ptr))
     }
+
+    pub fn downgrade(&self) -> Weak<T> {
+        // SAFETY: invariant
+        let _ = unsafe { &(*self.0.as_ptr()).weak }.fetch_add(1, Ordering::Relaxed);
+        Weak(self.0)
+    }
 }
 
 impl<T> Deref for Ref<T> {
@@ -168,3 +198,43 @@ impl<T> Drop for RefMut<T> {
         self.rc().store(0, Ordering::Release);
     }
 }
+
+impl<T> Weak<T> {
+    fn weak(&self) -> &AtomicUsize {
+        // SAFETY: invariant
+        unsafe { &(*self.0.as_ptr()).weak }
+    }
+
+    /// Returns a new `Ref` if a `Ref` still refers to the `StaticArc`.
+    pub fn upgrade(&self) -> Option<Ref<T>> {
+        // SAFETY: invariant
+        let rc = unsafe { &(*self.0.as_ptr()).refcnt };
+        loop {
+            let r = rc.load(Ordering::Acquire);
+
+            if r == 0 || r >= BORROWED_MUT - 1 {
+                return None;
+            }
+
+            if rc
+                .compare_exchange(r, r + 1, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                return Some(Ref(self.0));
+            }
+        }
+    }
+}
+
+impl<T> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        let _ = self.weak().fetch_add(1, Ordering::Relaxed);
+        Self(self.0)
+    }
+}
+
+impl<T> Drop for Weak<T> {
+    fn drop(&mut self) {
+        let _ = self.weak().fetch_sub(1, Ordering::Release);
+    }
+}
@@ -0,0 +1,174 @@
+//! Vector with a fixed capacity, which does not need heap allocation.
+
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::slice;
+
+/// A vector that can hold up to `N` values of type `T`.
+///
+/// # Safety
+///
+/// The first `len` elements of `buf` are initialized.
+pub struct StaticVec<T, const N: usize> {
+    buf: MaybeUninit<[T; N]>,
+    len: usize,
+}
+
+impl<T, const N: usize> StaticVec<T, N> {
+    /// Returns an empty `StaticVec`.
+    pub const fn new() -> Self {
+        Self {
+            buf: MaybeUninit::uninit(),
+            len: 0,
+        }
+    }
+
+    /// Returns the maximum number of values the vector can hold.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    fn as_ptr(&self) -> *const T {
+        self.buf.as_ptr() as *const T
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut T {
+        self.buf.as_mut_ptr() as *mut T
+    }
+
+    /// Appends `value` at the back. If the vector is full, returns `Err(value)`.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(value);
+        }
+        // SAFETY: `len < N`.
+        unsafe { ptr::write(self.as_mut_ptr().add(self.len), value) };
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Removes the last value and returns it, or `None` if the vector is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        // SAFETY: the element at `len` was initialized, and it is no longer considered so.
+        Some(unsafe { ptr::read(self.as_ptr().add(self.len)) })
+    }
+
+    /// Removes the value at `index` and returns it, shifting all values after it to the front.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "StaticVec::remove");
+        // SAFETY: `index < len`, and the moved elements stay within the first `len - 1` elements.
+        unsafe {
+            let p = self.as_mut_ptr().add(index);
+            let value = ptr::read(p);
+            ptr::copy(p.add(1), p, self.len - index - 1);
+            self.len -= 1;
+            value
+        }
+    }
+
+    /// Removes the value at `index` and returns it, replacing it with the last value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "StaticVec::swap_remove");
+        let last = self.len - 1;
+        self.swap(index, last);
+        self.pop().unwrap()
+    }
+
+    /// Keeps only the values for which `f` returns `true`, preserving their order.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        let len = self.len;
+        // If `f` panics, the remaining values are leaked rather than dropped twice.
+        self.len = 0;
+        let mut kept = 0;
+        for i in 0..len {
+            // SAFETY: the element at `i` is initialized, and `kept <= i`.
+            unsafe {
+                let p = self.as_mut_ptr().add(i);
+                if f(&*p) {
+                    ptr::copy(p, self.as_mut_ptr().add(kept), 1);
+                    kept += 1;
+                } else {
+                    ptr::drop_in_place(p);
+                }
+            }
+        }
+        self.len = kept;
+    }
+
+    /// Shortens the vector to `len` values, dropping the rest.
+    /// Does nothing if the vector is already shorter.
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            drop(self.pop());
+        }
+    }
+
+    /// Removes all values.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+}
+
+impl<T, const N: usize> Deref for StaticVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // SAFETY: invariant
+        unsafe { slice::from_raw_parts(self.as_ptr(), self.len) }
+    }
+}
+
+impl<T, const N: usize> DerefMut for StaticVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        // SAFETY: invariant
+        unsafe { slice::from_raw_parts_mut(self.as_mut_ptr(), self.len) }
+    }
+}
+
+impl<T: fmt::Debug, const N: usize> fmt::Debug for StaticVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<'s, T, const N: usize> IntoIterator for &'s StaticVec<T, N> {
+    type IntoIter = slice::Iter<'s, T>;
+    type Item = &'s T;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'s, T, const N: usize> IntoIterator for &'s mut StaticVec<T, N> {
+    type IntoIter = slice::IterMut<'s, T>;
+    type Item = &'s mut T;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl<T, const N: usize> Drop for StaticVec<T, N> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
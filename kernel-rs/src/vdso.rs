@@ -0,0 +1,32 @@
+//! The page that the kernel shares with each process, from which the process reads the clocks
+//! and the CPU it runs on without a system call. See `rv6_abi::vdso` for its layout, and
+//! user/vdso.c for the functions that read it.
+//!
+//! Each process has a page of its own at VDSO, which it can only read, and which the kernel
+//! fills each time it returns to the process, with interrupts disabled, so the process never
+//! sees it half-written. A process runs on one CPU at a time, so the CPU in its page is the one
+//! it runs on until its next trap, and the tick count is as recent as its last trap, which each
+//! clock tick causes. The parameters of the realtime clock are copied only when they changed, so
+//! that returning to a process does not take the lock of the realtime clock.
+
+use rv6_abi::vdso::{VdsoData, VDSO_ADDR};
+use static_assertions::const_assert_eq;
+use zerocopy::LayoutVerified;
+
+use crate::{arch::memlayout::VDSO, cpu::cpuid, proc::KernelCtx};
+
+const_assert_eq!(VDSO_ADDR, VDSO);
+
+impl KernelCtx<'_, '_> {
+    /// Fills the page shared with the current process, before returning to it.
+    /// Interrupts must be disabled.
+    pub fn update_vdso(&mut self) {
+        let clocks = self.kernel().clocks();
+        let page = self.proc_mut().memory_mut().vdso_mut();
+        let (data, _) = LayoutVerified::<_, VdsoData>::new_from_prefix(&mut page[..])
+            .expect("update_vdso: unaligned");
+        let data = data.into_mut();
+        clocks.fill_vdso(data);
+        data.cpu = cpuid() as u32;
+    }
+}
@@ -19,8 +19,16 @@ use bitflags::bitflags;
 use crate::arch::memlayout::VIRTIO0;
 
 mod virtio_disk;
+mod virtio_rng;
+mod virtio_vsock;
 
 pub use virtio_disk::VirtioDisk;
+pub use virtio_rng::VirtioRng;
+pub use virtio_vsock::{
+    VirtioVsock, VsockHdr, MAX_PAYLOAD, VSOCK_OP_CREDIT_REQUEST, VSOCK_OP_CREDIT_UPDATE,
+    VSOCK_OP_REQUEST, VSOCK_OP_RESPONSE, VSOCK_OP_RST, VSOCK_OP_RW, VSOCK_OP_SHUTDOWN,
+    VSOCK_SHUTDOWN_RCV, VSOCK_SHUTDOWN_SEND, VSOCK_TYPE_STREAM,
+};
 
 /// Memory mapped IO registers.
 /// The kernel and virtio driver communicates to each other using these registers.
@@ -35,7 +43,7 @@ enum MmioRegs {
     MagicValue = 0x000,
     /// version; 1 is legacy
     Version = 0x004,
-    /// device type; 1 is net, 2 is disk
+    /// device type; 1 is net, 2 is disk, 4 is entropy source, 19 is socket
     DeviceId = 0x008,
     /// 0x554d4551
     VendorId = 0x00c,
@@ -61,18 +69,28 @@ enum MmioRegs {
     InterruptAck = 0x064,
     /// read/write
     Status = 0x070,
+    /// The u64 at the start of the device-specific configuration, read-only: the capacity of a
+    /// disk in 512-byte sectors, or the CID of a socket device
+    ConfigLow = 0x100,
+    ConfigHigh = 0x104,
 }
 
 impl MmioRegs {
     fn read(self) -> u32 {
+        self.read_at(VIRTIO0)
+    }
+
+    /// Reads the register of the device whose registers start at `base`.
+    /// `base` must be VIRTIO0, VIRTIO1, or VIRTIO2.
+    fn read_at(self, base: usize) -> u32 {
         // SAFETY:
-        // * `src` is valid, as the kernel can access [VIRTIO0..VIRTIO0+PGSIZE).
+        // * `src` is valid, as the kernel can access [base..base+PGSIZE).
         // * `src` is properly aligned, as self % 4 == 0.
         // * `src` points to a properly initialized value, as u32 does not have
         //   any internal structure to be initialized.
         // * volatile concurrent accesses are safe.
         //   (https://github.com/kaist-cp/rv6/issues/188#issuecomment-683548362)
-        unsafe { ptr::read_volatile((VIRTIO0 as *mut u8).add(self as _) as _) }
+        unsafe { ptr::read_volatile((base as *mut u8).add(self as _) as _) }
     }
 
     /// # Safety
@@ -81,12 +99,22 @@ impl MmioRegs {
     /// For example, after writing at `QueueNotify`, the virtio driver reads/writes the address given by the kernel.
     /// If a wrong address was given, this could lead to undefined behavior.
     unsafe fn write(self, dst: u32) {
+        unsafe { self.write_at(VIRTIO0, dst) }
+    }
+
+    /// Writes the register of the device whose registers start at `base`.
+    /// `base` must be VIRTIO0, VIRTIO1, or VIRTIO2.
+    ///
+    /// # Safety
+    ///
+    /// See `MmioRegs::write`.
+    unsafe fn write_at(self, base: usize, dst: u32) {
         // SAFETY:
-        // * `dst` is valid, as the kernel can access [VIRTIO0..VIRTIO0+PGSIZE).
+        // * `dst` is valid, as the kernel can access [base..base+PGSIZE).
         // * `dst` is properly aligned, as self % 4 == 0.
         // * volatile concurrent accesses are safe.
         //   (https://github.com/kaist-cp/rv6/issues/188#issuecomment-683548362)
-        unsafe { ptr::write_volatile((VIRTIO0 as *mut u8).add(self as _) as _, dst) }
+        unsafe { ptr::write_volatile((base as *mut u8).add(self as _) as _, dst) }
     }
 
     /// Checks the virtio disk's properties.
@@ -96,7 +124,10 @@ impl MmioRegs {
             "could not find virtio disk"
         );
         assert!(MmioRegs::Version.read() == 1, "could not find virtio disk");
-        assert!(MmioRegs::DeviceId.read() == 2, "could not find virtio disk");
+        assert!(
+            MmioRegs::DeviceId.read() == VIRTIO_ID_BLOCK,
+            "could not find virtio disk"
+        );
         assert!(
             MmioRegs::VendorId.read() == 0x554d4551,
             "could not find virtio disk"
@@ -171,13 +202,32 @@ impl MmioRegs {
     }
 
     /// Acknowledges all interrupts.
-    fn intr_ack_all() {
+    /// Returns whether the device raised any.
+    fn intr_ack_all() -> bool {
         let intr_status = MmioRegs::InterruptStatus.read() & 0x3;
         // SAFETY: simply acknowledging interrupts does not cause undefined behavior.
         unsafe {
             MmioRegs::InterruptAck.write(intr_status);
         }
+        intr_status != 0
+    }
+}
+
+/// Device type of disks.
+pub const VIRTIO_ID_BLOCK: u32 = 2;
+
+/// Returns the device type of the virtio device whose registers start at `base`, or `None` if
+/// the slot is empty.
+/// `base` must be VIRTIO0, VIRTIO1, or VIRTIO2.
+pub fn virtio_device_id(base: usize) -> Option<u32> {
+    if MmioRegs::MagicValue.read_at(base) != 0x74726976
+        || MmioRegs::Version.read_at(base) != 1
+        || MmioRegs::VendorId.read_at(base) != 0x554d4551
+    {
+        return None;
     }
+    // An empty slot has device type 0.
+    Some(MmioRegs::DeviceId.read_at(base)).filter(|&id| id != 0)
 }
 
 bitflags! {
@@ -11,8 +11,6 @@ use core::ptr;
 use core::sync::atomic::{fence, Ordering};
 
 use arrayvec::ArrayVec;
-use bitmaps::Bitmap;
-use const_zero::const_zero;
 use pin_project::pin_project;
 
 use super::{
@@ -20,14 +18,32 @@ use super::{
     VIRTIO_BLK_T_IN, VIRTIO_BLK_T_OUT,
 };
 use crate::{
-    arch::addr::{PGSHIFT, PGSIZE},
+    arch::{
+        addr::{PGSHIFT, PGSIZE},
+        memlayout::{VIRTIO0, VIRTIO0_IRQ},
+    },
     bio::Buf,
+    driver::Device,
+    hal::{hal, Hal},
+    irq::{IrqChip, IrqReturn},
     kernel::KernelRef,
     lock::{SleepableLock, SleepableLockGuard},
     param::BSIZE,
     proc::KernelCtx,
+    psi::{PsiResource, PsiState},
+    register_driver,
+    softirq::SoftIrq,
+    time::ktime_now,
+    trace_event,
+    tracepoint::TraceEvent,
+    util::{
+        bitmap::{self, Bitmap},
+        branded::{BlockNo, DevNo},
+    },
 };
 
+register_driver!("virtio,device2", probe_disk);
+
 // It must be page-aligned.
 // It needs repr(C) because it is read by device.
 // https://github.com/kaist-cp/rv6/issues/52
@@ -60,7 +76,7 @@ pub struct VirtioDisk {
 #[pin_project]
 struct DiskInfo {
     /// is a descriptor allocated?
-    allocated: Bitmap<NUM>,
+    allocated: Bitmap<{ bitmap::words(NUM) }>,
 
     /// we've looked this far in used.
     used_idx: u16,
@@ -114,8 +130,7 @@ impl VirtioDisk {
 impl DiskInfo {
     const fn new() -> Self {
         Self {
-            // SAFETY: bitmap is safe to be zero-initialized.
-            allocated: unsafe { const_zero!(Bitmap::<NUM>) },
+            allocated: Bitmap::new(NUM),
             used_idx: 0,
             inflight: [InflightInfo::new(); NUM],
             ops: [VirtIOBlockOutHeader::default(); NUM],
@@ -179,10 +194,18 @@ impl Drop for Descriptor {
     }
 }
 
+/// Sets up the disk in the first virtio slot, and requests its interrupt.
+fn probe_disk(mut hal: Pin<&mut Hal>, dev: &Device) {
+    if let Device::VirtioMmio { base: VIRTIO0, .. } = dev {
+        hal.as_mut().disk_mut().get_pin_mut().as_ref().init();
+        hal.request_irq(IrqChip::Plic, VIRTIO0_IRQ, "virtio-blk", VirtioDisk::intr);
+    }
+}
+
 impl SleepableLock<VirtioDisk> {
     /// Return a locked Buf with the `latest` contents of the indicated block.
     /// If buf.valid is true, we don't need to access Disk.
-    pub fn read(self: Pin<&Self>, dev: u32, blockno: u32, ctx: &KernelCtx<'_, '_>) -> Buf {
+    pub fn read(self: Pin<&Self>, dev: DevNo, blockno: BlockNo, ctx: &KernelCtx<'_, '_>) -> Buf {
         let mut buf = ctx.kernel().bcache().get_buf(dev, blockno).lock(ctx);
         if !buf.deref_inner().valid {
             VirtioDisk::rw(&mut self.pinned_lock(), &mut buf, false, ctx);
@@ -241,7 +264,97 @@ impl VirtioDisk {
             );
         }
 
-        // plic.rs and trap.rs arrange for interrupts from VIRTIO0_IRQ.
+        // probe_disk requests the interrupts from VIRTIO0_IRQ.
+    }
+
+    /// Returns the size of the disk in blocks.
+    pub fn capacity(&self) -> u64 {
+        let sectors =
+            (MmioRegs::ConfigHigh.read() as u64) << 32 | MmioRegs::ConfigLow.read() as u64;
+        sectors / (BSIZE / 512) as u64
+    }
+
+    /// Resets the device and the queue, abandoning the requests in flight, so that the CPU that
+    /// panicked can use the disk with `rw_polled`.
+    ///
+    /// # Safety
+    ///
+    /// No other CPU may use the disk afterwards, as their requests will never complete.
+    pub unsafe fn reset(mut self: Pin<&mut Self>) {
+        MmioRegs::set_status(&VirtIOStatus::empty());
+        let this = self.as_mut().project();
+        *this.desc = [VirtqDesc::new(); NUM];
+        *this.avail = VirtqAvail::new();
+        *this.used = VirtqUsed::new();
+        let info = this.info.project();
+        *info.allocated = Bitmap::new(NUM);
+        *info.used_idx = 0;
+        *info.inflight = [InflightInfo::new(); NUM];
+        self.as_ref().init();
+    }
+
+    /// Reads or writes the block `blockno` from or to `data` without interrupts, by polling the
+    /// device until it finishes, for at most a second. Must be called only after `reset`, and
+    /// `data` must be identically mapped.
+    /// Returns `true` on success.
+    pub fn rw_polled(
+        self: Pin<&mut Self>,
+        blockno: u32,
+        data: &mut [u8; BSIZE],
+        write: bool,
+    ) -> bool {
+        let this = self.project();
+        let info = this.info.project();
+
+        // The same three descriptors as `rw` would use, as nobody else uses the disk.
+        info.ops[0] = VirtIOBlockOutHeader::new(write, blockno as usize * (BSIZE / 512));
+        this.desc[0] = VirtqDesc {
+            addr: &info.ops[0] as *const _ as _,
+            len: mem::size_of::<VirtIOBlockOutHeader>() as _,
+            flags: VirtqDescFlags::NEXT,
+            next: 1,
+        };
+        this.desc[1] = VirtqDesc {
+            addr: data.as_ptr() as _,
+            len: BSIZE as _,
+            flags: if write {
+                VirtqDescFlags::NEXT
+            } else {
+                VirtqDescFlags::NEXT | VirtqDescFlags::WRITE
+            },
+            next: 2,
+        };
+        info.inflight[0].status = true;
+        this.desc[2] = VirtqDesc {
+            addr: &info.inflight[0].status as *const _ as _,
+            len: 1,
+            flags: VirtqDescFlags::WRITE,
+            next: 0,
+        };
+
+        let ring_idx = this.avail.idx as usize % NUM;
+        this.avail.ring[ring_idx] = 0;
+        fence(Ordering::SeqCst);
+        this.avail.idx += 1;
+        fence(Ordering::SeqCst);
+
+        // SAFETY: the all three descriptors' fields are well set.
+        unsafe {
+            MmioRegs::notify_queue(0);
+        }
+
+        let deadline = ktime_now() + 1_000_000_000;
+        // SAFETY: `used.id` is a valid u16, which the device writes.
+        while unsafe { ptr::read_volatile(&this.used.id) } == *info.used_idx {
+            if ktime_now() > deadline {
+                return false;
+            }
+        }
+        fence(Ordering::SeqCst);
+        *info.used_idx += 1;
+        let _ = MmioRegs::intr_ack_all();
+        // SAFETY: `status` is a valid bool, which the device sets to 0 on success.
+        !unsafe { ptr::read_volatile(&info.inflight[0].status) }
     }
 
     // This method reads and writes disk by reading and writing MMIO registers.
@@ -254,7 +367,7 @@ impl VirtioDisk {
         write: bool,
         ctx: &KernelCtx<'_, '_>,
     ) {
-        let sector: usize = (*b).blockno as usize * (BSIZE / 512);
+        let sector: usize = (*b).blockno.into_u32() as usize * (BSIZE / 512);
 
         // The spec's Section 5.2 says that legacy block operations use
         // three descriptors: one for type/reserved/sector, one for the
@@ -341,11 +454,16 @@ impl VirtioDisk {
         unsafe {
             MmioRegs::notify_queue(0);
         }
+        trace_event!(TraceEvent::DiskIssue, b.blockno.into_u32(), write);
 
         // Wait for virtio_disk_intr() to say request has finished.
+        let io = Some(PsiState::Stalled(PsiResource::Io));
+        hal().psi().change(None, io);
         while b.deref_inner().disk {
             b.vdisk_request_waitchannel.sleep(guard, ctx);
         }
+        hal().psi().change(io, None);
+        trace_event!(TraceEvent::DiskComplete, b.blockno.into_u32(), write);
         // As it assigns null, the invariant of inflight is maintained even if
         // b: &mut Buf becomes invalid after this method returns.
         guard.get_pin_mut().project().info.project().inflight[desc[0].idx].b = ptr::null_mut();
@@ -353,15 +471,23 @@ impl VirtioDisk {
         guard.wakeup(ctx.kernel());
     }
 
-    pub fn intr(self: Pin<&mut Self>, kernel: KernelRef<'_, '_>) {
+    /// Handles an interrupt of the disk, by acknowledging it and raising its soft interrupt.
+    fn intr(_kernel: KernelRef<'_, '_>) -> IrqReturn {
         // The device won't raise another interrupt until we tell it
         // we've seen this interrupt, which the following line does.
         // This may race with the device writing new entries to
         // the "used" ring, in which case we may process the new
-        // completion entries in this interrupt, and have nothing to do
-        // in the next interrupt, which is harmless.
-        MmioRegs::intr_ack_all();
+        // completion entries in this soft interrupt, and have nothing
+        // to do in the next one, which is harmless.
+        if !MmioRegs::intr_ack_all() {
+            return IrqReturn::None;
+        }
+        hal().softirqs().raise(SoftIrq::Disk);
+        IrqReturn::Handled
+    }
 
+    /// The soft interrupt of the disk, which wakes up the processes whose requests completed.
+    pub fn complete(self: Pin<&mut Self>, kernel: KernelRef<'_, '_>) {
         fence(Ordering::SeqCst);
 
         // The device increments disk.used->idx when it
@@ -391,8 +517,7 @@ impl VirtioDisk {
     /// Find a free descriptor, mark it non-free, return its index.
     fn alloc(self: Pin<&mut Self>) -> Option<Descriptor> {
         let info = self.project().info.project();
-        let idx = info.allocated.first_false_index()?;
-        let _ = info.allocated.set(idx, true);
+        let idx = info.allocated.alloc()?;
         Some(Descriptor::new(idx))
     }
 
@@ -0,0 +1,172 @@
+/// Driver for qemu's virtio entropy source.
+/// The kernel reads from it only to seed its random number generator, so the driver polls the
+/// device instead of taking interrupts.
+///
+/// qemu ... -device virtio-rng-device,bus=virtio-mmio-bus.1
+use core::cmp;
+use core::hint::spin_loop;
+use core::marker::PhantomPinned;
+use core::pin::Pin;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+use pin_project::pin_project;
+
+use super::{MmioRegs, VirtIOStatus, VirtqAvail, VirtqDesc, VirtqDescFlags, VirtqUsed, NUM};
+use crate::{
+    arch::{
+        addr::{PGSHIFT, PGSIZE},
+        memlayout::VIRTIO1,
+    },
+    driver::Device,
+    hal::Hal,
+    register_driver,
+};
+
+/// Device type of entropy sources.
+const VIRTIO_ID_RNG: u32 = 4;
+
+/// Maximum number of bytes read from the device at once.
+const RNG_BUF_SIZE: usize = 64;
+
+register_driver!("virtio,device4", probe_rng);
+
+// It must be page-aligned.
+// It needs repr(C) because it is read by device.
+// https://github.com/kaist-cp/rv6/issues/52
+#[repr(C, align(4096))]
+#[pin_project]
+pub struct VirtioRng {
+    /// Only the first descriptor is used, since there is at most one request at a time.
+    desc: [VirtqDesc; NUM],
+
+    avail: VirtqAvail,
+
+    used: VirtqUsed,
+
+    /// The device writes random bytes here.
+    buf: [u8; RNG_BUF_SIZE],
+
+    /// we've looked this far in used.
+    used_idx: u16,
+
+    /// Whether qemu provides the device.
+    present: bool,
+
+    #[pin]
+    _marker: PhantomPinned,
+}
+
+/// Sets up the entropy source in the second virtio slot, which the kernel polls.
+fn probe_rng(hal: Pin<&mut Hal>, dev: &Device) {
+    if let Device::VirtioMmio { base: VIRTIO1, .. } = dev {
+        hal.rng_mut().get_pin_mut().init();
+    }
+}
+
+impl VirtioRng {
+    pub const fn new() -> Self {
+        Self {
+            desc: [VirtqDesc::new(); NUM],
+            avail: VirtqAvail::new(),
+            used: VirtqUsed::new(),
+            buf: [0; RNG_BUF_SIZE],
+            used_idx: 0,
+            present: false,
+            _marker: PhantomPinned,
+        }
+    }
+
+    /// Initializes the device, if qemu provides one.
+    pub fn init(self: Pin<&mut Self>) {
+        if MmioRegs::MagicValue.read_at(VIRTIO1) != 0x74726976
+            || MmioRegs::Version.read_at(VIRTIO1) != 1
+            || MmioRegs::DeviceId.read_at(VIRTIO1) != VIRTIO_ID_RNG
+            || MmioRegs::VendorId.read_at(VIRTIO1) != 0x554d4551
+        {
+            return;
+        }
+
+        let this = self.project();
+        let mut status = VirtIOStatus::ACKNOWLEDGE;
+        // SAFETY: setting status bits, features, and the page size does not cause side effects.
+        unsafe {
+            MmioRegs::Status.write_at(VIRTIO1, status.bits());
+            status.insert(VirtIOStatus::DRIVER);
+            MmioRegs::Status.write_at(VIRTIO1, status.bits());
+
+            // The device has no features we use.
+            MmioRegs::DriverFeatures.write_at(VIRTIO1, 0);
+            status.insert(VirtIOStatus::FEATURES_OK);
+            MmioRegs::Status.write_at(VIRTIO1, status.bits());
+            status.insert(VirtIOStatus::DRIVER_OK);
+            MmioRegs::Status.write_at(VIRTIO1, status.bits());
+            MmioRegs::GuestPageSize.write_at(VIRTIO1, PGSIZE as _);
+        }
+
+        // Initialize queue 0.
+        // SAFETY: simply selecting the queue does not cause side effects.
+        unsafe { MmioRegs::QueueSel.write_at(VIRTIO1, 0) };
+        if MmioRegs::QueueNumMax.read_at(VIRTIO1) < NUM as u32 {
+            return;
+        }
+        // SAFETY: `desc`, `avail`, and `used` form a virtqueue of NUM descriptors, as the
+        // device expects.
+        unsafe {
+            MmioRegs::QueueNum.write_at(VIRTIO1, NUM as _);
+            MmioRegs::QueuePfn.write_at(VIRTIO1, (this.desc.as_ptr() as usize >> PGSHIFT) as _);
+        }
+        *this.present = true;
+    }
+
+    /// Fills `out` with random bytes from the device, spinning until the device responds.
+    /// Returns the number of bytes written, which may be less than `out.len()`, and is 0 if
+    /// there is no device.
+    pub fn read(self: Pin<&mut Self>, out: &mut [u8]) -> usize {
+        let this = self.project();
+        if !*this.present {
+            return 0;
+        }
+
+        let len = cmp::min(out.len(), RNG_BUF_SIZE);
+        this.desc[0] = VirtqDesc {
+            addr: this.buf.as_ptr() as _,
+            len: len as _,
+            flags: VirtqDescFlags::WRITE,
+            next: 0,
+        };
+        let ring_idx = this.avail.idx as usize % NUM;
+        this.avail.ring[ring_idx] = 0;
+
+        fence(Ordering::SeqCst);
+
+        // Tell the device another avail ring entry is available.
+        this.avail.idx = this.avail.idx.wrapping_add(1);
+
+        fence(Ordering::SeqCst);
+
+        // SAFETY: the descriptor points to `buf`, which is valid for `len` bytes.
+        unsafe { MmioRegs::QueueNotify.write_at(VIRTIO1, 0) };
+
+        // SAFETY: `used.id` is a valid u16, which the device increments when it is done.
+        while unsafe { ptr::read_volatile(&this.used.id) } == *this.used_idx {
+            spin_loop();
+        }
+
+        fence(Ordering::SeqCst);
+
+        let n = cmp::min(
+            this.used.ring[*this.used_idx as usize % NUM].len as usize,
+            len,
+        );
+        *this.used_idx = this.used_idx.wrapping_add(1);
+
+        // The device's interrupt is not enabled in the PLIC, but acknowledge it anyway.
+        let intr_status = MmioRegs::InterruptStatus.read_at(VIRTIO1) & 0x3;
+        // SAFETY: simply acknowledging interrupts does not cause undefined behavior.
+        unsafe { MmioRegs::InterruptAck.write_at(VIRTIO1, intr_status) };
+
+        out[..n].copy_from_slice(&this.buf[..n]);
+        n
+    }
+}
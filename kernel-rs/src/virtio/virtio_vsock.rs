@@ -0,0 +1,369 @@
+/// Driver for qemu's virtio socket device, which carries the packets of the connections of
+/// `vsock` between the machine and the host.
+/// The device takes interrupts, since packets arrive whenever the host sends them.
+///
+/// qemu ... -device vhost-vsock-device,guest-cid=3,bus=virtio-mmio-bus.2
+use core::marker::PhantomPinned;
+use core::mem;
+use core::pin::Pin;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+use pin_project::pin_project;
+use zerocopy::{AsBytes, FromBytes, LayoutVerified, Unaligned};
+
+use super::{MmioRegs, VirtIOStatus, VirtqAvail, VirtqDesc, VirtqDescFlags, VirtqUsed, NUM};
+use crate::{
+    arch::{
+        addr::{PGSHIFT, PGSIZE},
+        memlayout::{VIRTIO2, VIRTIO2_IRQ},
+    },
+    driver::Device,
+    hal::Hal,
+    irq::IrqChip,
+    register_driver, some_or,
+    vsock::vsock_intr,
+};
+
+/// Device type of socket devices.
+const VIRTIO_ID_VSOCK: u32 = 19;
+
+/// Queue numbers.
+const RX_QUEUE: u32 = 0;
+const TX_QUEUE: u32 = 1;
+const EVENT_QUEUE: u32 = 2;
+
+/// Size of the buffer of a packet, header included. The device splits the data it receives
+/// from the host into packets that fit.
+const PKT_SIZE: usize = 1024;
+
+/// Size of the header of a packet.
+pub const HDR_SIZE: usize = mem::size_of::<VsockHdr>();
+
+/// Maximum number of data bytes in a packet.
+pub const MAX_PAYLOAD: usize = PKT_SIZE - HDR_SIZE;
+
+/// Packet types.
+pub const VSOCK_TYPE_STREAM: u16 = 1;
+
+/// Packet operations.
+pub const VSOCK_OP_REQUEST: u16 = 1;
+pub const VSOCK_OP_RESPONSE: u16 = 2;
+pub const VSOCK_OP_RST: u16 = 3;
+pub const VSOCK_OP_SHUTDOWN: u16 = 4;
+pub const VSOCK_OP_RW: u16 = 5;
+pub const VSOCK_OP_CREDIT_UPDATE: u16 = 6;
+pub const VSOCK_OP_CREDIT_REQUEST: u16 = 7;
+
+/// Flags of VSOCK_OP_SHUTDOWN: the sender will receive no more data, or send no more data.
+pub const VSOCK_SHUTDOWN_RCV: u32 = 1;
+pub const VSOCK_SHUTDOWN_SEND: u32 = 2;
+
+/// The event that the device sends when it was reset, e.g. after the machine migrated, which
+/// breaks all connections with the host.
+const VSOCK_EVENT_TRANSPORT_RESET: u32 = 0;
+
+register_driver!("virtio,device13", probe_vsock);
+
+/// The header of a packet, from the spec.
+/// https://docs.oasis-open.org/virtio/virtio/v1.1/csprd01/virtio-v1.1-csprd01.html#x1-3960006
+// It needs repr(C) because it is read by device.
+// https://github.com/kaist-cp/rv6/issues/52
+#[repr(C, packed)]
+#[derive(Copy, Clone, Default, AsBytes, FromBytes, Unaligned)]
+pub struct VsockHdr {
+    pub src_cid: u64,
+    pub dst_cid: u64,
+    pub src_port: u32,
+    pub dst_port: u32,
+
+    /// Number of data bytes that follow the header.
+    pub len: u32,
+
+    pub typ: u16,
+    pub op: u16,
+    pub flags: u32,
+
+    /// Size of the sender's receive buffer.
+    pub buf_alloc: u32,
+
+    /// Number of bytes the sender has taken out of its receive buffer.
+    pub fwd_cnt: u32,
+}
+
+/// A virtqueue: descriptors, the avail ring after them, and the used ring on the next page.
+// It must be page-aligned.
+// It needs repr(C) because it is read by device.
+// https://github.com/kaist-cp/rv6/issues/52
+#[repr(C, align(4096))]
+struct Virtq {
+    desc: [VirtqDesc; NUM],
+    avail: VirtqAvail,
+    used: VirtqUsed,
+}
+
+impl Virtq {
+    const fn new() -> Self {
+        Self {
+            desc: [VirtqDesc::new(); NUM],
+            avail: VirtqAvail::new(),
+            used: VirtqUsed::new(),
+        }
+    }
+
+    /// Gives the device the buffer of the `i`th descriptor.
+    fn push(&mut self, i: usize) {
+        let ring_idx = self.avail.idx as usize % NUM;
+        self.avail.ring[ring_idx] = i as u16;
+
+        fence(Ordering::SeqCst);
+
+        // Tell the device another avail ring entry is available.
+        self.avail.idx = self.avail.idx.wrapping_add(1);
+
+        fence(Ordering::SeqCst);
+    }
+
+    /// Takes back the next buffer that the device is done with, if any, and returns the index
+    /// of its descriptor and the number of bytes the device wrote to it.
+    fn pop(&mut self, used_idx: &mut u16) -> Option<(usize, usize)> {
+        // SAFETY: `used.id` is a valid u16, which the device increments when it adds an entry.
+        if unsafe { ptr::read_volatile(&self.used.id) } == *used_idx {
+            return None;
+        }
+
+        fence(Ordering::SeqCst);
+
+        let elem = self.used.ring[*used_idx as usize % NUM];
+        *used_idx = used_idx.wrapping_add(1);
+        Some((elem.id as usize, elem.len as usize))
+    }
+}
+
+// It must be page-aligned.
+// It needs repr(C) because it is read by device.
+// https://github.com/kaist-cp/rv6/issues/52
+#[repr(C, align(4096))]
+#[pin_project]
+pub struct VirtioVsock {
+    rx: Virtq,
+
+    tx: Virtq,
+
+    event: Virtq,
+
+    /// The device writes the packets it receives here, one for each descriptor of `rx`.
+    rx_bufs: [[u8; PKT_SIZE]; NUM],
+
+    /// The packets being sent, one for each descriptor of `tx`.
+    tx_bufs: [[u8; PKT_SIZE]; NUM],
+
+    /// The device writes events here, one for each descriptor of `event`.
+    events: [u32; NUM],
+
+    /// Whether the device is still sending the packet of each descriptor of `tx`.
+    tx_busy: [bool; NUM],
+
+    /// we've looked this far in the used rings of `rx`, `tx`, and `event`.
+    used_idx: [u16; 3],
+
+    /// The CID of the machine, if qemu provides the device.
+    cid: Option<u64>,
+
+    #[pin]
+    _marker: PhantomPinned,
+}
+
+/// Sets up the socket device in the third virtio slot.
+fn probe_vsock(mut hal: Pin<&mut Hal>, dev: &Device) {
+    if let Device::VirtioMmio { base: VIRTIO2, .. } = dev {
+        hal.as_mut().vsock_mut().get_pin_mut().init();
+        hal.request_irq(IrqChip::Plic, VIRTIO2_IRQ, "virtio-vsock", vsock_intr);
+    }
+}
+
+impl VirtioVsock {
+    pub const fn new() -> Self {
+        Self {
+            rx: Virtq::new(),
+            tx: Virtq::new(),
+            event: Virtq::new(),
+            rx_bufs: [[0; PKT_SIZE]; NUM],
+            tx_bufs: [[0; PKT_SIZE]; NUM],
+            events: [0; NUM],
+            tx_busy: [false; NUM],
+            used_idx: [0; 3],
+            cid: None,
+            _marker: PhantomPinned,
+        }
+    }
+
+    /// Initializes the device, and gives it buffers to receive packets and events in.
+    pub fn init(self: Pin<&mut Self>) {
+        if MmioRegs::DeviceId.read_at(VIRTIO2) != VIRTIO_ID_VSOCK {
+            return;
+        }
+
+        let this = self.project();
+        let mut status = VirtIOStatus::ACKNOWLEDGE;
+        // SAFETY: setting status bits, features, and the page size does not cause side effects.
+        unsafe {
+            MmioRegs::Status.write_at(VIRTIO2, status.bits());
+            status.insert(VirtIOStatus::DRIVER);
+            MmioRegs::Status.write_at(VIRTIO2, status.bits());
+
+            // The device has no features we use.
+            MmioRegs::DriverFeatures.write_at(VIRTIO2, 0);
+            status.insert(VirtIOStatus::FEATURES_OK);
+            MmioRegs::Status.write_at(VIRTIO2, status.bits());
+            MmioRegs::GuestPageSize.write_at(VIRTIO2, PGSIZE as _);
+        }
+
+        // Unlike the other devices, the queues must be ready before DRIVER_OK, when the host
+        // starts serving them.
+        for (num, queue) in [RX_QUEUE, TX_QUEUE, EVENT_QUEUE]
+            .iter()
+            .zip([&*this.rx, &*this.tx, &*this.event].iter())
+        {
+            // SAFETY: simply selecting the queue does not cause side effects.
+            unsafe { MmioRegs::QueueSel.write_at(VIRTIO2, *num) };
+            if MmioRegs::QueueNumMax.read_at(VIRTIO2) < NUM as u32 {
+                return;
+            }
+            // SAFETY: `queue` is a virtqueue of NUM descriptors, as the device expects, and
+            // it gives no buffers to the device yet.
+            unsafe {
+                MmioRegs::QueueNum.write_at(VIRTIO2, NUM as _);
+                MmioRegs::QueuePfn.write_at(VIRTIO2, (*queue as *const _ as usize >> PGSHIFT) as _);
+            }
+        }
+
+        for i in 0..NUM {
+            this.rx.desc[i] = VirtqDesc {
+                addr: this.rx_bufs[i].as_ptr() as _,
+                len: PKT_SIZE as _,
+                flags: VirtqDescFlags::WRITE,
+                next: 0,
+            };
+            this.rx.push(i);
+            this.event.desc[i] = VirtqDesc {
+                addr: &this.events[i] as *const _ as _,
+                len: mem::size_of::<u32>() as _,
+                flags: VirtqDescFlags::WRITE,
+                next: 0,
+            };
+            this.event.push(i);
+        }
+
+        status.insert(VirtIOStatus::DRIVER_OK);
+        // SAFETY: the descriptors of `rx` and `event` point to buffers that the device may write.
+        unsafe {
+            MmioRegs::Status.write_at(VIRTIO2, status.bits());
+            MmioRegs::QueueNotify.write_at(VIRTIO2, RX_QUEUE);
+            MmioRegs::QueueNotify.write_at(VIRTIO2, EVENT_QUEUE);
+        }
+
+        *this.cid = Some(Self::read_cid());
+    }
+
+    /// Reads the CID of the machine from the device's configuration.
+    fn read_cid() -> u64 {
+        (MmioRegs::ConfigHigh.read_at(VIRTIO2) as u64) << 32
+            | MmioRegs::ConfigLow.read_at(VIRTIO2) as u64
+    }
+
+    /// Returns the CID of the machine, or `None` if qemu does not provide the device.
+    pub fn cid(&self) -> Option<u64> {
+        self.cid
+    }
+
+    /// Returns whether the device can take another packet to send.
+    pub fn has_room(&self) -> bool {
+        self.cid.is_some() && self.tx_busy.iter().any(|busy| !busy)
+    }
+
+    /// Gives the device the packet of `hdr` and `data` to send.
+    /// Returns false if there is no device, or it has no room for the packet.
+    pub fn send(self: Pin<&mut Self>, hdr: &VsockHdr, data: &[u8]) -> bool {
+        let this = self.project();
+        let i = match this.tx_busy.iter().position(|busy| !busy) {
+            Some(i) if this.cid.is_some() => i,
+            _ => return false,
+        };
+        let len = HDR_SIZE + data.len();
+        assert!(len <= PKT_SIZE, "VirtioVsock::send");
+        this.tx_bufs[i][..HDR_SIZE].copy_from_slice(hdr.as_bytes());
+        this.tx_bufs[i][HDR_SIZE..len].copy_from_slice(data);
+        this.tx.desc[i] = VirtqDesc {
+            addr: this.tx_bufs[i].as_ptr() as _,
+            len: len as _,
+            flags: VirtqDescFlags::empty(),
+            next: 0,
+        };
+        this.tx_busy[i] = true;
+        this.tx.push(i);
+
+        // SAFETY: the descriptor points to the `i`th buffer of `tx_bufs`, which is valid for
+        // `len` bytes.
+        unsafe { MmioRegs::QueueNotify.write_at(VIRTIO2, TX_QUEUE) };
+        true
+    }
+
+    /// Acknowledges the device's interrupt, which its soft interrupt then handles with `intr`.
+    /// Returns whether the device raised one.
+    pub fn ack_intr() -> bool {
+        let intr_status = MmioRegs::InterruptStatus.read_at(VIRTIO2) & 0x3;
+        // SAFETY: simply acknowledging interrupts does not cause undefined behavior.
+        unsafe { MmioRegs::InterruptAck.write_at(VIRTIO2, intr_status) };
+        intr_status != 0
+    }
+
+    /// Takes back the buffers of the packets that the device sent.
+    /// Returns whether the device was reset, which breaks all connections with the host.
+    pub fn intr(self: Pin<&mut Self>) -> bool {
+        fence(Ordering::SeqCst);
+
+        let this = self.project();
+        while let Some((i, _)) = this.tx.pop(&mut this.used_idx[TX_QUEUE as usize]) {
+            this.tx_busy[i] = false;
+        }
+
+        let (mut events, mut reset) = (false, false);
+        while let Some((i, _)) = this.event.pop(&mut this.used_idx[EVENT_QUEUE as usize]) {
+            events = true;
+            reset |= this.events[i] == VSOCK_EVENT_TRANSPORT_RESET;
+            this.event.push(i);
+        }
+        if events {
+            // SAFETY: the descriptors of `event` point to buffers that the device may write.
+            unsafe { MmioRegs::QueueNotify.write_at(VIRTIO2, EVENT_QUEUE) };
+        }
+        if reset {
+            // The device tells the new CID, if the machine migrated.
+            *this.cid = Some(Self::read_cid());
+        }
+        reset
+    }
+
+    /// Passes the next packet that the device received to `f`, with its data, and gives its
+    /// buffer back to the device. Returns false if there is none.
+    pub fn recv<F: FnOnce(&VsockHdr, &[u8])>(self: Pin<&mut Self>, f: F) -> bool {
+        let this = self.project();
+        let (i, len) = some_or!(
+            this.rx.pop(&mut this.used_idx[RX_QUEUE as usize]),
+            return false
+        );
+        let packet = &this.rx_bufs[i][..len.min(PKT_SIZE)];
+        if let Some((hdr, data)) = LayoutVerified::<_, VsockHdr>::new_unaligned_from_prefix(packet)
+        {
+            let data_len = (hdr.len as usize).min(data.len());
+            f(&hdr, &data[..data_len]);
+        }
+        this.rx.push(i);
+
+        // SAFETY: the descriptor points to the `i`th buffer of `rx_bufs`, which the device may
+        // write.
+        unsafe { MmioRegs::QueueNotify.write_at(VIRTIO2, RX_QUEUE) };
+        true
+    }
+}
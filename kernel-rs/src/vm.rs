@@ -8,21 +8,29 @@ use crate::{
         pa2pte, pgrounddown, pgroundup, pte2pa, Addr, KVAddr, PAddr, UVAddr, VAddr, MAXVA, PGSIZE,
     },
     arch::memlayout::{
-        kstack, FINISHER, KERNBASE, PHYSTOP, PLIC, TRAMPOLINE, TRAPFRAME, UART0, VIRTIO0,
+        kstack, CLINT, FINISHER, FW_CFG, IORING, KERNBASE, PCIE_PIO, PCIE_PIO_SIZE, PHYSTOP, PLIC,
+        RTC, TRAMPOLINE, TRAPFRAME, UART0, VDSO, VIRTIO0, VIRTIO1, VIRTIO2,
     },
-    arch::riscv::{make_satp, sfence_vma, w_satp},
-    fs::{FileSystem, InodeGuard, Ufs},
+    arch::riscv::{make_satp, r_satp, sfence_vma, sfence_vma_addr, w_satp},
+    error::KernelError,
+    hal::hal,
     kalloc::Kmem,
+    ktest, ktest_assert,
     lock::SpinLock,
     page::Page,
+    pagecache::PageKey,
     param::NPROC,
-    proc::KernelCtx,
+    some_or,
+    util::branded::{DevNo, Inum},
 };
 
 extern "C" {
     // kernel.ld sets this to end of kernel code.
     static mut etext: [u8; 0];
 
+    // kernel.ld sets this to end of the kernel's read-only data.
+    static mut erodata: [u8; 0];
+
     // trampoline.S
     static mut trampoline: [u8; 0];
 }
@@ -39,6 +47,8 @@ bitflags! {
         const X = 1 << 3;
         /// user-accessible
         const U = 1 << 4;
+        /// copy-on-write: maps a page of the page cache, which the process may not write
+        const COW = 1 << 8;
     }
 }
 
@@ -104,6 +114,18 @@ impl PageTableEntry {
         self.inner = 0;
     }
 
+    /// Return `Some(..)` if it refers to a page-table page.
+    /// Return `None` if it refers to a data page.
+    /// Return `None` if it is invalid.
+    fn as_table(&self) -> Option<&RawPageTable> {
+        if self.is_table() {
+            // SAFETY: invariant.
+            Some(unsafe { &*(pte2pa(self.inner).into_usize() as *const _) })
+        } else {
+            None
+        }
+    }
+
     /// Return `Some(..)` if it refers to a page-table page.
     /// Return `None` if it refers to a data page.
     /// Return `None` if it is invalid.
@@ -238,6 +260,19 @@ impl<A: VAddr> PageTable<A> {
         Some(page_table.get_entry_mut(va.page_table_index(0)))
     }
 
+    /// Return the reference of the PTE in this page table that corresponds to virtual address
+    /// `va`, or `None` if a page-table page on the way is missing.
+    fn get(&self, va: A) -> Option<&PageTableEntry> {
+        assert!(va.into_usize() < MAXVA, "PageTable::get");
+        // SAFETY: self.ptr uniquely refers to a valid RawPageTable
+        // according to the invariant.
+        let mut page_table = unsafe { &*self.ptr };
+        for level in (1..3).rev() {
+            page_table = page_table.inner[va.page_table_index(level)].as_table()?;
+        }
+        Some(&page_table.inner[va.page_table_index(0)])
+    }
+
     fn insert(
         &mut self,
         va: A,
@@ -301,7 +336,9 @@ impl<A: VAddr> Drop for PageTable<A> {
 
 /// UserMemory manages the page table and allocated pages of a process. Its
 /// invariant guarantees that every PAddr mapped to VAddr except TRAMPOLINE and
-/// TRAPFRAME is from Page. This property is crucial for safety of methods that
+/// TRAPFRAME is from Page. The page at IORING, if any, is the ring page of
+/// ioring_setup(), and the page at VDSO is the page shared with the kernel, which UserMemory
+/// owns but does not count in its size. This property is crucial for safety of methods that
 /// read or write on memory, such as copy_in. Also, it is essential for safety
 /// of freeing a page created from each PAddr as well.
 ///
@@ -311,22 +348,30 @@ impl<A: VAddr> Drop for PageTable<A> {
 /// - If va ∈ dom(pt), va mod PGSIZE = 0 ∧ pt(va) mod PGSIZE = 0.
 /// - pt(TRAMPOLINE) = trampoline.
 /// - TRAPFRAME ∈ dom(pt).
-/// - If va ∈ dom(pt) ∧ va ∉ { TRAMPOLINE, TRAPFRAME },
+/// - pt(VDSO) = vdso.
+/// - If va ∈ dom(pt) ∧ va ∉ { TRAMPOLINE, TRAPFRAME } ∧ pt(va) is not copy-on-write,
 ///   then Page::from_usize(pt(va)) succeeds without breaking the invariant of Page.
-/// - If va ∈ dom(pt) where va ∉ { 0, TRAMPOLINE, TRAPFRAME },
+/// - If va ∈ dom(pt) ∧ pt(va) is copy-on-write, then pt(va) is a page of the page cache, which
+///   counts the mapping, and pt(va) is not writable.
+/// - If va ∈ dom(pt) where va ∉ { 0, VDSO, IORING, TRAMPOLINE, TRAPFRAME },
 ///   then va - PGSIZE ∈ dom(pt).
-/// - pgroundup(size) ∉ dom(pt).
+/// - pgroundup(size) ∉ dom(pt), and pgroundup(size) <= VDSO.
+/// - IORING ∈ dom(pt) iff ioring = Some(page), and then pt(IORING) = page.
 /// - If size > 0, then pgroundup(size) - PGSIZE ∈ dom(pt).
 pub struct UserMemory {
     /// Page table of process.
     page_table: PageTable<UVAddr>,
     /// Size of process memory (bytes).
     size: usize,
+    /// The ring page of ioring_setup(), mapped at IORING.
+    ioring: Option<Page>,
+    /// The page shared with the kernel, mapped read-only at VDSO.
+    vdso: Page,
 }
 
 impl UserMemory {
-    /// Create a user page table with no user memory, but with the trampoline
-    /// and a given trap frame. If `src_opt` is `Some(src)`, then load `src`
+    /// Create a user page table with no user memory, but with the trampoline,
+    /// a given trap frame, and a zeroed shared page. If `src_opt` is `Some(src)`, then load `src`
     /// into address 0 of the pagetable. In this case, src.len() must be less
     /// than a page.
     /// Return Some(..) if every allocation has succeeded.
@@ -366,9 +411,27 @@ impl UserMemory {
             )
             .ok()?;
 
+        // Map the shared page below the rings, which the process may only read.
+        let mut vdso = allocator.alloc()?;
+        vdso.write_bytes(0);
+        if page_table
+            .insert(
+                VDSO.into(),
+                vdso.addr(),
+                PteFlags::R | PteFlags::U,
+                allocator,
+            )
+            .is_err()
+        {
+            allocator.free(vdso);
+            return None;
+        }
+
         let mut memory = Self {
             page_table: scopeguard::ScopeGuard::into_inner(page_table),
             size: 0,
+            ioring: None,
+            vdso,
         };
 
         if let Some(src) = src_opt {
@@ -390,8 +453,9 @@ impl UserMemory {
     }
 
     /// Makes a new memory by copying a given memory. Copies both the page
-    /// table and the physical memory. Returns Some(memory) on success, None on
-    /// failure. Frees any allocated pages on failure.
+    /// table and the physical memory, except the ring page and the shared page. Returns Some(memory) on success, None on
+    /// failure. Frees any allocated pages on failure. The copy of a copy-on-write page is the
+    /// new memory's own, and writable.
     pub fn clone(&mut self, trap_frame: PAddr, allocator: Pin<&SpinLock<Kmem>>) -> Option<Self> {
         let new = Self::new(trap_frame, None, allocator)?;
         let mut new = scopeguard::guard(new, |mut new| {
@@ -405,7 +469,10 @@ impl UserMemory {
             assert!(pte.is_valid(), "clone_into: invalid page");
 
             let pa = pte.get_pa();
-            let flags = pte.get_flags();
+            let mut flags = pte.get_flags();
+            if flags.contains(PteFlags::COW) {
+                flags = (flags - PteFlags::COW) | PteFlags::W;
+            }
             let mut page = allocator.alloc()?;
             // SAFETY: pa is an address in page_table,
             // and thus it is the address of a page by the invariant.
@@ -427,50 +494,132 @@ impl UserMemory {
 
     /// Load data from a file into memory at virtual address va. va must be
     /// page-aligned, and the pages from va to va + sz must already be mapped.
+    /// Maps each whole page of the file copy-on-write from the page cache instead, if it can.
+    /// The file is inode `inum` of `dev`, and `read(dst, off)` copies its content at offset `off`
+    /// into `dst`, returning the number of bytes copied.
     ///
-    /// Returns Ok(()) on success, Err(()) on failure.
-    pub fn load_file(
+    /// Returns Ok(()) on success, Err(KernelError::Io) if the file is too short.
+    pub fn load_file<F: FnMut(&mut [u8], u32) -> usize>(
         &mut self,
         va: UVAddr,
-        ip: &mut InodeGuard<'_, <Ufs as FileSystem>::InodeInner>,
+        (dev, inum): (DevNo, Inum),
+        mut read: F,
         offset: u32,
         sz: u32,
-        ctx: &KernelCtx<'_, '_>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
         assert!(va.is_page_aligned(), "load_file: va must be page aligned");
         for i in num_iter::range_step(0, sz, PGSIZE as _) {
+            let n = cmp::min((sz - i) as usize, PGSIZE);
+            let key = PageKey {
+                dev,
+                inum,
+                off: offset + i,
+            };
+            if n == PGSIZE && self.map_cached(va + i as usize, key, &mut read) {
+                continue;
+            }
             let dst = self
-                .get_slice(va + i as usize)
+                .get_slice_mut(va + i as usize)
                 .expect("load_file: address should exist");
-            let n = cmp::min((sz - i) as usize, PGSIZE);
-            let bytes_read = ip.read_bytes_kernel(&mut dst[..n], offset + i, ctx);
+            let bytes_read = read(&mut dst[..n], offset + i);
             if bytes_read != n {
-                return Err(());
+                return Err(KernelError::Io);
             }
         }
         Ok(())
     }
 
+    /// Maps the page of the file that `key` names from the page cache at `va` copy-on-write, in
+    /// place of the page there, filling the cached page with `read` as `load_file` does. Returns
+    /// whether it did.
+    fn map_cached<F: FnMut(&mut [u8], u32) -> usize>(
+        &mut self,
+        va: UVAddr,
+        key: PageKey,
+        read: &mut F,
+    ) -> bool {
+        let fill = |page: &mut Page| read(&mut page[..], key.off) == PGSIZE;
+        let pa = some_or!(hal().pagecache().get(key, fill, hal().kmem()), return false);
+        let pte = self
+            .page_table
+            .get_mut(va, None)
+            .expect("map_cached: address should exist");
+        let old = pte.get_pa();
+        let flags = pte.get_flags();
+        pte.set_entry(pa, (flags - PteFlags::V - PteFlags::W) | PteFlags::COW);
+        if flags.contains(PteFlags::COW) {
+            hal().pagecache().unmap(old, hal().kmem());
+        } else {
+            // SAFETY: va < TRAPFRAME, so old is the address of a page.
+            hal()
+                .kmem()
+                .free(unsafe { Page::from_usize(old.into_usize()) });
+        }
+        true
+    }
+
+    /// Gives the process a copy of the cached page that it maps copy-on-write at `va`, which it
+    /// may write, in place of the cached page. Returns Err(KernelError::Fault) if `va` is not
+    /// mapped copy-on-write, and Err(KernelError::NoMemory) if no page is left for the copy.
+    pub fn break_cow(
+        &mut self,
+        va: UVAddr,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<(), KernelError> {
+        let va = pgrounddown(va.into_usize());
+        if va >= TRAPFRAME {
+            return Err(KernelError::Fault);
+        }
+        let pte = self
+            .page_table
+            .get_mut(va.into(), None)
+            .ok_or(KernelError::Fault)?;
+        let flags = pte.get_flags();
+        if !flags.contains(PteFlags::V | PteFlags::U | PteFlags::COW) {
+            return Err(KernelError::Fault);
+        }
+        let mut page = allocator.alloc().ok_or(KernelError::NoMemory)?;
+        let old = pte.get_pa();
+        // SAFETY: old is a page of the page cache, which nobody writes.
+        page.copy_from_slice(unsafe { slice::from_raw_parts(old.into_usize() as _, PGSIZE) });
+        pte.set_entry(
+            page.into_usize().into(),
+            (flags - PteFlags::V - PteFlags::COW) | PteFlags::W,
+        );
+        hal().pagecache().unmap(old, allocator);
+        Ok(())
+    }
+
     /// Allocate PTEs and physical memory to grow process to newsz, which need
-    /// not be page aligned. Returns Ok(new size) or Err(()) on error.
-    pub fn alloc(&mut self, newsz: usize, allocator: Pin<&SpinLock<Kmem>>) -> Result<usize, ()> {
+    /// not be page aligned. Returns Ok(new size) or Err(KernelError::NoMemory) on error.
+    pub fn alloc(
+        &mut self,
+        newsz: usize,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<usize, KernelError> {
         if newsz <= self.size {
             return Ok(self.size);
         }
+        if pgroundup(newsz) > VDSO {
+            return Err(KernelError::NoMemory);
+        }
 
         let oldsz = self.size;
         let mut this = scopeguard::guard(self, |this| {
             let _ = this.dealloc(oldsz, allocator);
         });
         while pgroundup(this.size) < pgroundup(newsz) {
-            let mut page = allocator.alloc().ok_or(())?;
+            let mut page = allocator.alloc().ok_or(KernelError::NoMemory)?;
             page.write_bytes(0);
             this.push_page(
                 page,
                 PteFlags::R | PteFlags::W | PteFlags::X | PteFlags::U,
                 allocator,
             )
-            .map_err(|page| allocator.free(page))?;
+            .map_err(|page| {
+                allocator.free(page);
+                KernelError::NoMemory
+            })?;
         }
         let this = scopeguard::ScopeGuard::into_inner(this);
         this.size = newsz;
@@ -485,17 +634,19 @@ impl UserMemory {
         }
 
         while pgroundup(newsz) < pgroundup(self.size) {
-            if let Some(page) = self.pop_page() {
-                allocator.free(page);
-            }
+            self.pop_page(allocator);
         }
         self.size = newsz;
         newsz
     }
 
     /// Grow or shrink process size by n bytes.
-    /// Return Ok(old size) on success, Err(()) on failure.
-    pub fn resize(&mut self, n: i32, allocator: Pin<&SpinLock<Kmem>>) -> Result<usize, ()> {
+    /// Return Ok(old size) on success, Err(KernelError::NoMemory) on failure.
+    pub fn resize(
+        &mut self,
+        n: i32,
+        allocator: Pin<&SpinLock<Kmem>>,
+    ) -> Result<usize, KernelError> {
         let size = self.size;
         match n.cmp(&0) {
             cmp::Ordering::Equal => (),
@@ -503,7 +654,10 @@ impl UserMemory {
                 let _ = self.alloc(size + n as usize, allocator)?;
             }
             cmp::Ordering::Less => {
-                let _ = self.dealloc(size - (-n as usize), allocator);
+                let newsz = size
+                    .checked_sub(n.unsigned_abs() as usize)
+                    .ok_or(KernelError::NoMemory)?;
+                let _ = self.dealloc(newsz, allocator);
             }
         };
         Ok(size)
@@ -520,15 +674,19 @@ impl UserMemory {
 
     /// Copy from kernel to user.
     /// Copy len bytes from src to virtual address dstva in a given page table.
-    /// Return Ok(()) on success, Err(()) on error.
-    pub fn copy_out_bytes(&mut self, dstva: UVAddr, src: &[u8]) -> Result<(), ()> {
+    /// Return Ok(()) on success, Err(KernelError::Fault) on error.
+    pub fn copy_out_bytes(&mut self, dstva: UVAddr, src: &[u8]) -> Result<(), KernelError> {
         let mut dst = dstva.into_usize();
         let mut len = src.len();
         let mut offset = 0;
         while len > 0 {
             let va = pgrounddown(dst);
             let poffset = dst - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            // The process may not write the shared page, so neither may the kernel for it.
+            if va == VDSO {
+                return Err(KernelError::Fault);
+            }
+            let page = self.get_slice_mut(va.into()).ok_or(KernelError::Fault)?;
             let n = cmp::min(PGSIZE - poffset, len);
             page[poffset..poffset + n].copy_from_slice(&src[offset..offset + n]);
             len -= n;
@@ -540,22 +698,22 @@ impl UserMemory {
 
     /// Copy from kernel to user.
     /// Copy from src to virtual address dstva in a given page table.
-    /// Return Ok(()) on success, Err(()) on error.
-    pub fn copy_out<T: AsBytes>(&mut self, dstva: UVAddr, src: &T) -> Result<(), ()> {
+    /// Return Ok(()) on success, Err(KernelError::Fault) on error.
+    pub fn copy_out<T: AsBytes>(&mut self, dstva: UVAddr, src: &T) -> Result<(), KernelError> {
         self.copy_out_bytes(dstva, src.as_bytes())
     }
 
     /// Copy from user to kernel.
     /// Copy len bytes to dst from virtual address srcva in a given page table.
-    /// Return Ok(()) on success, Err(()) on error.
-    pub fn copy_in_bytes(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), ()> {
+    /// Return Ok(()) on success, Err(KernelError::Fault) on error.
+    pub fn copy_in_bytes(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), KernelError> {
         let mut src = srcva.into_usize();
         let mut len = dst.len();
         let mut offset = 0;
         while len > 0 {
             let va = pgrounddown(src);
             let poffset = src - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            let page = self.get_slice(va.into()).ok_or(KernelError::Fault)?;
             let n = cmp::min(PGSIZE - poffset, len);
             dst[offset..offset + n].copy_from_slice(&page[poffset..poffset + n]);
             len -= n;
@@ -567,27 +725,28 @@ impl UserMemory {
 
     /// Copy from user to kernel.
     /// Copy to dst from virtual address srcva in a given page table.
-    /// Return Ok(()) on success, Err(()) on error.
+    /// Return Ok(()) on success, Err(KernelError::Fault) on error.
     pub unsafe fn copy_in<T: AsBytes + FromBytes>(
         &mut self,
         dst: &mut T,
         srcva: UVAddr,
-    ) -> Result<(), ()> {
+    ) -> Result<(), KernelError> {
         self.copy_in_bytes(dst.as_bytes_mut(), srcva)
     }
 
     /// Copy a null-terminated string from user to kernel.
     /// Copy bytes to dst from virtual address srcva in a given page table,
     /// until a '\0', or max.
-    /// Return OK(()) on success, Err(()) on error.
-    pub fn copy_in_str(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), ()> {
+    /// Return Ok(()) on success, Err(KernelError::Fault) on a bad address, or
+    /// Err(KernelError::NameTooLong) if no '\0' appears within max bytes.
+    pub fn copy_in_str(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), KernelError> {
         let mut src = srcva.into_usize();
         let mut offset = 0;
         let mut max = dst.len();
         while max > 0 {
             let va = pgrounddown(src);
             let poffset = src - va;
-            let page = self.get_slice(va.into()).ok_or(())?;
+            let page = self.get_slice(va.into()).ok_or(KernelError::Fault)?;
             let n = cmp::min(PGSIZE - poffset, max);
 
             let from = &page[poffset..poffset + n];
@@ -604,7 +763,80 @@ impl UserMemory {
                 }
             }
         }
-        Err(())
+        Err(KernelError::NameTooLong)
+    }
+
+    /// Maps a zeroed ring page at IORING.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn map_ioring(&mut self, allocator: Pin<&SpinLock<Kmem>>) -> Result<(), KernelError> {
+        if self.ioring.is_some() {
+            return Err(KernelError::Exists);
+        }
+        let mut page = allocator.alloc().ok_or(KernelError::NoMemory)?;
+        page.write_bytes(0);
+        let pa = page.addr();
+        if self
+            .page_table
+            .insert(
+                IORING.into(),
+                pa,
+                PteFlags::R | PteFlags::W | PteFlags::U,
+                allocator,
+            )
+            .is_err()
+        {
+            allocator.free(page);
+            return Err(KernelError::NoMemory);
+        }
+        self.ioring = Some(page);
+        Ok(())
+    }
+
+    /// Returns the ring page, or `None` if there is none.
+    pub fn ioring_mut(&mut self) -> Option<&mut Page> {
+        self.ioring.as_mut()
+    }
+
+    /// Returns the page shared with the kernel.
+    pub fn vdso_mut(&mut self) -> &mut Page {
+        &mut self.vdso
+    }
+
+    /// Calls `f` with each run of mapped pages that share their permissions, in address order, as
+    /// `f(start, end, perm)`. `perm` holds only R, W and X, and is empty for pages that the
+    /// process cannot access, like the guard page below the stack. The shared page is a run of
+    /// its own at VDSO, and so is the ring page, if any, at IORING.
+    pub fn for_each_region(&self, mut f: impl FnMut(usize, usize, PteFlags)) {
+        let perm = |va: usize| {
+            let flags = self
+                .page_table
+                .get(va.into())
+                .expect("for_each_region")
+                .get_flags();
+            if flags.contains(PteFlags::COW) {
+                // The process writes the page as if it were its own.
+                (flags | PteFlags::W) & (PteFlags::R | PteFlags::W | PteFlags::X)
+            } else if flags.contains(PteFlags::U) {
+                flags & (PteFlags::R | PteFlags::W | PteFlags::X)
+            } else {
+                PteFlags::empty()
+            }
+        };
+        let end = pgroundup(self.size);
+        let mut start = 0;
+        for va in num_iter::range_step(PGSIZE, end, PGSIZE) {
+            if perm(va) != perm(start) {
+                f(start, va, perm(start));
+                start = va;
+            }
+        }
+        if end > 0 {
+            f(start, end, perm(start));
+        }
+        f(VDSO, VDSO + PGSIZE, perm(VDSO));
+        if self.ioring.is_some() {
+            f(IORING, IORING + PGSIZE, perm(IORING));
+        }
     }
 
     /// Return the address of the page table for this memory in the riscv's sv39
@@ -614,15 +846,33 @@ impl UserMemory {
     }
 
     /// Return a page at va as a slice. Some(page) on success, None on failure.
-    fn get_slice(&mut self, va: UVAddr) -> Option<&mut [u8]> {
+    fn get_slice(&self, va: UVAddr) -> Option<&[u8]> {
         if va.into_usize() >= TRAPFRAME {
             return None;
         }
-        let pte = self.page_table.get_mut(va, None)?;
+        let pte = self.page_table.get(va)?;
         if !pte.is_user() {
             return None;
         }
         // SAFETY: va < TRAPFRAME, so pte.get_pa() is the address of a page.
+        Some(unsafe { slice::from_raw_parts(pte.get_pa().into_usize() as _, PGSIZE) })
+    }
+
+    /// Return a page at va as a mutable slice, copying it first if it is copy-on-write.
+    /// Some(page) on success, None on failure.
+    fn get_slice_mut(&mut self, va: UVAddr) -> Option<&mut [u8]> {
+        if va.into_usize() >= TRAPFRAME {
+            return None;
+        }
+        if self.page_table.get(va)?.flag_intersects(PteFlags::COW) {
+            self.break_cow(va, hal().kmem()).ok()?;
+        }
+        let pte = self.page_table.get_mut(va, None)?;
+        if !pte.is_user() {
+            return None;
+        }
+        // SAFETY: va < TRAPFRAME, and pte is not copy-on-write, so pte.get_pa() is the address of
+        // a page that this memory owns.
         Some(unsafe { slice::from_raw_parts_mut(pte.get_pa().into_usize() as _, PGSIZE) })
     }
 
@@ -645,25 +895,41 @@ impl UserMemory {
         Ok(())
     }
 
-    /// Decrease the size by removing the most recently appended page.
-    /// Some(page) if size > 0, None if size = 0.
-    fn pop_page(&mut self) -> Option<Page> {
+    /// Decrease the size by removing the most recently appended page, and free it, or unmap it
+    /// from the page cache if it is copy-on-write. Does nothing if size = 0.
+    fn pop_page(&mut self, allocator: Pin<&SpinLock<Kmem>>) {
         if self.size == 0 {
-            return None;
+            return;
         }
         self.size = pgroundup(self.size) - PGSIZE;
-        let pa = self
+        let cow = self
             .page_table
-            .remove(self.size.into())
+            .get(self.size.into())
             .expect("pop_page")
-            .into_usize();
-        // SAFETY: pa is an address in page_table,
-        // and, thus, it is the address of a page by the invariant.
-        Some(unsafe { Page::from_usize(pa) })
+            .flag_intersects(PteFlags::COW);
+        let pa = self.page_table.remove(self.size.into()).expect("pop_page");
+        if cow {
+            hal().pagecache().unmap(pa, allocator);
+        } else {
+            // SAFETY: pa is an address in page_table that is not copy-on-write,
+            // and, thus, it is the address of a page by the invariant.
+            allocator.free(unsafe { Page::from_usize(pa.into_usize()) });
+        }
     }
 
     pub fn free(mut self, allocator: Pin<&SpinLock<Kmem>>) {
         let _ = self.dealloc(0, allocator);
+        let pa = self
+            .page_table
+            .remove(VDSO.into())
+            .expect("free: vdso")
+            .into_usize();
+        // SAFETY: pa is the address of self.vdso, which is forgotten with self below.
+        allocator.free(unsafe { Page::from_usize(pa) });
+        if let Some(page) = self.ioring.take() {
+            let _ = self.page_table.remove(IORING.into());
+            allocator.free(page);
+        }
         // SAFETY: self will be dropped.
         unsafe { self.page_table.free(allocator) };
         mem::forget(self);
@@ -711,6 +977,17 @@ impl KernelMemory {
             )
             .ok()?;
 
+        // Real-time clock registers
+        page_table
+            .insert_range(
+                RTC.into(),
+                PGSIZE,
+                RTC.into(),
+                PteFlags::R | PteFlags::W,
+                allocator,
+            )
+            .ok()?;
+
         // Uart registers
         page_table
             .insert_range(
@@ -733,6 +1010,61 @@ impl KernelMemory {
             )
             .ok()?;
 
+        // Virtio mmio entropy source interface
+        page_table
+            .insert_range(
+                VIRTIO1.into(),
+                PGSIZE,
+                VIRTIO1.into(),
+                PteFlags::R | PteFlags::W,
+                allocator,
+            )
+            .ok()?;
+
+        // Virtio mmio socket device interface
+        page_table
+            .insert_range(
+                VIRTIO2.into(),
+                PGSIZE,
+                VIRTIO2.into(),
+                PteFlags::R | PteFlags::W,
+                allocator,
+            )
+            .ok()?;
+
+        // Firmware configuration device registers
+        page_table
+            .insert_range(
+                FW_CFG.into(),
+                PGSIZE,
+                FW_CFG.into(),
+                PteFlags::R | PteFlags::W,
+                allocator,
+            )
+            .ok()?;
+
+        // PCIe I/O ports
+        page_table
+            .insert_range(
+                PCIE_PIO.into(),
+                PCIE_PIO_SIZE,
+                PCIE_PIO.into(),
+                PteFlags::R | PteFlags::W,
+                allocator,
+            )
+            .ok()?;
+
+        // CLINT, whose timer registers the kernel programs
+        page_table
+            .insert_range(
+                CLINT.into(),
+                0x10000,
+                CLINT.into(),
+                PteFlags::R | PteFlags::W,
+                allocator,
+            )
+            .ok()?;
+
         // PLIC
         page_table
             .insert_range(
@@ -757,12 +1089,19 @@ impl KernelMemory {
             )
             .ok()?;
 
-        // Map kernel data and the physical RAM we'll make use of.
+        // Map kernel read-only data read-only, so that stray writes to it fault.
+        // SAFETY: we assume that reading the address of erodata is safe.
+        let ero = unsafe { erodata.as_mut_ptr() as usize };
+        page_table
+            .insert_range(et.into(), ero - et, et.into(), PteFlags::R, allocator)
+            .ok()?;
+
+        // Map kernel data and the physical RAM we'll make use of, not executable.
         page_table
             .insert_range(
-                et.into(),
-                PHYSTOP - et,
-                et.into(),
+                ero.into(),
+                PHYSTOP - ero,
+                ero.into(),
                 PteFlags::R | PteFlags::W,
                 allocator,
             )
@@ -803,6 +1142,16 @@ impl KernelMemory {
         })
     }
 
+    /// Returns the physical address that `va` is mapped to, and the permissions of its page,
+    /// or `None` if it is not mapped.
+    pub fn translate(&self, va: KVAddr) -> Option<(PAddr, PteFlags)> {
+        if va.into_usize() >= MAXVA {
+            return None;
+        }
+        let pte = self.page_table.get(va).filter(|pte| pte.is_data())?;
+        Some((pte.get_pa() + va.into_usize() % PGSIZE, pte.get_flags()))
+    }
+
     /// Switch h/w page table register to the kernel's page table, and enable paging.
     pub unsafe fn init_hart(&self) {
         unsafe {
@@ -811,3 +1160,75 @@ impl KernelMemory {
         }
     }
 }
+
+/// Map the page of RAM at `pa` in the kernel's direct map if `mapped`, or unmap it, and flush
+/// it from the TLB of this CPU. The other CPUs flush their TLBs once they take the IPI that this
+/// sends them, so they may reach the page until they enable interrupts.
+/// Returns `false` without doing so if paging is not on yet.
+///
+/// # Safety
+///
+/// The page must not be used while it is unmapped.
+pub unsafe fn set_kernel_mapped(pa: PAddr, mapped: bool) -> bool {
+    let satp = r_satp();
+    if satp == 0 {
+        return false;
+    }
+    let va = KVAddr::from(pa.into_usize());
+    assert!(
+        (KERNBASE..PHYSTOP).contains(&va.into_usize()),
+        "set_kernel_mapped"
+    );
+    // SAFETY: in the kernel, satp refers to the kernel's page table, which maps the pages of
+    // RAM with its own page-table pages that are never freed.
+    let mut page_table = unsafe { &mut *(((satp & ((1 << 44) - 1)) << 12) as *mut RawPageTable) };
+    for level in (1..3).rev() {
+        page_table = page_table
+            .get_table_mut(va.page_table_index(level), None)
+            .expect("set_kernel_mapped");
+    }
+    let pte = &mut page_table.inner[va.page_table_index(0)];
+    if mapped {
+        pte.inner |= PteFlags::V.bits();
+    } else {
+        pte.inner &= !PteFlags::V.bits();
+    }
+    unsafe { sfence_vma_addr(va.into_usize()) };
+    hal().ipis().call_others(flush_tlb, 0);
+    true
+}
+
+/// Flushes the TLB of this CPU, when another CPU changed the kernel's page table.
+fn flush_tlb(_: usize) {
+    // SAFETY: flushing the TLB only makes the CPU read the page table again.
+    unsafe { sfence_vma() };
+}
+
+ktest! {
+    fn page_table_insert(ctx) {
+        let allocator = hal().kmem();
+        let mut page_table = PageTable::<UVAddr>::new(allocator).expect("page_table_insert");
+        let pa = allocator.alloc().expect("page_table_insert").into_usize();
+        let va = UVAddr::from(0x40_1000);
+        let inserted = page_table
+            .insert(va, pa.into(), PteFlags::R | PteFlags::U, allocator)
+            .is_ok();
+        let entry = page_table
+            .get(va)
+            .map(|pte| (pte.get_pa().into_usize(), pte.get_flags()));
+        let next = page_table.get(va + PGSIZE).map_or(false, |pte| pte.is_valid());
+
+        if let Some(pte) = page_table.get_mut(va, None) {
+            pte.invalidate();
+        }
+        // SAFETY: `page_table` is not used after this.
+        unsafe { page_table.free(allocator) };
+        mem::forget(page_table);
+        // SAFETY: `pa` is the page allocated above.
+        allocator.free(unsafe { Page::from_usize(pa) });
+
+        ktest_assert!(inserted);
+        ktest_assert!(entry == Some((pa, PteFlags::V | PteFlags::R | PteFlags::U)));
+        ktest_assert!(!next);
+    }
+}
@@ -0,0 +1,885 @@
+//! Sockets of the AF_VSOCK family, which connect processes to programs on the host that the
+//! machine runs on, such as test harnesses, without networking.
+//!
+//! A socket is one end of a stream connection between two ports, each on a machine named by its
+//! CID. The host has CID 2, and the machine the CID that QEMU gives the virtio socket device,
+//! e.g. `make qemu VSOCK=3`. Packets to the host go through the device. Packets to
+//! VMADDR_CID_LOCAL, or to the machine's own CID, are delivered in place, so that processes can
+//! also connect to each other, with or without the device.
+//!
+//! Connections follow the protocol of the virtio spec: a REQUEST packet that a RESPONSE
+//! accepts or an RST refuses, RW packets of data, and SHUTDOWN and RST packets to close. Each
+//! packet tells the peer how much of the sender's receive buffer it has emptied, and a side
+//! sends no more data than the other has room for, so received data is never dropped.
+//!
+//! The system calls are those of BSD sockets, except that the addresses are always a
+//! `struct sockaddr_vm`, passed without its length.
+
+use core::{cmp, pin::Pin};
+
+use array_macro::array;
+use arrayvec::ArrayVec;
+use pin_project::pin_project;
+use rv6_abi::socket::{
+    SockaddrVm, AF_VSOCK, SOCK_STREAM, VMADDR_CID_ANY, VMADDR_CID_LOCAL, VMADDR_PORT_ANY,
+};
+use zerocopy::AsBytes;
+
+use crate::{
+    arch::addr::{Addr, UVAddr},
+    cred::Caps,
+    error::KernelError,
+    file::FileType,
+    fs::FcntlFlags,
+    hal::hal,
+    irq::IrqReturn,
+    kernel::KernelRef,
+    lock::SleepableLock,
+    param::NSOCK,
+    poll::PollEvents,
+    proc::KernelCtx,
+    softirq::SoftIrq,
+    virtio::{
+        VirtioVsock, VsockHdr, MAX_PAYLOAD, VSOCK_OP_CREDIT_REQUEST, VSOCK_OP_CREDIT_UPDATE,
+        VSOCK_OP_REQUEST, VSOCK_OP_RESPONSE, VSOCK_OP_RST, VSOCK_OP_RW, VSOCK_OP_SHUTDOWN,
+        VSOCK_SHUTDOWN_RCV, VSOCK_SHUTDOWN_SEND, VSOCK_TYPE_STREAM,
+    },
+};
+
+/// Size of the receive buffer of a connection.
+const RX_BUF_SIZE: usize = 4096;
+
+/// Ports below this can be bound only with `Caps::NET_BIND`. Sockets that are not bound when they
+/// connect are bound to a free port from here on.
+const FIRST_EPHEMERAL_PORT: u32 = 1024;
+
+/// Maximum number of control packets waiting for room in the device.
+const NPENDING: usize = 16;
+
+/// A socket, which a file of type `FileType::Socket` owns.
+#[derive(Copy, Clone)]
+pub struct Socket(usize);
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    /// The slot is free.
+    Free,
+
+    /// Created by socket(), and maybe bound.
+    Unconnected,
+
+    /// Listening for connections.
+    Listening,
+
+    /// Sent a REQUEST, and waiting for the answer.
+    Connecting,
+
+    Connected,
+
+    /// The peer reset or refused the connection.
+    Closed {
+        refused: bool,
+    },
+}
+
+struct Sock {
+    state: State,
+
+    /// The local port, if bound.
+    port: Option<u32>,
+
+    /// The local CID of the connection.
+    cid: u64,
+
+    peer_cid: u64,
+
+    peer_port: u32,
+
+    /// If listening, the number of connections that may wait for accept().
+    backlog: usize,
+
+    /// The listening socket that the connection came to, until accept() takes it.
+    listener: Option<usize>,
+
+    /// VSOCK_SHUTDOWN_* flags that the peer sent.
+    peer_shutdown: u32,
+
+    /// Data received and not read yet, in a ring.
+    rx: [u8; RX_BUF_SIZE],
+
+    /// Number of bytes received, and read, since the connection was made.
+    rx_cnt: u32,
+    fwd_cnt: u32,
+
+    /// `fwd_cnt` when the peer was last told it.
+    fwd_told: u32,
+
+    /// Number of bytes sent since the connection was made.
+    tx_cnt: u32,
+
+    /// The size of the peer's receive buffer, and how much it read, as it last told.
+    peer_buf_alloc: u32,
+    peer_fwd_cnt: u32,
+}
+
+impl Sock {
+    const fn new() -> Self {
+        Self {
+            state: State::Free,
+            port: None,
+            cid: 0,
+            peer_cid: 0,
+            peer_port: 0,
+            backlog: 0,
+            listener: None,
+            peer_shutdown: 0,
+            rx: [0; RX_BUF_SIZE],
+            rx_cnt: 0,
+            fwd_cnt: 0,
+            fwd_told: 0,
+            tx_cnt: 0,
+            peer_buf_alloc: 0,
+            peer_fwd_cnt: 0,
+        }
+    }
+
+    /// Makes the socket one end of a new connection.
+    fn connect(&mut self, cid: u64, peer_cid: u64, peer_port: u32, state: State) {
+        self.state = state;
+        self.cid = cid;
+        self.peer_cid = peer_cid;
+        self.peer_port = peer_port;
+        self.peer_shutdown = 0;
+        self.rx_cnt = 0;
+        self.fwd_cnt = 0;
+        self.fwd_told = 0;
+        self.tx_cnt = 0;
+        self.peer_buf_alloc = 0;
+        self.peer_fwd_cnt = 0;
+    }
+
+    /// Returns the header of a packet of the connection to the peer.
+    fn header(&mut self, op: u16, flags: u32, len: usize) -> VsockHdr {
+        self.fwd_told = self.fwd_cnt;
+        VsockHdr {
+            src_cid: self.cid,
+            dst_cid: self.peer_cid,
+            src_port: self.port.unwrap_or(0),
+            dst_port: self.peer_port,
+            len: len as u32,
+            typ: VSOCK_TYPE_STREAM,
+            op,
+            flags,
+            buf_alloc: RX_BUF_SIZE as u32,
+            fwd_cnt: self.fwd_cnt,
+        }
+    }
+
+    /// Returns the number of bytes received and not read yet.
+    fn buffered(&self) -> usize {
+        self.rx_cnt.wrapping_sub(self.fwd_cnt) as usize
+    }
+
+    /// Returns the number of bytes that the peer has room for.
+    fn credit(&self) -> usize {
+        let in_flight = self.tx_cnt.wrapping_sub(self.peer_fwd_cnt);
+        self.peer_buf_alloc.saturating_sub(in_flight) as usize
+    }
+
+    /// Returns whether the peer will send no more data.
+    fn at_eof(&self) -> bool {
+        matches!(self.state, State::Closed { .. }) || self.peer_shutdown & VSOCK_SHUTDOWN_SEND != 0
+    }
+
+    /// Appends `data` to the receive buffer, as far as it has room.
+    fn receive(&mut self, data: &[u8]) {
+        let len = cmp::min(data.len(), RX_BUF_SIZE - self.buffered());
+        for (i, byte) in data[..len].iter().enumerate() {
+            self.rx[(self.rx_cnt as usize + i) % RX_BUF_SIZE] = *byte;
+        }
+        self.rx_cnt = self.rx_cnt.wrapping_add(len as u32);
+    }
+
+    /// Returns the received data that is not read yet, in at most two parts.
+    fn unread(&self) -> (&[u8], &[u8]) {
+        let start = self.fwd_cnt as usize % RX_BUF_SIZE;
+        let len = self.buffered();
+        if start + len <= RX_BUF_SIZE {
+            (&self.rx[start..start + len], &[])
+        } else {
+            (&self.rx[start..], &self.rx[..start + len - RX_BUF_SIZE])
+        }
+    }
+}
+
+/// The sockets, apart from the device.
+struct Sockets {
+    socks: [Sock; NSOCK],
+
+    /// The CID of the machine, if it has a socket device.
+    cid: Option<u64>,
+
+    /// Control packets to the host that wait for room in the device.
+    pending: ArrayVec<VsockHdr, NPENDING>,
+
+    /// The next port to try binding to.
+    next_port: u32,
+}
+
+#[pin_project]
+pub struct Vsock {
+    #[pin]
+    dev: VirtioVsock,
+
+    socks: Sockets,
+
+    /// The data of a packet being written.
+    buf: [u8; MAX_PAYLOAD],
+}
+
+impl Sockets {
+    /// Returns whether packets to `cid` stay in this machine.
+    fn is_local(&self, cid: u64) -> bool {
+        cid == VMADDR_CID_LOCAL as u64 || Some(cid) == self.cid
+    }
+
+    fn port_in_use(&self, port: u32) -> bool {
+        self.socks
+            .iter()
+            .any(|sock| sock.state != State::Free && sock.port == Some(port))
+    }
+
+    /// Returns a free port, from FIRST_EPHEMERAL_PORT on.
+    fn ephemeral_port(&mut self) -> u32 {
+        loop {
+            let port = self.next_port;
+            self.next_port = if port >= VMADDR_PORT_ANY - 1 {
+                FIRST_EPHEMERAL_PORT
+            } else {
+                port + 1
+            };
+            if !self.port_in_use(port) {
+                return port;
+            }
+        }
+    }
+
+    /// Handles a packet that arrived for this machine, and returns the packet to answer with,
+    /// if any.
+    fn deliver(&mut self, hdr: &VsockHdr, data: &[u8]) -> Option<VsockHdr> {
+        let reply = |op| {
+            VsockHdr {
+                src_cid: hdr.dst_cid,
+                dst_cid: hdr.src_cid,
+                src_port: hdr.dst_port,
+                dst_port: hdr.src_port,
+                typ: VSOCK_TYPE_STREAM,
+                op,
+                buf_alloc: RX_BUF_SIZE as u32,
+                ..Default::default()
+            }
+        };
+        // Never answer an RST, lest two sides answer each other forever.
+        let reset = if hdr.op == VSOCK_OP_RST {
+            None
+        } else {
+            Some(reply(VSOCK_OP_RST))
+        };
+        if hdr.typ != VSOCK_TYPE_STREAM {
+            return reset;
+        }
+
+        let conn = self.socks.iter().position(|sock| {
+            matches!(sock.state, State::Connecting | State::Connected)
+                && sock.port == Some(hdr.dst_port)
+                && sock.peer_cid == hdr.src_cid
+                && sock.peer_port == hdr.src_port
+        });
+        let i = match conn {
+            Some(i) => i,
+            None if hdr.op == VSOCK_OP_REQUEST => return self.accept(hdr).or(reset),
+            None => return reset,
+        };
+        let sock = &mut self.socks[i];
+        sock.peer_buf_alloc = hdr.buf_alloc;
+        sock.peer_fwd_cnt = hdr.fwd_cnt;
+        match (hdr.op, sock.state) {
+            (VSOCK_OP_RESPONSE, State::Connecting) => sock.state = State::Connected,
+            (VSOCK_OP_RW, State::Connected) => sock.receive(data),
+            (VSOCK_OP_CREDIT_UPDATE, _) => (),
+            (VSOCK_OP_CREDIT_REQUEST, _) => return Some(sock.header(VSOCK_OP_CREDIT_UPDATE, 0, 0)),
+            (VSOCK_OP_SHUTDOWN, State::Connected) => {
+                sock.peer_shutdown |= hdr.flags;
+                if sock.peer_shutdown == VSOCK_SHUTDOWN_RCV | VSOCK_SHUTDOWN_SEND {
+                    // The peer is done. The data it sent can still be read.
+                    sock.state = State::Closed { refused: false };
+                    return reset;
+                }
+            }
+            (VSOCK_OP_RST, state) => {
+                sock.state = State::Closed {
+                    refused: state == State::Connecting,
+                }
+            }
+            _ => {
+                sock.state = State::Closed { refused: false };
+                return reset;
+            }
+        }
+        None
+    }
+
+    /// Makes a connection for the REQUEST `hdr` to a listening socket, which accept() will
+    /// take, and returns the RESPONSE. Returns `None` if no socket listens on the port, or it
+    /// has too many connections waiting.
+    fn accept(&mut self, hdr: &VsockHdr) -> Option<VsockHdr> {
+        let listener = self
+            .socks
+            .iter()
+            .position(|sock| sock.state == State::Listening && sock.port == Some(hdr.dst_port))?;
+        let backlog = self.socks[listener].backlog;
+        let waiting = self
+            .socks
+            .iter()
+            .filter(|sock| sock.listener == Some(listener))
+            .count();
+        if waiting >= backlog {
+            return None;
+        }
+        let sock = self
+            .socks
+            .iter_mut()
+            .find(|sock| sock.state == State::Free)?;
+        sock.connect(hdr.dst_cid, hdr.src_cid, hdr.src_port, State::Connected);
+        sock.port = Some(hdr.dst_port);
+        sock.listener = Some(listener);
+        sock.peer_buf_alloc = hdr.buf_alloc;
+        sock.peer_fwd_cnt = hdr.fwd_cnt;
+        Some(sock.header(VSOCK_OP_RESPONSE, 0, 0))
+    }
+
+    /// Frees the socket `i`, and closes its connection, or the connections that wait for it to
+    /// accept them. Returns the packets to send.
+    fn close(&mut self, i: usize) -> ArrayVec<VsockHdr, NSOCK> {
+        let mut packets = ArrayVec::new();
+        let sock = &mut self.socks[i];
+        match sock.state {
+            State::Connected => {
+                packets.push(sock.header(
+                    VSOCK_OP_SHUTDOWN,
+                    VSOCK_SHUTDOWN_RCV | VSOCK_SHUTDOWN_SEND,
+                    0,
+                ))
+            }
+            State::Connecting => packets.push(sock.header(VSOCK_OP_RST, 0, 0)),
+            _ => (),
+        }
+        sock.state = State::Free;
+        sock.port = None;
+        sock.listener = None;
+        for sock in self.socks.iter_mut() {
+            if sock.listener == Some(i) {
+                if sock.state == State::Connected {
+                    packets.push(sock.header(VSOCK_OP_RST, 0, 0));
+                }
+                sock.state = State::Free;
+                sock.port = None;
+                sock.listener = None;
+            }
+        }
+        packets
+    }
+}
+
+impl Vsock {
+    pub const fn new() -> Self {
+        Self {
+            dev: VirtioVsock::new(),
+            socks: Sockets {
+                socks: array![_ => Sock::new(); NSOCK],
+                cid: None,
+                pending: ArrayVec::new_const(),
+                next_port: FIRST_EPHEMERAL_PORT,
+            },
+            buf: [0; MAX_PAYLOAD],
+        }
+    }
+
+    /// Initializes the socket device. Called by its driver's probe function.
+    pub fn init(self: Pin<&mut Self>) {
+        let mut this = self.project();
+        this.dev.as_mut().init();
+        this.socks.cid = this.dev.cid();
+    }
+
+    /// Handles the interrupt of the device: takes the packets it received, and sends those that
+    /// were waiting for room.
+    fn intr(self: Pin<&mut Self>) {
+        let this = self.project();
+        let mut dev = this.dev;
+        let socks = this.socks;
+        if dev.as_mut().intr() {
+            socks.cid = dev.cid();
+            // The connections to the host are gone.
+            let cid = socks.cid;
+            for sock in socks.socks.iter_mut() {
+                if matches!(sock.state, State::Connecting | State::Connected)
+                    && sock.peer_cid != VMADDR_CID_LOCAL as u64
+                    && Some(sock.peer_cid) != cid
+                {
+                    sock.state = State::Closed {
+                        refused: sock.state == State::Connecting,
+                    };
+                }
+            }
+        }
+        let mut replies = ArrayVec::<VsockHdr, NPENDING>::new();
+        while dev.as_mut().recv(|hdr, data| {
+            if socks.cid == Some(hdr.dst_cid) {
+                if let Some(reply) = socks.deliver(hdr, data) {
+                    let _ = replies.try_push(reply);
+                }
+            }
+        }) {}
+        for reply in replies {
+            send_control(dev.as_mut(), socks, reply);
+        }
+        flush(dev, socks);
+    }
+}
+
+/// Sends the packet `hdr` with `data`, delivering it in place if it is for this machine.
+/// Returns false if it is for the host and the device has no room for it.
+fn send(mut dev: Pin<&mut VirtioVsock>, socks: &mut Sockets, hdr: &VsockHdr, data: &[u8]) -> bool {
+    if !socks.is_local(hdr.dst_cid) {
+        return dev.send(hdr, data);
+    }
+    if let Some(reply) = socks.deliver(hdr, data) {
+        let _ = send(dev.as_mut(), socks, &reply, &[]);
+    }
+    true
+}
+
+/// Sends the control packet `hdr`, or keeps it until the device has room.
+fn send_control(dev: Pin<&mut VirtioVsock>, socks: &mut Sockets, hdr: VsockHdr) {
+    if !send(dev, socks, &hdr, &[]) {
+        // A packet that does not fit is dropped, and the peer finds out when it times out.
+        let _ = socks.pending.try_push(hdr);
+    }
+}
+
+/// Sends the control packets that were waiting for room in the device, as far as it has room.
+fn flush(mut dev: Pin<&mut VirtioVsock>, socks: &mut Sockets) {
+    while let Some(hdr) = socks.pending.first() {
+        if !dev.as_mut().send(hdr, &[]) {
+            break;
+        }
+        let _ = socks.pending.remove(0);
+    }
+}
+
+/// Handles the interrupt of the socket device, by acknowledging it and raising its soft
+/// interrupt.
+pub fn vsock_intr(_kernel: KernelRef<'_, '_>) -> IrqReturn {
+    if !VirtioVsock::ack_intr() {
+        return IrqReturn::None;
+    }
+    hal().softirqs().raise(SoftIrq::Vsock);
+    IrqReturn::Handled
+}
+
+impl SleepableLock<Vsock> {
+    /// The soft interrupt of the socket device, which delivers the packets it received.
+    pub fn receive(self: Pin<&Self>, kernel: KernelRef<'_, '_>) {
+        let mut guard = self.pinned_lock();
+        guard.get_pin_mut().intr();
+        guard.wakeup(kernel);
+        kernel.poll_queue().wakeup(kernel);
+    }
+}
+
+impl Socket {
+    /// Reads up to `n` bytes that the peer sent into `addr`. Sleeps until there are some, or
+    /// returns `Err(KernelError::TryAgain)` if `nonblock` is set.
+    /// Returns Ok(number of bytes read), which is 0 at the end of the stream, on success,
+    /// Err(KernelError) on error.
+    pub fn read(
+        self,
+        addr: UVAddr,
+        n: usize,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let mut guard = hal().vsock().pinned_lock();
+        loop {
+            let sock = &guard.socks.socks[self.0];
+            if sock.buffered() > 0 || sock.at_eof() {
+                break;
+            }
+            if !matches!(sock.state, State::Connected) {
+                return Err(KernelError::NotConnected);
+            }
+            if nonblock {
+                return Err(KernelError::TryAgain);
+            }
+            if ctx.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            guard.sleep(ctx);
+        }
+
+        let sock = &guard.socks.socks[self.0];
+        let (first, second) = sock.unread();
+        let first = &first[..cmp::min(first.len(), n)];
+        let second = &second[..cmp::min(second.len(), n - first.len())];
+        let memory = ctx.proc_mut().memory_mut();
+        memory.copy_out_bytes(addr, first)?;
+        memory.copy_out_bytes(addr + first.len(), second)?;
+        let read = first.len() + second.len();
+
+        let this = guard.get_pin_mut().project();
+        let sock = &mut this.socks.socks[self.0];
+        sock.fwd_cnt = sock.fwd_cnt.wrapping_add(read as u32);
+        // Tell the peer it has room again once a quarter of the buffer is emptied, so that a
+        // peer that filled it wakes up.
+        if sock.state == State::Connected
+            && sock.fwd_cnt.wrapping_sub(sock.fwd_told) as usize >= RX_BUF_SIZE / 4
+        {
+            let hdr = sock.header(VSOCK_OP_CREDIT_UPDATE, 0, 0);
+            send_control(this.dev, this.socks, hdr);
+        }
+        guard.wakeup(ctx.kernel());
+        ctx.kernel().poll_queue().wakeup(ctx.kernel());
+        Ok(read)
+    }
+
+    /// Sends the `n` bytes at `addr` to the peer, sleeping while it has no room for them.
+    /// If `nonblock` is set, sends as much as the peer has room for, or returns
+    /// `Err(KernelError::TryAgain)` if that is nothing.
+    /// Returns Ok(number of bytes written) on success, Err(KernelError) on error.
+    pub fn write(
+        self,
+        addr: UVAddr,
+        n: usize,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let copy = |dst: &mut [u8], off: usize, ctx: &mut KernelCtx<'_, '_>| {
+            ctx.proc_mut().memory_mut().copy_in_bytes(dst, addr + off)
+        };
+        self.write_with(n, copy, nonblock, ctx)
+    }
+
+    /// Sends as much of `src` as the peer has room for, without sleeping.
+    /// Returns Ok(number of bytes written) on success, Err(KernelError) on error.
+    pub fn write_kernel(
+        self,
+        src: &[u8],
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError> {
+        let copy = |dst: &mut [u8], off: usize, _: &mut KernelCtx<'_, '_>| {
+            dst.copy_from_slice(&src[off..off + dst.len()]);
+            Ok(())
+        };
+        match self.write_with(src.len(), copy, true, ctx) {
+            Err(KernelError::TryAgain) => Ok(0),
+            res => res,
+        }
+    }
+
+    /// Sends `n` bytes, which `copy(dst, off, ctx)` copies from the `off`th byte on to `dst`.
+    fn write_with<F>(
+        self,
+        n: usize,
+        mut copy: F,
+        nonblock: bool,
+        ctx: &mut KernelCtx<'_, '_>,
+    ) -> Result<usize, KernelError>
+    where
+        F: FnMut(&mut [u8], usize, &mut KernelCtx<'_, '_>) -> Result<(), KernelError>,
+    {
+        let mut guard = hal().vsock().pinned_lock();
+        let mut written = 0;
+        while written < n {
+            let socks = &guard.socks;
+            let sock = &socks.socks[self.0];
+            match sock.state {
+                State::Connected if sock.peer_shutdown & VSOCK_SHUTDOWN_RCV == 0 => (),
+                State::Connected | State::Closed { .. } => return Err(KernelError::BrokenPipe),
+                _ => return Err(KernelError::NotConnected),
+            }
+            let len = cmp::min(cmp::min(n - written, sock.credit()), MAX_PAYLOAD);
+            let room =
+                socks.is_local(sock.peer_cid) || socks.pending.is_empty() && guard.dev.has_room();
+            if len == 0 || !room {
+                if nonblock {
+                    break;
+                }
+                if ctx.proc().killed() {
+                    return Err(KernelError::Interrupted);
+                }
+                guard.sleep(ctx);
+                continue;
+            }
+
+            let this = guard.get_pin_mut().project();
+            copy(&mut this.buf[..len], written, ctx)?;
+            let sock = &mut this.socks.socks[self.0];
+            let hdr = sock.header(VSOCK_OP_RW, 0, len);
+            sock.tx_cnt = sock.tx_cnt.wrapping_add(len as u32);
+            let _ = send(this.dev, this.socks, &hdr, &this.buf[..len]);
+            written += len;
+            guard.wakeup(ctx.kernel());
+            ctx.kernel().poll_queue().wakeup(ctx.kernel());
+        }
+        if written == 0 && n > 0 {
+            return Err(KernelError::TryAgain);
+        }
+        Ok(written)
+    }
+
+    /// Returns the readiness of the socket.
+    pub fn poll(self) -> PollEvents {
+        let guard = hal().vsock().pinned_lock();
+        let socks = &guard.socks;
+        let sock = &socks.socks[self.0];
+        let mut events = PollEvents::empty();
+        let waiting = socks.socks.iter().any(|conn| conn.listener == Some(self.0));
+        if sock.buffered() > 0 || sock.at_eof() || waiting {
+            events |= PollEvents::POLLIN;
+        }
+        if sock.state == State::Connected
+            && sock.peer_shutdown & VSOCK_SHUTDOWN_RCV == 0
+            && sock.credit() > 0
+        {
+            events |= PollEvents::POLLOUT;
+        }
+        if matches!(sock.state, State::Closed { .. }) {
+            events |= PollEvents::POLLHUP;
+        }
+        events
+    }
+
+    /// Closes the socket. Called when its file is freed.
+    pub fn close(self, kernel: KernelRef<'_, '_>) {
+        let mut guard = hal().vsock().pinned_lock();
+        let this = guard.get_pin_mut().project();
+        let mut dev = this.dev;
+        for hdr in this.socks.close(self.0) {
+            send_control(dev.as_mut(), this.socks, hdr);
+        }
+        guard.wakeup(kernel);
+        kernel.poll_queue().wakeup(kernel);
+    }
+}
+
+impl KernelCtx<'_, '_> {
+    /// Creates a socket of the family `domain` and the type `typ`, which may also contain
+    /// O_NONBLOCK and O_CLOEXEC, and allocates a file descriptor for it.
+    /// Returns Ok(file descriptor) on success, Err(KernelError) on error.
+    pub fn socket(&mut self, domain: i32, typ: i32, protocol: i32) -> Result<usize, KernelError> {
+        if domain != AF_VSOCK {
+            return Err(KernelError::FamilyNotSupported);
+        }
+        let status = FcntlFlags::from_bits(typ & !SOCK_STREAM)
+            .filter(|status| (FcntlFlags::O_NONBLOCK | FcntlFlags::O_CLOEXEC).contains(*status))
+            .filter(|_| typ & SOCK_STREAM != 0 && protocol == 0)
+            .ok_or(KernelError::InvalidArgument)?;
+
+        let mut guard = hal().vsock().pinned_lock();
+        let socks = &mut guard.get_pin_mut().project().socks.socks;
+        let i = socks
+            .iter()
+            .position(|sock| sock.state == State::Free)
+            .ok_or(KernelError::FileTableFull)?;
+        socks[i].state = State::Unconnected;
+        drop(guard);
+
+        let socket = Socket(i);
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::Socket { socket }, true, true)
+            .map_err(|_| {
+                socket.close(self.kernel());
+                KernelError::FileTableFull
+            })?;
+        f.set_status_flags(status);
+        let fd = f.fdalloc(self)?;
+        if status.contains(FcntlFlags::O_CLOEXEC) {
+            self.proc_mut()
+                .deref_mut_data()
+                .fds
+                .set_cloexec(fd as usize, true);
+        }
+        Ok(fd as usize)
+    }
+
+    /// Copies the address at `addr` from user space, and checks that it is of AF_VSOCK.
+    fn sockaddr(&mut self, addr: UVAddr) -> Result<SockaddrVm, KernelError> {
+        let mut sockaddr = SockaddrVm::default();
+        self.proc_mut()
+            .memory_mut()
+            .copy_in_bytes(sockaddr.as_bytes_mut(), addr)?;
+        if sockaddr.svm_family != AF_VSOCK as u16 {
+            return Err(KernelError::FamilyNotSupported);
+        }
+        Ok(sockaddr)
+    }
+
+    /// Binds the socket to the port of the address at `addr`, or to a free port if it is
+    /// VMADDR_PORT_ANY. Binding ports below 1024 needs `Caps::NET_BIND`.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn bind(&mut self, socket: Socket, addr: UVAddr) -> Result<(), KernelError> {
+        let sockaddr = self.sockaddr(addr)?;
+        if sockaddr.svm_port < FIRST_EPHEMERAL_PORT {
+            self.require_cap(Caps::NET_BIND)?;
+        }
+        let mut guard = hal().vsock().pinned_lock();
+        let socks = guard.get_pin_mut().project().socks;
+        if sockaddr.svm_cid != VMADDR_CID_ANY && !socks.is_local(sockaddr.svm_cid as u64) {
+            return Err(KernelError::InvalidArgument);
+        }
+        if socks.socks[socket.0].state != State::Unconnected || socks.socks[socket.0].port.is_some()
+        {
+            return Err(KernelError::InvalidArgument);
+        }
+        let port = if sockaddr.svm_port == VMADDR_PORT_ANY {
+            socks.ephemeral_port()
+        } else if socks.port_in_use(sockaddr.svm_port) {
+            return Err(KernelError::AddrInUse);
+        } else {
+            sockaddr.svm_port
+        };
+        socks.socks[socket.0].port = Some(port);
+        Ok(())
+    }
+
+    /// Makes the bound socket listen for connections, of which at most `backlog` wait for
+    /// accept().
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn listen(&mut self, socket: Socket, backlog: i32) -> Result<(), KernelError> {
+        let mut guard = hal().vsock().pinned_lock();
+        let sock = &mut guard.get_pin_mut().project().socks.socks[socket.0];
+        match sock.state {
+            State::Unconnected if sock.port.is_some() => (),
+            State::Listening => (),
+            State::Unconnected => return Err(KernelError::InvalidArgument),
+            _ => return Err(KernelError::IsConnected),
+        }
+        sock.state = State::Listening;
+        sock.backlog = cmp::max(1, cmp::min(backlog, NSOCK as i32)) as usize;
+        Ok(())
+    }
+
+    /// Takes a connection to the listening socket, sleeping until there is one, or returning
+    /// `Err(KernelError::TryAgain)` if `nonblock` is set. Allocates a file descriptor for it,
+    /// and copies the peer's address to `addr`, unless it is null.
+    /// Returns Ok(file descriptor) on success, Err(KernelError) on error.
+    pub fn accept(
+        &mut self,
+        socket: Socket,
+        addr: UVAddr,
+        nonblock: bool,
+    ) -> Result<usize, KernelError> {
+        let mut guard = hal().vsock().pinned_lock();
+        let (conn, sockaddr) = loop {
+            let socks = &mut guard.get_pin_mut().project().socks.socks;
+            if socks[socket.0].state != State::Listening {
+                return Err(KernelError::InvalidArgument);
+            }
+            if let Some(conn) = socks
+                .iter()
+                .position(|sock| sock.listener == Some(socket.0))
+            {
+                socks[conn].listener = None;
+                let sockaddr = SockaddrVm {
+                    svm_family: AF_VSOCK as u16,
+                    svm_port: socks[conn].peer_port,
+                    svm_cid: socks[conn].peer_cid as u32,
+                    ..Default::default()
+                };
+                break (Socket(conn), sockaddr);
+            }
+            if nonblock {
+                return Err(KernelError::TryAgain);
+            }
+            if self.proc().killed() {
+                return Err(KernelError::Interrupted);
+            }
+            guard.sleep(self);
+        };
+        drop(guard);
+
+        let f = self
+            .kernel()
+            .ftable()
+            .alloc_file(FileType::Socket { socket: conn }, true, true)
+            .map_err(|_| {
+                conn.close(self.kernel());
+                KernelError::FileTableFull
+            })?;
+        let fd = f.fdalloc(self)?;
+        if !addr.is_null() {
+            self.proc_mut().memory_mut().copy_out(addr, &sockaddr)?;
+        }
+        Ok(fd as usize)
+    }
+
+    /// Connects the socket to the address at `addr`, binding it to a free port first if it is
+    /// not bound, and sleeps until the peer accepts or refuses.
+    /// Returns Ok(()) on success, Err(KernelError) on error.
+    pub fn connect(&mut self, socket: Socket, addr: UVAddr) -> Result<(), KernelError> {
+        let sockaddr = self.sockaddr(addr)?;
+        let peer_cid = sockaddr.svm_cid as u64;
+        let mut guard = hal().vsock().pinned_lock();
+        let this = guard.get_pin_mut().project();
+        let socks = this.socks;
+        match socks.socks[socket.0].state {
+            State::Unconnected => (),
+            State::Listening => return Err(KernelError::InvalidArgument),
+            _ => return Err(KernelError::IsConnected),
+        }
+        if sockaddr.svm_cid == VMADDR_CID_ANY || sockaddr.svm_port == VMADDR_PORT_ANY {
+            return Err(KernelError::InvalidArgument);
+        }
+        let cid = if peer_cid == VMADDR_CID_LOCAL as u64 {
+            peer_cid
+        } else {
+            socks.cid.ok_or(KernelError::NoDevice)?
+        };
+        if socks.socks[socket.0].port.is_none() {
+            socks.socks[socket.0].port = Some(socks.ephemeral_port());
+        }
+        let sock = &mut socks.socks[socket.0];
+        sock.connect(cid, peer_cid, sockaddr.svm_port, State::Connecting);
+        let hdr = sock.header(VSOCK_OP_REQUEST, 0, 0);
+        send_control(this.dev, socks, hdr);
+        guard.wakeup(self.kernel());
+        self.kernel().poll_queue().wakeup(self.kernel());
+
+        loop {
+            match guard.socks.socks[socket.0].state {
+                State::Connected => return Ok(()),
+                State::Closed { refused } => {
+                    guard.get_pin_mut().project().socks.socks[socket.0].state = State::Unconnected;
+                    return Err(if refused {
+                        KernelError::ConnRefused
+                    } else {
+                        KernelError::ConnReset
+                    });
+                }
+                _ => (),
+            }
+            if self.proc().killed() {
+                let this = guard.get_pin_mut().project();
+                let sock = &mut this.socks.socks[socket.0];
+                let hdr = sock.header(VSOCK_OP_RST, 0, 0);
+                sock.state = State::Unconnected;
+                send_control(this.dev, this.socks, hdr);
+                return Err(KernelError::Interrupted);
+            }
+            guard.sleep(self);
+        }
+    }
+}
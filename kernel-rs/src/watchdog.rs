@@ -0,0 +1,162 @@
+//! A watchdog for soft lockups, which turns a CPU that silently stops scheduling into a report of
+//! where it is stuck.
+//!
+//! The scheduler loop of each CPU updates the CPU's heartbeat, and a CPU passes through the loop
+//! at least once a time slice, unless it is stuck in the kernel, such as spinning on a lock with
+//! interrupts disabled. Each CPU also has a watchdog timer, which checks the heartbeats of the
+//! other CPUs every `CHECK_NS`. When a CPU has not beaten for `STALL_NS`, the checker interrupts
+//! it in machine mode, which it takes even with interrupts disabled, for a snapshot of its pc
+//! and frame pointer, and at the next check prints where it is and the backtrace of its stack:
+//!
+//! ```text
+//! watchdog: CPU 1 stuck for 10s
+//! watchdog: CPU 1 at 0x0000000080012a4c rv6_kernel::lock::spinlock::RawSpinLock::acquire+0x3c
+//! backtrace:
+//!   #0 0x0000000080011f20 rv6_kernel::pipe::Pipe::read+0x54
+//!   ...
+//! ```
+//!
+//! A stuck CPU is reported once, until it beats again.
+
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+use array_macro::array;
+
+use crate::{
+    backtrace::{self, print_backtrace_at},
+    cpu::cpuid,
+    hal::hal,
+    kernel::{Kernel, KernelRef},
+    kmsg::Level,
+    log,
+    param::NCPU,
+    start::{request_snapshot, snapshot},
+    time::ktime_now,
+    timer::Timer,
+};
+
+/// Time between the checks of a CPU, in nanoseconds.
+pub const CHECK_NS: u64 = 1_000_000_000;
+
+/// Time without a heartbeat after which a CPU is stuck, in nanoseconds.
+const STALL_NS: u64 = 10_000_000_000;
+
+/// States of a CPU for the watchdog.
+const FINE: usize = 0;
+/// A checker asked the CPU for a snapshot.
+const ASKED: usize = 1;
+const REPORTED: usize = 2;
+
+pub struct Watchdog {
+    /// The monotonic time of the last heartbeat of each CPU, or 0 if it has not started.
+    beats: [AtomicU64; NCPU],
+
+    states: [AtomicUsize; NCPU],
+}
+
+impl Watchdog {
+    pub const fn new() -> Self {
+        Self {
+            beats: array![_ => AtomicU64::new(0); NCPU],
+            states: array![_ => AtomicUsize::new(FINE); NCPU],
+        }
+    }
+
+    /// Updates the heartbeat of this CPU. Called by its scheduler loop.
+    pub fn beat(&self) {
+        let id = cpuid();
+        self.beats[id].store(ktime_now(), Ordering::Relaxed);
+        if self.states[id].load(Ordering::Relaxed) != FINE
+            && self.states[id].swap(FINE, Ordering::Relaxed) == REPORTED
+        {
+            log!(Level::Warn, "watchdog", "watchdog: CPU {} recovered", id);
+        }
+    }
+
+    /// The function of the watchdog timer of each CPU. Checks the heartbeats of the other CPUs,
+    /// and reports those that are stuck.
+    pub fn check(_timer: Pin<&Timer>, now: u64, kernel: KernelRef<'_, '_>) -> Option<u64> {
+        let this = hal().get_ref().watchdog();
+        // A panic freezes the other CPUs.
+        if kernel.as_ref().is_panicked() {
+            return None;
+        }
+        for id in (0..NCPU).filter(|id| *id != cpuid()) {
+            let beat = this.beats[id].load(Ordering::Relaxed);
+            if beat == 0 || now.saturating_sub(beat) < STALL_NS {
+                continue;
+            }
+            // Another checker may be asking or reporting at the same time.
+            if this.states[id]
+                .compare_exchange(FINE, ASKED, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                request_snapshot(id);
+            } else if this.states[id]
+                .compare_exchange(ASKED, REPORTED, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                report(id, now - beat, kernel.as_ref());
+            }
+        }
+        Some(now + CHECK_NS)
+    }
+}
+
+/// Prints where the stuck CPU `id` is, from its snapshot.
+fn report(id: usize, stalled: u64, kernel: Pin<&Kernel>) {
+    log!(
+        Level::Error,
+        "watchdog",
+        "watchdog: CPU {} stuck for {}s",
+        id,
+        stalled / 1_000_000_000
+    );
+    match snapshot(id) {
+        None => {
+            log!(
+                Level::Error,
+                "watchdog",
+                "watchdog: CPU {} took no snapshot",
+                id
+            )
+        }
+        Some(snapshot) if snapshot.user => {
+            log!(
+                Level::Error,
+                "watchdog",
+                "watchdog: CPU {} at {:#018x} in user mode",
+                id,
+                snapshot.pc
+            )
+        }
+        Some(snapshot) => {
+            match backtrace::lookup(snapshot.pc) {
+                Some((name, off)) => {
+                    log!(
+                        Level::Error,
+                        "watchdog",
+                        "watchdog: CPU {} at {:#018x} {}+{:#x}",
+                        id,
+                        snapshot.pc,
+                        name,
+                        off
+                    )
+                }
+                None => {
+                    log!(
+                        Level::Error,
+                        "watchdog",
+                        "watchdog: CPU {} at {:#018x}",
+                        id,
+                        snapshot.pc
+                    )
+                }
+            }
+            print_backtrace_at(kernel, snapshot.fp);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+//! Work that subsystems queue to run later in a kernel thread, so that the process that queues it
+//! does not wait for it.
+//!
+//! `Kernel::init` starts `NWORKER` kernel threads, "kworker/0" and so on, which sleep on the
+//! kernel's `WorkQueue` until `WorkQueue::queue` adds a function and its argument to it. A worker
+//! takes the oldest item off the queue and runs it in its own process context, where it may sleep
+//! on locks and disk requests as any process does, while the other workers take the next items.
+//!
+//! Work that is queued already is not queued again, and work that finds the queue full is refused
+//! instead of making the caller wait, so that callers that hold locks can queue work. Only work
+//! that can be dropped or retried, such as reading ahead, belongs here.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use arrayvec::ArrayVec;
+
+use crate::{
+    error::KernelError,
+    kernel::KernelRef,
+    ktest, ktest_assert,
+    lock::SleepableLock,
+    param::NCPU,
+    proc::KernelCtx,
+    time::{ktime_now, TICK_NS},
+};
+
+/// Number of kernel threads that run queued work.
+pub const NWORKER: usize = NCPU;
+
+/// Maximum number of queued work items.
+const NWORK: usize = 32;
+
+/// A function queued to run in a worker, with its argument.
+#[derive(Copy, Clone)]
+struct Work {
+    func: fn(&KernelCtx<'_, '_>, usize),
+    arg: usize,
+}
+
+impl PartialEq for Work {
+    fn eq(&self, other: &Self) -> bool {
+        self.func as usize == other.func as usize && self.arg == other.arg
+    }
+}
+
+pub struct WorkQueue {
+    queue: SleepableLock<ArrayVec<Work, NWORK>>,
+}
+
+impl WorkQueue {
+    pub const fn new() -> Self {
+        Self {
+            queue: SleepableLock::new("workqueue", ArrayVec::new_const()),
+        }
+    }
+
+    /// Queues `func(arg)` to run in a worker, and wakes the workers up.
+    /// Returns Err(KernelError::TryAgain) if `NWORK` other items are queued.
+    pub fn queue(
+        &self,
+        func: fn(&KernelCtx<'_, '_>, usize),
+        arg: usize,
+        kernel: KernelRef<'_, '_>,
+    ) -> Result<(), KernelError> {
+        let work = Work { func, arg };
+        let mut queue = self.queue.lock();
+        if !queue.contains(&work) {
+            queue.try_push(work).map_err(|_| KernelError::TryAgain)?;
+            queue.wakeup(kernel);
+        }
+        Ok(())
+    }
+}
+
+/// The body of each worker, which runs queued work forever.
+pub fn worker(ctx: KernelCtx<'_, '_>) -> ! {
+    let workqueue = ctx.kernel().workqueue();
+    loop {
+        let mut queue = workqueue.queue.lock();
+        while queue.is_empty() {
+            queue.sleep(&ctx);
+        }
+        let work = queue.remove(0);
+        drop(queue);
+        (work.func)(&ctx, work.arg);
+    }
+}
+
+ktest! {
+    fn workqueue_runs_work(ctx) {
+        static ARG: AtomicUsize = AtomicUsize::new(0);
+        fn set(_ctx: &KernelCtx<'_, '_>, arg: usize) {
+            ARG.store(arg, Ordering::Release);
+        }
+
+        ktest_assert!(ctx.kernel().workqueue().queue(set, 42, ctx.kernel()).is_ok());
+        // The workers run once this process sleeps, or on the other CPUs.
+        let poll_queue = ctx.kernel().poll_queue();
+        let deadline = ktime_now() + 10 * TICK_NS;
+        while ARG.load(Ordering::Acquire) != 42 && ktime_now() < deadline {
+            poll_queue.wait(poll_queue.generation(), ctx);
+        }
+        ktest_assert!(ARG.load(Ordering::Acquire) == 42);
+    }
+}
@@ -0,0 +1,23 @@
+// Audit log events.
+// Keep in sync with kernel-rs/src/audit.rs.
+#define AUDIT_EXEC       1  // arg: inode number of the program
+#define AUDIT_SETUID     2  // arg: requested user ID
+#define AUDIT_SETGID     3  // arg: requested group ID
+#define AUDIT_DENIED     4  // arg: inode number of the file
+#define AUDIT_PRIVILEGED 5  // arg: system call number
+#define AUDIT_KILL       6  // arg: target process ID
+#define AUDIT_SETTIME    7  // arg: change of the realtime clock in nanoseconds
+#define AUDIT_CAPDROP    8  // arg: capabilities to drop
+
+struct auditrec {
+  uint64 seq;      // Sequence number, counting from zero at boot
+  uint64 time;     // Nanoseconds since boot
+  uint event;
+  int pid;
+  uint uid;
+  uint euid;
+  uint egid;
+  int error;       // Zero on success, or the error number
+  uint64 arg;
+  char name[16];   // Process name
+};
@@ -0,0 +1,22 @@
+// Generated from abi/src/cred.rs by abi/cheader.pl - do not edit.
+// Capabilities, each of which lets a process bypass a permission check or make a privileged
+// system call. The bits are those of the same capabilities in Linux.
+// 
+// A process whose effective user ID is the superuser's has the capabilities of its bounding
+// set, and other processes have none. capdrop() removes capabilities from the bounding set for
+// good, and fork() and exec() keep the set, so the superuser can run a program with fewer
+// privileges, which no set-user-ID program gives back.
+
+#define CAP_CHOWN 0x1  // Change the owner and group of files
+#define CAP_DAC_OVERRIDE 0x2  // Bypass the permission checks of files
+#define CAP_FOWNER 0x8  // Change the mode of files that others own
+#define CAP_KILL 0x20  // Send signals to the processes of other users
+#define CAP_SETGID 0x40  // Set the group IDs to any group
+#define CAP_SETUID 0x80  // Set the user IDs to any user
+#define CAP_NET_BIND 0x400  // Bind vsock ports below 1024
+#define CAP_SYS_PTRACE 0x80000  // Attach to processes with ptrace()
+#define CAP_SYS_ADMIN 0x200000  // Administer the system: mknod(), chroot(), quotas of others, tracing and debugging
+#define CAP_SYS_BOOT 0x400000  // Power off and suspend the machine
+#define CAP_SYS_RESOURCE 0x1000000  // Exceed disk quotas
+#define CAP_SYS_TIME 0x2000000  // Set the realtime clock
+#define CAP_SYSLOG 0x400000000  // Read and clear the kernel log
@@ -0,0 +1,11 @@
+// Generated from abi/src/dirent.rs by abi/cheader.pl - do not edit.
+// Directory entries. A directory is a file containing a sequence of them.
+
+#define DIRSIZ 14  // Maximum length of a file name
+
+struct dirent {
+  ushort inum;  // Inode number, or 0 if the entry is free
+  char name[DIRSIZ];  // File name, NUL-terminated if shorter than DIRSIZ
+};
+
+_Static_assert(sizeof(struct dirent) == 16, "struct dirent");
@@ -0,0 +1,44 @@
+// Generated from abi/src/errno.rs by abi/cheader.pl - do not edit.
+// Error codes, which a failed system call returns negated, and user programs find in errno.
+
+#define EPERM 1  // Operation not permitted
+#define ENOENT 2  // No such file or directory
+#define ESRCH 3  // No such process
+#define EINTR 4  // Interrupted system call
+#define EIO 5  // I/O error
+#define E2BIG 7  // Argument list too long
+#define ENOEXEC 8  // Exec format error
+#define EBADF 9  // Bad file descriptor
+#define ECHILD 10  // No child processes
+#define EAGAIN 11  // Resource temporarily unavailable
+#define ENOMEM 12  // Out of memory
+#define EACCES 13  // Permission denied
+#define EFAULT 14  // Bad address
+#define EBUSY 16  // Device or resource busy
+#define EEXIST 17  // File exists
+#define EXDEV 18  // Cross-device link
+#define ENODEV 19  // No such device
+#define ENOTDIR 20  // Not a directory
+#define EISDIR 21  // Is a directory
+#define EINVAL 22  // Invalid argument
+#define ENFILE 23  // Too many open files in system
+#define EMFILE 24  // Too many open files
+#define ENOTTY 25  // Inappropriate ioctl for device
+#define EFBIG 27  // File too large
+#define ENOSPC 28  // No space left on device
+#define ESPIPE 29  // Illegal seek
+#define EPIPE 32  // Broken pipe
+#define ERANGE 34  // Result too large
+#define ENAMETOOLONG 36  // File name too long
+#define ENOSYS 38  // Function not implemented
+#define ENOTEMPTY 39  // Directory not empty
+#define EIDRM 43  // Identifier removed
+#define ENOTSOCK 88  // Socket operation on non-socket
+#define EMSGSIZE 90  // Message too long
+#define EAFNOSUPPORT 97  // Address family not supported by protocol
+#define EADDRINUSE 98  // Address already in use
+#define ECONNRESET 104  // Connection reset by peer
+#define EISCONN 106  // Transport endpoint is already connected
+#define ENOTCONN 107  // Transport endpoint is not connected
+#define ECONNREFUSED 111  // Connection refused
+#define EDQUOT 122  // Disk quota exceeded
@@ -0,0 +1,4 @@
+// Sites of failinject().
+// Keep in sync with kernel-rs/src/failinject.rs.
+#define FAIL_KALLOC 0   // Page allocations
+#define FAIL_DISK   1   // Blocks of file contents read or written for read() and write()
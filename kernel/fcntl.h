@@ -3,3 +3,26 @@
 #define O_RDWR    0x002
 #define O_CREATE  0x200
 #define O_TRUNC   0x400
+#define O_NONBLOCK 0x800
+#define O_APPEND  0x1000
+#define O_CLOEXEC 0x80000
+
+#define F_GETFD   1
+#define F_SETFD   2
+#define F_GETFL   3
+#define F_SETFL   4
+#define F_SETPIPE_SZ 1031
+#define F_GETPIPE_SZ 1032
+
+#define FD_CLOEXEC 1
+
+// fadvise() advice.
+#define POSIX_FADV_NORMAL     0
+#define POSIX_FADV_RANDOM     1
+#define POSIX_FADV_SEQUENTIAL 2
+#define POSIX_FADV_DONTNEED   4
+
+// eventfd() flags.
+#define EFD_SEMAPHORE 1
+#define EFD_NONBLOCK  O_NONBLOCK
+#define EFD_CLOEXEC   O_CLOEXEC
@@ -37,4 +37,10 @@ struct devsw {
 
 extern struct devsw devsw[];
 
+// major device numbers, claimed in kernel-rs with register_chardev!.
 #define CONSOLE 1
+#define URANDOM 2
+#define TTYS1   3
+#define DEVNULL 4
+#define DEVZERO 5
+#define DEVFULL 6
@@ -6,7 +6,7 @@
 #define BSIZE 1024  // block size
 
 // Disk layout:
-// [ boot block | super block | log | inode blocks |
+// [ boot block | super block | log | inode blocks | inode bit map |
 //                                          free bit map | data blocks]
 //
 // mkfs computes the super block and builds an initial file system. The
@@ -19,12 +19,44 @@ struct superblock {
   uint nlog;         // Number of log blocks
   uint logstart;     // Block number of first log block
   uint inodestart;   // Block number of first inode block
+  uint ibmapstart;   // Block number of first inode bit map block
   uint bmapstart;    // Block number of first free map block
+  uint flags;        // FS_* flags
+  uint checksum;     // CRC32C of the fields above, given FS_CHECKSUMS
 };
 
-#define FSMAGIC 0x10203040
+#define FSMAGIC 0x10203041
 
-#define NDIRECT 12
+// Superblock flag: the superblock, the inode blocks, and the directory
+// blocks have checksums. The CRC32C of the first BSIZE-4 bytes of an
+// inode block or a directory block is stored in its last 4 bytes, so the
+// last inode of each inode block and the last entry of each directory
+// block are never used.
+#define FS_CHECKSUMS 1
+
+// Superblock flag: the addrs of each inode hold NEXTENT_INLINE extents,
+// followed by the block numbers of NEXTENT_BLOCKS extent blocks of
+// EXTENTS_PER_BLOCK extents each. The extents map the blocks of the file
+// in order, up to the first one of length 0.
+#define FS_EXTENTS 2
+
+// CRC32C (Castagnoli) of n bytes at p, as the kernel computes it.
+static inline uint
+crc32c(const void *p, uint n)
+{
+  const uchar *s = p;
+  uint crc = ~0;
+  int i;
+
+  while(n-- > 0){
+    crc ^= *s++;
+    for(i = 0; i < 8; i++)
+      crc = (crc >> 1) ^ (0x82F63B78 & -(crc & 1));
+  }
+  return ~crc;
+}
+
+#define NDIRECT 10
 #define NINDIRECT (BSIZE / sizeof(uint))
 #define MAXFILE (NDIRECT + NINDIRECT)
 
@@ -35,9 +67,24 @@ struct dinode {
   ushort minor;         // Minor device number (T_DEVICE only)
   short nlink;          // Number of links to inode in file system
   uint size;            // Size of file (bytes)
+  ushort mode;          // Permission bits, with I_SUID and I_SGID
+  ushort uid;           // Owner's user ID
+  ushort gid;           // Group ID
+  ushort unused;
   uint addrs[NDIRECT+1];   // Data block addresses
 };
 
+// A run of len disk blocks from start, given FS_EXTENTS.
+struct extent {
+  uint start;
+  uint len;
+};
+
+#define NEXTENT_INLINE 4
+#define NEXTENT_BLOCKS 3
+#define EXTENTS_PER_BLOCK (BSIZE / sizeof(struct extent))
+#define NEXTENT (NEXTENT_INLINE + NEXTENT_BLOCKS * EXTENTS_PER_BLOCK)
+
 // Inodes per block.
 #define IPB           (BSIZE / sizeof(struct dinode))
 
@@ -50,11 +97,8 @@ struct dinode {
 // Block of free map containing bit for block b
 #define BBLOCK(b, sb) ((b)/BPB + sb.bmapstart)
 
-// Directory is a file containing a sequence of dirent structures.
-#define DIRSIZ 14
+// Block of inode bit map containing bit for inode i
+#define IBBLOCK(i, sb) ((i)/BPB + sb.ibmapstart)
 
-struct dirent {
-  ushort inum;
-  char name[DIRSIZ];
-};
+#include "kernel/dirent.h"
 
@@ -0,0 +1,8 @@
+// Counters of fuzzinfo().
+// Keep in sync with kernel-rs/src/fuzz.rs.
+struct fuzzinfo {
+  uint64 calls;    // System calls that returned, including those that failed
+  uint64 errors;   // Of them, those that failed
+  uint64 faults;   // Of them, those that failed with EFAULT
+  uint64 refused;  // System calls refused in the fuzzing mode, not counted above
+};
@@ -0,0 +1,30 @@
+// ioctl requests.
+// Keep in sync with the drivers in kernel-rs/src.
+
+// Console.
+#define TIOCGWINSZ 0x5413  // Get the window size
+#define TIOCSWINSZ 0x5414  // Set the window size
+#define TCGETS     0x5401  // Get the terminal attributes
+#define TCSETS     0x5402  // Set the terminal attributes
+#define TIOCGPGRP  0x540f  // Get the foreground process group
+#define TIOCSPGRP  0x5410  // Set the foreground process group, or 0 for none
+
+struct winsize {
+  ushort ws_row;
+  ushort ws_col;
+  ushort ws_xpixel;
+  ushort ws_ypixel;
+};
+
+// Input flags.
+#define ICRNL  0000400  // Translate carriage return to newline
+
+// Local flags.
+#define ISIG   0000001  // Handle the special characters that act on processes
+#define ICANON 0000002  // Edit input, and make it available a line at a time
+#define ECHO   0000010  // Echo input characters
+
+struct termios {
+  uint c_iflag;
+  uint c_lflag;
+};
@@ -0,0 +1,49 @@
+// Generated from abi/src/ioring.rs by abi/cheader.pl - do not edit.
+// The rings of ioring_setup() and ioring_enter(), through which a process queues reads,
+// writes and fsyncs, and collects their results, without a system call for each.
+// 
+// ioring_setup() maps a page at IORING_ADDR, holding an IoringHeader, IORING_SQ_ENTRIES
+// submission entries from IORING_SQ_OFF, and IORING_CQ_ENTRIES completion entries from
+// IORING_CQ_OFF. The process fills the submission entry at sq_tail and advances sq_tail, and
+// ioring_enter() takes entries from sq_head, and adds a completion entry at cq_tail for each.
+// The process reads the completion entry at cq_head and advances cq_head. The indices only
+// grow, and index i is entry i % IORING_SQ_ENTRIES or i % IORING_CQ_ENTRIES.
+
+#define IORING_ADDR 0x3fffffd000  // User address of the ring page
+#define IORING_SQ_ENTRIES 64  // Number of submission entries
+#define IORING_CQ_ENTRIES 64  // Number of completion entries
+#define IORING_SQ_OFF 64  // Offset of the submission entries in the ring page
+#define IORING_CQ_OFF 2112  // Offset of the completion entries in the ring page
+
+#define IORING_OP_NOP 0  // Does nothing
+#define IORING_OP_READ 1  // Reads len bytes into addr, as read() does
+#define IORING_OP_WRITE 2  // Writes len bytes from addr, as write() does
+#define IORING_OP_FSYNC 3  // Waits until the writes to the file are on the disk
+
+struct ioring_header {
+  uint sq_head;  // Next submission entry for the kernel to take
+  uint sq_tail;  // Next submission entry for the process to fill
+  uint cq_head;  // Next completion entry for the process to read
+  uint cq_tail;  // Next completion entry for the kernel to fill
+};
+
+_Static_assert(sizeof(struct ioring_header) == 16, "struct ioring_header");
+
+struct ioring_sqe {
+  uint opcode;  // IORING_OP_*
+  int fd;  // File descriptor
+  uint64 addr;  // Buffer of a read or write
+  uint len;  // Number of bytes to read or write
+  uint reserved;  // Zero
+  uint64 user_data;  // Copied to the completion entry
+};
+
+_Static_assert(sizeof(struct ioring_sqe) == 32, "struct ioring_sqe");
+
+struct ioring_cqe {
+  uint64 user_data;  // user_data of the submission entry
+  int res;  // What the system call would return: a byte count, or a negated error number
+  uint flags;  // Zero
+};
+
+_Static_assert(sizeof(struct ioring_cqe) == 16, "struct ioring_cqe");
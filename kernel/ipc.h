@@ -0,0 +1,13 @@
+// Generated from abi/src/ipc.rs by abi/cheader.pl - do not edit.
+// Keys and flags of System V IPC objects: semaphore sets and message queues.
+// 
+// A process finds an object by its key, which processes agree on beforehand, and gets the
+// object's identifier, which the other calls take. The low nine bits of the flags are the
+// permissions of a new object, as in a file's mode.
+
+#define IPC_PRIVATE 0  // The key that always makes a new object, which other processes reach only by its identifier
+#define IPC_CREAT 0x200  // Make the object if no object has the key
+#define IPC_EXCL 0x400  // With IPC_CREAT, fail if an object has the key already
+#define IPC_NOWAIT 0x800  // Fail with EAGAIN instead of waiting
+
+#define IPC_RMID 0  // Command that removes the object, which only its owner may
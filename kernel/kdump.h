@@ -0,0 +1,36 @@
+// Crash dumps, which the kernel writes to the dump partition on panic.
+// Keep in sync with kernel-rs/src/kdump.rs.
+//
+// The dump partition is the last DUMPSIZE blocks of the disk, after the
+// file system. Its first block is a struct kdumphdr, and the log and the
+// memory regions follow, at the blocks that the header says.
+
+#define KDUMP_EMPTY 0x6b647030  // Magic of a partition without a dump
+#define KDUMP_MAGIC 0x6b647031  // Magic of a dump
+
+#define KDUMP_NREGS    8
+#define KDUMP_NREGION  2
+#define KDUMP_MSGLEN   256
+
+// Registers of the CPU that panicked, in the order of kdumphdr.regs.
+#define KDUMP_REG_NAMES \
+  { "sp", "fp", "tp", "sepc", "scause", "stval", "sstatus", "satp" }
+
+struct kdumpregion {
+  uint64 addr;   // Kernel virtual address
+  uint64 len;    // Length in bytes
+  uint64 block;  // First block in the partition
+};
+
+struct kdumphdr {
+  uint magic;
+  uint cpu;        // CPU that panicked
+  uint64 uptime;   // Time since boot, in nanoseconds
+  uint64 regs[KDUMP_NREGS];
+  uint64 logblock; // First block of the kernel log in the partition
+  uint64 loglen;   // Length of the kernel log in bytes
+  uint nregion;
+  uint pad;
+  struct kdumpregion regions[KDUMP_NREGION];  // The stack, and the kernel's data
+  char msg[KDUMP_MSGLEN];  // Panic message, NUL-terminated if shorter
+};
@@ -0,0 +1,21 @@
+#!/usr/bin/perl -w
+
+# Generate ksyms.S, the symbol table of the kernel for backtraces,
+# from the output of nm -n -C. See kernel-rs/src/backtrace.rs.
+
+print "# generated by ksyms.pl - do not edit\n";
+print ".section .ksyms, \"a\"\n";
+print ".balign 8\n";
+
+while(<STDIN>){
+    next unless /^([0-9a-f]+) [TtWw] (.+)$/;
+    my ($addr, $name) = ($1, $2);
+    # Drop the hashes of Rust symbols.
+    $name =~ s/::h[0-9a-f]{16}$//;
+    $name =~ s/([\\"])/\\$1/g;
+    my $len = length($name =~ s/\\(.)/$1/gr);
+    print ".quad 0x$addr\n";
+    print ".short $len\n";
+    print ".ascii \"$name\"\n";
+    print ".balign 8\n";
+}
@@ -0,0 +1,8 @@
+// Records of leakcheck().
+// Keep in sync with kernel-rs/src/leak.rs.
+struct leakrec {
+  char file[48];   // Source file of the caller, NUL-terminated if shorter
+  uint line;
+  uint pages;      // Number of pages still allocated
+  uint64 age;      // Nanoseconds since the oldest of them was allocated
+};
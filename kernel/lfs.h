@@ -0,0 +1,79 @@
+// On-disk format of LFS, the log-structured file system, which mkfs -l
+// builds. Keep in sync with kernel-rs/src/fs/lfs.
+//
+// Disk layout: LFS_NSEG segments of LFS_SEGSIZE blocks each.
+// [ boot block | unused | checkpoint region 0 | checkpoint region 1 ] [ segment 1 ] ...
+//
+// Segment 0 holds the two checkpoint regions, and the checkpoints
+// alternate between them. A region is a struct checkpoint, followed by
+// the segment usage table and the inode map. The other segments hold
+// partial segments: a struct segsummary, followed by the blocks that it
+// names. Each inode has a block of its own, which the inode map finds.
+
+#define LFS_SEGSIZE 32   // Blocks per segment
+#define LFS_NSEG    128  // Segments, counting segment 0
+#define LFS_SIZE    (LFS_NSEG * LFS_SEGSIZE)  // Size of the file system in blocks
+
+#define CHECKPOINT_MAGIC 0x4c465343
+#define CHECKPOINT_START 2  // First block of checkpoint region 0
+
+struct checkpoint {
+  uint magic;     // Must be CHECKPOINT_MAGIC
+  uint serial;    // Number of the checkpoint, which is in region serial % 2
+  uint next;      // Serial number of the next partial segment
+  uint seg;       // Segment and offset in it where that partial segment goes
+  uint start;
+  uint time;      // When the checkpoint was written, in seconds since the epoch
+  uint crc;       // CRC32C of the CRC32Cs of the blocks of the region,
+                  // that of the header with this field 0 first
+  uint reserved;  // Zero
+};
+
+struct segusage {
+  uint live;   // Number of live bytes in the segment
+  uint mtime;  // When a block was last written into it
+};
+
+#define SEGTABLE_BLOCKS ((LFS_NSEG * sizeof(struct segusage) + BSIZE - 1) / BSIZE)
+
+// The inode map holds the address of the block of each inode, or 0 if
+// the inode is free.
+#define IMAP_BLOCKS 4
+#define NIMAP (IMAP_BLOCKS * BSIZE / sizeof(uint))  // Counting inode 0
+
+#define CHECKPOINT_BLOCKS (1 + SEGTABLE_BLOCKS + IMAP_BLOCKS)
+
+#define SUMMARY_MAGIC    0x53454753
+#define SUMMARY_INODE    0xffffffff  // Offset of the block of the inode itself
+#define SUMMARY_INDIRECT 0xfffffffe  // Offset of the indirect block of the inode
+#define NPENDING (MAXOPBLOCKS * 2)   // Max blocks in a partial segment
+
+struct sumentry {
+  uint inum;  // Inode that the block belongs to, or 0 if the block is dead
+  uint off;   // Offset of the block in the inode, in blocks
+  uint crc;   // CRC32C of the block
+};
+
+struct segsummary {
+  uint magic;     // Must be SUMMARY_MAGIC
+  uint serial;    // Serial number of the partial segment
+  uint nblocks;   // Number of blocks after the summary
+  uint next_seg;  // Segment of the next partial segment
+  struct sumentry entries[NPENDING];
+};
+
+#define LFS_NDIRECT 12
+
+// On-disk inode structure, alone at the start of its block.
+struct lfs_dinode {
+  short type;           // File type, or 0 if the inode is free
+  ushort major;         // Major device number (T_DEVICE only)
+  ushort minor;         // Minor device number (T_DEVICE only)
+  short nlink;          // Number of links to inode in file system
+  uint size;            // Size of file (bytes)
+  ushort mode;          // Permission bits, with I_SUID and I_SGID
+  ushort uid;           // Owner's user ID
+  ushort gid;           // Group ID
+  ushort unused;
+  uint addrs[LFS_NDIRECT+1];  // Data block addresses, then the indirect block
+};
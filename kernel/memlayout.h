@@ -62,6 +62,10 @@
 //   fixed-size stack
 //   expandable heap
 //   ...
+//   VDSO (the page the kernel shares with the process)
+//   IORING (the rings of ioring_setup(), if any)
 //   TRAPFRAME (p->trapframe, used by the trampoline)
 //   TRAMPOLINE (the same page as in the kernel)
 #define TRAPFRAME (TRAMPOLINE - PGSIZE)
+#define IORING (TRAPFRAME - PGSIZE)
+#define VDSO (IORING - PGSIZE)
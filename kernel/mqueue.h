@@ -0,0 +1,21 @@
+// Generated from abi/src/mqueue.rs by abi/cheader.pl - do not edit.
+// Message queues of mq_open(), mq_send() and mq_receive(). See `ipc` for keys.
+// 
+// A queue holds up to mq_maxmsg messages of up to mq_msgsize bytes each, both fixed when the
+// queue is made. mq_receive() takes the oldest of the messages of the highest priority. A
+// descriptor of a queue works with poll(): it is readable while the queue holds a message, and
+// writable while the queue has room for one. Unlinking a queue frees its key, but the queue
+// lives until its last descriptor is closed.
+
+#define MQ_MAXMSG 10  // Maximum number of messages in a queue
+#define MQ_MSGSIZE 128  // Maximum size of a message
+#define MQ_PRIO_MAX 32  // Priorities are less than this
+
+struct mq_attr {
+  int mq_flags;  // O_NONBLOCK if the descriptor is nonblocking; ignored by mq_open()
+  int mq_maxmsg;  // Maximum number of messages in the queue
+  int mq_msgsize;  // Maximum size of a message in the queue
+  int mq_curmsgs;  // Number of messages in the queue; ignored by mq_open()
+};
+
+_Static_assert(sizeof(struct mq_attr) == 16, "struct mq_attr");
@@ -1,13 +1,16 @@
 #define NPROC        64  // maximum number of processes
 #define NCPU          8  // maximum number of CPUs
-#define NOFILE       16  // open files per process
-#define NFILE       100  // open files per system
+#define NOFILE       16  // statically allocated open files per process
+#define NFDPAGE       2  // max pages of open files per process past NOFILE
+#define NFILE       100  // statically allocated open files per system
 #define NINODE       50  // maximum number of active i-nodes
 #define NDEV         10  // maximum major device number
 #define ROOTDEV       1  // device number of file system root disk
 #define MAXARG       32  // max exec arguments
 #define MAXOPBLOCKS  10  // max # of blocks any FS op writes
 #define LOGSIZE      (MAXOPBLOCKS*3)  // max data blocks in on-disk log
-#define NBUF         (MAXOPBLOCKS*3)  // size of disk block cache
+#define NBUF         (MAXOPBLOCKS*3)  // size of each shard of disk block cache
+#define NBUFSHARD    4  // number of shards of disk block cache
 #define FSSIZE       2000  // size of file system in blocks
+#define DUMPSIZE     2048  // size of crash dump partition in blocks
 #define MAXPATH      128   // maximum file path name
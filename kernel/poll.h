@@ -0,0 +1,13 @@
+// poll() events.
+// Keep in sync with PollEvents in kernel-rs/src/poll.rs.
+#define POLLIN   0x01  // There is data to read
+#define POLLOUT  0x04  // Writing would not block
+#define POLLERR  0x08  // Writing would fail (always reported)
+#define POLLHUP  0x10  // The pipe has no writers (always reported)
+#define POLLNVAL 0x20  // The file descriptor is not open (always reported)
+
+struct pollfd {
+  int fd;         // File descriptor, ignored if negative
+  short events;   // Requested events
+  short revents;  // Returned events
+};
@@ -0,0 +1,13 @@
+// Commands and samples of profile().
+// Keep in sync with kernel-rs/src/profile.rs.
+#define PROF_OFF  0  // Stop taking samples
+#define PROF_ON   1  // Discard the samples, and start taking samples
+#define PROF_READ 2  // Move samples of a CPU's buffer to user memory
+
+struct profsample {
+  uint64 pc;
+  int pid;         // Running process, or 0 for the scheduler
+  ushort cpu;
+  ushort user;     // 1 if in user mode, 0 if in the kernel
+  char func[48];   // Kernel function, truncated from the front, NUL-terminated if shorter
+};
@@ -0,0 +1,21 @@
+// ptrace() requests.
+// Keep in sync with kernel-rs/src/proc/ptrace.rs.
+#define PTRACE_TRACEME    0   // Let the parent trace this process
+#define PTRACE_PEEKDATA   2   // Store the word at addr of the child to *data
+#define PTRACE_POKEDATA   5   // Write the word data to addr of the child
+#define PTRACE_CONT       7   // Resume the stopped child
+#define PTRACE_SINGLESTEP 9   // Resume the stopped child for one instruction
+#define PTRACE_GETREGS    12  // Store the registers of the child to *data
+#define PTRACE_ATTACH     16  // Trace a child, which stops soon
+#define PTRACE_DETACH     17  // Stop tracing the stopped child, and resume it
+
+// Why a traced child stopped, as WSTOPREASON() in kernel/signal.h reports.
+#define PTRACE_STOP_ATTACH     1
+#define PTRACE_STOP_EXEC       2
+#define PTRACE_STOP_BREAKPOINT 3
+#define PTRACE_STOP_STEP       4
+
+struct user_regs {
+  uint64 pc;
+  uint64 x[31];  // x1 to x31
+};
@@ -0,0 +1,23 @@
+// Generated from abi/src/quota.rs by abi/cheader.pl - do not edit.
+// Disk quotas of quotactl(), which limit the blocks and inodes that the files of each user
+// take.
+// 
+// A hard limit is never exceeded. A soft limit may be exceeded for a grace period, after which
+// it acts as a hard limit until usage drops below it. A limit of 0 is no limit. Blocks count
+// the data blocks of files, i.e. their sizes rounded up to whole blocks.
+
+#define Q_GETQUOTA 1  // Reads the limits and usage of a user into a Dqblk
+#define Q_SETQUOTA 2  // Sets the limits of a user from a Dqblk, ignoring its usage; superuser only
+
+struct dqblk {
+  uint bhardlimit;  // Hard limit on blocks
+  uint bsoftlimit;  // Soft limit on blocks
+  uint curblocks;  // Blocks in use
+  uint ihardlimit;  // Hard limit on inodes
+  uint isoftlimit;  // Soft limit on inodes
+  uint curinodes;  // Inodes in use
+  uint btime;  // Ticks left before the soft limit on blocks acts as a hard limit, or 0 if not over it
+  uint itime;  // Ticks left before the soft limit on inodes acts as a hard limit, or 0 if not over it
+};
+
+_Static_assert(sizeof(struct dqblk) == 32, "struct dqblk");
@@ -0,0 +1,4 @@
+// getrandom() flags.
+// Keep in sync with kernel-rs/src/random.rs.
+#define GRND_NONBLOCK 1  // Fail with EAGAIN instead of waiting for the generator to be seeded
+#define GRND_RANDOM   2  // Use the blocking pool (there is only one pool)
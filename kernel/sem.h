@@ -0,0 +1,25 @@
+// Generated from abi/src/sem.rs by abi/cheader.pl - do not edit.
+// Semaphore sets of semget(), semop() and semctl(). See `ipc` for keys and flags.
+// 
+// semop() applies an array of Sembufs to a set at once: either all of them, or, if one would
+// make a value negative or wait for a value to be 0, none, and waits until it can apply all of
+// them. An operation with SEM_UNDO is undone when the process exits, so that a process that
+// dies holding a semaphore releases it.
+
+#define SEMMSL 16  // Maximum number of semaphores in a set
+#define SEMOPM 8  // Maximum number of operations in a semop() call
+#define SEMVMX 32767  // Maximum value of a semaphore
+
+#define SEM_UNDO 0x1000  // Undo the operation when the process exits
+
+#define GETVAL 12  // semctl() command that returns the value of a semaphore
+#define GETNCNT 14  // semctl() command that returns the number of processes waiting for the value to grow
+#define SETVAL 16  // semctl() command that sets the value of a semaphore, dropping its undo adjustments
+
+struct sembuf {
+  ushort sem_num;  // Index of the semaphore in the set
+  short sem_op;  // Added to the value; 0 waits for the value to be 0
+  short sem_flg;  // IPC_NOWAIT and SEM_UNDO
+};
+
+_Static_assert(sizeof(struct sembuf) == 6, "struct sembuf");
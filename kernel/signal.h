@@ -0,0 +1,16 @@
+// Signals, which have only their default actions.
+// Keep in sync with kernel-rs/src/proc/signal.rs.
+#define SIGINT  2   // Kill; sent by control-c
+#define SIGKILL 9   // Kill
+#define SIGTERM 15  // Kill
+#define SIGCHLD 17  // Ignored; sent when a child exits
+#define SIGCONT 18  // Resume a stopped process
+#define SIGSTOP 19  // Stop
+#define SIGTSTP 20  // Stop; sent by control-z
+
+// Why a child stopped, as wait() reports.
+// The reasons of traced children are in kernel/ptrace.h.
+#define STOP_SUSPEND 5  // SIGSTOP or SIGTSTP
+
+#define WIFSTOPPED(status)  (((status) & 0xff) == 0x7f)
+#define WSTOPREASON(status) (((status) >> 8) & 0xff)
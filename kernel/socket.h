@@ -0,0 +1,22 @@
+// Generated from abi/src/socket.rs by abi/cheader.pl - do not edit.
+// Sockets of socket() and its relatives. The only family is AF_VSOCK, which connects the
+// machine to the host it runs on.
+
+#define AF_VSOCK 40  // Address family of virtio sockets
+
+#define SOCK_STREAM 1  // Connection-based byte stream
+
+#define VMADDR_CID_ANY 0xffffffff  // Binds to any CID of the machine
+#define VMADDR_CID_LOCAL 1  // CID of the machine itself, whose connections do not leave it
+#define VMADDR_CID_HOST 2  // CID of the host
+#define VMADDR_PORT_ANY 0xffffffff  // Binds to a free port
+
+struct sockaddr_vm {
+  ushort svm_family;  // AF_VSOCK
+  ushort svm_reserved1;  // Zero
+  uint svm_port;  // Port
+  uint svm_cid;  // Context ID, which names a machine
+  char svm_zero[4];  // Zero
+};
+
+_Static_assert(sizeof(struct sockaddr_vm) == 16, "struct sockaddr_vm");
@@ -1,11 +1,22 @@
-#define T_DIR     1   // Directory
-#define T_FILE    2   // File
-#define T_DEVICE  3   // Device
+// Generated from abi/src/stat.rs by abi/cheader.pl - do not edit.
+// File status, which fstat() returns.
+
+#define T_DIR 1  // Directory
+#define T_FILE 2  // File
+#define T_DEVICE 3  // Device
 
 struct stat {
-  int dev;     // File system's disk device
-  uint ino;    // Inode number
+  int dev;  // File system's disk device
+  uint ino;  // Inode number
   short type;  // Type of file
-  short nlink; // Number of links to file
-  uint64 size; // Size of file in bytes
+  short nlink;  // Number of links to file
+  uint mode;  // Permission bits, with I_SUID and I_SGID
+  uint64 size;  // Size of file in bytes
+  uint uid;  // Owner's user ID
+  uint gid;  // Group ID
 };
+
+_Static_assert(sizeof(struct stat) == 32, "struct stat");
+
+#define I_SUID 04000  // exec() sets the effective user ID to the owner
+#define I_SGID 02000  // exec() sets the effective group ID to the group
@@ -1,23 +1,88 @@
-// System call numbers
-#define SYS_fork    1
-#define SYS_exit    2
-#define SYS_wait    3
-#define SYS_pipe    4
-#define SYS_read    5
-#define SYS_kill    6
-#define SYS_exec    7
-#define SYS_fstat   8
-#define SYS_chdir   9
-#define SYS_dup    10
+// Generated from abi/src/syscall.rs by abi/cheader.pl - do not edit.
+// System call numbers, which user programs pass in a7.
+
+#define SYS_fork 1
+#define SYS_exit 2
+#define SYS_wait 3
+#define SYS_pipe 4
+#define SYS_read 5
+#define SYS_kill 6
+#define SYS_exec 7
+#define SYS_fstat 8
+#define SYS_chdir 9
+#define SYS_dup 10
 #define SYS_getpid 11
-#define SYS_sbrk   12
-#define SYS_sleep  13
+#define SYS_sbrk 12
+#define SYS_sleep 13
 #define SYS_uptime 14
-#define SYS_open   15
-#define SYS_write  16
-#define SYS_mknod  17
+#define SYS_open 15
+#define SYS_write 16
+#define SYS_mknod 17
 #define SYS_unlink 18
-#define SYS_link   19
-#define SYS_mkdir  20
-#define SYS_close  21
-#define SYS_poweroff    22
+#define SYS_link 19
+#define SYS_mkdir 20
+#define SYS_close 21
+#define SYS_poweroff 22
+#define SYS_sysinfo 23
+#define SYS_fcntl 24
+#define SYS_ioctl 25
+#define SYS_poll 26
+#define SYS_pipe2 27
+#define SYS_eventfd 28
+#define SYS_timerfd_create 29
+#define SYS_timerfd_settime 30
+#define SYS_timerfd_gettime 31
+#define SYS_getcwd 32
+#define SYS_chroot 33
+#define SYS_umask 34
+#define SYS_clock_gettime 35
+#define SYS_getrandom 36
+#define SYS_trace 37
+#define SYS_ptrace 38
+#define SYS_getuid 39
+#define SYS_geteuid 40
+#define SYS_getgid 41
+#define SYS_getegid 42
+#define SYS_setuid 43
+#define SYS_setgid 44
+#define SYS_chmod 45
+#define SYS_chown 46
+#define SYS_audit 47
+#define SYS_sigsend 48
+#define SYS_setpgid 49
+#define SYS_getpgid 50
+#define SYS_clock_settime 51
+#define SYS_adjtime 52
+#define SYS_dmesg 53
+#define SYS_logfilter 54
+#define SYS_traceon 55
+#define SYS_traceread 56
+#define SYS_leakcheck 57
+#define SYS_failinject 58
+#define SYS_ktest 59
+#define SYS_fuzzinfo 60
+#define SYS_profile 61
+#define SYS_suspend 62
+#define SYS_socket 63
+#define SYS_bind 64
+#define SYS_listen 65
+#define SYS_accept 66
+#define SYS_connect 67
+#define SYS_fadvise 68
+#define SYS_sendfile 69
+#define SYS_ioring_setup 70
+#define SYS_ioring_enter 71
+#define SYS_splice 72
+#define SYS_quotactl 73
+#define SYS_pidfd_open 74
+#define SYS_sigpending 75
+#define SYS_capget 76
+#define SYS_capdrop 77
+#define SYS_semget 78
+#define SYS_semop 79
+#define SYS_semctl 80
+#define SYS_mq_open 81
+#define SYS_mq_send 82
+#define SYS_mq_receive 83
+#define SYS_mq_unlink 84
+#define SYS_mq_getattr 85
@@ -0,0 +1,14 @@
+// Usage of a fixed-size kernel table.
+struct arenainfo {
+  uint capacity;   // Number of entries
+  uint in_use;     // Number of allocated entries
+  uint high_water; // Maximum number of entries allocated at once
+  uint failed;     // Number of allocations failed because the table was full
+};
+
+struct sysinfo {
+  struct arenainfo ftable; // Open file table
+  struct arenainfo itable; // In-memory inode table
+  struct arenainfo bcache; // Buffer cache
+  struct arenainfo pipes;  // Pipe table
+};
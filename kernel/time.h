@@ -0,0 +1,19 @@
+// Generated from abi/src/time.rs by abi/cheader.pl - do not edit.
+// Clocks and times of clock_gettime() and its relatives.
+
+#define CLOCK_REALTIME 0  // Wall-clock time since the Unix epoch
+#define CLOCK_MONOTONIC 1  // Time since boot, which never jumps
+
+struct timespec {
+  uint64 tv_sec;  // Seconds
+  uint64 tv_nsec;  // Nanoseconds
+};
+
+_Static_assert(sizeof(struct timespec) == 16, "struct timespec");
+
+struct timeval {
+  uint64 tv_sec;  // Seconds
+  uint64 tv_usec;  // Microseconds
+};
+
+_Static_assert(sizeof(struct timeval) == 16, "struct timeval");
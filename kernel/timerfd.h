@@ -0,0 +1,10 @@
+// timerfd_settime() flags.
+// Keep in sync with kernel-rs/src/timerfd.rs.
+#define TFD_TIMER_ABSTIME 1  // value is an absolute tick, as uptime() counts
+#define TFD_NONBLOCK      O_NONBLOCK
+#define TFD_CLOEXEC       O_CLOEXEC
+
+struct timerspec {
+  uint64 value;     // Ticks until the next expiration, or 0 if disarmed
+  uint64 interval;  // Ticks between expirations, or 0 if one-shot
+};
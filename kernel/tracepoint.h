@@ -0,0 +1,16 @@
+// Tracepoint events.
+// Keep in sync with kernel-rs/src/tracepoint.rs.
+#define TRACE_SCHED_SWITCH  1  // args: previous and next process IDs, 0 for the scheduler
+#define TRACE_SYSCALL_ENTER 2  // args: system call number, first argument
+#define TRACE_SYSCALL_EXIT  3  // args: system call number, return value
+#define TRACE_PAGE_FAULT    4  // args: faulting address, scause
+#define TRACE_DISK_ISSUE    5  // args: block number, 1 if a write
+#define TRACE_DISK_COMPLETE 6  // args: block number, 1 if a write
+
+struct tracerec {
+  uint64 seq;      // Sequence number in the CPU's buffer, counting from zero at boot
+  uint64 time;     // Nanoseconds since boot
+  uint cpu;
+  uint event;
+  uint64 args[2];
+};
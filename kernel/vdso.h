@@ -0,0 +1,25 @@
+// Generated from abi/src/vdso.rs by abi/cheader.pl - do not edit.
+// The page that the kernel shares with each process at VDSO_ADDR, from which the process reads
+// the clocks and its CPU without a system call.
+// 
+// The page holds a VdsoData, which the kernel fills each time it returns to the process, and
+// which the process can read but not write. The process reads the time CSR itself, which counts
+// at timebase_freq from boot, and converts it to nanoseconds to get the monotonic clock. The
+// realtime clock is realtime_offset plus the monotonic time, plus the part of slew applied by
+// then, which grows by 1ns every VDSO_SLEW_RATIO ns from slew_start, up to slew.
+
+#define VDSO_ADDR 0x3fffffc000  // User address of the shared page
+#define VDSO_SLEW_RATIO 2000  // A slew changes the realtime clock by 1ns every this many ns
+
+struct vdso_data {
+  uint64 ticks;  // Clock ticks since boot, as uptime() returns
+  uint64 timebase_freq;  // Frequency of the time CSR, in Hz
+  uint64 realtime_offset;  // Wall-clock time in ns when the monotonic clock was zero, without slew
+  long slew;  // Adjustment of realtime_offset being slewed, in ns
+  uint64 slew_start;  // Monotonic time when the slew started, in ns
+  uint64 realtime_seq;  // Changes each time the realtime clock is set or slewed
+  uint cpu;  // CPU that the process runs on
+  uint reserved;  // Zero
+};
+
+_Static_assert(sizeof(struct vdso_data) == 56, "struct vdso_data");
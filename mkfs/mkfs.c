@@ -4,12 +4,15 @@
 #include <string.h>
 #include <fcntl.h>
 #include <assert.h>
+#include <time.h>
 
 #define stat xv6_stat  // avoid clash with host struct stat
 #include "kernel/types.h"
 #include "kernel/fs.h"
 #include "kernel/stat.h"
 #include "kernel/param.h"
+#include "kernel/kdump.h"
+#include "kernel/lfs.h"
 
 #ifndef static_assert
 #define static_assert(a, b) do { switch (0) case 0: case (a): ; } while (0)
@@ -18,12 +21,14 @@
 #define NINODES 200
 
 // Disk layout:
-// [ boot block | sb block | log | inode blocks | free bit map | data blocks ]
+// [ boot block | sb block | log | inode blocks | inode bit map | free bit map |
+//                                            data blocks | dump partition ]
 
 int nbitmap = FSSIZE/(BSIZE*8) + 1;
 int ninodeblocks = NINODES / IPB + 1;
+int nibitmap = NINODES/(BSIZE*8) + 1;
 int nlog = LOGSIZE;
-int nmeta;    // Number of meta blocks (boot, sb, nlog, inode, bitmap)
+int nmeta;    // Number of meta blocks (boot, sb, nlog, inode, inode bitmap, bitmap)
 int nblocks;  // Number of data blocks
 
 int fsfd;
@@ -31,15 +36,32 @@ struct superblock sb;
 char zeroes[BSIZE];
 uint freeinode = 1;
 uint freeblock;
+int checksums;  // Whether to checksum metadata blocks, given -c
+int extents;    // Whether to map the blocks of files with extents, given -e
+int lfs;        // Whether to build LFS instead, given -l
+
+// LFS, given -l
+uint imap[NIMAP];
+struct segusage segtable[LFS_NSEG];
+struct sumentry lentries[LFS_SIZE];  // Summary entry of each block
+uint sumaddrs[LFS_SIZE];  // Address of each partial segment's summary
+uint sumlens[LFS_SIZE];   // Number of blocks in each partial segment
+uint nsum;                // Number of partial segments
 
 
 void balloc(int);
+void ibitmap(void);
 void wsect(uint, void*);
 void winode(uint, struct dinode*);
 void rinode(uint inum, struct dinode *ip);
 void rsect(uint sec, void *buf);
 uint ialloc(ushort type);
 void iappend(uint inum, void *p, int n);
+void dappend(uint inum, struct dirent *de);
+uint ebmap(struct dinode *din, uint fbn);
+void seal(char *buf);
+char *fsname(char *path);
+void mklfs(int argc, char *argv[]);
 
 // convert to intel byte order
 ushort
@@ -76,8 +98,19 @@ main(int argc, char *argv[])
 
   static_assert(sizeof(int) == 4, "Integers must be 4 bytes!");
 
-  if(argc < 2){
-    fprintf(stderr, "Usage: mkfs fs.img files...\n");
+  for(; argc > 1 && argv[1][0] == '-'; argc--, argv++){
+    if(strcmp(argv[1], "-c") == 0)
+      checksums = 1;
+    else if(strcmp(argv[1], "-e") == 0)
+      extents = 1;
+    else if(strcmp(argv[1], "-l") == 0)
+      lfs = 1;
+    else
+      argc = 0;
+  }
+  if(argc < 2 || (lfs && (checksums || extents))){
+    fprintf(stderr, "Usage: mkfs [-c] [-e] fs.img files...\n");
+    fprintf(stderr, "       mkfs -l fs.img files...\n");
     exit(1);
   }
 
@@ -90,8 +123,13 @@ main(int argc, char *argv[])
     exit(1);
   }
 
+  if(lfs){
+    mklfs(argc, argv);
+    exit(0);
+  }
+
   // 1 fs block = 1 disk sector
-  nmeta = 2 + nlog + ninodeblocks + nbitmap;
+  nmeta = 2 + nlog + ninodeblocks + nibitmap + nbitmap;
   nblocks = FSSIZE - nmeta;
 
   sb.magic = FSMAGIC;
@@ -101,61 +139,61 @@ main(int argc, char *argv[])
   sb.nlog = xint(nlog);
   sb.logstart = xint(2);
   sb.inodestart = xint(2+nlog);
-  sb.bmapstart = xint(2+nlog+ninodeblocks);
+  sb.ibmapstart = xint(2+nlog+ninodeblocks);
+  sb.bmapstart = xint(2+nlog+ninodeblocks+nibitmap);
+  sb.flags = xint((checksums ? FS_CHECKSUMS : 0) | (extents ? FS_EXTENTS : 0));
+  if(checksums){
+    sb.checksum = xint(crc32c(&sb, sizeof(sb) - sizeof(sb.checksum)));
+  }
 
-  printf("nmeta %d (boot, super, log blocks %u inode blocks %u, bitmap blocks %u) blocks %d total %d\n",
-         nmeta, nlog, ninodeblocks, nbitmap, nblocks, FSSIZE);
+  printf("nmeta %d (boot, super, log blocks %u inode blocks %u, inode bitmap blocks %u, bitmap blocks %u) blocks %d total %d\n",
+         nmeta, nlog, ninodeblocks, nibitmap, nbitmap, nblocks, FSSIZE);
 
   freeblock = nmeta;     // the first free block that we can allocate
 
-  for(i = 0; i < FSSIZE; i++)
+  for(i = 0; i < FSSIZE + DUMPSIZE; i++)
     wsect(i, zeroes);
 
   memset(buf, 0, sizeof(buf));
   memmove(buf, &sb, sizeof(sb));
   wsect(1, buf);
 
+  // Inode blocks need checksums even before they hold any inode.
+  memset(buf, 0, sizeof(buf));
+  seal(buf);
+  for(i = 0; i < ninodeblocks; i++)
+    wsect(xint(sb.inodestart) + i, buf);
+
+  // Mark the dump partition, so that the kernel writes crash dumps there.
+  memset(buf, 0, sizeof(buf));
+  ((struct kdumphdr*)buf)->magic = xint(KDUMP_EMPTY);
+  wsect(FSSIZE, buf);
+
   rootino = ialloc(T_DIR);
   assert(rootino == ROOTINO);
 
   bzero(&de, sizeof(de));
   de.inum = xshort(rootino);
   strcpy(de.name, ".");
-  iappend(rootino, &de, sizeof(de));
+  dappend(rootino, &de);
 
   bzero(&de, sizeof(de));
   de.inum = xshort(rootino);
   strcpy(de.name, "..");
-  iappend(rootino, &de, sizeof(de));
+  dappend(rootino, &de);
 
   for(i = 2; i < argc; i++){
-    // get rid of "user/"
-    char *shortname;
-    if(strncmp(argv[i], "user/", 5) == 0)
-      shortname = argv[i] + 5;
-    else
-      shortname = argv[i];
-    
-    assert(index(shortname, '/') == 0);
-
     if((fd = open(argv[i], 0)) < 0){
       perror(argv[i]);
       exit(1);
     }
 
-    // Skip leading _ in name when writing to file system.
-    // The binaries are named _rm, _cat, etc. to keep the
-    // build operating system from trying to execute them
-    // in place of system binaries like rm and cat.
-    if(shortname[0] == '_')
-      shortname += 1;
-
     inum = ialloc(T_FILE);
 
     bzero(&de, sizeof(de));
     de.inum = xshort(inum);
-    strncpy(de.name, shortname, DIRSIZ);
-    iappend(rootino, &de, sizeof(de));
+    strncpy(de.name, fsname(argv[i]), DIRSIZ);
+    dappend(rootino, &de);
 
     while((cc = read(fd, buf, sizeof(buf))) > 0)
       iappend(inum, buf, cc);
@@ -171,6 +209,7 @@ main(int argc, char *argv[])
   winode(rootino, &din);
 
   balloc(freeblock);
+  ibitmap();
 
   exit(0);
 }
@@ -199,6 +238,7 @@ winode(uint inum, struct dinode *ip)
   rsect(bn, buf);
   dip = ((struct dinode*)buf) + (inum % IPB);
   *dip = *ip;
+  seal(buf);
   wsect(bn, buf);
 }
 
@@ -234,10 +274,16 @@ ialloc(ushort type)
   uint inum = freeinode++;
   struct dinode din;
 
+  // The last inode of each block holds the checksum.
+  if(checksums && inum % IPB == IPB - 1)
+    inum = freeinode++;
+
   bzero(&din, sizeof(din));
   din.type = xshort(type);
   din.nlink = xshort(1);
   din.size = xint(0);
+  // The superuser owns every file, and the files are programs.
+  din.mode = xshort(0755);
   winode(inum, &din);
   return inum;
 }
@@ -258,6 +304,25 @@ balloc(int used)
   wsect(sb.bmapstart, buf);
 }
 
+// Write the inode bitmap, marking inode 0, the inodes that ialloc
+// allocated, and the inodes that hold checksums, given -c, so that the
+// kernel never allocates them.
+void
+ibitmap(void)
+{
+  uchar buf[BSIZE];
+  uint i;
+
+  assert(NINODES <= BSIZE*8);
+  bzero(buf, BSIZE);
+  for(i = 0; i < NINODES; i++){
+    if(i < freeinode || (checksums && i % IPB == IPB - 1))
+      buf[i/8] = buf[i/8] | (0x1 << (i%8));
+  }
+  printf("ibitmap: write inode bitmap block at sector %d\n", sb.ibmapstart);
+  wsect(sb.ibmapstart, buf);
+}
+
 #define min(a, b) ((a) < (b) ? (a) : (b))
 
 void
@@ -276,7 +341,9 @@ iappend(uint inum, void *xp, int n)
   while(n > 0){
     fbn = off / BSIZE;
     assert(fbn < MAXFILE);
-    if(fbn < NDIRECT){
+    if(extents){
+      x = ebmap(&din, fbn);
+    } else if(fbn < NDIRECT){
       if(xint(din.addrs[fbn]) == 0){
         din.addrs[fbn] = xint(freeblock++);
       }
@@ -295,6 +362,8 @@ iappend(uint inum, void *xp, int n)
     n1 = min(n, (fbn + 1) * BSIZE - off);
     rsect(x, buf);
     bcopy(p, buf + off - (fbn * BSIZE), n1);
+    if(xshort(din.type) == T_DIR)
+      seal(buf);
     wsect(x, buf);
     n -= n1;
     off += n1;
@@ -303,3 +372,342 @@ iappend(uint inum, void *xp, int n)
   din.size = xint(off);
   winode(inum, &din);
 }
+
+// Append a directory entry, skipping the last entry of a block, which
+// holds the checksum.
+void
+dappend(uint inum, struct dirent *de)
+{
+  struct dinode din;
+  struct dirent empty;
+
+  rinode(inum, &din);
+  if(checksums && xint(din.size) % BSIZE == BSIZE - sizeof(*de)){
+    bzero(&empty, sizeof(empty));
+    iappend(inum, &empty, sizeof(empty));
+  }
+  iappend(inum, de, sizeof(*de));
+}
+
+// Return extent i of din, which is in din itself, or in buf if *bn is not 0,
+// which then holds extent block *bn. Allocates the extent block if needed.
+struct extent*
+extent(struct dinode *din, uint i, struct extent *buf, uint *bn)
+{
+  uint *addr;
+
+  if(i < NEXTENT_INLINE){
+    *bn = 0;
+    return (struct extent*)din->addrs + i;
+  }
+  i -= NEXTENT_INLINE;
+  addr = &din->addrs[NEXTENT_INLINE * 2 + i / EXTENTS_PER_BLOCK];
+  if(xint(*addr) == 0)
+    *addr = xint(freeblock++);
+  *bn = xint(*addr);
+  rsect(*bn, buf);
+  return buf + i % EXTENTS_PER_BLOCK;
+}
+
+// Return the block holding block fbn of the file, given -e. A block past
+// the end of the file extends its last extent if it can, since files are
+// written only at their end.
+uint
+ebmap(struct dinode *din, uint fbn)
+{
+  struct extent buf[EXTENTS_PER_BLOCK], *e;
+  uint i, bn, first;
+
+  first = 0;
+  for(i = 0; i < NEXTENT; i++){
+    e = extent(din, i, buf, &bn);
+    if(xint(e->len) == 0)
+      break;
+    if(fbn < first + xint(e->len))
+      return xint(e->start) + fbn - first;
+    first += xint(e->len);
+  }
+  assert(i < NEXTENT && fbn == first);
+  if(i > 0){
+    e = extent(din, i - 1, buf, &bn);
+    if(xint(e->start) + xint(e->len) == freeblock){
+      e->len = xint(xint(e->len) + 1);
+      if(bn)
+        wsect(bn, buf);
+      return freeblock++;
+    }
+    e = extent(din, i, buf, &bn);
+  }
+  e->start = xint(freeblock);
+  e->len = xint(1);
+  if(bn)
+    wsect(bn, buf);
+  return freeblock++;
+}
+
+// Store the checksum of an inode block or a directory block in its last
+// 4 bytes, given -c.
+void
+seal(char *buf)
+{
+  uint checksum;
+
+  if(checksums){
+    checksum = xint(crc32c(buf, BSIZE - sizeof(checksum)));
+    memmove(buf + BSIZE - sizeof(checksum), &checksum, sizeof(checksum));
+  }
+}
+
+// Return the name of the file at path in the root directory.
+char*
+fsname(char *path)
+{
+  // get rid of "user/"
+  char *shortname;
+  if(strncmp(path, "user/", 5) == 0)
+    shortname = path + 5;
+  else
+    shortname = path;
+
+  assert(index(shortname, '/') == 0);
+
+  // Skip leading _ in name when writing to file system.
+  // The binaries are named _rm, _cat, etc. to keep the
+  // build operating system from trying to execute them
+  // in place of system binaries like rm and cat.
+  if(shortname[0] == '_')
+    shortname += 1;
+  return shortname;
+}
+
+// Return a new block for block off of inode inum, given -l, or for
+// the inode itself if off is SUMMARY_INODE. A partial segment ends once
+// its summary is full or its segment is, and the next one starts in
+// the next segment if no block would fit after its summary.
+uint
+lalloc(uint inum, uint off)
+{
+  if(nsum == 0 || sumlens[nsum-1] == NPENDING || freeblock % LFS_SEGSIZE == 0){
+    if(freeblock % LFS_SEGSIZE == LFS_SEGSIZE - 1)
+      freeblock++;
+    sumaddrs[nsum] = freeblock++;
+    sumlens[nsum++] = 0;
+  }
+  if(freeblock >= LFS_SIZE){
+    fprintf(stderr, "mkfs: the files do not fit into %d blocks\n", LFS_SIZE);
+    exit(1);
+  }
+  lentries[freeblock].inum = xint(inum);
+  lentries[freeblock].off = xint(off);
+  sumlens[nsum-1]++;
+  segtable[freeblock / LFS_SEGSIZE].live =
+    xint(xint(segtable[freeblock / LFS_SEGSIZE].live) + BSIZE);
+  return freeblock++;
+}
+
+void
+lwinode(uint inum, struct lfs_dinode *ip)
+{
+  char buf[BSIZE];
+
+  bzero(buf, BSIZE);
+  memmove(buf, ip, sizeof(*ip));
+  wsect(xint(imap[inum]), buf);
+}
+
+void
+lrinode(uint inum, struct lfs_dinode *ip)
+{
+  char buf[BSIZE];
+
+  rsect(xint(imap[inum]), buf);
+  memmove(ip, buf, sizeof(*ip));
+}
+
+// Allocate an inode, given -l, whose block the inode map points to.
+uint
+lialloc(ushort type)
+{
+  uint inum = freeinode++;
+  struct lfs_dinode din;
+
+  assert(inum < NIMAP);
+  imap[inum] = xint(lalloc(inum, SUMMARY_INODE));
+  bzero(&din, sizeof(din));
+  din.type = xshort(type);
+  din.nlink = xshort(1);
+  din.size = xint(0);
+  // The superuser owns every file, and the files are programs.
+  din.mode = xshort(0755);
+  lwinode(inum, &din);
+  return inum;
+}
+
+void
+liappend(uint inum, void *xp, int n)
+{
+  char *p = (char*)xp;
+  uint fbn, off, n1;
+  struct lfs_dinode din;
+  char buf[BSIZE];
+  uint indirect[NINDIRECT];
+  uint x;
+
+  lrinode(inum, &din);
+  off = xint(din.size);
+  while(n > 0){
+    fbn = off / BSIZE;
+    assert(fbn < LFS_NDIRECT + NINDIRECT);
+    if(fbn < LFS_NDIRECT){
+      if(xint(din.addrs[fbn]) == 0){
+        din.addrs[fbn] = xint(lalloc(inum, fbn));
+      }
+      x = xint(din.addrs[fbn]);
+    } else {
+      if(xint(din.addrs[LFS_NDIRECT]) == 0){
+        din.addrs[LFS_NDIRECT] = xint(lalloc(inum, SUMMARY_INDIRECT));
+      }
+      rsect(xint(din.addrs[LFS_NDIRECT]), (char*)indirect);
+      if(indirect[fbn - LFS_NDIRECT] == 0){
+        indirect[fbn - LFS_NDIRECT] = xint(lalloc(inum, fbn));
+        wsect(xint(din.addrs[LFS_NDIRECT]), (char*)indirect);
+      }
+      x = xint(indirect[fbn - LFS_NDIRECT]);
+    }
+    n1 = min(n, (fbn + 1) * BSIZE - off);
+    rsect(x, buf);
+    bcopy(p, buf + off - (fbn * BSIZE), n1);
+    wsect(x, buf);
+    n -= n1;
+    off += n1;
+    p += n1;
+  }
+  din.size = xint(off);
+  lwinode(inum, &din);
+}
+
+// Write the summaries of the partial segments, now that their blocks
+// are final, and checkpoint 0, which leads the kernel past them to
+// segment seg.
+void
+lcheckpoint(uint seg)
+{
+  static char region[CHECKPOINT_BLOCKS][BSIZE];
+  struct segsummary sum;
+  struct checkpoint cp;
+  uint crcs[CHECKPOINT_BLOCKS];
+  char buf[BSIZE];
+  uint i, j, b;
+
+  for(i = 0; i < nsum; i++){
+    bzero(&sum, sizeof(sum));
+    sum.magic = xint(SUMMARY_MAGIC);
+    sum.serial = xint(i);
+    sum.nblocks = xint(sumlens[i]);
+    sum.next_seg = xint(i + 1 < nsum ? sumaddrs[i+1] / LFS_SEGSIZE : seg);
+    for(j = 0; j < sumlens[i]; j++){
+      b = sumaddrs[i] + 1 + j;
+      rsect(b, buf);
+      sum.entries[j] = lentries[b];
+      sum.entries[j].crc = xint(crc32c(buf, BSIZE));
+    }
+    bzero(buf, BSIZE);
+    memmove(buf, &sum, sizeof(sum));
+    wsect(sumaddrs[i], buf);
+  }
+
+  assert(sizeof(segtable) <= SEGTABLE_BLOCKS * BSIZE);
+  assert(sizeof(imap) == IMAP_BLOCKS * BSIZE);
+  memmove(region[1], segtable, sizeof(segtable));
+  memmove(region[1 + SEGTABLE_BLOCKS], imap, sizeof(imap));
+  for(i = 1; i < CHECKPOINT_BLOCKS; i++)
+    crcs[i] = xint(crc32c(region[i], BSIZE));
+
+  bzero(&cp, sizeof(cp));
+  cp.magic = xint(CHECKPOINT_MAGIC);
+  cp.serial = xint(0);
+  cp.next = xint(nsum);
+  cp.seg = xint(seg);
+  cp.start = xint(0);
+  cp.time = xint(time(0));
+  crcs[0] = xint(crc32c(&cp, sizeof(cp)));
+  cp.crc = xint(crc32c(crcs, sizeof(crcs)));
+  memmove(region[0], &cp, sizeof(cp));
+
+  // Region 1 stays zero, so the kernel takes region 0.
+  for(i = 0; i < CHECKPOINT_BLOCKS; i++)
+    wsect(CHECKPOINT_START + i, region[i]);
+}
+
+// Build LFS, given -l. Block 1 stays zero, so the kernel does not take
+// the image for UFS.
+void
+mklfs(int argc, char *argv[])
+{
+  int i, cc, fd;
+  uint rootino, inum, seg;
+  struct dirent de;
+  char buf[BSIZE];
+
+  static_assert(sizeof(struct checkpoint) == 32, "struct checkpoint");
+  static_assert(sizeof(struct segsummary) <= BSIZE, "struct segsummary");
+  static_assert(sizeof(struct lfs_dinode) == 72, "struct lfs_dinode");
+  assert(CHECKPOINT_START + 2 * CHECKPOINT_BLOCKS <= LFS_SEGSIZE);
+
+  for(i = 0; i < LFS_SIZE + DUMPSIZE; i++)
+    wsect(i, zeroes);
+
+  // Mark the dump partition, so that the kernel writes crash dumps there.
+  memset(buf, 0, sizeof(buf));
+  ((struct kdumphdr*)buf)->magic = xint(KDUMP_EMPTY);
+  wsect(LFS_SIZE, buf);
+
+  // Segment 0 holds only the checkpoint regions.
+  freeblock = LFS_SEGSIZE;
+
+  rootino = lialloc(T_DIR);
+  assert(rootino == ROOTINO);
+
+  bzero(&de, sizeof(de));
+  de.inum = xshort(rootino);
+  strcpy(de.name, ".");
+  liappend(rootino, &de, sizeof(de));
+
+  bzero(&de, sizeof(de));
+  de.inum = xshort(rootino);
+  strcpy(de.name, "..");
+  liappend(rootino, &de, sizeof(de));
+
+  for(i = 2; i < argc; i++){
+    if((fd = open(argv[i], 0)) < 0){
+      perror(argv[i]);
+      exit(1);
+    }
+
+    inum = lialloc(T_FILE);
+
+    bzero(&de, sizeof(de));
+    de.inum = xshort(inum);
+    strncpy(de.name, fsname(argv[i]), DIRSIZ);
+    liappend(rootino, &de, sizeof(de));
+
+    while((cc = read(fd, buf, sizeof(buf))) > 0)
+      liappend(inum, buf, cc);
+
+    close(fd);
+  }
+
+  for(seg = 0; seg < LFS_NSEG; seg++)
+    segtable[seg].mtime = xint(time(0));
+
+  // The kernel writes on in the next segment, which must be free.
+  seg = (freeblock + LFS_SEGSIZE - 1) / LFS_SEGSIZE;
+  if(seg >= LFS_NSEG){
+    fprintf(stderr, "mkfs: the files leave no free segment\n");
+    exit(1);
+  }
+  lcheckpoint(seg);
+
+  printf("lfs: %u blocks in %u partial segments, %u of %d segments used, total %d\n",
+         freeblock - LFS_SEGSIZE, nsum, seg - 1, LFS_NSEG - 1, LFS_SIZE);
+}
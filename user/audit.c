@@ -0,0 +1,51 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/audit.h"
+#include "user/user.h"
+
+// audit
+// Prints the audit log, oldest record first, with times in milliseconds since boot.
+
+static char *events[] = {
+[AUDIT_EXEC]       "exec",
+[AUDIT_SETUID]     "setuid",
+[AUDIT_SETGID]     "setgid",
+[AUDIT_DENIED]     "denied",
+[AUDIT_PRIVILEGED] "privileged",
+[AUDIT_KILL]       "kill",
+[AUDIT_SETTIME]    "settime",
+[AUDIT_CAPDROP]    "capdrop",
+};
+
+int
+main(int argc, char *argv[])
+{
+  struct auditrec recs[8];
+  uint64 seq = 0;
+  char *event, name[sizeof(recs[0].name) + 1];
+  int i, n;
+
+  for(;;){
+    n = audit(recs, 8, seq);
+    if(n < 0){
+      fprintf(2, "audit: cannot read the audit log\n");
+      exit(1);
+    }
+    if(n == 0)
+      break;
+    for(i = 0; i < n; i++){
+      if(recs[i].seq != seq)
+        printf("(%l records lost)\n", recs[i].seq - seq);
+      event = "?";
+      if(recs[i].event < sizeof(events) / sizeof(events[0]) && events[recs[i].event])
+        event = events[recs[i].event];
+      memmove(name, recs[i].name, sizeof(recs[i].name));
+      name[sizeof(recs[i].name)] = 0;
+      printf("%l %l pid=%d %s uid=%d euid=%d egid=%d %s %l error=%d\n",
+             recs[i].seq, recs[i].time / 1000000, recs[i].pid, name,
+             recs[i].uid, recs[i].euid, recs[i].egid, event, recs[i].arg, recs[i].error);
+      seq = recs[i].seq + 1;
+    }
+  }
+  exit(0);
+}
@@ -0,0 +1,143 @@
+// A simple debugger.
+// dbg program [args...]
+// Runs program stopped at its first instruction, and reads commands:
+//   s          execute one instruction
+//   c          continue until a breakpoint or exit
+//   r          print the registers
+//   x addr     print the word at addr
+//   b addr     stop when the instruction at addr is executed, once
+//   q          kill the program and quit
+// Addresses are in hexadecimal.
+
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/ptrace.h"
+#include "kernel/signal.h"
+#include "user/user.h"
+
+#define EBREAK 0x00100073
+
+int pid;
+uint64 bpaddr, bpword;  // the breakpoint, and the word it replaced
+int bpset;
+
+uint64
+parsehex(char *s)
+{
+  uint64 x = 0;
+
+  while(*s == ' ')
+    s++;
+  if(s[0] == '0' && s[1] == 'x')
+    s += 2;
+  for(;; s++){
+    if(*s >= '0' && *s <= '9')
+      x = x * 16 + *s - '0';
+    else if(*s >= 'a' && *s <= 'f')
+      x = x * 16 + *s - 'a' + 10;
+    else
+      return x;
+  }
+}
+
+// Waits until the child stops, and returns its pc, or exits if it exited.
+uint64
+waitstop(void)
+{
+  int status;
+  struct user_regs regs;
+
+  if(wait(&status) != pid){
+    fprintf(2, "dbg: wait failed\n");
+    exit(1);
+  }
+  if(!WIFSTOPPED(status)){
+    printf("program exited with status %d\n", status);
+    exit(0);
+  }
+  ptrace(PTRACE_GETREGS, pid, 0, (uint64)&regs);
+  if(WSTOPREASON(status) == PTRACE_STOP_BREAKPOINT && bpset && regs.pc == bpaddr){
+    ptrace(PTRACE_POKEDATA, pid, (void*)bpaddr, bpword);
+    bpset = 0;
+    printf("breakpoint\n");
+  }
+  return regs.pc;
+}
+
+int
+main(int argc, char *argv[])
+{
+  char buf[64];
+  struct user_regs regs;
+  uint64 addr, word;
+  int i;
+
+  if(argc < 2){
+    fprintf(2, "usage: dbg program [args...]\n");
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    fprintf(2, "dbg: fork failed\n");
+    exit(1);
+  }
+  if(pid == 0){
+    ptrace(PTRACE_TRACEME, 0, 0, 0);
+    exec(argv[1], argv + 1);
+    fprintf(2, "dbg: exec %s failed\n", argv[1]);
+    exit(1);
+  }
+  printf("pc %p\n", waitstop());
+
+  for(;;){
+    printf("(dbg) ");
+    gets(buf, sizeof(buf));
+    if(buf[0] == 0){
+      // end of input
+      kill(pid);
+      exit(0);
+    }
+    switch(buf[0]){
+    case 's':
+      ptrace(PTRACE_SINGLESTEP, pid, 0, 0);
+      printf("pc %p\n", waitstop());
+      break;
+    case 'c':
+      ptrace(PTRACE_CONT, pid, 0, 0);
+      printf("pc %p\n", waitstop());
+      break;
+    case 'r':
+      ptrace(PTRACE_GETREGS, pid, 0, (uint64)&regs);
+      printf("pc %p\n", regs.pc);
+      for(i = 0; i < 31; i++)
+        printf("x%d %p\n", i + 1, regs.x[i]);
+      break;
+    case 'x':
+      addr = parsehex(buf + 1);
+      if(ptrace(PTRACE_PEEKDATA, pid, (void*)addr, (uint64)&word) < 0)
+        printf("cannot read %p\n", addr);
+      else
+        printf("%p: %p\n", addr, word);
+      break;
+    case 'b':
+      if(bpset){
+        printf("a breakpoint is already set at %p\n", bpaddr);
+        break;
+      }
+      bpaddr = parsehex(buf + 1);
+      if(ptrace(PTRACE_PEEKDATA, pid, (void*)bpaddr, (uint64)&bpword) < 0 ||
+         ptrace(PTRACE_POKEDATA, pid, (void*)bpaddr, (bpword & ~0xffffffffL) | EBREAK) < 0){
+        printf("cannot set a breakpoint at %p\n", bpaddr);
+        break;
+      }
+      bpset = 1;
+      break;
+    case 'q':
+      kill(pid);
+      wait(0);
+      exit(0);
+    default:
+      printf("commands: s, c, r, x addr, b addr, q\n");
+    }
+  }
+}
@@ -0,0 +1,22 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+// dmesg
+// Prints the kernel log.
+
+int
+main(int argc, char *argv[])
+{
+  char buf[512];
+  uint64 off = 0;
+  int n;
+
+  while((n = dmesg(buf, sizeof(buf), &off)) > 0)
+    write(1, buf, n);
+  if(n < 0){
+    fprintf(2, "dmesg: cannot read the kernel log\n");
+    exit(1);
+  }
+  exit(0);
+}
@@ -0,0 +1,120 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/fuzz.h"
+#include "kernel/syscall.h"
+#include "kernel/riscv.h"
+#include "user/user.h"
+
+// fuzz [seconds [seed]]
+// Makes system calls with random numbers and arguments for the given number of
+// seconds (10 by default), in the directory fuzzdir, and prints how many of them
+// returned. The kernel must be built with FUZZ=yes, which also keeps the calls
+// from powering off the machine. Calls that would make processes the fuzzer
+// cannot stop, or stop other processes, are left out. Calls of the superuser
+// may change any file.
+
+#define CHILD_TICKS 5   // How long each fuzzing process runs
+
+static char buf[2*PGSIZE];
+
+// Paths that the calls are given, relative to fuzzdir.
+static char *names[] = { "", ".", "f", "g", "d", "d/f", "d/..", "console" };
+
+static unsigned long next = 1;
+
+static uint64
+rand(void)
+{
+  next = next * 6364136223846793005ULL + 1442695040888963407ULL;
+  return next >> 16;
+}
+
+static uint64
+randarg(void)
+{
+  switch(rand() % 8){
+  case 0:
+    return 0;
+  case 1:
+    return rand() % 8;
+  case 2:
+    return -1;
+  case 3:
+    return (uint64)buf + rand() % sizeof(buf);
+  case 4:
+    return (uint64)names[rand() % (sizeof(names) / sizeof(names[0]))];
+  case 5:
+    return MAXVA - rand() % (4*PGSIZE);
+  case 6:
+    return rand() << 32 | rand();
+  default:
+    return rand() & 0xffffffff;
+  }
+}
+
+// Makes random system calls until killed.
+void
+go(void)
+{
+  int num, i;
+
+  for(i = 0; i < sizeof(buf); i++)
+    buf[i] = rand();
+  for(;;){
+    // Include some numbers past the last system call.
+    num = rand() % (SYS_fuzzinfo + 4);
+    if(num == SYS_fork || num == SYS_exec || num == SYS_kill || num == SYS_sigsend)
+      continue;
+    syscall(num, randarg(), randarg(), randarg(), randarg(), randarg(), randarg());
+  }
+}
+
+int
+main(int argc, char *argv[])
+{
+  struct fuzzinfo before, after;
+  int seconds = 10, pid, start;
+
+  if(argc > 3){
+    fprintf(2, "usage: fuzz [seconds [seed]]\n");
+    exit(1);
+  }
+  if(argc >= 2)
+    seconds = atoi(argv[1]);
+  if(argc == 3)
+    next = atoi(argv[2]);
+
+  if(fuzzinfo(&before) < 0){
+    fprintf(2, "fuzz: the kernel was not built with FUZZ=yes\n");
+    exit(1);
+  }
+  mkdir("fuzzdir");
+  if(chdir("fuzzdir") < 0){
+    fprintf(2, "fuzz: cannot chdir fuzzdir\n");
+    exit(1);
+  }
+
+  start = uptime();
+  while(uptime() - start < seconds * 10){
+    // Each process gets its own seed.
+    rand();
+    pid = fork();
+    if(pid < 0){
+      fprintf(2, "fuzz: fork failed\n");
+      exit(1);
+    }
+    if(pid == 0){
+      go();
+      exit(0);
+    }
+    sleep(CHILD_TICKS);
+    kill(pid);
+    wait(0);
+  }
+
+  fuzzinfo(&after);
+  printf("%l calls, %l failed, %l with EFAULT, %l refused\n",
+         after.calls - before.calls, after.errors - before.errors,
+         after.faults - before.faults, after.refused - before.refused);
+  exit(0);
+}
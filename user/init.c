@@ -8,6 +8,7 @@
 #include "kernel/file.h"
 #include "user/user.h"
 #include "kernel/fcntl.h"
+#include "kernel/ioctl.h"
 
 #ifdef USERTEST
 char *argv[] = { "usertests", 0 };
@@ -15,40 +16,92 @@ char *argv[] = { "usertests", 0 };
 char *argv[] = { "sh", 0 };
 #endif
 
+// Start argv[0], with its standard input and output on fd.
+int
+spawn(int fd)
+{
+  int pid;
+
+  printf("init: starting %s\n", argv[0]);
+  pid = fork();
+  if(pid < 0){
+    printf("init: fork failed\n");
+    exit(1);
+  }
+  if(pid == 0){
+    if(fd > 2){
+      close(0);
+      close(1);
+      close(2);
+      dup(fd);
+      dup(fd);
+      dup(fd);
+      close(fd);
+    }
+    exec(argv[0], argv);
+    printf("init: exec %s failed\n", argv[0]);
+    exit(1);
+  }
+  return pid;
+}
+
 int
 main(void)
 {
   // https://github.com/kaist-cp/rv6/commit/d12c1db8d9d7a7e5632e51ae712123d868087fe4
   // Add xstate to immediately run usertests and poweroff.
   int pid, wpid, xstate;
+  int ttyfd = -1, ttypid = -1;
+  struct stat st;
+  struct termios t;
 
   if(open("console", O_RDWR) < 0){
     mknod("console", CONSOLE, 0);
     open("console", O_RDWR);
   }
+  if(stat("urandom", &st) < 0){
+    mknod("urandom", URANDOM, 0);
+  }
   dup(0);  // stdout
   dup(0);  // stderr
 
+  if(stat("dev", &st) < 0)
+    mkdir("dev");
+  if(stat("dev/ttyS1", &st) < 0){
+    mknod("dev/ttyS1", TTYS1, 0);
+  }
+  if(stat("dev/null", &st) < 0)
+    mknod("dev/null", DEVNULL, 0);
+  if(stat("dev/zero", &st) < 0)
+    mknod("dev/zero", DEVZERO, 0);
+  if(stat("dev/full", &st) < 0)
+    mknod("dev/full", DEVFULL, 0);
+
+#ifndef USERTEST
+  // Start another shell on the second serial line, if the machine has one,
+  // so that the shell can be used apart from the kernel's messages.
+  ttyfd = open("dev/ttyS1", O_RDWR | O_CLOEXEC);
+  if(ttyfd >= 0 && ioctl(ttyfd, TCGETS, &t) < 0){
+    close(ttyfd);
+    ttyfd = -1;
+  }
+#endif
+
   for(;;){
-    printf("init: starting %s\n", argv[0]);
-    pid = fork();
-    if(pid < 0){
-      printf("init: fork failed\n");
-      exit(1);
-    }
-    if(pid == 0){
-      exec(argv[0], argv);
-      printf("init: exec %s failed\n", argv[0]);
-      exit(1);
-    }
+    pid = spawn(0);
+    if(ttyfd >= 0 && ttypid < 0)
+      ttypid = spawn(ttyfd);
 
     for(;;){
-      // this call to wait() returns if the shell exits,
+      // this call to wait() returns if a shell exits,
       // or if a parentless process exits.
       wpid = wait(&xstate);
       if(wpid == pid){
         // the shell exited; restart it.
         break;
+      } else if(ttypid > 0 && wpid == ttypid){
+        // the shell on the second serial line exited; restart it.
+        ttypid = spawn(ttyfd);
       } else if(wpid < 0){
         printf("init: wait returned an error\n");
         exit(1);
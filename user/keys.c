@@ -0,0 +1,28 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/ioctl.h"
+#include "user/user.h"
+
+// keys
+// Puts the console in raw mode and prints the code of each key as it
+// is pressed, until q is pressed.
+int
+main(int argc, char *argv[])
+{
+  struct termios old, raw;
+  char c;
+
+  if(ioctl(0, TCGETS, &old) < 0){
+    fprintf(2, "keys: standard input is not a terminal\n");
+    exit(1);
+  }
+  raw = old;
+  raw.c_iflag &= ~ICRNL;
+  raw.c_lflag &= ~(ICANON|ECHO);
+  ioctl(0, TCSETS, &raw);
+  printf("press keys, q to quit\n");
+  while(read(0, &c, 1) == 1 && c != 'q')
+    printf("%d\n", (uchar)c);
+  ioctl(0, TCSETS, &old);
+  exit(0);
+}
@@ -0,0 +1,38 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/leak.h"
+#include "user/user.h"
+
+// leaks [seconds]
+// Prints the code in the kernel that asked for pages more than the given number of
+// seconds ago (10 by default), which are still allocated. The kernel must be built
+// with LEAKCHECK=yes.
+
+int
+main(int argc, char *argv[])
+{
+  struct leakrec recs[32];
+  char file[sizeof(recs[0].file) + 1];
+  uint64 age = 10;
+  int i, n;
+
+  if(argc > 2){
+    fprintf(2, "usage: leaks [seconds]\n");
+    exit(1);
+  }
+  if(argc == 2)
+    age = atoi(argv[1]);
+
+  n = leakcheck(recs, 32, age * 1000000000ULL);
+  if(n < 0){
+    fprintf(2, "leaks: cannot check for leaks\n");
+    exit(1);
+  }
+  for(i = 0; i < n; i++){
+    memmove(file, recs[i].file, sizeof(recs[i].file));
+    file[sizeof(recs[i].file)] = 0;
+    printf("%s:%d %d pages, oldest %l ms ago\n", file, recs[i].line, recs[i].pages,
+           recs[i].age / 1000000);
+  }
+  exit(0);
+}
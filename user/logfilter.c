@@ -0,0 +1,20 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+// logfilter spec
+// Sets which messages the kernel log keeps, e.g. "warn,fs::lfs=debug".
+
+int
+main(int argc, char *argv[])
+{
+  if(argc != 2){
+    fprintf(2, "usage: logfilter level[,target=level]...\n");
+    exit(1);
+  }
+  if(logfilter(argv[1]) < 0){
+    fprintf(2, "logfilter: cannot set %s\n", argv[1]);
+    exit(1);
+  }
+  exit(0);
+}
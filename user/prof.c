@@ -0,0 +1,111 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/param.h"
+#include "kernel/profile.h"
+#include "user/user.h"
+
+// prof command [args]
+// Runs the command with the kernel's profiler on, and prints where the CPUs spent
+// their time meanwhile: the kernel functions and the user program counters that
+// were sampled the most, with their numbers of samples. Only the last samples of
+// each CPU are kept, about ten seconds' worth.
+
+#define NHOT 64   // Number of distinct places counted
+#define NSHOWN 20 // Number of places printed
+
+struct hot {
+  int user;
+  int pid;         // For user places
+  uint64 pc;       // For user places
+  char func[sizeof(((struct profsample*)0)->func) + 1];  // For kernel places
+  int samples;
+};
+
+struct hot hots[NHOT];
+int nhot, total, other;
+
+void
+count(struct profsample *s)
+{
+  struct hot *h;
+
+  total++;
+  for(h = hots; h < hots + nhot; h++){
+    if(h->user != s->user)
+      continue;
+    if(s->user ? h->pid == s->pid && h->pc == s->pc
+               : strncmp(h->func, s->func, sizeof(s->func)) == 0){
+      h->samples++;
+      return;
+    }
+  }
+  if(nhot == NHOT){
+    other++;
+    return;
+  }
+  h = &hots[nhot++];
+  h->user = s->user;
+  h->pid = s->pid;
+  h->pc = s->pc;
+  memmove(h->func, s->func, sizeof(s->func));
+  h->func[sizeof(s->func)] = 0;
+  h->samples = 1;
+}
+
+int
+main(int argc, char *argv[])
+{
+  struct profsample samples[16];
+  struct hot tmp;
+  int cpu, i, j, n, pid;
+
+  if(argc < 2){
+    fprintf(2, "usage: prof command [args]\n");
+    exit(1);
+  }
+  if(profile(PROF_ON, 0, 0, 0) < 0){
+    fprintf(2, "prof: cannot turn the profiler on\n");
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    fprintf(2, "prof: fork failed\n");
+    exit(1);
+  }
+  if(pid == 0){
+    exec(argv[1], argv + 1);
+    fprintf(2, "prof: exec %s failed\n", argv[1]);
+    exit(1);
+  }
+  wait(0);
+  profile(PROF_OFF, 0, 0, 0);
+
+  for(cpu = 0; cpu < NCPU; cpu++){
+    while((n = profile(PROF_READ, cpu, samples, 16)) > 0){
+      for(i = 0; i < n; i++)
+        count(&samples[i]);
+    }
+  }
+
+  // Sort by the number of samples, most first.
+  for(i = 0; i < nhot; i++){
+    for(j = i + 1; j < nhot; j++){
+      if(hots[j].samples > hots[i].samples){
+        tmp = hots[i];
+        hots[i] = hots[j];
+        hots[j] = tmp;
+      }
+    }
+  }
+
+  printf("%d samples\n", total);
+  for(i = 0; i < nhot && i < NSHOWN; i++){
+    if(hots[i].user)
+      printf("%d\tuser pid %d pc %p\n", hots[i].samples, hots[i].pid, hots[i].pc);
+    else
+      printf("%d\tkernel %s\n", hots[i].samples, hots[i].func[0] ? hots[i].func : "?");
+  }
+  if(other > 0)
+    printf("%d\tother\n", other);
+  exit(0);
+}
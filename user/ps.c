@@ -0,0 +1,103 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/fs.h"
+#include "kernel/fcntl.h"
+#include "user/user.h"
+
+// ps
+// Prints how busy the CPUs have been since boot, from /proc/stat, and a line
+// per process, from /proc/<pid>/stat: its pid, its parent's, its state, its
+// memory, and the CPU time it spent in user mode and in the kernel.
+
+// Reads the file at path into buf, NUL-terminated.
+// Returns the number of bytes read, or -1 if it cannot be opened.
+int
+readfile(char *path, char *buf, int size)
+{
+  int fd, n, total;
+
+  if((fd = open(path, O_RDONLY)) < 0)
+    return -1;
+  total = 0;
+  while(total < size - 1 && (n = read(fd, buf + total, size - 1 - total)) > 0)
+    total += n;
+  close(fd);
+  buf[total] = 0;
+  return total;
+}
+
+// Returns the number after the n-th space of s, or 0 if there is none.
+int
+field(char *s, int n)
+{
+  for(; n > 0 && *s; s++){
+    if(*s == ' ')
+      n--;
+  }
+  return atoi(s);
+}
+
+// Prints the CPU lines of /proc/stat, with the share of each state.
+void
+cpus(void)
+{
+  char buf[512], *line, *end;
+  int i, t[4], total;
+
+  if(readfile("/proc/stat", buf, sizeof(buf)) < 0){
+    fprintf(2, "ps: cannot open /proc/stat\n");
+    exit(1);
+  }
+  printf("CPU\tUSER\tSYSTEM\tIDLE\tIRQ\n");
+  for(line = buf; *line; line = end + 1){
+    end = strchr(line, '\n');
+    if(end == 0)
+      break;
+    *end = 0;
+    total = 0;
+    for(i = 0; i < 4; i++){
+      // "cpu  u s i q" has two spaces after the name.
+      t[i] = field(line, (line[3] == ' ') + i + 1);
+      total += t[i];
+    }
+    if(total == 0)
+      total = 1;
+    printf("%s", line[3] == ' ' ? "all" : line + 3);
+    for(i = 0; i < 4; i++)
+      printf("\t%d%%", t[i] * 100 / total);
+    printf("\n");
+  }
+}
+
+int
+main(int argc, char *argv[])
+{
+  char path[32], buf[128];
+  struct dirent de;
+  int fd;
+
+  if(argc != 1){
+    fprintf(2, "usage: ps\n");
+    exit(1);
+  }
+  cpus();
+
+  if((fd = open("/proc", O_RDONLY)) < 0){
+    fprintf(2, "ps: cannot open /proc\n");
+    exit(1);
+  }
+  printf("PID (NAME) STATE PPID SIZE UTIME STIME\n");
+  while(read(fd, &de, sizeof(de)) == sizeof(de)){
+    if(de.inum == 0 || de.name[0] < '0' || de.name[0] > '9')
+      continue;
+    strcpy(path, "/proc/");
+    memmove(path + 6, de.name, DIRSIZ);
+    path[6 + DIRSIZ] = 0;
+    strcpy(path + strlen(path), "/stat");
+    // The process may have exited meanwhile.
+    if(readfile(path, buf, sizeof(buf)) > 0)
+      printf("%s", buf);
+  }
+  close(fd);
+  exit(0);
+}
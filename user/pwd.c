@@ -0,0 +1,17 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/param.h"
+#include "user/user.h"
+
+int
+main(int argc, char *argv[])
+{
+  char buf[MAXPATH];
+
+  if(getcwd(buf, sizeof(buf)) < 0){
+    fprintf(2, "pwd: cannot get current directory\n");
+    exit(1);
+  }
+  printf("%s\n", buf);
+  exit(0);
+}
@@ -3,6 +3,8 @@
 #include "kernel/types.h"
 #include "user/user.h"
 #include "kernel/fcntl.h"
+#include "kernel/ioctl.h"
+#include "kernel/signal.h"
 
 // Parsed command representation
 #define EXEC  1
@@ -141,11 +143,26 @@ getcmd(char *buf, int nbuf)
   return 0;
 }
 
+// Let the job pid use the console until it exits or stops.
+void
+waitfg(int pid)
+{
+  int wpid, status, none = 0;
+
+  ioctl(0, TIOCSPGRP, &pid);
+  // Background jobs that were resumed may exit meanwhile.
+  while((wpid = wait(&status)) >= 0 && wpid != pid)
+    ;
+  if(wpid == pid && WIFSTOPPED(status))
+    printf("[%d] stopped\n", pid);
+  ioctl(0, TIOCSPGRP, &none);
+}
+
 int
 main(void)
 {
   static char buf[100];
-  int fd;
+  int fd, pid;
 
   // Ensure that three file descriptors are open.
   while((fd = open("console", O_RDWR)) >= 0){
@@ -164,9 +181,22 @@ main(void)
         fprintf(2, "cannot cd %s\n", buf+3);
       continue;
     }
-    if(fork1() == 0)
+    if((buf[0] == 'f' || buf[0] == 'b') && buf[1] == 'g' && buf[2] == ' '){
+      // Resume a stopped job, in the foreground or in the background.
+      pid = atoi(buf+3);
+      if(sigsend(-pid, SIGCONT) < 0)
+        fprintf(2, "no job %d\n", pid);
+      else if(buf[0] == 'f')
+        waitfg(pid);
+      continue;
+    }
+    // Each job gets a process group, which control-c and control-z signal.
+    if((pid = fork1()) == 0){
+      setpgid(0, 0);
       runcmd(parsecmd(buf));
-    wait(0);
+    }
+    setpgid(pid, pid);
+    waitfg(pid);
   }
   exit(0);
 }
@@ -0,0 +1,21 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+// strace mask command [args...]
+// Runs command, printing the system calls whose bits are set in mask.
+int
+main(int argc, char *argv[])
+{
+  if(argc < 3 || argv[1][0] < '0' || argv[1][0] > '9'){
+    fprintf(2, "usage: strace mask command [args...]\n");
+    exit(1);
+  }
+  if(trace(atoi(argv[1])) < 0){
+    fprintf(2, "strace: trace failed\n");
+    exit(1);
+  }
+  exec(argv[2], argv + 2);
+  fprintf(2, "strace: exec %s failed\n", argv[2]);
+  exit(1);
+}
@@ -0,0 +1,25 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+// suspend [seconds]
+// Suspends the machine until a key is pressed, or until the given number of
+// seconds has passed.
+
+int
+main(int argc, char *argv[])
+{
+  int start;
+
+  if(argc > 2){
+    fprintf(2, "usage: suspend [seconds]\n");
+    exit(1);
+  }
+  start = uptime();
+  if(suspend(argc == 2 ? atoi(argv[1]) : 0) < 0){
+    fprintf(2, "suspend: cannot suspend\n");
+    exit(1);
+  }
+  printf("suspended for %d ticks\n", uptime() - start);
+  exit(0);
+}
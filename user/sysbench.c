@@ -0,0 +1,64 @@
+#include "kernel/types.h"
+#include "kernel/time.h"
+#include "user/user.h"
+
+// sysbench [iterations]
+// Measures the latency of a round trip into the kernel: the time of
+// getpid(), the cheapest system call, against that of reading the
+// clock through the vdso page, which does not trap. Prints the
+// nanoseconds that each takes on average.
+
+#define NSEC_PER_SEC 1000000000UL
+
+static uint64
+now(void)
+{
+  struct timespec ts;
+
+  vdso_clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec * NSEC_PER_SEC + ts.tv_nsec;
+}
+
+// Nanoseconds that n calls of getpid() take.
+static uint64
+bench_syscall(int n)
+{
+  uint64 start = now();
+
+  for(int i = 0; i < n; i++)
+    getpid();
+  return now() - start;
+}
+
+// Nanoseconds that n reads of the clock take.
+static uint64
+bench_vdso(int n)
+{
+  uint64 start = now();
+
+  for(int i = 0; i < n; i++)
+    now();
+  return now() - start;
+}
+
+int
+main(int argc, char *argv[])
+{
+  int n = 100000;
+
+  if(argc > 2){
+    fprintf(2, "usage: sysbench [iterations]\n");
+    exit(1);
+  }
+  if(argc == 2 && (n = atoi(argv[1])) <= 0){
+    fprintf(2, "sysbench: bad iteration count %s\n", argv[1]);
+    exit(1);
+  }
+
+  // Warm up the caches and the TLB.
+  bench_syscall(n / 10 + 1);
+
+  printf("getpid: %d ns\n", (int)(bench_syscall(n) / n));
+  printf("vdso clock: %d ns\n", (int)(bench_vdso(n) / n));
+  exit(0);
+}
@@ -0,0 +1,63 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/param.h"
+#include "kernel/tracepoint.h"
+#include "user/user.h"
+
+// tracebuf [on|off]
+// Turns the kernel's tracepoints on or off, or prints the trace buffer of each CPU,
+// oldest record first, with times in nanoseconds since boot.
+
+static char *events[] = {
+[TRACE_SCHED_SWITCH]  "sched_switch",
+[TRACE_SYSCALL_ENTER] "syscall_enter",
+[TRACE_SYSCALL_EXIT]  "syscall_exit",
+[TRACE_PAGE_FAULT]    "page_fault",
+[TRACE_DISK_ISSUE]    "disk_issue",
+[TRACE_DISK_COMPLETE] "disk_complete",
+};
+
+int
+main(int argc, char *argv[])
+{
+  struct tracerec recs[8];
+  uint64 seq;
+  char *event;
+  int cpu, i, n;
+
+  if(argc == 2 && (strcmp(argv[1], "on") == 0 || strcmp(argv[1], "off") == 0)){
+    if(traceon(strcmp(argv[1], "on") == 0) < 0){
+      fprintf(2, "tracebuf: cannot turn tracing %s\n", argv[1]);
+      exit(1);
+    }
+    exit(0);
+  }
+  if(argc != 1){
+    fprintf(2, "usage: tracebuf [on|off]\n");
+    exit(1);
+  }
+
+  for(cpu = 0; cpu < NCPU; cpu++){
+    seq = 0;
+    for(;;){
+      n = traceread(cpu, recs, 8, seq);
+      if(n < 0){
+        fprintf(2, "tracebuf: cannot read the trace buffers\n");
+        exit(1);
+      }
+      if(n == 0)
+        break;
+      for(i = 0; i < n; i++){
+        if(recs[i].seq != seq)
+          printf("(%l records lost on cpu %d)\n", recs[i].seq - seq, cpu);
+        event = "?";
+        if(recs[i].event < sizeof(events) / sizeof(events[0]) && events[recs[i].event])
+          event = events[recs[i].event];
+        printf("%d %l %l %s %l %l\n", recs[i].cpu, recs[i].seq, recs[i].time, event,
+               recs[i].args[0], recs[i].args[1]);
+        seq = recs[i].seq + 1;
+      }
+    }
+  }
+  exit(0);
+}
@@ -1,8 +1,12 @@
 #include "kernel/types.h"
 #include "kernel/stat.h"
 #include "kernel/fcntl.h"
+#include "kernel/time.h"
 #include "user/user.h"
 
+// Error code of the last failed system call.
+int errno;
+
 char*
 strcpy(char *s, const char *t)
 {
@@ -22,6 +26,16 @@ strcmp(const char *p, const char *q)
   return (uchar)*p - (uchar)*q;
 }
 
+int
+strncmp(const char *p, const char *q, uint n)
+{
+  while(n > 0 && *p && *p == *q)
+    n--, p++, q++;
+  if(n == 0)
+    return 0;
+  return (uchar)*p - (uchar)*q;
+}
+
 uint
 strlen(const char *s)
 {
@@ -84,6 +98,16 @@ stat(const char *n, struct stat *st)
   return r;
 }
 
+int
+settimeofday(const struct timeval *tv)
+{
+  struct timespec ts;
+
+  ts.tv_sec = tv->tv_sec;
+  ts.tv_nsec = tv->tv_usec * 1000;
+  return clock_settime(CLOCK_REALTIME, &ts);
+}
+
 int
 atoi(const char *s)
 {
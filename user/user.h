@@ -1,5 +1,19 @@
 struct stat;
 struct rtcdate;
+struct sysinfo;
+struct pollfd;
+struct timerspec;
+struct timespec;
+struct timeval;
+struct auditrec;
+struct tracerec;
+struct leakrec;
+struct fuzzinfo;
+struct profsample;
+struct sockaddr_vm;
+struct dqblk;
+struct sembuf;
+struct mq_attr;
 
 // system calls
 int fork(void);
@@ -24,13 +38,80 @@ char* sbrk(int);
 int sleep(int);
 int uptime(void);
 int poweroff(int) __attribute__((noreturn));
+int sysinfo(struct sysinfo*);
+int fcntl(int, int, int);
+int ioctl(int, int, void*);
+int fadvise(int, uint, uint, int);
+int sendfile(int, int, uint*, int);
+void* ioring_setup(void);
+int ioring_enter(int);
+int splice(int, uint*, int, uint*, int);
+int quotactl(int, int, struct dqblk*);
+int pidfd_open(int, int);
+int sigpending(void);
+uint64 capget(void);
+int capdrop(uint64);
+int semget(int, int, int);
+int semop(int, struct sembuf*, int);
+int semctl(int, int, int, int);
+int mq_open(int, int, int, struct mq_attr*);
+int mq_send(int, const char*, int, uint);
+int mq_receive(int, char*, int, uint*);
+int mq_unlink(int);
+int mq_getattr(int, struct mq_attr*);
+int poll(struct pollfd*, int, int);
+int pipe2(int*, int);
+int eventfd(uint, int);
+int timerfd_create(int);
+int timerfd_settime(int, int, const struct timerspec*, struct timerspec*);
+int timerfd_gettime(int, struct timerspec*);
+int getcwd(char*, int);
+int chroot(const char*);
+int umask(int);
+int clock_gettime(int, struct timespec*);
+int getrandom(void*, int, int);
+int trace(uint64);
+int ptrace(int, int, void*, uint64);
+int getuid(void);
+int geteuid(void);
+int getgid(void);
+int getegid(void);
+int setuid(int);
+int setgid(int);
+int chmod(const char*, int);
+int chown(const char*, int, int);
+int audit(struct auditrec*, int, uint64);
+int sigsend(int, int);
+int setpgid(int, int);
+int getpgid(int);
+int clock_settime(int, const struct timespec*);
+int adjtime(const struct timespec*, struct timespec*);
+int dmesg(char*, int, uint64*);
+int logfilter(const char*);
+int traceon(int);
+int traceread(int, struct tracerec*, int, uint64);
+int leakcheck(struct leakrec*, int, uint64);
+int failinject(int, int);
+int ktest(const char*);
+int fuzzinfo(struct fuzzinfo*);
+int profile(int, int, struct profsample*, int);
+int suspend(int);
+int socket(int, int, int);
+int bind(int, const struct sockaddr_vm*);
+int listen(int, int);
+int accept(int, struct sockaddr_vm*);
+int connect(int, const struct sockaddr_vm*);
+long syscall(int, uint64, uint64, uint64, uint64, uint64, uint64);
 
 // ulib.c
+extern int errno;
 int stat(const char*, struct stat*);
+int settimeofday(const struct timeval*);
 char* strcpy(char*, const char*);
 void *memmove(void*, const void*, int);
 char* strchr(const char*, char c);
 int strcmp(const char*, const char*);
+int strncmp(const char*, const char*, uint);
 void fprintf(int, const char*, ...);
 void printf(const char*, ...);
 char* gets(char*, int max);
@@ -41,3 +122,9 @@ void free(void*);
 int atoi(const char*);
 int memcmp(const void *, const void *, uint);
 void *memcpy(void *, const void *, uint);
+
+// vdso.c
+int vdso_clock_gettime(int, struct timespec*);
+int gettimeofday(struct timeval*);
+int vdso_uptime(void);
+int getcpu(void);
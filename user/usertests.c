@@ -7,6 +7,29 @@
 #include "kernel/syscall.h"
 #include "kernel/memlayout.h"
 #include "kernel/riscv.h"
+#include "kernel/sysinfo.h"
+#include "kernel/errno.h"
+#include "kernel/ioctl.h"
+#include "kernel/poll.h"
+#include "kernel/timerfd.h"
+#include "kernel/time.h"
+#include "kernel/random.h"
+#include "kernel/ptrace.h"
+#include "kernel/signal.h"
+#include "kernel/audit.h"
+#include "kernel/cred.h"
+#include "kernel/tracepoint.h"
+#include "kernel/leak.h"
+#include "kernel/failinject.h"
+#include "kernel/fuzz.h"
+#include "kernel/profile.h"
+#include "kernel/socket.h"
+#include "kernel/ioring.h"
+#include "kernel/quota.h"
+#include "kernel/vdso.h"
+#include "kernel/ipc.h"
+#include "kernel/mqueue.h"
+#include "kernel/sem.h"
 
 //
 // Tests xv6 system calls.  usertests without arguments runs them all
@@ -646,6 +669,38 @@ createtest(char *s)
   }
 }
 
+// unlink must free the inode in the inode bitmap, or creating
+// more files than there are inodes runs out of them.
+void
+ireuse(char *s)
+{
+  int i, fd;
+  uint ino = 0;
+  struct stat st;
+
+  for(i = 0; i < 500; i++){
+    fd = open("ireuse", O_CREATE|O_RDWR);
+    if(fd < 0){
+      printf("%s: create %d failed\n", s, i);
+      exit(1);
+    }
+    if(fstat(fd, &st) < 0){
+      printf("%s: fstat failed\n", s);
+      exit(1);
+    }
+    if(i > 0 && st.ino != ino){
+      printf("%s: inode %d not reused, got %d\n", s, ino, st.ino);
+      exit(1);
+    }
+    ino = st.ino;
+    close(fd);
+    if(unlink("ireuse") < 0){
+      printf("%s: unlink failed\n", s);
+      exit(1);
+    }
+  }
+}
+
 void dirtest(char *s)
 {
   if(mkdir("dir0") < 0){
@@ -2654,6 +2709,3792 @@ execout(char *s)
   exit(0);
 }
 
+// four pages of initialized data, so that whole pages of it are in
+// usertests, which exec maps copy-on-write from the page cache.
+#define COWN (4*4096/sizeof(int))
+int cowdata[COWN] = { [COWN/4] = 1, [COWN/2] = 2 };
+
+// run by execcow in a new image of usertests, as "usertests -cow":
+// does it see cowdata as usertests has it, and may it write it,
+// by a store and by read()?
+int
+execcowcheck(void)
+{
+  int fds[2];
+  int v = 4;
+
+  if(cowdata[COWN/4] != 1 || cowdata[COWN/2] != 2)
+    return 1;
+  cowdata[COWN/4] = 3;
+  if(pipe(fds) < 0)
+    return 1;
+  if(write(fds[1], &v, sizeof(v)) != sizeof(v))
+    return 1;
+  if(read(fds[0], &cowdata[COWN/2], sizeof(v)) != sizeof(v))
+    return 1;
+  close(fds[0]);
+  close(fds[1]);
+  if(cowdata[COWN/4] != 3 || cowdata[COWN/2] != 4)
+    return 1;
+  return 0;
+}
+
+// a process that writes the pages that exec mapped copy-on-write
+// must not change what the next exec of the program sees.
+void
+execcow(char *s)
+{
+  char *args[] = { "usertests", "-cow", 0 };
+  int pid, xstatus;
+
+  for(int i = 0; i < 2; i++){
+    pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      exec("usertests", args);
+      printf("%s: exec failed\n", s);
+      exit(1);
+    }
+    wait(&xstatus);
+    if(xstatus != 0){
+      printf("%s: exec %d saw or wrote the wrong data\n", s, i);
+      exit(1);
+    }
+  }
+}
+
+// does sysinfo() report the open file table usage, and does the
+// high-water mark survive closing the files?
+void
+sysinfotest(char *s)
+{
+  struct sysinfo info0, info1, info2;
+  int fds[2];
+
+  if(sysinfo(&info0) < 0){
+    printf("%s: sysinfo failed\n", s);
+    exit(1);
+  }
+  if(info0.ftable.capacity < NFILE || info0.itable.capacity != NINODE ||
+     info0.bcache.capacity != NBUF * NBUFSHARD){
+    printf("%s: wrong capacities\n", s);
+    exit(1);
+  }
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  if(sysinfo(&info1) < 0){
+    printf("%s: sysinfo failed\n", s);
+    exit(1);
+  }
+  if(info1.ftable.in_use != info0.ftable.in_use + 2 ||
+     info1.ftable.high_water < info1.ftable.in_use){
+    printf("%s: pipe files not counted\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+  if(sysinfo(&info2) < 0){
+    printf("%s: sysinfo failed\n", s);
+    exit(1);
+  }
+  if(info2.ftable.in_use != info0.ftable.in_use ||
+     info2.ftable.high_water < info1.ftable.in_use){
+    printf("%s: closed files still counted\n", s);
+    exit(1);
+  }
+  if(sysinfo((struct sysinfo*)0xffffffffffffffffULL) >= 0){
+    printf("%s: sysinfo with a bad pointer succeeded\n", s);
+    exit(1);
+  }
+}
+
+// do failing system calls report the right errno?
+void
+errnotest(char *s)
+{
+  int fd;
+  char *args[] = { "echo", 0 };
+
+  errno = 0;
+  if(open("errno-nonexistent", O_RDONLY) >= 0 || errno != ENOENT){
+    printf("%s: open of a missing file: errno %d, expected ENOENT\n", s, errno);
+    exit(1);
+  }
+  if(read(-1, 0, 1) >= 0 || errno != EBADF){
+    printf("%s: read of a bad fd: errno %d, expected EBADF\n", s, errno);
+    exit(1);
+  }
+  if(mkdir("errnodir") < 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  if(mkdir("errnodir") >= 0 || errno != EEXIST){
+    printf("%s: mkdir of an existing dir: errno %d, expected EEXIST\n", s, errno);
+    exit(1);
+  }
+  if(open("errnodir", O_RDWR) >= 0 || errno != EISDIR){
+    printf("%s: open of a dir for writing: errno %d, expected EISDIR\n", s, errno);
+    exit(1);
+  }
+  fd = open("errnodir/file", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create errnodir/file failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(chdir("errnodir/file") >= 0 || errno != ENOTDIR){
+    printf("%s: chdir to a file: errno %d, expected ENOTDIR\n", s, errno);
+    exit(1);
+  }
+  if(unlink("errnodir") >= 0 || errno != ENOTEMPTY){
+    printf("%s: unlink of a non-empty dir: errno %d, expected ENOTEMPTY\n", s, errno);
+    exit(1);
+  }
+  if(unlink("errnodir/file") < 0 || unlink("errnodir") < 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+  if(wait(0) >= 0 || errno != ECHILD){
+    printf("%s: wait without children: errno %d, expected ECHILD\n", s, errno);
+    exit(1);
+  }
+  if(kill(-1) >= 0 || errno != ESRCH){
+    printf("%s: kill of a missing pid: errno %d, expected ESRCH\n", s, errno);
+    exit(1);
+  }
+  if(exec("errno-nonexistent", args) >= 0 || errno != ENOENT){
+    printf("%s: exec of a missing file: errno %d, expected ENOENT\n", s, errno);
+    exit(1);
+  }
+  if(fstat(0, (struct stat*)0xffffffffffffffffULL) >= 0 || errno != EFAULT){
+    printf("%s: fstat with a bad pointer: errno %d, expected EFAULT\n", s, errno);
+    exit(1);
+  }
+}
+
+// fcntl() descriptor and status flags: close-on-exec, append,
+// and non-blocking pipes.
+void
+fcntltest(char *s)
+{
+  int fd, fds[2], hold[2], i, n, pid;
+  char buf[8];
+  char *args[] = { "cat", 0 };
+
+  fd = open("fcntlfile", O_CREATE|O_RDWR|O_CLOEXEC);
+  if(fd < 0){
+    printf("%s: create fcntlfile failed\n", s);
+    exit(1);
+  }
+  if(fcntl(fd, F_GETFD, 0) != FD_CLOEXEC){
+    printf("%s: O_CLOEXEC not reported by F_GETFD\n", s);
+    exit(1);
+  }
+  if(fcntl(fd, F_SETFD, 0) != 0 || fcntl(fd, F_GETFD, 0) != 0){
+    printf("%s: F_SETFD failed to clear FD_CLOEXEC\n", s);
+    exit(1);
+  }
+  if(fcntl(fd, F_GETFL, 0) != O_RDWR){
+    printf("%s: F_GETFL returned %d, expected O_RDWR\n", s, fcntl(fd, F_GETFL, 0));
+    exit(1);
+  }
+  if(fcntl(fd, 1000, 0) >= 0 || errno != EINVAL){
+    printf("%s: unknown fcntl command: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+  if(write(fd, "ab", 2) != 2){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // a fresh open starts at offset 0, but O_APPEND writes go to the end.
+  fd = open("fcntlfile", O_RDWR|O_APPEND);
+  if(fd < 0 || fcntl(fd, F_GETFL, 0) != (O_RDWR|O_APPEND)){
+    printf("%s: open with O_APPEND failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "cd", 2) != 2){
+    printf("%s: append failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  fd = open("fcntlfile", O_RDONLY);
+  n = read(fd, buf, sizeof(buf));
+  close(fd);
+  unlink("fcntlfile");
+  if(n != 4 || memcmp(buf, "abcd", 4) != 0){
+    printf("%s: O_APPEND wrote %d bytes in the wrong place\n", s, n);
+    exit(1);
+  }
+
+  // an empty non-blocking pipe fails with EAGAIN instead of sleeping.
+  if(pipe(fds) < 0 || pipe(hold) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  if(fcntl(fds[0], F_SETFL, O_NONBLOCK) != 0){
+    printf("%s: F_SETFL failed\n", s);
+    exit(1);
+  }
+  if(read(fds[0], buf, 1) >= 0 || errno != EAGAIN){
+    printf("%s: read of an empty non-blocking pipe: errno %d, expected EAGAIN\n", s, errno);
+    exit(1);
+  }
+
+  // the child's copy of the write end must be closed by exec, so the
+  // read end sees end-of-file while cat keeps running on `hold`.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    fcntl(fds[1], F_SETFD, FD_CLOEXEC);
+    close(fds[0]);
+    close(0);
+    dup(hold[0]);
+    close(hold[0]);
+    close(hold[1]);
+    exec("cat", args);
+    printf("%s: exec cat failed\n", s);
+    exit(1);
+  }
+  close(fds[1]);
+  close(hold[0]);
+  for(i = 0; i < 100; i++){
+    n = read(fds[0], buf, 1);
+    if(n == 0)
+      break;
+    if(n > 0 || errno != EAGAIN){
+      printf("%s: unexpected read from the pipe\n", s);
+      exit(1);
+    }
+    sleep(1);
+  }
+  close(fds[0]);
+  close(hold[1]);
+  wait(0);
+  if(i == 100){
+    printf("%s: FD_CLOEXEC descriptor survived exec\n", s);
+    exit(1);
+  }
+}
+
+// ioctl() requests reach the console driver, and other files
+// refuse them.
+void
+ioctltest(char *s)
+{
+  struct winsize old, ws;
+  int fd;
+
+  if(ioctl(1, TIOCGWINSZ, &old) != 0){
+    printf("%s: TIOCGWINSZ on the console failed\n", s);
+    exit(1);
+  }
+  ws = old;
+  ws.ws_row = 50;
+  ws.ws_col = 132;
+  if(ioctl(1, TIOCSWINSZ, &ws) != 0){
+    printf("%s: TIOCSWINSZ on the console failed\n", s);
+    exit(1);
+  }
+  memset(&ws, 0, sizeof(ws));
+  if(ioctl(1, TIOCGWINSZ, &ws) != 0 || ws.ws_row != 50 || ws.ws_col != 132){
+    printf("%s: window size was not updated\n", s);
+    exit(1);
+  }
+  if(ioctl(1, TIOCSWINSZ, &old) != 0){
+    printf("%s: restoring the window size failed\n", s);
+    exit(1);
+  }
+  if(ioctl(1, TIOCGWINSZ, (void*)0xffffffffffffffffULL) >= 0 || errno != EFAULT){
+    printf("%s: TIOCGWINSZ with a bad pointer: errno %d, expected EFAULT\n", s, errno);
+    exit(1);
+  }
+  if(ioctl(1, 0x1234, 0) >= 0 || errno != ENOTTY){
+    printf("%s: unknown request: errno %d, expected ENOTTY\n", s, errno);
+    exit(1);
+  }
+
+  fd = open("ioctlfile", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create ioctlfile failed\n", s);
+    exit(1);
+  }
+  if(ioctl(fd, TIOCGWINSZ, &ws) >= 0 || errno != ENOTTY){
+    printf("%s: ioctl on a file: errno %d, expected ENOTTY\n", s, errno);
+    exit(1);
+  }
+  close(fd);
+  unlink("ioctlfile");
+}
+
+// the console starts in canonical mode, and TCSETS switches it to
+// raw mode and back.
+void
+termiostest(char *s)
+{
+  struct termios old, t;
+  int fds[2];
+
+  if(ioctl(0, TCGETS, &old) != 0){
+    printf("%s: TCGETS on the console failed\n", s);
+    exit(1);
+  }
+  if((old.c_lflag & (ICANON|ECHO|ISIG)) != (ICANON|ECHO|ISIG) || !(old.c_iflag & ICRNL)){
+    printf("%s: console is not in canonical mode: iflag 0x%x lflag 0x%x\n",
+           s, old.c_iflag, old.c_lflag);
+    exit(1);
+  }
+  t = old;
+  t.c_iflag &= ~ICRNL;
+  t.c_lflag &= ~(ICANON|ECHO);
+  if(ioctl(0, TCSETS, &t) != 0){
+    printf("%s: TCSETS on the console failed\n", s);
+    exit(1);
+  }
+  memset(&t, 0, sizeof(t));
+  if(ioctl(0, TCGETS, &t) != 0 || (t.c_lflag & (ICANON|ECHO)) || (t.c_iflag & ICRNL) ||
+     !(t.c_lflag & ISIG)){
+    printf("%s: raw mode was not set\n", s);
+    exit(1);
+  }
+  if(ioctl(0, TCSETS, &old) != 0){
+    printf("%s: restoring canonical mode failed\n", s);
+    exit(1);
+  }
+  if(ioctl(0, TCGETS, &t) != 0 || t.c_lflag != old.c_lflag || t.c_iflag != old.c_iflag){
+    printf("%s: canonical mode was not restored\n", s);
+    exit(1);
+  }
+
+  if(pipe(fds) != 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  if(ioctl(fds[0], TCGETS, &t) >= 0 || errno != ENOTTY){
+    printf("%s: TCGETS on a pipe: errno %d, expected ENOTTY\n", s, errno);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+}
+
+// poll() on pipes: readiness, timeouts, waking up on a write from
+// another process, and hang-up.
+void
+polltest(char *s)
+{
+  struct pollfd pfd[3];
+  int fds[2], pid, start;
+  char c;
+
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  pfd[0].fd = fds[0];
+  pfd[0].events = POLLIN;
+  pfd[1].fd = fds[1];
+  pfd[1].events = POLLOUT;
+  pfd[2].fd = -1;
+  pfd[2].events = POLLIN;
+  if(poll(pfd, 3, 0) != 1 || pfd[0].revents != 0 || pfd[1].revents != POLLOUT || pfd[2].revents != 0){
+    printf("%s: poll of an empty pipe returned wrong events\n", s);
+    exit(1);
+  }
+
+  // an empty pipe times out.
+  start = uptime();
+  if(poll(pfd, 1, 2) != 0){
+    printf("%s: poll of an empty pipe did not time out\n", s);
+    exit(1);
+  }
+  if(uptime() - start < 2){
+    printf("%s: poll returned before its timeout\n", s);
+    exit(1);
+  }
+
+  // a write from another process wakes up a poller without timeout.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    sleep(2);
+    write(fds[1], "x", 1);
+    exit(0);
+  }
+  if(poll(pfd, 1, -1) != 1 || pfd[0].revents != POLLIN){
+    printf("%s: poll was not woken up by a write\n", s);
+    exit(1);
+  }
+  wait(0);
+  if(read(fds[0], &c, 1) != 1 || c != 'x'){
+    printf("%s: read after poll failed\n", s);
+    exit(1);
+  }
+
+  // closing the write end hangs up the read end.
+  close(fds[1]);
+  if(poll(pfd, 1, -1) != 1 || !(pfd[0].revents & POLLHUP)){
+    printf("%s: no POLLHUP after closing the write end\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+
+  // closed descriptors are reported, not rejected.
+  pfd[0].fd = fds[0];
+  if(poll(pfd, 1, 0) != 1 || pfd[0].revents != POLLNVAL){
+    printf("%s: no POLLNVAL for a closed fd\n", s);
+    exit(1);
+  }
+}
+
+// pipe2() flags, and resizing a pipe's buffer with data in it.
+void
+pipe2test(char *s)
+{
+  int fds[2], i, n, size;
+  static char buf[4096];
+
+  if(pipe2(fds, O_RDWR) >= 0 || errno != EINVAL){
+    printf("%s: pipe2 with a bad flag: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+  if(pipe2(fds, O_NONBLOCK|O_CLOEXEC) < 0){
+    printf("%s: pipe2 failed\n", s);
+    exit(1);
+  }
+  if(fcntl(fds[0], F_GETFD, 0) != FD_CLOEXEC || fcntl(fds[1], F_GETFD, 0) != FD_CLOEXEC){
+    printf("%s: pipe2 did not set FD_CLOEXEC\n", s);
+    exit(1);
+  }
+  if(fcntl(fds[1], F_GETFL, 0) != (O_WRONLY|O_NONBLOCK)){
+    printf("%s: pipe2 did not set O_NONBLOCK\n", s);
+    exit(1);
+  }
+
+  // fill the pipe until a non-blocking write fails.
+  size = fcntl(fds[1], F_GETPIPE_SZ, 0);
+  if(size < 512){
+    printf("%s: F_GETPIPE_SZ returned %d\n", s, size);
+    exit(1);
+  }
+  for(i = 0; i < size; i++){
+    char c = i % 251;
+    if(write(fds[1], &c, 1) != 1){
+      printf("%s: write %d of %d failed\n", s, i, size);
+      exit(1);
+    }
+  }
+  if(write(fds[1], "x", 1) >= 0 || errno != EAGAIN){
+    printf("%s: write to a full pipe: errno %d, expected EAGAIN\n", s, errno);
+    exit(1);
+  }
+
+  // grow the pipe, keeping the data, and add more.
+  if(fcntl(fds[1], F_SETPIPE_SZ, 2 * size) != 2 * size){
+    printf("%s: F_SETPIPE_SZ failed to grow the pipe\n", s);
+    exit(1);
+  }
+  for(; i < size + 100; i++){
+    char c = i % 251;
+    if(write(fds[1], &c, 1) != 1){
+      printf("%s: write after growing failed\n", s);
+      exit(1);
+    }
+  }
+  if(fcntl(fds[0], F_SETPIPE_SZ, size) >= 0 || errno != EBUSY){
+    printf("%s: shrinking below the data: errno %d, expected EBUSY\n", s, errno);
+    exit(1);
+  }
+
+  for(i = 0; i < size + 100; i += n){
+    n = read(fds[0], buf, sizeof(buf));
+    if(n <= 0){
+      printf("%s: read returned %d\n", s, n);
+      exit(1);
+    }
+    for(int j = 0; j < n; j++){
+      if((buf[j] & 0xff) != (i + j) % 251){
+        printf("%s: wrong byte at %d\n", s, i + j);
+        exit(1);
+      }
+    }
+  }
+  if(read(fds[0], buf, 1) >= 0 || errno != EAGAIN){
+    printf("%s: read from an empty pipe: errno %d, expected EAGAIN\n", s, errno);
+    exit(1);
+  }
+  if(fcntl(fds[0], F_SETPIPE_SZ, 1) != size){
+    printf("%s: F_SETPIPE_SZ failed to shrink the pipe\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+}
+
+// eventfd() counters: accumulating writes, semaphore reads, and
+// waking up a poller in another process.
+void
+eventfdtest(char *s)
+{
+  struct pollfd pfd;
+  uint64 v;
+  int fd, pid;
+
+  fd = eventfd(3, EFD_NONBLOCK);
+  if(fd < 0){
+    printf("%s: eventfd failed\n", s);
+    exit(1);
+  }
+  v = 5;
+  if(write(fd, &v, sizeof(v)) != sizeof(v) || write(fd, &v, sizeof(v)) != sizeof(v)){
+    printf("%s: write to eventfd failed\n", s);
+    exit(1);
+  }
+  if(read(fd, &v, 4) >= 0 || errno != EINVAL){
+    printf("%s: short read: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+  if(read(fd, &v, sizeof(v)) != sizeof(v) || v != 13){
+    printf("%s: read %d from eventfd, expected 13\n", s, (int)v);
+    exit(1);
+  }
+  if(read(fd, &v, sizeof(v)) >= 0 || errno != EAGAIN){
+    printf("%s: read of a zero counter: errno %d, expected EAGAIN\n", s, errno);
+    exit(1);
+  }
+  close(fd);
+
+  fd = eventfd(2, EFD_SEMAPHORE);
+  if(read(fd, &v, sizeof(v)) != sizeof(v) || v != 1 ||
+     read(fd, &v, sizeof(v)) != sizeof(v) || v != 1){
+    printf("%s: semaphore reads did not return 1\n", s);
+    exit(1);
+  }
+
+  pfd.fd = fd;
+  pfd.events = POLLIN;
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    sleep(2);
+    v = 1;
+    write(fd, &v, sizeof(v));
+    exit(0);
+  }
+  if(poll(&pfd, 1, -1) != 1 || pfd.revents != POLLIN){
+    printf("%s: poll was not woken up by the eventfd\n", s);
+    exit(1);
+  }
+  wait(0);
+  if(read(fd, &v, sizeof(v)) != sizeof(v) || v != 1){
+    printf("%s: read after poll failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(eventfd(0, 0x4000) >= 0 || errno != EINVAL){
+    printf("%s: eventfd with a bad flag: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+}
+
+// A pidfd becomes readable when its process exits, which also
+// sends SIGCHLD to the parent, and stays so after the parent reaps it.
+void
+pidfdtest(char *s)
+{
+  struct pollfd pfd;
+  int fd, nbfd, pid, xstatus;
+  char c;
+
+  sigpending();
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    sleep(3);
+    exit(7);
+  }
+  fd = pidfd_open(pid, 0);
+  nbfd = pidfd_open(pid, O_NONBLOCK | O_CLOEXEC);
+  if(fd < 0 || nbfd < 0){
+    printf("%s: pidfd_open failed\n", s);
+    exit(1);
+  }
+  pfd.fd = fd;
+  pfd.events = POLLIN;
+  if(poll(&pfd, 1, 0) != 0){
+    printf("%s: pidfd of a running process is readable\n", s);
+    exit(1);
+  }
+  if(read(nbfd, &c, 1) >= 0 || errno != EAGAIN){
+    printf("%s: nonblocking read of a running process: errno %d, expected EAGAIN\n", s, errno);
+    exit(1);
+  }
+  if(write(fd, "x", 1) >= 0){
+    printf("%s: wrote to a pidfd\n", s);
+    exit(1);
+  }
+  if(poll(&pfd, 1, -1) != 1 || pfd.revents != POLLIN){
+    printf("%s: poll was not woken up by the exit\n", s);
+    exit(1);
+  }
+  if(read(fd, &c, 1) != 0 || read(nbfd, &c, 1) != 0){
+    printf("%s: read of an exited process did not return end of file\n", s);
+    exit(1);
+  }
+  if((sigpending() & (1 << SIGCHLD)) == 0){
+    printf("%s: no SIGCHLD\n", s);
+    exit(1);
+  }
+  if(sigpending() != 0){
+    printf("%s: sigpending() did not forget SIGCHLD\n", s);
+    exit(1);
+  }
+  if(wait(&xstatus) != pid || xstatus != 7){
+    printf("%s: wait after pidfd: status %d\n", s, xstatus);
+    exit(1);
+  }
+  pfd.revents = 0;
+  if(poll(&pfd, 1, 0) != 1 || pfd.revents != POLLIN){
+    printf("%s: pidfd of a reaped process is not readable\n", s);
+    exit(1);
+  }
+  close(fd);
+  close(nbfd);
+
+  if(pidfd_open(pid, 0) >= 0 || errno != ESRCH){
+    printf("%s: pidfd_open of a reaped process: errno %d, expected ESRCH\n", s, errno);
+    exit(1);
+  }
+  if(pidfd_open(getpid(), 0x4000) >= 0 || errno != EINVAL){
+    printf("%s: pidfd_open with a bad flag: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+}
+
+// Waits until a process waits for semaphore num of set id to grow.
+static void
+semwaiter(char *s, int id, int num)
+{
+  for(int i = 0; semctl(id, num, GETNCNT, 0) != 1; i++){
+    if(i == 50){
+      printf("%s: no process waits for the semaphore\n", s);
+      exit(1);
+    }
+    sleep(1);
+  }
+}
+
+// System V semaphores: atomic operations, waiting, undo on exit, and
+// removal under a waiter.
+void
+semtest(char *s)
+{
+  struct sembuf ops[2];
+  int id, pid, xstatus;
+
+  id = semget(IPC_PRIVATE, 2, 0600);
+  if(id < 0 || semctl(id, 0, GETVAL, 0) != 0){
+    printf("%s: semget failed\n", s);
+    exit(1);
+  }
+  ops[0].sem_num = 0;
+  ops[0].sem_op = 2;
+  ops[0].sem_flg = 0;
+  if(semop(id, ops, 1) < 0 || semctl(id, 0, GETVAL, 0) != 2){
+    printf("%s: semop did not add 2\n", s);
+    exit(1);
+  }
+
+  // Either all operations apply, or none.
+  ops[0].sem_num = 1;
+  ops[0].sem_op = 1;
+  ops[1].sem_num = 0;
+  ops[1].sem_op = -3;
+  ops[1].sem_flg = IPC_NOWAIT;
+  if(semop(id, ops, 2) >= 0 || errno != EAGAIN){
+    printf("%s: semop below 0: errno %d, expected EAGAIN\n", s, errno);
+    exit(1);
+  }
+  if(semctl(id, 1, GETVAL, 0) != 0){
+    printf("%s: semop applied part of its operations\n", s);
+    exit(1);
+  }
+
+  // A process waits until another one releases the semaphore.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    ops[0].sem_num = 1;
+    ops[0].sem_op = -1;
+    ops[0].sem_flg = 0;
+    exit(semop(id, ops, 1) < 0);
+  }
+  semwaiter(s, id, 1);
+  ops[0].sem_num = 1;
+  ops[0].sem_op = 1;
+  ops[0].sem_flg = 0;
+  if(semop(id, ops, 1) < 0 || wait(&xstatus) != pid || xstatus != 0){
+    printf("%s: waiter was not woken up\n", s);
+    exit(1);
+  }
+
+  // A killed process releases what it took with SEM_UNDO.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    ops[0].sem_num = 0;
+    ops[0].sem_op = -2;
+    ops[0].sem_flg = SEM_UNDO;
+    if(semop(id, ops, 1) < 0)
+      exit(1);
+    for(;;)
+      sleep(100);
+  }
+  while(semctl(id, 0, GETVAL, 0) != 0)
+    sleep(1);
+  kill(pid);
+  wait(0);
+  if(semctl(id, 0, GETVAL, 0) != 2){
+    printf("%s: exit did not undo: value %d, expected 2\n", s, semctl(id, 0, GETVAL, 0));
+    exit(1);
+  }
+
+  if(semctl(id, 0, SETVAL, 5) < 0 || semctl(id, 0, GETVAL, 0) != 5){
+    printf("%s: SETVAL failed\n", s);
+    exit(1);
+  }
+  if(semctl(id, 0, SETVAL, SEMVMX + 1) >= 0 || errno != ERANGE){
+    printf("%s: SETVAL past SEMVMX: errno %d, expected ERANGE\n", s, errno);
+    exit(1);
+  }
+
+  // Removing the set wakes up its waiters.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    ops[0].sem_num = 1;
+    ops[0].sem_op = -1;
+    ops[0].sem_flg = 0;
+    exit(semop(id, ops, 1) >= 0 || errno != EIDRM);
+  }
+  semwaiter(s, id, 1);
+  if(semctl(id, 0, IPC_RMID, 0) < 0 || wait(&xstatus) != pid || xstatus != 0){
+    printf("%s: waiter did not fail with EIDRM\n", s);
+    exit(1);
+  }
+  if(semctl(id, 0, GETVAL, 0) >= 0 || errno != EINVAL){
+    printf("%s: removed set: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+
+  id = semget(4242, 1, IPC_CREAT | IPC_EXCL | 0600);
+  if(id < 0 || semget(4242, 1, 0) != id){
+    printf("%s: semget by key failed\n", s);
+    exit(1);
+  }
+  if(semget(4242, 1, IPC_CREAT | IPC_EXCL | 0600) >= 0 || errno != EEXIST){
+    printf("%s: semget with IPC_EXCL: errno %d, expected EEXIST\n", s, errno);
+    exit(1);
+  }
+  if(semget(4242, 2, 0) >= 0 || errno != EINVAL){
+    printf("%s: semget of too many semaphores: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+  semctl(id, 0, IPC_RMID, 0);
+  if(semget(4242, 1, 0) >= 0 || errno != ENOENT){
+    printf("%s: semget of a removed key: errno %d, expected ENOENT\n", s, errno);
+    exit(1);
+  }
+}
+
+void
+mqtest(char *s)
+{
+  struct mq_attr attr;
+  struct pollfd pfd;
+  char buf[MQ_MSGSIZE];
+  uint prio;
+  int fd, nfd, pid, xstatus;
+
+  attr.mq_flags = 0;
+  attr.mq_maxmsg = 2;
+  attr.mq_msgsize = 16;
+  attr.mq_curmsgs = 0;
+  fd = mq_open(4343, O_RDWR | O_CREATE, 0600, &attr);
+  nfd = mq_open(4343, O_RDWR | O_NONBLOCK, 0, 0);
+  if(fd < 0 || nfd < 0){
+    printf("%s: mq_open failed\n", s);
+    exit(1);
+  }
+  if(mq_send(fd, "low", 4, 1) < 0 || mq_send(fd, "high", 5, 5) < 0){
+    printf("%s: mq_send failed\n", s);
+    exit(1);
+  }
+  if(mq_getattr(nfd, &attr) < 0 || attr.mq_curmsgs != 2 || attr.mq_msgsize != 16
+     || attr.mq_flags != O_NONBLOCK){
+    printf("%s: mq_getattr failed\n", s);
+    exit(1);
+  }
+  if(mq_send(nfd, "full", 5, 0) >= 0 || errno != EAGAIN){
+    printf("%s: mq_send to a full queue: errno %d, expected EAGAIN\n", s, errno);
+    exit(1);
+  }
+  if(mq_send(fd, "seventeen bytes!", 17, 0) >= 0 || errno != EMSGSIZE){
+    printf("%s: mq_send of a long message: errno %d, expected EMSGSIZE\n", s, errno);
+    exit(1);
+  }
+  if(mq_receive(fd, buf, 8, 0) >= 0 || errno != EMSGSIZE){
+    printf("%s: mq_receive into a short buffer: errno %d, expected EMSGSIZE\n", s, errno);
+    exit(1);
+  }
+
+  // The highest priority comes first.
+  if(mq_receive(fd, buf, sizeof(buf), &prio) != 5 || strcmp(buf, "high") != 0 || prio != 5
+     || mq_receive(nfd, buf, sizeof(buf), &prio) != 4 || strcmp(buf, "low") != 0 || prio != 1){
+    printf("%s: messages out of order\n", s);
+    exit(1);
+  }
+  if(mq_receive(nfd, buf, sizeof(buf), 0) >= 0 || errno != EAGAIN){
+    printf("%s: mq_receive from an empty queue: errno %d, expected EAGAIN\n", s, errno);
+    exit(1);
+  }
+  pfd.fd = fd;
+  pfd.events = POLLIN | POLLOUT;
+  if(poll(&pfd, 1, 0) != 1 || pfd.revents != POLLOUT){
+    printf("%s: poll of an empty queue: revents %x\n", s, pfd.revents);
+    exit(1);
+  }
+
+  // A receiver waits for a sender.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0)
+    exit(mq_receive(fd, buf, sizeof(buf), 0) != 3 || strcmp(buf, "hi") != 0);
+  sleep(1);
+  if(mq_send(fd, "hi", 3, 0) < 0 || wait(&xstatus) != pid || xstatus != 0){
+    printf("%s: receiver was not woken up\n", s);
+    exit(1);
+  }
+
+  // poll() waits for a message.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    sleep(1);
+    exit(mq_send(fd, "yo", 3, 0) < 0);
+  }
+  pfd.events = POLLIN;
+  if(poll(&pfd, 1, -1) != 1 || pfd.revents != POLLIN){
+    printf("%s: poll did not see the message\n", s);
+    exit(1);
+  }
+  wait(0);
+
+  // Unlinking frees the key, but the open descriptors keep the queue.
+  if(mq_unlink(4343) < 0){
+    printf("%s: mq_unlink failed\n", s);
+    exit(1);
+  }
+  if(mq_open(4343, O_RDWR, 0, 0) >= 0 || errno != ENOENT){
+    printf("%s: mq_open of an unlinked key: errno %d, expected ENOENT\n", s, errno);
+    exit(1);
+  }
+  if(mq_receive(nfd, buf, sizeof(buf), 0) != 3 || strcmp(buf, "yo") != 0){
+    printf("%s: unlinked queue lost its message\n", s);
+    exit(1);
+  }
+  close(fd);
+  close(nfd);
+}
+
+void
+timerfdtest(char *s)
+{
+  struct timerspec ts, old;
+  struct pollfd pfd;
+  uint64 v;
+  int fd;
+
+  fd = timerfd_create(TFD_NONBLOCK);
+  if(fd < 0){
+    printf("%s: timerfd_create failed\n", s);
+    exit(1);
+  }
+  if(read(fd, &v, sizeof(v)) >= 0 || errno != EAGAIN){
+    printf("%s: read of a disarmed timer: errno %d, expected EAGAIN\n", s, errno);
+    exit(1);
+  }
+  if(write(fd, &v, sizeof(v)) >= 0){
+    printf("%s: write to a timerfd succeeded\n", s);
+    exit(1);
+  }
+
+  // a one-shot timer expires once.
+  ts.value = 2;
+  ts.interval = 0;
+  if(timerfd_settime(fd, 0, &ts, 0) < 0){
+    printf("%s: timerfd_settime failed\n", s);
+    exit(1);
+  }
+  if(timerfd_gettime(fd, &old) < 0 || old.value == 0 || old.value > 2){
+    printf("%s: timerfd_gettime returned %d, expected 1 or 2\n", s, (int)old.value);
+    exit(1);
+  }
+  pfd.fd = fd;
+  pfd.events = POLLIN;
+  if(poll(&pfd, 1, -1) != 1 || pfd.revents != POLLIN){
+    printf("%s: poll was not woken up by the timer\n", s);
+    exit(1);
+  }
+  if(read(fd, &v, sizeof(v)) != sizeof(v) || v != 1){
+    printf("%s: read %d expirations, expected 1\n", s, (int)v);
+    exit(1);
+  }
+  sleep(3);
+  if(read(fd, &v, sizeof(v)) >= 0 || errno != EAGAIN){
+    printf("%s: one-shot timer expired again\n", s);
+    exit(1);
+  }
+
+  // an interval timer counts its expirations until read.
+  ts.value = 1;
+  ts.interval = 1;
+  timerfd_settime(fd, 0, &ts, 0);
+  sleep(5);
+  if(read(fd, &v, sizeof(v)) != sizeof(v) || v < 3){
+    printf("%s: read %d expirations, expected at least 3\n", s, (int)v);
+    exit(1);
+  }
+
+  // disarming returns the previous setting.
+  ts.value = 0;
+  if(timerfd_settime(fd, 0, &ts, &old) < 0 || old.interval != 1){
+    printf("%s: disarming did not return the old interval\n", s);
+    exit(1);
+  }
+  if(timerfd_gettime(fd, &old) < 0 || old.value != 0){
+    printf("%s: disarmed timer still armed\n", s);
+    exit(1);
+  }
+  if(timerfd_settime(fd, 0x100, &ts, 0) >= 0 || errno != EINVAL){
+    printf("%s: timerfd_settime with a bad flag: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+
+  // closing an armed timer disarms it.
+  ts.value = 1;
+  ts.interval = 1;
+  timerfd_settime(fd, 0, &ts, 0);
+  close(fd);
+  sleep(2);
+
+  fd = timerfd_create(0);
+  ts.value = uptime() + 2;
+  ts.interval = 0;
+  if(timerfd_settime(fd, TFD_TIMER_ABSTIME, &ts, 0) < 0){
+    printf("%s: timerfd_settime with TFD_TIMER_ABSTIME failed\n", s);
+    exit(1);
+  }
+  if(read(fd, &v, sizeof(v)) != sizeof(v) || v != 1){
+    printf("%s: blocking read returned %d expirations, expected 1\n", s, (int)v);
+    exit(1);
+  }
+  close(fd);
+}
+
+void
+getcwdtest(char *s)
+{
+  char buf[MAXPATH];
+
+  if(mkdir("gcwd") < 0 || mkdir("gcwd/sub") < 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  if(chdir("gcwd/sub") < 0){
+    printf("%s: chdir gcwd/sub failed\n", s);
+    exit(1);
+  }
+  if(getcwd(buf, sizeof(buf)) != 9 || strcmp(buf, "/gcwd/sub") != 0){
+    printf("%s: getcwd returned %s, expected /gcwd/sub\n", s, buf);
+    exit(1);
+  }
+  if(getcwd(buf, 9) >= 0 || errno != ERANGE){
+    printf("%s: getcwd with a short buffer: errno %d, expected ERANGE\n", s, errno);
+    exit(1);
+  }
+  chdir("..");
+  if(getcwd(buf, sizeof(buf)) < 0 || strcmp(buf, "/gcwd") != 0){
+    printf("%s: getcwd returned %s, expected /gcwd\n", s, buf);
+    exit(1);
+  }
+
+  // the current directory has no path once it is removed.
+  chdir("sub");
+  if(unlink("/gcwd/sub") < 0){
+    printf("%s: unlink /gcwd/sub failed\n", s);
+    exit(1);
+  }
+  if(getcwd(buf, sizeof(buf)) >= 0 || errno != ENOENT){
+    printf("%s: getcwd in a removed directory: errno %d, expected ENOENT\n", s, errno);
+    exit(1);
+  }
+
+  chdir("/");
+  if(getcwd(buf, sizeof(buf)) != 1 || strcmp(buf, "/") != 0){
+    printf("%s: getcwd returned %s, expected /\n", s, buf);
+    exit(1);
+  }
+  unlink("gcwd");
+}
+
+void
+chroottest(char *s)
+{
+  char buf[MAXPATH];
+  int fd, pid, xstatus;
+
+  if(mkdir("croot") < 0 || mkdir("croot/d") < 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  fd = open("croot/f", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create croot/f failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(chroot("croot/f") >= 0 || errno != ENOTDIR){
+      printf("%s: chroot to a file: errno %d, expected ENOTDIR\n", s, errno);
+      exit(1);
+    }
+    if(chroot("croot") < 0){
+      printf("%s: chroot failed\n", s);
+      exit(1);
+    }
+    fd = open("/f", O_RDONLY);
+    if(fd < 0){
+      printf("%s: open /f in the new root failed\n", s);
+      exit(1);
+    }
+    close(fd);
+    if(open("/croot", O_RDONLY) >= 0 || open("/../../croot/f", O_RDONLY) >= 0){
+      printf("%s: escaped the new root through an absolute path\n", s);
+      exit(1);
+    }
+    chdir("/d");
+    if(getcwd(buf, sizeof(buf)) < 0 || strcmp(buf, "/d") != 0){
+      printf("%s: getcwd returned %s, expected /d\n", s, buf);
+      exit(1);
+    }
+    chdir("../../..");
+    if(getcwd(buf, sizeof(buf)) < 0 || strcmp(buf, "/") != 0){
+      printf("%s: escaped the new root through ..\n", s);
+      exit(1);
+    }
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(xstatus);
+
+  // chroot is per process.
+  fd = open("/croot/f", O_RDONLY);
+  if(fd < 0){
+    printf("%s: chroot of the child changed the parent's root\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("croot/f");
+  unlink("croot/d");
+  unlink("croot");
+}
+
+// Names that were looked up and not found are remembered by the
+// kernel, and must be found once they are created.
+void
+negativetest(char *s)
+{
+  char name[16];
+  int fd, i;
+
+  if(mkdir("neg") < 0 || chdir("neg") < 0){
+    printf("%s: mkdir neg failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 2; i++){
+    if(open("f", O_RDONLY) >= 0 || errno != ENOENT){
+      printf("%s: open of a missing file: errno %d, expected ENOENT\n", s, errno);
+      exit(1);
+    }
+  }
+  fd = open("f", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create f failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if((fd = open("f", O_RDONLY)) < 0){
+    printf("%s: f not found after create\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  // Through link() and mkdir(), and after many other failed lookups.
+  for(i = 0; i < 100; i++){
+    name[0] = 'm';
+    name[1] = '0' + i / 10;
+    name[2] = '0' + i % 10;
+    name[3] = 0;
+    if(open(name, O_RDONLY) >= 0){
+      printf("%s: open of missing %s succeeded\n", s, name);
+      exit(1);
+    }
+  }
+  if(open("g", O_RDONLY) >= 0 || open("d", O_RDONLY) >= 0 ||
+     link("f", "g") < 0 || mkdir("d") < 0){
+    printf("%s: link or mkdir failed\n", s);
+    exit(1);
+  }
+  if((fd = open("g", O_RDONLY)) < 0){
+    printf("%s: g not found after link\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(chdir("d") < 0 || chdir("..") < 0){
+    printf("%s: d not found after mkdir\n", s);
+    exit(1);
+  }
+
+  if(unlink("f") < 0 || unlink("g") < 0 || unlink("d") < 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+  if(open("f", O_RDONLY) >= 0){
+    printf("%s: f found after unlink\n", s);
+    exit(1);
+  }
+  if(chdir("..") < 0 || unlink("neg") < 0){
+    printf("%s: unlink neg failed\n", s);
+    exit(1);
+  }
+}
+
+// a directory shrinks back when the files at its end go, and reuses the
+// entries of files that went, so files coming and going don't grow it.
+void
+dirsizetest(char *s)
+{
+  char name[16];
+  struct stat st;
+  uint size, grown;
+  int fd, i;
+
+  if(mkdir("dirsz") < 0 || stat("dirsz", &st) < 0){
+    printf("%s: mkdir dirsz failed\n", s);
+    exit(1);
+  }
+  size = st.size;
+  strcpy(name, "dirsz/f00");
+  for(i = 0; i < 100; i++){
+    name[7] = '0' + i / 10;
+    name[8] = '0' + i % 10;
+    if((fd = open(name, O_CREATE|O_RDWR)) < 0){
+      printf("%s: create %s failed\n", s, name);
+      exit(1);
+    }
+    close(fd);
+  }
+  if(stat("dirsz", &st) < 0 || st.size <= size){
+    printf("%s: dirsz did not grow\n", s);
+    exit(1);
+  }
+  grown = st.size;
+  // Removing the first file leaves a hole, which the next file fills.
+  if(unlink("dirsz/f00") < 0 || (fd = open("dirsz/new", O_CREATE|O_RDWR)) < 0){
+    printf("%s: unlink or create failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(stat("dirsz", &st) < 0 || st.size != grown){
+    printf("%s: dirsz has size %d, expected %d\n", s, st.size, grown);
+    exit(1);
+  }
+  unlink("dirsz/new");
+  for(i = 1; i < 100; i++){
+    name[7] = '0' + i / 10;
+    name[8] = '0' + i % 10;
+    if(unlink(name) < 0){
+      printf("%s: unlink %s failed\n", s, name);
+      exit(1);
+    }
+  }
+  if(stat("dirsz", &st) < 0 || st.size != size){
+    printf("%s: dirsz has size %d after unlinks, expected %d\n", s, st.size, size);
+    exit(1);
+  }
+
+  for(i = 0; i < 50; i++){
+    if((fd = open("dirsz/tmp", O_CREATE|O_RDWR)) < 0 || unlink("dirsz/tmp") < 0){
+      printf("%s: create or unlink tmp failed\n", s);
+      exit(1);
+    }
+    close(fd);
+  }
+  if(stat("dirsz", &st) < 0 || st.size != size){
+    printf("%s: dirsz has size %d after churn, expected %d\n", s, st.size, size);
+    exit(1);
+  }
+  if(unlink("dirsz") < 0){
+    printf("%s: unlink dirsz failed\n", s);
+    exit(1);
+  }
+}
+
+// fadvise() only changes how much is read ahead and what stays cached,
+// never what read() returns.
+void
+fadvisetest(char *s)
+{
+  static char buf[BSIZE * 20];
+  int advice[] = { POSIX_FADV_SEQUENTIAL, POSIX_FADV_DONTNEED, POSIX_FADV_RANDOM, POSIX_FADV_NORMAL };
+  int fd, fds[2], i, j, n;
+
+  fd = open("fadvise", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < sizeof(buf); i++)
+    buf[i] = i / BSIZE + i;
+  if(write(fd, buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  for(j = 0; j < sizeof(advice) / sizeof(advice[0]); j++){
+    fd = open("fadvise", O_RDONLY);
+    if(fd < 0 || fadvise(fd, 0, 0, advice[j]) != 0){
+      printf("%s: fadvise %d failed\n", s, advice[j]);
+      exit(1);
+    }
+    memset(buf, 0, sizeof(buf));
+    for(n = 0; n < sizeof(buf); n += i){
+      i = read(fd, buf + n, 1000);
+      if(i <= 0){
+        printf("%s: read failed after fadvise %d\n", s, advice[j]);
+        exit(1);
+      }
+    }
+    for(i = 0; i < sizeof(buf); i++){
+      if(buf[i] != (char)(i / BSIZE + i)){
+        printf("%s: wrong byte %d after fadvise %d\n", s, i, advice[j]);
+        exit(1);
+      }
+    }
+    close(fd);
+  }
+
+  fd = open("fadvise", O_RDONLY);
+  if(fadvise(fd, 0, 0, 3) >= 0 || errno != EINVAL){
+    printf("%s: fadvise with unknown advice: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+  close(fd);
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  if(fadvise(fds[0], 0, 0, POSIX_FADV_NORMAL) >= 0 || errno != ESPIPE){
+    printf("%s: fadvise on a pipe: errno %d, expected ESPIPE\n", s, errno);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+  unlink("fadvise");
+}
+
+// sendfile() moves a file into a pipe, from the file offset or from a
+// given offset, which it advances instead.
+void
+sendfiletest(char *s)
+{
+  static char buf[3000];
+  int fd, fds[2], i, n, pid, xstatus;
+  uint off;
+
+  fd = open("sendfile", O_CREATE|O_RDWR);
+  for(i = 0; i < sizeof(buf); i++)
+    buf[i] = i % 251;
+  if(fd < 0 || write(fd, buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(fds[1]);
+    memset(buf, 0, sizeof(buf));
+    for(n = 0; n < sizeof(buf); n += i)
+      if((i = read(fds[0], buf + n, sizeof(buf) - n)) <= 0)
+        exit(1);
+    for(i = 0; i < sizeof(buf); i++)
+      if(buf[i] != (char)(i % 251))
+        exit(2);
+    if(read(fds[0], buf, 1) != 0)
+      exit(3);
+    exit(0);
+  }
+  close(fds[0]);
+  fd = open("sendfile", O_RDONLY);
+  // More than the file holds: it stops at the end.
+  if((n = sendfile(fds[1], fd, 0, 10000)) != sizeof(buf)){
+    printf("%s: sendfile sent %d\n", s, n);
+    exit(1);
+  }
+  close(fds[1]);
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: reader failed check %d\n", s, xstatus);
+    exit(1);
+  }
+  if(read(fd, buf, 1) != 0){
+    printf("%s: file offset not at the end\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  fd = open("sendfile", O_RDWR);
+  off = 1000;
+  if(sendfile(fds[1], fd, &off, 100) != 100 || off != 1100){
+    printf("%s: sendfile from an offset failed\n", s);
+    exit(1);
+  }
+  if(read(fds[0], buf, 100) != 100 || buf[0] != (char)(1000 % 251) || buf[99] != (char)(1099 % 251)){
+    printf("%s: wrong data from an offset\n", s);
+    exit(1);
+  }
+  if(read(fd, buf, 1) != 1 || buf[0] != 0){
+    printf("%s: sendfile from an offset moved the file offset\n", s);
+    exit(1);
+  }
+  if(sendfile(fd, fd, 0, 1) >= 0 || errno != EINVAL){
+    printf("%s: sendfile to a file: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+  if(sendfile(fds[1], fds[0], 0, 1) >= 0 || errno != EINVAL){
+    printf("%s: sendfile from a pipe: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+  close(fd);
+  close(fds[0]);
+  close(fds[1]);
+  unlink("sendfile");
+}
+
+// splice() moves data from a file into a pipe, from a pipe into a pipe,
+// whose whole pages change hands, and from a pipe into a file.
+void
+splicetest(char *s)
+{
+  static char buf[PGSIZE];
+  int fd, p[2], q[2], i, n;
+  uint off;
+
+  for(i = 0; i < sizeof(buf); i++)
+    buf[i] = i % 251;
+  fd = open("splice", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, buf, 3000) != 3000){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  if(pipe(p) < 0 || pipe(q) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+
+  off = 1000;
+  if((n = splice(fd, &off, p[1], 0, 100)) != 100 || off != 1100){
+    printf("%s: splice from a file moved %d\n", s, n);
+    exit(1);
+  }
+  if(read(p[0], buf, 100) != 100 || buf[0] != (char)(1000 % 251) || buf[99] != (char)(1099 % 251)){
+    printf("%s: wrong data from a file\n", s);
+    exit(1);
+  }
+
+  for(i = 0; i < sizeof(buf); i++)
+    buf[i] = i % 249;
+  if(write(p[1], buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: pipe write failed\n", s);
+    exit(1);
+  }
+  if((n = splice(p[0], 0, q[1], 0, 2 * sizeof(buf))) != sizeof(buf)){
+    printf("%s: splice between pipes moved %d\n", s, n);
+    exit(1);
+  }
+  memset(buf, 0, sizeof(buf));
+  if(read(q[0], buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: pipe read failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < sizeof(buf); i++){
+    if(buf[i] != (char)(i % 249)){
+      printf("%s: wrong data between pipes at %d\n", s, i);
+      exit(1);
+    }
+  }
+
+  if(write(p[1], "spliced", 7) != 7){
+    printf("%s: pipe write failed\n", s);
+    exit(1);
+  }
+  off = 2000;
+  if((n = splice(p[0], 0, fd, &off, 1000)) != 7 || off != 2007){
+    printf("%s: splice to a file moved %d\n", s, n);
+    exit(1);
+  }
+  close(fd);
+  fd = open("splice", O_RDONLY);
+  if(read(fd, buf, 3000) != 3000 || memcmp(buf + 2000, "spliced", 7) != 0
+     || buf[2007] != (char)(2007 % 251)){
+    printf("%s: wrong data in the file\n", s);
+    exit(1);
+  }
+
+  if(splice(fd, 0, fd, 0, 1) >= 0 || errno != EINVAL){
+    printf("%s: splice between files: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+  if(splice(p[0], &off, q[1], 0, 1) >= 0 || errno != ESPIPE){
+    printf("%s: splice with a pipe offset: errno %d, expected ESPIPE\n", s, errno);
+    exit(1);
+  }
+  if(splice(p[0], 0, p[1], 0, 1) >= 0 || errno != EINVAL){
+    printf("%s: splice into the same pipe: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+  close(p[1]);
+  if(splice(p[0], 0, q[1], 0, 1) != 0){
+    printf("%s: splice from a closed pipe did not return 0\n", s);
+    exit(1);
+  }
+  close(fd);
+  close(p[0]);
+  close(q[0]);
+  close(q[1]);
+  unlink("splice");
+}
+
+// ioring_enter() runs the reads, writes and fsyncs queued in the ring
+// page of ioring_setup(), and posts their results in order.
+struct ioring_sqe*
+ioring_sqe(struct ioring_header *h)
+{
+  struct ioring_sqe *sqe;
+
+  sqe = (struct ioring_sqe*)((char*)h + IORING_SQ_OFF) + h->sq_tail % IORING_SQ_ENTRIES;
+  memset(sqe, 0, sizeof(*sqe));
+  sqe->user_data = h->sq_tail;
+  h->sq_tail++;
+  return sqe;
+}
+
+struct ioring_cqe*
+ioring_cqe(struct ioring_header *h)
+{
+  if(h->cq_head == h->cq_tail)
+    return 0;
+  return (struct ioring_cqe*)((char*)h + IORING_CQ_OFF) + h->cq_head++ % IORING_CQ_ENTRIES;
+}
+
+void
+ioringtest(char *s)
+{
+  static char buf[3000], buf2[3000];
+  struct ioring_header *h;
+  struct ioring_sqe *sqe;
+  struct ioring_cqe *cqe;
+  int fd, i, n, pid, xstatus;
+
+  h = ioring_setup();
+  if((uint64)h != IORING || h->sq_head != 0 || h->cq_tail != 0){
+    printf("%s: ioring_setup failed\n", s);
+    exit(1);
+  }
+  if((uint64)ioring_setup() != -1 || errno != EEXIST){
+    printf("%s: second ioring_setup: errno %d, expected EEXIST\n", s, errno);
+    exit(1);
+  }
+
+  for(i = 0; i < sizeof(buf); i++)
+    buf[i] = i % 251;
+  fd = open("ioring", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+  sqe = ioring_sqe(h);
+  sqe->opcode = IORING_OP_WRITE;
+  sqe->fd = fd;
+  sqe->addr = (uint64)buf;
+  sqe->len = sizeof(buf);
+  ioring_sqe(h)->opcode = IORING_OP_NOP;
+  sqe = ioring_sqe(h);
+  sqe->opcode = IORING_OP_FSYNC;
+  sqe->fd = fd;
+  sqe = ioring_sqe(h);
+  sqe->opcode = IORING_OP_READ;
+  sqe->fd = 1000;
+  sqe->len = 1;
+  if((n = ioring_enter(10)) != 4){
+    printf("%s: ioring_enter ran %d\n", s, n);
+    exit(1);
+  }
+  if(h->sq_head != 4 || h->cq_tail != 4){
+    printf("%s: ring indices %d %d\n", s, h->sq_head, h->cq_tail);
+    exit(1);
+  }
+  for(i = 0; (cqe = ioring_cqe(h)) != 0; i++){
+    n = i == 0 ? sizeof(buf) : i == 3 ? -EBADF : 0;
+    if(cqe->user_data != i || cqe->res != n){
+      printf("%s: completion %d: %d, expected %d\n", s, i, cqe->res, n);
+      exit(1);
+    }
+  }
+  close(fd);
+
+  // The first read goes to the unused end of the ring page itself.
+  fd = open("ioring", O_RDONLY);
+  sqe = ioring_sqe(h);
+  sqe->opcode = IORING_OP_READ;
+  sqe->fd = fd;
+  sqe->addr = IORING + PGSIZE - 16;
+  sqe->len = 16;
+  sqe = ioring_sqe(h);
+  sqe->opcode = IORING_OP_READ;
+  sqe->fd = fd;
+  sqe->addr = (uint64)buf2 + 16;
+  sqe->len = sizeof(buf2) - 16;
+  if(ioring_enter(2) != 2 || (cqe = ioring_cqe(h)) == 0 || cqe->res != 16
+     || (cqe = ioring_cqe(h)) == 0 || cqe->res != sizeof(buf2) - 16){
+    printf("%s: reads failed\n", s);
+    exit(1);
+  }
+  memmove(buf2, (char*)IORING + PGSIZE - 16, 16);
+  if(memcmp(buf, buf2, sizeof(buf)) != 0){
+    printf("%s: read wrong data\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("ioring");
+
+  // A full completion ring stops ioring_enter().
+  for(i = 0; i < IORING_CQ_ENTRIES + 1; i++)
+    ioring_sqe(h)->opcode = IORING_OP_NOP;
+  if((n = ioring_enter(IORING_CQ_ENTRIES + 1)) != IORING_CQ_ENTRIES){
+    printf("%s: ioring_enter ran %d with a full ring\n", s, n);
+    exit(1);
+  }
+  if(ioring_enter(1) >= 0 || errno != EBUSY){
+    printf("%s: full ring: errno %d, expected EBUSY\n", s, errno);
+    exit(1);
+  }
+  while(ioring_cqe(h) != 0)
+    ;
+  if(ioring_enter(1) != 1 || ioring_cqe(h) == 0){
+    printf("%s: ioring_enter failed after emptying the ring\n", s);
+    exit(1);
+  }
+
+  // The child of fork() has no ring.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(ioring_enter(1) >= 0 || errno != EINVAL)
+      exit(1);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: the child has a ring\n", s);
+    exit(1);
+  }
+}
+
+// a process can have many more than NOFILE descriptors, which fork()
+// copies and close-on-exec flags mark like the first ones.
+// quotactl() limits the blocks and inodes that the files of a user take.
+void
+quotatest(char *s)
+{
+  struct dqblk q;
+  char buf[BSIZE];
+  int i, fd, pid, xstatus;
+
+  if(mkdir("qdir") < 0 || chown("qdir", 11, 11) < 0){
+    printf("%s: mkdir or chown failed\n", s);
+    exit(1);
+  }
+  if(quotactl(Q_GETQUOTA, 11, &q) < 0 || q.curblocks != 1 || q.curinodes != 1){
+    printf("%s: uid 11 uses %d blocks and %d inodes, expected 1 and 1\n",
+           s, q.curblocks, q.curinodes);
+    exit(1);
+  }
+  memset(&q, 0, sizeof(q));
+  q.bhardlimit = 4;
+  q.ihardlimit = 3;
+  if(quotactl(Q_SETQUOTA, 11, &q) < 0){
+    printf("%s: Q_SETQUOTA failed\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(11) < 0 || chdir("qdir") < 0)
+      exit(1);
+    if(quotactl(Q_SETQUOTA, 11, &q) >= 0 || errno != EPERM)
+      exit(2);
+    if(quotactl(Q_GETQUOTA, 0, &q) >= 0 || errno != EPERM)
+      exit(3);
+    // qdir, f1 and f2 take the 3 inodes.
+    if((fd = open("f1", O_CREATE|O_RDWR)) < 0 || open("f2", O_CREATE|O_RDWR) < 0)
+      exit(4);
+    if(open("f3", O_CREATE|O_RDWR) >= 0 || errno != EDQUOT)
+      exit(5);
+    // qdir and f1 take the 4 blocks.
+    memset(buf, 'q', sizeof(buf));
+    for(i = 0; i < 3; i++)
+      if(write(fd, buf, sizeof(buf)) != sizeof(buf))
+        exit(6);
+    if(write(fd, buf, 1) >= 0 || errno != EDQUOT)
+      exit(7);
+    close(fd);
+    if(unlink("f1") < 0)
+      exit(8);
+    if(quotactl(Q_GETQUOTA, 11, &q) < 0 || q.curblocks != 1 || q.curinodes != 2)
+      exit(9);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: quota of uid 11: step %d\n", s, xstatus);
+    exit(1);
+  }
+
+  memset(&q, 0, sizeof(q));
+  if(unlink("qdir/f2") < 0 || unlink("qdir") < 0 || quotactl(Q_SETQUOTA, 11, &q) < 0){
+    printf("%s: cleanup failed\n", s);
+    exit(1);
+  }
+  if(quotactl(Q_GETQUOTA, 11, &q) < 0 || q.curblocks != 0 || q.curinodes != 0){
+    printf("%s: uid 11 still uses %d blocks and %d inodes\n", s, q.curblocks, q.curinodes);
+    exit(1);
+  }
+}
+
+void
+manyfdstest(char *s)
+{
+  int fds[2], fd, last, i, pid, xstatus;
+  char c;
+
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  last = fds[1];
+  for(i = 0; i < 1000; i++){
+    if((fd = dup(fds[1])) < 0)
+      break;
+    last = fd;
+  }
+  if(i == 1000 || errno != EMFILE){
+    printf("%s: dup did not run out with EMFILE after %d\n", s, i);
+    exit(1);
+  }
+  if(last < 4 * NOFILE){
+    printf("%s: only %d descriptors\n", s, last + 1);
+    exit(1);
+  }
+
+  if(fcntl(last, F_SETFD, FD_CLOEXEC) < 0 || fcntl(last, F_GETFD, 0) != FD_CLOEXEC){
+    printf("%s: close-on-exec of descriptor %d not set\n", s, last);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(fcntl(last, F_GETFD, 0) != FD_CLOEXEC || write(last, "x", 1) != 1)
+      exit(1);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0 || read(fds[0], &c, 1) != 1 || c != 'x'){
+    printf("%s: child could not write to descriptor %d\n", s, last);
+    exit(1);
+  }
+
+  for(fd = fds[1] + 1; fd <= last; fd++){
+    if(close(fd) < 0){
+      printf("%s: close %d failed\n", s, fd);
+      exit(1);
+    }
+  }
+  // The lowest closed descriptor comes first again.
+  if((fd = dup(fds[1])) != fds[1] + 1){
+    printf("%s: dup returned %d, expected %d\n", s, fd, fds[1] + 1);
+    exit(1);
+  }
+  close(fd);
+  close(fds[0]);
+  close(fds[1]);
+}
+
+void
+umasktest(char *s)
+{
+  int pid, xstatus;
+
+  if(umask(077) != 022){
+    printf("%s: default umask is not 022\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(umask(01777) != 077){
+      printf("%s: umask was not inherited across fork\n", s);
+      exit(1);
+    }
+    if(umask(0) != 0777){
+      printf("%s: umask kept bits other than 0777\n", s);
+      exit(1);
+    }
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(xstatus);
+  if(umask(022) != 077){
+    printf("%s: umask of the child changed the parent's\n", s);
+    exit(1);
+  }
+}
+
+void
+clocktest(char *s)
+{
+  struct timespec t0, t1, rt;
+  struct timeval tv;
+  uint64 elapsed;
+
+  if(clock_gettime(CLOCK_MONOTONIC, &t0) < 0){
+    printf("%s: clock_gettime(CLOCK_MONOTONIC) failed\n", s);
+    exit(1);
+  }
+  sleep(2);
+  if(clock_gettime(CLOCK_MONOTONIC, &t1) < 0 || t1.tv_nsec >= 1000000000){
+    printf("%s: bad CLOCK_MONOTONIC reading\n", s);
+    exit(1);
+  }
+  // two ticks are about 200ms.
+  elapsed = (t1.tv_sec - t0.tv_sec) * 1000000000 + t1.tv_nsec - t0.tv_nsec;
+  if(elapsed < 100000000 || elapsed > 10000000000ULL){
+    printf("%s: sleep(2) took %d ms of CLOCK_MONOTONIC\n", s, (int)(elapsed / 1000000));
+    exit(1);
+  }
+
+  // the realtime clock is after 2020-01-01.
+  if(clock_gettime(CLOCK_REALTIME, &rt) < 0 || rt.tv_sec < 1577836800){
+    printf("%s: bad CLOCK_REALTIME reading\n", s);
+    exit(1);
+  }
+  if(gettimeofday(&tv) < 0 || tv.tv_sec < rt.tv_sec || tv.tv_usec >= 1000000){
+    printf("%s: bad gettimeofday reading\n", s);
+    exit(1);
+  }
+  if(clock_gettime(7, &rt) >= 0 || errno != EINVAL){
+    printf("%s: clock_gettime with a bad clock: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+}
+
+static uint64
+tsns(struct timespec *ts)
+{
+  return ts->tv_sec * 1000000000 + ts->tv_nsec;
+}
+
+// The shared page gives the clocks, the tick count and the CPU
+// without a system call, in agreement with the system calls, and
+// neither the process nor the kernel on its behalf may write it.
+void
+vdsotest(char *s)
+{
+  static int clocks[] = { CLOCK_MONOTONIC, CLOCK_REALTIME };
+  struct timespec t0, t1, t2;
+  int i, t, fds[2], pid, xstatus;
+
+  for(i = 0; i < 2; i++){
+    clock_gettime(clocks[i], &t0);
+    if(vdso_clock_gettime(clocks[i], &t1) < 0){
+      printf("%s: vdso_clock_gettime(%d) failed\n", s, clocks[i]);
+      exit(1);
+    }
+    clock_gettime(clocks[i], &t2);
+    if(tsns(&t1) < tsns(&t0) || tsns(&t1) > tsns(&t2) || t1.tv_nsec >= 1000000000){
+      printf("%s: clock %d read %d.%d between %d.%d and %d.%d\n", s, clocks[i],
+             (int)t1.tv_sec, (int)t1.tv_nsec, (int)t0.tv_sec, (int)t0.tv_nsec,
+             (int)t2.tv_sec, (int)t2.tv_nsec);
+      exit(1);
+    }
+  }
+  if(vdso_clock_gettime(7, &t0) >= 0 || errno != EINVAL){
+    printf("%s: vdso_clock_gettime with a bad clock: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+
+  t = uptime();
+  if(vdso_uptime() < t){
+    printf("%s: shared page has %d ticks, uptime() %d\n", s, vdso_uptime(), t);
+    exit(1);
+  }
+  if(getcpu() < 0 || getcpu() >= NCPU){
+    printf("%s: bad cpu %d\n", s, getcpu());
+    exit(1);
+  }
+
+  if(pipe(fds) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  write(fds[1], "x", 1);
+  if(read(fds[0], (void*)VDSO_ADDR, 1) >= 0 || errno != EFAULT){
+    printf("%s: read into the shared page: errno %d, expected EFAULT\n", s, errno);
+    exit(1);
+  }
+  close(fds[0]);
+  close(fds[1]);
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    *(volatile int*)VDSO_ADDR = 1;
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != -1){
+    printf("%s: wrote the shared page\n", s);
+    exit(1);
+  }
+}
+
+void
+settimetest(char *s)
+{
+  struct timespec m0, m1, rt, ts, d, old;
+  int pid, xstatus;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0)
+      exit(1);
+    clock_gettime(CLOCK_REALTIME, &ts);
+    if(clock_settime(CLOCK_REALTIME, &ts) >= 0 || errno != EPERM)
+      exit(2);
+    d.tv_sec = 0;
+    d.tv_nsec = 1000;
+    if(adjtime(&d, 0) >= 0 || errno != EPERM)
+      exit(3);
+    if(adjtime(0, &old) < 0)
+      exit(4);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: unprivileged clock change: step %d\n", s, xstatus);
+    exit(1);
+  }
+
+  // setting the realtime clock leaves the monotonic clock alone.
+  clock_gettime(CLOCK_MONOTONIC, &m0);
+  clock_gettime(CLOCK_REALTIME, &rt);
+  ts = rt;
+  ts.tv_sec += 1000;
+  if(clock_settime(CLOCK_REALTIME, &ts) < 0){
+    printf("%s: clock_settime failed\n", s);
+    exit(1);
+  }
+  clock_gettime(CLOCK_REALTIME, &ts);
+  clock_gettime(CLOCK_MONOTONIC, &m1);
+  ts.tv_sec -= 1000;
+  if(clock_settime(CLOCK_REALTIME, &ts) < 0){
+    printf("%s: clock_settime back failed\n", s);
+    exit(1);
+  }
+  if(ts.tv_sec < rt.tv_sec || ts.tv_sec > rt.tv_sec + 10 || m1.tv_sec > m0.tv_sec + 10){
+    printf("%s: clock_settime moved the clocks wrongly\n", s);
+    exit(1);
+  }
+  if(clock_settime(CLOCK_MONOTONIC, &ts) >= 0 || errno != EINVAL){
+    printf("%s: clock_settime(CLOCK_MONOTONIC): errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+  ts.tv_nsec = 1000000000;
+  if(clock_settime(CLOCK_REALTIME, &ts) >= 0 || errno != EINVAL){
+    printf("%s: clock_settime with bad nanoseconds: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+
+  // a slew is applied gradually, and a new one replaces it.
+  d.tv_sec = 1;
+  d.tv_nsec = 0;
+  if(adjtime(&d, 0) < 0){
+    printf("%s: adjtime failed\n", s);
+    exit(1);
+  }
+  d.tv_sec = -1;
+  d.tv_nsec = 999000000;
+  if(adjtime(&d, &old) < 0 || old.tv_sec != 0 || old.tv_nsec == 0){
+    printf("%s: adjtime did not return the pending slew\n", s);
+    exit(1);
+  }
+  if(adjtime(0, &old) < 0 || old.tv_sec != -1 || old.tv_nsec < 999000000){
+    printf("%s: adjtime lost a negative slew\n", s);
+    exit(1);
+  }
+  d.tv_sec = 0;
+  d.tv_nsec = 0;
+  if(adjtime(&d, 0) < 0 || adjtime(0, &old) < 0 || old.tv_sec != 0 || old.tv_nsec != 0){
+    printf("%s: adjtime did not cancel the slew\n", s);
+    exit(1);
+  }
+}
+
+void
+getrandomtest(char *s)
+{
+  char a[600], b[600];
+  int fd, i, same;
+
+  memset(a, 0, sizeof(a));
+  memset(b, 0, sizeof(b));
+  if(getrandom(a, sizeof(a), 0) != sizeof(a) || getrandom(b, sizeof(b), GRND_NONBLOCK) != sizeof(b)){
+    printf("%s: getrandom failed\n", s);
+    exit(1);
+  }
+  same = 0;
+  for(i = 0; i < sizeof(a); i++)
+    if(a[i] == b[i])
+      same++;
+  // about 600/256 bytes match by chance.
+  if(same > 20){
+    printf("%s: two getrandom calls returned %d equal bytes\n", s, same);
+    exit(1);
+  }
+  if(getrandom(a, 8, 0x100) >= 0 || errno != EINVAL){
+    printf("%s: getrandom with a bad flag: errno %d, expected EINVAL\n", s, errno);
+    exit(1);
+  }
+
+  fd = open("/urandom", O_RDWR);
+  if(fd < 0){
+    printf("%s: open /urandom failed\n", s);
+    exit(1);
+  }
+  if(read(fd, a, sizeof(a)) != sizeof(a)){
+    printf("%s: read from /urandom failed\n", s);
+    exit(1);
+  }
+  if(memcmp(a, b, sizeof(a)) == 0){
+    printf("%s: /urandom repeated getrandom output\n", s);
+    exit(1);
+  }
+  if(write(fd, "entropy", 7) != 7){
+    printf("%s: write to /urandom failed\n", s);
+    exit(1);
+  }
+  close(fd);
+}
+
+// trace() selects system calls to log, and the mask is inherited by
+// children. traced calls must behave as usual, even when they fail.
+void
+tracetest(char *s)
+{
+  int pid, xstatus;
+
+  if(trace((1 << SYS_getpid) | (1 << SYS_close)) != 0){
+    printf("%s: trace failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(getpid() <= 0)
+      exit(1);
+    if(close(-1) >= 0 || errno != EBADF)
+      exit(2);
+    exit(0);
+  }
+  if(getpid() == pid){
+    printf("%s: getpid returned the child's pid\n", s);
+    exit(1);
+  }
+  trace(0);
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: traced calls in child failed (%d)\n", s, xstatus);
+    exit(1);
+  }
+}
+
+volatile uint64 ptraceflag;
+
+// a parent attaches to a spinning child, reads its registers, writes
+// its memory, single-steps it, and lets it exit.
+void
+ptracetest(char *s)
+{
+  int pid, status, i;
+  struct user_regs regs;
+  uint64 word;
+
+  if(ptrace(PTRACE_CONT, getpid(), 0, 0) >= 0 || errno != ESRCH){
+    printf("%s: ptrace on a non-child: errno %d, expected ESRCH\n", s, errno);
+    exit(1);
+  }
+
+  ptraceflag = 0;
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    while(ptraceflag == 0)
+      ;
+    exit(ptraceflag == 42 ? 0 : 1);
+  }
+
+  if(ptrace(PTRACE_ATTACH, pid, 0, 0) != 0){
+    printf("%s: attach failed\n", s);
+    exit(1);
+  }
+  if(wait(&status) != pid || !WIFSTOPPED(status) || WSTOPREASON(status) != PTRACE_STOP_ATTACH){
+    printf("%s: child did not stop after attach (status %x)\n", s, status);
+    exit(1);
+  }
+  if(ptrace(PTRACE_GETREGS, pid, 0, (uint64)&regs) != 0 || regs.pc == 0){
+    printf("%s: getregs failed\n", s);
+    exit(1);
+  }
+  if(ptrace(PTRACE_PEEKDATA, pid, (void*)&ptraceflag, (uint64)&word) != 0 || word != 0){
+    printf("%s: peek failed\n", s);
+    exit(1);
+  }
+
+  for(i = 0; i < 4; i++){
+    if(ptrace(PTRACE_SINGLESTEP, pid, 0, 0) != 0){
+      printf("%s: singlestep failed\n", s);
+      exit(1);
+    }
+    if(wait(&status) != pid || !WIFSTOPPED(status) || WSTOPREASON(status) != PTRACE_STOP_STEP){
+      printf("%s: child did not stop after a step (status %x)\n", s, status);
+      exit(1);
+    }
+  }
+
+  if(ptrace(PTRACE_POKEDATA, pid, (void*)&ptraceflag, 42) != 0){
+    printf("%s: poke failed\n", s);
+    exit(1);
+  }
+  if(ptrace(PTRACE_CONT, pid, 0, 0) != 0){
+    printf("%s: cont failed\n", s);
+    exit(1);
+  }
+  if(ptrace(PTRACE_CONT, pid, 0, 0) >= 0 || errno != ESRCH){
+    printf("%s: cont of a running child succeeded\n", s);
+    exit(1);
+  }
+  if(wait(&status) != pid || status != 0){
+    printf("%s: child exited with status %x, expected 0\n", s, status);
+    exit(1);
+  }
+}
+
+// files get the creator's IDs and a mode masked by umask, only their
+// owner may chmod them, and a process that gave up the superuser's IDs
+// is held to the permission bits and cannot get them back.
+void
+uidtest(char *s)
+{
+  int fd, pid, xstatus;
+  struct stat st;
+  char *args[] = { "echo", "hi", 0 };
+
+  if(getuid() != 0 || geteuid() != 0 || getgid() != 0 || getegid() != 0){
+    printf("%s: usertests does not run as the superuser\n", s);
+    exit(1);
+  }
+  unlink("uidfile");
+  unlink("uiddir");
+  fd = open("uidfile", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create uidfile failed\n", s);
+    exit(1);
+  }
+  if(fstat(fd, &st) < 0 || st.mode != 0644 || st.uid != 0 || st.gid != 0){
+    printf("%s: new file has mode 0x%x uid %d gid %d\n", s, st.mode, st.uid, st.gid);
+    exit(1);
+  }
+  close(fd);
+  if(mkdir("uiddir") < 0 || chmod("uiddir", 0777) < 0){
+    printf("%s: mkdir uiddir failed\n", s);
+    exit(1);
+  }
+  if(chmod("uidfile", 0600) < 0 || stat("uidfile", &st) < 0 || st.mode != 0600){
+    printf("%s: chmod failed\n", s);
+    exit(1);
+  }
+  // Inodes keep IDs in 16 bits, so larger ones must not be cut down to another user's.
+  if(setuid(0x10000) >= 0 || errno != EINVAL || setgid(0x10000) >= 0 || errno != EINVAL ||
+     chown("uidfile", 0x1000a, 0) >= 0 || errno != EINVAL || getuid() != 0 || getgid() != 0){
+    printf("%s: an ID above 65535 was not refused\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setgid(20) < 0 || setuid(10) < 0)
+      exit(1);
+    if(getuid() != 10 || geteuid() != 10 || getgid() != 20 || getegid() != 20)
+      exit(2);
+    if(setuid(0) >= 0 || errno != EPERM)
+      exit(3);
+    if(open("uidfile", O_RDONLY) >= 0 || errno != EACCES)
+      exit(4);
+    if(chmod("uidfile", 0666) >= 0 || errno != EPERM)
+      exit(5);
+    if(unlink("uidfile") >= 0 || errno != EACCES)
+      exit(6);
+    if(mknod("uiddir/dev", 1, 0) >= 0 || errno != EPERM)
+      exit(7);
+    if(chroot("uiddir") >= 0 || errno != EPERM)
+      exit(8);
+    if(chown("uiddir", 10, 20) >= 0 || errno != EPERM)
+      exit(9);
+    fd = open("uiddir/mine", O_CREATE|O_RDWR);
+    if(fd < 0 || fstat(fd, &st) < 0 || st.uid != 10 || st.gid != 20)
+      exit(10);
+    close(fd);
+    if(chmod("uiddir/mine", 0) < 0 || open("uiddir/mine", O_RDONLY) >= 0)
+      exit(11);
+    if(exec("uiddir/mine", args) >= 0 || errno != EACCES)
+      exit(12);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: unprivileged child failed check %d\n", s, xstatus);
+    exit(1);
+  }
+
+  if(stat("uiddir/mine", &st) < 0 || st.uid != 10 || chown("uiddir/mine", 0, 0) < 0 ||
+     stat("uiddir/mine", &st) < 0 || st.uid != 0 || st.gid != 0){
+    printf("%s: chown failed\n", s);
+    exit(1);
+  }
+  if(unlink("uiddir/mine") < 0 || unlink("uiddir") < 0 || unlink("uidfile") < 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+}
+
+// the superuser has every capability and other users have none. a dropped
+// capability is gone for good and refuses what it allowed, and without
+// CAP_KILL a process cannot signal the processes of other users.
+void
+captest(char *s)
+{
+  uint64 all = CAP_CHOWN | CAP_DAC_OVERRIDE | CAP_FOWNER | CAP_KILL | CAP_SETGID |
+    CAP_SETUID | CAP_NET_BIND | CAP_SYS_PTRACE | CAP_SYS_ADMIN | CAP_SYS_BOOT |
+    CAP_SYS_RESOURCE | CAP_SYS_TIME | CAP_SYSLOG;
+  uint64 kept = all & ~(CAP_SYS_ADMIN | CAP_CHOWN);
+  int pid, victim, xstatus;
+
+  if(capget() != all){
+    printf("%s: the superuser has capabilities %p\n", s, capget());
+    exit(1);
+  }
+  if(capdrop(1UL << 63) >= 0 || errno != EINVAL){
+    printf("%s: dropped an unknown capability\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(capdrop(CAP_SYS_ADMIN | CAP_CHOWN) < 0 || capget() != kept)
+      exit(1);
+    if(mknod("capdev", 1, 0) >= 0 || errno != EPERM)
+      exit(2);
+    if(chown(".", 0, 0) >= 0 || errno != EPERM)
+      exit(3);
+    // Becoming the superuser again does not give them back.
+    if(setuid(0) < 0 || capget() != kept)
+      exit(4);
+    pid = fork();
+    if(pid == 0)
+      exit(capget() == kept ? 0 : 1);
+    wait(&xstatus);
+    if(xstatus != 0)
+      exit(5);
+    if(setuid(10) < 0 || capget() != 0)
+      exit(6);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: child that dropped capabilities failed check %d\n", s, xstatus);
+    exit(1);
+  }
+
+  victim = fork();
+  if(victim < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(victim == 0){
+    sleep(100);
+    exit(0);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    kill(victim);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0)
+      exit(1);
+    if(kill(victim) >= 0 || errno != EPERM)
+      exit(2);
+    if(sigsend(victim, SIGTERM) >= 0 || errno != EPERM)
+      exit(3);
+    exit(0);
+  }
+  wait(&xstatus);
+  kill(victim);
+  wait(0);
+  if(xstatus != 0){
+    printf("%s: unprivileged child failed check %d\n", s, xstatus);
+    exit(1);
+  }
+}
+
+// a device file gets its mode from its device, whatever the umask, and
+// only a device meant for everyone is open to other users until the
+// superuser grants more.
+void
+devpermtest(char *s)
+{
+  struct stat st;
+  int pid, xstatus, old;
+
+  old = umask(077);
+  // major 1 is the console; no device has major 9.
+  if(mknod("devcons", 1, 0) < 0 || mknod("devnone", 9, 0) < 0){
+    printf("%s: mknod failed\n", s);
+    exit(1);
+  }
+  umask(old);
+  if(stat("devcons", &st) < 0 || st.mode != 0666){
+    printf("%s: console device has mode 0x%x\n", s, st.mode);
+    exit(1);
+  }
+  if(stat("devnone", &st) < 0 || st.mode != 0600){
+    printf("%s: unknown device has mode 0x%x\n", s, st.mode);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0)
+      exit(1);
+    if(open("devnone", O_RDONLY) >= 0 || errno != EACCES)
+      exit(2);
+    if(open("devcons", O_RDWR) < 0)
+      exit(3);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: unprivileged child failed check %d\n", s, xstatus);
+    exit(1);
+  }
+
+  if(chmod("devnone", 0666) < 0){
+    printf("%s: chmod failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0 || open("devnone", O_RDONLY) < 0)
+      exit(1);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: granted device not opened\n", s);
+    exit(1);
+  }
+  unlink("devcons");
+  unlink("devnone");
+}
+
+// /dev/null, /dev/zero and /dev/full, made here with their majors
+// (4, 5 and 6), never block and read and write as on Linux.
+void
+memdevtest(char *s)
+{
+  int null, zero, full, i;
+  char buf[600];
+  struct pollfd pfd[3];
+
+  if(mknod("mnull", 4, 0) < 0 || mknod("mzero", 5, 0) < 0 || mknod("mfull", 6, 0) < 0){
+    printf("%s: mknod failed\n", s);
+    exit(1);
+  }
+  null = open("mnull", O_RDWR);
+  zero = open("mzero", O_RDWR);
+  full = open("mfull", O_RDWR);
+  if(null < 0 || zero < 0 || full < 0){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+
+  memset(buf, 'x', sizeof(buf));
+  if(write(null, buf, sizeof(buf)) != sizeof(buf) || read(null, buf, sizeof(buf)) != 0){
+    printf("%s: /dev/null is not empty\n", s);
+    exit(1);
+  }
+  if(write(zero, buf, sizeof(buf)) != sizeof(buf) || read(zero, buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: /dev/zero read or write failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < sizeof(buf); i++){
+    if(buf[i] != 0){
+      printf("%s: /dev/zero read 0x%x at %d\n", s, buf[i], i);
+      exit(1);
+    }
+  }
+  memset(buf, 'x', sizeof(buf));
+  if(read(full, buf, 10) != 10 || buf[0] != 0 || buf[9] != 0 || buf[10] != 'x'){
+    printf("%s: /dev/full read failed\n", s);
+    exit(1);
+  }
+  if(write(full, buf, 1) >= 0 || errno != ENOSPC){
+    printf("%s: /dev/full write did not fail with ENOSPC\n", s);
+    exit(1);
+  }
+
+  pfd[0].fd = null;
+  pfd[1].fd = zero;
+  pfd[2].fd = full;
+  for(i = 0; i < 3; i++)
+    pfd[i].events = POLLIN|POLLOUT;
+  if(poll(pfd, 3, 0) != 3){
+    printf("%s: poll found the devices not ready\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 3; i++){
+    if(pfd[i].revents != (POLLIN|POLLOUT)){
+      printf("%s: poll of device %d returned 0x%x\n", s, i, pfd[i].revents);
+      exit(1);
+    }
+  }
+
+  close(null);
+  close(zero);
+  close(full);
+  unlink("mnull");
+  unlink("mzero");
+  unlink("mfull");
+}
+
+// the audit log records setuid attempts and denied privileged calls,
+// and only the superuser may read it.
+// Makes a process fault, and returns whether the kernel logged it.
+int
+faultlogged(char *s)
+{
+  char buf[256], *msg = "usertrap";
+  uint64 off = 0, end;
+  int i, n, pid, xstatus, matched;
+
+  // skip to the end of the log.
+  while((n = dmesg(buf, sizeof(buf), &off)) > 0)
+    ;
+  if(n < 0){
+    printf("%s: dmesg failed\n", s);
+    exit(1);
+  }
+  end = off;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    *(volatile int *)0xffffffffffULL = 0;
+    exit(0);
+  }
+  wait(&xstatus);
+
+  // look for the message, which may be split between reads.
+  matched = 0;
+  while((n = dmesg(buf, sizeof(buf), &off)) > 0){
+    for(i = 0; i < n && msg[matched]; i++){
+      if(buf[i] == msg[matched])
+        matched++;
+      else
+        matched = buf[i] == msg[0];
+    }
+  }
+  return msg[matched] == 0 && off > end;
+}
+
+void
+dmesgtest(char *s)
+{
+  char buf[256];
+  uint64 off = 0, end;
+
+  while(dmesg(buf, sizeof(buf), &off) > 0)
+    ;
+  end = off;
+  if(dmesg(buf, sizeof(buf), &off) != 0 || off != end){
+    printf("%s: dmesg at the end moved\n", s);
+    exit(1);
+  }
+  // a process that faults makes the kernel print a message.
+  if(!faultlogged(s)){
+    printf("%s: the kernel log missed a message\n", s);
+    exit(1);
+  }
+  if(dmesg((char*)0xffffffffffULL, 1, &end) >= 0 || errno != EFAULT){
+    printf("%s: dmesg to a bad buffer: errno %d, expected EFAULT\n", s, errno);
+    exit(1);
+  }
+}
+
+void
+logfiltertest(char *s)
+{
+  int pid, xstatus;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0)
+      exit(1);
+    if(logfilter("error") >= 0 || errno != EPERM)
+      exit(2);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: unprivileged logfilter: step %d\n", s, xstatus);
+    exit(1);
+  }
+  if(logfilter("loud") >= 0 || errno != EINVAL || logfilter("trap=") >= 0 || errno != EINVAL){
+    printf("%s: logfilter accepted a bad level\n", s);
+    exit(1);
+  }
+
+  if(logfilter("info,trap=error") < 0){
+    printf("%s: logfilter failed\n", s);
+    exit(1);
+  }
+  if(faultlogged(s)){
+    logfilter("info");
+    printf("%s: the log filter kept a dropped message\n", s);
+    exit(1);
+  }
+  // the closest target wins.
+  if(logfilter("error,tra=info,trap=debug") < 0 || !faultlogged(s)){
+    logfilter("info");
+    printf("%s: the log filter dropped a kept message\n", s);
+    exit(1);
+  }
+  if(logfilter("info") < 0){
+    printf("%s: logfilter reset failed\n", s);
+    exit(1);
+  }
+}
+
+// finds the records of a getpid() that returned mypid, and of a page fault at
+// 0xffffffffff, in the trace buffers.
+// returns a mask of 1 for the system call entry, 2 for its exit, and 4 for the fault.
+int
+tracefound(char *s, int mypid)
+{
+  struct tracerec recs[8];
+  uint64 seq;
+  int cpu, i, n, found = 0;
+
+  for(cpu = 0; cpu < NCPU; cpu++){
+    seq = 0;
+    while((n = traceread(cpu, recs, 8, seq)) > 0){
+      for(i = 0; i < n; i++){
+        if(recs[i].cpu != cpu){
+          printf("%s: record of cpu %d in the buffer of cpu %d\n", s, recs[i].cpu, cpu);
+          exit(1);
+        }
+        if(recs[i].event == TRACE_SYSCALL_ENTER && recs[i].args[0] == SYS_getpid)
+          found |= 1;
+        if(recs[i].event == TRACE_SYSCALL_EXIT && recs[i].args[0] == SYS_getpid &&
+           recs[i].args[1] == mypid)
+          found |= 2;
+        if(recs[i].event == TRACE_PAGE_FAULT && recs[i].args[0] == 0xffffffffffULL)
+          found |= 4;
+        seq = recs[i].seq + 1;
+      }
+    }
+    if(n < 0){
+      printf("%s: traceread failed\n", s);
+      exit(1);
+    }
+  }
+  return found;
+}
+
+void
+tracepointtest(char *s)
+{
+  int pid, xstatus, mypid;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0)
+      exit(1);
+    if(traceon(1) >= 0 || errno != EPERM)
+      exit(2);
+    if(traceread(0, 0, 0, 0) >= 0 || errno != EPERM)
+      exit(3);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: unprivileged tracing: step %d\n", s, xstatus);
+    exit(1);
+  }
+  if(traceread(NCPU, 0, 0, 0) >= 0 || errno != EINVAL){
+    printf("%s: traceread of a cpu that does not exist\n", s);
+    exit(1);
+  }
+
+  if(traceon(1) < 0){
+    printf("%s: traceon failed\n", s);
+    exit(1);
+  }
+  mypid = getpid();
+  pid = fork();
+  if(pid < 0){
+    traceon(0);
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    *(volatile int *)0xffffffffffULL = 0;
+    exit(0);
+  }
+  wait(0);
+  traceon(0);
+  if(tracefound(s, mypid) != 7){
+    printf("%s: tracepoints missed events: found %d\n", s, tracefound(s, mypid));
+    exit(1);
+  }
+}
+
+// returns the number of pages that leakcheck() reports, or -1 if the
+// kernel has no leak detector.
+int
+leakedpages(char *s)
+{
+  struct leakrec recs[32];
+  int i, n, pages = 0;
+
+  n = leakcheck(recs, 32, 0);
+  if(n < 0 && errno == ENOSYS)
+    return -1;
+  if(n < 0){
+    printf("%s: leakcheck failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < n; i++)
+    pages += recs[i].pages;
+  return pages;
+}
+
+void
+leaktest(char *s)
+{
+  int pid, xstatus, before, after;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0)
+      exit(1);
+    if(leakcheck(0, 0, 0) >= 0 || errno != EPERM)
+      exit(2);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: unprivileged leakcheck: step %d\n", s, xstatus);
+    exit(1);
+  }
+
+  before = leakedpages(s);
+  if(before < 0)
+    return;
+  if(sbrk(4 * PGSIZE) == (char*)-1){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  after = leakedpages(s);
+  sbrk(-4 * PGSIZE);
+  if(after < before + 4){
+    printf("%s: leakcheck missed pages: %d before, %d after\n", s, before, after);
+    exit(1);
+  }
+  if(leakedpages(s) > after - 4){
+    printf("%s: leakcheck kept freed pages\n", s);
+    exit(1);
+  }
+}
+
+// make each page allocation of fork() fail in turn, and then
+// a write() and a read() of a file.
+void
+failinjecttest(char *s)
+{
+  int n, pid, left, fd, xstatus;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0)
+      exit(1);
+    if(failinject(FAIL_KALLOC, 1) >= 0 || errno != EPERM)
+      exit(2);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: unprivileged failinject: step %d\n", s, xstatus);
+    exit(1);
+  }
+
+  for(n = 1; n < 1000; n++){
+    failinject(FAIL_KALLOC, n);
+    pid = fork();
+    if(pid == 0)
+      exit(0);
+    left = failinject(FAIL_KALLOC, 0);
+    if(pid > 0)
+      wait(0);
+    if(n == 1 && pid >= 0){
+      printf("%s: fork did not fail\n", s);
+      exit(1);
+    }
+    if(left > 0){
+      if(pid < 0){
+        printf("%s: fork failed without a fault\n", s);
+        exit(1);
+      }
+      break;
+    }
+  }
+  if(n == 1000){
+    printf("%s: fork never got past the faults\n", s);
+    exit(1);
+  }
+
+  fd = open("failinject", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+  memset(buf, 'a', BSIZE);
+  failinject(FAIL_DISK, 1);
+  if(write(fd, buf, BSIZE) >= 0 || errno != EIO){
+    printf("%s: write did not fail with EIO\n", s);
+    exit(1);
+  }
+  if(write(fd, buf, BSIZE) != BSIZE){
+    printf("%s: write failed after the fault\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  fd = open("failinject", O_RDONLY);
+  failinject(FAIL_DISK, 1);
+  if(read(fd, buf, BSIZE) >= 0 || errno != EIO){
+    printf("%s: read did not fail with EIO\n", s);
+    exit(1);
+  }
+  if(read(fd, buf, BSIZE) != BSIZE || buf[0] != 'a'){
+    printf("%s: read failed after the fault\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("failinject");
+}
+
+// run the kernel's own tests, if it was built with them.
+void
+ktesttest(char *s)
+{
+  int pid, failed, xstatus;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0)
+      exit(1);
+    if(ktest("") >= 0 || errno != EPERM)
+      exit(2);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: unprivileged ktest: step %d\n", s, xstatus);
+    exit(1);
+  }
+
+  failed = ktest("");
+  if(failed < 0 && errno == ENOSYS)
+    return;
+  if(failed != 0){
+    printf("%s: %d kernel tests failed\n", s, failed);
+    exit(1);
+  }
+}
+
+// arguments that a fuzzer may pass must fail, not crash the kernel.
+// with the fuzzing mode, the calls are counted, and poweroff() is refused.
+void
+fuzztest(char *s)
+{
+  struct fuzzinfo before, after;
+  struct timerspec ts;
+  char *argv[] = { "echo", 0 };
+  int fd;
+
+  if(sbrk(-0x7fffffff) != (char*)-1 || sbrk(-0x7fffffff - 1) != (char*)-1){
+    printf("%s: sbrk of a huge size did not fail\n", s);
+    exit(1);
+  }
+  if(exec(argv[0], (char**)0xfffffffffffffff8ULL) >= 0 || errno != EFAULT){
+    printf("%s: exec of a wrapping argv did not fail with EFAULT\n", s);
+    exit(1);
+  }
+
+  fd = timerfd_create(0);
+  if(fd < 0){
+    printf("%s: timerfd_create failed\n", s);
+    exit(1);
+  }
+  ts.value = ~0ULL;
+  ts.interval = ~0ULL;
+  if(timerfd_settime(fd, 0, &ts, 0) < 0 || timerfd_settime(fd, TFD_TIMER_ABSTIME, &ts, 0) < 0){
+    printf("%s: timerfd_settime of a huge time failed\n", s);
+    exit(1);
+  }
+  if(timerfd_gettime(fd, &ts) < 0 || ts.value == 0){
+    printf("%s: timer of a huge time is not armed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(fuzzinfo(&before) < 0){
+    if(errno != ENOSYS){
+      printf("%s: fuzzinfo failed\n", s);
+      exit(1);
+    }
+    return;
+  }
+  // poweroff() of user.h does not return.
+  if(syscall(SYS_poweroff, 0, 0, 0, 0, 0, 0) != -EPERM){
+    printf("%s: poweroff was not refused\n", s);
+    exit(1);
+  }
+  getpid();
+  close(-1);
+  fuzzinfo(&after);
+  if(after.calls - before.calls < 3 || after.errors - before.errors < 1 ||
+     after.refused != before.refused + 1){
+    printf("%s: calls were not counted\n", s);
+    exit(1);
+  }
+}
+
+// the profiler samples this process while it spins in user mode.
+void
+proftest(char *s)
+{
+  struct profsample samples[16];
+  int pid, cpu, i, n, start, mine, xstatus;
+  volatile int spin = 0;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0)
+      exit(1);
+    if(profile(PROF_ON, 0, 0, 0) >= 0 || errno != EPERM)
+      exit(2);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: unprivileged profile: step %d\n", s, xstatus);
+    exit(1);
+  }
+
+  if(profile(PROF_ON, 0, 0, 0) < 0){
+    printf("%s: profile on failed\n", s);
+    exit(1);
+  }
+  start = uptime();
+  while(uptime() - start < 5)
+    spin++;
+  profile(PROF_OFF, 0, 0, 0);
+
+  mine = 0;
+  for(cpu = 0; cpu < NCPU; cpu++){
+    while((n = profile(PROF_READ, cpu, samples, 16)) > 0){
+      for(i = 0; i < n; i++){
+        if(samples[i].cpu != cpu){
+          printf("%s: sample of cpu %d in the buffer of cpu %d\n", s, samples[i].cpu, cpu);
+          exit(1);
+        }
+        if(samples[i].user && samples[i].pid == getpid())
+          mine++;
+      }
+    }
+    if(n < 0){
+      printf("%s: profile read failed\n", s);
+      exit(1);
+    }
+  }
+  if(mine == 0){
+    printf("%s: no samples of this process\n", s);
+    exit(1);
+  }
+  if(profile(PROF_READ, NCPU, samples, 16) >= 0 || profile(3, 0, 0, 0) >= 0){
+    printf("%s: profile of a bad cpu or command did not fail\n", s);
+    exit(1);
+  }
+}
+
+// Reads the file at path into buf, NUL-terminated.
+// Returns the number of bytes read, or -1 if it cannot be opened.
+int
+readproc(char *path, char *buf, int size)
+{
+  int fd, n, total;
+
+  if((fd = open(path, O_RDONLY)) < 0)
+    return -1;
+  total = 0;
+  while(total < size - 1 && (n = read(fd, buf + total, size - 1 - total)) > 0)
+    total += n;
+  close(fd);
+  buf[total] = 0;
+  return total;
+}
+
+// Writes the decimal digits of n, which is positive, to buf, NUL-terminated.
+void
+procitoa(int n, char *buf)
+{
+  char tmp[16];
+  int i = 0;
+
+  do {
+    tmp[i++] = '0' + n % 10;
+    n /= 10;
+  } while(n > 0);
+  while(i > 0)
+    *buf++ = tmp[--i];
+  *buf = 0;
+}
+
+// Returns the number after the n-th space of str.
+int
+procfield(char *str, int n)
+{
+  for(; n > 0 && *str; str++){
+    if(*str == ' ')
+      n--;
+  }
+  return atoi(str);
+}
+
+void
+proctest(char *s)
+{
+  char buf[256], path[32], want[32], *p;
+  struct dirent de;
+  struct stat st;
+  int fd, pid, found, start;
+  volatile int spin = 0;
+
+  if(readproc("/proc/stat", buf, sizeof(buf)) <= 0 || strncmp(buf, "cpu  ", 5) != 0
+     || strncmp(strchr(buf, '\n'), "\ncpu0 ", 6) != 0){
+    printf("%s: bad /proc/stat: %s\n", s, buf);
+    exit(1);
+  }
+  // "cpu0 spin <usage> <time> wfi <usage> <time>"
+  if(readproc("/proc/idle", buf, sizeof(buf)) <= 0 || strncmp(buf, "cpu0 spin ", 10) != 0){
+    printf("%s: bad /proc/idle: %s\n", s, buf);
+    exit(1);
+  }
+  for(p = buf; *p != '\n' && strncmp(p, " wfi ", 5) != 0; p++)
+    ;
+  if(*p == '\n'){
+    printf("%s: no wfi in /proc/idle: %s\n", s, buf);
+    exit(1);
+  }
+
+  // Spend some time in user mode.
+  start = uptime();
+  while(uptime() - start < 3)
+    spin++;
+  if(readproc("/proc/self/stat", buf, sizeof(buf)) <= 0){
+    printf("%s: cannot read /proc/self/stat\n", s);
+    exit(1);
+  }
+  // "<pid> (usertests) R <ppid> <size> <utime> <stime>"
+  if(atoi(buf) != getpid() || strncmp(strchr(buf, ' '), " (usertests) R ", 15) != 0
+     || procfield(buf, 4) != (uint64)sbrk(0) || procfield(buf, 5) == 0){
+    printf("%s: bad /proc/self/stat: %s", s, buf);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    sleep(1000);
+    exit(0);
+  }
+  sleep(2);
+  strcpy(path, "/proc/");
+  procitoa(pid, path + strlen(path));
+  strcpy(path + strlen(path), "/stat");
+  if(readproc(path, buf, sizeof(buf)) <= 0 || atoi(buf) != pid
+     || strchr(buf, ')')[2] != 'S' || procfield(buf, 3) != getpid()){
+    printf("%s: bad %s: %s", s, path, buf);
+    exit(1);
+  }
+
+  // /proc lists the child.
+  if((fd = open("/proc", O_RDONLY)) < 0 || fstat(fd, &st) < 0 || st.type != T_DIR){
+    printf("%s: cannot open /proc as a directory\n", s);
+    exit(1);
+  }
+  procitoa(pid, want);
+  found = 0;
+  while(read(fd, &de, sizeof(de)) == sizeof(de)){
+    if(de.inum != 0 && strncmp(de.name, want, DIRSIZ) == 0)
+      found = 1;
+  }
+  close(fd);
+  if(!found){
+    printf("%s: /proc does not list %d\n", s, pid);
+    exit(1);
+  }
+
+  kill(pid);
+  wait(0);
+  if(open(path, O_RDONLY) >= 0 || open("/proc/stat", O_WRONLY) >= 0
+     || open("/proc/nothing", O_RDONLY) >= 0){
+    printf("%s: bad /proc open did not fail\n", s);
+    exit(1);
+  }
+}
+
+// Returns whether the line at p of /proc/interrupts starts with prefix,
+// and ends with the handler names names.
+int
+irqline(char *p, char *prefix, char *names)
+{
+  char *eol = strchr(p, '\n');
+  int n = strlen(names);
+
+  return eol && strncmp(p, prefix, strlen(prefix)) == 0 && eol - p > n
+    && eol[-n - 1] == ' ' && strncmp(eol - n, names, n) == 0;
+}
+
+// /proc/interrupts counts the interrupts of each line, such as the
+// disk's, and the timers' and IPIs', which share a line.
+void
+interruptstest(char *s)
+{
+  char buf[1024], *p;
+  int disk = 0, timer = 0;
+
+  if(readproc("/proc/interrupts", buf, sizeof(buf)) <= 0){
+    printf("%s: cannot read /proc/interrupts\n", s);
+    exit(1);
+  }
+  for(p = buf; *p; p = strchr(p, '\n') + 1){
+    if(irqline(p, "plic 1 ", "virtio-blk"))
+      disk = 1;
+    // "cpu 1 <count on cpu0> ... <time> ipi,timer"
+    if(irqline(p, "cpu 1 ", "ipi,timer") && procfield(p, 2) > 0)
+      timer = 1;
+    if(strchr(p, '\n') == 0)
+      break;
+  }
+  if(!disk || !timer){
+    printf("%s: bad /proc/interrupts: %s\n", s, buf);
+    exit(1);
+  }
+}
+
+// Returns the number of interrupts of the disk's line that CPU id took,
+// from /proc/interrupts, or -1 if there is no such line.
+int
+diskirqs(int id)
+{
+  char buf[1024], *p;
+
+  if(readproc("/proc/interrupts", buf, sizeof(buf)) <= 0)
+    return -1;
+  for(p = buf; *p; p = strchr(p, '\n') + 1){
+    if(strncmp(p, "plic 1 ", 7) == 0)
+      return procfield(p, 2 + id);
+    if(strchr(p, '\n') == 0)
+      break;
+  }
+  return -1;
+}
+
+// /proc/irq/<n>/affinity sends the interrupts of a line of the PLIC,
+// such as the disk's, to the CPUs of a mask, which only root may set.
+void
+irqaffinitytest(char *s)
+{
+  char buf[256], *p;
+  struct dirent de;
+  int fd, pid, xstatus, found, ncpu, id, before[NCPU];
+
+  if((fd = open("/proc/irq", O_RDONLY)) < 0){
+    printf("%s: cannot open /proc/irq\n", s);
+    exit(1);
+  }
+  found = 0;
+  while(read(fd, &de, sizeof(de)) == sizeof(de)){
+    if(de.inum != 0 && strncmp(de.name, "1", DIRSIZ) == 0)
+      found = 1;
+  }
+  close(fd);
+  if(!found){
+    printf("%s: /proc/irq does not list the disk's line\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0)
+      exit(1);
+    if(open("/proc/irq/1/affinity", O_WRONLY) >= 0 || errno != EACCES)
+      exit(2);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: unprivileged child failed check %d\n", s, xstatus);
+    exit(1);
+  }
+
+  // Send the disk's interrupts to CPU 0 only.
+  if((fd = open("/proc/irq/1/affinity", O_WRONLY)) < 0 || write(fd, "1\n", 2) != 2){
+    printf("%s: cannot write /proc/irq/1/affinity\n", s);
+    exit(1);
+  }
+  if(write(fd, "0", 1) >= 0 || errno != EINVAL || write(fd, "xyz", 3) >= 0
+     || errno != EINVAL){
+    printf("%s: bad affinity was accepted\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(readproc("/proc/irq/1/affinity", buf, sizeof(buf)) <= 0 || strcmp(buf, "1\n") != 0){
+    printf("%s: bad /proc/irq/1/affinity: %s\n", s, buf);
+    exit(1);
+  }
+
+  // Then the disk interrupts no other CPU.
+  if(readproc("/proc/stat", buf, sizeof(buf)) <= 0){
+    printf("%s: cannot read /proc/stat\n", s);
+    exit(1);
+  }
+  // A line for all CPUs, then one for each.
+  ncpu = -1;
+  for(p = buf; *p; p++){
+    if(*p == '\n')
+      ncpu++;
+  }
+  for(id = 1; id < ncpu; id++)
+    before[id] = diskirqs(id);
+  if((fd = open("irqaff", O_CREATE|O_WRONLY)) < 0 || write(fd, buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: cannot write irqaff\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("irqaff");
+  for(id = 1; id < ncpu; id++){
+    if(diskirqs(id) != before[id]){
+      printf("%s: CPU %d took a disk interrupt\n", s, id);
+      exit(1);
+    }
+  }
+
+  // Give the line back to every CPU.
+  if((fd = open("/proc/irq/1/affinity", O_WRONLY)) < 0 || write(fd, "ff", 2) != 2){
+    printf("%s: cannot write /proc/irq/1/affinity\n", s);
+    exit(1);
+  }
+  close(fd);
+}
+
+// Returns the total of the line of /proc/pressure/<resource> that
+// starts with kind, "some" or "full", or -1 if there is none.
+int
+pressuretotal(char *resource, char *kind)
+{
+  char buf[256], path[32], *p, *eol;
+  int n = strlen(kind);
+
+  strcpy(path, "/proc/pressure/");
+  strcpy(path + strlen(path), resource);
+  if(readproc(path, buf, sizeof(buf)) <= 0)
+    return -1;
+  for(p = buf; *p; p = eol + 1){
+    if((eol = strchr(p, '\n')) == 0)
+      break;
+    if(strncmp(p, kind, n) != 0 || p[n] != ' ')
+      continue;
+    for(; p < eol; p++){
+      if(strncmp(p, "total=", 6) == 0)
+        return atoi(p + 6);
+    }
+  }
+  return -1;
+}
+
+// /proc/pressure counts the time that processes wait for the CPUs,
+// as when more of them spin than there are CPUs, and for the disk.
+void
+pressuretest(char *s)
+{
+  char buf[BSIZE];
+  int i, fd, cpu, io, pids[NCPU + 1];
+
+  if(pressuretotal("memory", "some") < 0 || pressuretotal("io", "full") < 0){
+    printf("%s: cannot read /proc/pressure\n", s);
+    exit(1);
+  }
+
+  cpu = pressuretotal("cpu", "some");
+  for(i = 0; i < NCPU + 1; i++){
+    pids[i] = fork();
+    if(pids[i] < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pids[i] == 0)
+      for(;;);
+  }
+  sleep(5);
+  for(i = 0; i < NCPU + 1; i++){
+    kill(pids[i]);
+    wait(0);
+  }
+  if(pressuretotal("cpu", "some") <= cpu){
+    printf("%s: no CPU pressure with %d spinners\n", s, NCPU + 1);
+    exit(1);
+  }
+
+  io = pressuretotal("io", "some");
+  memset(buf, 'p', sizeof(buf));
+  if((fd = open("pressure", O_CREATE|O_WRONLY)) < 0 || write(fd, buf, sizeof(buf)) != sizeof(buf)){
+    printf("%s: cannot write pressure\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("pressure");
+  if(pressuretotal("io", "some") <= io){
+    printf("%s: no I/O pressure from writing a file\n", s);
+    exit(1);
+  }
+}
+
+// /proc/<pid>/maps lists the runs of pages of a process, with the
+// guard page below the stack unreachable, and the shared page and
+// the ring page named.
+void
+mapstest(char *s)
+{
+  char buf[512], path[32], *p;
+  int pid, xstatus, guard;
+  uint64 end, top;
+
+  if(readproc("/proc/self/maps", buf, sizeof(buf)) <= 0 || strncmp(buf, "00000000-", 9) != 0){
+    printf("%s: bad /proc/self/maps: %s\n", s, buf);
+    exit(1);
+  }
+  // "<start>-<end> <perms> 00000000 00:00 0", with the last run below
+  // the shared page ending at the break.
+  guard = 0;
+  end = 0;
+  for(p = buf; *p && p[8] == '-'; p = strchr(p, '\n') + 1){
+    if(strncmp(p + 18, "---p", 4) == 0)
+      guard = 1;
+    else if(strncmp(p + 18, "rwxp", 4) != 0){
+      printf("%s: bad permissions in /proc/self/maps: %s\n", s, buf);
+      exit(1);
+    }
+    end = 0;
+    for(p += 9; *p != ' '; p++)
+      end = end * 16 + (*p >= 'a' ? *p - 'a' + 10 : *p - '0');
+  }
+  top = PGROUNDUP((uint64)sbrk(0));
+  if(!guard || end != top){
+    printf("%s: no guard page, or maps end at %p, not %p: %s\n", s, end, top, buf);
+    exit(1);
+  }
+  if(strcmp(p, "3fffffc000-3fffffd000 r--p 00000000 00:00 0 [vdso]\n") != 0){
+    printf("%s: bad shared page in /proc/self/maps: %s\n", s, buf);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(ioring_setup() == (void*)-1)
+      exit(1);
+    if(readproc("/proc/self/maps", buf, sizeof(buf)) <= 0)
+      exit(2);
+    if(strcmp(buf + strlen(buf) - 10, " [ioring]\n") != 0)
+      exit(3);
+    sleep(1000);
+    exit(0);
+  }
+  sleep(2);
+  strcpy(path, "/proc/");
+  procitoa(pid, path + strlen(path));
+  strcpy(path + strlen(path), "/maps");
+  if(readproc(path, buf, sizeof(buf)) <= 0 || strncmp(buf, "00000000-", 9) != 0
+     || strcmp(buf + strlen(buf) - 10, " [ioring]\n") != 0){
+    printf("%s: bad %s: %s", s, path, buf);
+    exit(1);
+  }
+  kill(pid);
+  wait(&xstatus);
+  if(xstatus != -1){
+    printf("%s: child failed check %d\n", s, xstatus);
+    exit(1);
+  }
+}
+
+void
+suspendtest(char *s)
+{
+  int pid, start, last, now, gap, xstatus;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0)
+      exit(1);
+    if(suspend(1) >= 0 || errno != EPERM)
+      exit(2);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: unprivileged suspend: step %d\n", s, xstatus);
+    exit(1);
+  }
+
+  // A process that spins through the suspension sees the clock jump.
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    start = last = uptime();
+    gap = 0;
+    while((now = uptime()) < start + 40){
+      if(now - last > gap)
+        gap = now - last;
+      last = now;
+    }
+    exit(gap >= 15 ? 0 : 1);
+  }
+  sleep(2);
+  start = uptime();
+  if(suspend(2) < 0){
+    printf("%s: suspend failed\n", s);
+    exit(1);
+  }
+  if(uptime() - start < 19){
+    printf("%s: woke up after %d ticks\n", s, uptime() - start);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: a process ran while the machine was suspended\n", s);
+    exit(1);
+  }
+}
+
+// AF_VSOCK sockets connected over VMADDR_CID_LOCAL, which need no
+// device: errors, a transfer larger than the receive buffer, and the
+// end of the stream once the peer closes.
+void
+vsocktest(char *s)
+{
+  struct sockaddr_vm addr;
+  char buf[512];
+  int l, c, conn, pid, i, n, total, xstatus;
+
+  if(socket(AF_VSOCK + 1, SOCK_STREAM, 0) >= 0 || errno != EAFNOSUPPORT){
+    printf("%s: socket of a bad family: errno %d, expected EAFNOSUPPORT\n", s, errno);
+    exit(1);
+  }
+  l = socket(AF_VSOCK, SOCK_STREAM, 0);
+  if(l < 0){
+    printf("%s: socket failed\n", s);
+    exit(1);
+  }
+  memset(&addr, 0, sizeof(addr));
+  addr.svm_family = AF_VSOCK;
+  addr.svm_cid = VMADDR_CID_ANY;
+  addr.svm_port = 5000;
+  if(bind(l, &addr) < 0 || listen(l, 1) < 0){
+    printf("%s: cannot listen on port 5000\n", s);
+    exit(1);
+  }
+  c = socket(AF_VSOCK, SOCK_STREAM, 0);
+  if(bind(c, &addr) >= 0 || errno != EADDRINUSE){
+    printf("%s: second bind: errno %d, expected EADDRINUSE\n", s, errno);
+    exit(1);
+  }
+  addr.svm_cid = VMADDR_CID_LOCAL;
+  addr.svm_port = 5001;
+  if(connect(c, &addr) >= 0 || errno != ECONNREFUSED){
+    printf("%s: connect to no listener: errno %d, expected ECONNREFUSED\n", s, errno);
+    exit(1);
+  }
+  close(c);
+  if(read(l, buf, 1) >= 0 || errno != ENOTCONN){
+    printf("%s: read of a listener: errno %d, expected ENOTCONN\n", s, errno);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(l);
+    c = socket(AF_VSOCK, SOCK_STREAM, 0);
+    addr.svm_port = 5000;
+    if(c < 0 || connect(c, &addr) < 0)
+      exit(1);
+    for(i = 0; i < sizeof(buf); i++)
+      buf[i] = i;
+    // Twenty times the receive buffer, so the writer must wait for the reader.
+    for(i = 0; i < 160; i++)
+      if(write(c, buf, sizeof(buf)) != sizeof(buf))
+        exit(2);
+    if(read(c, buf, 3) != 3 || memcmp(buf, "bye", 3) != 0)
+      exit(3);
+    exit(0);
+  }
+
+  conn = accept(l, &addr);
+  if(conn < 0){
+    printf("%s: accept failed\n", s);
+    exit(1);
+  }
+  if(addr.svm_family != AF_VSOCK || addr.svm_cid != VMADDR_CID_LOCAL || addr.svm_port < 1024){
+    printf("%s: accepted from %d:%d\n", s, addr.svm_cid, addr.svm_port);
+    exit(1);
+  }
+  total = 0;
+  while(total < 160 * sizeof(buf)){
+    n = read(conn, buf, sizeof(buf));
+    if(n <= 0){
+      printf("%s: read %d after %d bytes\n", s, n, total);
+      exit(1);
+    }
+    for(i = 0; i < n; i++){
+      if(buf[i] != (char)((total + i) % sizeof(buf))){
+        printf("%s: wrong byte at %d\n", s, total + i);
+        exit(1);
+      }
+    }
+    total += n;
+  }
+  if(write(conn, "bye", 3) != 3){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: connecting side failed at step %d\n", s, xstatus);
+    exit(1);
+  }
+  if(read(conn, buf, sizeof(buf)) != 0){
+    printf("%s: no end of stream after the peer closed\n", s);
+    exit(1);
+  }
+  close(conn);
+  close(l);
+}
+
+void
+audittest(char *s)
+{
+  struct auditrec recs[16];
+  uint64 seq = 0;
+  int i, n, pid, xstatus, setuid_seen = 0, denied_seen = 0;
+
+  // skip the records already in the log.
+  while((n = audit(recs, 16, seq)) > 0)
+    seq = recs[n-1].seq + 1;
+  if(n < 0){
+    printf("%s: audit failed\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setuid(10) < 0 || setuid(0) >= 0)
+      exit(1);
+    if(audit(recs, 16, 0) >= 0 || errno != EPERM)
+      exit(2);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: child failed check %d\n", s, xstatus);
+    exit(1);
+  }
+
+  while((n = audit(recs, 16, seq)) > 0){
+    for(i = 0; i < n; i++){
+      if(recs[i].seq < seq){
+        printf("%s: record %l read twice\n", s, recs[i].seq);
+        exit(1);
+      }
+      if(recs[i].pid != pid)
+        continue;
+      if(recs[i].event == AUDIT_SETUID && recs[i].arg == 0 &&
+         recs[i].error == EPERM && recs[i].uid == 10)
+        setuid_seen = 1;
+      if(recs[i].event == AUDIT_PRIVILEGED && recs[i].arg == SYS_audit &&
+         recs[i].error == EPERM)
+        denied_seen = 1;
+    }
+    seq = recs[n-1].seq + 1;
+  }
+  if(!setuid_seen || !denied_seen){
+    printf("%s: missing audit records %d %d\n", s, setuid_seen, denied_seen);
+    exit(1);
+  }
+}
+
+// process groups, and the stop, continue, and kill actions of signals
+// that job control uses.
+void
+jobtest(char *s)
+{
+  int pid, status, fg, pgrp;
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(setpgid(0, 0) < 0 || getpgid(0) != getpid())
+      exit(1);
+    for(;;)
+      sleep(1);
+  }
+  if(setpgid(pid, pid) < 0 || getpgid(pid) != pid){
+    printf("%s: setpgid failed\n", s);
+    exit(1);
+  }
+  if(setpgid(0, 999999) >= 0 || errno != EPERM){
+    printf("%s: joined a group that does not exist\n", s);
+    exit(1);
+  }
+
+  if(sigsend(-pid, SIGSTOP) < 0){
+    printf("%s: sigsend SIGSTOP failed\n", s);
+    exit(1);
+  }
+  if(wait(&status) != pid || !WIFSTOPPED(status) || WSTOPREASON(status) != STOP_SUSPEND){
+    printf("%s: stop was not reported: status 0x%x\n", s, status);
+    exit(1);
+  }
+  if(sigsend(-pid, SIGCONT) < 0 || sigsend(pid, SIGTSTP) < 0){
+    printf("%s: sigsend failed\n", s);
+    exit(1);
+  }
+  if(wait(&status) != pid || !WIFSTOPPED(status)){
+    printf("%s: second stop was not reported: status 0x%x\n", s, status);
+    exit(1);
+  }
+  // Killing a stopped process resumes it so that it can exit.
+  if(sigsend(-pid, SIGINT) < 0){
+    printf("%s: sigsend SIGINT failed\n", s);
+    exit(1);
+  }
+  if(wait(&status) != pid || status != -1){
+    printf("%s: child was not killed: status 0x%x\n", s, status);
+    exit(1);
+  }
+  if(sigsend(pid, SIGINT) >= 0 || errno != ESRCH){
+    printf("%s: signaled a process that exited\n", s);
+    exit(1);
+  }
+  if(sigsend(getpid(), 1234) >= 0 || errno != EINVAL){
+    printf("%s: sent an unknown signal\n", s);
+    exit(1);
+  }
+
+  if(ioctl(0, TIOCGPGRP, &fg) < 0){
+    printf("%s: TIOCGPGRP failed\n", s);
+    exit(1);
+  }
+  pgrp = getpgid(0);
+  if(ioctl(0, TIOCSPGRP, &pgrp) < 0 || ioctl(0, TIOCGPGRP, &pgrp) < 0 || pgrp != getpgid(0)){
+    printf("%s: TIOCSPGRP failed\n", s);
+    exit(1);
+  }
+  ioctl(0, TIOCSPGRP, &fg);
+}
+
+// can processes together keep more than NFILE files open? the file
+// table should grow beyond its static size and shrink afterwards.
+void
+manyfiles(char *s)
+{
+  enum { NCHILD = 12, NOPEN = NOFILE - 6 };
+  struct sysinfo info;
+  int ready[2], hold[2];
+  char c;
+
+  if(pipe(ready) < 0 || pipe(hold) < 0){
+    printf("%s: pipe failed\n", s);
+    exit(1);
+  }
+  for(int i = 0; i < NCHILD; i++){
+    int pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      close(hold[1]);
+      for(int j = 0; j < NOPEN; j++){
+        if(open("README", 0) < 0){
+          printf("%s: open failed\n", s);
+          exit(1);
+        }
+      }
+      write(ready[1], "x", 1);
+      // wait until the parent closes hold[1].
+      read(hold[0], &c, 1);
+      exit(0);
+    }
+  }
+  close(hold[0]);
+  for(int i = 0; i < NCHILD; i++){
+    if(read(ready[0], &c, 1) != 1){
+      printf("%s: a child failed to open files\n", s);
+      exit(1);
+    }
+  }
+  if(sysinfo(&info) < 0){
+    printf("%s: sysinfo failed\n", s);
+    exit(1);
+  }
+  if(info.ftable.in_use <= NFILE || info.ftable.capacity <= NFILE){
+    printf("%s: file table did not grow\n", s);
+    exit(1);
+  }
+  close(hold[1]);
+  for(int i = 0; i < NCHILD; i++){
+    int xstatus;
+    wait(&xstatus);
+    if(xstatus != 0)
+      exit(1);
+  }
+  if(sysinfo(&info) < 0){
+    printf("%s: sysinfo failed\n", s);
+    exit(1);
+  }
+  if(info.ftable.capacity != NFILE){
+    printf("%s: file table did not shrink\n", s);
+    exit(1);
+  }
+}
+
 //
 // use sbrk() to count how many free physical memory pages there are.
 // touches the pages to force allocation.
@@ -2755,6 +6596,8 @@ main(int argc, char *argv[])
     continuous = 1;
   } else if(argc == 2 && strcmp(argv[1], "-C") == 0){
     continuous = 2;
+  } else if(argc == 2 && strcmp(argv[1], "-cow") == 0){
+    exit(execcowcheck());
   } else if(argc == 2 && argv[1][0] != '-'){
     justone = argv[1];
   } else if(argc > 1){
@@ -2768,6 +6611,7 @@ main(int argc, char *argv[])
   } tests[] = {
     {manywrites, "manywrites"},
     {execout, "execout"},
+    {execcow, "execcow"},
     {copyin, "copyin"},
     {copyout, "copyout"},
     {copyinstr1, "copyinstr1"},
@@ -2811,6 +6655,7 @@ main(int argc, char *argv[])
     {writetest, "writetest"},
     {writebig, "writebig"},
     {createtest, "createtest"},
+    {ireuse, "ireuse"},
     {openiputtest, "openiput"},
     {exitiputtest, "exitiput"},
     {iputtest, "iput"},
@@ -2825,6 +6670,57 @@ main(int argc, char *argv[])
     {dirfile, "dirfile"},
     {iref, "iref"},
     {forktest, "forktest"},
+    {sysinfotest, "sysinfo"},
+    {errnotest, "errno"},
+    {fcntltest, "fcntl"},
+    {ioctltest, "ioctl"},
+    {termiostest, "termios"},
+    {polltest, "poll"},
+    {pipe2test, "pipe2"},
+    {eventfdtest, "eventfd"},
+    {pidfdtest, "pidfd"},
+    {semtest, "sem"},
+    {mqtest, "mq"},
+    {timerfdtest, "timerfd"},
+    {getcwdtest, "getcwd"},
+    {chroottest, "chroot"},
+    {negativetest, "negative"},
+    {dirsizetest, "dirsize"},
+    {fadvisetest, "fadvise"},
+    {sendfiletest, "sendfile"},
+    {ioringtest, "ioring"},
+    {splicetest, "splice"},
+    {quotatest, "quota"},
+    {manyfdstest, "manyfds"},
+    {umasktest, "umask"},
+    {clocktest, "clock"},
+    {vdsotest, "vdso"},
+    {settimetest, "settime"},
+    {getrandomtest, "getrandom"},
+    {tracetest, "trace"},
+    {ptracetest, "ptrace"},
+    {uidtest, "uid"},
+    {captest, "cap"},
+    {devpermtest, "devperm"},
+    {memdevtest, "memdev"},
+    {audittest, "audit"},
+    {dmesgtest, "dmesg"},
+    {logfiltertest, "logfilter"},
+    {tracepointtest, "tracepoints"},
+    {leaktest, "leakcheck"},
+    {failinjecttest, "failinject"},
+    {ktesttest, "ktest"},
+    {fuzztest, "fuzz"},
+    {proftest, "profile"},
+    {proctest, "proc"},
+    {interruptstest, "interrupts"},
+    {irqaffinitytest, "irqaffinity"},
+    {pressuretest, "pressure"},
+    {mapstest, "maps"},
+    {suspendtest, "suspend"},
+    {vsocktest, "vsock"},
+    {jobtest, "job"},
+    {manyfiles, "manyfiles"},
     {bigdir, "bigdir"}, // slow
     { 0, 0},
   };
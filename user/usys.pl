@@ -1,39 +1,45 @@
 #!/usr/bin/perl -w
 
 # Generate usys.S, the stubs for syscalls.
+# Run from the top directory, as the Makefile does.
 
 print "# generated by usys.pl - do not edit\n";
 
 print "#include \"kernel/syscall.h\"\n";
 
+# The kernel returns -errno on failure.
+# Store errno and return -1 instead, as user programs expect.
 sub entry {
     my $name = shift;
     print ".global $name\n";
     print "${name}:\n";
     print " li a7, SYS_${name}\n";
     print " ecall\n";
+    print " bltz a0, 1f\n";
+    print " ret\n";
+    print "1:\n";
+    print " neg a0, a0\n";
+    print " la t0, errno\n";
+    print " sw a0, 0(t0)\n";
+    print " li a0, -1\n";
     print " ret\n";
 }
 	
-entry("fork");
-entry("exit");
-entry("wait");
-entry("pipe");
-entry("read");
-entry("write");
-entry("close");
-entry("kill");
-entry("exec");
-entry("open");
-entry("mknod");
-entry("unlink");
-entry("fstat");
-entry("link");
-entry("mkdir");
-entry("chdir");
-entry("dup");
-entry("getpid");
-entry("sbrk");
-entry("sleep");
-entry("uptime");
-entry("poweroff");
+# A stub for each system call of kernel/syscall.h, which abi/cheader.pl generates.
+open(my $syscalls, "<", "kernel/syscall.h") or die "usys.pl: cannot open kernel/syscall.h\n";
+while (my $line = <$syscalls>) {
+    entry($1) if $line =~ /^#define SYS_(\w+) /;
+}
+close($syscalls);
+
+# syscall(num, a0, ..., a5) makes the system call num, for programs that choose it at run time,
+# and returns what the kernel does, which is -errno on failure.
+print ".global syscall\n";
+print "syscall:\n";
+print " mv a7, a0\n";
+for my $i (0..5) {
+    my $next = $i + 1;
+    print " mv a$i, a$next\n";
+}
+print " ecall\n";
+print " ret\n";
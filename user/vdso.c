@@ -0,0 +1,94 @@
+// Clocks and the current CPU, read from the page that the kernel
+// shares with each process at VDSO_ADDR, without a system call.
+// See kernel/vdso.h for what the page holds.
+
+#include "kernel/types.h"
+#include "kernel/time.h"
+#include "kernel/vdso.h"
+#include "user/user.h"
+
+#define NSEC_PER_SEC 1000000000UL
+
+static volatile struct vdso_data *vdso = (struct vdso_data*)VDSO_ADDR;
+
+static uint64
+rdtime(void)
+{
+  uint64 x;
+  asm volatile("rdtime %0" : "=r" (x));
+  return x;
+}
+
+// Nanoseconds since boot, as the kernel converts the time CSR.
+static uint64
+monotonic(uint64 freq)
+{
+  uint64 cycles = rdtime();
+  return cycles / freq * NSEC_PER_SEC + cycles % freq * NSEC_PER_SEC / freq;
+}
+
+// The part of the slew applied by the monotonic time now.
+static long
+slewed(long slew, uint64 start, uint64 now)
+{
+  long max = (now > start ? now - start : 0) / VDSO_SLEW_RATIO;
+
+  if(slew > max)
+    return max;
+  if(slew < -max)
+    return -max;
+  return slew;
+}
+
+// clock_gettime() without a trap, for CLOCK_REALTIME and
+// CLOCK_MONOTONIC. Other clocks go to the system call.
+int
+vdso_clock_gettime(int clock, struct timespec *ts)
+{
+  uint64 seq, now, offset, start;
+  long slew;
+
+  if(clock != CLOCK_REALTIME && clock != CLOCK_MONOTONIC)
+    return clock_gettime(clock, ts);
+  // The kernel refills the page whenever a trap interrupts us, so
+  // retry if the clock was set or slewed meanwhile.
+  do {
+    seq = vdso->realtime_seq;
+    offset = vdso->realtime_offset;
+    slew = vdso->slew;
+    start = vdso->slew_start;
+    now = monotonic(vdso->timebase_freq);
+  } while(vdso->realtime_seq != seq);
+  if(clock == CLOCK_REALTIME)
+    now = offset + now + slewed(slew, start, now);
+  ts->tv_sec = now / NSEC_PER_SEC;
+  ts->tv_nsec = now % NSEC_PER_SEC;
+  return 0;
+}
+
+int
+gettimeofday(struct timeval *tv)
+{
+  struct timespec ts;
+
+  if(vdso_clock_gettime(CLOCK_REALTIME, &ts) < 0)
+    return -1;
+  tv->tv_sec = ts.tv_sec;
+  tv->tv_usec = ts.tv_nsec / 1000;
+  return 0;
+}
+
+// Clock ticks since boot, as uptime() returns, as of the last trap.
+int
+vdso_uptime(void)
+{
+  return vdso->ticks;
+}
+
+// The CPU that the process runs on. It may have moved by the time
+// the caller looks.
+int
+getcpu(void)
+{
+  return vdso->cpu;
+}
@@ -0,0 +1,119 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/poll.h"
+#include "kernel/socket.h"
+#include "user/user.h"
+
+// vsock -l port [command [args...]]
+// vsock cid port
+// The first form listens on the port, and runs the command, sh by
+// default, for each connection in turn, with the connection as its standard
+// input, output, and error. The second connects to the port of the
+// machine with the given CID, 2 for the host, and copies standard input
+// to the connection and the connection to standard output.
+
+static char *shargv[] = { "sh", 0 };
+
+static void
+setaddr(struct sockaddr_vm *addr, uint cid, uint port)
+{
+  memset(addr, 0, sizeof(*addr));
+  addr->svm_family = AF_VSOCK;
+  addr->svm_cid = cid;
+  addr->svm_port = port;
+}
+
+static void
+serve(int port, char **argv)
+{
+  struct sockaddr_vm addr;
+  int s, conn, i;
+
+  s = socket(AF_VSOCK, SOCK_STREAM, 0);
+  if(s < 0){
+    fprintf(2, "vsock: socket failed\n");
+    exit(1);
+  }
+  setaddr(&addr, VMADDR_CID_ANY, port);
+  if(bind(s, &addr) < 0 || listen(s, 1) < 0){
+    fprintf(2, "vsock: cannot listen on port %d\n", port);
+    exit(1);
+  }
+  for(;;){
+    conn = accept(s, &addr);
+    if(conn < 0){
+      fprintf(2, "vsock: accept failed\n");
+      exit(1);
+    }
+    if(fork() == 0){
+      close(s);
+      for(i = 0; i < 3; i++){
+        close(i);
+        dup(conn);
+      }
+      close(conn);
+      exec(argv[0], argv);
+      fprintf(2, "vsock: exec %s failed\n", argv[0]);
+      exit(1);
+    }
+    close(conn);
+    // One connection at a time.
+    wait(0);
+  }
+}
+
+static void
+relay(int cid, int port)
+{
+  struct sockaddr_vm addr;
+  struct pollfd pfd[2];
+  char buf[512];
+  int s, n;
+
+  s = socket(AF_VSOCK, SOCK_STREAM, 0);
+  if(s < 0){
+    fprintf(2, "vsock: socket failed\n");
+    exit(1);
+  }
+  setaddr(&addr, cid, port);
+  if(connect(s, &addr) < 0){
+    fprintf(2, "vsock: cannot connect to %d:%d\n", cid, port);
+    exit(1);
+  }
+  pfd[0].fd = 0;
+  pfd[0].events = POLLIN;
+  pfd[1].fd = s;
+  pfd[1].events = POLLIN;
+  for(;;){
+    if(poll(pfd, 2, -1) < 0)
+      break;
+    if(pfd[0].revents){
+      n = read(0, buf, sizeof(buf));
+      if(n <= 0)
+        break;
+      if(write(s, buf, n) != n)
+        break;
+    }
+    if(pfd[1].revents){
+      n = read(s, buf, sizeof(buf));
+      if(n <= 0)
+        break;
+      write(1, buf, n);
+    }
+  }
+  close(s);
+}
+
+int
+main(int argc, char *argv[])
+{
+  if(argc >= 3 && strcmp(argv[1], "-l") == 0){
+    serve(atoi(argv[2]), argc > 3 ? argv + 3 : shargv);
+  } else if(argc == 3){
+    relay(atoi(argv[1]), atoi(argv[2]));
+  } else {
+    fprintf(2, "usage: vsock -l port [command [args...]]\n       vsock cid port\n");
+    exit(1);
+  }
+  exit(0);
+}